@@ -0,0 +1,145 @@
+//! `#[derive(NVEntity)]` for neural_vault_core
+//!
+//! Use it through the core crate's `derive` feature, which re-exports it
+//! next to the `NVEntity` trait.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitStr};
+
+/// Map a struct with named fields to a collection
+///
+/// Container attribute: `#[nv(collection = "name")]`, defaulting to the
+/// struct name in snake_case. Field attributes: `#[nv(id)]` for the
+/// document ID, `#[nv(rename = "name")]` and `#[nv(skip)]`.
+#[proc_macro_derive(NVEntity, attributes(nv))]
+pub fn derive_entity(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+#[derive(Default)]
+struct FieldAttrs {
+    id: bool,
+    skip: bool,
+    rename: Option<String>,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new_spanned(name, "NVEntity needs a struct with named fields")),
+        },
+        _ => return Err(syn::Error::new_spanned(name, "NVEntity can only be derived for structs")),
+    };
+
+    let mut collection = None;
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("nv")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("collection") {
+                collection = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("expected `collection = \"...\"`"))
+            }
+        })?;
+    }
+    let collection = collection.unwrap_or_else(|| snake_case(&name.to_string()));
+
+    let mut id_field = None;
+    let mut to_data = Vec::new();
+    let mut from_document = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named field");
+        let ty = &field.ty;
+        let attrs = field_attrs(field)?;
+        if attrs.id {
+            if id_field.is_some() {
+                return Err(syn::Error::new_spanned(ident, "only one field can be `#[nv(id)]`"));
+            }
+            id_field = Some(ident);
+            from_document.push(quote! {
+                #ident: <#ty as ::neural_vault_core::entity::NVId>::from_id(document.id.clone())
+            });
+        } else if attrs.skip {
+            from_document.push(quote! { #ident: ::std::default::Default::default() });
+        } else {
+            let key = attrs.rename.unwrap_or_else(|| ident.to_string());
+            to_data.push(quote! {
+                data.insert(#key.to_string(), ::neural_vault_core::entity::NVField::to_value(&self.#ident));
+            });
+            from_document.push(quote! {
+                #ident: ::neural_vault_core::entity::field::<#ty>(document, #collection, #key)?
+            });
+        }
+    }
+
+    let id = match id_field {
+        Some(ident) => quote! { ::neural_vault_core::entity::NVId::as_id(&self.#ident) },
+        None => quote! { ::std::option::Option::None },
+    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::neural_vault_core::entity::NVEntity for #name #ty_generics #where_clause {
+            const COLLECTION: &'static str = #collection;
+
+            fn id(&self) -> ::std::option::Option<&str> {
+                #id
+            }
+
+            fn to_data(&self) -> ::std::collections::HashMap<::std::string::String, ::neural_vault_core::NVValue> {
+                let mut data = ::std::collections::HashMap::new();
+                #(#to_data)*
+                data
+            }
+
+            fn from_document(document: &::neural_vault_core::NVDocument) -> ::neural_vault_core::NVResult<Self> {
+                ::std::result::Result::Ok(Self {
+                    #(#from_document,)*
+                })
+            }
+        }
+    })
+}
+
+fn field_attrs(field: &syn::Field) -> syn::Result<FieldAttrs> {
+    let mut attrs = FieldAttrs::default();
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("nv")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                attrs.id = true;
+            } else if meta.path.is_ident("skip") {
+                attrs.skip = true;
+            } else if meta.path.is_ident("rename") {
+                attrs.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else {
+                return Err(meta.error("expected `id`, `skip` or `rename = \"...\"`"));
+            }
+            Ok(())
+        })?;
+    }
+    if attrs.id && (attrs.skip || attrs.rename.is_some()) {
+        return Err(syn::Error::new_spanned(field, "`#[nv(id)]` can't be combined with `skip` or `rename`"));
+    }
+    Ok(attrs)
+}
+
+/// `UserProfile` -> `user_profile`
+fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
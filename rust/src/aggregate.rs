@@ -0,0 +1,202 @@
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::{NVDocument, NVValue};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Aggregate function computed per group
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Aggregate {
+    /// Number of documents in the group
+    Count,
+    /// Sum of a numeric field (non-numbers are ignored)
+    Sum(String),
+    /// Mean of a numeric field (non-numbers are ignored)
+    Avg(String),
+    /// Smallest value of a numeric field (non-numbers are ignored)
+    Min(String),
+    /// Largest value of a numeric field (non-numbers are ignored)
+    Max(String),
+}
+
+impl AggregateDefinition {
+    /// Check that every aggregate can be maintained incrementally
+    ///
+    /// `Min` and `Max` can't: removing the current extreme would need the
+    /// group rescanned. They're available in pipelines instead.
+    pub fn validate(&self) -> NVResult<()> {
+        match self.aggregates.iter().find(|(_, aggregate)| matches!(aggregate, Aggregate::Min(_) | Aggregate::Max(_))) {
+            Some((name, _)) => Err(NeuralVaultError::ValidationError(format!(
+                "Aggregate '{}' can't be materialized; use Min and Max in a pipeline",
+                name
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Definition of a materialized aggregate view
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AggregateDefinition {
+    /// Collection being aggregated
+    pub collection: String,
+    /// Field to group by; `None` aggregates the whole collection
+    pub group_by: Option<String>,
+    /// Output name and function of each aggregate
+    pub aggregates: Vec<(String, Aggregate)>,
+}
+
+/// Running totals of one group
+#[derive(Debug, Clone, Default)]
+struct GroupState {
+    /// Group value (null when the group field is missing)
+    key: Option<NVValue>,
+    count: i64,
+    /// Per aggregate: sum of numeric values and how many there were
+    sums: Vec<(f64, i64)>,
+}
+
+/// Aggregate results kept up to date as documents change
+///
+/// Every change subtracts the old version's contribution and adds the new
+/// one, so results never require rescanning the collection.
+#[derive(Debug, Clone)]
+pub struct MaterializedAggregate {
+    definition: AggregateDefinition,
+    groups: HashMap<String, GroupState>,
+}
+
+impl MaterializedAggregate {
+    pub fn new(definition: AggregateDefinition) -> Self {
+        Self {
+            definition,
+            groups: HashMap::new(),
+        }
+    }
+
+    pub fn definition(&self) -> &AggregateDefinition {
+        &self.definition
+    }
+
+    /// Add a document's contribution
+    pub fn add(&mut self, document: &NVDocument) {
+        self.apply(document, 1);
+    }
+
+    /// Remove a document's contribution
+    pub fn remove(&mut self, document: &NVDocument) {
+        self.apply(document, -1);
+    }
+
+    fn apply(&mut self, document: &NVDocument, sign: i64) {
+        if document.collection != self.definition.collection || document.deleted {
+            return;
+        }
+
+        let key = self
+            .definition
+            .group_by
+            .as_ref()
+            .and_then(|field| document.get(field))
+            .cloned();
+        let group_id = serde_json::Value::from(key.clone().unwrap_or(NVValue::Null)).to_string();
+
+        let group = self.groups.entry(group_id.clone()).or_insert_with(|| GroupState {
+            key,
+            count: 0,
+            sums: vec![(0.0, 0); self.definition.aggregates.len()],
+        });
+
+        group.count += sign;
+        for ((_, aggregate), (sum, n)) in self.definition.aggregates.iter().zip(&mut group.sums) {
+            if let Aggregate::Sum(field) | Aggregate::Avg(field) = aggregate {
+                if let Some(NVValue::Number(value)) = document.get(field) {
+                    *sum += sign as f64 * value;
+                    *n += sign;
+                }
+            }
+        }
+
+        if group.count <= 0 {
+            self.groups.remove(&group_id);
+        }
+    }
+
+    /// One row per group, with the group value under the `group_by` field
+    /// name and each aggregate under its output name
+    pub fn rows(&self) -> Vec<HashMap<String, NVValue>> {
+        let mut groups: Vec<&GroupState> = self.groups.values().collect();
+        groups.sort_by_key(|group| {
+            serde_json::Value::from(group.key.clone().unwrap_or(NVValue::Null)).to_string()
+        });
+
+        groups
+            .into_iter()
+            .map(|group| {
+                let mut row = HashMap::new();
+                if let Some(field) = &self.definition.group_by {
+                    row.insert(field.clone(), group.key.clone().unwrap_or(NVValue::Null));
+                }
+                for ((name, aggregate), (sum, n)) in self.definition.aggregates.iter().zip(&group.sums) {
+                    let value = match aggregate {
+                        Aggregate::Count => NVValue::Number(group.count as f64),
+                        Aggregate::Sum(_) => NVValue::Number(*sum),
+                        Aggregate::Avg(_) if *n > 0 => NVValue::Number(sum / *n as f64),
+                        Aggregate::Avg(_) | Aggregate::Min(_) | Aggregate::Max(_) => NVValue::Null,
+                    };
+                    row.insert(name.clone(), value);
+                }
+                row
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(status: &str, total: f64) -> NVDocument {
+        let mut data = HashMap::new();
+        data.insert("status".to_string(), NVValue::String(status.to_string()));
+        data.insert("total".to_string(), NVValue::Number(total));
+        NVDocument::new(format!("{}-{}", status, total), "orders".to_string(), data)
+    }
+
+    #[test]
+    fn test_incremental_group_totals() {
+        let mut view = MaterializedAggregate::new(AggregateDefinition {
+            collection: "orders".to_string(),
+            group_by: Some("status".to_string()),
+            aggregates: vec![
+                ("count".to_string(), Aggregate::Count),
+                ("revenue".to_string(), Aggregate::Sum("total".to_string())),
+                ("avg".to_string(), Aggregate::Avg("total".to_string())),
+            ],
+        });
+
+        let paid = order("paid", 10.0);
+        view.add(&paid);
+        view.add(&order("paid", 30.0));
+        view.add(&order("open", 5.0));
+        view.remove(&paid);
+
+        let rows = view.rows();
+        assert_eq!(rows.len(), 2);
+        let paid_row = rows
+            .iter()
+            .find(|row| row["status"] == NVValue::String("paid".to_string()))
+            .unwrap();
+        assert_eq!(paid_row["count"], NVValue::Number(1.0));
+        assert_eq!(paid_row["revenue"], NVValue::Number(30.0));
+        assert_eq!(paid_row["avg"], NVValue::Number(30.0));
+
+        // Groups disappear once empty
+        view.remove(&order("open", 5.0));
+        assert_eq!(view.rows().len(), 1);
+
+        let mut extremes = view.definition().clone();
+        assert!(extremes.validate().is_ok());
+        extremes.aggregates.push(("largest".to_string(), Aggregate::Max("total".to_string())));
+        assert!(extremes.validate().is_err());
+    }
+}
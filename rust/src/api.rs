@@ -1,22 +1,98 @@
-use crate::database::{DatabaseStats, NeuralVault};
-use crate::error::{NeuralVaultError, NVResult};
-use crate::models::{DatabaseConfig, LogicalOperator, NVDocument, NVQuery, NVValue, QueryOperator, UpdateOperation};
-use std::collections::HashMap;
+use crate::aggregate::{Aggregate, AggregateDefinition};
+use crate::auth::{Access, Role};
+use crate::capped::CollectionCap;
+use crate::schema::CollectionSchema;
+use crate::chunking::ChunkOptions;
+use crate::database::NeuralVault;
+use crate::index::{HighlightOptions, IndexDefinition, TextIndexDefinition, VectorIndexDefinition};
+use crate::pipeline::{Interval, Stage};
+use crate::search::HybridQuery;
+use crate::query::{json, CancellationToken, Cursor, QueryCache, QueryTemplate};
+use crate::error::NeuralVaultError;
+use crate::replication::{Follower, FollowerHandle, ReplicationLeader};
+use crate::storage::{BackupKey, CompactionProgress};
+use crate::sync::ConflictResolver;
+use crate::timeseries::TimeSeriesOptions;
+use crate::wire::WireFormat;
+use chrono::{DateTime, Utc};
+use crate::models::{DatabaseConfig, IdStrategy, NVDocument, NVQuery, NVValue, OnDelete, Reference, UpdateOperation, WriteOp};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 /// Global database instance
 static DB_INSTANCE: Mutex<Option<Arc<NeuralVault>>> = Mutex::new(None);
 
+/// Oplog server for followers, while this instance is a leader
+static LEADER: Mutex<Option<ReplicationLeader>> = Mutex::new(None);
+
+/// Set by `cancel_compaction` to stop the running compaction
+static COMPACTION_CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Latest progress report of the running or last compaction
+static COMPACTION_PROGRESS: Mutex<Option<CompactionProgress>> = Mutex::new(None);
+
+/// Background sync, while this instance follows a leader
+static FOLLOWER: Mutex<Option<FollowerHandle>> = Mutex::new(None);
+
+/// Open query cursors, by handle, with when each was last read; a cursor
+/// being read is out of the map and `None` here
+static CURSORS: Mutex<BTreeMap<u64, (Option<Cursor>, Instant)>> = Mutex::new(BTreeMap::new());
+
+/// Handle of the next cursor opened
+static NEXT_CURSOR: AtomicU64 = AtomicU64::new(1);
+
+/// Queries parsed by `parse_query_json`, so repeated queries skip parsing
+static QUERY_CACHE: Mutex<QueryCache> = Mutex::new(QueryCache::new(QUERY_CACHE_CAPACITY));
+
+/// Shared by the queries running through this API; `cancel_queries`
+/// cancels it and starts a new one for later queries
+static QUERY_CANCELLATION: Mutex<Option<CancellationToken>> = Mutex::new(None);
+
+/// Most queries kept in `QUERY_CACHE`
+const QUERY_CACHE_CAPACITY: usize = 256;
+
+/// Most cursors kept open; opening another closes the least recently read
+const MAX_OPEN_CURSORS: usize = 256;
+
+/// How long a cursor stays open without being read
+const CURSOR_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Prepared query templates, by handle
+static TEMPLATES: Mutex<BTreeMap<u64, QueryTemplate>> = Mutex::new(BTreeMap::new());
+
+/// Handle of the next template prepared
+static NEXT_TEMPLATE: AtomicU64 = AtomicU64::new(1);
+
 /// Initialize database with configuration
 pub fn init_database(path: String) -> Result<String, String> {
-    let config = DatabaseConfig {
+    open_database(DatabaseConfig {
         path,
         ..Default::default()
-    };
+    })
+}
+
+/// Initialize the database from a JSON `DatabaseConfig`
+///
+/// Every option can be set, such as `{"path": "data", "kdf_memory_kib":
+/// 65536, "max_database_size": 1073741824, "flush_interval_ms": 50,
+/// "backup_dir": "backups", "transaction_retries": 3}`; fields left out
+/// keep their defaults.
+pub fn init_database_with_config(config_json: String) -> Result<String, String> {
+    let config: DatabaseConfig =
+        serde_json::from_str(&config_json).map_err(|e| format!("Invalid database config: {}", e))?;
+    open_database(config)
+}
+
+fn open_database(config: DatabaseConfig) -> Result<String, String> {
+    // Release any previous instance first so its directory lock is freed
+    let mut instance = DB_INSTANCE.lock().unwrap();
+    instance.take();
+    CURSORS.lock().unwrap().clear();
 
     match NeuralVault::new(config) {
         Ok(db) => {
-            let mut instance = DB_INSTANCE.lock().unwrap();
             *instance = Some(Arc::new(db));
             Ok("Database initialized successfully".to_string())
         }
@@ -24,6 +100,18 @@ pub fn init_database(path: String) -> Result<String, String> {
     }
 }
 
+/// Close the database
+///
+/// Dropping the instance persists its index so the next open is fast.
+pub fn close_database() -> Result<String, String> {
+    let mut instance = DB_INSTANCE.lock().unwrap();
+    CURSORS.lock().unwrap().clear();
+    match instance.take() {
+        Some(_) => Ok("Database closed successfully".to_string()),
+        None => Err("Database not initialized".to_string()),
+    }
+}
+
 /// Get database instance
 fn get_db() -> Result<Arc<NeuralVault>, String> {
     let instance = DB_INSTANCE.lock().unwrap();
@@ -51,6 +139,27 @@ pub fn create_document(
         .map_err(|e| format!("Create failed: {}", e))
 }
 
+/// Create a document with a caller-chosen ID
+///
+/// Errors starting with "Duplicate ID" mean a document with this ID exists.
+pub fn create_document_with_id(
+    id: String,
+    collection: String,
+    json_data: String,
+) -> Result<String, String> {
+    let db = get_db()?;
+
+    let json_value: serde_json::Value = serde_json::from_str(&json_data)
+        .map_err(|e| format!("Invalid JSON: {}", e))?;
+
+    let data = json_to_hashmap(json_value)?;
+
+    db.create_with_id(id, collection, data).map_err(|e| match e {
+        NeuralVaultError::DuplicateId(id) => format!("Duplicate ID: {}", id),
+        e => format!("Create failed: {}", e),
+    })
+}
+
 /// Find documents
 pub fn find_documents(
     collection: String,
@@ -61,7 +170,7 @@ pub fn find_documents(
     // Parse query
     let query = parse_query_json(collection, query_json)?;
 
-    let documents = db.find(query)
+    let documents = db.find_cancellable(query, &query_cancellation())
         .map_err(|e| format!("Find failed: {}", e))?;
 
     // Convert to JSON
@@ -71,6 +180,234 @@ pub fn find_documents(
     Ok(json)
 }
 
+/// Find documents along with how the query ran, as
+/// `{"documents": [...], "stats": {"scanned", "returned", "elapsed_ms", "index", "plan"}}`
+pub fn find_documents_with_stats(
+    collection: String,
+    query_json: String,
+) -> Result<String, String> {
+    let db = get_db()?;
+
+    let query = parse_query_json(collection, query_json)?;
+
+    let (documents, stats) = db.find_with_stats(query, &query_cancellation())
+        .map_err(|e| format!("Find failed: {}", e))?;
+
+    serde_json::to_string(&serde_json::json!({
+        "documents": documents,
+        "stats": stats,
+    }))
+    .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Find documents, encoded as "json", "msgpack" or "cbor"
+pub fn find_documents_encoded(
+    collection: String,
+    query_json: String,
+    format: String,
+) -> Result<Vec<u8>, String> {
+    let db = get_db()?;
+
+    let format = parse_wire_format(&format)?;
+    let query = parse_query_json(collection, query_json)?;
+
+    let documents = db.find_cancellable(query, &query_cancellation())
+        .map_err(|e| format!("Find failed: {}", e))?;
+
+    format.encode(&documents)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Run a SQL `SELECT` such as
+/// `SELECT * FROM users WHERE age > 22 ORDER BY name LIMIT 10`, returning
+/// the documents as JSON
+pub fn query_sql(statement: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let documents = db.query_sql(&statement)
+        .map_err(|e| format!("Query failed: {}", e))?;
+
+    serde_json::to_string(&documents)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Run a query and open a cursor over its results, returning the cursor's
+/// handle
+///
+/// Read the results with `next_batch` rather than all at once with
+/// `find_documents`, then release them with `close_cursor`. Cursors left
+/// unread for ten minutes are closed, as is the least recently read one
+/// once `MAX_OPEN_CURSORS` are open.
+pub fn open_cursor(collection: String, query_json: String) -> Result<u64, String> {
+    let db = get_db()?;
+
+    let query = parse_query_json(collection, query_json)?;
+
+    let cursor = db.open_cursor(query)
+        .map_err(|e| format!("Find failed: {}", e))?;
+
+    let handle = NEXT_CURSOR.fetch_add(1, Ordering::Relaxed);
+    let mut cursors = CURSORS.lock().unwrap();
+    evict_idle_cursors(&mut cursors);
+    if cursors.len() >= MAX_OPEN_CURSORS {
+        let oldest = cursors.iter().min_by_key(|(_, (_, read))| *read).map(|(handle, _)| *handle);
+        if let Some(oldest) = oldest {
+            cursors.remove(&oldest);
+        }
+    }
+    cursors.insert(handle, (Some(cursor), Instant::now()));
+    Ok(handle)
+}
+
+/// The next `n` results of a cursor as a JSON array; empty once they run out
+pub fn next_batch(cursor: u64, n: usize) -> Result<String, String> {
+    let documents = read_batch(cursor, n)?;
+
+    serde_json::to_string(&documents)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// `next_batch`, encoded as "json", "msgpack" or "cbor"
+pub fn next_batch_encoded(cursor: u64, n: usize, format: String) -> Result<Vec<u8>, String> {
+    let format = parse_wire_format(&format)?;
+
+    let documents = read_batch(cursor, n)?;
+
+    format.encode(&documents)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Read the next `n` results of an open cursor
+///
+/// The cursor is taken out of `CURSORS` while its documents are read, so
+/// reads of other cursors don't wait on this one. It's put back unless it
+/// was closed meanwhile.
+fn read_batch(cursor: u64, n: usize) -> Result<Vec<NVDocument>, String> {
+    let db = get_db()?;
+
+    let mut open = {
+        let mut cursors = CURSORS.lock().unwrap();
+        evict_idle_cursors(&mut cursors);
+        let (open, read) = cursors
+            .get_mut(&cursor)
+            .ok_or_else(|| format!("Unknown cursor: {}", cursor))?;
+        *read = Instant::now();
+        open.take()
+            .ok_or_else(|| format!("Cursor {} is already being read", cursor))?
+    };
+
+    let documents = db.next_batch(&mut open, n);
+    if let Some((slot, read)) = CURSORS.lock().unwrap().get_mut(&cursor) {
+        *slot = Some(open);
+        *read = Instant::now();
+    }
+
+    documents.map_err(|e| format!("Find failed: {}", e))
+}
+
+/// Close cursors that haven't been read for `CURSOR_IDLE_TIMEOUT`
+fn evict_idle_cursors(cursors: &mut BTreeMap<u64, (Option<Cursor>, Instant)>) {
+    cursors.retain(|_, (_, read)| read.elapsed() < CURSOR_IDLE_TIMEOUT);
+}
+
+/// Release a cursor's remaining results; false if it wasn't open
+pub fn close_cursor(cursor: u64) -> Result<bool, String> {
+    Ok(CURSORS.lock().unwrap().remove(&cursor).is_some())
+}
+
+/// Parse a query once for running many times, returning its handle
+///
+/// Condition values written as `":name"` are placeholders, filled in from
+/// the parameters given to `execute_prepared_query`.
+pub fn prepare_query(collection: String, query_json: String) -> Result<u64, String> {
+    let query = parse_query_json(collection, query_json)?;
+
+    let handle = NEXT_TEMPLATE.fetch_add(1, Ordering::Relaxed);
+    TEMPLATES.lock().unwrap().insert(handle, QueryTemplate::new(query));
+    Ok(handle)
+}
+
+/// Run a prepared query with parameters given as a JSON object, returning
+/// the documents as JSON
+pub fn execute_prepared_query(template: u64, params_json: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let params_value: serde_json::Value = serde_json::from_str(&params_json)
+        .map_err(|e| format!("Invalid JSON: {}", e))?;
+    let params = json_to_hashmap(params_value)?;
+
+    let query = TEMPLATES
+        .lock()
+        .unwrap()
+        .get(&template)
+        .ok_or_else(|| format!("Unknown prepared query: {}", template))?
+        .bind(&params)
+        .map_err(|e| format!("Find failed: {}", e))?;
+
+    let documents = db.find_cancellable(query, &query_cancellation())
+        .map_err(|e| format!("Find failed: {}", e))?;
+
+    serde_json::to_string(&documents)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Forget a prepared query; false if there was none
+pub fn drop_prepared_query(template: u64) -> Result<bool, String> {
+    Ok(TEMPLATES.lock().unwrap().remove(&template).is_some())
+}
+
+/// Hits, misses and size of the parsed query cache, as JSON
+pub fn query_cache_stats() -> Result<String, String> {
+    let stats = QUERY_CACHE.lock().unwrap().stats();
+    serde_json::to_string(&stats)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Empty the parsed query cache
+pub fn clear_query_cache() -> Result<String, String> {
+    QUERY_CACHE.lock().unwrap().clear();
+    Ok("Query cache cleared".to_string())
+}
+
+/// Warnings about a query that parses but can't match as written, as a
+/// JSON array of `{"condition", "field", "message"}`
+pub fn validate_query(
+    collection: String,
+    query_json: String,
+) -> Result<String, String> {
+    let db = get_db()?;
+
+    let warnings = db.validate_query(&collection, &query_json)
+        .map_err(|e| format!("Validation failed: {}", e))?;
+
+    serde_json::to_string(&warnings)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Describe how a query would be executed, as JSON
+pub fn explain_query(
+    collection: String,
+    query_json: String,
+) -> Result<String, String> {
+    let db = get_db()?;
+
+    let query = parse_query_json(collection, query_json)?;
+
+    let plan = db.explain(&query)
+        .map_err(|e| format!("Explain failed: {}", e))?;
+
+    serde_json::to_string(&plan)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Indexes recommended from the queries run so far, as JSON
+pub fn suggest_indexes() -> Result<String, String> {
+    let db = get_db()?;
+
+    serde_json::to_string(&db.suggest_indexes())
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
 /// Find document by ID
 pub fn find_document_by_id(id: String) -> Result<String, String> {
     let db = get_db()?;
@@ -84,6 +421,19 @@ pub fn find_document_by_id(id: String) -> Result<String, String> {
     Ok(json)
 }
 
+/// Find document by ID, encoded as "json", "msgpack" or "cbor"
+pub fn find_document_by_id_encoded(id: String, format: String) -> Result<Vec<u8>, String> {
+    let db = get_db()?;
+
+    let format = parse_wire_format(&format)?;
+
+    let document = db.find_by_id(&id)
+        .map_err(|e| format!("Find failed: {}", e))?;
+
+    format.encode(&document)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
 /// Update documents
 pub fn update_documents(
     collection: String,
@@ -114,6 +464,26 @@ pub fn update_document_by_id(
     Ok("Document updated successfully".to_string())
 }
 
+/// Update a document only if it matches a condition
+///
+/// `condition_json` is a single condition object as used in queries.
+/// Returns whether the update was applied.
+pub fn update_document_if(
+    id: String,
+    condition_json: String,
+    updates_json: String,
+) -> Result<bool, String> {
+    let db = get_db()?;
+
+    let condition: serde_json::Value = serde_json::from_str(&condition_json)
+        .map_err(|e| format!("Invalid condition JSON: {}", e))?;
+    let condition = json::parse_condition(&condition).map_err(|e| e.to_string())?;
+    let updates = parse_updates_json(updates_json)?;
+
+    db.update_if(&id, condition, updates)
+        .map_err(|e| format!("Update failed: {}", e))
+}
+
 /// Delete documents
 pub fn delete_documents(
     collection: String,
@@ -127,6 +497,19 @@ pub fn delete_documents(
         .map_err(|e| format!("Delete failed: {}", e))
 }
 
+/// Move documents matching a query into an archive segment
+pub fn archive_documents(
+    collection: String,
+    query_json: String,
+) -> Result<usize, String> {
+    let db = get_db()?;
+
+    let query = parse_query_json(collection, query_json)?;
+
+    db.archive(query)
+        .map_err(|e| format!("Archive failed: {}", e))
+}
+
 /// Delete document by ID
 pub fn delete_document_by_id(id: String) -> Result<String, String> {
     let db = get_db()?;
@@ -145,6 +528,17 @@ pub fn count_documents(collection: String) -> Result<usize, String> {
         .map_err(|e| format!("Count failed: {}", e))
 }
 
+/// Up to `n` random documents of a collection, as JSON
+pub fn sample_documents(collection: String, n: usize) -> Result<String, String> {
+    let db = get_db()?;
+
+    let documents = db.sample(&collection, n)
+        .map_err(|e| format!("Sample failed: {}", e))?;
+
+    serde_json::to_string(&documents)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
 /// Get all collections
 pub fn get_collections() -> Result<Vec<String>, String> {
     let db = get_db()?;
@@ -171,110 +565,1216 @@ pub fn get_stats() -> Result<String, String> {
     Ok(json)
 }
 
-// Helper functions
+/// Get the disk usage breakdown as JSON
+pub fn get_disk_usage() -> Result<String, String> {
+    let db = get_db()?;
 
-fn json_to_hashmap(value: serde_json::Value) -> Result<HashMap<String, NVValue>, String> {
-    match value {
-        serde_json::Value::Object(obj) => {
-            Ok(obj.into_iter().map(|(k, v)| (k, NVValue::from(v))).collect())
-        }
-        _ => Err("Expected JSON object".to_string()),
-    }
+    let usage = db.disk_usage()
+        .map_err(|e| format!("Failed to get disk usage: {}", e))?;
+
+    serde_json::to_string(&usage)
+        .map_err(|e| format!("Serialization failed: {}", e))
 }
 
-fn parse_query_json(collection: String, query_json: String) -> Result<NVQuery, String> {
-    if query_json.is_empty() || query_json == "{}" {
-        return Ok(NVQuery::new(collection));
-    }
+/// Apply a JSON array of write operations in one batch
+///
+/// Each operation is an object with an `op` of "insert", "update", "delete"
+/// or "upsert". Returns a JSON array with `{"id": ...}` or `{"error": ...}`
+/// per operation.
+pub fn bulk_write(ops_json: String) -> Result<String, String> {
+    let db = get_db()?;
 
-    let json: serde_json::Value = serde_json::from_str(&query_json)
-        .map_err(|e| format!("Invalid query JSON: {}", e))?;
+    let json: serde_json::Value = serde_json::from_str(&ops_json)
+        .map_err(|e| format!("Invalid JSON: {}", e))?;
 
-    let mut query = NVQuery::new(collection);
+    let ops = json.as_array()
+        .ok_or("Expected JSON array of operations")?
+        .iter()
+        .map(parse_write_op)
+        .collect::<Result<Vec<_>, _>>()?;
 
-    // Parse conditions
-    if let Some(conditions) = json.get("conditions").and_then(|v| v.as_array()) {
-        for (i, cond) in conditions.iter().enumerate() {
-            let field = cond.get("field")
-                .and_then(|v| v.as_str())
-                .ok_or("Missing field in condition")?
-                .to_string();
+    let results = db.bulk_write(ops)
+        .map_err(|e| format!("Bulk write failed: {}", e))?;
 
-            let operator_str = cond.get("operator")
-                .and_then(|v| v.as_str())
-                .ok_or("Missing operator in condition")?;
+    let report: Vec<serde_json::Value> = results
+        .into_iter()
+        .map(|result| match result {
+            Ok(id) => serde_json::json!({ "id": id }),
+            Err(e) => serde_json::json!({ "error": e.to_string() }),
+        })
+        .collect();
 
-            let operator = parse_operator(operator_str)?;
+    Ok(serde_json::Value::Array(report).to_string())
+}
 
-            let value = cond.get("value")
-                .ok_or("Missing value in condition")?
-                .clone();
+/// Import `mongoexport` output (Extended JSON, one document per line or a
+/// JSON array) into a collection, returning each document's ID or error
+pub fn import_mongo_export(collection: String, export: String) -> Result<String, String> {
+    let db = get_db()?;
 
-            let logical_op = if i > 0 {
-                let op_str = cond.get("logical")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("and");
-                Some(parse_logical_operator(op_str)?)
-            } else {
-                None
-            };
+    let results = db.import_mongo(&collection, &export)
+        .map_err(|e| format!("Import failed: {}", e))?;
 
-            query.add_condition(field, operator, NVValue::from(value), logical_op);
-        }
-    }
+    let report: Vec<serde_json::Value> = results
+        .into_iter()
+        .map(|result| match result {
+            Ok(id) => serde_json::json!({ "id": id }),
+            Err(e) => serde_json::json!({ "error": e.to_string() }),
+        })
+        .collect();
 
-    // Parse order_by
-    if let Some(order_by) = json.get("order_by").and_then(|v| v.as_str()) {
-        query.order_by = Some(order_by.to_string());
-        query.order_desc = json.get("order_desc").and_then(|v| v.as_bool()).unwrap_or(false);
-    }
+    Ok(serde_json::Value::Array(report).to_string())
+}
 
-    // Parse limit and skip
-    query.limit = json.get("limit").and_then(|v| v.as_u64()).map(|v| v as usize);
-    query.skip = json.get("skip").and_then(|v| v.as_u64()).map(|v| v as usize);
+/// Import the tables of a SQLite database, returning a JSON report per
+/// table
+///
+/// Options are JSON like `{"tables": ["users"], "collections": {"users":
+/// "people"}, "skip_nulls": true, "blobs": "skip"}`; fields left out keep
+/// their defaults, so `{}` imports every table into the collection of the
+/// same name.
+#[cfg(feature = "sqlite")]
+pub fn import_sqlite_database(path: String, options_json: String) -> Result<String, String> {
+    let db = get_db()?;
 
-    Ok(query)
+    let options: crate::import::SqliteImportOptions = serde_json::from_str(&options_json)
+        .map_err(|e| format!("Invalid import options: {}", e))?;
+
+    let tables = db
+        .import_sqlite(std::path::Path::new(&path), options)
+        .map_err(|e| format!("Import failed: {}", e))?;
+
+    serde_json::to_string(&tables).map_err(|e| format!("Serialization failed: {}", e))
 }
 
-fn parse_updates_json(updates_json: String) -> Result<Vec<UpdateOperation>, String> {
-    let json: serde_json::Value = serde_json::from_str(&updates_json)
-        .map_err(|e| format!("Invalid updates JSON: {}", e))?;
+/// Export every collection into a directory as "parquet" or "arrow" (Arrow
+/// IPC) files, returning a JSON report per collection
+#[cfg(feature = "analytics")]
+pub fn export_collections(directory: String, format: String) -> Result<String, String> {
+    let db = get_db()?;
 
-    let mut updates = Vec::new();
+    let format = match format.as_str() {
+        "parquet" => crate::export::ExportFormat::Parquet,
+        "arrow" => crate::export::ExportFormat::ArrowIpc,
+        other => return Err(format!("Unknown export format: {}", other)),
+    };
+    let exported = db
+        .export_collections(std::path::Path::new(&directory), format)
+        .map_err(|e| format!("Export failed: {}", e))?;
 
-    if let serde_json::Value::Object(obj) = json {
-        for (field, value) in obj {
-            updates.push(UpdateOperation {
-                field,
-                value: NVValue::from(value),
-            });
-        }
-    }
+    serde_json::to_string(&exported).map_err(|e| format!("Serialization failed: {}", e))
+}
 
-    Ok(updates)
+/// Set a collection's ID strategy ("uuid", "ulid", "auto_increment" or "provided")
+pub fn set_id_strategy(collection: String, strategy: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let strategy = IdStrategy::parse(&strategy)
+        .ok_or_else(|| format!("Unknown ID strategy: {}", strategy))?;
+
+    db.set_id_strategy(&collection, strategy)
+        .map_err(|e| format!("Failed to set ID strategy: {}", e))?;
+
+    Ok("ID strategy updated successfully".to_string())
 }
 
-fn parse_operator(op: &str) -> Result<QueryOperator, String> {
-    match op {
-        "==" | "equals" => Ok(QueryOperator::Equals),
-        "!=" | "not_equals" => Ok(QueryOperator::NotEquals),
-        ">" | "greater_than" => Ok(QueryOperator::GreaterThan),
-        ">=" | "greater_than_or_equal" => Ok(QueryOperator::GreaterThanOrEqual),
-        "<" | "less_than" => Ok(QueryOperator::LessThan),
-        "<=" | "less_than_or_equal" => Ok(QueryOperator::LessThanOrEqual),
-        "contains" => Ok(QueryOperator::Contains),
-        "starts_with" => Ok(QueryOperator::StartsWith),
-        "ends_with" => Ok(QueryOperator::EndsWith),
-        "in" => Ok(QueryOperator::In),
-        "not_in" => Ok(QueryOperator::NotIn),
-        _ => Err(format!("Unknown operator: {}", op)),
-    }
+/// Declare a collection's fields from JSON like
+/// `{"fields": {"status": {"default": {"value": "open"}}, "number": {"default": "sequence"}}}`;
+/// defaults are `{"value": ..}`, `"now"`, `"uuid"` or `"sequence"`
+pub fn set_schema(collection: String, schema_json: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let schema: CollectionSchema = serde_json::from_str(&schema_json)
+        .map_err(|e| format!("Invalid schema: {}", e))?;
+
+    db.set_schema(&collection, schema)
+        .map_err(|e| format!("Failed to set schema: {}", e))?;
+
+    Ok("Schema set successfully".to_string())
 }
 
-fn parse_logical_operator(op: &str) -> Result<LogicalOperator, String> {
-    match op.to_lowercase().as_str() {
-        "and" | "&&" => Ok(LogicalOperator::And),
-        "or" | "||" => Ok(LogicalOperator::Or),
-        _ => Err(format!("Unknown logical operator: {}", op)),
-    }
+/// A collection's schema as JSON, or `null` if it has none
+pub fn get_schema(collection: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    serde_json::to_string(&db.schema(&collection))
+        .map_err(|e| format!("Failed to serialize schema: {}", e))
+}
+
+/// Remove a collection's schema, returning whether it had one
+pub fn remove_schema(collection: String) -> Result<bool, String> {
+    let db = get_db()?;
+
+    db.remove_schema(&collection)
+        .map_err(|e| format!("Failed to remove schema: {}", e))
+}
+
+/// Cap a collection from JSON like `{"max_documents": 1000, "max_bytes": 1048576}`;
+/// either limit may be left out
+pub fn set_capped(collection: String, cap_json: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let cap: CollectionCap = serde_json::from_str(&cap_json)
+        .map_err(|e| format!("Invalid cap: {}", e))?;
+
+    db.set_capped(&collection, cap)
+        .map_err(|e| format!("Failed to cap collection: {}", e))?;
+
+    Ok("Collection capped successfully".to_string())
+}
+
+/// Remove a collection's cap, returning whether it had one
+pub fn uncap(collection: String) -> Result<bool, String> {
+    let db = get_db()?;
+
+    db.uncap(&collection)
+        .map_err(|e| format!("Failed to uncap collection: {}", e))
+}
+
+/// Make a collection a time series from JSON like
+/// `{"time_field": "at", "bucket_secs": 3600, "retention_secs": 86400}`
+pub fn set_time_series(collection: String, options_json: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let options: TimeSeriesOptions = serde_json::from_str(&options_json)
+        .map_err(|e| format!("Invalid time series options: {}", e))?;
+
+    db.set_time_series(&collection, options)
+        .map_err(|e| format!("Failed to set time series: {}", e))?;
+
+    Ok("Time series set successfully".to_string())
+}
+
+/// Events of a time series between two times in milliseconds since the
+/// epoch, oldest first, as JSON
+pub fn find_time_range(collection: String, from_ms: i64, to_ms: i64) -> Result<String, String> {
+    let db = get_db()?;
+
+    let documents = db.find_time_range(&collection, millis_to_date(from_ms)?, millis_to_date(to_ms)?)
+        .map_err(|e| format!("Time range query failed: {}", e))?;
+
+    serde_json::to_string(&documents)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Roll up a time series into one row per `interval_secs`, with
+/// aggregates given as JSON like `[["mean", {"Avg": "value"}], ["peak", {"Max": "value"}]]`
+pub fn downsample(
+    collection: String,
+    from_ms: i64,
+    to_ms: i64,
+    interval_secs: u64,
+    aggregates_json: String,
+) -> Result<String, String> {
+    let db = get_db()?;
+
+    let aggregates: Vec<(String, Aggregate)> = serde_json::from_str(&aggregates_json)
+        .map_err(|e| format!("Invalid aggregates: {}", e))?;
+
+    let rows = db
+        .downsample(
+            &collection,
+            millis_to_date(from_ms)?,
+            millis_to_date(to_ms)?,
+            Interval::Seconds(interval_secs),
+            &aggregates,
+        )
+        .map_err(|e| format!("Downsampling failed: {}", e))?;
+
+    serde_json::to_string(&rows)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Delete time-series events past their retention, returning how many
+pub fn apply_retention() -> Result<usize, String> {
+    let db = get_db()?;
+
+    db.apply_retention()
+        .map_err(|e| format!("Retention failed: {}", e))
+}
+
+fn millis_to_date(millis: i64) -> Result<DateTime<Utc>, String> {
+    DateTime::from_timestamp_millis(millis).ok_or_else(|| format!("Time out of range: {}", millis))
 }
+
+/// Declare that `collection.field` references documents in `target`
+///
+/// `on_delete` is "cascade", "set_null" or "restrict".
+pub fn add_reference(
+    collection: String,
+    field: String,
+    target: String,
+    on_delete: String,
+) -> Result<String, String> {
+    let db = get_db()?;
+
+    let on_delete = OnDelete::parse(&on_delete)
+        .ok_or_else(|| format!("Unknown on_delete action: {}", on_delete))?;
+
+    db.add_reference(Reference { collection, field, target, on_delete })
+        .map_err(|e| format!("Failed to add reference: {}", e))?;
+
+    Ok("Reference added successfully".to_string())
+}
+
+/// Provide the 32-byte key for encrypted fields
+pub fn unlock_fields(key: Vec<u8>) -> Result<String, String> {
+    let db = get_db()?;
+
+    db.unlock_fields(&key)
+        .map_err(|e| format!("Failed to unlock fields: {}", e))?;
+
+    Ok("Fields unlocked successfully".to_string())
+}
+
+/// Document changes after `sequence` as JSON; pass the returned `next`
+/// to continue
+pub fn changes_since(sequence: u64) -> Result<String, String> {
+    let db = get_db()?;
+
+    let changes = db.changes_since(sequence)
+        .map_err(|e| format!("Reading changes failed: {}", e))?;
+
+    serde_json::to_string(&changes)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Apply a JSON array of write operations all together, returning the
+/// document IDs as a JSON array
+///
+/// Unlike `bulk_write`, a conflicting operation fails the whole batch
+/// before anything is written.
+pub fn commit_transaction(ops_json: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let ops: Vec<WriteOp> = serde_json::from_str(&ops_json)
+        .map_err(|e| format!("Invalid operations JSON: {}", e))?;
+
+    let mut tx = db.transaction();
+    for op in ops {
+        tx.write(op);
+    }
+    let ids = tx.commit()
+        .map_err(|e| format!("Transaction failed: {}", e))?;
+
+    serde_json::to_string(&ids)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Make a collection's documents merge field by field during sync
+pub fn enable_crdt(collection: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    db.enable_crdt(&collection)
+        .map_err(|e| format!("Failed to enable CRDT: {}", e))?;
+
+    Ok("CRDT enabled successfully".to_string())
+}
+
+/// Sync with the database at `path`, settling conflicts by last writer
+/// wins; returns a JSON report
+pub fn sync_with_database(path: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let remote = NeuralVault::new(DatabaseConfig {
+        path,
+        ..Default::default()
+    })
+    .map_err(|e| format!("Failed to open remote database: {}", e))?;
+
+    let report = crate::sync::sync(&db, &remote, &ConflictResolver::LastWriterWins)
+        .map_err(|e| format!("Sync failed: {}", e))?;
+
+    serde_json::to_string(&report)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Serve the oplog to followers, returning the address listened on
+pub fn start_replication_leader(address: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let leader = ReplicationLeader::start(db, &address)
+        .map_err(|e| format!("Failed to start leader: {}", e))?;
+    let address = leader.address().to_string();
+    *LEADER.lock().unwrap() = Some(leader);
+
+    Ok(address)
+}
+
+/// Stop serving the oplog
+pub fn stop_replication_leader() -> Result<String, String> {
+    match LEADER.lock().unwrap().take() {
+        Some(_) => Ok("Leader stopped successfully".to_string()),
+        None => Err("Not running as a leader".to_string()),
+    }
+}
+
+/// Follow the leader at `address`, syncing every `interval_ms`; `token`
+/// must have read access to every collection on the leader
+pub fn follow_leader(address: String, token: String, interval_ms: u64) -> Result<String, String> {
+    let db = get_db()?;
+
+    let handle = Follower::new(db, address, token).start(std::time::Duration::from_millis(interval_ms));
+    *FOLLOWER.lock().unwrap() = Some(handle);
+
+    Ok("Following leader".to_string())
+}
+
+/// Stop following, promoting this instance to take its own writes
+pub fn stop_following() -> Result<String, String> {
+    match FOLLOWER.lock().unwrap().take() {
+        Some(handle) => {
+            handle.stop();
+            Ok("Stopped following successfully".to_string())
+        }
+        None => Err("Not following a leader".to_string()),
+    }
+}
+
+/// Turn the audit trail of document writes on or off
+pub fn enable_audit_log(enabled: bool) -> Result<String, String> {
+    let db = get_db()?;
+
+    db.enable_audit_log(enabled)
+        .map_err(|e| format!("Failed to configure audit log: {}", e))?;
+
+    Ok("Audit log configured successfully".to_string())
+}
+
+/// Record `actor` as the author of later writes to the open database,
+/// whichever thread makes them, or clear it with `None`
+pub fn set_actor(actor: Option<String>) -> Result<String, String> {
+    let db = get_db()?;
+
+    db.set_actor(actor);
+    Ok("Actor set successfully".to_string())
+}
+
+/// Create or replace a role from a JSON map of collection to access,
+/// e.g. `{"posts": "write", "*": "read"}`
+pub fn create_role(name: String, permissions_json: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let json: HashMap<String, String> = serde_json::from_str(&permissions_json)
+        .map_err(|e| format!("Invalid permissions JSON: {}", e))?;
+    let permissions = json
+        .into_iter()
+        .map(|(collection, access)| {
+            Access::parse(&access)
+                .map(|access| (collection, access))
+                .ok_or_else(|| format!("Unknown access level: {}", access))
+        })
+        .collect::<Result<_, _>>()?;
+
+    db.create_role(Role { name, permissions })
+        .map_err(|e| format!("Failed to create role: {}", e))?;
+
+    Ok("Role created successfully".to_string())
+}
+
+/// Create or replace a user with a JSON array of role names
+pub fn create_user(name: String, roles_json: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let roles: Vec<String> = serde_json::from_str(&roles_json)
+        .map_err(|e| format!("Invalid roles JSON: {}", e))?;
+
+    db.create_user(&name, roles)
+        .map_err(|e| format!("Failed to create user: {}", e))?;
+
+    Ok("User created successfully".to_string())
+}
+
+/// Issue an API token for a user
+pub fn issue_token(user: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    db.issue_token(&user)
+        .map_err(|e| format!("Failed to issue token: {}", e))
+}
+
+/// Revoke an API token
+pub fn revoke_token(token: String) -> Result<bool, String> {
+    let db = get_db()?;
+
+    db.revoke_token(&token)
+        .map_err(|e| format!("Failed to revoke token: {}", e))
+}
+
+/// Check a token's access ("read" or "write") to a collection, returning
+/// the user name
+pub fn authorize(token: String, collection: String, access: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let access = Access::parse(&access)
+        .ok_or_else(|| format!("Unknown access level: {}", access))?;
+
+    db.authorize(&token, &collection, access)
+        .map_err(|e| format!("Authorization failed: {}", e))
+}
+
+/// Unlock encrypted fields with a password, setting it on first use
+pub fn unlock_with_password(password: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    db.unlock_with_password(&password)
+        .map_err(|e| format!("Failed to unlock fields: {}", e))?;
+
+    Ok("Fields unlocked successfully".to_string())
+}
+
+/// Change the password protecting encrypted fields
+pub fn change_password(old_password: String, new_password: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    db.change_password(&old_password, &new_password)
+        .map_err(|e| format!("Failed to change password: {}", e))?;
+
+    Ok("Password changed successfully".to_string())
+}
+
+/// Re-encrypt all encrypted fields under a new random master key
+pub fn rotate_master_key(password: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    db.rotate_master_key(&password)
+        .map_err(|e| format!("Key rotation failed: {}", e))?;
+
+    Ok("Master key rotated successfully".to_string())
+}
+
+/// Re-encrypt all encrypted fields under a new 32-byte key
+pub fn rotate_encryption_key(old_key: Vec<u8>, new_key: Vec<u8>) -> Result<String, String> {
+    let db = get_db()?;
+
+    db.rotate_encryption_key(&old_key, &new_key)
+        .map_err(|e| format!("Key rotation failed: {}", e))?;
+
+    Ok("Encryption key rotated successfully".to_string())
+}
+
+/// Back up the database into a new directory under `destination`,
+/// returning its path and sequence number as JSON
+pub fn backup_database(destination: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let info = db.backup(&destination)
+        .map_err(|e| format!("Backup failed: {}", e))?;
+
+    serde_json::to_string(&info)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Write the changes since `sequence` to an incremental backup file under
+/// `destination`, returning its path and new sequence number as JSON
+pub fn backup_database_incremental(destination: String, sequence: u64) -> Result<String, String> {
+    let db = get_db()?;
+
+    let info = db.backup_incremental(&destination, sequence)
+        .map_err(|e| format!("Backup failed: {}", e))?;
+
+    serde_json::to_string(&info)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Encrypt backups with a key derived from `passphrase`, or stop
+/// encrypting them with `None`
+pub fn set_backup_passphrase(passphrase: Option<String>) -> Result<String, String> {
+    let db = get_db()?;
+
+    db.set_backup_key(passphrase.map(BackupKey::Passphrase));
+
+    Ok("Backup passphrase set successfully".to_string())
+}
+
+/// Restore a full backup and a chain of incremental backups into a new
+/// database at `path`, which becomes the open database; `passphrase`
+/// decrypts encrypted backups
+pub fn restore_database(
+    path: String,
+    full: String,
+    incrementals: Vec<String>,
+    passphrase: Option<String>,
+) -> Result<String, String> {
+    let config = DatabaseConfig {
+        path,
+        ..Default::default()
+    };
+
+    let mut instance = DB_INSTANCE.lock().unwrap();
+    instance.take();
+
+    let key = passphrase.map(BackupKey::Passphrase);
+    let db = NeuralVault::restore(config, &full, &incrementals, key.as_ref())
+        .map_err(|e| format!("Restore failed: {}", e))?;
+    *instance = Some(Arc::new(db));
+
+    Ok("Database restored successfully".to_string())
+}
+
+/// Make every write durable
+pub fn flush_database() -> Result<String, String> {
+    let db = get_db()?;
+
+    db.flush()
+        .map_err(|e| format!("Flush failed: {}", e))?;
+
+    Ok("Database flushed successfully".to_string())
+}
+
+/// Rewrite the data files without superseded and deleted documents
+pub fn compact_database() -> Result<String, String> {
+    let db = get_db()?;
+
+    COMPACTION_CANCELLED.store(false, Ordering::SeqCst);
+    db.compact_with_progress(|progress| {
+        *COMPACTION_PROGRESS.lock().unwrap() = Some(*progress);
+        !COMPACTION_CANCELLED.load(Ordering::SeqCst)
+    })
+    .map_err(|e| format!("Compaction failed: {}", e))?;
+
+    Ok("Database compacted successfully".to_string())
+}
+
+/// Salvage every readable document into a new data file, returning a JSON
+/// report of what was recovered and lost
+pub fn recover_database() -> Result<String, String> {
+    let db = get_db()?;
+
+    let report = db.recover()
+        .map_err(|e| format!("Recovery failed: {}", e))?;
+
+    serde_json::to_string(&report)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Ask a running `compact_database` to stop; it fails with a cancellation
+/// error and leaves the database unchanged
+pub fn cancel_compaction() -> Result<String, String> {
+    COMPACTION_CANCELLED.store(true, Ordering::SeqCst);
+    Ok("Compaction cancellation requested".to_string())
+}
+
+/// Progress of the running or last compaction as JSON, or `null`
+pub fn get_compaction_progress() -> Result<String, String> {
+    let progress = *COMPACTION_PROGRESS.lock().unwrap();
+    serde_json::to_string(&progress)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Store a field encrypted with the field key
+pub fn encrypt_field(collection: String, field: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    db.encrypt_field(&collection, &field)
+        .map_err(|e| format!("Failed to encrypt field: {}", e))?;
+
+    Ok("Field encrypted successfully".to_string())
+}
+
+/// Save a query on `collection` as a named view
+///
+/// The view can then be passed as the collection to `find_documents`.
+pub fn create_view(name: String, collection: String, query_json: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let query = parse_query_json(collection, query_json)?;
+
+    db.create_view(&name, query)
+        .map_err(|e| format!("Failed to create view: {}", e))?;
+
+    Ok("View created successfully".to_string())
+}
+
+/// Remove a saved view
+pub fn drop_view(name: String) -> Result<bool, String> {
+    let db = get_db()?;
+
+    db.drop_view(&name)
+        .map_err(|e| format!("Failed to drop view: {}", e))
+}
+
+/// Create a materialized aggregate view from a JSON definition
+///
+/// Example: `{"collection": "orders", "group_by": "status",
+/// "aggregates": [["count", "Count"], ["revenue", {"Sum": "total"}]]}`
+pub fn create_aggregate_view(name: String, definition_json: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let definition: AggregateDefinition = serde_json::from_str(&definition_json)
+        .map_err(|e| format!("Invalid aggregate definition: {}", e))?;
+
+    db.create_aggregate_view(&name, definition)
+        .map_err(|e| format!("Failed to create aggregate view: {}", e))?;
+
+    Ok("Aggregate view created successfully".to_string())
+}
+
+/// Get the rows of a materialized aggregate view as JSON
+pub fn get_aggregate_view(name: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let rows = db.aggregate_view(&name)
+        .map_err(|e| format!("Failed to get aggregate view: {}", e))?;
+
+    serde_json::to_string(&rows)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Run an aggregation pipeline given as a JSON array of stages, returning
+/// the resulting rows as JSON
+///
+/// Example: `[{"Match": {"conditions": [{"field": "status", "operator":
+/// "Equals", "value": "paid"}]}}, {"Group": {"by": "region", "aggregates":
+/// [["revenue", {"Sum": "total"}]]}}, {"Sort": {"field": "revenue",
+/// "descending": true}}, {"Limit": 5}]`
+pub fn aggregate_pipeline(collection: String, pipeline_json: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let stages: Vec<Stage> = serde_json::from_str(&pipeline_json)
+        .map_err(|e| format!("Invalid pipeline: {}", e))?;
+
+    let rows = db.aggregate_cancellable(&collection, stages, &query_cancellation())
+        .map_err(|e| format!("Aggregation failed: {}", e))?;
+
+    serde_json::to_string(&rows)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Stop the finds and aggregations running through this API, which fail
+/// with a cancellation error; queries started afterwards run normally
+pub fn cancel_queries() -> Result<String, String> {
+    if let Some(token) = QUERY_CANCELLATION.lock().unwrap().take() {
+        token.cancel();
+    }
+    Ok("Query cancellation requested".to_string())
+}
+
+/// Token for a query starting now, shared with the others running
+fn query_cancellation() -> CancellationToken {
+    QUERY_CANCELLATION.lock().unwrap().get_or_insert_with(CancellationToken::new).clone()
+}
+
+/// Create a secondary index from a JSON definition
+///
+/// Example: `{"name": "open_tasks", "collection": "tasks", "fields": ["due"],
+/// "filter": [{"field": "status", "operator": "==", "value": "open"}]}`
+pub fn create_index(definition_json: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let json: serde_json::Value = serde_json::from_str(&definition_json)
+        .map_err(|e| format!("Invalid JSON: {}", e))?;
+
+    let string_field = |name: &str| -> Result<String, String> {
+        json.get(name)
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+            .ok_or_else(|| format!("Missing {} in index definition", name))
+    };
+
+    let fields = json.get("fields")
+        .and_then(|v| v.as_array())
+        .ok_or("Missing fields in index definition")?
+        .iter()
+        .map(|v| v.as_str().map(str::to_string).ok_or("Index fields must be strings"))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let filter = match json.get("filter").and_then(|v| v.as_array()) {
+        Some(conditions) => conditions
+            .iter()
+            .map(|condition| json::parse_condition(condition).map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => Vec::new(),
+    };
+
+    db.create_index(IndexDefinition {
+        name: string_field("name")?,
+        collection: string_field("collection")?,
+        fields,
+        filter,
+    })
+    .map_err(|e| format!("Failed to create index: {}", e))?;
+
+    Ok("Index created successfully".to_string())
+}
+
+/// Remove a secondary index
+pub fn drop_index(name: String) -> Result<bool, String> {
+    let db = get_db()?;
+
+    db.drop_index(&name)
+        .map_err(|e| format!("Failed to drop index: {}", e))
+}
+
+/// Create a full-text index from a JSON definition
+///
+/// Example: `{"name": "note_text", "collection": "notes", "fields": ["title", "body"]}`.
+/// An optional `"tokenizer"` such as `{"language": "english", "stemming":
+/// true, "stop_words": true, "extra_stop_words": ["vault"], "cjk_ngrams": 2}`
+/// changes how text is split into terms; languages are "english" (the
+/// default), "french", "german" and "spanish".
+pub fn create_text_index(definition_json: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let definition: TextIndexDefinition = serde_json::from_str(&definition_json)
+        .map_err(|e| format!("Invalid text index definition: {}", e))?;
+
+    db.create_text_index(definition)
+        .map_err(|e| format!("Failed to create text index: {}", e))?;
+
+    Ok("Text index created successfully".to_string())
+}
+
+/// Create an autocomplete index over the values of a field
+pub fn create_completion_index(collection: String, field: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    db.create_completion_index(&collection, &field)
+        .map_err(|e| format!("Failed to create autocomplete index: {}", e))?;
+
+    Ok("Autocomplete index created successfully".to_string())
+}
+
+/// Remove the autocomplete index on a field
+pub fn drop_completion_index(collection: String, field: String) -> Result<bool, String> {
+    let db = get_db()?;
+
+    db.drop_completion_index(&collection, &field)
+        .map_err(|e| format!("Failed to drop autocomplete index: {}", e))
+}
+
+/// Complete a prefix to the most common values of a field, as a JSON
+/// array of `{"value": ..., "count": ...}`
+pub fn suggest(collection: String, field: String, prefix: String, k: usize) -> Result<String, String> {
+    let db = get_db()?;
+
+    let suggestions = db.suggest(&collection, &field, &prefix, k)
+        .map_err(|e| format!("Autocomplete failed: {}", e))?;
+
+    let json: Vec<serde_json::Value> = suggestions
+        .into_iter()
+        .map(|(value, count)| serde_json::json!({"value": value, "count": count}))
+        .collect();
+    serde_json::to_string(&json)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Remove a full-text index
+pub fn drop_text_index(name: String) -> Result<bool, String> {
+    let db = get_db()?;
+
+    db.drop_text_index(&name)
+        .map_err(|e| format!("Failed to drop text index: {}", e))
+}
+
+/// Find the `k` documents most relevant to `text`, as a JSON array of
+/// `{"document": ..., "score": ...}`, best first
+pub fn search_text(index: String, text: String, k: usize) -> Result<String, String> {
+    let db = get_db()?;
+
+    let matches = db.search_text(&index, &text, k)
+        .map_err(|e| format!("Text search failed: {}", e))?;
+
+    scored_documents_json(matches, "score")
+}
+
+/// Like `search_text`, also matching words within `max_distance` edits of
+/// the query's
+pub fn search_text_fuzzy(index: String, text: String, k: usize, max_distance: usize) -> Result<String, String> {
+    let db = get_db()?;
+
+    let matches = db.search_text_fuzzy(&index, &text, k, max_distance)
+        .map_err(|e| format!("Text search failed: {}", e))?;
+
+    scored_documents_json(matches, "score")
+}
+
+/// Like `search_text`, adding `"highlights"` to each result: per matching
+/// field, the character ranges of the matches and a marked-up snippet
+///
+/// Example options: `{"pre_tag": "<em>", "post_tag": "</em>",
+/// "snippet_terms": 20, "max_distance": 1}`; any may be left out, and
+/// matches are wrapped in `**` by default.
+pub fn search_text_highlighted(
+    index: String,
+    text: String,
+    k: usize,
+    options_json: String,
+) -> Result<String, String> {
+    let db = get_db()?;
+
+    let options: HighlightOptions = serde_json::from_str(&options_json)
+        .map_err(|e| format!("Invalid highlight options: {}", e))?;
+
+    let matches = db.search_text_highlighted(&index, &text, k, &options)
+        .map_err(|e| format!("Text search failed: {}", e))?;
+
+    let json: Vec<serde_json::Value> = matches
+        .into_iter()
+        .map(|(document, score, highlights)| {
+            serde_json::json!({"document": document, "score": score, "highlights": highlights})
+        })
+        .collect();
+    serde_json::to_string(&json)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Rank documents by text relevance and vector similarity together
+///
+/// Example: `{"text_index": "note_text", "text": "rust storage",
+/// "vector_index": "note_embeddings", "vector": [0.1, 0.3], "limit": 10}`.
+/// Rankings are fused by reciprocal rank unless `"fusion"` is
+/// `{"weighted": {"text": 0.3, "vector": 0.7}}` or
+/// `{"reciprocal_rank": {"k": 60}}`. Returns a JSON array of
+/// `{"document": ..., "score": ...}`, best first.
+pub fn hybrid_search(query_json: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let query: HybridQuery = serde_json::from_str(&query_json)
+        .map_err(|e| format!("Invalid hybrid query: {}", e))?;
+
+    let matches = db.hybrid_search(&query)
+        .map_err(|e| format!("Hybrid search failed: {}", e))?;
+
+    scored_documents_json(matches, "score")
+}
+
+/// Create a vector index from a JSON definition
+///
+/// Example: `{"name": "note_embeddings", "collection": "notes", "field":
+/// "embedding", "dimensions": 384, "metric": "cosine"}`. The metric may be
+/// "cosine" (the default), "euclidean" or "dot". Vectors are stored as f32s
+/// unless `"quantization"` is "int8" or `{"product": {"subvectors": 48}}`.
+pub fn create_vector_index(definition_json: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let definition: VectorIndexDefinition = serde_json::from_str(&definition_json)
+        .map_err(|e| format!("Invalid vector index definition: {}", e))?;
+
+    db.create_vector_index(definition)
+        .map_err(|e| format!("Failed to create vector index: {}", e))?;
+
+    Ok("Vector index created successfully".to_string())
+}
+
+/// Remove a vector index
+pub fn drop_vector_index(name: String) -> Result<bool, String> {
+    let db = get_db()?;
+
+    db.drop_vector_index(&name)
+        .map_err(|e| format!("Failed to drop vector index: {}", e))
+}
+
+/// Find the `k` documents nearest to a JSON vector, as a JSON array of
+/// `{"document": ..., "distance": ...}`, closest first
+pub fn find_similar(index: String, vector_json: String, k: usize) -> Result<String, String> {
+    let db = get_db()?;
+
+    let vector: Vec<f32> = serde_json::from_str(&vector_json)
+        .map_err(|e| format!("Invalid vector: {}", e))?;
+
+    let matches = db.find_similar(&index, &vector, k)
+        .map_err(|e| format!("Similarity search failed: {}", e))?;
+
+    scored_documents_json(matches, "distance")
+}
+
+/// Find the `k` documents nearest to a JSON vector among those matching a
+/// query (only its conditions are used), in the same form as `find_similar`
+pub fn find_similar_where(
+    index: String,
+    vector_json: String,
+    k: usize,
+    query_json: String,
+) -> Result<String, String> {
+    let db = get_db()?;
+
+    let vector: Vec<f32> = serde_json::from_str(&vector_json)
+        .map_err(|e| format!("Invalid vector: {}", e))?;
+    let collection = db.vector_indexes()
+        .into_iter()
+        .find(|(definition, _)| definition.name == index)
+        .map(|(definition, _)| definition.collection)
+        .ok_or_else(|| format!("Vector index '{}' not found", index))?;
+    let filter = parse_query_json(collection, query_json)?;
+
+    let matches = db.find_similar_where(&index, &vector, k, filter)
+        .map_err(|e| format!("Similarity search failed: {}", e))?;
+
+    scored_documents_json(matches, "distance")
+}
+
+/// Split a text field of a document into chunk documents, returning their
+/// IDs as a JSON array
+///
+/// Example options: `{"max_tokens": 200, "overlap": 20, "by": "sentences"}`;
+/// any may be left out, and `"by"` may also be "tokens".
+pub fn ingest_chunks(
+    source_id: String,
+    field: String,
+    chunk_collection: String,
+    options_json: String,
+) -> Result<String, String> {
+    let db = get_db()?;
+
+    let options: ChunkOptions = serde_json::from_str(&options_json)
+        .map_err(|e| format!("Invalid chunk options: {}", e))?;
+
+    let ids = db.ingest_chunks(&source_id, &field, &chunk_collection, &options)
+        .map_err(|e| format!("Chunking failed: {}", e))?;
+
+    serde_json::to_string(&ids)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Serialize ranked documents as `[{"document": ..., <label>: ...}]`
+fn scored_documents_json(matches: Vec<(NVDocument, f32)>, label: &str) -> Result<String, String> {
+    let json: Vec<serde_json::Value> = matches
+        .into_iter()
+        .map(|(document, score)| serde_json::json!({"document": document, label: score}))
+        .collect();
+    serde_json::to_string(&json)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Get a user-defined metadata value as JSON (`null` if unset)
+pub fn get_metadata(key: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let value = db.get_metadata(&key)
+        .map_err(|e| format!("Failed to get metadata: {}", e))?;
+
+    let json = value.map(serde_json::Value::from).unwrap_or(serde_json::Value::Null);
+    Ok(json.to_string())
+}
+
+/// Set a user-defined metadata value from JSON
+pub fn set_metadata(key: String, value_json: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let value: serde_json::Value = serde_json::from_str(&value_json)
+        .map_err(|e| format!("Invalid JSON: {}", e))?;
+
+    db.set_metadata(&key, NVValue::from(value))
+        .map_err(|e| format!("Failed to set metadata: {}", e))?;
+
+    Ok("Metadata updated successfully".to_string())
+}
+
+/// Attach bytes to a document under `name`, returning the attachment's
+/// details as JSON
+pub fn put_attachment(document_id: String, name: String, data: Vec<u8>) -> Result<String, String> {
+    let db = get_db()?;
+
+    let info = db.put_attachment(&document_id, &name, data.as_slice())
+        .map_err(|e| format!("Failed to store attachment: {}", e))?;
+
+    serde_json::to_string(&info)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Attach the file at `path` to a document, streaming it in
+pub fn put_attachment_file(document_id: String, name: String, path: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let file = std::fs::File::open(&path)
+        .map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let info = db.put_attachment(&document_id, &name, std::io::BufReader::new(file))
+        .map_err(|e| format!("Failed to store attachment: {}", e))?;
+
+    serde_json::to_string(&info)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Read a document's attachment into memory
+pub fn get_attachment(document_id: String, name: String) -> Result<Vec<u8>, String> {
+    let db = get_db()?;
+
+    let mut reader = db.get_attachment(&document_id, &name)
+        .map_err(|e| format!("Failed to get attachment: {}", e))?;
+    let mut data = Vec::new();
+    std::io::Read::read_to_end(&mut reader, &mut data)
+        .map_err(|e| format!("Failed to read attachment: {}", e))?;
+
+    Ok(data)
+}
+
+/// Stream a document's attachment out to the file at `path`, returning
+/// its size
+pub fn save_attachment(document_id: String, name: String, path: String) -> Result<u64, String> {
+    let db = get_db()?;
+
+    let mut reader = db.get_attachment(&document_id, &name)
+        .map_err(|e| format!("Failed to get attachment: {}", e))?;
+    let mut file = std::fs::File::create(&path)
+        .map_err(|e| format!("Failed to create {}: {}", path, e))?;
+    std::io::copy(&mut reader, &mut file)
+        .map_err(|e| format!("Failed to write attachment: {}", e))
+}
+
+/// Remove a document's attachment, returning whether it existed
+pub fn delete_attachment(document_id: String, name: String) -> Result<bool, String> {
+    let db = get_db()?;
+
+    db.delete_attachment(&document_id, &name)
+        .map_err(|e| format!("Failed to delete attachment: {}", e))
+}
+
+/// A document's attachments as a JSON array
+pub fn list_attachments(document_id: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let attachments = db.attachments(&document_id)
+        .map_err(|e| format!("Failed to list attachments: {}", e))?;
+
+    serde_json::to_string(&attachments)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+/// Get a key-value entry as JSON, `null` if the key isn't set
+pub fn kv_get(key: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let value = db.kv_get(&key)
+        .map_err(|e| format!("Failed to get key: {}", e))?;
+
+    let json = value.map(serde_json::Value::from).unwrap_or(serde_json::Value::Null);
+    Ok(json.to_string())
+}
+
+/// Set a key-value entry from JSON
+pub fn kv_set(key: String, value_json: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let value: serde_json::Value = serde_json::from_str(&value_json)
+        .map_err(|e| format!("Invalid JSON: {}", e))?;
+
+    db.kv_set(&key, NVValue::from(value))
+        .map_err(|e| format!("Failed to set key: {}", e))?;
+
+    Ok("Key set successfully".to_string())
+}
+
+/// Remove a key-value entry, returning whether it was set
+pub fn kv_delete(key: String) -> Result<bool, String> {
+    let db = get_db()?;
+
+    db.kv_delete(&key)
+        .map_err(|e| format!("Failed to delete key: {}", e))
+}
+
+/// Keys starting with `prefix` as a JSON array
+pub fn kv_keys(prefix: String) -> Result<String, String> {
+    let db = get_db()?;
+
+    let keys = db.kv_keys(&prefix)
+        .map_err(|e| format!("Failed to list keys: {}", e))?;
+
+    serde_json::to_string(&keys)
+        .map_err(|e| format!("Serialization failed: {}", e))
+}
+
+// Helper functions
+
+fn json_to_hashmap(value: serde_json::Value) -> Result<HashMap<String, NVValue>, String> {
+    match value {
+        serde_json::Value::Object(obj) => {
+            Ok(obj.into_iter().map(|(k, v)| (k, NVValue::from(v))).collect())
+        }
+        _ => Err("Expected JSON object".to_string()),
+    }
+}
+
+fn parse_wire_format(format: &str) -> Result<WireFormat, String> {
+    WireFormat::parse(format).ok_or_else(|| format!("Unknown wire format: {}", format))
+}
+
+/// Parse a query, reusing the result for text seen before
+fn parse_query_json(collection: String, query_json: String) -> Result<NVQuery, String> {
+    let key = QueryCache::key(&collection, &query_json);
+    if let Some(query) = QUERY_CACHE.lock().unwrap().get(&key) {
+        return Ok(query);
+    }
+
+    let query = json::parse_query(collection, &query_json).map_err(|e| e.to_string())?;
+    QUERY_CACHE.lock().unwrap().insert(key, query.clone());
+    Ok(query)
+}
+
+fn parse_updates_json(updates_json: String) -> Result<Vec<UpdateOperation>, String> {
+    let json: serde_json::Value = serde_json::from_str(&updates_json)
+        .map_err(|e| format!("Invalid updates JSON: {}", e))?;
+
+    let mut updates = Vec::new();
+
+    if let serde_json::Value::Object(obj) = json {
+        for (field, value) in obj {
+            match field.as_str() {
+                // {"$merge": {"profile": {...}}} deep-merges into existing objects
+                "$merge" => {
+                    let fields = match value {
+                        serde_json::Value::Object(fields) => fields,
+                        _ => return Err("$merge expects an object".to_string()),
+                    };
+                    for (field, value) in fields {
+                        updates.push(UpdateOperation::merge(field, NVValue::from(value)));
+                    }
+                }
+                // {"$unset": ["field", ...]} or {"$unset": {"field": true, ...}}
+                "$unset" => match value {
+                    serde_json::Value::Array(fields) => {
+                        for field in fields {
+                            let field = field.as_str()
+                                .ok_or("$unset expects field names")?;
+                            updates.push(UpdateOperation::unset(field));
+                        }
+                    }
+                    serde_json::Value::Object(fields) => {
+                        updates.extend(fields.into_iter().map(|(field, _)| UpdateOperation::unset(field)));
+                    }
+                    serde_json::Value::String(field) => updates.push(UpdateOperation::unset(field)),
+                    _ => return Err("$unset expects a field name, array or object".to_string()),
+                },
+                _ => updates.push(UpdateOperation::set(field, NVValue::from(value))),
+            }
+        }
+    }
+
+    Ok(updates)
+}
+
+fn parse_write_op(op: &serde_json::Value) -> Result<WriteOp, String> {
+    let string_field = |name: &str| -> Result<String, String> {
+        op.get(name)
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+            .ok_or_else(|| format!("Missing {} in write operation", name))
+    };
+    let data = || match op.get("data") {
+        Some(data) => json_to_hashmap(data.clone()),
+        None => Ok(HashMap::new()),
+    };
+
+    match op.get("op").and_then(|v| v.as_str()) {
+        Some("insert") => Ok(WriteOp::Insert {
+            id: op.get("id").and_then(|v| v.as_str()).map(str::to_string),
+            collection: string_field("collection")?,
+            data: data()?,
+        }),
+        Some("update") => Ok(WriteOp::Update {
+            id: string_field("id")?,
+            updates: parse_updates_json(
+                op.get("updates").map(|v| v.to_string()).unwrap_or_else(|| "{}".to_string()),
+            )?,
+        }),
+        Some("delete") => Ok(WriteOp::Delete { id: string_field("id")? }),
+        Some("upsert") => Ok(WriteOp::Upsert {
+            id: string_field("id")?,
+            collection: string_field("collection")?,
+            data: data()?,
+        }),
+        Some(other) => Err(format!("Unknown write operation: {}", other)),
+        None => Err("Missing op in write operation".to_string()),
+    }
+}
+
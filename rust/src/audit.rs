@@ -0,0 +1,79 @@
+use crate::models::{NVDocument, NVValue};
+use chrono::Utc;
+use std::collections::HashMap;
+
+/// Reserved collection holding the audit trail
+pub const AUDIT_COLLECTION: &str = "_nv_audit";
+
+/// Build the audit entry for a document write
+///
+/// `fields` lists the fields whose values changed: every field for creates
+/// and deletes. Values themselves are not copied, so encrypted fields stay
+/// out of the trail.
+pub fn entry(
+    id: String,
+    actor: Option<&str>,
+    previous: Option<&NVDocument>,
+    current: Option<&NVDocument>,
+) -> NVDocument {
+    let (action, document) = match (previous, current) {
+        (None, Some(current)) => ("create", current),
+        (Some(_), Some(current)) => ("update", current),
+        (Some(previous), None) => ("delete", previous),
+        (None, None) => unreachable!("audit entry without a document"),
+    };
+
+    let empty = HashMap::new();
+    let before = previous.map_or(&empty, |doc| &doc.data);
+    let after = current.map_or(&empty, |doc| &doc.data);
+    let mut fields: Vec<&String> = before
+        .keys()
+        .chain(after.keys().filter(|field| !before.contains_key(*field)))
+        .filter(|field| before.get(*field) != after.get(*field))
+        .collect();
+    fields.sort();
+
+    let now = Utc::now();
+    let mut data = HashMap::new();
+    data.insert(
+        "actor".to_string(),
+        actor.map_or(NVValue::Null, |actor| NVValue::String(actor.to_string())),
+    );
+    data.insert("action".to_string(), NVValue::String(action.to_string()));
+    data.insert("collection".to_string(), NVValue::String(document.collection.clone()));
+    data.insert("document_id".to_string(), NVValue::String(document.id.clone()));
+    data.insert(
+        "fields".to_string(),
+        NVValue::Array(fields.into_iter().map(|f| NVValue::String(f.clone())).collect()),
+    );
+    data.insert("timestamp".to_string(), NVValue::Number(now.timestamp_millis() as f64));
+
+    NVDocument::new(id, AUDIT_COLLECTION.to_string(), data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_lists_changed_fields() {
+        let mut data = HashMap::new();
+        data.insert("a".to_string(), NVValue::Number(1.0));
+        data.insert("b".to_string(), NVValue::Number(2.0));
+        let previous = NVDocument::new("d".to_string(), "things".to_string(), data);
+        let mut current = previous.clone();
+        current.set("b".to_string(), NVValue::Number(3.0));
+        current.set("c".to_string(), NVValue::Null);
+
+        let audit = entry("e".to_string(), Some("ops"), Some(&previous), Some(&current));
+        assert_eq!(audit.get("actor"), Some(&NVValue::String("ops".to_string())));
+        assert_eq!(audit.get("action"), Some(&NVValue::String("update".to_string())));
+        assert_eq!(
+            audit.get("fields"),
+            Some(&NVValue::Array(vec![
+                NVValue::String("b".to_string()),
+                NVValue::String("c".to_string())
+            ]))
+        );
+    }
+}
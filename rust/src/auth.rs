@@ -0,0 +1,181 @@
+use crate::crypto;
+use crate::error::{NeuralVaultError, NVResult};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Collection name granting access to every collection
+pub const ALL_COLLECTIONS: &str = "*";
+
+/// Level of access to a collection; `Write` includes `Read`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+impl Access {
+    /// Parse "read" or "write"
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "read" => Some(Access::Read),
+            "write" => Some(Access::Write),
+            _ => None,
+        }
+    }
+}
+
+/// A named set of per-collection permissions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Role {
+    pub name: String,
+    /// Access granted per collection, or for all of them under `"*"`
+    pub permissions: HashMap<String, Access>,
+}
+
+impl Role {
+    /// Whether this role grants `access` to `collection`
+    pub fn allows(&self, collection: &str, access: Access) -> bool {
+        [collection, ALL_COLLECTIONS]
+            .iter()
+            .filter_map(|name| self.permissions.get(*name))
+            .any(|granted| *granted >= access)
+    }
+}
+
+/// A principal that authenticates with tokens and holds roles
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct User {
+    pub name: String,
+    pub roles: Vec<String>,
+}
+
+/// Users, roles and tokens for a server layer to enforce
+///
+/// Only SHA-256 digests of tokens are kept, so a leaked catalog doesn't
+/// leak working credentials.
+#[derive(Debug, Default)]
+pub struct AccessControl {
+    roles: HashMap<String, Role>,
+    users: HashMap<String, User>,
+    /// Token digest to user name
+    tokens: HashMap<String, String>,
+}
+
+impl AccessControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_role(&mut self, role: Role) {
+        self.roles.insert(role.name.clone(), role);
+    }
+
+    pub fn remove_role(&mut self, name: &str) -> bool {
+        self.roles.remove(name).is_some()
+    }
+
+    pub fn role(&self, name: &str) -> Option<&Role> {
+        self.roles.get(name)
+    }
+
+    pub fn set_user(&mut self, user: User) {
+        self.users.insert(user.name.clone(), user);
+    }
+
+    /// Remove a user, returning the digests of the tokens it held
+    pub fn remove_user(&mut self, name: &str) -> Option<Vec<String>> {
+        self.users.remove(name)?;
+        let digests: Vec<String> = self
+            .tokens
+            .iter()
+            .filter(|(_, user)| *user == name)
+            .map(|(digest, _)| digest.clone())
+            .collect();
+        for digest in &digests {
+            self.tokens.remove(digest);
+        }
+        Some(digests)
+    }
+
+    pub fn user(&self, name: &str) -> Option<&User> {
+        self.users.get(name)
+    }
+
+    pub fn add_token(&mut self, digest: String, user: String) {
+        self.tokens.insert(digest, user);
+    }
+
+    pub fn remove_token(&mut self, digest: &str) -> bool {
+        self.tokens.remove(digest).is_some()
+    }
+
+    /// The user a token authenticates
+    pub fn authenticate(&self, token: &str) -> NVResult<&User> {
+        self.tokens
+            .get(&token_digest(token))
+            .and_then(|name| self.users.get(name))
+            .ok_or_else(|| NeuralVaultError::AccessDenied("Invalid token".to_string()))
+    }
+
+    /// The user a token authenticates, if `access` to `collection` is
+    /// granted by one of its roles
+    pub fn authorize(&self, token: &str, collection: &str, access: Access) -> NVResult<&User> {
+        let user = self.authenticate(token)?;
+
+        let allowed = user
+            .roles
+            .iter()
+            .filter_map(|name| self.roles.get(name))
+            .any(|role| role.allows(collection, access));
+        if !allowed {
+            return Err(NeuralVaultError::AccessDenied(format!(
+                "User {} lacks {:?} access to {}",
+                user.name, access, collection
+            )));
+        }
+        Ok(user)
+    }
+}
+
+/// A new random API token
+pub fn new_token() -> String {
+    crypto::to_hex(&crypto::random_bytes(32))
+}
+
+/// Hex SHA-256 digest under which a token is stored
+pub fn token_digest(token: &str) -> String {
+    crypto::to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorize() {
+        let mut access = AccessControl::new();
+        access.set_role(Role {
+            name: "editor".to_string(),
+            permissions: HashMap::from([
+                ("posts".to_string(), Access::Write),
+                (ALL_COLLECTIONS.to_string(), Access::Read),
+            ]),
+        });
+        access.set_user(User {
+            name: "sam".to_string(),
+            roles: vec!["editor".to_string()],
+        });
+        let token = new_token();
+        access.add_token(token_digest(&token), "sam".to_string());
+
+        assert_eq!(access.authorize(&token, "posts", Access::Write).unwrap().name, "sam");
+        assert!(access.authorize(&token, "users", Access::Read).is_ok());
+        assert!(access.authorize(&token, "users", Access::Write).is_err());
+        assert!(access.authorize(&new_token(), "posts", Access::Read).is_err());
+
+        // Removing a user revokes its tokens
+        assert_eq!(access.remove_user("sam").unwrap().len(), 1);
+        assert!(access.authorize(&token, "posts", Access::Read).is_err());
+    }
+}
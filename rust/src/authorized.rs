@@ -0,0 +1,120 @@
+use crate::auth::Access;
+use crate::database::NeuralVault;
+use crate::error::NVResult;
+use crate::models::{NVDocument, NVQuery, NVValue, UpdateOperation};
+use crate::pipeline::{Row, Stage};
+use crate::query::targets;
+use std::collections::HashMap;
+
+/// A handle that enforces an API token's permissions
+///
+/// Created by `NeuralVault::authorized`. Every call is checked against the
+/// token's roles as they are at that moment, so revoking the token or
+/// changing a role applies to the next call; a denied call fails with
+/// `AccessDenied` before anything is written. Queries need read access to
+/// every collection they read, populated references and subqueries
+/// included. Writes by ID need write access to the document's collection;
+/// reference actions triggered by a delete apply as declared.
+pub struct AuthorizedVault<'db> {
+    db: &'db NeuralVault,
+    token: String,
+    user: String,
+}
+
+impl<'db> AuthorizedVault<'db> {
+    pub(crate) fn new(db: &'db NeuralVault, token: &str, user: String) -> Self {
+        Self {
+            db,
+            token: token.to_string(),
+            user,
+        }
+    }
+
+    /// The user the token authenticated when the handle was created
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    pub fn create(&self, collection: &str, data: HashMap<String, NVValue>) -> NVResult<String> {
+        self.check(collection, Access::Write)?;
+        self.db.create(collection.to_string(), data)
+    }
+
+    pub fn create_with_id(&self, id: String, collection: &str, data: HashMap<String, NVValue>) -> NVResult<String> {
+        self.check(collection, Access::Write)?;
+        self.db.create_with_id(id, collection.to_string(), data)
+    }
+
+    pub fn find(&self, query: NVQuery) -> NVResult<Vec<NVDocument>> {
+        self.check_query(&query, Access::Read)?;
+        self.db.find(query)
+    }
+
+    pub fn find_by_id(&self, id: &str) -> NVResult<NVDocument> {
+        let document = self.db.find_by_id(id)?;
+        self.check(&document.collection, Access::Read)?;
+        Ok(document)
+    }
+
+    pub fn update(&self, query: NVQuery, updates: Vec<UpdateOperation>) -> NVResult<usize> {
+        self.check_query(&query, Access::Write)?;
+        self.db.update(query, updates)
+    }
+
+    pub fn update_by_id(&self, id: &str, updates: Vec<UpdateOperation>) -> NVResult<()> {
+        self.check(&self.db.find_by_id(id)?.collection, Access::Write)?;
+        self.db.update_by_id(id, updates)
+    }
+
+    pub fn kill(&self, query: NVQuery) -> NVResult<usize> {
+        self.check_query(&query, Access::Write)?;
+        self.db.kill(query)
+    }
+
+    pub fn kill_by_id(&self, id: &str) -> NVResult<()> {
+        self.check(&self.db.find_by_id(id)?.collection, Access::Write)?;
+        self.db.kill_by_id(id)
+    }
+
+    pub fn count(&self, collection: &str) -> NVResult<usize> {
+        self.check(collection, Access::Read)?;
+        self.db.count(collection)
+    }
+
+    pub fn aggregate(&self, collection: &str, stages: Vec<Stage>) -> NVResult<Vec<Row>> {
+        self.check(collection, Access::Read)?;
+        self.db.aggregate(collection, stages)
+    }
+
+    /// Check `access` to the query's collections, and read access to the
+    /// ones its populated references and subqueries read
+    fn check_query(&self, query: &NVQuery, access: Access) -> NVResult<()> {
+        for collection in query.collection.split(targets::SEPARATOR) {
+            self.check(collection.trim(), access)?;
+        }
+        for spec in &query.populate {
+            let target = match spec.split_once(':') {
+                Some((_, target)) => target.to_string(),
+                None => match self
+                    .db
+                    .references()
+                    .into_iter()
+                    .find(|r| r.collection == query.collection && r.field == *spec)
+                {
+                    Some(reference) => reference.target,
+                    // The query fails on it without reading anything
+                    None => continue,
+                },
+            };
+            self.check(&target, Access::Read)?;
+        }
+        for subquery in &query.subqueries {
+            self.check_query(&subquery.query, Access::Read)?;
+        }
+        Ok(())
+    }
+
+    fn check(&self, collection: &str, access: Access) -> NVResult<()> {
+        self.db.authorize(&self.token, collection, access).map(|_| ())
+    }
+}
@@ -0,0 +1,218 @@
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::NVDocument;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+
+/// Limits of a capped collection; the oldest documents are evicted once
+/// either is exceeded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct CollectionCap {
+    pub max_documents: Option<usize>,
+    /// Bytes taken by the documents' records in the data file
+    pub max_bytes: Option<u64>,
+}
+
+impl CollectionCap {
+    pub fn validate(&self) -> NVResult<()> {
+        if self.max_documents.is_none() && self.max_bytes.is_none() {
+            return Err(NeuralVaultError::ValidationError(
+                "A cap needs max_documents or max_bytes".to_string(),
+            ));
+        }
+        if self.max_documents == Some(0) || self.max_bytes == Some(0) {
+            return Err(NeuralVaultError::ValidationError("Cap limits must be positive".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// The documents of a capped collection in creation order
+#[derive(Debug, Clone)]
+pub struct CappedCollection {
+    cap: CollectionCap,
+    order: BTreeSet<(DateTime<Utc>, String)>,
+    created: HashMap<String, DateTime<Utc>>,
+}
+
+impl CappedCollection {
+    pub fn new(cap: CollectionCap) -> Self {
+        Self {
+            cap,
+            order: BTreeSet::new(),
+            created: HashMap::new(),
+        }
+    }
+
+    pub fn cap(&self) -> CollectionCap {
+        self.cap
+    }
+
+    pub fn len(&self) -> usize {
+        self.created.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty()
+    }
+
+    /// Track the current version of a document
+    pub fn add(&mut self, document: &NVDocument) {
+        self.remove(&document.id);
+        if document.deleted {
+            return;
+        }
+        self.order.insert((document.created_at, document.id.clone()));
+        self.created.insert(document.id.clone(), document.created_at);
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        if let Some(created) = self.created.remove(id) {
+            self.order.remove(&(created, id.to_string()));
+        }
+    }
+
+    /// IDs of the oldest documents to evict to get back within the cap,
+    /// given the stored size of each document
+    ///
+    /// The newest document is always kept, even if it alone is too big.
+    pub fn overflow(&self, size: impl Fn(&str) -> u64) -> Vec<String> {
+        let mut count = self.order.len();
+        let mut bytes = match self.cap.max_bytes {
+            Some(_) => self.order.iter().map(|(_, id)| size(id)).sum(),
+            None => 0,
+        };
+        let over = |count: usize, bytes: u64| {
+            self.cap.max_documents.is_some_and(|max| count > max) || self.cap.max_bytes.is_some_and(|max| bytes > max)
+        };
+
+        let mut evicted = Vec::new();
+        for (_, id) in &self.order {
+            if count <= 1 || !over(count, bytes) {
+                break;
+            }
+            count -= 1;
+            if self.cap.max_bytes.is_some() {
+                bytes -= size(id);
+            }
+            evicted.push(id.clone());
+        }
+        evicted
+    }
+}
+
+/// Documents in the order they were last updated, oldest first
+///
+/// Tracks the cache collections that quota eviction draws from.
+#[derive(Debug, Clone, Default)]
+pub struct UpdateOrder {
+    order: BTreeSet<(DateTime<Utc>, String)>,
+    updated: HashMap<String, DateTime<Utc>>,
+}
+
+impl UpdateOrder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track the current version of a document
+    pub fn add(&mut self, document: &NVDocument) {
+        self.remove(&document.id);
+        if document.deleted {
+            return;
+        }
+        self.order.insert((document.updated_at, document.id.clone()));
+        self.updated.insert(document.id.clone(), document.updated_at);
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        if let Some(updated) = self.updated.remove(id) {
+            self.order.remove(&(updated, id.to_string()));
+        }
+    }
+
+    /// The least recently updated document after `after`, or the least
+    /// recently updated of all
+    pub fn next(&self, after: Option<&(DateTime<Utc>, String)>) -> Option<(DateTime<Utc>, String)> {
+        match after {
+            Some(after) => self.order.range((Bound::Excluded(after), Bound::Unbounded)).next(),
+            None => self.order.iter().next(),
+        }
+        .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn entry(id: &str, minutes: i64) -> NVDocument {
+        let mut document = NVDocument::new(id.to_string(), "logs".to_string(), HashMap::new());
+        document.created_at = DateTime::<Utc>::UNIX_EPOCH + Duration::minutes(minutes);
+        document
+    }
+
+    #[test]
+    fn test_overflow_evicts_oldest() {
+        let mut capped = CappedCollection::new(CollectionCap {
+            max_documents: Some(2),
+            max_bytes: None,
+        });
+        for (id, minutes) in [("c", 3), ("a", 1), ("b", 2)] {
+            capped.add(&entry(id, minutes));
+        }
+        assert_eq!(capped.overflow(|_| 0), vec!["a".to_string()]);
+        capped.remove("a");
+        assert!(capped.overflow(|_| 0).is_empty());
+
+        let by_bytes = CappedCollection {
+            cap: CollectionCap {
+                max_documents: None,
+                max_bytes: Some(100),
+            },
+            ..capped
+        };
+        assert_eq!(by_bytes.overflow(|id| if id == "c" { 80 } else { 30 }), vec!["b".to_string()]);
+        // The newest document stays even when it's over the cap alone
+        assert_eq!(by_bytes.overflow(|_| 500), vec!["b".to_string()]);
+    }
+
+    #[test]
+    fn test_update_order() {
+        let mut order = UpdateOrder::new();
+        for (id, minutes) in [("c", 3), ("a", 1), ("b", 2)] {
+            let mut document = entry(id, 0);
+            document.updated_at = DateTime::<Utc>::UNIX_EPOCH + Duration::minutes(minutes);
+            order.add(&document);
+        }
+        // Updating a document moves it to the back
+        let mut document = entry("a", 0);
+        document.updated_at = DateTime::<Utc>::UNIX_EPOCH + Duration::minutes(4);
+        order.add(&document);
+        order.remove("c");
+
+        let first = order.next(None).unwrap();
+        assert_eq!(first.1, "b");
+        assert_eq!(order.next(Some(&first)).unwrap().1, "a");
+        assert!(order.next(order.next(Some(&first)).as_ref()).is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(CollectionCap::default().validate().is_err());
+        assert!(CollectionCap {
+            max_documents: Some(0),
+            max_bytes: None
+        }
+        .validate()
+        .is_err());
+        assert!(CollectionCap {
+            max_documents: None,
+            max_bytes: Some(1 << 20)
+        }
+        .validate()
+        .is_ok());
+    }
+}
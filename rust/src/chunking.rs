@@ -0,0 +1,245 @@
+use crate::error::{NeuralVaultError, NVResult};
+use serde::{Deserialize, Serialize};
+
+/// Chunk field holding the ID of the document it was cut from
+pub const SOURCE_ID: &str = "source_id";
+/// Chunk field naming the source field
+pub const SOURCE_FIELD: &str = "source_field";
+/// Chunk field holding the chunk's position among its siblings, from 0
+pub const CHUNK_INDEX: &str = "chunk";
+/// Chunk field holding the chunk's text
+pub const TEXT: &str = "text";
+/// Chunk fields holding the character range of the chunk in the source text
+pub const START: &str = "start";
+pub const END: &str = "end";
+
+/// Where chunks may be cut
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkBoundary {
+    /// Between any two words
+    Tokens,
+    /// Between sentences, unless one sentence alone is too long
+    #[default]
+    Sentences,
+}
+
+/// How long text is split into chunks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChunkOptions {
+    /// Most words per chunk
+    pub max_tokens: usize,
+    /// Words repeated from the end of one chunk at the start of the next
+    pub overlap: usize,
+    pub by: ChunkBoundary,
+}
+
+impl Default for ChunkOptions {
+    fn default() -> Self {
+        Self {
+            max_tokens: 200,
+            overlap: 20,
+            by: ChunkBoundary::Sentences,
+        }
+    }
+}
+
+impl ChunkOptions {
+    pub fn validate(&self) -> NVResult<()> {
+        if self.max_tokens == 0 || self.overlap >= self.max_tokens {
+            return Err(NeuralVaultError::ValidationError(format!(
+                "Chunks need at least one token and an overlap below max_tokens, got {} and {}",
+                self.max_tokens, self.overlap
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A piece of a longer text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub text: String,
+    /// Character offsets into the source text
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Split text into chunks of at most `max_tokens` words, consecutive
+/// chunks sharing up to `overlap` words
+///
+/// Words are runs of non-whitespace. With sentence boundaries, whole
+/// sentences are packed into each chunk and the overlap is made of whole
+/// sentences too; a sentence longer than a chunk is cut between words.
+pub fn split(text: &str, options: &ChunkOptions) -> NVResult<Vec<Chunk>> {
+    options.validate()?;
+    let words = words(text);
+    if words.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Word ranges [first, last) of each chunk
+    let ranges = match options.by {
+        ChunkBoundary::Tokens => windows(0, words.len(), options),
+        ChunkBoundary::Sentences => {
+            let mut ranges = Vec::new();
+            let sentences = sentences(text, &words);
+            let mut start = 0;
+            while start < sentences.len() {
+                let first = sentences[start].0;
+                let mut end = start;
+                while end < sentences.len() && sentences[end].1 - first <= options.max_tokens {
+                    end += 1;
+                }
+                if end == start {
+                    // One sentence too long for a chunk
+                    ranges.extend(windows(sentences[start].0, sentences[start].1, options));
+                    start += 1;
+                    continue;
+                }
+                ranges.push((first, sentences[end - 1].1));
+                if end == sentences.len() {
+                    break;
+                }
+
+                // Repeat the trailing sentences that fit in the overlap
+                let mut next = end;
+                while next > start + 1 && sentences[end - 1].1 - sentences[next - 1].0 <= options.overlap {
+                    next -= 1;
+                }
+                start = next;
+            }
+            ranges
+        }
+    };
+
+    let offsets = char_offsets(text);
+    Ok(ranges
+        .into_iter()
+        .map(|(first, last)| {
+            let (start, end) = (words[first].0, words[last - 1].1);
+            Chunk {
+                text: text[start..end].to_string(),
+                start: offsets(start),
+                end: offsets(end),
+            }
+        })
+        .collect())
+}
+
+/// Byte ranges of the words in `text`
+fn words(text: &str) -> Vec<(usize, usize)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                words.push((s, i));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        words.push((s, text.len()));
+    }
+    words
+}
+
+/// Word ranges of the sentences, each ending at a word that ends in
+/// `.`, `!` or `?` or before a blank line
+fn sentences(text: &str, words: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut sentences = Vec::new();
+    let mut first = 0;
+    for (i, &(_, end)) in words.iter().enumerate() {
+        let terminated = text[..end].ends_with(['.', '!', '?']);
+        let paragraph = words
+            .get(i + 1)
+            .is_some_and(|&(next, _)| text[end..next].matches('\n').count() > 1);
+        if terminated || paragraph || i + 1 == words.len() {
+            sentences.push((first, i + 1));
+            first = i + 1;
+        }
+    }
+    sentences
+}
+
+/// Fixed-size word windows over `[first, last)`
+fn windows(first: usize, last: usize, options: &ChunkOptions) -> Vec<(usize, usize)> {
+    let step = options.max_tokens - options.overlap;
+    let mut ranges = Vec::new();
+    let mut start = first;
+    loop {
+        let end = (start + options.max_tokens).min(last);
+        ranges.push((start, end));
+        if end == last {
+            return ranges;
+        }
+        start += step;
+    }
+}
+
+/// Maps byte offsets in `text` to character offsets
+fn char_offsets(text: &str) -> impl Fn(usize) -> usize + '_ {
+    move |byte| text[..byte].chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(chunks: &[Chunk]) -> Vec<&str> {
+        chunks.iter().map(|chunk| chunk.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_token_windows_overlap() {
+        let options = ChunkOptions {
+            max_tokens: 4,
+            overlap: 1,
+            by: ChunkBoundary::Tokens,
+        };
+        let chunks = split("one two three four five six seven", &options).unwrap();
+        assert_eq!(texts(&chunks), vec!["one two three four", "four five six seven"]);
+        assert_eq!((chunks[1].start, chunks[1].end), (14, 33));
+
+        assert!(split("  \n ", &options).unwrap().is_empty());
+        assert!(split("text", &ChunkOptions { overlap: 4, ..options }).is_err());
+    }
+
+    #[test]
+    fn test_sentence_packing() {
+        let options = ChunkOptions {
+            max_tokens: 6,
+            overlap: 2,
+            by: ChunkBoundary::Sentences,
+        };
+        let text = "Rust is fast. It is safe. Ownership rules!\n\nA very long sentence that cannot fit in one chunk at all";
+        let chunks = split(text, &options).unwrap();
+        assert_eq!(
+            texts(&chunks),
+            vec![
+                "Rust is fast. It is safe.",
+                "Ownership rules!",
+                "A very long sentence that cannot",
+                "that cannot fit in one chunk",
+                "one chunk at all",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sentence_overlap_and_offsets() {
+        let options = ChunkOptions {
+            max_tokens: 5,
+            overlap: 2,
+            by: ChunkBoundary::Sentences,
+        };
+        let chunks = split("Héllo wörld. Go on. Stop now please.", &options).unwrap();
+        // "Go on." is short enough to repeat in the next chunk
+        assert_eq!(texts(&chunks), vec!["Héllo wörld. Go on.", "Go on. Stop now please."]);
+        assert_eq!((chunks[1].start, chunks[1].end), (13, 36));
+    }
+}
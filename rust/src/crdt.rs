@@ -0,0 +1,145 @@
+use crate::models::{NVDocument, NVValue};
+use std::collections::{HashMap, HashSet};
+
+/// Reserved field holding a CRDT document's per-field clock
+///
+/// Maps each field name to `[timestamp_ms, replica_id]`, the stamp of the
+/// write that last set (or removed) it.
+pub const CLOCK_FIELD: &str = "_nv_clock";
+
+/// When and where a field was last written; later stamps win, with the
+/// replica ID breaking ties
+type Stamp = (f64, String);
+
+/// Whether a document carries a CRDT clock
+pub fn is_crdt(document: &NVDocument) -> bool {
+    document.data.contains_key(CLOCK_FIELD)
+}
+
+/// Stamp the fields a write changed with `now_ms` and `replica`
+///
+/// The clock is carried over from `previous`, so callers never need to
+/// supply it. Stamps only move forward, even if the system clock doesn't.
+pub fn stamp(previous: Option<&NVDocument>, document: &mut NVDocument, replica: &str, now_ms: f64) {
+    let mut clock = previous.map(clock).unwrap_or_default();
+    document.data.remove(CLOCK_FIELD);
+
+    let empty = HashMap::new();
+    let before = previous.map_or(&empty, |doc| &doc.data);
+    let fields: HashSet<&String> = before
+        .keys()
+        .chain(document.data.keys())
+        .filter(|field| *field != CLOCK_FIELD)
+        .collect();
+
+    for field in fields {
+        if before.get(field) != document.data.get(field) {
+            let last = clock.get(field).map_or(0.0, |(time, _)| *time);
+            clock.insert(field.clone(), (now_ms.max(last + 1.0), replica.to_string()));
+        }
+    }
+
+    document.data.insert(CLOCK_FIELD.to_string(), encode(&clock));
+}
+
+/// Merge two versions of a CRDT document field by field
+///
+/// Each field takes the value (or absence) with the later stamp, so the
+/// result is the same whichever side merges.
+pub fn merge(a: &NVDocument, b: &NVDocument) -> NVDocument {
+    let (clock_a, clock_b) = (clock(a), clock(b));
+    let unstamped = (0.0, String::new());
+
+    let mut merged = a.clone();
+    merged.data.clear();
+    merged.updated_at = a.updated_at.max(b.updated_at);
+
+    let mut clock = HashMap::new();
+    let fields: HashSet<&String> = clock_a
+        .keys()
+        .chain(clock_b.keys())
+        .chain(a.data.keys())
+        .chain(b.data.keys())
+        .filter(|field| *field != CLOCK_FIELD)
+        .collect();
+
+    for field in fields {
+        let stamp_a = clock_a.get(field).unwrap_or(&unstamped);
+        let stamp_b = clock_b.get(field).unwrap_or(&unstamped);
+        let (winner, stamp) = match later(stamp_a, stamp_b) {
+            true => (b, stamp_b),
+            false => (a, stamp_a),
+        };
+        if let Some(value) = winner.data.get(field) {
+            merged.data.insert(field.clone(), value.clone());
+        }
+        if stamp != &unstamped {
+            clock.insert(field.clone(), stamp.clone());
+        }
+    }
+
+    merged.data.insert(CLOCK_FIELD.to_string(), encode(&clock));
+    merged
+}
+
+/// Whether stamp `b` is later than stamp `a`
+fn later(a: &Stamp, b: &Stamp) -> bool {
+    b.0 > a.0 || (b.0 == a.0 && b.1 > a.1)
+}
+
+fn clock(document: &NVDocument) -> HashMap<String, Stamp> {
+    let Some(NVValue::Object(clock)) = document.data.get(CLOCK_FIELD) else {
+        return HashMap::new();
+    };
+    clock
+        .iter()
+        .filter_map(|(field, stamp)| match stamp {
+            NVValue::Array(parts) => match parts.as_slice() {
+                [NVValue::Number(time), NVValue::String(replica)] => {
+                    Some((field.clone(), (*time, replica.clone())))
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+fn encode(clock: &HashMap<String, Stamp>) -> NVValue {
+    NVValue::Object(
+        clock
+            .iter()
+            .map(|(field, (time, replica))| {
+                let stamp = vec![NVValue::Number(*time), NVValue::String(replica.clone())];
+                (field.clone(), NVValue::Array(stamp))
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(previous: &NVDocument, field: &str, value: NVValue, replica: &str, now: f64) -> NVDocument {
+        let mut document = previous.clone();
+        document.set(field.to_string(), value);
+        stamp(Some(previous), &mut document, replica, now);
+        document
+    }
+
+    #[test]
+    fn test_concurrent_edits_merge_per_field() {
+        let mut base = NVDocument::new("d".to_string(), "c".to_string(), HashMap::new());
+        stamp(None, &mut base, "a", 1.0);
+
+        let left = version(&base, "title", NVValue::String("left".to_string()), "a", 10.0);
+        let right = version(&base, "body", NVValue::String("right".to_string()), "b", 5.0);
+        let right = version(&right, "title", NVValue::String("older".to_string()), "b", 6.0);
+
+        let merged = merge(&left, &right);
+        assert_eq!(merged.data, merge(&right, &left).data);
+        assert_eq!(merged.get("title"), Some(&NVValue::String("left".to_string())));
+        assert_eq!(merged.get("body"), Some(&NVValue::String("right".to_string())));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::error::{NeuralVaultError, NVResult};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Length of a field encryption key in bytes
+pub const KEY_LEN: usize = 32;
+
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+
+/// Authenticated encryption (ChaCha20-Poly1305) for individual field values
+///
+/// Sealed values are `[nonce(12)][ciphertext][tag(16)]`, with a fresh random
+/// nonce per value.
+#[derive(Clone)]
+pub struct FieldCipher {
+    cipher: ChaCha20Poly1305,
+}
+
+impl FieldCipher {
+    /// Create a cipher from a 32-byte key
+    pub fn new(key: &[u8]) -> NVResult<Self> {
+        let cipher = ChaCha20Poly1305::new_from_slice(key).map_err(|_| {
+            NeuralVaultError::EncryptionError(format!("Field keys must be {} bytes", KEY_LEN))
+        })?;
+        Ok(Self { cipher })
+    }
+
+    /// Encrypt a value
+    pub fn seal(&self, plaintext: &[u8]) -> NVResult<Vec<u8>> {
+        self.seal_with(plaintext, &[])
+    }
+
+    /// Encrypt a value, authenticating `aad` along with it
+    ///
+    /// `open_with` must be given the same `aad`.
+    pub fn seal_with(&self, plaintext: &[u8], aad: &[u8]) -> NVResult<Vec<u8>> {
+        let nonce = random_bytes(NONCE_LEN);
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad })
+            .map_err(|_| NeuralVaultError::EncryptionError("Encryption failed".to_string()))?;
+
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypt a value produced by `seal`, failing if it was sealed with
+    /// another key or has been tampered with
+    pub fn open(&self, sealed: &[u8]) -> NVResult<Vec<u8>> {
+        self.open_with(sealed, &[])
+    }
+
+    /// Decrypt a value produced by `seal_with`
+    pub fn open_with(&self, sealed: &[u8], aad: &[u8]) -> NVResult<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return Err(NeuralVaultError::EncryptionError("Sealed value is truncated".to_string()));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+            .map_err(|_| {
+                NeuralVaultError::EncryptionError(
+                    "Wrong key or corrupted encrypted value".to_string(),
+                )
+            })
+    }
+}
+
+// Never print key material
+impl fmt::Debug for FieldCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FieldCipher { .. }")
+    }
+}
+
+/// Argon2id cost settings for deriving keys from passwords
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// Memory cost in KiB
+    pub memory_kib: u32,
+    /// Number of passes over memory
+    pub iterations: u32,
+}
+
+impl KdfParams {
+    /// Derive a key from a password and salt
+    pub fn derive(&self, password: &str, salt: &[u8]) -> NVResult<Vec<u8>> {
+        let params = Params::new(self.memory_kib, self.iterations, 1, Some(KEY_LEN))
+            .map_err(|e| NeuralVaultError::InvalidConfiguration(format!("Invalid KDF settings: {}", e)))?;
+
+        let mut key = vec![0u8; KEY_LEN];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), salt, &mut key)
+            .map_err(|e| NeuralVaultError::EncryptionError(format!("Key derivation failed: {}", e)))?;
+        Ok(key)
+    }
+}
+
+/// A master key encrypted under a key derived from a password
+///
+/// The KDF settings are stored with it, so changing the configured
+/// settings only affects keys wrapped afterwards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WrappedKey {
+    salt: String,
+    params: KdfParams,
+    wrapped: String,
+}
+
+impl WrappedKey {
+    /// Encrypt `master` under `password` with a fresh salt
+    pub fn wrap(master: &[u8], password: &str, params: KdfParams) -> NVResult<Self> {
+        let salt = random_bytes(SALT_LEN);
+        let cipher = FieldCipher::new(&params.derive(password, &salt)?)?;
+        Ok(Self {
+            salt: to_hex(&salt),
+            params,
+            wrapped: to_hex(&cipher.seal(master)?),
+        })
+    }
+
+    /// Recover the master key, failing if the password is wrong
+    pub fn unwrap(&self, password: &str) -> NVResult<Vec<u8>> {
+        let key = self.params.derive(password, &from_hex(&self.salt)?)?;
+        FieldCipher::new(&key)?
+            .open(&from_hex(&self.wrapped)?)
+            .map_err(|_| NeuralVaultError::EncryptionError("Wrong password".to_string()))
+    }
+
+    pub fn params(&self) -> KdfParams {
+        self.params
+    }
+}
+
+/// Random bytes from the operating system's generator
+pub fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+/// Lowercase hex encoding, for storing sealed bytes as catalog strings
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decode `to_hex` output
+pub fn from_hex(hex: &str) -> NVResult<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(NeuralVaultError::SerializationError("Odd-length hex string".to_string()));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|e| NeuralVaultError::SerializationError(e.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let cipher = FieldCipher::new(&[7u8; KEY_LEN]).unwrap();
+        let sealed = cipher.seal(b"123-45-6789").unwrap();
+        assert!(!sealed.windows(11).any(|window| window == b"123-45-6789"));
+        assert_eq!(cipher.open(&sealed).unwrap(), b"123-45-6789");
+
+        // Nonces are fresh, so equal values don't produce equal ciphertexts
+        assert_ne!(cipher.seal(b"123-45-6789").unwrap(), sealed);
+
+        let other = FieldCipher::new(&[8u8; KEY_LEN]).unwrap();
+        assert!(other.open(&sealed).is_err());
+        assert!(FieldCipher::new(&[0u8; 16]).is_err());
+
+        assert_eq!(from_hex(&to_hex(&sealed)).unwrap(), sealed);
+    }
+
+    #[test]
+    fn test_wrapped_key() {
+        let params = KdfParams { memory_kib: 64, iterations: 1 };
+        let master = random_bytes(KEY_LEN);
+        let wrapped = WrappedKey::wrap(&master, "correct horse", params).unwrap();
+
+        assert_eq!(wrapped.unwrap("correct horse").unwrap(), master);
+        assert!(wrapped.unwrap("battery staple").is_err());
+        assert!(params.derive("x", &[0u8; SALT_LEN]).unwrap() != params.derive("y", &[0u8; SALT_LEN]).unwrap());
+        assert!(KdfParams { memory_kib: 0, iterations: 1 }.derive("x", &[0u8; SALT_LEN]).is_err());
+    }
+}
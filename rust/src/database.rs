@@ -1,10 +1,60 @@
+use crate::aggregate::{Aggregate, AggregateDefinition, MaterializedAggregate};
+use crate::audit::{self, AUDIT_COLLECTION};
+use crate::auth::{self, Access, AccessControl, Role, User};
+use crate::authorized::AuthorizedVault;
+use crate::chunking::{self, ChunkOptions};
+use crate::crdt;
+use crate::crypto::{self, FieldCipher, KdfParams, WrappedKey};
+use crate::embedding::{Embedder, EmbedderRegistry, FieldEmbedder};
+use crate::entity::{self, Entities, NVEntity};
 use crate::error::{NeuralVaultError, NVResult};
-use crate::models::{DatabaseConfig, NVDocument, NVQuery, NVValue, UpdateOperation};
-use crate::query::QueryProcessor;
-use crate::storage::FileManager;
-use parking_lot::RwLock;
-use std::collections::HashMap;
+use crate::expression;
+#[cfg(feature = "analytics")]
+use crate::export;
+use crate::capped::{CappedCollection, CollectionCap, UpdateOrder};
+use crate::kv::{self, KV_COLLECTION};
+use crate::migration::MigrationRegistry;
+use crate::object::NVObject;
+use crate::schema::{self, CollectionSchema};
+use crate::hooks::{AfterUpdateHook, BeforeCreateHook, BeforeDeleteHook, HookRegistry, Middleware};
+use crate::ids::UlidGenerator;
+use crate::import;
+use crate::index::vector::{self, VectorIndex, VectorIndexDefinition};
+use crate::index::highlight::{self, Highlight, HighlightOptions};
+use crate::index::{
+    CompletionIndex, CompletionIndexDefinition, IndexDefinition, Quantization, SecondaryIndex, TextIndex,
+    TextIndexDefinition,
+};
+use crate::models::{
+    DatabaseConfig, IdStrategy, LogicalOperator, NVDocument, NVQuery, NVValue, OnDelete, QueryCondition,
+    QueryOperator, QuotaPolicy, Reference, UpdateOperation,
+    VERSION_FIELD, WriteOp,
+};
+use crate::pipeline::{self, Interval, Row, Stage};
+use crate::query::cursor::Pending;
+use crate::query::{json, planner, sql, targets, validate, CancellationToken, Cursor, QueryStats, IndexAdvisor, IndexSuggestion, QueryPlan, QueryProcessor, QueryWarning, ScanTally, VectorFilterPlan};
+use crate::replication::{Change, ChangeSet, OplogEntry, OplogPage, OplogPosition, CHANGES_PAGE_SIZE};
+use crate::search::{self, HybridQuery};
+use crate::scoped::ScopedVault;
+use crate::snapshot::Snapshot;
+use crate::storage::{archive, attachments, backup, AttachmentInfo, AttachmentReader, BackupInfo, BackupKey, BackupScheduler, CompactionProgress, CorruptRange, DiskUsage, FileManager, RecordView, RecoveryReport, WriteBatch};
+use crate::timeseries::{TimeSeries, TimeSeriesOptions};
+use crate::transaction::Transaction;
+use crate::sync::{SyncState, SyncWrite};
+use crate::system::{keys, SystemCatalog, SYSTEM_COLLECTION};
+use crate::validation;
+use chrono::{DateTime, Utc};
+use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cell::{OnceCell, RefCell};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
 /// Main database engine
@@ -12,75 +62,1135 @@ pub struct NeuralVault {
     config: DatabaseConfig,
     storage: Arc<FileManager>,
     query_processor: QueryProcessor,
+    system: SystemCatalog,
+    /// Per-collection ID strategies, mirrored from the system catalog
+    id_strategies: RwLock<HashMap<String, IdStrategy>>,
+    /// Collection schemas, mirrored from the system catalog
+    schemas: RwLock<HashMap<String, CollectionSchema>>,
+    /// Last number issued by each counter, by catalog key
+    sequences: Mutex<HashMap<String, u64>>,
+    ulids: UlidGenerator,
+    hooks: RwLock<HookRegistry>,
+    embedders: RwLock<EmbedderRegistry>,
+    /// Reference declarations, mirrored from the system catalog
+    references: RwLock<Vec<Reference>>,
+    /// Saved views by name, mirrored from the system catalog
+    views: RwLock<HashMap<String, NVQuery>>,
+    /// Materialized aggregate views by name, maintained on every write
+    aggregates: RwLock<HashMap<String, MaterializedAggregate>>,
+    /// Secondary indexes by name, maintained on every write
+    indexes: RwLock<HashMap<String, SecondaryIndex>>,
+    /// Full-text indexes by name, maintained on every write
+    texts: RwLock<HashMap<String, TextIndex>>,
+    /// Autocomplete indexes by `collection.field`, maintained on every write
+    completions: RwLock<HashMap<String, CompletionIndex>>,
+    /// Capped collections by name, tracking their documents' ages on every
+    /// write
+    capped: RwLock<HashMap<String, CappedCollection>>,
+    /// Time-series collections by name, bucketing their events on every
+    /// write
+    time_series: RwLock<HashMap<String, TimeSeries>>,
+    /// Vector indexes by name, maintained on every write and checkpointed
+    /// to disk
+    vectors: RwLock<HashMap<String, VectorIndex>>,
+    /// Full-scan statistics behind `suggest_indexes`
+    advisor: IndexAdvisor,
+    /// IDs with archived copies by collection, read from the segments the
+    /// first time a collection is written
+    archived_ids: RwLock<HashMap<String, HashSet<String>>>,
+    /// Users, roles and tokens, mirrored from the system catalog
+    access: RwLock<AccessControl>,
+    /// Record every document write in the audit collection
+    audit: AtomicBool,
+    /// Who writes through this handle are made on behalf of
+    actor: RwLock<Option<String>>,
+    /// Collections whose documents are CRDT maps
+    crdt_collections: RwLock<HashSet<String>>,
+    /// Cached `replica_id`, once one exists
+    replica: RwLock<Option<String>>,
+    /// The current oplog; held while compaction renumbers it
+    oplog: Arc<RwLock<OplogState>>,
+    /// Documents of the cache collections by last update, for quota eviction
+    cache_order: RwLock<UpdateOrder>,
+    /// Held while evicting cache documents to stay under the size quota
+    eviction: Mutex<()>,
+    /// Held while writing or removing attachments
+    attachment_writes: Mutex<()>,
+    /// Documents deleted since their attachments were last removed
+    detached: Mutex<Vec<String>>,
+    /// Migrations registered through this handle
+    migrations: RwLock<MigrationRegistry>,
+    /// Held while `migrate` runs
+    migrating: Mutex<()>,
+    /// Key backups are encrypted with, if any
+    backup_key: Arc<RwLock<Option<BackupKey>>>,
+    /// The latest backup taken through this handle, so an incremental
+    /// continuing from it can leave out the files it already holds
+    last_backup: Arc<Mutex<Option<BackupInfo>>>,
+    /// Writes scheduled backups, when `backup_dir` is set
+    _backups: Option<BackupScheduler>,
     initialized: bool,
 }
 
 impl NeuralVault {
     /// Create a new database instance
     pub fn new(config: DatabaseConfig) -> NVResult<Self> {
-        let storage = Arc::new(FileManager::new(&config.path)?);
-        
-        // Rebuild index on startup
-        storage.rebuild_index()?;
+        let storage = if config.read_only {
+            FileManager::open_read_only(&config.path)?
+        } else {
+            FileManager::new(&config.path)?
+        };
+        let storage = Arc::new(
+            storage
+                .with_checkpoint_interval(config.checkpoint_interval)
+                .with_commit_window(Duration::from_micros(config.commit_window_micros))
+                .with_background_flush(
+                    Duration::from_millis(config.flush_interval_ms),
+                    config.flush_threshold_bytes,
+                )
+                .with_size_limits(config.max_document_size, config.overflow_threshold)
+                .with_max_size(config.max_database_size),
+        );
+
+        // Load the last index checkpoint and replay newer writes, or rebuild
+        storage.load_or_rebuild_index()?;
+
+        // Record the format version on first open and reject newer formats
+        let system = SystemCatalog::new(storage.clone());
+        system.initialize()?;
+
+        for (key, _) in system.list(keys::ENCRYPTED_FIELD_PREFIX)? {
+            if let Some((collection, field)) = key[keys::ENCRYPTED_FIELD_PREFIX.len()..].split_once('.') {
+                storage.seal_field(collection, field);
+            }
+        }
+
+        let id_strategies = system
+            .list(keys::ID_STRATEGY_PREFIX)?
+            .into_iter()
+            .filter_map(|(key, value)| match value {
+                NVValue::String(name) => Some((
+                    key[keys::ID_STRATEGY_PREFIX.len()..].to_string(),
+                    IdStrategy::parse(&name)?,
+                )),
+                _ => None,
+            })
+            .collect();
+
+        let schemas = system
+            .list(keys::SCHEMA_PREFIX)?
+            .into_iter()
+            .filter_map(|(key, value)| match value {
+                NVValue::String(json) => Some((
+                    key[keys::SCHEMA_PREFIX.len()..].to_string(),
+                    serde_json::from_str(&json).ok()?,
+                )),
+                _ => None,
+            })
+            .collect();
+
+        let references = system
+            .list(keys::REFERENCE_PREFIX)?
+            .into_iter()
+            .filter_map(|(_, value)| serde_json::from_value(serde_json::Value::from(value)).ok())
+            .collect();
+
+        let views = system
+            .list(keys::VIEW_PREFIX)?
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let query = serde_json::from_value(serde_json::Value::from(value)).ok()?;
+                Some((key[keys::VIEW_PREFIX.len()..].to_string(), query))
+            })
+            .collect();
+
+        let audit = matches!(system.get(keys::AUDIT_LOG)?, Some(NVValue::Bool(true)));
+
+        let crdt_collections = system
+            .list(keys::CRDT_PREFIX)?
+            .into_iter()
+            .map(|(key, _)| key[keys::CRDT_PREFIX.len()..].to_string())
+            .collect();
+        let replica = match system.get(keys::REPLICA_ID)? {
+            Some(NVValue::String(id)) => Some(id),
+            _ => None,
+        };
+
+        let oplog = match (system.get(keys::OPLOG_ID)?, system.get(keys::OPLOG_BASE)?) {
+            (Some(NVValue::String(id)), Some(NVValue::Number(base))) => OplogState {
+                id,
+                base: base as u64,
+            },
+            (Some(NVValue::String(id)), _) if config.read_only => OplogState { id, base: 0 },
+            _ if config.read_only => OplogState { id: String::new(), base: 0 },
+            (id, _) => {
+                // Compaction rewrites both entries, so they must exist
+                let oplog = OplogState {
+                    id: match id {
+                        Some(NVValue::String(id)) => id,
+                        _ => Uuid::new_v4().to_string(),
+                    },
+                    base: 0,
+                };
+                system.set(keys::OPLOG_ID, NVValue::String(oplog.id.clone()))?;
+                system.set(keys::OPLOG_BASE, NVValue::Number(0.0))?;
+                oplog
+            }
+        };
+
+        let mut access = AccessControl::new();
+        for (_, value) in system.list(keys::ROLE_PREFIX)? {
+            if let Ok(role) = serde_json::from_value(value.into()) {
+                access.set_role(role);
+            }
+        }
+        for (_, value) in system.list(keys::AUTH_USER_PREFIX)? {
+            if let Ok(user) = serde_json::from_value(value.into()) {
+                access.set_user(user);
+            }
+        }
+        for (key, value) in system.list(keys::TOKEN_PREFIX)? {
+            if let NVValue::String(user) = value {
+                access.add_token(key[keys::TOKEN_PREFIX.len()..].to_string(), user);
+            }
+        }
+
+        let query_processor =
+            QueryProcessor::with_parallelism(config.query_threads, config.parallel_query_threshold)?;
+
+        // Materialized aggregates are rebuilt from the data on open
+        let mut aggregates = HashMap::new();
+        for (key, value) in system.list(keys::AGGREGATE_PREFIX)? {
+            let Ok(definition) = serde_json::from_value::<AggregateDefinition>(value.into()) else {
+                continue;
+            };
+            aggregates.insert(key[keys::AGGREGATE_PREFIX.len()..].to_string(), MaterializedAggregate::new(definition));
+        }
+
+        // As are secondary indexes
+        let mut indexes = HashMap::new();
+        for (_, value) in system.list(keys::INDEX_PREFIX)? {
+            let Ok(definition) = serde_json::from_value::<IndexDefinition>(value.into()) else {
+                continue;
+            };
+            indexes.insert(definition.name.clone(), SecondaryIndex::new(definition));
+        }
+
+        // Full-text indexes
+        let mut texts = HashMap::new();
+        for (_, value) in system.list(keys::TEXT_INDEX_PREFIX)? {
+            // Older definitions were stored as objects
+            let definition = match value {
+                NVValue::String(json) => serde_json::from_str::<TextIndexDefinition>(&json),
+                value => serde_json::from_value(value.into()),
+            };
+            let Ok(definition) = definition else { continue };
+            texts.insert(definition.name.clone(), TextIndex::new(definition));
+        }
+
+        // Autocomplete indexes
+        let mut completions = HashMap::new();
+        for (key, value) in system.list(keys::COMPLETION_INDEX_PREFIX)? {
+            let Ok(definition) = serde_json::from_value::<CompletionIndexDefinition>(value.into()) else {
+                continue;
+            };
+            completions.insert(key[keys::COMPLETION_INDEX_PREFIX.len()..].to_string(), CompletionIndex::new(definition));
+        }
+
+        // Capped collections, which track their documents
+        let mut capped = HashMap::new();
+        for (key, value) in system.list(keys::CAPPED_PREFIX)? {
+            let NVValue::String(json) = value else { continue };
+            let Ok(cap) = serde_json::from_str::<CollectionCap>(&json) else {
+                continue;
+            };
+            capped.insert(key[keys::CAPPED_PREFIX.len()..].to_string(), CappedCollection::new(cap));
+        }
+
+        // And time-series collections
+        let mut time_series = HashMap::new();
+        for (key, value) in system.list(keys::TIME_SERIES_PREFIX)? {
+            let NVValue::String(json) = value else { continue };
+            let Ok(options) = serde_json::from_str::<TimeSeriesOptions>(&json) else {
+                continue;
+            };
+            time_series.insert(key[keys::TIME_SERIES_PREFIX.len()..].to_string(), TimeSeries::new(options));
+        }
+
+        // Fill them all in with one scan of each collection they cover
+        let collections: BTreeSet<String> = aggregates
+            .values()
+            .map(|aggregate| aggregate.definition().collection.clone())
+            .chain(indexes.values().map(|index| index.definition().collection.clone()))
+            .chain(texts.values().map(|index| index.definition().collection.clone()))
+            .chain(completions.values().map(|index| index.definition().collection.clone()))
+            .chain(capped.keys().cloned())
+            .chain(time_series.keys().cloned())
+            .chain(config.cache_collections.iter().cloned())
+            .collect();
+        let mut cache_order = UpdateOrder::new();
+        for collection in &collections {
+            let mut aggregates: Vec<_> =
+                aggregates.values_mut().filter(|a| a.definition().collection == *collection).collect();
+            let mut indexes: Vec<_> = indexes.values_mut().filter(|i| i.definition().collection == *collection).collect();
+            let mut texts: Vec<_> = texts.values_mut().filter(|i| i.definition().collection == *collection).collect();
+            let mut completions: Vec<_> =
+                completions.values_mut().filter(|i| i.definition().collection == *collection).collect();
+            let mut tracked = capped.get_mut(collection);
+            let mut series = time_series.get_mut(collection);
+            let cached = config.cache_collections.contains(collection);
+
+            for document in storage.scan_collection(collection)? {
+                aggregates.iter_mut().for_each(|aggregate| aggregate.add(&document));
+                indexes.iter_mut().for_each(|index| index.add(&document, &query_processor));
+                texts.iter_mut().for_each(|index| index.add(&document));
+                completions.iter_mut().for_each(|index| index.add(&document));
+                if let Some(tracked) = tracked.as_mut() {
+                    tracked.add(&document);
+                }
+                if let Some(series) = series.as_mut() {
+                    series.add(&document);
+                }
+                if cached {
+                    cache_order.add(&document);
+                }
+            }
+        }
+
+        // Vector indexes resume from their checkpoints where possible
+        let mut vectors = HashMap::new();
+        for (_, value) in system.list(keys::VECTOR_INDEX_PREFIX)? {
+            let NVValue::String(json) = value else { continue };
+            let Ok(definition) = serde_json::from_str::<VectorIndexDefinition>(&json) else {
+                continue;
+            };
+            let path = vector_index_path(&config.path, &definition.name);
+            let index = open_vector_index(&storage, definition, &path, &oplog.id)?;
+            vectors.insert(index.definition().name.clone(), index);
+        }
+
+        let oplog = Arc::new(RwLock::new(oplog));
+        let backup_key = Arc::new(RwLock::new(None));
+        let last_backup = Arc::new(Mutex::new(None));
+        let backups = match &config.backup_dir {
+            Some(dir) if !config.read_only && config.backup_interval_secs > 0 => {
+                let storage = storage.clone();
+                let oplog = oplog.clone();
+                let backup_key = backup_key.clone();
+                let last_backup = last_backup.clone();
+                let archive_dir = Path::new(&config.path).join("archive");
+                let attachments_dir = Path::new(&config.path).join("attachments");
+                let destination = PathBuf::from(dir);
+                let kdf = KdfParams {
+                    memory_kib: config.kdf_memory_kib,
+                    iterations: config.kdf_iterations,
+                };
+                Some(BackupScheduler::start(
+                    move || {
+                        let oplog = oplog.read();
+                        let key = backup_key.read().clone();
+                        let info = backup::create(
+                            &storage,
+                            &archive_dir,
+                            &attachments_dir,
+                            &destination,
+                            oplog.base,
+                            key.as_ref().map(|key| (key, kdf)),
+                            true,
+                        )?;
+                        *last_backup.lock() = Some(info.clone());
+                        Ok(info)
+                    },
+                    PathBuf::from(dir),
+                    Duration::from_secs(config.backup_interval_secs),
+                    config.backup_keep,
+                ))
+            }
+            _ => None,
+        };
 
         Ok(Self {
             config,
             storage,
-            query_processor: QueryProcessor::new(),
+            query_processor,
+            system,
+            id_strategies: RwLock::new(id_strategies),
+            schemas: RwLock::new(schemas),
+            sequences: Mutex::new(HashMap::new()),
+            ulids: UlidGenerator::new(),
+            hooks: RwLock::new(HookRegistry::new()),
+            embedders: RwLock::new(EmbedderRegistry::new()),
+            references: RwLock::new(references),
+            views: RwLock::new(views),
+            aggregates: RwLock::new(aggregates),
+            indexes: RwLock::new(indexes),
+            texts: RwLock::new(texts),
+            completions: RwLock::new(completions),
+            capped: RwLock::new(capped),
+            time_series: RwLock::new(time_series),
+            vectors: RwLock::new(vectors),
+            advisor: IndexAdvisor::new(),
+            archived_ids: RwLock::new(HashMap::new()),
+            access: RwLock::new(access),
+            audit: AtomicBool::new(audit),
+            actor: RwLock::new(None),
+            crdt_collections: RwLock::new(crdt_collections),
+            replica: RwLock::new(replica),
+            oplog,
+            cache_order: RwLock::new(cache_order),
+            eviction: Mutex::new(()),
+            attachment_writes: Mutex::new(()),
+            detached: Mutex::new(Vec::new()),
+            migrations: RwLock::new(MigrationRegistry::new()),
+            migrating: Mutex::new(()),
+            backup_key,
+            last_backup,
+            _backups: backups,
             initialized: true,
         })
     }
 
+    /// Create a document from any value that serializes to a map
+    ///
+    /// A string `_id` entry is used as the document ID, as with
+    /// `create_with_id`; otherwise the collection's strategy picks one.
+    pub fn create_typed<T: Serialize + ?Sized>(&self, collection: &str, value: &T) -> NVResult<String> {
+        match entity::serialize(value)? {
+            (Some(id), data) => self.create_with_id(id, collection.to_string(), data),
+            (None, data) => self.create(collection.to_string(), data),
+        }
+    }
+
     /// Create a new document
     pub fn create(&self, collection: String, data: HashMap<String, NVValue>) -> NVResult<String> {
         self.ensure_initialized()?;
+        self.make_room()?;
+        validation::validate_collection_name(&collection)?;
+        validation::validate_fields(&data)?;
 
-        // Generate unique ID
-        let id = Uuid::new_v4().to_string();
+        // Generate an ID using the collection's strategy
+        let id = self.generate_id(&collection, &data)?;
 
         // Create document
-        let document = NVDocument::new(id.clone(), collection, data);
+        let document = self.prepare_create(NVDocument::new(id.clone(), collection, data))?;
+
+        // Persist to storage, rejecting IDs that are already taken
+        self.storage
+            .write_batch(|batch| self.insert_document(batch, &document))??;
+
+        Ok(id)
+    }
+
+    /// Create a document with a caller-chosen ID
+    ///
+    /// Fails with `DuplicateId` if a live document already has this ID, which
+    /// makes retried writes idempotent and allows natural keys.
+    pub fn create_with_id(
+        &self,
+        id: String,
+        collection: String,
+        data: HashMap<String, NVValue>,
+    ) -> NVResult<String> {
+        self.ensure_initialized()?;
+        self.make_room()?;
+        validation::validate_document_id(&id)?;
+        validation::validate_collection_name(&collection)?;
+        validation::validate_fields(&data)?;
 
-        // Persist to storage
-        self.storage.append(&document)?;
+        let document = self.prepare_create(NVDocument::new(id.clone(), collection, data))?;
+        self.storage
+            .write_batch(|batch| self.insert_document(batch, &document))??;
 
         Ok(id)
     }
 
+    /// Import the output of `mongoexport` into a collection
+    ///
+    /// Extended JSON values are mapped as described in
+    /// `import::from_extended_json`, and each document's `_id` becomes its
+    /// ID. Documents are inserted with `bulk_write`, so one that fails (a
+    /// duplicate ID, say) doesn't stop the rest; the result for each is its
+    /// ID or its error.
+    pub fn import_mongo(&self, collection: &str, export: &str) -> NVResult<Vec<NVResult<String>>> {
+        self.ensure_initialized()?;
+        let ops = import::parse_mongo_export(export)?
+            .into_iter()
+            .map(|(id, data)| WriteOp::Insert {
+                id,
+                collection: collection.to_string(),
+                data,
+            })
+            .collect();
+        self.bulk_write(ops)
+    }
+
+    /// Import the tables of a SQLite database, one collection per table
+    ///
+    /// Rows are read and inserted `IMPORT_BATCH_SIZE` at a time, so large
+    /// tables aren't held in memory. Rows that fail to insert are reported
+    /// per table rather than stopping the import.
+    #[cfg(feature = "sqlite")]
+    pub fn import_sqlite(
+        &self,
+        path: &Path,
+        options: import::SqliteImportOptions,
+    ) -> NVResult<Vec<import::ImportedTable>> {
+        self.ensure_initialized()?;
+        let source = import::SqliteSource::open(path, options)?;
+
+        let mut results = Vec::new();
+        for table in source.tables()? {
+            let collection = source.collection(&table);
+            validation::validate_collection_name(&collection)?;
+
+            let mut result = import::ImportedTable {
+                table: table.clone(),
+                collection: collection.clone(),
+                imported: 0,
+                errors: Vec::new(),
+            };
+            source.read_table(&table, IMPORT_BATCH_SIZE, |documents| {
+                let ops = documents
+                    .into_iter()
+                    .map(|(id, data)| WriteOp::Insert {
+                        id,
+                        collection: collection.clone(),
+                        data,
+                    })
+                    .collect();
+                for written in self.bulk_write(ops)? {
+                    match written {
+                        Ok(_) => result.imported += 1,
+                        Err(e) => result.errors.push(e.to_string()),
+                    }
+                }
+                Ok(())
+            })?;
+            results.push(result);
+        }
+        Ok(results)
+    }
+
+    /// Apply a mix of inserts, updates, deletes and upserts
+    ///
+    /// All operations run under one storage lock acquisition and are synced
+    /// with a single fsync. A failing operation doesn't stop the others; the
+    /// result for each operation is its document ID or its error.
+    pub fn bulk_write(&self, ops: Vec<WriteOp>) -> NVResult<Vec<NVResult<String>>> {
+        self.ensure_initialized()?;
+        self.make_room()?;
+
+        // Validate and assign IDs before taking the storage locks, since ID
+        // generation may itself write to the system catalog
+        let prepared: Vec<NVResult<WriteOp>> = ops.into_iter().map(|op| self.prepare_write(op)).collect();
+
+        let hooks = self.hooks.read();
+        let mut updated = Vec::new();
+        let results: Vec<NVResult<String>> = self.write_batch(|batch| {
+            prepared
+                .into_iter()
+                .map(|op| {
+                    op.and_then(|op| {
+                        let (id, update) = self.apply_write(batch, &hooks, op)?;
+                        updated.extend(update);
+                        Ok(id)
+                    })
+                })
+                .collect()
+        })?;
+
+        // After-update hooks run once the batch is synced
+        for (previous, current) in updated {
+            hooks.after_update(&previous, &current);
+        }
+
+        Ok(results)
+    }
+
+    /// Start collecting writes to apply together
+    pub fn transaction(&self) -> Transaction<'_> {
+        Transaction::new(self)
+    }
+
+    /// Run `f` in a transaction and commit it, retrying on write conflicts
+    ///
+    /// `f` runs again from scratch with a fresh transaction each time a
+    /// document it read or wrote was changed by another writer before the
+    /// commit, up to `transaction_retries` times. Since every document the
+    /// transaction depends on is checked under the commit's lock, the
+    /// result is as if the transactions ran one after another. `f` should
+    /// only have effects through the transaction.
+    pub fn transact<T>(&self, mut f: impl FnMut(&mut Transaction) -> NVResult<T>) -> NVResult<T> {
+        let mut retries = 0;
+        loop {
+            let mut tx = self.transaction();
+            let value = f(&mut tx)?;
+            match tx.commit() {
+                Ok(_) => return Ok(value),
+                Err(NeuralVaultError::WriteConflict(_)) if retries < self.config.transaction_retries => {
+                    retries += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Identifies the stored version of a document, if it exists
+    pub(crate) fn document_version(&self, id: &str) -> Option<u64> {
+        self.storage.version(id)
+    }
+
+    /// Apply a transaction's writes in one batch
+    ///
+    /// Unlike `bulk_write`, the operations are checked against each other
+    /// and the stored documents before any is written, and the first
+    /// failure fails the whole commit, undoing the writes made before it.
+    /// Documents whose version no longer matches `versions` fail it with
+    /// `WriteConflict`.
+    pub(crate) fn commit_writes(
+        &self,
+        ops: Vec<WriteOp>,
+        versions: HashMap<String, Option<u64>>,
+        catalog: Vec<(String, NVValue)>,
+    ) -> NVResult<Vec<String>> {
+        self.ensure_initialized()?;
+        self.make_room()?;
+        let prepared = ops
+            .into_iter()
+            .map(|op| self.prepare_write(op))
+            .collect::<NVResult<Vec<_>>>()?;
+
+        let hooks = self.hooks.read();
+        let mut updated = Vec::new();
+        let commit = |batch: &mut WriteBatch| -> NVResult<Vec<String>> {
+            let changed = versions.iter().find(|(id, version)| batch.version(id) != **version);
+            if let Some((id, _)) = changed {
+                return Err(NeuralVaultError::WriteConflict(format!(
+                    "Document {} changed during the transaction",
+                    id
+                )));
+            }
+            check_conflicts(batch, prepared.iter())?;
+
+            let mut ids = Vec::with_capacity(prepared.len());
+            for op in prepared {
+                let (id, update) = self.apply_write(batch, &hooks, op)?;
+                updated.extend(update);
+                ids.push(id);
+            }
+            for (key, value) in catalog {
+                SystemCatalog::set_in(batch, &key, value)?;
+            }
+            Ok(ids)
+        };
+        let revert = |written: Option<&NVDocument>, before: Option<&NVDocument>| self.record_change(written, before);
+        let ids = self.write_batch_atomic(commit, revert)?;
+
+        for (previous, current) in updated {
+            hooks.after_update(&previous, &current);
+        }
+        Ok(ids)
+    }
+
     /// Find documents matching a query
     pub fn find(&self, query: NVQuery) -> NVResult<Vec<NVDocument>> {
+        self.find_cancellable(query, &CancellationToken::new())
+    }
+
+    /// Like `find`, deserializing each document into `T`
+    ///
+    /// The document ID is available to `T` as `_id`.
+    pub fn find_typed<T: DeserializeOwned>(&self, query: NVQuery) -> NVResult<Vec<T>> {
+        self.find(query)?.iter().map(entity::deserialize).collect()
+    }
+
+    /// Like `find`, failing with `Cancelled` once `cancel` is cancelled or
+    /// times out
+    ///
+    /// The query's own `timeout_ms`, if any, applies too.
+    pub fn find_cancellable(&self, query: NVQuery, cancel: &CancellationToken) -> NVResult<Vec<NVDocument>> {
+        let redact = !query.include_sensitive;
+        let mut documents = self.find_scoped(query, cancel, &mut QueryStats::default())?;
+        if redact {
+            self.redact(&mut documents);
+        }
+        self.hooks.read().on_read(&mut documents)?;
+        Ok(documents)
+    }
+
+    /// Like `find_cancellable`, also reporting how many documents were
+    /// read, how long it took and which index was used
+    pub fn find_with_stats(
+        &self,
+        query: NVQuery,
+        cancel: &CancellationToken,
+    ) -> NVResult<(Vec<NVDocument>, QueryStats)> {
+        let started = Instant::now();
+        let mut stats = QueryStats::default();
+        let redact = !query.include_sensitive;
+        let mut documents = self.find_scoped(query, cancel, &mut stats)?;
+        if redact {
+            self.redact(&mut documents);
+        }
+        self.hooks.read().on_read(&mut documents)?;
+        stats.returned = documents.len();
+        stats.elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+        stats.index = stats.plan.as_ref().and_then(|plan| plan.index()).map(str::to_string);
+        Ok((documents, stats))
+    }
+
+    /// Run a query once middleware has had its say, without the read
+    /// middleware, so writes see documents whole
+    fn find_scoped(&self, mut query: NVQuery, cancel: &CancellationToken, stats: &mut QueryStats) -> NVResult<Vec<NVDocument>> {
+        self.hooks.read().on_query(&mut query)?;
+        self.run_find(query, cancel, stats)
+    }
+
+    /// Run a query, adding what it read to `stats`
+    fn run_find(&self, mut query: NVQuery, cancel: &CancellationToken, stats: &mut QueryStats) -> NVResult<Vec<NVDocument>> {
+        self.ensure_initialized()?;
+        let limited;
+        let cancel = match query.timeout_ms.take() {
+            Some(timeout) => {
+                limited = cancel.limited_to(Duration::from_millis(timeout));
+                &limited
+            }
+            None => cancel,
+        };
+        cancel.check()?;
+
+        if !query.subqueries.is_empty() {
+            self.resolve_subqueries(&mut query, cancel, stats)?;
+        }
+
+        if targets::is_multi(&query.collection) {
+            return self.find_across(query, cancel, stats);
+        }
+
+        // Compute fields from whole documents, then project
+        if !query.computed.is_empty() {
+            let computed = std::mem::take(&mut query.computed);
+            let projection = query.projection.take().map(|mut projection| {
+                projection.extend(computed.iter().map(|(name, _)| name.clone()));
+                projection
+            });
+            let mut documents = self.run_find(query, cancel, stats)?;
+            for doc in &mut documents {
+                expression::compute(&mut doc.data, &computed, &self.query_processor);
+                if let Some(projection) = &projection {
+                    doc.project(projection);
+                }
+            }
+            return Ok(documents);
+        }
+
+        if query.include_archived {
+            return self.find_with_archive(query, cancel, stats);
+        }
+
+        // Queries against a saved view filter the view's results
+        let view = self.views.read().get(&query.collection).cloned();
+        if let Some(view) = view {
+            return self.find_in_view(view, query, cancel, stats);
+        }
+
+        // Skip the scan when the bloom filters rule out a required equality
+        if self.ruled_out_by_filters(&query) {
+            stats.plan = Some(QueryPlan::RuledOut);
+            return Ok(Vec::new());
+        }
+
+        let planned = planner::plan(&query, &self.indexes.read(), &self.query_processor);
+        stats.plan = Some(planned.plan);
+        let mut documents = match planned.candidates {
+            // Served entirely from a covering index
+            None if planned.documents.is_some() => {
+                let documents = planned.documents.unwrap_or_default();
+                stats.scanned += documents.len();
+                self.query_processor.filter(documents, &query)?
+            }
+            // Read in sort order and stop once the page is full
+            None if planned.ordered.is_some() => {
+                let index = planned.ordered.as_deref().unwrap_or_default();
+                match self.find_index_ordered(index, &query, cancel, stats)? {
+                    Some(documents) => documents,
+                    // The index was dropped mid-walk; plan again without it
+                    None => return self.run_find(query, cancel, stats),
+                }
+            }
+            // Read only the documents the index points to
+            Some(ids) => {
+                let mut candidates = Vec::with_capacity(ids.len());
+                for id in &ids {
+                    cancel.check()?;
+                    candidates.extend(self.read_live(id)?);
+                }
+                stats.scanned += ids.len();
+                self.query_processor.filter(candidates, &query)?
+            }
+            None => {
+                // Load raw records; decoding happens alongside filtering
+                let records = self.storage.read_all_raw()?;
+                stats.scanned += records.len();
+
+                // Apply query filters
+                let overflow = |offset, len| self.storage.read_overflow(offset, len);
+                let opener = |sealed: &[u8]| self.storage.open_sealed(sealed);
+                let tally = ScanTally::new(&query);
+                let documents = self.query_processor.filter_records_cancellable(
+                    records, &query, &overflow, &opener, cancel, Some(&tally),
+                )?;
+                self.advisor.record_scan(&query, &self.query_processor, &tally);
+                documents
+            }
+        };
+
+        if let Some(projection) = &query.projection {
+            documents.iter_mut().for_each(|doc| doc.project(projection));
+        }
+        if !query.populate.is_empty() {
+            cancel.check()?;
+            self.populate(&query, &mut documents)?;
+        }
+        Ok(documents)
+    }
+
+    /// Walk `index` in the query's order a page at a time, reading
+    /// documents until `skip + limit` match
+    ///
+    /// The run of documents tied with the last match is read in full and
+    /// the matches are sorted again, so ties are broken the way a full
+    /// scan breaks them. Returns `None` if the index is dropped meanwhile.
+    fn find_index_ordered(
+        &self,
+        index: &str,
+        query: &NVQuery,
+        cancel: &CancellationToken,
+        stats: &mut QueryStats,
+    ) -> NVResult<Option<Vec<NVDocument>>> {
+        let wanted = query.skip.unwrap_or(0).saturating_add(query.limit.unwrap_or(usize::MAX));
+        let mut matched = Vec::new();
+        let mut last_key = None;
+        let mut after = None;
+        loop {
+            let page = match self.indexes.read().get(index) {
+                Some(index) => index.ordered_page(query.order_desc, after.as_ref(), ORDERED_PAGE),
+                None => return Ok(None),
+            };
+            let Some(last) = page.last().cloned() else { break };
+            for (key, id) in &page {
+                if matched.len() >= wanted && last_key.as_ref() != Some(&key[0]) {
+                    return Ok(Some(self.query_processor.finish(matched, query)?));
+                }
+                cancel.check()?;
+                stats.scanned += 1;
+                if let Some(document) = self.read_live(id)? {
+                    if self.query_processor.matches(&document, query) {
+                        matched.push(document);
+                        last_key = Some(key[0].clone());
+                    }
+                }
+            }
+            after = Some(last);
+        }
+        Ok(Some(self.query_processor.finish(matched, query)?))
+    }
+
+    /// Run an aggregation pipeline over a collection
+    ///
+    /// A leading `Match` stage is run as the query that reads the
+    /// collection, so it can use indexes; the other stages run over the
+    /// documents' data in order.
+    pub fn aggregate(&self, collection: &str, stages: Vec<Stage>) -> NVResult<Vec<Row>> {
+        self.aggregate_cancellable(collection, stages, &CancellationToken::new())
+    }
+
+    /// Like `aggregate`, failing with `Cancelled` once `cancel` is
+    /// cancelled or times out
+    pub fn aggregate_cancellable(
+        &self,
+        collection: &str,
+        stages: Vec<Stage>,
+        cancel: &CancellationToken,
+    ) -> NVResult<Vec<Row>> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(collection)?;
+
+        let mut query = NVQuery::new(collection.to_string());
+        let mut stages = stages.as_slice();
+        if let [Stage::Match { conditions, logical_operators }, rest @ ..] = stages {
+            query.conditions = conditions.clone();
+            query.logical_operators = logical_operators.clone();
+            stages = rest;
+        }
+
+        let mut rows = self.find_cancellable(query, cancel)?.into_iter().map(|doc| doc.data).collect();
+        for stage in stages {
+            cancel.check()?;
+            rows = pipeline::run(rows, std::slice::from_ref(stage), &self.query_processor);
+        }
+        Ok(rows)
+    }
+
+    /// Run a SQL `SELECT`; see `query::sql::parse_sql` for what's supported
+    ///
+    /// This and the `query_sql` API function are the only SQL front ends;
+    /// there is no command-line tool in this tree to expose it through yet.
+    pub fn query_sql(&self, statement: &str) -> NVResult<Vec<NVDocument>> {
+        self.find(sql::parse_sql(statement)?)
+    }
+
+    /// Run a query, returning its results through a cursor that hands them
+    /// out in batches
+    ///
+    /// Only the IDs of the results are kept; `next_batch` reads them.
+    /// Records are matched one at a time, so opening a cursor holds no
+    /// more than the IDs either, except for queries that combine several
+    /// sources (subqueries, several collections, views or archives), which
+    /// run in full.
+    pub fn open_cursor(&self, mut query: NVQuery) -> NVResult<Cursor> {
+        self.hooks.read().on_query(&mut query)?;
+
+        // Shape each batch as it's read rather than the results up front
+        let mut shape = NVQuery::new(query.collection.clone());
+        shape.projection = query.projection.take();
+        shape.computed = std::mem::take(&mut query.computed);
+        shape.populate = std::mem::take(&mut query.populate);
+        shape.include_sensitive = query.include_sensitive;
+
+        let combined = !query.subqueries.is_empty()
+            || targets::is_multi(&query.collection)
+            || query.include_archived
+            || self.views.read().contains_key(&query.collection);
+        if !combined {
+            let pending = self.matching_ids(&query)?.into_iter().map(Pending::Stored).collect();
+            return Ok(Cursor::new(pending, shape));
+        }
+
+        let documents = self.run_find(query, &CancellationToken::new(), &mut QueryStats::default())?;
+        let pending = documents
+            .into_iter()
+            .map(|doc| match self.storage.contains(&doc.id) {
+                true => Pending::Stored(doc.id),
+                false => Pending::Held(doc),
+            })
+            .collect();
+        Ok(Cursor::new(pending, shape))
+    }
+
+    /// IDs of the documents of one collection matching `query`, in its
+    /// order
+    ///
+    /// Records are decoded one at a time from a snapshot, and each match
+    /// is cut down to its ID, timestamps and the field the query orders
+    /// by, so ordering, skip and limit apply exactly as in `find`.
+    fn matching_ids(&self, query: &NVQuery) -> NVResult<Vec<String>> {
+        self.ensure_initialized()?;
+        if self.ruled_out_by_filters(query) {
+            return Ok(Vec::new());
+        }
+        let cancel = match query.timeout_ms {
+            Some(timeout) => CancellationToken::new().limited_to(Duration::from_millis(timeout)),
+            None => CancellationToken::new(),
+        };
+        let order_root = query.order_by.as_deref().map(|field| field.split('.').next().unwrap_or(field));
+
+        let overflow = |offset, len| self.storage.read_overflow(offset, len);
+        let opener = |sealed: &[u8]| self.storage.open_sealed(sealed);
+        let mut matches = Vec::new();
+        self.storage.snapshot().for_each_raw(|data| {
+            cancel.check()?;
+            if let Some(mut document) = self.query_processor.match_record(data, query, &overflow, &opener, None) {
+                let key = order_root.and_then(|root| document.data.remove_entry(root));
+                document.data = key.into_iter().collect();
+                matches.push(document);
+            }
+            Ok(())
+        })?;
+
+        let matches = self.query_processor.finish(matches, query)?;
+        Ok(matches.into_iter().map(|document| document.id).collect())
+    }
+
+    /// The next `n` results of a cursor, or fewer once they run out
+    ///
+    /// Results deleted since the cursor opened are skipped.
+    pub fn next_batch(&self, cursor: &mut Cursor, n: usize) -> NVResult<Vec<NVDocument>> {
+        self.ensure_initialized()?;
+        let mut documents = Vec::with_capacity(n.min(cursor.remaining()));
+        while documents.len() < n {
+            match cursor.pop() {
+                Some(Pending::Held(document)) => documents.push(document),
+                Some(Pending::Stored(id)) => documents.extend(self.read_live(&id)?),
+                None => break,
+            }
+        }
+
+        // Same order as `run_find`: project, populate, then compute fields
+        let shape = &cursor.shape;
+        let projection = shape.projection.clone().map(|mut projection| {
+            projection.extend(shape.computed.iter().map(|(name, _)| name.clone()));
+            projection
+        });
+        if let Some(projection) = &projection {
+            documents.iter_mut().for_each(|doc| doc.project(projection));
+        }
+        if !shape.populate.is_empty() {
+            let mut query = shape.clone();
+            for run in documents.chunk_by_mut(|a, b| a.collection == b.collection) {
+                query.collection.clone_from(&run[0].collection);
+                self.populate(&query, run)?;
+            }
+        }
+        if !shape.computed.is_empty() {
+            for doc in &mut documents {
+                expression::compute(&mut doc.data, &shape.computed, &self.query_processor);
+                if let Some(projection) = &projection {
+                    doc.project(projection);
+                }
+            }
+        }
+
+        if !shape.include_sensitive {
+            self.redact(&mut documents);
+        }
+        self.hooks.read().on_read(&mut documents)?;
+        Ok(documents)
+    }
+
+    /// Take a consistent read-only view of the database as it is now
+    pub fn snapshot(&self) -> NVResult<Snapshot<'_>> {
+        self.ensure_initialized()?;
+        Ok(Snapshot::new(
+            self.storage.snapshot(),
+            &self.query_processor,
+            self.schemas.read().clone(),
+        ))
+    }
+
+    /// Typed access to the collection of `T`; see `NVEntity`
+    pub fn entities<T: NVEntity>(&self) -> Entities<'_, T> {
+        Entities::new(self)
+    }
+
+    /// A handle whose collections are private to `tenant`; see `ScopedVault`
+    pub fn scoped(&self, tenant: &str) -> NVResult<ScopedVault<'_>> {
+        self.ensure_initialized()?;
+        validation::validate_tenant_id(tenant)?;
+        Ok(ScopedVault::new(self, tenant))
+    }
+
+    /// Indexes that would have spared the full scans run so far
+    pub fn suggest_indexes(&self) -> Vec<IndexSuggestion> {
+        self.advisor.suggest(&self.indexes.read())
+    }
+
+    /// Parse a JSON query over `collection` and report conditions that
+    /// can't match as written, without running it
+    ///
+    /// Fails with `InvalidQuery` if the query doesn't parse. Values are
+    /// checked against what their operators compare, such as `>` with a
+    /// string or `in` without an array, and against the types of fields
+    /// the collection's schema gives defaults.
+    pub fn validate_query(&self, collection: &str, query_json: &str) -> NVResult<Vec<QueryWarning>> {
         self.ensure_initialized()?;
+        let query = json::parse_query(collection.to_string(), query_json)?;
+        Ok(validate::check(&query, &|collection| self.schema(collection)))
+    }
+
+    /// Describe how `find` would execute a query without running it
+    pub fn explain(&self, query: &NVQuery) -> NVResult<QueryPlan> {
+        self.ensure_initialized()?;
+
+        if targets::is_multi(&query.collection) {
+            let parts = self
+                .resolve_targets(&query.collection)?
+                .into_iter()
+                .map(|collection| {
+                    let part = NVQuery {
+                        collection: collection.clone(),
+                        ..query.clone()
+                    };
+                    Ok((collection, self.explain(&part)?))
+                })
+                .collect::<NVResult<_>>()?;
+            return Ok(QueryPlan::Union { parts });
+        }
 
-        // Scan collection
-        let documents = self.storage.scan_collection(&query.collection)?;
+        let view = self.views.read().get(&query.collection).cloned();
+        if let Some(view) = view {
+            return Ok(QueryPlan::View {
+                name: query.collection.clone(),
+                source: Box::new(self.explain(&view)?),
+            });
+        }
 
-        // Apply query filters
-        self.query_processor.filter(documents, &query)
+        if self.ruled_out_by_filters(query) {
+            return Ok(QueryPlan::RuledOut);
+        }
+        Ok(planner::plan(query, &self.indexes.read(), &self.query_processor).plan)
     }
 
-    /// Find a single document by ID
+    /// Find a single document by ID, without its sensitive fields
     pub fn find_by_id(&self, id: &str) -> NVResult<NVDocument> {
         self.ensure_initialized()?;
-        self.storage.read(id)
+        let mut document = self.storage.read(id)?;
+        self.redact(std::slice::from_mut(&mut document));
+        self.hooks.read().on_read(std::slice::from_mut(&mut document))?;
+        Ok(document)
+    }
+
+    /// Find a single document by ID, sensitive fields included
+    pub fn find_by_id_unredacted(&self, id: &str) -> NVResult<NVDocument> {
+        self.ensure_initialized()?;
+        let mut document = self.storage.read(id)?;
+        self.hooks.read().on_read(std::slice::from_mut(&mut document))?;
+        Ok(document)
+    }
+
+    /// Read a document, or `None` if it isn't live; other errors, such as
+    /// a corrupt record, are returned
+    fn read_live(&self, id: &str) -> NVResult<Option<NVDocument>> {
+        match self.storage.read(id) {
+            Ok(document) => Ok(Some(document)),
+            Err(NeuralVaultError::DocumentNotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Remove the fields their collection's schema marks sensitive
+    fn redact(&self, documents: &mut [NVDocument]) {
+        schema::redact_documents(&self.schemas.read(), documents);
     }
 
     /// Update documents matching a query
-    pub fn update(&self, query: NVQuery, updates: Vec<UpdateOperation>) -> NVResult<usize> {
+    pub fn update(&self, mut query: NVQuery, updates: Vec<UpdateOperation>) -> NVResult<usize> {
         self.ensure_initialized()?;
+        self.make_room()?;
+        validate_updates(&updates)?;
 
-        // Find matching documents
-        let documents = self.find(query)?;
+        // Find matching documents; populated fields must not be written back
+        query.populate.clear();
+        query.projection = None;
+        query.computed.clear();
+        let documents = self.find_scoped(query, &CancellationToken::new(), &mut QueryStats::default())?;
         let count = documents.len();
 
         // Update each document
-        for mut doc in documents {
-            // Apply updates
-            for update in &updates {
-                doc.set(update.field.clone(), update.value.clone());
-            }
+        for doc in documents {
+            let (previous, current) = self.write_batch(|batch| -> NVResult<_> {
+                // Apply updates to the latest version
+                let previous = batch.read(&doc.id)?;
+                let mut current = previous.clone();
+                for update in &updates {
+                    current.apply(update.clone());
+                }
 
-            // Save updated document
-            self.storage.append(&doc)?;
+                // Save the new version and retire the old one
+                self.put_document(batch, Some(&previous), &current)?;
+                Ok((previous, current))
+            })??;
+            self.hooks.read().after_update(&previous, &current);
         }
 
         Ok(count)
@@ -89,65 +1199,3163 @@ impl NeuralVault {
     /// Update a single document by ID
     pub fn update_by_id(&self, id: &str, updates: Vec<UpdateOperation>) -> NVResult<()> {
         self.ensure_initialized()?;
+        self.make_room()?;
+        validate_updates(&updates)?;
 
-        // Read document
-        let mut document = self.storage.read(id)?;
+        let (previous, document) = self.write_batch(|batch| -> NVResult<_> {
+            // Read document
+            let previous = batch.read(id)?;
+            let mut document = previous.clone();
 
-        // Apply updates
-        for update in updates {
-            document.set(update.field, update.value);
-        }
+            // Apply updates
+            for update in updates {
+                document.apply(update);
+            }
 
-        // Save updated document
-        self.storage.append(&document)?;
+            // Save the new version and retire the old one
+            self.put_document(batch, Some(&previous), &document)?;
+            Ok((previous, document))
+        })??;
+        self.hooks.read().after_update(&previous, &document);
 
         Ok(())
     }
 
-    /// Delete documents matching a query (soft delete)
-    pub fn kill(&self, query: NVQuery) -> NVResult<usize> {
+    /// Update a document only if it currently satisfies `condition`
+    ///
+    /// The check and the write happen under the storage lock, so no other
+    /// write can change the document in between. Returns whether the update
+    /// was applied.
+    pub fn update_if(
+        &self,
+        id: &str,
+        condition: QueryCondition,
+        updates: Vec<UpdateOperation>,
+    ) -> NVResult<bool> {
         self.ensure_initialized()?;
+        self.make_room()?;
+        validate_updates(&updates)?;
 
-        // Find matching documents
-        let documents = self.find(query)?;
-        let count = documents.len();
+        let versions = self.write_batch(|batch| -> NVResult<_> {
+            let mut document = batch.read(id)?;
+            if !self.query_processor.matches_condition(&document, &condition) {
+                return Ok(None);
+            }
 
-        // Mark each as deleted
-        for doc in documents {
-            self.storage.mark_deleted(&doc.id)?;
+            let previous = document.clone();
+            for update in updates {
+                document.apply(update);
+            }
+            self.put_document(batch, Some(&previous), &document)?;
+            Ok(Some((previous, document)))
+        })??;
+
+        match versions {
+            Some((previous, current)) => {
+                self.hooks.read().after_update(&previous, &current);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Move documents matching a query into a compressed, read-only archive
+    /// segment, returning how many were moved
+    ///
+    /// Archived documents leave the data file and its indexes, keeping it
+    /// small, but are still returned by queries with `include_archived` set.
+    /// They're removed without running delete hooks or reference actions.
+    /// Collections with encrypted fields can't be archived, since segments
+    /// aren't encrypted.
+    pub fn archive(&self, mut query: NVQuery) -> NVResult<usize> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(&query.collection)?;
+        if self.encrypted_fields()?.iter().any(|(collection, _)| *collection == query.collection) {
+            return Err(NeuralVaultError::ValidationError(format!(
+                "Collection {} has encrypted fields and can't be archived",
+                query.collection
+            )));
         }
+        query.include_archived = false;
+        self.hooks.read().on_query(&mut query)?;
+
+        // Select and remove under the batch's locks, so nothing archived can
+        // change in between
+        self.write_batch(|batch| -> NVResult<usize> {
+            let candidates = batch.scan_collection(&query.collection)?;
+            let documents = self.query_processor.filter(candidates, &query)?;
+            if documents.is_empty() {
+                return Ok(0);
+            }
+
+            archive::write(&self.archive_dir(), &self.ulids.next(), &query.collection, &documents)?;
+            for document in &documents {
+                self.remove_document(batch, &document.id)?;
+                // The new segment's copy is the one queries see
+                SystemCatalog::remove_in(batch, &superseded_key(&document.collection, &document.id))?;
+            }
+            if let Some(ids) = self.archived_ids.write().get_mut(&query.collection) {
+                ids.extend(documents.iter().map(|document| document.id.clone()));
+            }
+            Ok(documents.len())
+        })?
+    }
+
+    /// Delete documents matching a query (soft delete)
+    pub fn kill(&self, mut query: NVQuery) -> NVResult<usize> {
+        self.ensure_initialized()?;
 
+        // Find matching documents; hooks see them whole
+        query.projection = None;
+        query.computed.clear();
+        let documents = self.find_scoped(query, &CancellationToken::new(), &mut QueryStats::default())?;
+        let count = documents.len();
+
+        self.delete_documents(&documents)?;
         Ok(count)
     }
 
     /// Delete a single document by ID
     pub fn kill_by_id(&self, id: &str) -> NVResult<()> {
         self.ensure_initialized()?;
-        self.storage.mark_deleted(id)?;
+
+        let document = self.storage.read(id)?;
+        self.delete_documents(std::slice::from_ref(&document))
+    }
+
+    /// Declare that `reference.field` in `reference.collection` holds IDs of
+    /// documents in `reference.target`
+    ///
+    /// Deleting a target document then cascades, nulls the field or is
+    /// refused, depending on `reference.on_delete`.
+    pub fn add_reference(&self, reference: Reference) -> NVResult<()> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(&reference.collection)?;
+        validation::validate_collection_name(&reference.target)?;
+        validation::validate_field_name(&reference.field)?;
+
+        let key = format!(
+            "{}{}.{}",
+            keys::REFERENCE_PREFIX,
+            reference.collection,
+            reference.field
+        );
+        self.system.set(&key, serde_json::to_value(&reference)?.into())?;
+
+        let mut references = self.references.write();
+        references.retain(|r| !(r.collection == reference.collection && r.field == reference.field));
+        references.push(reference);
         Ok(())
     }
 
-    /// Count documents in a collection
-    pub fn count(&self, collection: &str) -> NVResult<usize> {
+    /// Save a query under a name that can then be queried like a collection
+    ///
+    /// Queries against the view are applied to the view's results, after its
+    /// own ordering, skip and limit. Replaces any view with the same name.
+    pub fn create_view(&self, name: &str, query: NVQuery) -> NVResult<()> {
         self.ensure_initialized()?;
-        let documents = self.storage.scan_collection(collection)?;
-        Ok(documents.len())
+        validation::validate_collection_name(name)?;
+        if self.storage.has_collection(name)? {
+            return Err(NeuralVaultError::ValidationError(format!(
+                "'{}' is already a collection",
+                name
+            )));
+        }
+
+        // Views may build on other views, but not on themselves
+        {
+            let views = self.views.read();
+            let mut source = query.collection.as_str();
+            let mut depth = 0;
+            loop {
+                if source == name {
+                    return Err(NeuralVaultError::ValidationError(format!(
+                        "View '{}' would depend on itself",
+                        name
+                    )));
+                }
+                match views.get(source) {
+                    Some(parent) if depth < views.len() => source = &parent.collection,
+                    _ => break,
+                }
+                depth += 1;
+            }
+        }
+
+        let key = format!("{}{}", keys::VIEW_PREFIX, name);
+        self.system.set(&key, serde_json::to_value(&query)?.into())?;
+        self.views.write().insert(name.to_string(), query);
+        Ok(())
     }
 
-    /// Get all collection names
-    pub fn collections(&self) -> NVResult<Vec<String>> {
+    /// Remove a saved view, returning whether it existed
+    pub fn drop_view(&self, name: &str) -> NVResult<bool> {
+        self.ensure_initialized()?;
+        let removed = self.system.remove(&format!("{}{}", keys::VIEW_PREFIX, name))?;
+        self.views.write().remove(name);
+        Ok(removed)
+    }
+
+    /// Names of all saved views, sorted
+    pub fn views(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.views.read().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Create a materialized aggregate view
+    ///
+    /// The view is computed once from the collection and then kept up to
+    /// date on every write; reading it never rescans the collection.
+    pub fn create_aggregate_view(&self, name: &str, definition: AggregateDefinition) -> NVResult<()> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(name)?;
+        validation::validate_collection_name(&definition.collection)?;
+        definition.validate()?;
+        for field in aggregate_fields(&definition) {
+            self.ensure_not_encrypted(&definition.collection, field)?;
+        }
+
+        let key = format!("{}{}", keys::AGGREGATE_PREFIX, name);
+        self.system.set(&key, serde_json::to_value(&definition)?.into())?;
+
+        // Build under the storage lock so no write is missed or counted twice
+        let mut aggregate = MaterializedAggregate::new(definition);
+        self.write_batch(|batch| -> NVResult<()> {
+            for document in batch.scan_collection(&aggregate.definition().collection)? {
+                aggregate.add(&document);
+            }
+            self.aggregates.write().insert(name.to_string(), aggregate);
+            Ok(())
+        })??;
+        Ok(())
+    }
+
+    /// Current rows of a materialized aggregate view
+    pub fn aggregate_view(&self, name: &str) -> NVResult<Vec<HashMap<String, NVValue>>> {
+        self.ensure_initialized()?;
+        self.aggregates
+            .read()
+            .get(name)
+            .map(MaterializedAggregate::rows)
+            .ok_or_else(|| NeuralVaultError::CollectionNotFound(name.to_string()))
+    }
+
+    /// Remove a materialized aggregate view, returning whether it existed
+    pub fn drop_aggregate_view(&self, name: &str) -> NVResult<bool> {
+        self.ensure_initialized()?;
+        let removed = self.system.remove(&format!("{}{}", keys::AGGREGATE_PREFIX, name))?;
+        self.aggregates.write().remove(name);
+        Ok(removed)
+    }
+
+    /// Create a secondary index
+    ///
+    /// With a non-empty `filter` the index is partial: only documents that
+    /// satisfy every filter condition are indexed, which keeps it small and
+    /// spares writes to other documents. Replaces any index with the same
+    /// name.
+    pub fn create_index(&self, definition: IndexDefinition) -> NVResult<()> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(&definition.name)?;
+        validation::validate_collection_name(&definition.collection)?;
+        if definition.fields.is_empty() {
+            return Err(NeuralVaultError::ValidationError(format!(
+                "Index '{}' must have at least one field",
+                definition.name
+            )));
+        }
+        for field in &definition.fields {
+            validation::validate_field_name(field)?;
+        }
+        for field in definition.fields.iter().chain(definition.filter.iter().map(|c| &c.field)) {
+            self.ensure_not_encrypted(&definition.collection, field)?;
+            // Versions change as writes are stored, after indexes see them
+            if field == VERSION_FIELD {
+                return Err(NeuralVaultError::ValidationError(format!(
+                    "Index '{}' can't use {}",
+                    definition.name, VERSION_FIELD
+                )));
+            }
+        }
+
+        let key = format!("{}{}", keys::INDEX_PREFIX, definition.name);
+        self.system.set(&key, serde_json::to_value(&definition)?.into())?;
+
+        // Build under the storage lock so no write is missed or indexed twice
+        let mut index = SecondaryIndex::new(definition);
+        self.write_batch(|batch| -> NVResult<()> {
+            for document in batch.scan_collection(&index.definition().collection)? {
+                index.add(&document, &self.query_processor);
+            }
+            self.indexes.write().insert(index.definition().name.clone(), index);
+            Ok(())
+        })??;
+        Ok(())
+    }
+
+    /// Remove a secondary index, returning whether it existed
+    pub fn drop_index(&self, name: &str) -> NVResult<bool> {
+        self.ensure_initialized()?;
+        let removed = self.system.remove(&format!("{}{}", keys::INDEX_PREFIX, name))?;
+        self.indexes.write().remove(name);
+        Ok(removed)
+    }
+
+    /// Create a full-text index ranking documents by BM25
+    ///
+    /// Replaces any full-text index with the same name.
+    pub fn create_text_index(&self, definition: TextIndexDefinition) -> NVResult<()> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(&definition.name)?;
+        validation::validate_collection_name(&definition.collection)?;
+        if definition.fields.is_empty() {
+            return Err(NeuralVaultError::ValidationError(format!(
+                "Text index '{}' must have at least one field",
+                definition.name
+            )));
+        }
+        for field in &definition.fields {
+            validation::validate_field_name(field)?;
+            self.ensure_not_encrypted(&definition.collection, field)?;
+        }
+        if definition.tokenizer.cjk_ngrams == Some(0) {
+            return Err(NeuralVaultError::ValidationError(format!(
+                "Text index '{}' needs n-grams of at least one character",
+                definition.name
+            )));
+        }
+
+        // Stored as a JSON string: catalog numbers are floats, `cjk_ngrams` isn't
+        let key = format!("{}{}", keys::TEXT_INDEX_PREFIX, definition.name);
+        self.system.set(&key, NVValue::String(serde_json::to_string(&definition)?))?;
+
+        // Build under the storage lock so no write is missed or indexed twice
+        let mut index = TextIndex::new(definition);
+        self.write_batch(|batch| -> NVResult<()> {
+            for document in batch.scan_collection(&index.definition().collection)? {
+                index.add(&document);
+            }
+            self.texts.write().insert(index.definition().name.clone(), index);
+            Ok(())
+        })??;
+        Ok(())
+    }
+
+    /// Remove a full-text index, returning whether it existed
+    pub fn drop_text_index(&self, name: &str) -> NVResult<bool> {
+        self.ensure_initialized()?;
+        let removed = self.system.remove(&format!("{}{}", keys::TEXT_INDEX_PREFIX, name))?;
+        self.texts.write().remove(name);
+        Ok(removed)
+    }
+
+    /// Definitions of all full-text indexes with their number of documents,
+    /// sorted by name
+    pub fn text_indexes(&self) -> Vec<(TextIndexDefinition, usize)> {
+        let mut indexes: Vec<(TextIndexDefinition, usize)> = self
+            .texts
+            .read()
+            .values()
+            .map(|index| (index.definition().clone(), index.len()))
+            .collect();
+        indexes.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+        indexes
+    }
+
+    /// Index the values of `collection.field` for `suggest`, replacing any
+    /// existing autocomplete index on it
+    pub fn create_completion_index(&self, collection: &str, field: &str) -> NVResult<()> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(collection)?;
+        validation::validate_field_name(field)?;
+        self.ensure_not_encrypted(collection, field)?;
+
+        let definition = CompletionIndexDefinition {
+            collection: collection.to_string(),
+            field: field.to_string(),
+        };
+        let key = format!("{}.{}", collection, field);
+        self.system.set(
+            &format!("{}{}", keys::COMPLETION_INDEX_PREFIX, key),
+            serde_json::to_value(&definition)?.into(),
+        )?;
+
+        // Build under the storage lock so no write is missed or indexed twice
+        let mut index = CompletionIndex::new(definition);
+        self.write_batch(|batch| -> NVResult<()> {
+            for document in batch.scan_collection(collection)? {
+                index.add(&document);
+            }
+            self.completions.write().insert(key, index);
+            Ok(())
+        })??;
+        Ok(())
+    }
+
+    /// Remove the autocomplete index on `collection.field`, returning
+    /// whether it existed
+    pub fn drop_completion_index(&self, collection: &str, field: &str) -> NVResult<bool> {
+        self.ensure_initialized()?;
+        let key = format!("{}.{}", collection, field);
+        let removed = self.system.remove(&format!("{}{}", keys::COMPLETION_INDEX_PREFIX, key))?;
+        self.completions.write().remove(&key);
+        Ok(removed)
+    }
+
+    /// Definitions of all autocomplete indexes with their number of
+    /// distinct values, sorted by collection and field
+    pub fn completion_indexes(&self) -> Vec<(CompletionIndexDefinition, usize)> {
+        let mut indexes: Vec<(CompletionIndexDefinition, usize)> = self
+            .completions
+            .read()
+            .values()
+            .map(|index| (index.definition().clone(), index.len()))
+            .collect();
+        indexes.sort_by(|a, b| (&a.0.collection, &a.0.field).cmp(&(&b.0.collection, &b.0.field)));
+        indexes
+    }
+
+    /// Up to `k` values of `collection.field` starting with `prefix`,
+    /// ignoring case, with how many documents have each, most common first
+    ///
+    /// Needs an autocomplete index on the field.
+    pub fn suggest(&self, collection: &str, field: &str, prefix: &str, k: usize) -> NVResult<Vec<(String, u32)>> {
+        self.ensure_initialized()?;
+        self.completions
+            .read()
+            .get(&format!("{}.{}", collection, field))
+            .map(|index| index.suggest(prefix, k))
+            .ok_or_else(|| {
+                NeuralVaultError::IndexError(format!("No autocomplete index on {}.{}", collection, field))
+            })
+    }
+
+    /// The `k` documents most relevant to `text` by BM25, best first, with
+    /// their scores
+    pub fn search_text(&self, index: &str, text: &str, k: usize) -> NVResult<Vec<(NVDocument, f32)>> {
+        self.ensure_initialized()?;
+        let matches = self.text_index(index, |index| index.search(text, k))?;
+        self.with_documents(matches)
+    }
+
+    /// Like `search_text`, also matching words within `max_distance` edits
+    /// of the query's, so typos still find results
+    pub fn search_text_fuzzy(
+        &self,
+        index: &str,
+        text: &str,
+        k: usize,
+        max_distance: usize,
+    ) -> NVResult<Vec<(NVDocument, f32)>> {
+        self.ensure_initialized()?;
+        let matches = self.text_index(index, |index| index.search_fuzzy(text, k, max_distance))?;
+        self.with_documents(matches)
+    }
+
+    /// Like `search_text`, with where the query matched in each result and
+    /// a marked-up snippet of the best stretch of each matching field
+    pub fn search_text_highlighted(
+        &self,
+        index: &str,
+        text: &str,
+        k: usize,
+        options: &HighlightOptions,
+    ) -> NVResult<Vec<(NVDocument, f32, Vec<Highlight>)>> {
+        self.ensure_initialized()?;
+        let (definition, matches) = self.text_index(index, |index| {
+            (index.definition().clone(), index.search_fuzzy(text, k, options.max_distance))
+        })?;
+        Ok(self
+            .with_documents(matches)?
+            .into_iter()
+            .map(|(document, score)| {
+                let highlights = highlight::highlight(&definition, &document, text, options);
+                (document, score, highlights)
+            })
+            .collect())
+    }
+
+    /// Documents ranked by both text relevance and vector similarity,
+    /// best first, with their blended scores
+    ///
+    /// Each index contributes its top candidates, which are then fused as
+    /// `query.fusion` says. Both indexes must cover the same collection.
+    pub fn hybrid_search(&self, query: &HybridQuery) -> NVResult<Vec<(NVDocument, f32)>> {
+        self.ensure_initialized()?;
+        let candidates = query.candidates();
+        let (text_collection, text) = self.text_index(&query.text_index, |index| {
+            (index.definition().collection.clone(), index.search(&query.text, candidates))
+        })?;
+
+        let vectors = self.vectors.read();
+        let vector_index = vectors.get(&query.vector_index).ok_or_else(|| {
+            NeuralVaultError::IndexError(format!("Vector index '{}' not found", query.vector_index))
+        })?;
+        if vector_index.definition().collection != text_collection {
+            return Err(NeuralVaultError::InvalidQuery(format!(
+                "Text index '{}' and vector index '{}' cover different collections",
+                query.text_index, query.vector_index
+            )));
+        }
+        let vector = vector_index.search(&query.vector, candidates)?;
+        drop(vectors);
+
+        self.with_documents(search::fuse(&text, &vector, query.fusion, query.limit))
+    }
+
+    fn vector_index<R>(&self, name: &str, read: impl FnOnce(&VectorIndex) -> R) -> NVResult<R> {
+        self.vectors
+            .read()
+            .get(name)
+            .map(read)
+            .ok_or_else(|| NeuralVaultError::IndexError(format!("Vector index '{}' not found", name)))
+    }
+
+    fn text_index<R>(&self, name: &str, read: impl FnOnce(&TextIndex) -> R) -> NVResult<R> {
+        self.texts
+            .read()
+            .get(name)
+            .map(read)
+            .ok_or_else(|| NeuralVaultError::IndexError(format!("Text index '{}' not found", name)))
+    }
+
+    /// Read the documents behind ranked `(document ID, score)` pairs,
+    /// without their sensitive fields
+    fn with_documents(&self, ranked: Vec<(String, f32)>) -> NVResult<Vec<(NVDocument, f32)>> {
+        ranked
+            .into_iter()
+            .map(|(id, score)| {
+                let mut document = self.storage.read(&id)?;
+                self.redact(std::slice::from_mut(&mut document));
+                Ok((document, score))
+            })
+            .collect()
+    }
+
+    /// Create a vector index over an array-of-numbers field
+    ///
+    /// Replaces any vector index with the same name. The index is written to
+    /// disk straight away and on every checkpoint, so reopening the database
+    /// only replays the writes made since.
+    pub fn create_vector_index(&self, definition: VectorIndexDefinition) -> NVResult<()> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(&definition.name)?;
+        validation::validate_collection_name(&definition.collection)?;
+        validation::validate_field_name(&definition.field)?;
+        if definition.dimensions == 0 {
+            return Err(NeuralVaultError::ValidationError(format!(
+                "Vector index '{}' must have at least one dimension",
+                definition.name
+            )));
+        }
+        if let Quantization::Product { subvectors } = definition.quantization {
+            if subvectors == 0 || !definition.dimensions.is_multiple_of(subvectors) {
+                return Err(NeuralVaultError::ValidationError(format!(
+                    "Vector index '{}' can't split {} dimensions into {} subvectors",
+                    definition.name, definition.dimensions, subvectors
+                )));
+            }
+        }
+        self.ensure_not_encrypted(&definition.collection, &definition.field)?;
+
+        // Stored as a JSON string: catalog numbers are floats, `dimensions` isn't
+        let key = format!("{}{}", keys::VECTOR_INDEX_PREFIX, definition.name);
+        self.system.set(&key, NVValue::String(serde_json::to_string(&definition)?))?;
+
+        // Build under the storage lock so no write is missed or indexed twice
+        let oplog = self.oplog.read();
+        let mut index = VectorIndex::new(definition);
+        self.write_batch(|batch| -> NVResult<()> {
+            for document in batch.scan_collection(&index.definition().collection)? {
+                index.add(&document);
+            }
+            let path = vector_index_path(&self.config.path, &index.definition().name);
+            vector::save(&path, &oplog.id, batch.data_len()?, &index)?;
+            self.vectors.write().insert(index.definition().name.clone(), index);
+            Ok(())
+        })??;
+        Ok(())
+    }
+
+    /// Remove a vector index, returning whether it existed
+    pub fn drop_vector_index(&self, name: &str) -> NVResult<bool> {
+        self.ensure_initialized()?;
+        let removed = self.system.remove(&format!("{}{}", keys::VECTOR_INDEX_PREFIX, name))?;
+        self.vectors.write().remove(name);
+        match std::fs::remove_file(vector_index_path(&self.config.path, name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        Ok(removed)
+    }
+
+    /// Definitions of all vector indexes with their number of vectors,
+    /// sorted by name
+    pub fn vector_indexes(&self) -> Vec<(VectorIndexDefinition, usize)> {
+        let mut indexes: Vec<(VectorIndexDefinition, usize)> = self
+            .vectors
+            .read()
+            .values()
+            .map(|index| (index.definition().clone(), index.len()))
+            .collect();
+        indexes.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+        indexes
+    }
+
+    /// The `k` documents whose vectors are nearest to `vector`, closest
+    /// first, with their distances under the index's metric
+    ///
+    /// Results are approximate: a near neighbour may occasionally be missed.
+    pub fn find_similar(&self, index: &str, vector: &[f32], k: usize) -> NVResult<Vec<(NVDocument, f32)>> {
+        self.ensure_initialized()?;
+        let matches = self.vector_index(index, |index| index.search(vector, k))??;
+        self.with_documents(matches)
+    }
+
+    /// Like `find_similar`, only returning documents that match `filter`
+    ///
+    /// Only the filter's conditions are used. When an index narrows them
+    /// down to few documents, those are found first and ranked exactly;
+    /// otherwise the vector index is searched ever more broadly, dropping
+    /// neighbours that don't match, until `k` are found or it is exhausted.
+    pub fn find_similar_where(
+        &self,
+        index: &str,
+        vector: &[f32],
+        k: usize,
+        mut filter: NVQuery,
+    ) -> NVResult<Vec<(NVDocument, f32)>> {
+        self.ensure_initialized()?;
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+        let (collection, vectors) =
+            self.vector_index(index, |index| (index.definition().collection.clone(), index.len()))?;
+        if filter.collection != collection {
+            return Err(NeuralVaultError::InvalidQuery(format!(
+                "Vector index '{}' covers {}, not {}",
+                index, collection, filter.collection
+            )));
+        }
+
+        if !filter.subqueries.is_empty() {
+            self.resolve_subqueries(&mut filter, &CancellationToken::new(), &mut QueryStats::default())?;
+        }
+        filter.order_by = None;
+        filter.limit = None;
+        filter.skip = None;
+        filter.projection = None;
+        filter.populate.clear();
+        filter.computed.clear();
+
+        let plan = if self.ruled_out_by_filters(&filter) {
+            QueryPlan::RuledOut
+        } else {
+            planner::plan(&filter, &self.indexes.read(), &self.query_processor).plan
+        };
+        match planner::plan_vector_filter(&plan, vectors) {
+            VectorFilterPlan::RuledOut => Ok(Vec::new()),
+            VectorFilterPlan::PreFilter => {
+                let mut documents: HashMap<String, NVDocument> = self
+                    .find(filter)?
+                    .into_iter()
+                    .map(|document| (document.id.clone(), document))
+                    .collect();
+                let ranked = self.vector_index(index, |index| {
+                    index.rank(vector, documents.keys().map(String::as_str), k)
+                })??;
+                Ok(ranked
+                    .into_iter()
+                    .filter_map(|(id, distance)| Some((documents.remove(&id)?, distance)))
+                    .collect())
+            }
+            VectorFilterPlan::PostFilter => {
+                let mut breadth = k.saturating_mul(4);
+                loop {
+                    let neighbours = self.vector_index(index, |index| index.search(vector, breadth))??;
+                    let exhausted = neighbours.len() < breadth;
+                    let mut found = Vec::new();
+                    for (id, distance) in neighbours {
+                        let mut document = self.storage.read(&id)?;
+                        if self.query_processor.matches(&document, &filter) {
+                            if !filter.include_sensitive {
+                                self.redact(std::slice::from_mut(&mut document));
+                            }
+                            found.push((document, distance));
+                            if found.len() == k {
+                                return Ok(found);
+                            }
+                        }
+                    }
+                    if exhausted {
+                        return Ok(found);
+                    }
+                    breadth = breadth.saturating_mul(4);
+                }
+            }
+        }
+    }
+
+    /// Definitions of all indexes with their number of entries, sorted by name
+    pub fn indexes(&self) -> Vec<(IndexDefinition, usize)> {
+        let mut indexes: Vec<(IndexDefinition, usize)> = self
+            .indexes
+            .read()
+            .values()
+            .map(|index| (index.definition().clone(), index.len()))
+            .collect();
+        indexes.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+        indexes
+    }
+
+    /// All declared references
+    pub fn references(&self) -> Vec<Reference> {
+        self.references.read().clone()
+    }
+
+    /// Add middleware that sees the queries, reads and creates of every
+    /// collection, e.g. to add a tenant condition to every query
+    ///
+    /// Like hooks, middleware must not call back into the database.
+    pub fn use_middleware(&self, middleware: Box<dyn Middleware>) {
+        self.hooks.write().add_middleware(middleware);
+    }
+
+    /// Register a data migration to be run by `migrate`
+    ///
+    /// Versions order migrations and must be unique; the same migrations
+    /// are meant to be registered every time the database is opened.
+    /// Registering a version older than the last one applied fails unless
+    /// that version was applied too, since it would never run.
+    ///
+    /// Nothing runs migrations when the database opens: register them all,
+    /// then call `migrate` before using the database.
+    pub fn register_migration(
+        &self,
+        version: u32,
+        migration: impl Fn(&NeuralVault, &mut Transaction) -> NVResult<()> + Send + Sync + 'static,
+    ) -> NVResult<()> {
+        let applied = self.schema_version()?;
+        if version < applied && !self.system.contains(&migration_key(version)) {
+            return Err(NeuralVaultError::ValidationError(format!(
+                "Migration {} is older than the applied migration {}",
+                version, applied
+            )));
+        }
+        self.migrations.write().register(version, Arc::new(migration))
+    }
+
+    /// Run the registered migrations this database hasn't had yet, oldest
+    /// first, returning their versions
+    ///
+    /// Each migration's writes are committed in one batch together with
+    /// its version, so it either runs exactly once on this database or
+    /// leaves nothing behind. A failing migration stops the run with
+    /// `MigrationFailed` and later ones don't run; it's retried by the next
+    /// `migrate`.
+    pub fn migrate(&self) -> NVResult<Vec<u32>> {
+        self.ensure_initialized()?;
+        if self.config.read_only {
+            return Err(NeuralVaultError::ReadOnly);
+        }
+        let _running = self.migrating.lock();
+        let pending = self.migrations.read().pending(self.schema_version()?);
+
+        let mut applied = Vec::with_capacity(pending.len());
+        for (version, migration) in pending {
+            let failed = |e: NeuralVaultError| NeuralVaultError::MigrationFailed(version, e.to_string());
+            let mut tx = self.transaction();
+            tx.set_catalog(&migration_key(version), NVValue::Bool(true));
+            tx.set_catalog(keys::SCHEMA_VERSION, NVValue::Number(version as f64));
+            migration(self, &mut tx).map_err(failed)?;
+            tx.commit().map_err(failed)?;
+            applied.push(version);
+        }
+        Ok(applied)
+    }
+
+    /// Version of the last migration applied, or 0 if none has been
+    pub fn schema_version(&self) -> NVResult<u32> {
+        self.ensure_initialized()?;
+        Ok(match self.system.get(keys::SCHEMA_VERSION)? {
+            Some(NVValue::Number(version)) => version as u32,
+            _ => 0,
+        })
+    }
+
+    /// Register a hook run before documents are created in a collection
+    ///
+    /// The hook may modify the document or veto the create by returning an
+    /// error. Hooks must not call back into the database.
+    pub fn on_before_create(&self, collection: &str, hook: BeforeCreateHook) {
+        self.hooks.write().add_before_create(collection, hook);
+    }
+
+    /// Register a hook run after documents in a collection are updated
+    pub fn on_after_update(&self, collection: &str, hook: AfterUpdateHook) {
+        self.hooks.write().add_after_update(collection, hook);
+    }
+
+    /// Register a hook run before documents in a collection are deleted
+    ///
+    /// Returning an error vetoes the delete.
+    pub fn on_before_delete(&self, collection: &str, hook: BeforeDeleteHook) {
+        self.hooks.write().add_before_delete(collection, hook);
+    }
+
+    /// Compute `target` from the text in `source` whenever a document in
+    /// the collection is written
+    ///
+    /// The vector is stored as an array of numbers, ready for a vector
+    /// index, and only recomputed when the text changes. Existing documents
+    /// are embedded the next time they're written. Like hooks, embedders run
+    /// under the storage lock and must not call back into the database; an
+    /// embedder error fails the write.
+    pub fn register_embedder(
+        &self,
+        collection: &str,
+        source: &str,
+        target: &str,
+        embedder: Arc<dyn Embedder>,
+    ) -> NVResult<()> {
+        validation::validate_collection_name(collection)?;
+        validation::validate_field_name(source)?;
+        validation::validate_field_name(target)?;
+        if source == target {
+            return Err(NeuralVaultError::ValidationError(format!(
+                "Embedder for {} can't overwrite its source field {}",
+                collection, source
+            )));
+        }
+        self.embedders.write().add(
+            collection,
+            FieldEmbedder {
+                source: source.to_string(),
+                target: target.to_string(),
+                embedder,
+            },
+        );
+        Ok(())
+    }
+
+    /// Stop computing `target` in a collection, returning whether an
+    /// embedder was registered for it
+    pub fn unregister_embedder(&self, collection: &str, target: &str) -> bool {
+        self.embedders.write().remove(collection, target)
+    }
+
+    /// Split the text in `field` of a document into chunks stored as
+    /// documents in `chunk_collection`, returning the chunk IDs in order
+    ///
+    /// Each chunk records its source document, field, position and
+    /// character range, and is indexed like any other document, so text
+    /// indexes, vector indexes and embedders on `chunk_collection` cover it.
+    /// Chunks from an earlier ingest of the same field are replaced, and a
+    /// cascading reference is declared so deleting the source deletes its
+    /// chunks.
+    pub fn ingest_chunks(
+        &self,
+        source_id: &str,
+        field: &str,
+        chunk_collection: &str,
+        options: &ChunkOptions,
+    ) -> NVResult<Vec<String>> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(chunk_collection)?;
+        let mut transaction = self.transaction();
+        let source = transaction.read(source_id)?;
+        let text = match source.get(field) {
+            Some(NVValue::String(text)) => text.clone(),
+            _ => {
+                return Err(NeuralVaultError::ValidationError(format!(
+                    "Field {} of document {} is not text",
+                    field, source_id
+                )))
+            }
+        };
+        let chunks = chunking::split(&text, options)?;
+
+        let existing = self
+            .references
+            .read()
+            .iter()
+            .find(|r| r.collection == chunk_collection && r.field == chunking::SOURCE_ID)
+            .cloned();
+        match existing {
+            Some(reference) if reference.target != source.collection => {
+                return Err(NeuralVaultError::ValidationError(format!(
+                    "{} holds chunks of {}, not {}",
+                    chunk_collection, reference.target, source.collection
+                )))
+            }
+            Some(_) => {}
+            None => self.add_reference(Reference {
+                collection: chunk_collection.to_string(),
+                field: chunking::SOURCE_ID.to_string(),
+                target: source.collection.clone(),
+                on_delete: OnDelete::Cascade,
+            })?,
+        }
+
+        let mut previous = NVQuery::new(chunk_collection.to_string());
+        previous.add_condition(
+            chunking::SOURCE_ID.to_string(),
+            QueryOperator::Equals,
+            NVValue::String(source_id.to_string()),
+            None,
+        );
+        previous.add_condition(
+            chunking::SOURCE_FIELD.to_string(),
+            QueryOperator::Equals,
+            NVValue::String(field.to_string()),
+            Some(LogicalOperator::And),
+        );
+        let previous = self.find(previous)?;
+        for chunk in &previous {
+            transaction.delete(&chunk.id);
+        }
+
+        for (position, chunk) in chunks.into_iter().enumerate() {
+            let mut data = HashMap::new();
+            data.insert(chunking::SOURCE_ID.to_string(), NVValue::String(source_id.to_string()));
+            data.insert(chunking::SOURCE_FIELD.to_string(), NVValue::String(field.to_string()));
+            data.insert(chunking::CHUNK_INDEX.to_string(), NVValue::Number(position as f64));
+            data.insert(chunking::START.to_string(), NVValue::Number(chunk.start as f64));
+            data.insert(chunking::END.to_string(), NVValue::Number(chunk.end as f64));
+            data.insert(chunking::TEXT.to_string(), NVValue::String(chunk.text));
+            transaction.insert(chunk_collection, data);
+        }
+
+        let mut ids = transaction.commit()?;
+        ids.drain(..previous.len());
+        Ok(ids)
+    }
+
+    /// Like `find_similar`, embedding `text` with the embedder that fills
+    /// the index's field
+    pub fn find_similar_text(&self, index: &str, text: &str, k: usize) -> NVResult<Vec<(NVDocument, f32)>> {
+        self.ensure_initialized()?;
+        let (collection, field) = self.vector_index(index, |index| {
+            (index.definition().collection.clone(), index.definition().field.clone())
+        })?;
+
+        let embedder = self
+            .embedders
+            .read()
+            .get(&collection, &field)
+            .map(|registered| registered.embedder.clone())
+            .ok_or_else(|| {
+                NeuralVaultError::EmbeddingError(format!("No embedder registered for {}.{}", collection, field))
+            })?;
+        let vector = embedder.embed(text)?;
+        self.find_similar(index, &vector, k)
+    }
+
+    /// Count documents in a collection
+    pub fn count(&self, collection: &str) -> NVResult<usize> {
+        self.ensure_initialized()?;
+        let documents = self.storage.scan_collection(collection)?;
+        Ok(documents.len())
+    }
+
+    /// Up to `n` documents of a collection, chosen uniformly at random,
+    /// without their sensitive fields
+    pub fn sample(&self, collection: &str, n: usize) -> NVResult<Vec<NVDocument>> {
+        self.ensure_initialized()?;
+        let mut documents = self.storage.sample_collection(collection, n)?;
+        self.redact(&mut documents);
+        self.hooks.read().on_read(&mut documents)?;
+        Ok(documents)
+    }
+
+    /// Export a collection for analytics tools, one row per document
+    ///
+    /// The schema is inferred from the documents; see
+    /// `export::infer_schema`. Documents are read `export::BATCH_ROWS` at
+    /// a time, once to infer the schema and again to write them, from a
+    /// snapshot so both passes see the same versions. Compaction fails
+    /// while an export runs.
+    #[cfg(feature = "analytics")]
+    pub fn export_collection(
+        &self,
+        collection: &str,
+        path: &Path,
+        format: export::ExportFormat,
+    ) -> NVResult<export::ExportedCollection> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(collection)?;
+
+        let snapshot = self.storage.snapshot();
+        let ids = snapshot.collection_ids(collection)?;
+        let mut query = NVQuery::new(collection.to_string());
+        self.hooks.read().on_query(&mut query)?;
+        // Each page is read the way `find` returns documents
+        let page = |ids: &[String]| -> NVResult<Vec<NVDocument>> {
+            let documents = ids.iter().map(|id| snapshot.read(id)).collect::<NVResult<Vec<_>>>()?;
+            let mut documents = self.query_processor.filter(documents, &query)?;
+            self.redact(&mut documents);
+            self.hooks.read().on_read(&mut documents)?;
+            Ok(documents)
+        };
+
+        let mut schema = export::SchemaInference::new();
+        for ids in ids.chunks(export::BATCH_ROWS) {
+            schema.add(&page(ids)?);
+        }
+        let columns = schema.columns();
+        let rows = export::write_batches(&columns, path, format, ids.chunks(export::BATCH_ROWS).map(page))?;
+        Ok(export::ExportedCollection {
+            collection: collection.to_string(),
+            path: path.to_string_lossy().into_owned(),
+            rows,
+            columns,
+        })
+    }
+
+    /// Export every collection into `directory`, one
+    /// `<collection>.<extension>` file each
+    #[cfg(feature = "analytics")]
+    pub fn export_collections(
+        &self,
+        directory: &Path,
+        format: export::ExportFormat,
+    ) -> NVResult<Vec<export::ExportedCollection>> {
+        std::fs::create_dir_all(directory)?;
+        self.collections()?
+            .iter()
+            .map(|collection| {
+                let path = directory.join(format!("{}.{}", collection, format.extension()));
+                self.export_collection(collection, &path, format)
+            })
+            .collect()
+    }
+
+    /// Get all collection names
+    pub fn collections(&self) -> NVResult<Vec<String>> {
         self.ensure_initialized()?;
         
         let documents = self.storage.scan_all()?;
         let mut collections: Vec<String> = documents
             .into_iter()
-            .map(|doc| doc.collection)
+            .map(|doc| doc.collection)
+            .filter(|collection| {
+                collection != SYSTEM_COLLECTION && collection != AUDIT_COLLECTION && collection != KV_COLLECTION
+            })
+            .collect();
+        
+        collections.sort();
+        collections.dedup();
+        
+        Ok(collections)
+    }
+
+    /// Provide the key for encrypted fields, making them readable and
+    /// writable through this handle
+    ///
+    /// The first key ever provided is remembered (as a sealed check value,
+    /// never the key itself) and any other key is rejected afterwards.
+    pub fn unlock_fields(&self, key: &[u8]) -> NVResult<()> {
+        self.ensure_initialized()?;
+        let cipher = FieldCipher::new(key)?;
+
+        match self.system.get(keys::FIELD_KEY_CHECK)? {
+            Some(NVValue::String(check)) => {
+                cipher.open(&crypto::from_hex(&check)?).map_err(|_| {
+                    NeuralVaultError::EncryptionError("Wrong field encryption key".to_string())
+                })?;
+            }
+            _ => {
+                let check = cipher.seal(FIELD_KEY_CHECK_VALUE)?;
+                self.system
+                    .set(keys::FIELD_KEY_CHECK, NVValue::String(crypto::to_hex(&check)))?;
+            }
+        }
+
+        self.storage.set_field_cipher(Some(cipher));
+        Ok(())
+    }
+
+    /// Unlock encrypted fields with a password
+    ///
+    /// The first call generates a random master key and stores it wrapped
+    /// under a key derived from `password`; later calls unwrap it. Fails on
+    /// databases whose field key was provided directly.
+    pub fn unlock_with_password(&self, password: &str) -> NVResult<()> {
+        self.ensure_initialized()?;
+
+        let master = match self.wrapped_key()? {
+            Some(wrapped) => wrapped.unwrap(password)?,
+            None => {
+                if self.system.get(keys::FIELD_KEY_CHECK)?.is_some() {
+                    return Err(NeuralVaultError::EncryptionError(
+                        "Database uses a directly provided field key".to_string(),
+                    ));
+                }
+                let master = crypto::random_bytes(crypto::KEY_LEN);
+                let wrapped = WrappedKey::wrap(&master, password, self.kdf_params())?;
+                self.system.set(keys::WRAPPED_KEY, NVValue::String(serde_json::to_string(&wrapped)?))?;
+                master
+            }
+        };
+        self.unlock_fields(&master)
+    }
+
+    /// Change the password protecting the field master key
+    ///
+    /// Only the master key is re-wrapped, using the configured KDF settings;
+    /// no data is re-encrypted.
+    pub fn change_password(&self, old_password: &str, new_password: &str) -> NVResult<()> {
+        self.ensure_initialized()?;
+        let wrapped = self.wrapped_key()?.ok_or_else(|| {
+            NeuralVaultError::EncryptionError("No password has been set".to_string())
+        })?;
+
+        let master = wrapped.unwrap(old_password)?;
+        let rewrapped = WrappedKey::wrap(&master, new_password, self.kdf_params())?;
+        self.system
+            .set(keys::WRAPPED_KEY, NVValue::String(serde_json::to_string(&rewrapped)?))
+    }
+
+    fn wrapped_key(&self) -> NVResult<Option<WrappedKey>> {
+        // Stored as a JSON string, since catalog numbers are floats
+        match self.system.get(keys::WRAPPED_KEY)? {
+            Some(NVValue::String(json)) => Ok(Some(serde_json::from_str(&json)?)),
+            _ => Ok(None),
+        }
+    }
+
+    fn kdf_params(&self) -> KdfParams {
+        KdfParams {
+            memory_kib: self.config.kdf_memory_kib,
+            iterations: self.config.kdf_iterations,
+        }
+    }
+
+    /// Re-encrypt every encrypted field under a new key
+    ///
+    /// Runs a compaction, so superseded versions sealed with the old key
+    /// (and plaintext from before fields were encrypted) are dropped as
+    /// well. This handle uses the new key afterwards; the old one is
+    /// rejected from then on.
+    pub fn rotate_encryption_key(&self, old_key: &[u8], new_key: &[u8]) -> NVResult<()> {
+        self.ensure_initialized()?;
+        if self.wrapped_key()?.is_some() {
+            return Err(NeuralVaultError::EncryptionError(
+                "Field key is password-protected; use rotate_master_key".to_string(),
+            ));
+        }
+        self.rekey(old_key, new_key, None)
+    }
+
+    /// Replace the password-protected master key with a new random one
+    ///
+    /// Like `rotate_encryption_key`, but for databases unlocked with a
+    /// password. The password stays the same.
+    pub fn rotate_master_key(&self, password: &str) -> NVResult<()> {
+        self.ensure_initialized()?;
+        let wrapped = self.wrapped_key()?.ok_or_else(|| {
+            NeuralVaultError::EncryptionError("No password has been set".to_string())
+        })?;
+
+        let old_master = wrapped.unwrap(password)?;
+        let new_master = crypto::random_bytes(crypto::KEY_LEN);
+        let rewrapped = WrappedKey::wrap(&new_master, password, self.kdf_params())?;
+        self.rekey(&old_master, &new_master, Some(rewrapped))
+    }
+
+    /// Re-encrypt under `new_key`, replacing the key check (and the wrapped
+    /// master key, if given) in the same compaction
+    fn rekey(&self, old_key: &[u8], new_key: &[u8], wrapped: Option<WrappedKey>) -> NVResult<()> {
+        let old = FieldCipher::new(old_key)?;
+        let new = FieldCipher::new(new_key)?;
+
+        let Some(NVValue::String(check)) = self.system.get(keys::FIELD_KEY_CHECK)? else {
+            return Err(NeuralVaultError::EncryptionError(
+                "No field encryption key has been set".to_string(),
+            ));
+        };
+        old.open(&crypto::from_hex(&check)?).map_err(|_| {
+            NeuralVaultError::EncryptionError("Wrong field encryption key".to_string())
+        })?;
+        let new_check = NVValue::String(crypto::to_hex(&new.seal(FIELD_KEY_CHECK_VALUE)?));
+        let wrapped = wrapped
+            .map(|wrapped| serde_json::to_string(&wrapped).map(NVValue::String))
+            .transpose()?;
+
+        // Read with the old key, write with the new one
+        let succession = OplogSuccession::new(&self.oplog);
+        self.storage.set_field_cipher(Some(old));
+        let oplog = self.storage.compact_rekeyed(
+            new,
+            |document, replaced_len| {
+                SystemCatalog::update_entry(document, keys::FIELD_KEY_CHECK, new_check.clone());
+                if let Some(wrapped) = &wrapped {
+                    SystemCatalog::update_entry(document, keys::WRAPPED_KEY, wrapped.clone());
+                }
+                succession.rewrite(document, replaced_len);
+            },
+            || succession.lock(),
+        )?;
+        succession.finish(oplog);
+        // Vector index checkpoints point into the replaced log
+        self.save_vector_indexes()
+    }
+
+    /// Store `collection.field` encrypted with the field key
+    ///
+    /// Requires `unlock_fields`. Existing documents are rewritten with the
+    /// field encrypted and the data file is compacted, so no plaintext
+    /// version is left behind; this fails while a snapshot is open, and
+    /// for collections with archived documents, whose copies can't be
+    /// rewritten. Handles without the key read documents without encrypted
+    /// fields and can't write to the collection. Encrypted fields can't be
+    /// indexed or aggregated.
+    pub fn encrypt_field(&self, collection: &str, field: &str) -> NVResult<()> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(collection)?;
+        validation::validate_field_name(field)?;
+        if !self.storage.has_field_cipher() {
+            return Err(NeuralVaultError::EncryptionError(
+                "Unlock the database with the field key first".to_string(),
+            ));
+        }
+
+        let indexed = self.indexes.read().values().any(|index| {
+            let definition = index.definition();
+            definition.collection == collection
+                && (definition.fields.iter().any(|f| f == field)
+                    || definition.filter.iter().any(|c| c.field == field))
+        });
+        let aggregated = self.aggregates.read().values().any(|aggregate| {
+            let definition = aggregate.definition();
+            definition.collection == collection && aggregate_fields(definition).any(|f| f == field)
+        });
+        if indexed || aggregated {
+            return Err(NeuralVaultError::ValidationError(format!(
+                "Field {}.{} is used by an index or aggregate view",
+                collection, field
+            )));
+        }
+        if !archive::read_collection(&self.archive_dir(), collection)?.is_empty() {
+            return Err(NeuralVaultError::ValidationError(format!(
+                "Collection {} has archived documents, which would keep {} in plaintext",
+                collection, field
+            )));
+        }
+        if self.storage.snapshots_open() {
+            return Err(NeuralVaultError::StorageError(
+                "Can't encrypt a field while snapshots are open".to_string(),
+            ));
+        }
+
+        let key = format!("{}{}.{}", keys::ENCRYPTED_FIELD_PREFIX, collection, field);
+        self.system.set(&key, NVValue::Bool(true))?;
+        self.storage.seal_field(collection, field);
+
+        // Rewrite existing plaintext values, then drop the versions that
+        // held them
+        self.write_batch(|batch| -> NVResult<()> {
+            for document in batch.scan_collection(collection)? {
+                if document.data.contains_key(field) {
+                    batch.put(&document)?;
+                }
+            }
+            Ok(())
+        })??;
+        self.compact()
+    }
+
+    /// Encrypted fields as `(collection, field)` pairs
+    pub fn encrypted_fields(&self) -> NVResult<Vec<(String, String)>> {
+        self.ensure_initialized()?;
+        Ok(self
+            .system
+            .list(keys::ENCRYPTED_FIELD_PREFIX)?
+            .into_iter()
+            .filter_map(|(key, _)| {
+                let (collection, field) = key[keys::ENCRYPTED_FIELD_PREFIX.len()..].split_once('.')?;
+                Some((collection.to_string(), field.to_string()))
+            })
+            .collect())
+    }
+
+    fn ensure_not_encrypted(&self, collection: &str, field: &str) -> NVResult<()> {
+        if self.storage.is_sealed(collection, field) {
+            return Err(NeuralVaultError::ValidationError(format!(
+                "Field {}.{} is encrypted",
+                collection, field
+            )));
+        }
+        Ok(())
+    }
+
+    /// Create or replace an access control role
+    pub fn create_role(&self, role: Role) -> NVResult<()> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(&role.name)?;
+        for collection in role.permissions.keys() {
+            if collection != auth::ALL_COLLECTIONS {
+                validation::validate_collection_name(collection)?;
+            }
+        }
+
+        let key = format!("{}{}", keys::ROLE_PREFIX, role.name);
+        self.system.set(&key, serde_json::to_value(&role)?.into())?;
+        self.access.write().set_role(role);
+        Ok(())
+    }
+
+    /// Remove a role, returning whether it existed
+    ///
+    /// Users keep the role's name but no longer get its permissions.
+    pub fn drop_role(&self, name: &str) -> NVResult<bool> {
+        self.ensure_initialized()?;
+        self.system.remove(&format!("{}{}", keys::ROLE_PREFIX, name))?;
+        Ok(self.access.write().remove_role(name))
+    }
+
+    /// Create or replace a user holding existing roles
+    pub fn create_user(&self, name: &str, roles: Vec<String>) -> NVResult<()> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(name)?;
+        if let Some(role) = roles.iter().find(|role| self.access.read().role(role).is_none()) {
+            return Err(NeuralVaultError::ValidationError(format!("Unknown role: {}", role)));
+        }
+
+        let user = User { name: name.to_string(), roles };
+        let key = format!("{}{}", keys::AUTH_USER_PREFIX, name);
+        self.system.set(&key, serde_json::to_value(&user)?.into())?;
+        self.access.write().set_user(user);
+        Ok(())
+    }
+
+    /// Remove a user and revoke its tokens, returning whether it existed
+    pub fn drop_user(&self, name: &str) -> NVResult<bool> {
+        self.ensure_initialized()?;
+        let Some(digests) = self.access.write().remove_user(name) else {
+            return Ok(false);
+        };
+        for digest in digests {
+            self.system.remove(&format!("{}{}", keys::TOKEN_PREFIX, digest))?;
+        }
+        self.system.remove(&format!("{}{}", keys::AUTH_USER_PREFIX, name))?;
+        Ok(true)
+    }
+
+    /// Issue a new API token for a user
+    ///
+    /// The token is returned once; only its digest is stored.
+    pub fn issue_token(&self, user: &str) -> NVResult<String> {
+        self.ensure_initialized()?;
+        if self.access.read().user(user).is_none() {
+            return Err(NeuralVaultError::ValidationError(format!("Unknown user: {}", user)));
+        }
+
+        let token = auth::new_token();
+        let digest = auth::token_digest(&token);
+        self.system.set(
+            &format!("{}{}", keys::TOKEN_PREFIX, digest),
+            NVValue::String(user.to_string()),
+        )?;
+        self.access.write().add_token(digest, user.to_string());
+        Ok(token)
+    }
+
+    /// Revoke a token, returning whether it was valid
+    pub fn revoke_token(&self, token: &str) -> NVResult<bool> {
+        self.ensure_initialized()?;
+        let digest = auth::token_digest(token);
+        self.system.remove(&format!("{}{}", keys::TOKEN_PREFIX, digest))?;
+        Ok(self.access.write().remove_token(&digest))
+    }
+
+    /// Check that a token grants `access` to `collection`, returning the
+    /// authenticated user's name
+    ///
+    /// For a server layer to call before serving each request. The
+    /// embedded API itself is not restricted; `authorized` returns a
+    /// handle that checks every call.
+    pub fn authorize(&self, token: &str, collection: &str, access: Access) -> NVResult<String> {
+        self.ensure_initialized()?;
+        Ok(self.access.read().authorize(token, collection, access)?.name.clone())
+    }
+
+    /// A handle limited to what `token` is allowed; see `AuthorizedVault`
+    ///
+    /// Fails with `AccessDenied` if the token is invalid.
+    pub fn authorized(&self, token: &str) -> NVResult<AuthorizedVault<'_>> {
+        self.ensure_initialized()?;
+        let user = self.access.read().authenticate(token)?.name.clone();
+        Ok(AuthorizedVault::new(self, token, user))
+    }
+
+    /// Turn the audit trail on or off; the setting persists across opens
+    ///
+    /// While on, every create, update and delete appends an entry to the
+    /// `_nv_audit` collection in the same batch as the write. Entries can
+    /// be queried like any collection but never modified.
+    pub fn enable_audit_log(&self, enabled: bool) -> NVResult<()> {
+        self.ensure_initialized()?;
+        self.system.set(keys::AUDIT_LOG, NVValue::Bool(enabled))?;
+        self.audit.store(enabled, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Record `actor` as the author of later writes through this handle,
+    /// from any thread, or clear it with `None`; returns the previous actor
+    pub fn set_actor(&self, actor: Option<String>) -> Option<String> {
+        std::mem::replace(&mut *self.actor.write(), actor)
+    }
+
+    /// The actor recorded for writes through this handle
+    pub fn actor(&self) -> Option<String> {
+        self.actor.read().clone()
+    }
+
+    /// Reclaim space used by superseded and deleted documents, and by
+    /// attachment chunks no attachment references any more
+    ///
+    /// Compaction moves records, so it starts a new oplog; followers then
+    /// re-read it from the beginning.
+    pub fn compact(&self) -> NVResult<()> {
+        self.compact_with_progress(|_| true)
+    }
+
+    /// Compact, reporting progress to `progress`
+    ///
+    /// `progress` is called periodically while live documents are copied;
+    /// returning false cancels the compaction with `Cancelled` and leaves
+    /// the database as it was. No storage or oplog lock is held while it
+    /// runs, so it may read and write the database, but it must not start
+    /// another compaction (`compact`, `recover`, `encrypt_field` or a key
+    /// rotation), which waits for this one and never returns. A snapshot
+    /// it leaves open makes this compaction fail.
+    pub fn compact_with_progress(&self, progress: impl FnMut(&CompactionProgress) -> bool) -> NVResult<()> {
+        self.ensure_initialized()?;
+        let succession = OplogSuccession::new(&self.oplog);
+        let oplog = self.storage.compact_rewriting(
+            |document, replaced_len| succession.rewrite(document, replaced_len),
+            progress,
+            || succession.lock(),
+        )?;
+        succession.finish(oplog);
+        // Vector index checkpoints point into the replaced log
+        self.save_vector_indexes()?;
+
+        let _writes = self.attachment_writes.lock();
+        attachments::collect_garbage(&self.attachments_dir(), |id| self.storage.contains(id))?;
+        Ok(())
+    }
+
+    /// Salvage every readable document into a new data file
+    ///
+    /// Corrupted ranges of the data file are skipped and documents whose
+    /// newest version was lost fall back to an older readable one. Like
+    /// compaction, this starts a new oplog. Secondary, text and vector
+    /// indexes and materialized aggregates are rebuilt from the salvaged
+    /// documents.
+    pub fn recover(&self) -> NVResult<RecoveryReport> {
+        self.ensure_initialized()?;
+        let succession = OplogSuccession::new(&self.oplog);
+        let (report, oplog) = self.storage.recover(
+            |document, replaced_len| succession.rewrite(document, replaced_len),
+            || succession.lock(),
+        )?;
+        succession.finish(oplog);
+
+        self.write_batch(|batch| -> NVResult<()> {
+            for index in self.indexes.write().values_mut() {
+                let mut rebuilt = SecondaryIndex::new(index.definition().clone());
+                for document in batch.scan_collection(&rebuilt.definition().collection)? {
+                    rebuilt.add(&document, &self.query_processor);
+                }
+                *index = rebuilt;
+            }
+            for aggregate in self.aggregates.write().values_mut() {
+                let mut rebuilt = MaterializedAggregate::new(aggregate.definition().clone());
+                for document in batch.scan_collection(&rebuilt.definition().collection)? {
+                    rebuilt.add(&document);
+                }
+                *aggregate = rebuilt;
+            }
+            for index in self.texts.write().values_mut() {
+                let mut rebuilt = TextIndex::new(index.definition().clone());
+                for document in batch.scan_collection(&rebuilt.definition().collection)? {
+                    rebuilt.add(&document);
+                }
+                *index = rebuilt;
+            }
+            for index in self.completions.write().values_mut() {
+                let mut rebuilt = CompletionIndex::new(index.definition().clone());
+                for document in batch.scan_collection(&rebuilt.definition().collection)? {
+                    rebuilt.add(&document);
+                }
+                *index = rebuilt;
+            }
+            for (collection, tracked) in self.capped.write().iter_mut() {
+                let mut rebuilt = CappedCollection::new(tracked.cap());
+                for document in batch.scan_collection(collection)? {
+                    rebuilt.add(&document);
+                }
+                *tracked = rebuilt;
+            }
+            for (collection, series) in self.time_series.write().iter_mut() {
+                let mut rebuilt = TimeSeries::new(series.options().clone());
+                for document in batch.scan_collection(collection)? {
+                    rebuilt.add(&document);
+                }
+                *series = rebuilt;
+            }
+            let mut cache_order = UpdateOrder::new();
+            for collection in &self.config.cache_collections {
+                for document in batch.scan_collection(collection)? {
+                    cache_order.add(&document);
+                }
+            }
+            *self.cache_order.write() = cache_order;
+            for index in self.vectors.write().values_mut() {
+                let mut rebuilt = VectorIndex::new(index.definition().clone());
+                for document in batch.scan_collection(&rebuilt.definition().collection)? {
+                    rebuilt.add(&document);
+                }
+                *index = rebuilt;
+            }
+            Ok(())
+        })??;
+        self.save_vector_indexes()?;
+        Ok(report)
+    }
+
+    /// Unreadable ranges of the data file found by scans and reads so far
+    pub fn corrupt_ranges(&self) -> Vec<CorruptRange> {
+        self.storage.corrupt_ranges()
+    }
+
+    /// Document changes made after `sequence`, for external sync services
+    ///
+    /// Start from 0 and pass each result's `next` to the following call;
+    /// storing `next` makes the feed resumable across restarts. Sequence
+    /// numbers keep increasing across compactions, but a sequence from
+    /// before the latest compaction can't be resumed exactly: the result
+    /// then has `reset` set and lists every live document instead.
+    /// Catalog entries are left out. At most `CHANGES_PAGE_SIZE` changes are
+    /// returned per call.
+    pub fn changes_since(&self, sequence: u64) -> NVResult<ChangeSet> {
+        self.ensure_initialized()?;
+        let oplog = self.oplog.read();
+        // The start of a compacted log holds old documents, not new changes
+        let (offset, reset) = match sequence.checked_sub(oplog.base) {
+            Some(offset) if offset > 0 || oplog.base == 0 => (offset, false),
+            _ => (0, true),
+        };
+
+        let (records, next) = self.storage.records_since(offset, CHANGES_PAGE_SIZE)?;
+        let changes = records
+            .into_iter()
+            .filter(|(_, document)| {
+                document.collection != SYSTEM_COLLECTION
+                    // Markers written before they carried the collection
+                    && !document.id.starts_with(&format!("{}:", SYSTEM_COLLECTION))
+            })
+            .map(|(offset, document)| Change {
+                sequence: oplog.base + offset,
+                id: document.id.clone(),
+                collection: document.collection.clone(),
+                document: (!document.deleted).then_some(document),
+            })
+            .collect();
+
+        Ok(ChangeSet {
+            changes,
+            next: oplog.base + next,
+            reset,
+        })
+    }
+
+    /// Read the oplog from `position` for a follower or sync service
+    ///
+    /// The oplog is the data file itself read in append order. A position
+    /// from an older log (or none) starts over from the beginning and sets
+    /// `reset` on the page. Entries carry record payloads with encrypted
+    /// fields still sealed; catalog entries this database keeps to itself,
+    /// such as its keys, roles and tokens, are left out.
+    pub fn oplog(&self, position: Option<&OplogPosition>, limit: usize) -> NVResult<OplogPage> {
+        self.ensure_initialized()?;
+        let oplog = self.oplog.read();
+        let (offset, reset) = match position {
+            Some(position) if position.log_id == oplog.id => (position.offset, false),
+            _ => (0, true),
+        };
+
+        let (records, next) = self.storage.sealed_records_since(offset, limit)?;
+        let mut entries = Vec::with_capacity(records.len());
+        for (offset, record) in records {
+            if !is_unreplicated(RecordView::parse(&record)?.id()) {
+                entries.push(OplogEntry { offset, record });
+            }
+        }
+        Ok(OplogPage {
+            log_id: oplog.id.clone(),
+            entries,
+            next,
+            reset,
+        })
+    }
+
+    /// Write records and deletion markers received from a leader as-is
+    ///
+    /// Hooks and the audit log are bypassed, since the leader already ran
+    /// them, and encrypted fields are written still sealed. Catalog entries
+    /// a database keeps to itself are skipped.
+    pub(crate) fn apply_replicated(&self, records: &[Vec<u8>]) -> NVResult<()> {
+        self.ensure_initialized()?;
+        let mut views = Vec::with_capacity(records.len());
+        for record in records {
+            let view = RecordView::parse(record)?;
+            if !is_unreplicated(view.id()) {
+                views.push((record, view));
+            }
+        }
+        if views.is_empty() {
+            return Ok(());
+        }
+
+        self.write_batch(|batch| -> NVResult<()> {
+            for (record, view) in views {
+                let id = view.id();
+                let previous = match batch.contains(id) {
+                    true => Some(batch.read(id)?),
+                    false => None,
+                };
+                if !view.deleted() {
+                    batch.put_record(record)?;
+                    let current = batch.read(id)?;
+                    self.record_change(previous.as_ref(), Some(&current));
+                } else if previous.is_some() {
+                    batch.delete(id)?;
+                    self.detached.lock().push(id.to_string());
+                    self.record_change(previous.as_ref(), None);
+                }
+            }
+            Ok(())
+        })?
+    }
+
+    /// Write the documents and deletion markers a sync settled on, keeping
+    /// their timestamps
+    ///
+    /// Unlike `apply_replicated`, each goes through the checks and hooks a
+    /// local write would, and is audited; embeddings and CRDT stamps come
+    /// with the document. All of them are written in one batch, which
+    /// fails with `WriteConflict`, writing nothing, if a document is no
+    /// longer at the version the sync read.
+    pub(crate) fn apply_synced(&self, writes: &[SyncWrite]) -> NVResult<()> {
+        self.ensure_initialized()?;
+        if writes.is_empty() {
+            return Ok(());
+        }
+        for write in writes {
+            validation::validate_document_id(&write.document.id)?;
+            if !write.document.deleted {
+                validate_synced(&write.document)?;
+            }
+        }
+        self.make_room()?;
+
+        let hooks = self.hooks.read();
+        let mut updated = Vec::new();
+        let apply = |batch: &mut WriteBatch| -> NVResult<()> {
+            let changed = writes.iter().find(|write| batch.version(&write.document.id) != write.expected);
+            if let Some(write) = changed {
+                return Err(NeuralVaultError::WriteConflict(format!(
+                    "Document {} changed during the sync",
+                    write.document.id
+                )));
+            }
+
+            for SyncWrite { document, .. } in writes {
+                // A cascade earlier in the batch may have deleted it already
+                let previous = match batch.contains(&document.id) {
+                    true => Some(batch.read(&document.id)?),
+                    false => None,
+                };
+                match (document.deleted, previous) {
+                    (true, Some(previous)) => {
+                        let plan = self.check_delete(batch, &hooks, &previous)?;
+                        self.apply_delete_plan(batch, &plan)?;
+                        self.remove_document(batch, &document.id)?;
+                    }
+                    (true, None) => {}
+                    (false, Some(previous)) => {
+                        self.store_document(batch, Some(&previous), document)?;
+                        updated.push((previous, document.clone()));
+                    }
+                    (false, None) => {
+                        let mut document = document.clone();
+                        hooks.before_create(&mut document)?;
+                        validate_synced(&document)?;
+                        self.store_document(batch, None, &document)?;
+                    }
+                }
+            }
+            Ok(())
+        };
+        let revert = |written: Option<&NVDocument>, before: Option<&NVDocument>| self.record_change(written, before);
+        self.write_batch_atomic(apply, revert)?;
+
+        for (previous, current) in updated {
+            hooks.after_update(&previous, &current);
+        }
+        Ok(())
+    }
+
+    /// IDs of every live document a leader could have written
+    pub(crate) fn replicated_ids(&self) -> NVResult<Vec<String>> {
+        Ok(self
+            .storage
+            .scan_all()?
+            .into_iter()
+            .filter(|doc| !is_unreplicated(&doc.id))
+            .map(|doc| doc.id)
+            .collect())
+    }
+
+    /// Identity of this database for offline sync, created on first use
+    pub fn replica_id(&self) -> NVResult<String> {
+        self.ensure_initialized()?;
+        let mut replica = self.replica.write();
+        if let Some(id) = replica.as_ref() {
+            return Ok(id.clone());
+        }
+        let id = Uuid::new_v4().to_string();
+        self.system.set(keys::REPLICA_ID, NVValue::String(id.clone()))?;
+        *replica = Some(id.clone());
+        Ok(id)
+    }
+
+    /// Make a collection's documents CRDT maps with a last-writer-wins
+    /// register per field
+    ///
+    /// Every write stamps the fields it changes in the reserved `_nv_clock`
+    /// field, and `sync` merges concurrent edits of such documents field by
+    /// field, so edits to different fields all survive and edits to the
+    /// same field agree on the later one. Enable it on every database that
+    /// syncs the collection. Existing documents get stamps as their fields
+    /// are next written.
+    pub fn enable_crdt(&self, collection: &str) -> NVResult<()> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(collection)?;
+        // Stamps need the replica ID, which can't be created mid-write
+        self.replica_id()?;
+
+        self.system.set(&format!("{}{}", keys::CRDT_PREFIX, collection), NVValue::Bool(true))?;
+        self.crdt_collections.write().insert(collection.to_string());
+        Ok(())
+    }
+
+    /// How far this database has synchronized with a peer
+    pub(crate) fn sync_state(&self, peer: &str) -> NVResult<SyncState> {
+        Ok(match self.system.get(&format!("{}{}", keys::SYNC_PREFIX, peer))? {
+            Some(NVValue::String(json)) => serde_json::from_str(&json)?,
+            _ => SyncState::default(),
+        })
+    }
+
+    pub(crate) fn set_sync_state(&self, peer: &str, state: &SyncState) -> NVResult<()> {
+        self.system.set(
+            &format!("{}{}", keys::SYNC_PREFIX, peer),
+            NVValue::String(serde_json::to_string(state)?),
+        )
+    }
+
+    /// This database's position in its leader's oplog
+    pub(crate) fn replication_cursor(&self) -> NVResult<Option<OplogPosition>> {
+        Ok(match self.system.get(keys::REPLICATION_CURSOR)? {
+            Some(NVValue::String(json)) => Some(serde_json::from_str(&json)?),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn set_replication_cursor(&self, position: Option<&OplogPosition>) -> NVResult<()> {
+        match position {
+            Some(position) => self.system.set(
+                keys::REPLICATION_CURSOR,
+                NVValue::String(serde_json::to_string(position)?),
+            ),
+            None => self.system.remove(keys::REPLICATION_CURSOR).map(|_| ()),
+        }
+    }
+
+    /// Make a collection a time series, bucketing its events by
+    /// `options.time_field` for `find_time_range` and `downsample`
+    ///
+    /// With `retention_secs` set, events older than that are deleted as new
+    /// ones arrive, or by `apply_retention`; expired events are deleted
+    /// straight away.
+    pub fn set_time_series(&self, collection: &str, options: TimeSeriesOptions) -> NVResult<()> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(collection)?;
+        validation::validate_field_name(&options.time_field)?;
+        options.validate()?;
+
+        self.system.set(
+            &format!("{}{}", keys::TIME_SERIES_PREFIX, collection),
+            NVValue::String(serde_json::to_string(&options)?),
+        )?;
+
+        // Bucket under the storage lock so no write is missed
+        self.write_batch(|batch| -> NVResult<()> {
+            let mut series = TimeSeries::new(options);
+            for document in batch.scan_collection(collection)? {
+                series.add(&document);
+            }
+            self.time_series.write().insert(collection.to_string(), series);
+            self.enforce_retention(batch, collection).map(|_| ())
+        })??;
+        Ok(())
+    }
+
+    /// Stop treating a collection as a time series, returning whether it
+    /// was one; its documents are kept
+    pub fn drop_time_series(&self, collection: &str) -> NVResult<bool> {
+        self.ensure_initialized()?;
+        let removed = self.system.remove(&format!("{}{}", keys::TIME_SERIES_PREFIX, collection))?;
+        self.time_series.write().remove(collection);
+        Ok(removed)
+    }
+
+    /// Options of a time-series collection
+    pub fn time_series_options(&self, collection: &str) -> Option<TimeSeriesOptions> {
+        self.time_series.read().get(collection).map(|series| series.options().clone())
+    }
+
+    /// Events of a time-series collection in `from..to`, oldest first
+    pub fn find_time_range(
+        &self,
+        collection: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> NVResult<Vec<NVDocument>> {
+        self.ensure_initialized()?;
+        let ids = self
+            .time_series
+            .read()
+            .get(collection)
+            .ok_or_else(|| NeuralVaultError::InvalidQuery(format!("{} is not a time series", collection)))?
+            .range(from.timestamp_millis(), to.timestamp_millis());
+        // Skip events deleted since the IDs were collected
+        let mut events = Vec::with_capacity(ids.len());
+        for id in &ids {
+            events.extend(self.read_live(id)?);
+        }
+        self.redact(&mut events);
+        Ok(events)
+    }
+
+    /// Roll up the events of a time-series collection in `from..to` into
+    /// one row per `interval`, as a pipeline `Window` stage over the time
+    /// field would
+    pub fn downsample(
+        &self,
+        collection: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        interval: Interval,
+        aggregates: &[(String, Aggregate)],
+    ) -> NVResult<Vec<Row>> {
+        let events = self.find_time_range(collection, from, to)?;
+        let Some(options) = self.time_series_options(collection) else {
+            return Ok(Vec::new());
+        };
+        let stage = Stage::Window {
+            field: options.time_field,
+            interval,
+            aggregates: aggregates.to_vec(),
+        };
+        let rows = events.into_iter().map(|event| event.data).collect();
+        Ok(pipeline::run(rows, &[stage], &self.query_processor))
+    }
+
+    /// Delete the events of every time-series collection that are past
+    /// their retention period, returning how many were deleted
+    pub fn apply_retention(&self) -> NVResult<usize> {
+        self.ensure_initialized()?;
+        let collections: Vec<String> = self.time_series.read().keys().cloned().collect();
+        self.write_batch(|batch| -> NVResult<usize> {
+            let mut deleted = 0;
+            for collection in &collections {
+                deleted += self.enforce_retention(batch, collection)?;
+            }
+            Ok(deleted)
+        })?
+    }
+
+    /// Cap a collection at a number of documents or bytes, evicting its
+    /// oldest documents whenever a write goes over
+    ///
+    /// Documents already over the cap are evicted straight away. Suits
+    /// logs and recent-activity feeds.
+    pub fn set_capped(&self, collection: &str, cap: CollectionCap) -> NVResult<()> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(collection)?;
+        cap.validate()?;
+
+        self.system.set(
+            &format!("{}{}", keys::CAPPED_PREFIX, collection),
+            NVValue::String(serde_json::to_string(&cap)?),
+        )?;
+
+        // Track under the storage lock so no write is missed
+        self.write_batch(|batch| -> NVResult<()> {
+            let mut tracked = CappedCollection::new(cap);
+            for document in batch.scan_collection(collection)? {
+                tracked.add(&document);
+            }
+            self.capped.write().insert(collection.to_string(), tracked);
+            self.enforce_cap(batch, collection)
+        })??;
+        Ok(())
+    }
+
+    /// Remove a collection's cap, returning whether it had one
+    pub fn uncap(&self, collection: &str) -> NVResult<bool> {
+        self.ensure_initialized()?;
+        let removed = self.system.remove(&format!("{}{}", keys::CAPPED_PREFIX, collection))?;
+        self.capped.write().remove(collection);
+        Ok(removed)
+    }
+
+    /// The cap on a collection, if it has one
+    pub fn collection_cap(&self, collection: &str) -> Option<CollectionCap> {
+        self.capped.read().get(collection).map(CappedCollection::cap)
+    }
+
+    /// Set how IDs are assigned for new documents in a collection
+    pub fn set_id_strategy(&self, collection: &str, strategy: IdStrategy) -> NVResult<()> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(collection)?;
+
+        self.system.set(
+            &format!("{}{}", keys::ID_STRATEGY_PREFIX, collection),
+            NVValue::String(strategy.as_str().to_string()),
+        )?;
+        self.id_strategies.write().insert(collection.to_string(), strategy);
+        Ok(())
+    }
+
+    /// Declare the fields of a collection, replacing any previous schema
+    ///
+    /// Field defaults are filled in whenever a document is created without
+    /// the field, before create hooks run; existing documents are left as
+    /// they are. Upserts only get defaults when the document is new.
+    /// Sensitive fields can still be queried on, but reads (queries,
+    /// snapshots, searches and populated references) leave them out of the
+    /// documents returned unless the query sets `include_sensitive` or
+    /// `find_by_id_unredacted` is used.
+    pub fn set_schema(&self, collection: &str, schema: CollectionSchema) -> NVResult<()> {
+        self.ensure_initialized()?;
+        validation::validate_collection_name(collection)?;
+        schema.validate()?;
+
+        self.system.set(
+            &format!("{}{}", keys::SCHEMA_PREFIX, collection),
+            NVValue::String(serde_json::to_string(&schema)?),
+        )?;
+        self.schemas.write().insert(collection.to_string(), schema);
+        Ok(())
+    }
+
+    /// Remove a collection's schema, returning whether it had one
+    pub fn remove_schema(&self, collection: &str) -> NVResult<bool> {
+        self.ensure_initialized()?;
+        let removed = self.system.remove(&format!("{}{}", keys::SCHEMA_PREFIX, collection))?;
+        self.schemas.write().remove(collection);
+        Ok(removed)
+    }
+
+    /// The schema of a collection, if it has one
+    pub fn schema(&self, collection: &str) -> Option<CollectionSchema> {
+        self.schemas.read().get(collection).cloned()
+    }
+
+    /// Fill in the schema defaults missing from a new document's data
+    fn apply_defaults(&self, collection: &str, data: &mut HashMap<String, NVValue>) -> NVResult<()> {
+        let Some(schema) = self.schema(collection).filter(CollectionSchema::has_defaults) else {
+            return Ok(());
+        };
+        schema.apply_defaults(data, |field| self.next_sequence(&format!("{}.{}", collection, field)))
+    }
+
+    /// ID strategy used for a collection
+    pub fn id_strategy(&self, collection: &str) -> IdStrategy {
+        self.id_strategies
+            .read()
+            .get(collection)
+            .copied()
+            .unwrap_or(self.config.id_strategy)
+    }
+
+    /// Generate an ID for a new document using the collection's strategy
+    fn generate_id(&self, collection: &str, data: &HashMap<String, NVValue>) -> NVResult<String> {
+        let id = match self.id_strategy(collection) {
+            IdStrategy::Uuid => Uuid::new_v4().to_string(),
+            IdStrategy::Ulid => self.ulids.next(),
+            IdStrategy::AutoIncrement => self.next_auto_id()?.to_string(),
+            IdStrategy::Provided => match data.get("id") {
+                Some(NVValue::String(id)) => id.clone(),
+                _ => {
+                    return Err(NeuralVaultError::ValidationError(format!(
+                        "Collection '{}' requires a string 'id' field",
+                        collection
+                    )))
+                }
+            },
+        };
+        validation::validate_document_id(&id)?;
+        Ok(id)
+    }
+
+    /// Validate a bulk write operation and assign its ID if needed
+    fn prepare_write(&self, op: WriteOp) -> NVResult<WriteOp> {
+        match op {
+            WriteOp::Insert { id, collection, mut data } => {
+                validation::validate_collection_name(&collection)?;
+                validation::validate_fields(&data)?;
+                let id = match id {
+                    Some(id) => {
+                        validation::validate_document_id(&id)?;
+                        id
+                    }
+                    None => self.generate_id(&collection, &data)?,
+                };
+                self.apply_defaults(&collection, &mut data)?;
+                Ok(WriteOp::Insert { id: Some(id), collection, data })
+            }
+            WriteOp::Update { id, updates } => {
+                validate_updates(&updates)?;
+                Ok(WriteOp::Update { id, updates })
+            }
+            WriteOp::Delete { id } => Ok(WriteOp::Delete { id }),
+            WriteOp::Upsert { id, collection, mut data } => {
+                validation::validate_document_id(&id)?;
+                validation::validate_collection_name(&collection)?;
+                validation::validate_fields(&data)?;
+                if !self.storage.contains(&id) {
+                    self.apply_defaults(&collection, &mut data)?;
+                }
+                Ok(WriteOp::Upsert { id, collection, data })
+            }
+        }
+    }
+
+    /// Evict the least recently updated cache documents once the database
+    /// nears its size quota, so the write that follows fits
+    ///
+    /// Only applies with `QuotaPolicy::EvictOldest`. Eviction starts above
+    /// 90% of the quota and deletes cache documents until live data is
+    /// down to 75%, then compacts to give the space back. Evictions are
+    /// ordinary deletes: hooks can veto them and references are followed.
+    fn make_room(&self) -> NVResult<()> {
+        let limit = self.config.max_database_size;
+        if limit == 0 || self.config.quota_policy != QuotaPolicy::EvictOldest {
+            return Ok(());
+        }
+        if self.storage.size()? < limit / 10 * 9 {
+            return Ok(());
+        }
+
+        let _eviction = self.eviction.lock();
+        if self.storage.size()? < limit / 10 * 9 {
+            return Ok(());
+        }
+
+        let target = limit / 4 * 3;
+        let hooks = self.hooks.read();
+        let live = self.write_batch(|batch| -> NVResult<u64> {
+            let mut live = batch.live_size();
+            let mut after = None;
+            while live > target {
+                let Some(next) = self.cache_order.read().next(after.as_ref()) else {
+                    break;
+                };
+                let document = batch.read(&next.1)?;
+                after = Some(next);
+
+                // Documents a hook or reference keeps are passed over
+                let Ok(plan) = self.check_delete(batch, &hooks, &document) else {
+                    continue;
+                };
+                let removed = std::iter::once(&document).chain(&plan.deletes);
+                let size: u64 = removed.filter_map(|removed| batch.stored_size(&removed.id)).sum();
+                self.apply_delete_plan(batch, &plan)?;
+                self.remove_document(batch, &document.id)?;
+                live = live.saturating_sub(size);
+            }
+            Ok(live)
+        })??;
+        drop(hooks);
+
+        // Compacting also catches up on a compaction an open snapshot
+        // refused earlier; while one is open, the write fails only if it
+        // really doesn't fit
+        if live < limit / 10 * 9 {
+            match self.compact() {
+                Err(_) if self.storage.snapshots_open() => {}
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Run a query over live and archived documents together
+    ///
+    /// A live document hides any archived copy with the same ID, for good:
+    /// the copy stays hidden once the live document is deleted.
+    fn find_with_archive(
+        &self,
+        mut query: NVQuery,
+        cancel: &CancellationToken,
+        stats: &mut QueryStats,
+    ) -> NVResult<Vec<NVDocument>> {
+        query.include_archived = false;
+        let populate = std::mem::take(&mut query.populate);
+        let projection = query.projection.take();
+        let (skip, limit) = (query.skip.take(), query.limit.take());
+
+        let mut documents = self.run_find(query.clone(), cancel, stats)?;
+        let archived = archive::read_collection(&self.archive_dir(), &query.collection)?;
+        stats.scanned += archived.len();
+        documents.extend(archived.into_iter().filter(|doc| {
+            !self.storage.contains(&doc.id) && !self.system.contains(&superseded_key(&doc.collection, &doc.id))
+        }));
+
+        query.skip = skip;
+        query.limit = limit;
+        cancel.check()?;
+        let mut documents = self.query_processor.filter(documents, &query)?;
+        if let Some(projection) = &projection {
+            documents.iter_mut().for_each(|doc| doc.project(projection));
+        }
+
+        if !populate.is_empty() {
+            query.populate = populate;
+            self.populate(&query, &mut documents)?;
+        }
+        Ok(documents)
+    }
+
+    /// Directory holding archive segments
+    fn archive_dir(&self) -> PathBuf {
+        Path::new(&self.config.path).join("archive")
+    }
+
+    /// Directory holding attachments
+    fn attachments_dir(&self) -> PathBuf {
+        Path::new(&self.config.path).join("attachments")
+    }
+
+    /// Run `query` over the results of a saved view
+    fn find_in_view(
+        &self,
+        view: NVQuery,
+        mut query: NVQuery,
+        cancel: &CancellationToken,
+        stats: &mut QueryStats,
+    ) -> NVResult<Vec<NVDocument>> {
+        let name = std::mem::replace(&mut query.collection, view.collection.clone());
+        let populate = std::mem::take(&mut query.populate);
+
+        let base = self.run_find(view, cancel, stats)?;
+        stats.plan = stats.plan.take().map(|source| QueryPlan::View {
+            name,
+            source: Box::new(source),
+        });
+        let mut documents = self.query_processor.filter(base, &query)?;
+        if let Some(projection) = &query.projection {
+            documents.iter_mut().for_each(|doc| doc.project(projection));
+        }
+
+        if !populate.is_empty() {
+            query.populate = populate;
+            self.populate(&query, &mut documents)?;
+        }
+        Ok(documents)
+    }
+
+    /// Run a query against each collection it names, merging the results
+    ///
+    /// Each collection is queried for a full page, so ordering, paging and
+    /// projection are applied once to the merged results; every document
+    /// keeps its own collection.
+    fn find_across(&self, mut query: NVQuery, cancel: &CancellationToken, stats: &mut QueryStats) -> NVResult<Vec<NVDocument>> {
+        let collections = self.resolve_targets(&query.collection)?;
+
+        let projection = query.projection.take().map(|mut projection| {
+            projection.extend(query.computed.iter().map(|(name, _)| name.clone()));
+            projection
+        });
+        let mut part = query.clone();
+        part.skip = None;
+        part.limit = query.limit.map(|limit| limit.saturating_add(query.skip.unwrap_or(0)));
+
+        let mut documents = Vec::new();
+        let mut parts = Vec::with_capacity(collections.len());
+        for collection in collections {
+            part.collection = collection.clone();
+            documents.extend(self.run_find(part.clone(), cancel, stats)?);
+            parts.extend(stats.plan.take().map(|plan| (collection, plan)));
+        }
+        stats.plan = Some(QueryPlan::Union { parts });
+
+        let mut documents = self.query_processor.finish(documents, &query)?;
+        if let Some(projection) = &projection {
+            documents.iter_mut().for_each(|doc| doc.project(projection));
+        }
+        Ok(documents)
+    }
+
+    /// The collections a cross-collection target names
+    fn resolve_targets(&self, target: &str) -> NVResult<Vec<String>> {
+        let existing = if targets::has_pattern(target) {
+            self.collections()?
+        } else {
+            Vec::new()
+        };
+        Ok(targets::resolve(target, &existing))
+    }
+
+    /// Run a query's subqueries, making their results the values of the
+    /// conditions they belong to
+    fn resolve_subqueries(
+        &self,
+        query: &mut NVQuery,
+        cancel: &CancellationToken,
+        stats: &mut QueryStats,
+    ) -> NVResult<()> {
+        for subquery in std::mem::take(&mut query.subqueries) {
+            let condition = query.conditions.get_mut(subquery.condition).ok_or_else(|| {
+                NeuralVaultError::InvalidQuery(format!("Subquery for missing condition {}", subquery.condition))
+            })?;
+            if !matches!(condition.operator, QueryOperator::In | QueryOperator::NotIn) {
+                return Err(NeuralVaultError::InvalidQuery(format!(
+                    "Subquery on '{}' needs an In or NotIn condition",
+                    condition.field
+                )));
+            }
+
+            let results = self.find_scoped(subquery.query, cancel, stats)?;
+            let values = match &subquery.select {
+                None => results.into_iter().map(|doc| NVValue::String(doc.id)).collect(),
+                Some(field) => {
+                    let mut values = Vec::new();
+                    for doc in results {
+                        if let Some(value) = doc.data.get(field) {
+                            if !values.contains(value) {
+                                values.push(value.clone());
+                            }
+                        }
+                    }
+                    values
+                }
+            };
+            condition.value = NVValue::Array(values);
+        }
+        Ok(())
+    }
+
+    /// Replace reference fields in query results with the referenced documents
+    ///
+    /// Each referenced document is read once however many results point to
+    /// it. IDs inside arrays are resolved element by element; IDs that don't
+    /// resolve to a live document in the target collection become null.
+    fn populate(&self, query: &NVQuery, documents: &mut [NVDocument]) -> NVResult<()> {
+        let mut resolved: HashMap<(String, String), NVValue> = HashMap::new();
+
+        for spec in &query.populate {
+            let (field, target) = match spec.split_once(':') {
+                Some((field, target)) => (field.to_string(), target.to_string()),
+                None => {
+                    let target = self
+                        .references
+                        .read()
+                        .iter()
+                        .find(|r| r.collection == query.collection && r.field == *spec)
+                        .map(|r| r.target.clone())
+                        .ok_or_else(|| {
+                            NeuralVaultError::InvalidQuery(format!(
+                                "No reference declared for {}.{}; use \"field:collection\"",
+                                query.collection, spec
+                            ))
+                        })?;
+                    (spec.clone(), target)
+                }
+            };
+
+            let schema = match query.include_sensitive {
+                true => None,
+                false => self.schema(&target).filter(CollectionSchema::has_sensitive),
+            };
+            for document in documents.iter_mut() {
+                if let Some(value) = document.data.get_mut(&field) {
+                    self.resolve_references(value, &target, schema.as_ref(), &mut resolved);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolve an ID (or array of IDs) into embedded documents in place,
+    /// without the fields `schema` marks sensitive
+    fn resolve_references(
+        &self,
+        value: &mut NVValue,
+        target: &str,
+        schema: Option<&CollectionSchema>,
+        resolved: &mut HashMap<(String, String), NVValue>,
+    ) {
+        match value {
+            NVValue::String(id) => {
+                let key = (target.to_string(), id.clone());
+                *value = resolved
+                    .entry(key)
+                    .or_insert_with(|| match self.storage.read(id) {
+                        Ok(mut document) if document.collection == target => {
+                            if let Some(schema) = schema {
+                                schema.redact(&mut document.data);
+                            }
+                            embed(document)
+                        }
+                        _ => NVValue::Null,
+                    })
+                    .clone();
+            }
+            NVValue::Array(items) => {
+                for item in items {
+                    self.resolve_references(item, target, schema, resolved);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Delete documents along with the effects of references to them
+    ///
+    /// Before-delete hooks of every affected document run first, so a veto
+    /// leaves everything untouched; the writes then go in one batch, which
+    /// is undone as a whole if any of them fails.
+    ///
+    /// Plans are worked out under the storage lock, from the documents as
+    /// they are when the batch runs.
+    fn delete_documents(&self, documents: &[NVDocument]) -> NVResult<()> {
+        let hooks = self.hooks.read();
+        let delete = |batch: &mut WriteBatch| -> NVResult<()> {
+            let mut plans = Vec::with_capacity(documents.len());
+            for document in documents {
+                // Documents deleted since they were found are skipped
+                if !batch.contains(&document.id) {
+                    continue;
+                }
+                let document = batch.read(&document.id)?;
+                let plan = self.check_delete(batch, &hooks, &document)?;
+                plans.push((document.id, plan));
+            }
+
+            for (id, plan) in plans {
+                // Documents already removed by an earlier cascade are skipped
+                if batch.contains(&id) {
+                    self.apply_delete_plan(batch, &plan)?;
+                    self.remove_document(batch, &id)?;
+                }
+            }
+            Ok(())
+        };
+        let revert = |written: Option<&NVDocument>, before: Option<&NVDocument>| self.record_change(written, before);
+        self.write_batch_atomic(delete, revert)
+    }
+
+    /// Plan the delete of `document` and run the before-delete hooks of
+    /// every document it removes, writing nothing
+    fn check_delete(&self, batch: &mut WriteBatch, hooks: &HookRegistry, document: &NVDocument) -> NVResult<DeletePlan> {
+        let plan = self.plan_delete(batch, document)?;
+        hooks.before_delete(document)?;
+        for child in &plan.deletes {
+            hooks.before_delete(child)?;
+        }
+        Ok(plan)
+    }
+
+    /// Work out which documents referencing `root` must be deleted or nulled
+    ///
+    /// Fails if a `Restrict` reference still points at `root` or at a
+    /// document that would be cascaded.
+    fn plan_delete(&self, batch: &mut WriteBatch, root: &NVDocument) -> NVResult<DeletePlan> {
+        let references = self.references.read().clone();
+        let mut plan = DeletePlan::default();
+        if references.is_empty() {
+            return Ok(plan);
+        }
+
+        let mut visited = HashSet::new();
+        visited.insert(root.id.clone());
+        let mut pending = vec![root.clone()];
+
+        while let Some(parent) = pending.pop() {
+            let parent_id = NVValue::String(parent.id.clone());
+            for reference in references.iter().filter(|r| r.target == parent.collection) {
+                let children = batch
+                    .scan_collection(&reference.collection)?
+                    .into_iter()
+                    .filter(|child| child.get(&reference.field) == Some(&parent_id));
+
+                for child in children {
+                    if visited.contains(&child.id) {
+                        continue;
+                    }
+                    match reference.on_delete {
+                        OnDelete::Restrict => {
+                            return Err(NeuralVaultError::ValidationError(format!(
+                                "Document {} is still referenced by {}.{} of document {}",
+                                parent.id, reference.collection, reference.field, child.id
+                            )))
+                        }
+                        OnDelete::SetNull => {
+                            plan.nullify.push((child.id.clone(), reference.field.clone()));
+                        }
+                        OnDelete::Cascade => {
+                            visited.insert(child.id.clone());
+                            plan.deletes.push(child.clone());
+                            pending.push(child);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(plan)
+    }
+
+    /// Write the nulled fields and cascaded deletes of a delete plan
+    fn apply_delete_plan(&self, batch: &mut WriteBatch, plan: &DeletePlan) -> NVResult<()> {
+        for (id, field) in &plan.nullify {
+            if batch.contains(id) {
+                let previous = batch.read(id)?;
+                let mut child = previous.clone();
+                child.set(field.clone(), NVValue::Null);
+                self.put_document(batch, Some(&previous), &child)?;
+            }
+        }
+        for child in &plan.deletes {
+            if batch.contains(&child.id) {
+                self.remove_document(batch, &child.id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a new document in a batch, rejecting IDs that are already taken
+    fn insert_document(&self, batch: &mut WriteBatch, document: &NVDocument) -> NVResult<()> {
+        let document = self.embedders.read().apply(None, document)?;
+        let document = self.stamp_crdt(None, &document);
+        self.store_document(batch, None, &document)
+    }
+
+    /// Write a new version of a document in a batch
+    ///
+    /// `previous` must be the version currently stored, read in the same batch.
+    fn put_document(
+        &self,
+        batch: &mut WriteBatch,
+        previous: Option<&NVDocument>,
+        document: &NVDocument,
+    ) -> NVResult<()> {
+        let document = self.embedders.read().apply(previous, document)?;
+        let document = self.stamp_crdt(previous, &document);
+        self.store_document(batch, previous, &document)
+    }
+
+    /// Write a document in a batch as it is, without computing embeddings
+    /// or CRDT stamps, and keep everything that follows a write in step
+    ///
+    /// `previous` is the version it replaces, read in the same batch; with
+    /// none, the ID must not be taken.
+    fn store_document(
+        &self,
+        batch: &mut WriteBatch,
+        previous: Option<&NVDocument>,
+        document: &NVDocument,
+    ) -> NVResult<()> {
+        ensure_not_audit(&document.collection)?;
+        match previous {
+            Some(_) => batch.put(document)?,
+            None => {
+                if self.views.read().contains_key(&document.collection) {
+                    return Err(NeuralVaultError::ValidationError(format!(
+                        "'{}' is a view; write to its collection instead",
+                        document.collection
+                    )));
+                }
+                batch.insert(document)?;
+                self.supersede_archived(batch, document)?;
+            }
+        }
+        self.record_change(previous, Some(document));
+        self.append_audit(batch, previous, Some(document))?;
+        if previous.is_none() {
+            self.enforce_retention(batch, &document.collection)?;
+        }
+        self.enforce_cap(batch, &document.collection)
+    }
+
+    /// Mark any archived copy of a newly written document as replaced, so
+    /// it stays hidden once the live document is deleted
+    fn supersede_archived(&self, batch: &mut WriteBatch, document: &NVDocument) -> NVResult<()> {
+        let archived = self.archived_ids.read().get(&document.collection).map(|ids| ids.contains(&document.id));
+        let archived = match archived {
+            Some(archived) => archived,
+            None => {
+                let ids: HashSet<String> = archive::read_collection(&self.archive_dir(), &document.collection)?
+                    .into_iter()
+                    .map(|archived| archived.id)
+                    .collect();
+                let archived = ids.contains(&document.id);
+                self.archived_ids.write().insert(document.collection.clone(), ids);
+                archived
+            }
+        };
+        if !archived {
+            return Ok(());
+        }
+        SystemCatalog::set_in(batch, &superseded_key(&document.collection, &document.id), NVValue::Bool(true))
+    }
+
+    /// Delete the events of a time-series collection past its retention
+    /// period, without delete hooks or reference actions, returning how
+    /// many were deleted
+    fn enforce_retention(&self, batch: &mut WriteBatch, collection: &str) -> NVResult<usize> {
+        let expired = match self.time_series.read().get(collection) {
+            Some(series) => series.expired(Utc::now().timestamp_millis()),
+            None => return Ok(0),
+        };
+        for id in &expired {
+            self.remove_document(batch, id)?;
+        }
+        Ok(expired.len())
+    }
+
+    /// Evict the oldest documents of a capped collection until it's back
+    /// within its cap
+    ///
+    /// Like archiving, eviction runs no delete hooks or reference actions.
+    fn enforce_cap(&self, batch: &mut WriteBatch, collection: &str) -> NVResult<()> {
+        let evicted = match self.capped.read().get(collection) {
+            Some(tracked) => tracked.overflow(|id| batch.stored_size(id).unwrap_or(0)),
+            None => return Ok(()),
+        };
+        for id in evicted {
+            self.remove_document(batch, &id)?;
+        }
+        Ok(())
+    }
+
+    /// A document with its CRDT clock updated, if its collection has one
+    fn stamp_crdt<'d>(&self, previous: Option<&NVDocument>, document: &'d NVDocument) -> Cow<'d, NVDocument> {
+        if !self.crdt_collections.read().contains(&document.collection) {
+            return Cow::Borrowed(document);
+        }
+        let replica = self.replica.read().clone().unwrap_or_default();
+        let mut stamped = document.clone();
+        crdt::stamp(previous, &mut stamped, &replica, Utc::now().timestamp_millis() as f64);
+        Cow::Owned(stamped)
+    }
+
+    /// Delete a document in a batch
+    ///
+    /// Its attachments are removed once the batch is written.
+    fn remove_document(&self, batch: &mut WriteBatch, id: &str) -> NVResult<()> {
+        let previous = batch.read(id)?;
+        ensure_not_audit(&previous.collection)?;
+        batch.delete(id)?;
+        self.detached.lock().push(id.to_string());
+        self.record_change(Some(&previous), None);
+        self.append_audit(batch, Some(&previous), None)
+    }
+
+    /// Run `apply` in a write batch, then remove the attachments of the
+    /// documents it deleted
+    fn write_batch<R>(&self, apply: impl FnOnce(&mut WriteBatch) -> R) -> NVResult<R> {
+        let result = self.storage.write_batch(apply)?;
+        self.remove_detached_attachments()?;
+        Ok(result)
+    }
+
+    /// Like `write_batch`, undoing every write if `apply` fails
+    fn write_batch_atomic<R>(
+        &self,
+        apply: impl FnOnce(&mut WriteBatch) -> NVResult<R>,
+        revert: impl FnMut(Option<&NVDocument>, Option<&NVDocument>),
+    ) -> NVResult<R> {
+        let result = self.storage.write_batch_atomic(apply, revert);
+        self.remove_detached_attachments()?;
+        result
+    }
+
+    /// Remove the attachments of documents deleted by earlier batches
+    ///
+    /// Documents live again, because their batch was undone or a new one
+    /// took the ID since, keep theirs. Any left behind by a crash are
+    /// removed by `compact`.
+    fn remove_detached_attachments(&self) -> NVResult<()> {
+        let detached = std::mem::take(&mut *self.detached.lock());
+        if detached.is_empty() {
+            return Ok(());
+        }
+        let _writes = self.attachment_writes.lock();
+        for id in detached {
+            if !self.storage.contains(&id) {
+                attachments::remove_all(&self.attachments_dir(), &id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record a document write in the audit trail, if enabled
+    fn append_audit(
+        &self,
+        batch: &mut WriteBatch,
+        previous: Option<&NVDocument>,
+        current: Option<&NVDocument>,
+    ) -> NVResult<()> {
+        if !self.audit.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let actor = self.actor.read().clone();
+        let entry = audit::entry(self.ulids.next(), actor.as_deref(), previous, current);
+        batch.insert(&entry)?;
+        self.record_change(None, Some(&entry));
+        Ok(())
+    }
+
+    /// Keep derived state in step with a document write
+    ///
+    /// Called while the storage locks are held, so changes are observed in
+    /// the order they were written.
+    fn record_change(&self, previous: Option<&NVDocument>, current: Option<&NVDocument>) {
+        let mut indexes = self.indexes.write();
+        for index in indexes.values_mut() {
+            if let Some(previous) = previous {
+                index.remove(previous, &self.query_processor);
+            }
+            if let Some(current) = current {
+                index.add(current, &self.query_processor);
+            }
+        }
+
+        let mut texts = self.texts.write();
+        for index in texts.values_mut() {
+            match (previous, current) {
+                (_, Some(current)) => index.add(current),
+                (Some(previous), None) => index.remove(&previous.id),
+                (None, None) => {}
+            }
+        }
+
+        let mut completions = self.completions.write();
+        for index in completions.values_mut() {
+            match (previous, current) {
+                (_, Some(current)) => index.add(current),
+                (Some(previous), None) => index.remove(&previous.id),
+                (None, None) => {}
+            }
+        }
+
+        let mut capped = self.capped.write();
+        if let Some(previous) = previous {
+            if let Some(tracked) = capped.get_mut(&previous.collection) {
+                tracked.remove(&previous.id);
+            }
+        }
+        if let Some(current) = current {
+            if let Some(tracked) = capped.get_mut(&current.collection) {
+                tracked.add(current);
+            }
+        }
+
+        let mut time_series = self.time_series.write();
+        if let Some(previous) = previous {
+            if let Some(series) = time_series.get_mut(&previous.collection) {
+                series.remove(&previous.id);
+            }
+        }
+        if let Some(current) = current {
+            if let Some(series) = time_series.get_mut(&current.collection) {
+                series.add(current);
+            }
+        }
+
+        if let Some(previous) = previous {
+            if self.config.cache_collections.contains(&previous.collection) {
+                self.cache_order.write().remove(&previous.id);
+            }
+        }
+        if let Some(current) = current {
+            if self.config.cache_collections.contains(&current.collection) {
+                self.cache_order.write().add(current);
+            }
+        }
+
+        let mut vectors = self.vectors.write();
+        for index in vectors.values_mut() {
+            match (previous, current) {
+                (_, Some(current)) => index.add(current),
+                (Some(previous), None) => index.remove(&previous.id),
+                (None, None) => {}
+            }
+        }
+
+        let mut aggregates = self.aggregates.write();
+        for aggregate in aggregates.values_mut() {
+            if let Some(previous) = previous {
+                aggregate.remove(previous);
+            }
+            if let Some(current) = current {
+                aggregate.add(current);
+            }
+        }
+    }
+
+    /// Run before-create hooks and re-check the fields they may have changed
+    fn prepare_create(&self, mut document: NVDocument) -> NVResult<NVDocument> {
+        self.apply_defaults(&document.collection, &mut document.data)?;
+        self.hooks.read().before_create(&mut document)?;
+        validation::validate_fields(&document.data)?;
+        Ok(document)
+    }
+
+    /// Apply one prepared bulk write operation
+    ///
+    /// Returns the document ID and, for updates, the previous and new
+    /// versions so after-update hooks can run once the batch is synced.
+    fn apply_write(
+        &self,
+        batch: &mut WriteBatch,
+        hooks: &HookRegistry,
+        op: WriteOp,
+    ) -> NVResult<(String, Option<(NVDocument, NVDocument)>)> {
+        match op {
+            WriteOp::Insert { id, collection, data } => {
+                let id = id.unwrap_or_default();
+                let mut document = NVDocument::new(id.clone(), collection, data);
+                hooks.before_create(&mut document)?;
+                validation::validate_fields(&document.data)?;
+                self.insert_document(batch, &document)?;
+                Ok((id, None))
+            }
+            WriteOp::Update { id, updates } => {
+                let mut document = batch.read(&id)?;
+                let previous = document.clone();
+                for update in updates {
+                    document.apply(update);
+                }
+                self.put_document(batch, Some(&previous), &document)?;
+                Ok((id, Some((previous, document))))
+            }
+            WriteOp::Delete { id } => {
+                let document = batch.read(&id)?;
+                let plan = self.check_delete(batch, hooks, &document)?;
+                self.apply_delete_plan(batch, &plan)?;
+                self.remove_document(batch, &id)?;
+                Ok((id, None))
+            }
+            WriteOp::Upsert { id, collection, data } => {
+                if batch.contains(&id) {
+                    let mut document = batch.read(&id)?;
+                    let previous = document.clone();
+                    document.collection = collection;
+                    document.data = data;
+                    document.updated_at = Utc::now();
+                    self.put_document(batch, Some(&previous), &document)?;
+                    Ok((id, Some((previous, document))))
+                } else {
+                    let mut document = NVDocument::new(id.clone(), collection, data);
+                    hooks.before_create(&mut document)?;
+                    validation::validate_fields(&document.data)?;
+                    self.insert_document(batch, &document)?;
+                    Ok((id, None))
+                }
+            }
+        }
+    }
+
+    /// Reserve the next number of the sequence default of `collection.field`
+    fn next_sequence(&self, field: &str) -> NVResult<u64> {
+        self.next_number(&format!("{}{}", keys::SEQUENCE_PREFIX, field))
+    }
+
+    /// Reserve the next auto-increment document ID
+    ///
+    /// IDs are unique across collections, so every collection draws from
+    /// one counter. Databases that kept a counter per collection start it
+    /// past the highest of those.
+    fn next_auto_id(&self) -> NVResult<u64> {
+        self.next_number(keys::ID_SEQUENCE)
+    }
+
+    /// Reserve the next number of the counter stored under `key`
+    ///
+    /// The counter is persisted before the number is handed out, so a crash
+    /// can leave gaps but never reuses a number.
+    fn next_number(&self, key: &str) -> NVResult<u64> {
+        let mut sequences = self.sequences.lock();
+
+        let current = match sequences.get(key) {
+            Some(current) => *current,
+            None => match self.system.get(key)? {
+                Some(NVValue::Number(n)) => n as u64,
+                _ if key == keys::ID_SEQUENCE => self
+                    .system
+                    .list(keys::SEQUENCE_PREFIX)?
+                    .into_iter()
+                    .filter_map(|(_, value)| value.as_f64())
+                    .fold(0, |highest, n| highest.max(n as u64)),
+                _ => 0,
+            },
+        };
+
+        let next = current + 1;
+        self.system.set(key, NVValue::Number(next as f64))?;
+        sequences.insert(key.to_string(), next);
+        Ok(next)
+    }
+
+    /// Get a user-defined metadata value
+    pub fn get_metadata(&self, key: &str) -> NVResult<Option<NVValue>> {
+        self.ensure_initialized()?;
+        self.system.get(&format!("{}{}", keys::USER_PREFIX, key))
+    }
+
+    /// Set a user-defined metadata value
+    pub fn set_metadata(&self, key: &str, value: NVValue) -> NVResult<()> {
+        self.ensure_initialized()?;
+        self.system.set(&format!("{}{}", keys::USER_PREFIX, key), value)
+    }
+
+    /// Remove a user-defined metadata value, returning whether it existed
+    pub fn remove_metadata(&self, key: &str) -> NVResult<bool> {
+        self.ensure_initialized()?;
+        self.system.remove(&format!("{}{}", keys::USER_PREFIX, key))
+    }
+
+    /// All user-defined metadata, sorted by key
+    pub fn list_metadata(&self) -> NVResult<Vec<(String, NVValue)>> {
+        self.ensure_initialized()?;
+        Ok(self
+            .system
+            .list(keys::USER_PREFIX)?
+            .into_iter()
+            .map(|(key, value)| (key[keys::USER_PREFIX.len()..].to_string(), value))
+            .collect())
+    }
+
+    /// Store a file from `contents` as the attachment `name` of a document,
+    /// replacing any attachment with that name
+    ///
+    /// Attachments live in chunked files beside the data file rather than
+    /// in the document, and are streamed in and out a chunk at a time.
+    /// Chunks are stored by content, so identical files attached to several
+    /// documents take the space of one. Attachments are removed with their
+    /// document however it's deleted or archived, and included in backups;
+    /// the space of removed ones is reclaimed by `compact`.
+    pub fn put_attachment(&self, document_id: &str, name: &str, mut contents: impl Read) -> NVResult<AttachmentInfo> {
+        self.ensure_initialized()?;
+        if self.config.read_only {
+            return Err(NeuralVaultError::ReadOnly);
+        }
+        attachments::validate_name(name)?;
+
+        // Checked under the lock, so a delete can't remove the document's
+        // attachments in between and leave this one behind
+        let _writes = self.attachment_writes.lock();
+        if !self.storage.contains(document_id) {
+            return Err(NeuralVaultError::DocumentNotFound(document_id.to_string()));
+        }
+        attachments::put(&self.attachments_dir(), document_id, name, &mut contents)
+    }
+
+    /// Open a document's attachment for streaming
+    pub fn get_attachment(&self, document_id: &str, name: &str) -> NVResult<AttachmentReader> {
+        self.ensure_initialized()?;
+        attachments::open(&self.attachments_dir(), document_id, name)?.ok_or_else(|| {
+            NeuralVaultError::DocumentNotFound(format!("Attachment '{}' of {}", name, document_id))
+        })
+    }
+
+    /// Remove a document's attachment, returning whether it existed
+    pub fn delete_attachment(&self, document_id: &str, name: &str) -> NVResult<bool> {
+        self.ensure_initialized()?;
+        if self.config.read_only {
+            return Err(NeuralVaultError::ReadOnly);
+        }
+        let _writes = self.attachment_writes.lock();
+        attachments::remove(&self.attachments_dir(), document_id, name)
+    }
+
+    /// A document's attachments, sorted by name
+    pub fn attachments(&self, document_id: &str) -> NVResult<Vec<AttachmentInfo>> {
+        self.ensure_initialized()?;
+        attachments::list(&self.attachments_dir(), document_id)
+    }
+
+    /// Store `value` under `key` in the key-value store, replacing any
+    /// previous value
+    ///
+    /// Entries live in the reserved `_nv_kv` collection, apart from
+    /// documents, and aren't subject to hooks or middleware.
+    pub fn kv_set(&self, key: &str, value: NVValue) -> NVResult<()> {
+        self.ensure_initialized()?;
+        self.make_room()?;
+        kv::validate_key(key)?;
+
+        let mut entry = kv::entry(key, value);
+        self.write_batch(|batch| -> NVResult<()> {
+            if batch.contains(&entry.id) {
+                let previous = batch.read(&entry.id)?;
+                entry.created_at = previous.created_at;
+                self.put_document(batch, Some(&previous), &entry)
+            } else {
+                self.insert_document(batch, &entry)
+            }
+        })?
+    }
+
+    /// The value stored under `key`, if any
+    pub fn kv_get(&self, key: &str) -> NVResult<Option<NVValue>> {
+        self.ensure_initialized()?;
+        Ok(self.read_live(&kv::entry_id(key))?.and_then(|document| kv::key_value(&document).map(|(_, value)| value)))
+    }
+
+    /// Remove `key` from the key-value store, returning whether it was set
+    pub fn kv_delete(&self, key: &str) -> NVResult<bool> {
+        self.ensure_initialized()?;
+        let id = kv::entry_id(key);
+        self.write_batch(|batch| -> NVResult<bool> {
+            if !batch.contains(&id) {
+                return Ok(false);
+            }
+            self.remove_document(batch, &id)?;
+            Ok(true)
+        })?
+    }
+
+    /// Keys in the key-value store starting with `prefix`, sorted
+    pub fn kv_keys(&self, prefix: &str) -> NVResult<Vec<String>> {
+        self.ensure_initialized()?;
+        let mut keys: Vec<String> = self
+            .storage
+            .scan_collection(KV_COLLECTION)?
+            .iter()
+            .filter_map(kv::key_value)
+            .map(|(key, _)| key)
+            .filter(|key| key.starts_with(prefix))
             .collect();
-        
-        collections.sort();
-        collections.dedup();
-        
-        Ok(collections)
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Format version the database was created with, or last upgraded to
+    pub fn format_version(&self) -> NVResult<Option<u32>> {
+        self.ensure_initialized()?;
+        self.system.format_version()
+    }
+
+    /// When the database was created
+    pub fn created_at(&self) -> NVResult<Option<DateTime<Utc>>> {
+        self.ensure_initialized()?;
+        self.system.created_at()
+    }
+
+    /// Report where the database's disk space goes, overall and per
+    /// collection, to decide when compaction is worthwhile
+    pub fn disk_usage(&self) -> NVResult<DiskUsage> {
+        self.ensure_initialized()?;
+        let mut usage = self.storage.disk_usage()?;
+        usage.archive_bytes = archive::size(&self.archive_dir())?;
+        usage.attachment_bytes = attachments::size(&self.attachments_dir())?;
+        usage.total_bytes += usage.archive_bytes + usage.attachment_bytes;
+        Ok(usage)
+    }
+
+    /// Encrypt backups written from now on, including scheduled ones, with
+    /// `key`, or stop encrypting them with `None`
+    ///
+    /// The key is independent of the database's own keys, so a backup stays
+    /// safe even if they're compromised. Passphrases are stretched with the
+    /// configured KDF settings. The key isn't stored; restoring needs it.
+    pub fn set_backup_key(&self, key: Option<BackupKey>) {
+        *self.backup_key.write() = key;
+    }
+
+    /// Write a full backup into a new directory under `destination`
+    ///
+    /// Unless encrypted, the backup is a complete database directory that
+    /// can be opened in place of the original. Writes wait while the files
+    /// are copied. The returned sequence number is where the next
+    /// incremental backup starts.
+    pub fn backup(&self, destination: &str) -> NVResult<BackupInfo> {
+        self.ensure_initialized()?;
+        // Compaction renumbers the oplog, so it waits until the copy is done
+        let oplog = self.oplog.read();
+        let key = self.backup_key.read().clone();
+        let info = backup::create(
+            &self.storage,
+            &self.archive_dir(),
+            &self.attachments_dir(),
+            Path::new(destination),
+            oplog.base,
+            key.as_ref().map(|key| (key, self.kdf_params())),
+            false,
+        )?;
+        *self.last_backup.lock() = Some(info.clone());
+        Ok(info)
+    }
+
+    /// Write the changes made since `sequence` to a new incremental backup
+    /// file under `destination`
+    ///
+    /// `sequence` is the one returned by the previous full or incremental
+    /// backup. Compaction starts a new oplog, so a sequence from before the
+    /// latest compaction fails and a new full backup is needed.
+    ///
+    /// Records are stored as they are on disk, so encrypted fields stay
+    /// encrypted. Every archive segment and attachment file is listed;
+    /// when `sequence` is that of the latest backup taken through this
+    /// handle, the ones it holds that can't have changed are left out.
+    pub fn backup_incremental(&self, destination: &str, sequence: u64) -> NVResult<BackupInfo> {
+        self.ensure_initialized()?;
+        let oplog = self.oplog.read();
+        let mut offset = match sequence.checked_sub(oplog.base) {
+            Some(offset) if offset > 0 || oplog.base == 0 => offset,
+            _ => {
+                return Err(NeuralVaultError::StorageError(format!(
+                    "Sequence {} predates the latest compaction; take a full backup",
+                    sequence
+                )))
+            }
+        };
+
+        let mut records = Vec::new();
+        loop {
+            let (page, next) = self.storage.sealed_records_since(offset, CHANGES_PAGE_SIZE)?;
+            for (_, record) in page {
+                if !is_local_entry(RecordView::parse(&record)?.id()) {
+                    records.push(record);
+                }
+            }
+            if next == offset {
+                break;
+            }
+            offset = next;
+        }
+
+        let previous = self.last_backup.lock().clone().filter(|info| info.sequence == sequence);
+        let files = backup::incremental_files(
+            &self.archive_dir(),
+            &self.attachments_dir(),
+            previous.as_ref().map(|info| &info.files),
+        )?;
+        let key = self.backup_key.read().clone();
+        let info = backup::write_incremental(
+            Path::new(destination),
+            sequence,
+            oplog.base + offset,
+            records,
+            files,
+            key.as_ref(),
+            self.kdf_params(),
+        )?;
+        *self.last_backup.lock() = Some(info.clone());
+        Ok(info)
+    }
+
+    /// Restore a full backup and then a chain of incremental backups, in
+    /// order, into a new database at `config.path`
+    ///
+    /// Each incremental must start at or before the sequence the previous
+    /// backup covers up to, so no changes are missed; replaying changes
+    /// twice is harmless. `key` decrypts encrypted backups.
+    pub fn restore(
+        config: DatabaseConfig,
+        full: &str,
+        incrementals: &[String],
+        key: Option<&BackupKey>,
+    ) -> NVResult<Self> {
+        let target = PathBuf::from(&config.path);
+        backup::restore_files(Path::new(full), &target, key)?;
+        let mut sequence = backup::sequence(Path::new(full))?;
+
+        let db = Self::new(config.clone())?;
+        for path in incrementals {
+            let incremental = backup::read_incremental(Path::new(path), key)?;
+            if incremental.from > sequence {
+                return Err(NeuralVaultError::StorageError(format!(
+                    "Incremental backup {} starts at sequence {}, after the {} restored so far",
+                    path, incremental.from, sequence
+                )));
+            }
+            if let Some(files) = &incremental.files {
+                backup::restore_incremental_files(&target, files)?;
+            }
+            db.storage.write_batch(|batch| -> NVResult<()> {
+                for document in &incremental.documents {
+                    restore_change(batch, &document.id, document.deleted, |batch| batch.put(document))?;
+                }
+                for record in &incremental.records {
+                    let view = RecordView::parse(record)?;
+                    restore_change(batch, view.id(), view.deleted(), |batch| batch.put_record(record))?;
+                }
+                Ok(())
+            })??;
+            sequence = sequence.max(incremental.to);
+        }
+        drop(db);
+
+        // Reopen so catalog changes (indexes, views, ...) take effect
+        Self::new(config)
+    }
+
+    /// Make every write durable
+    ///
+    /// Only needed with `flush_interval_ms` set, when writes return before
+    /// they're synced.
+    pub fn flush(&self) -> NVResult<()> {
+        self.ensure_initialized()?;
+        self.storage.flush()
+    }
+
+    /// Checkpoint the index so the next open only replays newer writes
+    pub fn checkpoint(&self) -> NVResult<()> {
+        self.ensure_initialized()?;
+        self.storage.checkpoint()?;
+        self.save_vector_indexes()
+    }
+
+    /// Write every vector index to disk with the data file position it
+    /// covers, so the next open only replays newer writes
+    fn save_vector_indexes(&self) -> NVResult<()> {
+        if self.config.read_only || self.vectors.read().is_empty() {
+            return Ok(());
+        }
+        // Oplog before storage locks, the order compaction takes them in
+        let oplog = self.oplog.read();
+        self.write_batch(|batch| -> NVResult<()> {
+            let data_len = batch.data_len()?;
+            for index in self.vectors.read().values() {
+                let path = vector_index_path(&self.config.path, &index.definition().name);
+                vector::save(&path, &oplog.id, data_len, index)?;
+            }
+            Ok(())
+        })?
     }
 
     /// Get database statistics
@@ -155,16 +4363,39 @@ impl NeuralVault {
         self.ensure_initialized()?;
         
         let storage_stats = self.storage.statistics();
-        let collections = self.collections()?;
+        let mut total_documents = 0;
+        let mut collections = Vec::new();
+        for document in self.storage.scan_all()? {
+            if [SYSTEM_COLLECTION, AUDIT_COLLECTION, KV_COLLECTION].contains(&document.collection.as_str()) {
+                continue;
+            }
+            total_documents += 1;
+            collections.push(document.collection);
+        }
+        collections.sort();
+        collections.dedup();
 
         Ok(DatabaseStats {
-            total_documents: storage_stats.document_count,
+            total_documents,
             total_collections: collections.len(),
             storage_size_bytes: storage_stats.file_size_bytes,
             collections,
         })
     }
 
+    /// Whether the collection's bloom filters prove the query can't match
+    fn ruled_out_by_filters(&self, query: &NVQuery) -> bool {
+        self.query_processor
+            .required_conditions(query)
+            .into_iter()
+            .filter(|condition| matches!(condition.operator, QueryOperator::Equals))
+            .any(|condition| {
+                !self
+                    .storage
+                    .may_contain_value(&query.collection, &condition.field, &condition.value)
+            })
+    }
+
     /// Ensure database is initialized
     fn ensure_initialized(&self) -> NVResult<()> {
         if !self.initialized {
@@ -174,6 +4405,284 @@ impl NeuralVault {
     }
 }
 
+/// Rows per `bulk_write` when importing from SQLite
+#[cfg(feature = "sqlite")]
+const IMPORT_BATCH_SIZE: usize = 1000;
+
+/// Index entries read at a time when walking an index in sort order
+const ORDERED_PAGE: usize = 256;
+
+/// Plaintext sealed under the field key to verify it on unlock
+const FIELD_KEY_CHECK_VALUE: &[u8] = b"neural_vault field key";
+
+/// Catalog key recording that migration `version` was applied
+fn migration_key(version: u32) -> String {
+    format!("{}{}", keys::MIGRATION_PREFIX, version)
+}
+
+/// Catalog key marking the archived copies of `id` in `collection` as
+/// replaced by a live document
+fn superseded_key(collection: &str, id: &str) -> String {
+    format!("{}{}/{}", keys::SUPERSEDED_PREFIX, collection, id)
+}
+
+/// Fields an aggregate view groups by or aggregates over
+fn aggregate_fields(definition: &AggregateDefinition) -> impl Iterator<Item = &String> {
+    definition
+        .group_by
+        .iter()
+        .chain(definition.aggregates.iter().filter_map(|(_, aggregate)| match aggregate {
+            Aggregate::Count => None,
+            Aggregate::Sum(field) | Aggregate::Avg(field) | Aggregate::Min(field) | Aggregate::Max(field) => Some(field),
+        }))
+}
+
+/// A document as an embedded object, with its ID under `id`
+fn embed(document: NVDocument) -> NVValue {
+    let mut object = NVObject::from(document.data);
+    object.insert("id".to_string(), NVValue::String(document.id));
+    NVValue::Object(object)
+}
+
+/// Check that a sequence of writes can all be applied
+///
+/// Tracks which IDs the earlier operations create and delete, so an
+/// insert after a delete of the same ID is fine.
+fn check_conflicts<'o>(batch: &WriteBatch, ops: impl Iterator<Item = &'o WriteOp>) -> NVResult<()> {
+    let mut live: HashMap<&str, bool> = HashMap::new();
+    for op in ops {
+        let (id, exists_after) = match op {
+            WriteOp::Insert { id, .. } => (id.as_deref().unwrap_or_default(), true),
+            WriteOp::Update { id, .. } => (id.as_str(), true),
+            WriteOp::Delete { id } => (id.as_str(), false),
+            WriteOp::Upsert { id, .. } => (id.as_str(), true),
+        };
+        let exists = live.get(id).copied().unwrap_or_else(|| batch.contains(id));
+        match op {
+            WriteOp::Insert { .. } if exists => {
+                return Err(NeuralVaultError::DuplicateId(id.to_string()))
+            }
+            WriteOp::Update { .. } | WriteOp::Delete { .. } if !exists => {
+                return Err(NeuralVaultError::DocumentNotFound(id.to_string()))
+            }
+            _ => {}
+        }
+        live.insert(id, exists_after);
+    }
+    Ok(())
+}
+
+impl Drop for NeuralVault {
+    fn drop(&mut self) {
+        // Best effort: a stale checkpoint is only slower to open
+        let _ = self.save_vector_indexes();
+    }
+}
+
+/// Where a vector index is checkpointed
+fn vector_index_path(database: &str, name: &str) -> PathBuf {
+    Path::new(database).join("vectors").join(format!("{}.nvvec", name))
+}
+
+/// Load a vector index from its checkpoint and replay newer writes, or
+/// rebuild it from the collection if the checkpoint can't be used
+fn open_vector_index(
+    storage: &FileManager,
+    definition: VectorIndexDefinition,
+    path: &Path,
+    log_id: &str,
+) -> NVResult<VectorIndex> {
+    if let Some(checkpoint) = vector::load(path)? {
+        // Offsets from another log (before a compaction) mean nothing here
+        if checkpoint.log_id == log_id && *checkpoint.index.definition() == definition {
+            let mut index = checkpoint.index;
+            let mut offset = checkpoint.data_len;
+            let replayed = loop {
+                match storage.records_since(offset, CHANGES_PAGE_SIZE) {
+                    Ok((records, next)) => {
+                        for (_, document) in &records {
+                            index.add(document);
+                        }
+                        if next == offset {
+                            break true;
+                        }
+                        offset = next;
+                    }
+                    Err(_) => break false,
+                }
+            };
+            if replayed {
+                return Ok(index);
+            }
+        }
+    }
+
+    let mut index = VectorIndex::new(definition);
+    for document in storage.scan_collection(&index.definition().collection)? {
+        index.add(&document);
+    }
+    Ok(index)
+}
+
+/// Identity and numbering of the current oplog
+///
+/// A change's sequence number is `base` plus its offset in the data file.
+/// Each compaction starts a log whose base is past every sequence number of
+/// the log it replaces.
+#[derive(Clone)]
+struct OplogState {
+    id: String,
+    base: u64,
+}
+
+impl OplogState {
+    /// The log started by compacting a data file of `replaced_len` bytes
+    fn successor(&self, replaced_len: u64) -> OplogState {
+        OplogState {
+            id: Uuid::new_v4().to_string(),
+            base: self.base + replaced_len,
+        }
+    }
+
+    /// Store this state in `document` if it is one of its catalog entries
+    fn update_entries(&self, document: &mut NVDocument) {
+        SystemCatalog::update_entry(document, keys::OPLOG_ID, NVValue::String(self.id.clone()));
+        SystemCatalog::update_entry(document, keys::OPLOG_BASE, NVValue::Number(self.base as f64));
+    }
+}
+
+/// The oplog a compaction starts, stored in the catalog as documents are
+/// copied
+///
+/// The successor's base depends on the length of the replaced data file,
+/// which is only final in the catch-up. Documents holding oplog entries are
+/// redone there if the file has grown, so the successor is rebuilt whenever
+/// the length changes. The oplog is only locked for the catch-up.
+struct OplogSuccession<'a> {
+    oplog: &'a RwLock<OplogState>,
+    current: OnceCell<OplogState>,
+    next: RefCell<Option<(u64, OplogState)>>,
+}
+
+impl<'a> OplogSuccession<'a> {
+    fn new(oplog: &'a RwLock<OplogState>) -> Self {
+        Self {
+            oplog,
+            current: OnceCell::new(),
+            next: RefCell::new(None),
+        }
+    }
+
+    /// Store the successor of a `replaced_len` byte log in `document`, if
+    /// it holds oplog entries
+    fn rewrite(&self, document: &mut NVDocument, replaced_len: u64) {
+        // Only compaction replaces the oplog, and it runs one at a time
+        let current = self.current.get_or_init(|| self.oplog.read().clone());
+        let mut next = self.next.borrow_mut();
+        if !matches!(&*next, Some((len, _)) if *len == replaced_len) {
+            *next = Some((replaced_len, current.successor(replaced_len)));
+        }
+        if let Some((_, next)) = &*next {
+            next.update_entries(document);
+        }
+    }
+
+    /// Lock the oplog for the catch-up
+    fn lock(&self) -> RwLockWriteGuard<'a, OplogState> {
+        let oplog = self.oplog.write();
+        self.current.get_or_init(|| oplog.clone());
+        oplog
+    }
+
+    /// Replace the oplog, through the guard `lock` returned
+    fn finish(self, mut oplog: RwLockWriteGuard<'a, OplogState>) {
+        *oplog = match self.next.into_inner() {
+            Some((_, next)) => next,
+            None => oplog.successor(0),
+        };
+    }
+}
+
+/// Whether a document ID is that of catalog state about this database's
+/// own oplog, which is never replicated
+fn is_local_entry(id: &str) -> bool {
+    [keys::OPLOG_ID, keys::OPLOG_BASE, keys::REPLICATION_CURSOR, keys::REPLICA_ID]
+        .iter()
+        .any(|key| id == SystemCatalog::document_id(key))
+}
+
+/// Whether a document ID is that of catalog state a leader never sends:
+/// its own oplog state, and the keys and credentials guarding its data
+fn is_unreplicated(id: &str) -> bool {
+    is_local_entry(id)
+        || [keys::FIELD_KEY_CHECK, keys::WRAPPED_KEY]
+            .iter()
+            .any(|key| id == SystemCatalog::document_id(key))
+        || [keys::ROLE_PREFIX, keys::AUTH_USER_PREFIX, keys::TOKEN_PREFIX]
+            .iter()
+            .any(|prefix| id.starts_with(&SystemCatalog::document_id(prefix)))
+}
+
+/// Replay one change from an incremental backup: a deletion marker deletes
+/// the document if it's there, anything else is written with `put`
+fn restore_change(
+    batch: &mut WriteBatch,
+    id: &str,
+    deleted: bool,
+    put: impl FnOnce(&mut WriteBatch) -> NVResult<()>,
+) -> NVResult<()> {
+    if is_local_entry(id) {
+        return Ok(());
+    }
+    match deleted {
+        true if batch.contains(id) => batch.delete(id),
+        true => Ok(()),
+        false => put(batch),
+    }
+}
+
+/// Check a document written by another database
+///
+/// Like a local write, except for the reserved names the engine itself
+/// writes: key-value entries and CRDT clocks.
+fn validate_synced(document: &NVDocument) -> NVResult<()> {
+    ensure_not_audit(&document.collection)?;
+    if document.collection != KV_COLLECTION {
+        validation::validate_collection_name(&document.collection)?;
+    }
+    document
+        .data
+        .keys()
+        .filter(|field| *field != crdt::CLOCK_FIELD)
+        .try_for_each(|field| validation::validate_data_field(field))
+}
+
+/// Reject writes to audit entries, which are append-only
+fn ensure_not_audit(collection: &str) -> NVResult<()> {
+    if collection == AUDIT_COLLECTION {
+        return Err(NeuralVaultError::ValidationError(
+            "Audit log entries cannot be modified".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Documents affected by deleting a referenced document
+#[derive(Default)]
+struct DeletePlan {
+    /// Documents deleted by cascading references
+    deletes: Vec<NVDocument>,
+    /// (document ID, field) pairs set to null
+    nullify: Vec<(String, String)>,
+}
+
+/// Check the field names targeted by a set of updates
+fn validate_updates(updates: &[UpdateOperation]) -> NVResult<()> {
+    updates
+        .iter()
+        .try_for_each(|update| validation::validate_data_field(&update.field))
+}
+
 /// Database statistics
 #[derive(Debug, Clone)]
 pub struct DatabaseStats {
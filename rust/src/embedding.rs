@@ -0,0 +1,186 @@
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::{NVDocument, NVValue};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Turns text into an embedding vector, e.g. with an on-device model or a
+/// remote API
+pub trait Embedder: Send + Sync {
+    /// Length of every vector `embed` returns
+    fn dimensions(&self) -> usize;
+
+    fn embed(&self, text: &str) -> NVResult<Vec<f32>>;
+}
+
+/// An embedder keeping `target` in step with the text in `source`
+#[derive(Clone)]
+pub struct FieldEmbedder {
+    pub source: String,
+    pub target: String,
+    pub embedder: Arc<dyn Embedder>,
+}
+
+/// Per-collection embedders, applied to every local write
+///
+/// Like hooks, embedders run while storage locks are held, so they must not
+/// call back into the database.
+#[derive(Default)]
+pub struct EmbedderRegistry {
+    embedders: HashMap<String, Vec<FieldEmbedder>>,
+}
+
+impl EmbedderRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register an embedder, replacing any that writes the same field
+    pub fn add(&mut self, collection: &str, embedder: FieldEmbedder) {
+        let embedders = self.embedders.entry(collection.to_string()).or_default();
+        embedders.retain(|existing| existing.target != embedder.target);
+        embedders.push(embedder);
+    }
+
+    /// Remove the embedder writing `target`, returning whether there was one
+    pub fn remove(&mut self, collection: &str, target: &str) -> bool {
+        let Some(embedders) = self.embedders.get_mut(collection) else {
+            return false;
+        };
+        let before = embedders.len();
+        embedders.retain(|existing| existing.target != target);
+        before != embedders.len()
+    }
+
+    /// The embedder writing `target` in a collection
+    pub fn get(&self, collection: &str, target: &str) -> Option<&FieldEmbedder> {
+        self.embedders.get(collection)?.iter().find(|e| e.target == target)
+    }
+
+    /// A document with its embeddings brought up to date
+    ///
+    /// A vector is only computed when the source text is new or has changed
+    /// since `previous`; when the text is removed, so is its vector.
+    pub fn apply<'d>(&self, previous: Option<&NVDocument>, document: &'d NVDocument) -> NVResult<Cow<'d, NVDocument>> {
+        let Some(embedders) = self.embedders.get(&document.collection) else {
+            return Ok(Cow::Borrowed(document));
+        };
+
+        let mut document = Cow::Borrowed(document);
+        for FieldEmbedder { source, target, embedder } in embedders {
+            let previous_text = previous.and_then(|p| p.get(source));
+            match document.get(source) {
+                Some(NVValue::String(text)) => {
+                    let unchanged = previous_text == document.get(source) && document.get(target).is_some();
+                    if unchanged {
+                        continue;
+                    }
+                    let vector = embed(embedder.as_ref(), text)?;
+                    document.to_mut().data.insert(target.clone(), vector);
+                }
+                _ if matches!(previous_text, Some(NVValue::String(_))) && document.get(target).is_some() => {
+                    document.to_mut().data.remove(target);
+                }
+                _ => {}
+            }
+        }
+        Ok(document)
+    }
+}
+
+/// Embed text as an array value, checking the embedder's dimensions
+pub fn embed(embedder: &dyn Embedder, text: &str) -> NVResult<NVValue> {
+    let vector = embedder.embed(text)?;
+    if vector.len() != embedder.dimensions() {
+        return Err(NeuralVaultError::EmbeddingError(format!(
+            "Embedder returned {} dimensions instead of {}",
+            vector.len(),
+            embedder.dimensions()
+        )));
+    }
+    Ok(NVValue::Array(vector.into_iter().map(|x| NVValue::Number(x as f64)).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Counts letters a and b, remembering how often it was called
+    #[derive(Default)]
+    struct LetterCounter(AtomicUsize);
+
+    impl Embedder for LetterCounter {
+        fn dimensions(&self) -> usize {
+            2
+        }
+
+        fn embed(&self, text: &str) -> NVResult<Vec<f32>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![text.matches('a').count() as f32, text.matches('b').count() as f32])
+        }
+    }
+
+    fn note(text: Option<&str>) -> NVDocument {
+        let mut data = HashMap::new();
+        if let Some(text) = text {
+            data.insert("text".to_string(), NVValue::String(text.to_string()));
+        }
+        NVDocument::new("n1".to_string(), "notes".to_string(), data)
+    }
+
+    #[test]
+    fn test_embeddings_follow_source_text() {
+        let counter = Arc::new(LetterCounter::default());
+        let mut registry = EmbedderRegistry::new();
+        registry.add(
+            "notes",
+            FieldEmbedder {
+                source: "text".to_string(),
+                target: "embedding".to_string(),
+                embedder: counter.clone(),
+            },
+        );
+        let vector = |a: f64, b: f64| NVValue::Array(vec![NVValue::Number(a), NVValue::Number(b)]);
+
+        let created = registry.apply(None, &note(Some("abba"))).unwrap().into_owned();
+        assert_eq!(created.get("embedding"), Some(&vector(2.0, 2.0)));
+
+        // Unchanged text keeps its vector without calling the embedder
+        let mut same = created.clone();
+        same.data.insert("tag".to_string(), NVValue::Bool(true));
+        assert!(matches!(registry.apply(Some(&created), &same).unwrap(), Cow::Borrowed(_)));
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+        let mut edited = created.clone();
+        edited.data.insert("text".to_string(), NVValue::String("aaa".to_string()));
+        let edited = registry.apply(Some(&created), &edited).unwrap().into_owned();
+        assert_eq!(edited.get("embedding"), Some(&vector(3.0, 0.0)));
+
+        let mut cleared = edited.clone();
+        cleared.data.remove("text");
+        assert_eq!(registry.apply(Some(&edited), &cleared).unwrap().get("embedding"), None);
+
+        // Other collections are left alone
+        let mut other = note(Some("ab"));
+        other.collection = "tasks".to_string();
+        assert_eq!(registry.apply(None, &other).unwrap().get("embedding"), None);
+
+        assert!(registry.remove("notes", "embedding"));
+        assert_eq!(registry.apply(None, &note(Some("ab"))).unwrap().get("embedding"), None);
+    }
+
+    #[test]
+    fn test_dimension_mismatch_is_an_error() {
+        struct Broken;
+        impl Embedder for Broken {
+            fn dimensions(&self) -> usize {
+                3
+            }
+            fn embed(&self, _: &str) -> NVResult<Vec<f32>> {
+                Ok(vec![1.0])
+            }
+        }
+        assert!(matches!(embed(&Broken, "text"), Err(NeuralVaultError::EmbeddingError(_))));
+    }
+}
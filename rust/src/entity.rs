@@ -0,0 +1,402 @@
+use crate::database::NeuralVault;
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::{NVDocument, NVQuery, NVValue, WriteOp, ID_FIELD};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::marker::PhantomData;
+
+/// A Rust type stored as the documents of a collection
+///
+/// Usually derived with `#[derive(NVEntity)]` (the `derive` feature):
+///
+/// ```ignore
+/// #[derive(NVEntity)]
+/// #[nv(collection = "users")]
+/// struct User {
+///     #[nv(id)]
+///     id: String,
+///     name: String,
+///     #[nv(rename = "e-mail")]
+///     email: Option<String>,
+///     #[nv(skip)]
+///     cached: Vec<u8>,
+/// }
+/// ```
+///
+/// The `#[nv(id)]` field, a `String` or `Option<String>`, holds the
+/// document ID and isn't stored in the data; other fields are converted
+/// with `NVField`, and skipped ones are filled from `Default` when read.
+pub trait NVEntity: Sized {
+    /// Collection the entities are stored in
+    const COLLECTION: &'static str;
+
+    /// Document ID, if the entity has one yet
+    fn id(&self) -> Option<&str>;
+
+    /// The entity's fields as document data
+    fn to_data(&self) -> HashMap<String, NVValue>;
+
+    /// Read an entity from a document of its collection
+    fn from_document(document: &NVDocument) -> NVResult<Self>;
+}
+
+/// A value that can be stored in a document field
+pub trait NVField: Sized {
+    fn to_value(&self) -> NVValue;
+
+    /// Convert back from a stored value, or `None` if it has another type
+    fn from_value(value: &NVValue) -> Option<Self>;
+
+    /// Value to use when the field is missing, if that's allowed
+    fn missing() -> Option<Self> {
+        None
+    }
+}
+
+/// A type that can hold an entity's document ID
+pub trait NVId {
+    fn as_id(&self) -> Option<&str>;
+    fn from_id(id: String) -> Self;
+}
+
+impl NVId for String {
+    fn as_id(&self) -> Option<&str> {
+        Some(self.as_str()).filter(|id| !id.is_empty())
+    }
+
+    fn from_id(id: String) -> Self {
+        id
+    }
+}
+
+impl NVId for Option<String> {
+    fn as_id(&self) -> Option<&str> {
+        self.as_deref()
+    }
+
+    fn from_id(id: String) -> Self {
+        Some(id)
+    }
+}
+
+/// Read a field of an entity from a document; used by the derive
+pub fn field<T: NVField>(document: &NVDocument, entity: &str, name: &str) -> NVResult<T> {
+    match document.get(name) {
+        Some(value) => T::from_value(value).ok_or_else(|| {
+            NeuralVaultError::SerializationError(format!(
+                "Field '{}' of {} {} has the wrong type",
+                name, entity, document.id
+            ))
+        }),
+        None => T::missing().ok_or_else(|| {
+            NeuralVaultError::SerializationError(format!(
+                "Field '{}' of {} {} is missing",
+                name, entity, document.id
+            ))
+        }),
+    }
+}
+
+/// Document data and optional ID from any `Serialize` struct or map
+///
+/// A string `_id` entry becomes the document ID; a null one is dropped.
+pub fn serialize<T: Serialize + ?Sized>(value: &T) -> NVResult<(Option<String>, HashMap<String, NVValue>)> {
+    let mut data = match NVValue::from(serde_json::to_value(value)?) {
+        NVValue::Object(data) => data,
+        _ => {
+            return Err(NeuralVaultError::SerializationError(
+                "Typed documents must serialize to a map".to_string(),
+            ))
+        }
+    };
+    let id = match data.remove(ID_FIELD) {
+        None | Some(NVValue::Null) => None,
+        Some(NVValue::String(id)) => Some(id),
+        Some(_) => {
+            return Err(NeuralVaultError::SerializationError(format!(
+                "'{}' must be a string",
+                ID_FIELD
+            )))
+        }
+    };
+    Ok((id, data.into_iter().collect()))
+}
+
+/// Read a document's data, with its ID as `_id`, into any `Deserialize` type
+pub fn deserialize<T: DeserializeOwned>(document: &NVDocument) -> NVResult<T> {
+    let mut object: serde_json::Map<String, serde_json::Value> =
+        document.data.iter().map(|(key, value)| (key.clone(), to_json(value))).collect();
+    object.insert(ID_FIELD.to_string(), serde_json::Value::String(document.id.clone()));
+    serde_json::from_value(serde_json::Value::Object(object)).map_err(|e| {
+        NeuralVaultError::SerializationError(format!("Document {} doesn't fit the type: {}", document.id, e))
+    })
+}
+
+/// Like `serde_json::Value::from`, but whole numbers become integers so
+/// they deserialize into integer fields
+fn to_json(value: &NVValue) -> serde_json::Value {
+    match value {
+        NVValue::Number(n) if n.fract() == 0.0 && n.abs() < 9_007_199_254_740_992.0 => serde_json::Value::from(*n as i64),
+        NVValue::Array(items) => serde_json::Value::Array(items.iter().map(to_json).collect()),
+        NVValue::Object(entries) => {
+            serde_json::Value::Object(entries.iter().map(|(key, value)| (key.clone(), to_json(value))).collect())
+        }
+        value => serde_json::Value::from(value.clone()),
+    }
+}
+
+impl NVField for NVValue {
+    fn to_value(&self) -> NVValue {
+        self.clone()
+    }
+
+    fn from_value(value: &NVValue) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+impl NVField for bool {
+    fn to_value(&self) -> NVValue {
+        NVValue::Bool(*self)
+    }
+
+    fn from_value(value: &NVValue) -> Option<Self> {
+        match value {
+            NVValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl NVField for String {
+    fn to_value(&self) -> NVValue {
+        NVValue::String(self.clone())
+    }
+
+    fn from_value(value: &NVValue) -> Option<Self> {
+        match value {
+            NVValue::String(s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl NVField for f64 {
+    fn to_value(&self) -> NVValue {
+        NVValue::Number(*self)
+    }
+
+    fn from_value(value: &NVValue) -> Option<Self> {
+        match value {
+            NVValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+impl NVField for f32 {
+    fn to_value(&self) -> NVValue {
+        NVValue::Number(*self as f64)
+    }
+
+    fn from_value(value: &NVValue) -> Option<Self> {
+        f64::from_value(value).map(|n| n as f32)
+    }
+}
+
+/// Integers are stored as numbers and only read back when whole and in range
+macro_rules! integer_fields {
+    ($($ty:ty),*) => {$(
+        impl NVField for $ty {
+            fn to_value(&self) -> NVValue {
+                NVValue::Number(*self as f64)
+            }
+
+            fn from_value(value: &NVValue) -> Option<Self> {
+                match value {
+                    NVValue::Number(n) if n.fract() == 0.0 && *n >= <$ty>::MIN as f64 && *n <= <$ty>::MAX as f64 => {
+                        Some(*n as $ty)
+                    }
+                    _ => None,
+                }
+            }
+        }
+    )*};
+}
+
+integer_fields!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// Stored as an RFC 3339 string; millisecond numbers are read too
+impl NVField for DateTime<Utc> {
+    fn to_value(&self) -> NVValue {
+        NVValue::String(self.to_rfc3339_opts(SecondsFormat::Millis, true))
+    }
+
+    fn from_value(value: &NVValue) -> Option<Self> {
+        DateTime::from_timestamp_millis(crate::expression::timestamp(value)?)
+    }
+}
+
+/// `None` is stored as null, and a missing field reads as `None`
+impl<T: NVField> NVField for Option<T> {
+    fn to_value(&self) -> NVValue {
+        self.as_ref().map_or(NVValue::Null, T::to_value)
+    }
+
+    fn from_value(value: &NVValue) -> Option<Self> {
+        match value {
+            NVValue::Null => Some(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+
+    fn missing() -> Option<Self> {
+        Some(None)
+    }
+}
+
+impl<T: NVField> NVField for Vec<T> {
+    fn to_value(&self) -> NVValue {
+        NVValue::Array(self.iter().map(T::to_value).collect())
+    }
+
+    fn from_value(value: &NVValue) -> Option<Self> {
+        match value {
+            NVValue::Array(items) => items.iter().map(T::from_value).collect(),
+            _ => None,
+        }
+    }
+}
+
+impl<T: NVField> NVField for HashMap<String, T> {
+    fn to_value(&self) -> NVValue {
+        NVValue::Object(self.iter().map(|(key, value)| (key.clone(), value.to_value())).collect())
+    }
+
+    fn from_value(value: &NVValue) -> Option<Self> {
+        match value {
+            NVValue::Object(entries) => entries
+                .iter()
+                .map(|(key, value)| Some((key.clone(), T::from_value(value)?)))
+                .collect(),
+            _ => None,
+        }
+    }
+}
+
+impl<T: NVField> NVField for BTreeMap<String, T> {
+    fn to_value(&self) -> NVValue {
+        NVValue::Object(self.iter().map(|(key, value)| (key.clone(), value.to_value())).collect())
+    }
+
+    fn from_value(value: &NVValue) -> Option<Self> {
+        match value {
+            NVValue::Object(entries) => entries
+                .iter()
+                .map(|(key, value)| Some((key.clone(), T::from_value(value)?)))
+                .collect(),
+            _ => None,
+        }
+    }
+}
+
+/// Typed access to the collection of an entity type
+///
+/// Created by `NeuralVault::entities`. Entities are read whole, sensitive
+/// fields included, so saving one back never drops them.
+pub struct Entities<'db, T> {
+    db: &'db NeuralVault,
+    entity: PhantomData<fn() -> T>,
+}
+
+impl<'db, T: NVEntity> Entities<'db, T> {
+    pub(crate) fn new(db: &'db NeuralVault) -> Self {
+        Self {
+            db,
+            entity: PhantomData,
+        }
+    }
+
+    /// An empty query over the entities' collection
+    pub fn query(&self) -> NVQuery {
+        NVQuery::new(T::COLLECTION.to_string())
+    }
+
+    /// Store a new entity, returning its ID
+    ///
+    /// Entities without an ID get one from the collection's ID strategy.
+    pub fn insert(&self, entity: &T) -> NVResult<String> {
+        let data = entity.to_data();
+        match entity.id() {
+            Some(id) => self.db.create_with_id(id.to_string(), T::COLLECTION.to_string(), data),
+            None => self.db.create(T::COLLECTION.to_string(), data),
+        }
+    }
+
+    /// Store an entity under its ID, replacing any document there
+    pub fn save(&self, entity: &T) -> NVResult<()> {
+        let id = entity.id().ok_or_else(|| {
+            NeuralVaultError::ValidationError(format!("{} entity has no ID to save under", T::COLLECTION))
+        })?;
+        let op = WriteOp::Upsert {
+            id: id.to_string(),
+            collection: T::COLLECTION.to_string(),
+            data: entity.to_data(),
+        };
+        self.db.bulk_write(vec![op])?.remove(0).map(|_| ())
+    }
+
+    /// Read an entity by ID
+    ///
+    /// Documents of other collections are reported as not found.
+    pub fn get(&self, id: &str) -> NVResult<T> {
+        let document = self.db.find_by_id_unredacted(id)?;
+        if document.collection != T::COLLECTION {
+            return Err(NeuralVaultError::DocumentNotFound(id.to_string()));
+        }
+        T::from_document(&document)
+    }
+
+    /// Entities matching a query; its collection is replaced with the
+    /// entities' own
+    pub fn find(&self, mut query: NVQuery) -> NVResult<Vec<T>> {
+        query.collection = T::COLLECTION.to_string();
+        query.include_sensitive = true;
+        self.db.find(query)?.iter().map(T::from_document).collect()
+    }
+
+    /// Every entity in the collection
+    pub fn all(&self) -> NVResult<Vec<T>> {
+        self.find(self.query())
+    }
+
+    pub fn delete(&self, id: &str) -> NVResult<()> {
+        self.get(id)?;
+        self.db.kill_by_id(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_round_trips() {
+        assert_eq!(u8::from_value(&42u8.to_value()), Some(42));
+        assert_eq!(u8::from_value(&NVValue::Number(256.0)), None);
+        assert_eq!(i64::from_value(&NVValue::Number(1.5)), None);
+        assert_eq!(Option::<String>::from_value(&NVValue::Null), Some(None));
+        assert_eq!(Option::<String>::missing(), Some(None));
+        assert_eq!(String::missing(), None);
+
+        let tags = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(Vec::<String>::from_value(&tags.to_value()), Some(tags));
+        assert_eq!(Vec::<String>::from_value(&NVValue::Array(vec![NVValue::Bool(true)])), None);
+
+        let at = DateTime::from_timestamp_millis(1_700_000_000_123).unwrap();
+        assert_eq!(DateTime::<Utc>::from_value(&at.to_value()), Some(at));
+        assert_eq!(DateTime::<Utc>::from_value(&NVValue::Number(1_700_000_000_123.0)), Some(at));
+    }
+}
@@ -41,6 +41,39 @@ pub enum NeuralVaultError {
 
     #[error("Validation error: {0}")]
     ValidationError(String),
+
+    #[error("Document already exists: {0}")]
+    DuplicateId(String),
+
+    #[error("Database is locked by another process: {0}")]
+    AlreadyLocked(String),
+
+    #[error("Database is open in read-only mode")]
+    ReadOnly,
+
+    #[error("Encryption error: {0}")]
+    EncryptionError(String),
+
+    #[error("Access denied: {0}")]
+    AccessDenied(String),
+
+    #[error("Write conflict: {0}")]
+    WriteConflict(String),
+
+    #[error("Replication error: {0}")]
+    ReplicationError(String),
+
+    #[error("Storage quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    #[error("Cancelled: {0}")]
+    Cancelled(String),
+
+    #[error("Embedding error: {0}")]
+    EmbeddingError(String),
+
+    #[error("Migration {0} failed: {1}")]
+    MigrationFailed(u32, String),
 }
 
 impl From<std::io::Error> for NeuralVaultError {
@@ -0,0 +1,272 @@
+use super::{infer_schema, Column, ColumnType, CREATED_AT_COLUMN, ID_COLUMN, UPDATED_AT_COLUMN};
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::{NVDocument, NVValue};
+use arrow::array::{ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray, TimestampMillisecondArray};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use chrono::{DateTime, Utc};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Rows per record batch (and so per Parquet row group at most)
+pub const BATCH_ROWS: usize = 64 * 1024;
+
+const TIMEZONE: &str = "UTC";
+
+/// File format for `write_collection`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Snappy-compressed Parquet
+    Parquet,
+    /// The Arrow IPC file format (Feather v2)
+    ArrowIpc,
+}
+
+impl ExportFormat {
+    /// File extension for this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::ArrowIpc => "arrow",
+        }
+    }
+}
+
+/// Write `documents` to `path` as one table, returning the inferred columns
+pub fn write_collection(documents: &[NVDocument], path: &Path, format: ExportFormat) -> NVResult<Vec<Column>> {
+    let columns = infer_schema(documents);
+    write_batches(&columns, path, format, documents.chunks(BATCH_ROWS).map(Ok))?;
+    Ok(columns)
+}
+
+/// Write batches of documents to `path` as one table with `columns`,
+/// returning how many rows were written
+///
+/// Batches are read one at a time and each becomes a record batch, so they
+/// should hold at most `BATCH_ROWS` documents. `columns` must fit every
+/// document, as when inferred from all of them.
+pub fn write_batches<D: AsRef<[NVDocument]>>(
+    columns: &[Column],
+    path: &Path,
+    format: ExportFormat,
+    batches: impl Iterator<Item = NVResult<D>>,
+) -> NVResult<usize> {
+    let schema: SchemaRef = Arc::new(Schema::new(columns.iter().map(field).collect::<Vec<_>>()));
+    let file = BufWriter::new(File::create(path)?);
+    let mut rows = 0;
+    let batches = batches.map(|documents| {
+        let documents = documents?;
+        rows += documents.as_ref().len();
+        record_batch(&schema, columns, documents.as_ref())
+    });
+
+    match format {
+        ExportFormat::Parquet => {
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .set_max_row_group_size(BATCH_ROWS)
+                .build();
+            let mut writer = ArrowWriter::try_new(file, schema.clone(), Some(properties)).map_err(export_error)?;
+            for batch in batches {
+                writer.write(&batch?).map_err(export_error)?;
+            }
+            writer.close().map_err(export_error)?;
+        }
+        ExportFormat::ArrowIpc => {
+            let mut writer = FileWriter::try_new(file, &schema).map_err(export_error)?;
+            for batch in batches {
+                writer.write(&batch?).map_err(export_error)?;
+            }
+            writer.finish().map_err(export_error)?;
+        }
+    }
+    Ok(rows)
+}
+
+fn field(column: &Column) -> Field {
+    let data_type = match column.column_type {
+        ColumnType::Bool => DataType::Boolean,
+        ColumnType::Int64 => DataType::Int64,
+        ColumnType::Float64 => DataType::Float64,
+        ColumnType::String | ColumnType::Json => DataType::Utf8,
+        ColumnType::Timestamp => DataType::Timestamp(TimeUnit::Millisecond, Some(TIMEZONE.into())),
+    };
+    Field::new(column.name.as_str(), data_type, column.nullable)
+}
+
+fn record_batch(schema: &SchemaRef, columns: &[Column], documents: &[NVDocument]) -> NVResult<RecordBatch> {
+    let arrays = columns.iter().map(|column| array(column, documents)).collect();
+    RecordBatch::try_new(schema.clone(), arrays).map_err(export_error)
+}
+
+/// Build one column's values for `documents`
+fn array(column: &Column, documents: &[NVDocument]) -> ArrayRef {
+    match column.name.as_str() {
+        ID_COLUMN => return Arc::new(StringArray::from_iter_values(documents.iter().map(|d| d.id.as_str()))),
+        CREATED_AT_COLUMN => return timestamps(documents.iter().map(|d| d.created_at)),
+        UPDATED_AT_COLUMN => return timestamps(documents.iter().map(|d| d.updated_at)),
+        _ => {}
+    }
+
+    let values = documents
+        .iter()
+        .map(|d| d.data.get(&column.name).filter(|value| !matches!(value, NVValue::Null)));
+    match column.column_type {
+        ColumnType::Bool => Arc::new(
+            values
+                .map(|value| match value {
+                    Some(NVValue::Bool(b)) => Some(*b),
+                    _ => None,
+                })
+                .collect::<BooleanArray>(),
+        ),
+        ColumnType::Int64 => Arc::new(
+            values
+                .map(|value| match value {
+                    Some(NVValue::Number(n)) => Some(*n as i64),
+                    _ => None,
+                })
+                .collect::<Int64Array>(),
+        ),
+        ColumnType::Float64 => Arc::new(
+            values
+                .map(|value| match value {
+                    Some(NVValue::Number(n)) => Some(*n),
+                    _ => None,
+                })
+                .collect::<Float64Array>(),
+        ),
+        ColumnType::String => Arc::new(
+            values
+                .map(|value| match value {
+                    Some(NVValue::String(s)) => Some(s.as_str()),
+                    _ => None,
+                })
+                .collect::<StringArray>(),
+        ),
+        ColumnType::Json => Arc::new(
+            values
+                .map(|value| value.map(|value| serde_json::Value::from(value.clone()).to_string()))
+                .collect::<StringArray>(),
+        ),
+        // Only the built-in columns are timestamps
+        ColumnType::Timestamp => Arc::new(TimestampMillisecondArray::new_null(documents.len()).with_timezone(TIMEZONE)),
+    }
+}
+
+fn timestamps(times: impl Iterator<Item = DateTime<Utc>>) -> ArrayRef {
+    let millis: Vec<i64> = times.map(|time| time.timestamp_millis()).collect();
+    Arc::new(TimestampMillisecondArray::from(millis).with_timezone(TIMEZONE))
+}
+
+fn export_error(err: impl std::fmt::Display) -> NeuralVaultError {
+    NeuralVaultError::StorageError(format!("Export failed: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::Array;
+    use arrow::ipc::reader::FileReader;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    fn document(id: &str, fields: Vec<(&str, NVValue)>) -> NVDocument {
+        let data: HashMap<String, NVValue> = fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+        NVDocument::new(id.to_string(), "items".to_string(), data)
+    }
+
+    fn read_back(path: &Path, format: ExportFormat) -> Vec<RecordBatch> {
+        let file = File::open(path).unwrap();
+        match format {
+            ExportFormat::Parquet => ParquetRecordBatchReaderBuilder::try_new(file)
+                .unwrap()
+                .build()
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap(),
+            ExportFormat::ArrowIpc => FileReader::try_new(file, None).unwrap().collect::<Result<_, _>>().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip_keeps_types_and_nulls() {
+        let documents = vec![
+            document("a", vec![
+                ("count", NVValue::Number(1.0)),
+                ("price", NVValue::Number(2.5)),
+                ("name", NVValue::String("one".to_string())),
+                ("active", NVValue::Bool(true)),
+                ("tags", NVValue::Array(vec![NVValue::String("x".to_string())])),
+            ]),
+            document("b", vec![
+                ("count", NVValue::Number(3.0)),
+                ("price", NVValue::Number(4.0)),
+                ("name", NVValue::Null),
+                ("active", NVValue::Bool(false)),
+            ]),
+        ];
+
+        let dir = tempdir().unwrap();
+        for format in [ExportFormat::Parquet, ExportFormat::ArrowIpc] {
+            let path = dir.path().join(format!("items.{}", format.extension()));
+            let columns = write_collection(&documents, &path, format).unwrap();
+            let batches = read_back(&path, format);
+            assert_eq!(batches.len(), 1);
+            let batch = &batches[0];
+            assert_eq!(batch.num_rows(), 2);
+
+            // The file's schema matches the inferred columns
+            let schema = batch.schema();
+            assert_eq!(schema.fields().len(), columns.len());
+            for column in &columns {
+                let field = schema.field_with_name(&column.name).unwrap();
+                assert_eq!(field.data_type(), super::field(column).data_type());
+                assert_eq!(field.is_nullable(), column.nullable);
+            }
+            assert_eq!(
+                schema.field_with_name(CREATED_AT_COLUMN).unwrap().data_type(),
+                &DataType::Timestamp(TimeUnit::Millisecond, Some(TIMEZONE.into()))
+            );
+
+            let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+            let ids = column(ID_COLUMN);
+            let ids = ids.as_any().downcast_ref::<StringArray>().unwrap();
+            assert_eq!((ids.value(0), ids.value(1)), ("a", "b"));
+
+            let created = column(CREATED_AT_COLUMN);
+            let created = created.as_any().downcast_ref::<TimestampMillisecondArray>().unwrap();
+            assert_eq!(created.value(0), documents[0].created_at.timestamp_millis());
+
+            let count = column("count");
+            let count = count.as_any().downcast_ref::<Int64Array>().unwrap();
+            assert_eq!((count.value(0), count.value(1)), (1, 3));
+
+            let price = column("price");
+            let price = price.as_any().downcast_ref::<Float64Array>().unwrap();
+            assert_eq!((price.value(0), price.value(1)), (2.5, 4.0));
+
+            let active = column("active");
+            let active = active.as_any().downcast_ref::<BooleanArray>().unwrap();
+            assert!(active.value(0) && !active.value(1));
+
+            // Null and missing values come back as nulls
+            let name = column("name");
+            let name = name.as_any().downcast_ref::<StringArray>().unwrap();
+            assert_eq!(name.value(0), "one");
+            assert!(name.is_null(1));
+
+            let tags = column("tags");
+            let tags = tags.as_any().downcast_ref::<StringArray>().unwrap();
+            assert_eq!(tags.value(0), r#"["x"]"#);
+            assert!(tags.is_null(1));
+        }
+    }
+}
@@ -0,0 +1,203 @@
+#[cfg(feature = "analytics")]
+pub mod columnar;
+
+#[cfg(feature = "analytics")]
+pub use columnar::{write_batches, write_collection, ExportFormat, BATCH_ROWS};
+
+use crate::models::{NVDocument, NVValue};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Column holding each document's ID
+pub const ID_COLUMN: &str = "_id";
+/// Column holding each document's creation time
+pub const CREATED_AT_COLUMN: &str = "_created_at";
+/// Column holding each document's last update time
+pub const UPDATED_AT_COLUMN: &str = "_updated_at";
+
+const RESERVED_COLUMNS: [&str; 3] = [ID_COLUMN, CREATED_AT_COLUMN, UPDATED_AT_COLUMN];
+
+/// The type a column is exported as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnType {
+    Bool,
+    /// Numbers that are all whole and fit in an `i64`
+    Int64,
+    Float64,
+    String,
+    /// Milliseconds since the epoch, UTC
+    Timestamp,
+    /// Arrays, objects and fields with mixed types, as JSON text
+    Json,
+}
+
+/// One exported column
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Column {
+    pub name: String,
+    pub column_type: ColumnType,
+    /// Whether some document has null or no value for it
+    pub nullable: bool,
+}
+
+/// Result of exporting one collection
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportedCollection {
+    pub collection: String,
+    pub path: String,
+    pub rows: usize,
+    pub columns: Vec<Column>,
+}
+
+/// Infer a flat schema for `documents`
+///
+/// The ID and timestamp columns come first, then one column per top-level
+/// field in name order. A field's type is the narrowest that holds every
+/// value it takes: integers widen to floats, and any other mix (or a
+/// nested value) falls back to JSON text. A field that is only ever null
+/// is exported as a nullable string. Fields named like the ID and timestamp
+/// columns are left out.
+pub fn infer_schema(documents: &[NVDocument]) -> Vec<Column> {
+    let mut inference = SchemaInference::new();
+    inference.add(documents);
+    inference.columns()
+}
+
+/// `infer_schema` over documents seen a batch at a time
+#[derive(Debug, Default)]
+pub struct SchemaInference {
+    /// Each field's type so far and how many documents have a value for it
+    fields: BTreeMap<String, (Option<ColumnType>, usize)>,
+    rows: usize,
+}
+
+impl SchemaInference {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Widen the schema to fit `documents`
+    pub fn add(&mut self, documents: &[NVDocument]) {
+        self.rows += documents.len();
+        for document in documents {
+            for (name, value) in &document.data {
+                if RESERVED_COLUMNS.contains(&name.as_str()) {
+                    continue;
+                }
+                let (column_type, present) = self.fields.entry(name.clone()).or_insert((None, 0));
+                if let Some(value_type) = value_type(value) {
+                    *column_type = Some(match *column_type {
+                        None => value_type,
+                        Some(existing) => widen(existing, value_type),
+                    });
+                    *present += 1;
+                }
+            }
+        }
+    }
+
+    /// The schema fitting every document added
+    pub fn columns(self) -> Vec<Column> {
+        let mut columns = vec![
+            Column { name: ID_COLUMN.to_string(), column_type: ColumnType::String, nullable: false },
+            Column { name: CREATED_AT_COLUMN.to_string(), column_type: ColumnType::Timestamp, nullable: false },
+            Column { name: UPDATED_AT_COLUMN.to_string(), column_type: ColumnType::Timestamp, nullable: false },
+        ];
+        columns.extend(self.fields.into_iter().map(|(name, (column_type, present))| Column {
+            name,
+            column_type: column_type.unwrap_or(ColumnType::String),
+            nullable: present < self.rows,
+        }));
+        columns
+    }
+}
+
+/// The column type a single value needs, or `None` for null
+fn value_type(value: &NVValue) -> Option<ColumnType> {
+    match value {
+        NVValue::Null => None,
+        NVValue::Bool(_) => Some(ColumnType::Bool),
+        NVValue::Number(n) if is_int64(*n) => Some(ColumnType::Int64),
+        NVValue::Number(_) => Some(ColumnType::Float64),
+        NVValue::String(_) => Some(ColumnType::String),
+        NVValue::Array(_) | NVValue::Object(_) => Some(ColumnType::Json),
+    }
+}
+
+/// The narrowest type holding values of both `a` and `b`
+fn widen(a: ColumnType, b: ColumnType) -> ColumnType {
+    match (a, b) {
+        (a, b) if a == b => a,
+        (ColumnType::Int64, ColumnType::Float64) | (ColumnType::Float64, ColumnType::Int64) => ColumnType::Float64,
+        _ => ColumnType::Json,
+    }
+}
+
+/// Whether `n` is a whole number that converts to an `i64` exactly
+pub(crate) fn is_int64(n: f64) -> bool {
+    n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn document(id: &str, fields: Vec<(&str, NVValue)>) -> NVDocument {
+        let data: HashMap<String, NVValue> = fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+        NVDocument::new(id.to_string(), "items".to_string(), data)
+    }
+
+    #[test]
+    fn test_infer_schema_widens_and_marks_nullable() {
+        let documents = vec![
+            document("a", vec![
+                ("count", NVValue::Number(1.0)),
+                ("price", NVValue::Number(2.0)),
+                ("name", NVValue::String("one".to_string())),
+                ("tags", NVValue::Array(vec![])),
+                ("mixed", NVValue::Bool(true)),
+                ("empty", NVValue::Null),
+                (ID_COLUMN, NVValue::String("shadowed".to_string())),
+            ]),
+            document("b", vec![
+                ("count", NVValue::Number(3.0)),
+                ("price", NVValue::Number(2.5)),
+                ("mixed", NVValue::String("yes".to_string())),
+            ]),
+        ];
+
+        let columns = infer_schema(&documents);
+        let types: Vec<(&str, ColumnType, bool)> = columns
+            .iter()
+            .map(|column| (column.name.as_str(), column.column_type, column.nullable))
+            .collect();
+        assert_eq!(types, vec![
+            (ID_COLUMN, ColumnType::String, false),
+            (CREATED_AT_COLUMN, ColumnType::Timestamp, false),
+            (UPDATED_AT_COLUMN, ColumnType::Timestamp, false),
+            ("count", ColumnType::Int64, false),
+            ("empty", ColumnType::String, true),
+            ("mixed", ColumnType::Json, false),
+            ("name", ColumnType::String, true),
+            ("price", ColumnType::Float64, false),
+            ("tags", ColumnType::Json, true),
+        ]);
+    }
+
+    #[test]
+    fn test_inference_across_batches_matches_one_pass() {
+        let documents = vec![
+            document("a", vec![("count", NVValue::Number(1.0)), ("name", NVValue::String("one".to_string()))]),
+            document("b", vec![("count", NVValue::Number(1.5))]),
+            document("c", vec![("count", NVValue::Number(2.0)), ("name", NVValue::String("three".to_string()))]),
+        ];
+
+        let mut inference = SchemaInference::new();
+        for batch in documents.chunks(2) {
+            inference.add(batch);
+        }
+        assert_eq!(inference.columns(), infer_schema(&documents));
+    }
+}
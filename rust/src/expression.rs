@@ -0,0 +1,186 @@
+use crate::models::{NVQuery, NVValue, QueryCondition};
+use crate::query::QueryProcessor;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A value computed from a document's fields
+///
+/// Evaluation never fails: an operand of the wrong type (or a division by
+/// zero, or a date that doesn't parse) makes the result null.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Expression {
+    /// The value of a field; null if missing
+    Field(String),
+    Literal(NVValue),
+    /// Strings joined together; numbers and booleans are written out, and
+    /// any null part makes the result null
+    Concat(Vec<Expression>),
+    Add(Vec<Expression>),
+    Subtract(Box<Expression>, Box<Expression>),
+    Multiply(Vec<Expression>),
+    Divide(Box<Expression>, Box<Expression>),
+    /// Time from `start` to `end` in whole `unit`s, rounded toward zero.
+    /// Dates are RFC 3339 strings or milliseconds since the epoch.
+    DateDiff {
+        start: Box<Expression>,
+        end: Box<Expression>,
+        unit: DateUnit,
+    },
+    /// `then` where the condition holds, `otherwise` elsewhere
+    If {
+        condition: QueryCondition,
+        then: Box<Expression>,
+        otherwise: Box<Expression>,
+    },
+}
+
+/// Unit of a `DateDiff`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DateUnit {
+    Milliseconds,
+    Seconds,
+    Minutes,
+    Hours,
+    Days,
+}
+
+impl DateUnit {
+    fn millis(self) -> i64 {
+        match self {
+            DateUnit::Milliseconds => 1,
+            DateUnit::Seconds => 1_000,
+            DateUnit::Minutes => 60_000,
+            DateUnit::Hours => 3_600_000,
+            DateUnit::Days => 86_400_000,
+        }
+    }
+}
+
+impl Expression {
+    /// Evaluate against a row of field values
+    pub fn evaluate(&self, row: &HashMap<String, NVValue>, processor: &QueryProcessor) -> NVValue {
+        let number = |expression: &Expression| match expression.evaluate(row, processor) {
+            NVValue::Number(n) => Some(n),
+            _ => None,
+        };
+
+        match self {
+            Expression::Field(field) => row.get(field).cloned().unwrap_or(NVValue::Null),
+            Expression::Literal(value) => value.clone(),
+            Expression::Concat(parts) => {
+                let mut joined = String::new();
+                for part in parts {
+                    match part.evaluate(row, processor) {
+                        NVValue::String(s) => joined.push_str(&s),
+                        NVValue::Number(n) => joined.push_str(&n.to_string()),
+                        NVValue::Bool(b) => joined.push_str(&b.to_string()),
+                        _ => return NVValue::Null,
+                    }
+                }
+                NVValue::String(joined)
+            }
+            Expression::Add(terms) => terms
+                .iter()
+                .try_fold(0.0, |sum, term| Some(sum + number(term)?))
+                .map_or(NVValue::Null, NVValue::Number),
+            Expression::Multiply(factors) => factors
+                .iter()
+                .try_fold(1.0, |product, factor| Some(product * number(factor)?))
+                .map_or(NVValue::Null, NVValue::Number),
+            Expression::Subtract(a, b) => match (number(a), number(b)) {
+                (Some(a), Some(b)) => NVValue::Number(a - b),
+                _ => NVValue::Null,
+            },
+            Expression::Divide(a, b) => match (number(a), number(b)) {
+                (Some(a), Some(b)) if b != 0.0 => NVValue::Number(a / b),
+                _ => NVValue::Null,
+            },
+            Expression::DateDiff { start, end, unit } => {
+                match (timestamp(&start.evaluate(row, processor)), timestamp(&end.evaluate(row, processor))) {
+                    (Some(start), Some(end)) => NVValue::Number(((end - start) / unit.millis()) as f64),
+                    _ => NVValue::Null,
+                }
+            }
+            Expression::If { condition, then, otherwise } => {
+                let mut query = NVQuery::new(String::new());
+                query.conditions.push(condition.clone());
+                if processor.matches_row(row, &query) {
+                    then.evaluate(row, processor)
+                } else {
+                    otherwise.evaluate(row, processor)
+                }
+            }
+        }
+    }
+}
+
+/// Add each computed field to `row`, evaluated against its other fields
+pub fn compute(row: &mut HashMap<String, NVValue>, fields: &[(String, Expression)], processor: &QueryProcessor) {
+    let values: Vec<NVValue> = fields.iter().map(|(_, expression)| expression.evaluate(row, processor)).collect();
+    for ((name, _), value) in fields.iter().zip(values) {
+        row.insert(name.clone(), value);
+    }
+}
+
+/// Milliseconds since the epoch of a date value
+pub(crate) fn timestamp(value: &NVValue) -> Option<i64> {
+    match value {
+        NVValue::Number(millis) => Some(*millis as i64),
+        NVValue::String(s) => DateTime::parse_from_rfc3339(s).ok().map(|date| date.with_timezone(&Utc).timestamp_millis()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::QueryOperator;
+
+    fn field(name: &str) -> Box<Expression> {
+        Box::new(Expression::Field(name.to_string()))
+    }
+
+    #[test]
+    fn test_evaluate_expressions() {
+        let mut row = HashMap::new();
+        row.insert("first".to_string(), NVValue::String("Ada".to_string()));
+        row.insert("last".to_string(), NVValue::String("Lovelace".to_string()));
+        row.insert("price".to_string(), NVValue::Number(12.0));
+        row.insert("quantity".to_string(), NVValue::Number(3.0));
+        row.insert("joined".to_string(), NVValue::String("2024-01-01T00:00:00Z".to_string()));
+        row.insert("left".to_string(), NVValue::String("2024-03-01T12:00:00Z".to_string()));
+
+        let fields = vec![
+            ("name".to_string(), Expression::Concat(vec![
+                Expression::Field("first".to_string()),
+                Expression::Literal(NVValue::String(" ".to_string())),
+                Expression::Field("last".to_string()),
+            ])),
+            ("total".to_string(), Expression::Multiply(vec![
+                Expression::Field("price".to_string()),
+                Expression::Field("quantity".to_string()),
+            ])),
+            ("per_item".to_string(), Expression::Divide(field("price"), Box::new(Expression::Literal(NVValue::Number(0.0))))),
+            ("days".to_string(), Expression::DateDiff { start: field("joined"), end: field("left"), unit: DateUnit::Days }),
+            ("size".to_string(), Expression::If {
+                condition: QueryCondition {
+                    field: "quantity".to_string(),
+                    operator: QueryOperator::GreaterThan,
+                    value: NVValue::Number(2.0),
+                },
+                then: Box::new(Expression::Literal(NVValue::String("bulk".to_string()))),
+                otherwise: Box::new(Expression::Literal(NVValue::String("single".to_string()))),
+            }),
+            ("missing".to_string(), Expression::Add(vec![Expression::Field("price".to_string()), Expression::Field("tax".to_string())])),
+        ];
+        compute(&mut row, &fields, &QueryProcessor::new());
+
+        assert_eq!(row["name"], NVValue::String("Ada Lovelace".to_string()));
+        assert_eq!(row["total"], NVValue::Number(36.0));
+        assert_eq!(row["per_item"], NVValue::Null);
+        assert_eq!(row["days"], NVValue::Number(60.0));
+        assert_eq!(row["size"], NVValue::String("bulk".to_string()));
+        assert_eq!(row["missing"], NVValue::Null);
+    }
+}
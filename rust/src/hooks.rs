@@ -0,0 +1,132 @@
+use crate::error::NVResult;
+use crate::models::{NVDocument, NVQuery};
+use std::collections::HashMap;
+
+/// Runs before a document is written; may modify it or veto with an error
+pub type BeforeCreateHook = Box<dyn Fn(&mut NVDocument) -> NVResult<()> + Send + Sync>;
+
+/// Runs after a document update is written, with the previous and new
+/// versions; the write already happened, so it can't fail it
+pub type AfterUpdateHook = Box<dyn Fn(&NVDocument, &NVDocument) + Send + Sync>;
+
+/// Runs before a document is deleted; may veto with an error
+pub type BeforeDeleteHook = Box<dyn Fn(&NVDocument) -> NVResult<()> + Send + Sync>;
+
+/// Observes or rewrites operations on every collection, e.g. to scope
+/// queries to a tenant or redact fields on the way out
+///
+/// Each method passes the operation through unchanged unless overridden,
+/// and may veto it with an error.
+pub trait Middleware: Send + Sync {
+    /// Runs before a query is executed, including the queries selecting
+    /// documents to update, delete or archive
+    fn on_query(&self, _query: &mut NVQuery) -> NVResult<()> {
+        Ok(())
+    }
+
+    /// Runs on each document returned by `find` and `find_by_id`
+    fn on_read(&self, _document: &mut NVDocument) -> NVResult<()> {
+        Ok(())
+    }
+
+    /// Runs before a new document is written, ahead of the collection's
+    /// before-create hooks
+    fn on_create(&self, _document: &mut NVDocument) -> NVResult<()> {
+        Ok(())
+    }
+}
+
+/// Per-collection document lifecycle hooks, and middleware that sees
+/// every collection
+///
+/// Hooks and middleware run in registration order. They may run while
+/// storage locks are held, so they must not call back into the database.
+#[derive(Default)]
+pub struct HookRegistry {
+    middleware: Vec<Box<dyn Middleware>>,
+    before_create: HashMap<String, Vec<BeforeCreateHook>>,
+    after_update: HashMap<String, Vec<AfterUpdateHook>>,
+    before_delete: HashMap<String, Vec<BeforeDeleteHook>>,
+}
+
+impl HookRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_middleware(&mut self, middleware: Box<dyn Middleware>) {
+        self.middleware.push(middleware);
+    }
+
+    pub fn add_before_create(&mut self, collection: &str, hook: BeforeCreateHook) {
+        self.before_create
+            .entry(collection.to_string())
+            .or_default()
+            .push(hook);
+    }
+
+    pub fn add_after_update(&mut self, collection: &str, hook: AfterUpdateHook) {
+        self.after_update
+            .entry(collection.to_string())
+            .or_default()
+            .push(hook);
+    }
+
+    pub fn add_before_delete(&mut self, collection: &str, hook: BeforeDeleteHook) {
+        self.before_delete
+            .entry(collection.to_string())
+            .or_default()
+            .push(hook);
+    }
+
+    /// Run the middleware over a query about to be executed
+    pub fn on_query(&self, query: &mut NVQuery) -> NVResult<()> {
+        for middleware in &self.middleware {
+            middleware.on_query(query)?;
+        }
+        Ok(())
+    }
+
+    /// Run the middleware over documents about to be returned
+    pub fn on_read(&self, documents: &mut [NVDocument]) -> NVResult<()> {
+        for middleware in &self.middleware {
+            for document in documents.iter_mut() {
+                middleware.on_read(document)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the middleware, then the before-create hooks of the document's
+    /// collection
+    pub fn before_create(&self, document: &mut NVDocument) -> NVResult<()> {
+        for middleware in &self.middleware {
+            middleware.on_create(document)?;
+        }
+        if let Some(hooks) = self.before_create.get(&document.collection) {
+            for hook in hooks {
+                hook(document)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the after-update hooks of the document's collection
+    pub fn after_update(&self, previous: &NVDocument, current: &NVDocument) {
+        if let Some(hooks) = self.after_update.get(&current.collection) {
+            for hook in hooks {
+                hook(previous, current);
+            }
+        }
+    }
+
+    /// Run the before-delete hooks of the document's collection
+    pub fn before_delete(&self, document: &NVDocument) -> NVResult<()> {
+        if let Some(hooks) = self.before_delete.get(&document.collection) {
+            for hook in hooks {
+                hook(document)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use chrono::Utc;
+use parking_lot::Mutex;
+use uuid::Uuid;
+
+/// Crockford base32 alphabet used by ULIDs
+const ULID_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Mask for the 80 random bits of a ULID
+const ULID_RANDOM_MASK: u128 = (1 << 80) - 1;
+
+/// Generates ULIDs that sort in creation order
+///
+/// IDs generated within the same millisecond increment the random part of
+/// the previous ID instead of drawing new randomness, so they stay ordered.
+pub struct UlidGenerator {
+    last: Mutex<(u64, u128)>,
+}
+
+impl UlidGenerator {
+    pub fn new() -> Self {
+        Self {
+            last: Mutex::new((0, 0)),
+        }
+    }
+
+    /// Generate the next ULID
+    pub fn next(&self) -> String {
+        let now = Utc::now().timestamp_millis().max(0) as u64;
+        let mut last = self.last.lock();
+
+        let (millis, random) = if now > last.0 {
+            (now, random_bits())
+        } else if last.1 < ULID_RANDOM_MASK {
+            (last.0, last.1 + 1)
+        } else {
+            // Random part exhausted within this millisecond; borrow the next one
+            (last.0 + 1, random_bits())
+        };
+        *last = (millis, random);
+
+        encode_ulid(((millis as u128) << 80) | random)
+    }
+}
+
+impl Default for UlidGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 80 random bits, leaving headroom so increments rarely overflow
+fn random_bits() -> u128 {
+    (Uuid::new_v4().as_u128() & ULID_RANDOM_MASK) >> 1
+}
+
+/// Encode a 128-bit ULID as 26 Crockford base32 characters
+fn encode_ulid(mut value: u128) -> String {
+    let mut out = [0u8; 26];
+    for slot in out.iter_mut().rev() {
+        *slot = ULID_ALPHABET[(value & 31) as usize];
+        value >>= 5;
+    }
+    String::from_utf8(out.to_vec()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ulids_are_sorted_and_unique() {
+        let generator = UlidGenerator::new();
+        let ids: Vec<String> = (0..1000).map(|_| generator.next()).collect();
+
+        assert!(ids.iter().all(|id| id.len() == 26));
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,7 @@
+pub mod mongo;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+pub use mongo::{from_extended_json, parse_mongo_export, ImportedDocument};
+#[cfg(feature = "sqlite")]
+pub use sqlite::{BlobEncoding, ImportedTable, SqliteImportOptions, SqliteSource};
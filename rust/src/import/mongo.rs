@@ -0,0 +1,235 @@
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::NVValue;
+use crate::object::NVObject;
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// A document read from an export: its `_id`, if any, and its other fields
+pub type ImportedDocument = (Option<String>, HashMap<String, NVValue>);
+
+/// Parse the output of `mongoexport`
+///
+/// Accepts one document per line (the default) or a single JSON array
+/// (`--jsonArray`), in relaxed or canonical Extended JSON. Each document's
+/// `_id` is split off to become its ID; see `from_extended_json` for how
+/// other values are mapped.
+pub fn parse_mongo_export(input: &str) -> NVResult<Vec<ImportedDocument>> {
+    let values = if input.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<Value>>(input)?
+    } else {
+        input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                serde_json::from_str(line).map_err(|e| {
+                    NeuralVaultError::SerializationError(format!("Line {}: {}", number + 1, e))
+                })
+            })
+            .collect::<NVResult<Vec<Value>>>()?
+    };
+
+    values
+        .into_iter()
+        .map(|value| {
+            let Value::Object(mut fields) = value else {
+                return Err(NeuralVaultError::SerializationError(
+                    "Expected each exported document to be a JSON object".to_string(),
+                ));
+            };
+            let id = fields.remove("_id").map(id_string).transpose()?;
+            let data = fields
+                .into_iter()
+                .map(|(key, value)| Ok((key, from_extended_json(value)?)))
+                .collect::<NVResult<_>>()?;
+            Ok((id, data))
+        })
+        .collect()
+}
+
+/// Convert an Extended JSON value to an `NVValue`
+///
+/// - `$oid`, `$symbol` and `$code` become their string
+/// - `$date` becomes milliseconds since the epoch, as `_created_at` is
+///   read, so range conditions compare dates
+/// - `$numberInt`, `$numberLong`, `$numberDouble` and `$numberDecimal`
+///   become numbers (64-bit integers and decimals may lose precision)
+/// - `$binary` becomes its base64 string
+/// - `$timestamp` becomes its seconds
+/// - `$regularExpression` becomes its pattern
+/// - `$minKey`, `$maxKey` and `$undefined` become null
+pub fn from_extended_json(value: Value) -> NVResult<NVValue> {
+    Ok(match value {
+        Value::Array(items) => NVValue::Array(
+            items
+                .into_iter()
+                .map(from_extended_json)
+                .collect::<NVResult<_>>()?,
+        ),
+        Value::Object(fields) => match wrapper(&fields) {
+            Some((key, inner)) => from_wrapper(key, inner)?,
+            None => NVValue::Object(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| Ok((key, from_extended_json(value)?)))
+                    .collect::<NVResult<_>>()?,
+            ),
+        },
+        other => NVValue::from(other),
+    })
+}
+
+/// The type key and value of a single-key `{"$type": ...}` wrapper
+fn wrapper(fields: &Map<String, Value>) -> Option<(&str, &Value)> {
+    if fields.len() != 1 {
+        // `$binary` and `$regularExpression` have two keys in the legacy format
+        return fields
+            .get("$binary")
+            .map(|inner| ("$binary", inner))
+            .or_else(|| fields.get("$regex").map(|inner| ("$regex", inner)));
+    }
+    let (key, inner) = fields.iter().next()?;
+    key.starts_with('$').then_some((key.as_str(), inner))
+}
+
+fn from_wrapper(key: &str, inner: &Value) -> NVResult<NVValue> {
+    let invalid = || NeuralVaultError::SerializationError(format!("Invalid Extended JSON {}: {}", key, inner));
+
+    Ok(match key {
+        "$oid" | "$symbol" | "$code" => NVValue::String(inner.as_str().ok_or_else(invalid)?.to_string()),
+        "$date" => NVValue::Number(date(inner).ok_or_else(invalid)? as f64),
+        "$numberInt" | "$numberLong" | "$numberDouble" | "$numberDecimal" => {
+            NVValue::Number(number(inner).ok_or_else(invalid)?)
+        }
+        // Canonical `{"base64", "subType"}` or legacy string
+        "$binary" => match inner {
+            Value::String(base64) => NVValue::String(base64.clone()),
+            _ => NVValue::String(inner.get("base64").and_then(Value::as_str).ok_or_else(invalid)?.to_string()),
+        },
+        "$timestamp" => NVValue::Number(inner.get("t").and_then(Value::as_f64).ok_or_else(invalid)?),
+        "$regularExpression" => {
+            NVValue::String(inner.get("pattern").and_then(Value::as_str).ok_or_else(invalid)?.to_string())
+        }
+        "$regex" => NVValue::String(inner.as_str().ok_or_else(invalid)?.to_string()),
+        "$minKey" | "$maxKey" | "$undefined" => NVValue::Null,
+        // Not an Extended JSON type, just a field that starts with `$`
+        _ => {
+            let mut fields = NVObject::new();
+            fields.insert(key.to_string(), from_extended_json(inner.clone())?);
+            NVValue::Object(fields)
+        }
+    })
+}
+
+/// Milliseconds since the epoch of a `$date` given as an ISO string,
+/// `{"$numberLong": millis}` or plain millis
+fn date(value: &Value) -> Option<i64> {
+    let date = match value {
+        Value::String(iso) => DateTime::parse_from_rfc3339(iso).ok()?.with_timezone(&Utc),
+        Value::Object(fields) => DateTime::<Utc>::from_timestamp_millis(number(fields.get("$numberLong")?)? as i64)?,
+        Value::Number(millis) => DateTime::<Utc>::from_timestamp_millis(millis.as_i64()?)?,
+        _ => return None,
+    };
+    Some(date.timestamp_millis())
+}
+
+/// A number given as a JSON number or a string, including `"NaN"` and
+/// `"Infinity"`
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => match text.as_str() {
+            "Infinity" => Some(f64::INFINITY),
+            "-Infinity" => Some(f64::NEG_INFINITY),
+            "NaN" => Some(f64::NAN),
+            text => text.parse().ok(),
+        },
+        _ => None,
+    }
+}
+
+/// A document ID from an `_id` value
+///
+/// Object IDs become their hex string, strings stay as they are and
+/// integers keep their digits, without going through a float that would
+/// merge large ones. Compound IDs become their Extended JSON.
+fn id_string(value: Value) -> NVResult<String> {
+    if let Some(integer) = integer_id(&value) {
+        return Ok(integer);
+    }
+    Ok(match from_extended_json(value.clone())? {
+        NVValue::String(id) => id,
+        NVValue::Number(number) if number.fract() == 0.0 && number.abs() < 1e15 => format!("{}", number as i64),
+        NVValue::Number(number) => number.to_string(),
+        _ => value.to_string(),
+    })
+}
+
+/// The digits of an integer `_id`, given as a JSON integer or a
+/// `$numberInt`/`$numberLong` wrapper
+fn integer_id(value: &Value) -> Option<String> {
+    let integer = match value {
+        Value::Number(number) => number.as_i64().map(|n| n as i128).or(number.as_u64().map(|n| n as i128))?,
+        Value::Object(fields) if fields.len() == 1 => match fields.iter().next()? {
+            (key, Value::String(digits)) if key == "$numberInt" || key == "$numberLong" => digits.parse().ok()?,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(integer.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_and_relaxed_extended_json() {
+        let export = r#"
+{"_id":{"$oid":"5f1b2c3d4e5f6a7b8c9d0e1f"},"name":"Ada","born":{"$date":{"$numberLong":"-4861728000000"}},"visits":{"$numberLong":"42"},"score":{"$numberDouble":"NaN"}}
+{"_id":7,"name":"Grace","born":{"$date":"1906-12-09T00:00:00Z"},"tags":[{"$numberInt":"1"},{"$minKey":1}],"meta":{"$set":true}}
+"#;
+        let documents = parse_mongo_export(export).unwrap();
+        assert_eq!(documents.len(), 2);
+
+        let (id, ada) = &documents[0];
+        assert_eq!(id.as_deref(), Some("5f1b2c3d4e5f6a7b8c9d0e1f"));
+        assert_eq!(ada["born"], NVValue::Number(-4861728000000.0));
+        assert_eq!(ada["visits"], NVValue::Number(42.0));
+        assert!(matches!(ada["score"], NVValue::Number(score) if score.is_nan()));
+
+        let (id, grace) = &documents[1];
+        assert_eq!(id.as_deref(), Some("7"));
+        assert_eq!(grace["born"], NVValue::Number(-1990137600000.0));
+        assert_eq!(grace["tags"], NVValue::Array(vec![NVValue::Number(1.0), NVValue::Null]));
+        assert!(matches!(&grace["meta"], NVValue::Object(meta) if meta["$set"] == NVValue::Bool(true)));
+    }
+
+    #[test]
+    fn test_json_array_export() {
+        let export = r#"[{"_id":"a","n":1},{"n":2}]"#;
+        let documents = parse_mongo_export(export).unwrap();
+        assert_eq!(documents[0].0.as_deref(), Some("a"));
+        assert_eq!(documents[1].0, None);
+
+        assert!(parse_mongo_export("{\"n\":1}\n{oops}").is_err());
+    }
+
+    #[test]
+    fn test_large_integer_ids_stay_distinct() {
+        let export = r#"
+{"_id":{"$numberLong":"1234567890123456789"}}
+{"_id":{"$numberLong":"1234567890123456790"}}
+{"_id":9007199254740993}
+{"_id":{"$numberInt":"-7"}}
+"#;
+        let ids: Vec<Option<String>> = parse_mongo_export(export).unwrap().into_iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, vec![
+            Some("1234567890123456789".to_string()),
+            Some("1234567890123456790".to_string()),
+            Some("9007199254740993".to_string()),
+            Some("-7".to_string()),
+        ]);
+    }
+}
@@ -0,0 +1,325 @@
+use crate::crypto;
+use crate::error::{NeuralVaultError, NVResult};
+use crate::import::ImportedDocument;
+use crate::models::NVValue;
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// How SQLite BLOB values are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlobEncoding {
+    /// As a lowercase hex string
+    #[default]
+    Hex,
+    /// Left out of the document
+    Skip,
+}
+
+/// How tables and values are mapped by `SqliteSource`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SqliteImportOptions {
+    /// Tables to import (empty = every table)
+    pub tables: Vec<String>,
+    /// Collection to import each table into (default: the table's name)
+    pub collections: HashMap<String, String>,
+    /// Use a single-column primary key as the document ID; otherwise the
+    /// collection's ID strategy assigns one
+    pub primary_key_as_id: bool,
+    /// Prefix primary-key IDs with the collection and a colon, as in
+    /// `users:1`, since IDs are unique across collections and tables often
+    /// number their rows from 1 alike
+    pub qualify_ids: bool,
+    /// Store integers in columns declared `BOOLEAN` or `BOOL` as booleans
+    pub detect_booleans: bool,
+    /// Parse text in columns declared `JSON` into objects and arrays
+    pub parse_json: bool,
+    /// Leave NULL columns out of documents instead of storing null
+    pub skip_nulls: bool,
+    /// How BLOB columns are stored
+    pub blobs: BlobEncoding,
+}
+
+impl Default for SqliteImportOptions {
+    fn default() -> Self {
+        Self {
+            tables: Vec::new(),
+            collections: HashMap::new(),
+            primary_key_as_id: true,
+            qualify_ids: true,
+            detect_booleans: true,
+            parse_json: true,
+            skip_nulls: false,
+            blobs: BlobEncoding::Hex,
+        }
+    }
+}
+
+/// Result of importing one table
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportedTable {
+    pub table: String,
+    pub collection: String,
+    /// Rows written as documents
+    pub imported: usize,
+    /// Why each remaining row couldn't be written
+    pub errors: Vec<String>,
+}
+
+/// A SQLite database read as documents, one collection per table
+pub struct SqliteSource {
+    connection: Connection,
+    options: SqliteImportOptions,
+}
+
+/// What a column's declared type says about mapping its values
+struct Column {
+    name: String,
+    boolean: bool,
+    json: bool,
+}
+
+impl SqliteSource {
+    /// Open a SQLite file for reading
+    pub fn open(path: &Path, options: SqliteImportOptions) -> NVResult<Self> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(sqlite_error)?;
+        Ok(Self { connection, options })
+    }
+
+    /// The tables to import, in name order
+    pub fn tables(&self) -> NVResult<Vec<String>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name")
+            .map_err(sqlite_error)?;
+        let tables = statement
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(sqlite_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(sqlite_error)?;
+
+        if self.options.tables.is_empty() {
+            return Ok(tables);
+        }
+        for table in &self.options.tables {
+            if !tables.contains(table) {
+                return Err(NeuralVaultError::CollectionNotFound(format!("SQLite table '{}'", table)));
+            }
+        }
+        Ok(tables.into_iter().filter(|table| self.options.tables.contains(table)).collect())
+    }
+
+    /// The collection a table is imported into
+    pub fn collection(&self, table: &str) -> String {
+        self.options.collections.get(table).cloned().unwrap_or_else(|| table.to_string())
+    }
+
+    /// Read a table's rows as documents, passing them to `visit` at most
+    /// `batch_size` at a time
+    pub fn read_table(
+        &self,
+        table: &str,
+        batch_size: usize,
+        mut visit: impl FnMut(Vec<ImportedDocument>) -> NVResult<()>,
+    ) -> NVResult<()> {
+        let (columns, primary_key) = self.columns(table)?;
+        let id_column = primary_key.filter(|_| self.options.primary_key_as_id);
+        let id_prefix = match self.options.qualify_ids {
+            true => format!("{}:", self.collection(table)),
+            false => String::new(),
+        };
+
+        let sql = format!("SELECT {} FROM {}", select_list(&columns), quote(table));
+        let mut statement = self.connection.prepare(&sql).map_err(sqlite_error)?;
+        let mut rows = statement.query([]).map_err(sqlite_error)?;
+
+        let mut batch = Vec::with_capacity(batch_size);
+        while let Some(row) = rows.next().map_err(sqlite_error)? {
+            let mut id = None;
+            let mut data = HashMap::with_capacity(columns.len());
+            for (i, column) in columns.iter().enumerate() {
+                let value = row.get_ref(i).map_err(sqlite_error)?;
+                if Some(i) == id_column {
+                    id = id_string(value).map(|id| format!("{}{}", id_prefix, id));
+                    continue;
+                }
+                if let Some(value) = self.convert(column, value) {
+                    data.insert(column.name.clone(), value);
+                }
+            }
+
+            batch.push((id, data));
+            if batch.len() >= batch_size {
+                visit(std::mem::take(&mut batch))?;
+            }
+        }
+        if !batch.is_empty() {
+            visit(batch)?;
+        }
+        Ok(())
+    }
+
+    /// A table's columns and the index of its primary key, if it's a
+    /// single column
+    fn columns(&self, table: &str) -> NVResult<(Vec<Column>, Option<usize>)> {
+        let mut statement = self
+            .connection
+            .prepare(&format!("PRAGMA table_info({})", quote(table)))
+            .map_err(sqlite_error)?;
+        let info = statement
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?, row.get::<_, i64>(5)?))
+            })
+            .map_err(sqlite_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(sqlite_error)?;
+
+        let primary_keys: Vec<usize> = info
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, _, pk))| *pk > 0)
+            .map(|(i, _)| i)
+            .collect();
+        let columns = info
+            .into_iter()
+            .map(|(name, declared, _)| {
+                let declared = declared.to_ascii_uppercase();
+                Column {
+                    name,
+                    boolean: declared == "BOOLEAN" || declared == "BOOL",
+                    json: declared == "JSON",
+                }
+            })
+            .collect();
+        Ok((columns, (primary_keys.len() == 1).then(|| primary_keys[0])))
+    }
+
+    /// Map a column value, or `None` to leave it out
+    fn convert(&self, column: &Column, value: ValueRef) -> Option<NVValue> {
+        Some(match value {
+            ValueRef::Null if self.options.skip_nulls => return None,
+            ValueRef::Null => NVValue::Null,
+            ValueRef::Integer(i) if column.boolean && self.options.detect_booleans => NVValue::Bool(i != 0),
+            ValueRef::Integer(i) => NVValue::Number(i as f64),
+            ValueRef::Real(f) => NVValue::Number(f),
+            ValueRef::Text(text) => {
+                let text = String::from_utf8_lossy(text);
+                let json = (column.json && self.options.parse_json)
+                    .then(|| serde_json::from_str::<serde_json::Value>(&text).ok())
+                    .flatten();
+                match json {
+                    Some(json) => NVValue::from(json),
+                    None => NVValue::String(text.into_owned()),
+                }
+            }
+            ValueRef::Blob(bytes) => match self.options.blobs {
+                BlobEncoding::Hex => NVValue::String(crypto::to_hex(bytes)),
+                BlobEncoding::Skip => return None,
+            },
+        })
+    }
+}
+
+/// A document ID from a primary key value; NULL and blobs get none
+fn id_string(value: ValueRef) -> Option<String> {
+    match value {
+        ValueRef::Integer(i) => Some(i.to_string()),
+        ValueRef::Real(f) => Some(f.to_string()),
+        ValueRef::Text(text) => Some(String::from_utf8_lossy(text).into_owned()),
+        ValueRef::Null | ValueRef::Blob(_) => None,
+    }
+}
+
+fn select_list(columns: &[Column]) -> String {
+    columns.iter().map(|column| quote(&column.name)).collect::<Vec<_>>().join(", ")
+}
+
+/// Quote an SQL identifier
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn sqlite_error(err: rusqlite::Error) -> NeuralVaultError {
+    NeuralVaultError::StorageError(format!("SQLite error: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn read_all(source: &SqliteSource, table: &str) -> Vec<ImportedDocument> {
+        let mut documents = Vec::new();
+        source
+            .read_table(table, 2, |batch| {
+                documents.extend(batch);
+                Ok(())
+            })
+            .unwrap();
+        documents
+    }
+
+    #[test]
+    fn test_maps_declared_types_and_primary_keys() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("source.db");
+        let connection = Connection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE items (id INTEGER PRIMARY KEY, active BOOLEAN, meta JSON, raw BLOB, note TEXT);
+                 INSERT INTO items VALUES (7, 1, '{\"tags\": [\"a\"]}', x'00ff10', NULL);
+                 INSERT INTO items VALUES (8, 0, 'not json', NULL, 'hi');
+                 CREATE TABLE pairs (a TEXT, b TEXT, PRIMARY KEY (a, b));
+                 INSERT INTO pairs VALUES ('x', 'y');",
+            )
+            .unwrap();
+        drop(connection);
+
+        let source = SqliteSource::open(&path, SqliteImportOptions::default()).unwrap();
+        assert_eq!(source.tables().unwrap(), vec!["items".to_string(), "pairs".to_string()]);
+
+        let items = read_all(&source, "items");
+        let (id, first) = &items[0];
+        assert_eq!(id.as_deref(), Some("items:7"));
+        assert!(!first.contains_key("id"));
+        assert_eq!(first["active"], NVValue::Bool(true));
+        assert!(matches!(&first["meta"], NVValue::Object(meta) if meta.contains_key("tags")));
+        assert_eq!(first["raw"], NVValue::String("00ff10".to_string()));
+        assert_eq!(first["note"], NVValue::Null);
+        let (_, second) = &items[1];
+        assert_eq!(second["active"], NVValue::Bool(false));
+        assert_eq!(second["meta"], NVValue::String("not json".to_string()));
+
+        // A composite primary key stays in the data
+        let pairs = read_all(&source, "pairs");
+        assert_eq!(pairs[0].0, None);
+        assert_eq!(pairs[0].1["a"], NVValue::String("x".to_string()));
+
+        let options = SqliteImportOptions {
+            skip_nulls: true,
+            detect_booleans: false,
+            qualify_ids: false,
+            blobs: BlobEncoding::Skip,
+            ..Default::default()
+        };
+        let source = SqliteSource::open(&path, options).unwrap();
+        let items = read_all(&source, "items");
+        let (id, first) = &items[0];
+        assert_eq!(id.as_deref(), Some("7"));
+        assert!(!first.contains_key("note") && !first.contains_key("raw"));
+        assert_eq!(first["active"], NVValue::Number(1.0));
+    }
+
+    #[test]
+    fn test_options_from_json() {
+        let options: SqliteImportOptions =
+            serde_json::from_str(r#"{"tables": ["items"], "skip_nulls": true, "blobs": "skip"}"#).unwrap();
+        assert_eq!(options.tables, vec!["items".to_string()]);
+        assert!(options.skip_nulls && options.primary_key_as_id);
+        assert_eq!(options.blobs, BlobEncoding::Skip);
+    }
+}
@@ -0,0 +1,182 @@
+use crate::models::{NVDocument, NVValue};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Definition of an autocomplete index over the values of one field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompletionIndexDefinition {
+    pub collection: String,
+    /// Field whose string values, or strings in arrays, are suggested
+    pub field: String,
+}
+
+/// In-memory index completing prefixes to the values of a field, most
+/// common first
+///
+/// Values are kept sorted by their lowercase form, so completing a prefix
+/// is a range scan over just the values that start with it.
+#[derive(Debug, Clone)]
+pub struct CompletionIndex {
+    definition: CompletionIndexDefinition,
+    /// Lowercase value to its spellings and how many documents use each
+    values: BTreeMap<String, HashMap<String, u32>>,
+    /// Document ID to the values it contributed
+    documents: HashMap<String, Vec<String>>,
+}
+
+impl CompletionIndex {
+    pub fn new(definition: CompletionIndexDefinition) -> Self {
+        Self {
+            definition,
+            values: BTreeMap::new(),
+            documents: HashMap::new(),
+        }
+    }
+
+    pub fn definition(&self) -> &CompletionIndexDefinition {
+        &self.definition
+    }
+
+    /// Number of distinct values, ignoring case
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Index the current version of a document, replacing any earlier one
+    pub fn add(&mut self, document: &NVDocument) {
+        self.remove(&document.id);
+        if document.collection != self.definition.collection || document.deleted {
+            return;
+        }
+
+        let mut values: Vec<String> = match document.get(&self.definition.field) {
+            Some(NVValue::String(value)) => vec![value.clone()],
+            Some(NVValue::Array(items)) => items
+                .iter()
+                .filter_map(|item| match item {
+                    NVValue::String(value) => Some(value.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        // Each document counts once per value, whatever its spellings
+        values.retain(|value| !value.trim().is_empty());
+        values.sort_by_key(|value| value.to_lowercase());
+        values.dedup_by_key(|value| value.to_lowercase());
+        if values.is_empty() {
+            return;
+        }
+
+        for value in &values {
+            *self
+                .values
+                .entry(value.to_lowercase())
+                .or_default()
+                .entry(value.clone())
+                .or_default() += 1;
+        }
+        self.documents.insert(document.id.clone(), values);
+    }
+
+    /// Remove a document from the index
+    pub fn remove(&mut self, id: &str) {
+        let Some(values) = self.documents.remove(id) else {
+            return;
+        };
+        for value in values {
+            let key = value.to_lowercase();
+            let Some(spellings) = self.values.get_mut(&key) else {
+                continue;
+            };
+            if let Some(count) = spellings.get_mut(&value) {
+                *count -= 1;
+                if *count == 0 {
+                    spellings.remove(&value);
+                }
+            }
+            if spellings.is_empty() {
+                self.values.remove(&key);
+            }
+        }
+    }
+
+    /// Up to `k` values starting with `prefix`, ignoring case, with how
+    /// many documents have them, most common first
+    ///
+    /// Each value is given in its most common spelling.
+    pub fn suggest(&self, prefix: &str, k: usize) -> Vec<(String, u32)> {
+        let prefix = prefix.to_lowercase();
+        let mut matches: Vec<(String, u32)> = self
+            .values
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, spellings)| {
+                let total = spellings.values().sum();
+                let (spelling, _) = spellings
+                    .iter()
+                    .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+                    .expect("values have at least one spelling");
+                (spelling.clone(), total)
+            })
+            .collect();
+        matches.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        matches.truncate(k);
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn product(id: &str, name: &str, tags: &[&str]) -> NVDocument {
+        let mut data = HashMap::new();
+        data.insert("name".to_string(), NVValue::String(name.to_string()));
+        data.insert(
+            "tags".to_string(),
+            NVValue::Array(tags.iter().map(|tag| NVValue::String(tag.to_string())).collect()),
+        );
+        NVDocument::new(id.to_string(), "products".to_string(), data)
+    }
+
+    fn index(field: &str) -> CompletionIndex {
+        CompletionIndex::new(CompletionIndexDefinition {
+            collection: "products".to_string(),
+            field: field.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_suggest_by_popularity() {
+        let mut index = index("tags");
+        index.add(&product("a", "Kettle", &["kitchen", "Kitchenware"]));
+        index.add(&product("b", "Knife", &["Kitchen", "knives", "kitchen"]));
+        index.add(&product("c", "Kite", &["kitchen", "outdoor"]));
+
+        assert_eq!(
+            index.suggest("KIT", 10),
+            vec![("kitchen".to_string(), 3), ("Kitchenware".to_string(), 1)]
+        );
+        assert_eq!(index.suggest("k", 1), vec![("kitchen".to_string(), 3)]);
+        assert!(index.suggest("z", 10).is_empty());
+        assert_eq!(index.suggest("", 10).len(), 4);
+    }
+
+    #[test]
+    fn test_replace_and_remove() {
+        let mut index = index("name");
+        index.add(&product("a", "Kettle", &[]));
+        index.add(&product("a", "Kite", &[]));
+        assert_eq!(index.suggest("ke", 10), vec![]);
+        assert_eq!(index.suggest("ki", 10), vec![("Kite".to_string(), 1)]);
+
+        index.remove("a");
+        assert!(index.is_empty());
+        assert!(index.documents.is_empty());
+    }
+}
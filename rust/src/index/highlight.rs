@@ -0,0 +1,224 @@
+use super::text::TextIndexDefinition;
+use super::tokenizer::Tokenizer;
+use crate::models::{NVDocument, NVValue};
+use crate::query::fuzzy;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// How matches are marked up in text search results
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HighlightOptions {
+    /// Inserted before each match in snippets
+    pub pre_tag: String,
+    /// Inserted after each match in snippets
+    pub post_tag: String,
+    /// Terms of context in each snippet
+    pub snippet_terms: usize,
+    /// Also match words within this many edits of a query word
+    pub max_distance: usize,
+}
+
+impl Default for HighlightOptions {
+    fn default() -> Self {
+        Self {
+            pre_tag: "**".to_string(),
+            post_tag: "**".to_string(),
+            snippet_terms: 20,
+            max_distance: 0,
+        }
+    }
+}
+
+/// Where a search matched in one field of a result
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Highlight {
+    pub field: String,
+    /// Position in the field, for arrays of strings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub element: Option<usize>,
+    /// Character ranges of the matches
+    pub matches: Vec<(usize, usize)>,
+    /// The stretch of text with the most matches, marked up
+    pub snippet: String,
+}
+
+/// Find the terms of `query` in a document's indexed fields, using the
+/// index's tokenizer so stemmed and n-gram matches are found too
+pub fn highlight(
+    definition: &TextIndexDefinition,
+    document: &NVDocument,
+    query: &str,
+    options: &HighlightOptions,
+) -> Vec<Highlight> {
+    let tokenizer = &definition.tokenizer;
+    let terms = tokenizer.tokenize(query);
+    let mut highlights = Vec::new();
+    for field in &definition.fields {
+        match document.get(field) {
+            Some(NVValue::String(text)) => {
+                highlights.extend(highlight_text(tokenizer, &terms, text, options).map(|(matches, snippet)| {
+                    Highlight {
+                        field: field.clone(),
+                        element: None,
+                        matches,
+                        snippet,
+                    }
+                }));
+            }
+            Some(NVValue::Array(values)) => {
+                for (element, value) in values.iter().enumerate() {
+                    let NVValue::String(text) = value else { continue };
+                    if let Some((matches, snippet)) = highlight_text(tokenizer, &terms, text, options) {
+                        highlights.push(Highlight {
+                            field: field.clone(),
+                            element: Some(element),
+                            matches,
+                            snippet,
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    highlights
+}
+
+/// Character ranges of the matches in `text` and its best snippet, if
+/// anything matched
+fn highlight_text(
+    tokenizer: &Tokenizer,
+    terms: &[String],
+    text: &str,
+    options: &HighlightOptions,
+) -> Option<(Vec<(usize, usize)>, String)> {
+    let spans = tokenizer.spans(text);
+    let matched: Vec<bool> = spans
+        .iter()
+        .map(|(term, _)| {
+            terms.iter().any(|query| {
+                query == term
+                    || (options.max_distance > 0 && fuzzy::distance(query, term, options.max_distance).is_some())
+            })
+        })
+        .collect();
+
+    // Overlapping n-grams merge into one match
+    let mut merged: Vec<Range<usize>> = Vec::new();
+    for ((_, range), _) in spans.iter().zip(&matched).filter(|(_, &hit)| hit) {
+        match merged.last_mut() {
+            Some(last) if range.start < last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range.clone()),
+        }
+    }
+    if merged.is_empty() {
+        return None;
+    }
+
+    // The window of terms holding the most matches
+    let width = options.snippet_terms.clamp(1, spans.len());
+    let mut count = matched[..width].iter().filter(|&&hit| hit).count();
+    let (mut best, mut best_count) = (0, count);
+    for start in 1..=spans.len() - width {
+        count = count + matched[start + width - 1] as usize - matched[start - 1] as usize;
+        if count > best_count {
+            (best, best_count) = (start, count);
+        }
+    }
+    // Lead with its first match, which keeps every match after it
+    let first = (best..best + width).find(|&i| matched[i]).unwrap_or(best);
+    let best = first.min(spans.len() - width);
+    let (from, to) = (spans[best].1.start, spans[best + width - 1].1.end);
+
+    let mut snippet = String::new();
+    if text[..from].chars().any(char::is_alphanumeric) {
+        snippet.push('…');
+    }
+    let mut cursor = from;
+    for range in merged.iter().filter(|range| range.start < to && range.end > from) {
+        let (start, end) = (range.start.max(from), range.end.min(to));
+        snippet.push_str(&text[cursor..start]);
+        snippet.push_str(&options.pre_tag);
+        snippet.push_str(&text[start..end]);
+        snippet.push_str(&options.post_tag);
+        cursor = end;
+    }
+    snippet.push_str(&text[cursor..to]);
+    if text[to..].chars().any(char::is_alphanumeric) {
+        snippet.push('…');
+    }
+
+    let chars = |byte: usize| text[..byte].chars().count();
+    let matches = merged.iter().map(|range| (chars(range.start), chars(range.end))).collect();
+    Some((matches, snippet))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn definition(tokenizer: Tokenizer) -> TextIndexDefinition {
+        TextIndexDefinition {
+            name: "note_text".to_string(),
+            collection: "notes".to_string(),
+            fields: vec!["body".to_string(), "tags".to_string()],
+            tokenizer,
+        }
+    }
+
+    fn note(body: &str, tags: &[&str]) -> NVDocument {
+        let mut data = HashMap::new();
+        data.insert("body".to_string(), NVValue::String(body.to_string()));
+        data.insert(
+            "tags".to_string(),
+            NVValue::Array(tags.iter().map(|tag| NVValue::String(tag.to_string())).collect()),
+        );
+        NVDocument::new("n".to_string(), "notes".to_string(), data)
+    }
+
+    #[test]
+    fn test_snippet_around_matches() {
+        let options = HighlightOptions {
+            snippet_terms: 4,
+            ..Default::default()
+        };
+        let document = note("One two three. Rust makes storage safe, and Rust is fast!", &["misc", "rust"]);
+        let highlights = highlight(&definition(Tokenizer::default()), &document, "rust", &options);
+
+        assert_eq!(highlights.len(), 2);
+        assert_eq!(highlights[0].matches, vec![(15, 19), (44, 48)]);
+        assert_eq!(highlights[0].snippet, "…**Rust** makes storage safe…");
+        assert_eq!(highlights[1].element, Some(1));
+        assert_eq!(highlights[1].snippet, "**rust**");
+
+        let whole = highlight(&definition(Tokenizer::default()), &document, "fast", &HighlightOptions::default());
+        assert_eq!(whole[0].snippet, "One two three. Rust makes storage safe, and Rust is **fast**");
+        assert!(highlight(&definition(Tokenizer::default()), &document, "python", &options).is_empty());
+    }
+
+    #[test]
+    fn test_stemmed_fuzzy_and_ngram_matches() {
+        let stemming = definition(Tokenizer {
+            stemming: true,
+            ..Default::default()
+        });
+        let document = note("Indexing documents", &[]);
+        let options = HighlightOptions {
+            pre_tag: "<b>".to_string(),
+            post_tag: "</b>".to_string(),
+            max_distance: 1,
+            ..Default::default()
+        };
+        assert_eq!(highlight(&stemming, &document, "indexes documnt", &options)[0].snippet, "<b>Indexing</b> <b>documents</b>");
+
+        let ngrams = definition(Tokenizer {
+            cjk_ngrams: Some(2),
+            ..Default::default()
+        });
+        let highlights = highlight(&ngrams, &note("新数据库系统", &[]), "数据库", &HighlightOptions::default());
+        assert_eq!(highlights[0].matches, vec![(1, 4)]);
+        assert_eq!(highlights[0].snippet, "新**数据库**系统");
+    }
+}
@@ -0,0 +1,91 @@
+use crate::models::NVValue;
+use std::cmp::Ordering;
+
+/// Totally ordered `f64` for use in index keys
+#[derive(Debug, Clone, Copy)]
+pub struct F64Key(pub f64);
+
+impl PartialEq for F64Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for F64Key {}
+
+impl PartialOrd for F64Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for F64Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// One field of an index key
+///
+/// Variants are ordered by type first (missing < null < bool < number <
+/// string < other), then by value.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IndexKey {
+    /// The document has no such field
+    Missing,
+    Null,
+    Bool(bool),
+    Number(F64Key),
+    String(String),
+    /// Arrays and objects, which are indexed but never looked up by value
+    Other,
+}
+
+impl IndexKey {
+    /// Key for an optional field value
+    pub fn from_value(value: Option<&NVValue>) -> Self {
+        match value {
+            None => IndexKey::Missing,
+            Some(NVValue::Null) => IndexKey::Null,
+            Some(NVValue::Bool(b)) => IndexKey::Bool(*b),
+            // Normalize -0.0 so it sorts with 0.0
+            Some(NVValue::Number(n)) => IndexKey::Number(F64Key(if *n == 0.0 { 0.0 } else { *n })),
+            Some(NVValue::String(s)) => IndexKey::String(s.clone()),
+            Some(NVValue::Array(_)) | Some(NVValue::Object(_)) => IndexKey::Other,
+        }
+    }
+
+    /// The value this key was built from, if it can be recovered
+    pub fn to_value(&self) -> Option<NVValue> {
+        match self {
+            IndexKey::Null => Some(NVValue::Null),
+            IndexKey::Bool(b) => Some(NVValue::Bool(*b)),
+            IndexKey::Number(n) => Some(NVValue::Number(n.0)),
+            IndexKey::String(s) => Some(NVValue::String(s.clone())),
+            IndexKey::Missing | IndexKey::Other => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_ordering() {
+        let keys = [
+            IndexKey::Missing,
+            IndexKey::from_value(Some(&NVValue::Null)),
+            IndexKey::from_value(Some(&NVValue::Bool(true))),
+            IndexKey::from_value(Some(&NVValue::Number(-1.5))),
+            IndexKey::from_value(Some(&NVValue::Number(2.0))),
+            IndexKey::from_value(Some(&NVValue::String("a".to_string()))),
+            IndexKey::from_value(Some(&NVValue::Array(vec![]))),
+        ];
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(
+            IndexKey::from_value(Some(&NVValue::Number(-0.0))),
+            IndexKey::from_value(Some(&NVValue::Number(0.0)))
+        );
+    }
+}
@@ -0,0 +1,17 @@
+pub mod completion;
+pub mod highlight;
+pub mod key;
+pub mod quantization;
+pub mod secondary;
+pub mod text;
+pub mod tokenizer;
+pub mod vector;
+
+pub use completion::{CompletionIndex, CompletionIndexDefinition};
+pub use highlight::{Highlight, HighlightOptions};
+pub use key::IndexKey;
+pub use quantization::Quantization;
+pub use secondary::{IndexDefinition, IndexEntry, SecondaryIndex};
+pub use text::{TextIndex, TextIndexDefinition};
+pub use tokenizer::{Language, Tokenizer};
+pub use vector::{VectorIndex, VectorIndexDefinition, VectorMetric};
@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+
+/// Centroids per subvector; codes fit in a byte
+const CENTROIDS: usize = 256;
+
+/// Refinement rounds when training a codebook
+const KMEANS_ITERATIONS: usize = 8;
+
+/// How a vector index stores its vectors, trading accuracy for size
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quantization {
+    /// 4 bytes per dimension, exact
+    #[default]
+    None,
+    /// 1 byte per dimension plus a 4 byte scale per vector
+    Int8,
+    /// 1 byte per subvector, each the nearest of 256 trained centroids
+    ///
+    /// `subvectors` must divide the index's dimensions. Vectors are kept
+    /// unquantized until enough exist to train the centroids on.
+    Product { subvectors: usize },
+}
+
+/// A stored vector
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Encoded {
+    Full(Vec<f32>),
+    /// Components are `code * scale`
+    Int8 { scale: f32, codes: Vec<i8> },
+    /// Centroid per subvector
+    Product(Vec<u8>),
+}
+
+impl Encoded {
+    /// Scalar-quantize a vector against its largest component
+    pub fn int8(vector: &[f32]) -> Self {
+        let max = vector.iter().fold(0.0f32, |max, x| max.max(x.abs()));
+        let scale = if max > 0.0 { max / 127.0 } else { 1.0 };
+        Encoded::Int8 {
+            scale,
+            codes: vector.iter().map(|x| (x / scale).round() as i8).collect(),
+        }
+    }
+
+    /// Approximate size in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Encoded::Full(vector) => vector.len() * 4,
+            Encoded::Int8 { codes, .. } => codes.len() + 4,
+            Encoded::Product(codes) => codes.len(),
+        }
+    }
+}
+
+/// Trained centroids for product quantization
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Codebook {
+    /// Dimensions per subvector
+    width: usize,
+    /// Per subvector, its centroids laid end to end
+    centroids: Vec<Vec<f32>>,
+}
+
+impl Codebook {
+    /// Train centroids for `subvectors` slices of the given vectors with
+    /// k-means
+    ///
+    /// Initial centroids are spread evenly through the input, so training
+    /// the same vectors gives the same codebook.
+    pub fn train(vectors: &[&[f32]], subvectors: usize) -> Self {
+        let width = vectors[0].len() / subvectors;
+        let k = CENTROIDS.min(vectors.len());
+        let centroids = (0..subvectors)
+            .map(|s| {
+                let slice = |v: &[f32]| v[s * width..(s + 1) * width].to_vec();
+                let mut centroids: Vec<Vec<f32>> =
+                    (0..k).map(|i| slice(vectors[i * vectors.len() / k])).collect();
+
+                for _ in 0..KMEANS_ITERATIONS {
+                    let mut sums = vec![vec![0.0f32; width]; k];
+                    let mut counts = vec![0usize; k];
+                    for vector in vectors {
+                        let point = &vector[s * width..(s + 1) * width];
+                        let nearest = nearest(centroids.iter().map(Vec::as_slice), point);
+                        counts[nearest] += 1;
+                        sums[nearest].iter_mut().zip(point).for_each(|(sum, x)| *sum += x);
+                    }
+                    // Empty clusters keep their previous centroid
+                    for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+                        if count > 0 {
+                            *centroid = sum.into_iter().map(|x| x / count as f32).collect();
+                        }
+                    }
+                }
+                centroids.concat()
+            })
+            .collect();
+
+        Self { width, centroids }
+    }
+
+    pub fn encode(&self, vector: &[f32]) -> Encoded {
+        Encoded::Product(
+            self.centroids
+                .iter()
+                .enumerate()
+                .map(|(s, centroids)| {
+                    let point = &vector[s * self.width..(s + 1) * self.width];
+                    nearest(centroids.chunks(self.width), point) as u8
+                })
+                .collect(),
+        )
+    }
+
+    /// The components of a product-quantized vector
+    pub fn decode<'a>(&'a self, codes: &'a [u8]) -> impl Iterator<Item = f32> + 'a {
+        codes.iter().zip(&self.centroids).flat_map(move |(&code, centroids)| {
+            let start = code as usize * self.width;
+            centroids[start..start + self.width].iter().copied()
+        })
+    }
+}
+
+/// Index of the centroid closest to `point`
+fn nearest<'a>(centroids: impl Iterator<Item = &'a [f32]>, point: &[f32]) -> usize {
+    centroids
+        .map(|centroid| centroid.iter().zip(point).map(|(c, x)| (c - x) * (c - x)).sum::<f32>())
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map_or(0, |(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_int8_round_trip() {
+        let vector = [0.5, -1.0, 0.25, 0.0];
+        let Encoded::Int8 { scale, codes } = Encoded::int8(&vector) else {
+            panic!("expected int8 codes");
+        };
+        assert_eq!(codes, vec![64, -127, 32, 0]);
+        for (code, x) in codes.iter().zip(vector) {
+            assert!((*code as f32 * scale - x).abs() < 0.01);
+        }
+        assert_eq!(Encoded::int8(&vector).size(), 8);
+        // Re-encoding decoded values gives the same codes
+        let decoded: Vec<f32> = codes.iter().map(|&c| c as f32 * scale).collect();
+        assert_eq!(Encoded::int8(&decoded), Encoded::int8(&vector));
+    }
+
+    #[test]
+    fn test_product_quantization() {
+        // Two well separated clusters per subvector
+        let vectors: Vec<Vec<f32>> = (0..40)
+            .map(|i| {
+                let jitter = (i % 5) as f32 * 0.01;
+                if i % 2 == 0 {
+                    vec![1.0 + jitter, 1.0, -1.0, -1.0 - jitter]
+                } else {
+                    vec![-1.0 - jitter, -1.0, 1.0, 1.0 + jitter]
+                }
+            })
+            .collect();
+        let slices: Vec<&[f32]> = vectors.iter().map(Vec::as_slice).collect();
+        let codebook = Codebook::train(&slices, 2);
+
+        let encoded = codebook.encode(&vectors[0]);
+        assert_eq!(encoded.size(), 2);
+        let Encoded::Product(codes) = &encoded else {
+            panic!("expected product codes");
+        };
+        let decoded: Vec<f32> = codebook.decode(codes).collect();
+        assert_eq!(decoded.len(), 4);
+        for (x, y) in decoded.iter().zip(&vectors[0]) {
+            assert!((x - y).abs() < 0.05);
+        }
+        assert_eq!(codebook.encode(&decoded), encoded);
+    }
+}
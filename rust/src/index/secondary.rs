@@ -0,0 +1,298 @@
+use crate::index::key::IndexKey;
+use crate::models::{NVDocument, QueryCondition};
+use crate::query::QueryProcessor;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+
+/// Definition of a secondary index
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexDefinition {
+    pub name: String,
+    pub collection: String,
+    /// Indexed fields, most significant first
+    pub fields: Vec<String>,
+    /// Only documents satisfying all of these conditions are indexed
+    #[serde(default)]
+    pub filter: Vec<QueryCondition>,
+}
+
+impl IndexDefinition {
+    /// Whether this is a partial index
+    pub fn is_partial(&self) -> bool {
+        !self.filter.is_empty()
+    }
+}
+
+/// A document's keys and ID, as stored in a `SecondaryIndex`
+pub type IndexEntry = (Vec<IndexKey>, String);
+
+/// In-memory ordered index over one or more fields of a collection
+///
+/// Entries are `(key, document ID)` pairs, so documents sharing a key are
+/// ordered by ID and every document appears at most once. Timestamps are
+/// kept alongside so covered queries can be answered from the index alone.
+#[derive(Debug, Clone)]
+pub struct SecondaryIndex {
+    definition: IndexDefinition,
+    entries: BTreeSet<IndexEntry>,
+    timestamps: HashMap<String, (DateTime<Utc>, DateTime<Utc>)>,
+}
+
+impl SecondaryIndex {
+    pub fn new(definition: IndexDefinition) -> Self {
+        Self {
+            definition,
+            entries: BTreeSet::new(),
+            timestamps: HashMap::new(),
+        }
+    }
+
+    pub fn definition(&self) -> &IndexDefinition {
+        &self.definition
+    }
+
+    /// Number of indexed documents
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether a document belongs in this index
+    pub fn covers(&self, document: &NVDocument, processor: &QueryProcessor) -> bool {
+        document.collection == self.definition.collection
+            && !document.deleted
+            && self
+                .definition
+                .filter
+                .iter()
+                .all(|condition| processor.matches_condition(document, condition))
+    }
+
+    /// Index a document if it belongs in this index
+    pub fn add(&mut self, document: &NVDocument, processor: &QueryProcessor) {
+        if self.covers(document, processor) {
+            self.entries.insert((self.key_of(document), document.id.clone()));
+            self.timestamps
+                .insert(document.id.clone(), (document.created_at, document.updated_at));
+        }
+    }
+
+    /// Remove a previously indexed version of a document
+    pub fn remove(&mut self, document: &NVDocument, processor: &QueryProcessor) {
+        if self.covers(document, processor) {
+            self.entries.remove(&(self.key_of(document), document.id.clone()));
+            self.timestamps.remove(&document.id);
+        }
+    }
+
+    /// IDs of documents whose first indexed field lies within the bounds
+    pub fn range(&self, lower: Bound<IndexKey>, upper: Bound<IndexKey>) -> Vec<String> {
+        let start = match &lower {
+            Bound::Included(key) | Bound::Excluded(key) => {
+                Bound::Included((vec![key.clone()], String::new()))
+            }
+            Bound::Unbounded => Bound::Unbounded,
+        };
+
+        let upper_ok = |key: &IndexKey| match &upper {
+            Bound::Included(bound) => key <= bound,
+            Bound::Excluded(bound) => key < bound,
+            Bound::Unbounded => true,
+        };
+
+        self.entries
+            .range((start, Bound::Unbounded))
+            .skip_while(|(key, _)| matches!(&lower, Bound::Excluded(bound) if key[0] == *bound))
+            .take_while(|(key, _)| upper_ok(&key[0]))
+            .map(|(_, id)| id.clone())
+            .collect()
+    }
+
+    /// Whether walking the index sorts documents by the first field the
+    /// way the query processor does
+    ///
+    /// The processor only orders numbers, strings and booleans among
+    /// their own type, and NaN against nothing, so the walk agrees with it
+    /// only when the present values are all of one of those types and no
+    /// number is NaN.
+    pub fn orders_like_processor(&self) -> bool {
+        let mut present = self.entries.range((vec![IndexKey::Null], String::new())..);
+        let first = present.next();
+        let last = present.next_back().or(first);
+        match (first, last) {
+            (Some((first, _)), Some((last, _))) => match (&first[0], &last[0]) {
+                (IndexKey::Number(a), IndexKey::Number(b)) => !a.0.is_nan() && !b.0.is_nan(),
+                (IndexKey::String(_), IndexKey::String(_)) | (IndexKey::Bool(_), IndexKey::Bool(_)) => true,
+                _ => false,
+            },
+            _ => true,
+        }
+    }
+
+    /// Up to `limit` entries following `after`, in the order the query
+    /// processor sorts by the first field
+    ///
+    /// Ascending, documents missing the field come last; descending is the
+    /// exact reverse. Entries tied on the first field are ordered by the
+    /// remaining fields, not as the processor breaks ties.
+    pub fn ordered_page(&self, descending: bool, after: Option<&IndexEntry>, limit: usize) -> Vec<IndexEntry> {
+        let first_present = (vec![IndexKey::Null], String::new());
+        let all_present = (Bound::Included(&first_present), Bound::Unbounded);
+        let all_missing = (Bound::Unbounded, Bound::Excluded(&first_present));
+        let nothing = (Bound::Included(&first_present), Bound::Excluded(&first_present));
+        let (present, missing) = match after {
+            None => (all_present, all_missing),
+            Some(after) => match (descending, after.0[0] == IndexKey::Missing) {
+                (false, false) => ((Bound::Excluded(after), Bound::Unbounded), all_missing),
+                (false, true) => (nothing, (Bound::Excluded(after), Bound::Excluded(&first_present))),
+                (true, false) => ((Bound::Included(&first_present), Bound::Excluded(after)), nothing),
+                (true, true) => (all_present, (Bound::Unbounded, Bound::Excluded(after))),
+            },
+        };
+
+        let present = self.entries.range(present);
+        let missing = self.entries.range(missing);
+        let entries: Box<dyn Iterator<Item = &IndexEntry>> = if descending {
+            Box::new(missing.rev().chain(present.rev()))
+        } else {
+            Box::new(present.chain(missing))
+        };
+        entries.take(limit).cloned().collect()
+    }
+
+    /// Rebuild every indexed document from its keys alone
+    ///
+    /// Only the indexed fields are present in the returned documents.
+    /// Returns `None` if any key can't be turned back into its value.
+    pub fn documents(&self) -> Option<Vec<NVDocument>> {
+        self.entries
+            .iter()
+            .map(|(key, id)| {
+                let mut data = HashMap::new();
+                for (field, key) in self.definition.fields.iter().zip(key) {
+                    match key {
+                        IndexKey::Missing => {}
+                        key => {
+                            data.insert(field.clone(), key.to_value()?);
+                        }
+                    }
+                }
+
+                let mut document =
+                    NVDocument::new(id.clone(), self.definition.collection.clone(), data);
+                if let Some((created_at, updated_at)) = self.timestamps.get(id) {
+                    document.created_at = *created_at;
+                    document.updated_at = *updated_at;
+                }
+                Some(document)
+            })
+            .collect()
+    }
+
+    fn key_of(&self, document: &NVDocument) -> Vec<IndexKey> {
+        self.definition
+            .fields
+            .iter()
+            .map(|field| IndexKey::from_value(document.field(field).as_deref()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{NVValue, QueryOperator};
+    use std::collections::HashMap;
+
+    fn task(id: &str, status: &str, priority: f64) -> NVDocument {
+        let mut data = HashMap::new();
+        data.insert("status".to_string(), NVValue::String(status.to_string()));
+        data.insert("priority".to_string(), NVValue::Number(priority));
+        NVDocument::new(id.to_string(), "tasks".to_string(), data)
+    }
+
+    #[test]
+    fn test_partial_index_range() {
+        let processor = QueryProcessor::new();
+        let mut index = SecondaryIndex::new(IndexDefinition {
+            name: "open_by_priority".to_string(),
+            collection: "tasks".to_string(),
+            fields: vec!["priority".to_string()],
+            filter: vec![QueryCondition {
+                field: "status".to_string(),
+                operator: QueryOperator::Equals,
+                value: NVValue::String("open".to_string()),
+            }],
+        });
+
+        let done = task("d", "done", 5.0);
+        for doc in [task("a", "open", 1.0), task("b", "open", 3.0), task("c", "open", 5.0), done.clone()] {
+            index.add(&doc, &processor);
+        }
+        // Documents outside the filter are never indexed
+        assert_eq!(index.len(), 3);
+        index.remove(&done, &processor);
+        assert_eq!(index.len(), 3);
+
+        let number = |n| IndexKey::from_value(Some(&NVValue::Number(n)));
+        assert_eq!(
+            index.range(Bound::Excluded(number(1.0)), Bound::Included(number(5.0))),
+            vec!["b".to_string(), "c".to_string()]
+        );
+        assert_eq!(
+            index.range(Bound::Unbounded, Bound::Excluded(number(3.0))),
+            vec!["a".to_string()]
+        );
+        let ids = |page: Vec<IndexEntry>| page.into_iter().map(|(_, id)| id).collect::<Vec<_>>();
+        assert_eq!(ids(index.ordered_page(true, None, 10)), vec!["c", "b", "a"]);
+
+        assert!(index.orders_like_processor());
+        let covered = index.documents().unwrap();
+        assert_eq!(covered[0].id, "a");
+        assert_eq!(covered[0].get("priority"), Some(&NVValue::Number(1.0)));
+        assert_eq!(covered[0].get("status"), None);
+    }
+
+    #[test]
+    fn test_ordered_pages() {
+        let processor = QueryProcessor::new();
+        let mut index = SecondaryIndex::new(IndexDefinition {
+            name: "by_priority".to_string(),
+            collection: "tasks".to_string(),
+            fields: vec!["priority".to_string()],
+            filter: Vec::new(),
+        });
+        for doc in [task("a", "open", 2.0), task("b", "open", 1.0), task("c", "open", 2.0)] {
+            index.add(&doc, &processor);
+        }
+        index.add(&NVDocument::new("d".to_string(), "tasks".to_string(), HashMap::new()), &processor);
+        index.add(&NVDocument::new("e".to_string(), "tasks".to_string(), HashMap::new()), &processor);
+
+        // Walk two entries at a time, resuming after the last one seen
+        let walk = |descending| {
+            let mut ids = Vec::new();
+            let mut after = None;
+            loop {
+                let page = index.ordered_page(descending, after.as_ref(), 2);
+                let Some(last) = page.last().cloned() else { break };
+                ids.extend(page.into_iter().map(|(_, id)| id));
+                after = Some(last);
+            }
+            ids
+        };
+        assert_eq!(walk(false), vec!["b", "a", "c", "d", "e"]);
+        assert_eq!(walk(true), vec!["e", "d", "c", "a", "b"]);
+        assert!(index.orders_like_processor());
+
+        // Numbers and strings don't sort against each other
+        let mut mixed = task("f", "open", 0.0);
+        mixed.data.insert("priority".to_string(), NVValue::String("high".to_string()));
+        index.add(&mixed, &processor);
+        assert!(!index.orders_like_processor());
+    }
+}
@@ -0,0 +1,258 @@
+use super::tokenizer::Tokenizer;
+use crate::models::{NVDocument, NVValue};
+use crate::query::fuzzy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// BM25 term frequency saturation
+const K1: f32 = 1.2;
+
+/// BM25 document length normalization
+const B: f32 = 0.75;
+
+/// Definition of a full-text index over one or more string fields
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextIndexDefinition {
+    pub name: String,
+    pub collection: String,
+    /// Fields whose text is indexed; arrays of strings are indexed too
+    pub fields: Vec<String>,
+    #[serde(default)]
+    pub tokenizer: Tokenizer,
+}
+
+/// In-memory inverted index ranking documents by BM25
+///
+/// Each document's terms are kept so a new version can replace the old
+/// one without having to read it back.
+#[derive(Debug, Clone)]
+pub struct TextIndex {
+    definition: TextIndexDefinition,
+    /// Term to document ID to number of occurrences
+    postings: HashMap<String, HashMap<String, u32>>,
+    /// Document ID to its distinct terms and their total count
+    documents: HashMap<String, (Vec<String>, u32)>,
+    total_terms: u64,
+}
+
+impl TextIndex {
+    pub fn new(definition: TextIndexDefinition) -> Self {
+        Self {
+            definition,
+            postings: HashMap::new(),
+            documents: HashMap::new(),
+            total_terms: 0,
+        }
+    }
+
+    pub fn definition(&self) -> &TextIndexDefinition {
+        &self.definition
+    }
+
+    /// Number of indexed documents
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// Index the current version of a document, replacing any earlier one
+    pub fn add(&mut self, document: &NVDocument) {
+        self.remove(&document.id);
+        if document.collection != self.definition.collection || document.deleted {
+            return;
+        }
+
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for field in &self.definition.fields {
+            match document.get(field) {
+                Some(NVValue::String(text)) => self.count_terms(text, &mut counts),
+                Some(NVValue::Array(values)) => {
+                    for value in values {
+                        if let NVValue::String(text) = value {
+                            self.count_terms(text, &mut counts);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if counts.is_empty() {
+            return;
+        }
+
+        let length: u32 = counts.values().sum();
+        let terms = counts.keys().cloned().collect();
+        for (term, count) in counts {
+            self.postings.entry(term).or_default().insert(document.id.clone(), count);
+        }
+        self.documents.insert(document.id.clone(), (terms, length));
+        self.total_terms += length as u64;
+    }
+
+    /// Remove a document from the index
+    pub fn remove(&mut self, id: &str) {
+        let Some((terms, length)) = self.documents.remove(id) else {
+            return;
+        };
+        for term in terms {
+            if let Some(postings) = self.postings.get_mut(&term) {
+                postings.remove(id);
+                if postings.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+        self.total_terms -= length as u64;
+    }
+
+    /// Up to `k` `(document ID, score)` pairs matching any term of `text`,
+    /// best first
+    pub fn search(&self, text: &str, k: usize) -> Vec<(String, f32)> {
+        self.search_fuzzy(text, k, 0)
+    }
+
+    /// Like `search`, also matching indexed terms within `max_distance`
+    /// edits of a query term
+    ///
+    /// A term `d` edits away scores `1 / (1 + d)` of an exact match.
+    pub fn search_fuzzy(&self, text: &str, k: usize, max_distance: usize) -> Vec<(String, f32)> {
+        if self.documents.is_empty() {
+            return Vec::new();
+        }
+        let mut terms = self.definition.tokenizer.tokenize(text);
+        terms.sort();
+        terms.dedup();
+
+        let n = self.documents.len() as f32;
+        let average_length = self.total_terms as f32 / n;
+        let mut scores: HashMap<&str, f32> = HashMap::new();
+        for term in &terms {
+            let matching: Vec<(&HashMap<String, u32>, f32)> = if max_distance == 0 {
+                self.postings.get(term).map(|postings| (postings, 1.0)).into_iter().collect()
+            } else {
+                self.postings
+                    .iter()
+                    .filter_map(|(candidate, postings)| {
+                        let distance = fuzzy::distance(term, candidate, max_distance)?;
+                        Some((postings, 1.0 / (1.0 + distance as f32)))
+                    })
+                    .collect()
+            };
+            for (postings, weight) in matching {
+                let df = postings.len() as f32;
+                let idf = (1.0 + (n - df + 0.5) / (df + 0.5)).ln();
+                for (id, &count) in postings {
+                    let length = self.documents[id].1 as f32;
+                    let tf = count as f32;
+                    let score = idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * length / average_length));
+                    *scores.entry(id.as_str()).or_default() += weight * score;
+                }
+            }
+        }
+
+        let mut ranked: Vec<(String, f32)> = scores
+            .into_iter()
+            .map(|(id, score)| (id.to_string(), score))
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ranked.truncate(k);
+        ranked
+    }
+
+    fn count_terms(&self, text: &str, counts: &mut HashMap<String, u32>) {
+        for term in self.definition.tokenizer.tokenize(text) {
+            *counts.entry(term).or_default() += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn article(id: &str, title: &str, body: &str) -> NVDocument {
+        let mut data = HashMap::new();
+        data.insert("title".to_string(), NVValue::String(title.to_string()));
+        data.insert("body".to_string(), NVValue::String(body.to_string()));
+        NVDocument::new(id.to_string(), "articles".to_string(), data)
+    }
+
+    fn index() -> TextIndex {
+        TextIndex::new(TextIndexDefinition {
+            name: "article_text".to_string(),
+            collection: "articles".to_string(),
+            fields: vec!["title".to_string(), "body".to_string()],
+            tokenizer: Tokenizer::default(),
+        })
+    }
+
+    #[test]
+    fn test_bm25_ranking() {
+        let mut index = index();
+        index.add(&article("a", "Rust storage engines", "Notes on log-structured storage."));
+        index.add(&article("b", "Gardening", "Tomatoes need sun; storage of seeds is easy."));
+        index.add(&article("c", "Cooking", "Pasta, sauce and more pasta."));
+
+        let results = index.search("STORAGE engines", 10);
+        let ids: Vec<&str> = results.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert!(results[0].1 > results[1].1);
+
+        assert!(index.search("nothing matches", 10).is_empty());
+        assert_eq!(index.search("storage", 1).len(), 1);
+    }
+
+    #[test]
+    fn test_replace_and_remove() {
+        let mut index = index();
+        index.add(&article("a", "Rust", "ownership and borrowing"));
+        index.add(&article("a", "Go", "goroutines"));
+        assert_eq!(index.len(), 1);
+        assert!(index.search("borrowing", 10).is_empty());
+        assert_eq!(index.search("goroutines", 10)[0].0, "a");
+
+        index.remove("a");
+        assert!(index.is_empty());
+        assert!(index.postings.is_empty());
+        assert_eq!(index.total_terms, 0);
+    }
+
+    #[test]
+    fn test_fuzzy_search() {
+        let mut index = index();
+        index.add(&article("a", "Payments", "How to receive payments"));
+        index.add(&article("b", "Receipts", "Keep every receipt"));
+
+        assert!(index.search("recieve", 10).is_empty());
+        let results = index.search_fuzzy("recieve paymnts", 10, 1);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, "a");
+        assert_eq!(index.search_fuzzy("receipts", 10, 1)[0].0, "b");
+
+        // Exact matches outrank fuzzy ones
+        index.add(&article("c", "Payments", "How to recieve payments"));
+        let ids: Vec<String> = index.search_fuzzy("receive", 10, 1).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, vec!["a", "c"]);
+    }
+
+    #[test]
+    fn test_queries_use_the_index_tokenizer() {
+        let mut index = TextIndex::new(TextIndexDefinition {
+            tokenizer: Tokenizer {
+                stemming: true,
+                stop_words: true,
+                ..Default::default()
+            },
+            ..index().definition
+        });
+        index.add(&article("a", "Indexing", "The engine indexes documents"));
+        index.add(&article("b", "Cooking", "The pasta"));
+
+        assert_eq!(index.search("indexed document", 10)[0].0, "a");
+        // Stop words match nothing
+        assert!(index.search("the", 10).is_empty());
+    }
+}
@@ -0,0 +1,369 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::ops::Range;
+
+/// Language whose stemming rules and stop words a tokenizer uses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    English,
+    French,
+    German,
+    Spanish,
+}
+
+impl Language {
+    /// Common words that rarely help a search
+    pub fn stop_words(self) -> &'static [&'static str] {
+        match self {
+            Language::English => &[
+                "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "has", "have",
+                "he", "her", "his", "i", "in", "is", "it", "its", "not", "of", "on", "or", "she",
+                "that", "the", "their", "them", "they", "this", "to", "was", "we", "were", "will",
+                "with", "you",
+            ],
+            Language::French => &[
+                "au", "aux", "avec", "ce", "ces", "dans", "de", "des", "du", "elle", "en", "est",
+                "et", "il", "ils", "je", "la", "le", "les", "leur", "lui", "mais", "me", "mes", "ne",
+                "nous", "on", "ou", "par", "pas", "pour", "qu", "que", "qui", "sa", "se", "ses",
+                "son", "sur", "te", "tu", "un", "une", "vous",
+            ],
+            Language::German => &[
+                "aber", "als", "am", "an", "auch", "auf", "aus", "bei", "bis", "das", "dass", "dem",
+                "den", "der", "des", "die", "du", "ein", "eine", "einem", "einen", "einer", "eines",
+                "er", "es", "für", "hat", "ich", "im", "in", "ist", "mit", "nach", "nicht", "noch",
+                "oder", "sie", "sind", "um", "und", "von", "vor", "war", "wie", "wir", "zu", "zum",
+                "zur",
+            ],
+            Language::Spanish => &[
+                "a", "al", "como", "con", "de", "del", "el", "ella", "ellos", "en", "entre", "es",
+                "esta", "este", "la", "las", "le", "les", "lo", "los", "me", "mi", "muy", "no",
+                "nos", "o", "para", "pero", "por", "que", "se", "sin", "sobre", "su", "sus", "te",
+                "tu", "un", "una", "uno", "y", "ya",
+            ],
+        }
+    }
+
+    /// Reduce a lowercase word to its stem
+    ///
+    /// English uses the Porter algorithm; the other languages strip common
+    /// inflectional suffixes.
+    pub fn stem(self, word: &str) -> String {
+        match self {
+            Language::English => porter(word),
+            Language::French => strip_suffix(
+                word,
+                &[
+                    "issements", "issement", "atrices", "ements", "ations", "atrice", "ation",
+                    "ement", "ances", "euses", "ance", "euse", "ités", "ives", "eaux", "ité", "ive",
+                    "eau", "ifs", "es", "if", "s", "x", "e",
+                ],
+            ),
+            Language::German => strip_suffix(
+                word,
+                &["heiten", "keiten", "ungen", "heit", "keit", "ung", "ern", "em", "en", "er", "es", "e", "s"],
+            ),
+            Language::Spanish => strip_suffix(
+                word,
+                &[
+                    "amientos", "imientos", "aciones", "amiento", "imiento", "idades", "ación",
+                    "mente", "idad", "es", "as", "os", "a", "o", "s",
+                ],
+            ),
+        }
+    }
+}
+
+/// How a text index splits text into terms
+///
+/// The same rules apply to indexed documents and to queries. The default
+/// lowercases alphanumeric runs and nothing more.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tokenizer {
+    pub language: Language,
+    /// Reduce words to their stems, so "running" matches "runs"
+    pub stemming: bool,
+    /// Drop the language's stop words
+    pub stop_words: bool,
+    /// Further words to drop, matched case-insensitively
+    pub extra_stop_words: Vec<String>,
+    /// Split runs of Chinese, Japanese and Korean characters, which aren't
+    /// separated by spaces, into overlapping n-grams of this many characters
+    pub cjk_ngrams: Option<usize>,
+}
+
+impl Tokenizer {
+    /// Split text into terms
+    pub fn tokenize(&self, text: &str) -> Vec<String> {
+        self.spans(text).into_iter().map(|(term, _)| term).collect()
+    }
+
+    /// Split text into terms with the byte range each came from
+    pub fn spans(&self, text: &str) -> Vec<(String, Range<usize>)> {
+        let extra: HashSet<String> = self.extra_stop_words.iter().map(|w| w.to_lowercase()).collect();
+        let mut terms = Vec::new();
+        for run in text.split(|c: char| !c.is_alphanumeric()).filter(|run| !run.is_empty()) {
+            // Runs are slices of `text`
+            let start = run.as_ptr() as usize - text.as_ptr() as usize;
+            let Some(n) = self.cjk_ngrams else {
+                self.push_word(run, start, &extra, &mut terms);
+                continue;
+            };
+
+            let mut rest = run;
+            let mut offset = start;
+            while let Some(first) = rest.chars().next() {
+                let cjk = is_cjk(first);
+                let end = rest.find(|c| is_cjk(c) != cjk).unwrap_or(rest.len());
+                let (part, tail) = rest.split_at(end);
+                if cjk {
+                    let chars: Vec<(usize, char)> = part.char_indices().collect();
+                    if chars.len() <= n {
+                        terms.push((part.to_string(), offset..offset + part.len()));
+                    } else {
+                        for gram in chars.windows(n) {
+                            let (first, last) = (gram[0].0, gram[n - 1].0 + gram[n - 1].1.len_utf8());
+                            terms.push((part[first..last].to_string(), offset + first..offset + last));
+                        }
+                    }
+                } else {
+                    self.push_word(part, offset, &extra, &mut terms);
+                }
+                offset += part.len();
+                rest = tail;
+            }
+        }
+        terms
+    }
+
+    fn push_word(&self, word: &str, start: usize, extra: &HashSet<String>, terms: &mut Vec<(String, Range<usize>)>) {
+        let range = start..start + word.len();
+        let word = word.to_lowercase();
+        if (self.stop_words && self.language.stop_words().contains(&word.as_str())) || extra.contains(&word) {
+            return;
+        }
+        terms.push((if self.stemming { self.language.stem(&word) } else { word }, range));
+    }
+}
+
+/// Whether a character belongs to a script written without spaces
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF       // Hiragana and Katakana
+        | 0x3400..=0x4DBF     // CJK extension A
+        | 0x4E00..=0x9FFF     // CJK unified ideographs
+        | 0xAC00..=0xD7AF     // Hangul syllables
+        | 0xF900..=0xFAFF     // CJK compatibility ideographs
+        | 0x20000..=0x2A6DF)  // CJK extension B
+}
+
+/// Remove the first matching suffix that leaves at least three characters
+fn strip_suffix(word: &str, suffixes: &[&str]) -> String {
+    for suffix in suffixes {
+        if let Some(stem) = word.strip_suffix(suffix) {
+            if stem.chars().count() >= 3 {
+                return stem.to_string();
+            }
+        }
+    }
+    word.to_string()
+}
+
+/// The Porter stemmer, for lowercase ASCII words
+fn porter(word: &str) -> String {
+    if word.len() <= 2 || !word.bytes().all(|b| b.is_ascii_lowercase()) {
+        return word.to_string();
+    }
+    let mut w = word.as_bytes().to_vec();
+
+    // Step 1a: plurals
+    if w.ends_with(b"sses") || w.ends_with(b"ies") {
+        w.truncate(w.len() - 2);
+    } else if w.ends_with(b"s") && !w.ends_with(b"ss") {
+        w.pop();
+    }
+
+    // Step 1b: past tenses and gerunds
+    if w.ends_with(b"eed") {
+        if measure(&w[..w.len() - 3]) > 0 {
+            w.pop();
+        }
+    } else if let Some(stem) = [&b"ed"[..], b"ing"]
+        .iter()
+        .find(|suffix| w.ends_with(suffix))
+        .map(|suffix| w.len() - suffix.len())
+    {
+        if has_vowel(&w[..stem]) {
+            w.truncate(stem);
+            if w.ends_with(b"at") || w.ends_with(b"bl") || w.ends_with(b"iz") {
+                w.push(b'e');
+            } else if double(&w) && !matches!(w[w.len() - 1], b'l' | b's' | b'z') {
+                w.pop();
+            } else if measure(&w) == 1 && cvc(&w) {
+                w.push(b'e');
+            }
+        }
+    }
+
+    // Step 1c
+    if w.ends_with(b"y") && has_vowel(&w[..w.len() - 1]) {
+        *w.last_mut().unwrap() = b'i';
+    }
+
+    // Steps 2 and 3: map derivational suffixes to simpler ones
+    replace_suffix(
+        &mut w,
+        &[
+            ("ational", "ate"), ("tional", "tion"), ("enci", "ence"), ("anci", "ance"),
+            ("izer", "ize"), ("abli", "able"), ("alli", "al"), ("entli", "ent"), ("eli", "e"),
+            ("ousli", "ous"), ("ization", "ize"), ("ation", "ate"), ("ator", "ate"),
+            ("alism", "al"), ("iveness", "ive"), ("fulness", "ful"), ("ousness", "ous"),
+            ("aliti", "al"), ("iviti", "ive"), ("biliti", "ble"),
+        ],
+    );
+    replace_suffix(
+        &mut w,
+        &[
+            ("icate", "ic"), ("ative", ""), ("alize", "al"), ("iciti", "ic"), ("ical", "ic"),
+            ("ful", ""), ("ness", ""),
+        ],
+    );
+
+    // Step 4: drop suffixes from longer stems
+    const STEP4: &[&str] = &[
+        "ement", "ance", "ence", "able", "ible", "ment", "ant", "ent", "ism", "ate", "iti", "ous",
+        "ive", "ize", "ion", "al", "er", "ic", "ou",
+    ];
+    if let Some(suffix) = STEP4.iter().find(|suffix| w.ends_with(suffix.as_bytes())) {
+        let stem = w.len() - suffix.len();
+        if measure(&w[..stem]) > 1 && (*suffix != "ion" || matches!(w[stem - 1], b's' | b't')) {
+            w.truncate(stem);
+        }
+    }
+
+    // Step 5: tidy up a final e or ll
+    if w.ends_with(b"e") {
+        let stem = &w[..w.len() - 1];
+        let m = measure(stem);
+        if m > 1 || (m == 1 && !cvc(stem)) {
+            w.pop();
+        }
+    }
+    if w.ends_with(b"ll") && measure(&w) > 1 {
+        w.pop();
+    }
+
+    String::from_utf8(w).expect("stemming keeps ASCII")
+}
+
+/// Apply the first rule whose suffix matches, if the stem before it has a
+/// measure above 0
+fn replace_suffix(w: &mut Vec<u8>, rules: &[(&str, &str)]) {
+    if let Some((suffix, replacement)) = rules.iter().find(|(suffix, _)| w.ends_with(suffix.as_bytes())) {
+        let stem = w.len() - suffix.len();
+        if measure(&w[..stem]) > 0 {
+            w.truncate(stem);
+            w.extend_from_slice(replacement.as_bytes());
+        }
+    }
+}
+
+fn consonant(w: &[u8], i: usize) -> bool {
+    match w[i] {
+        b'a' | b'e' | b'i' | b'o' | b'u' => false,
+        b'y' => i == 0 || !consonant(w, i - 1),
+        _ => true,
+    }
+}
+
+/// Number of vowel-consonant sequences
+fn measure(w: &[u8]) -> usize {
+    let mut m = 0;
+    let mut vowel = false;
+    for i in 0..w.len() {
+        if consonant(w, i) {
+            m += vowel as usize;
+            vowel = false;
+        } else {
+            vowel = true;
+        }
+    }
+    m
+}
+
+fn has_vowel(w: &[u8]) -> bool {
+    (0..w.len()).any(|i| !consonant(w, i))
+}
+
+/// Ends in a doubled consonant
+fn double(w: &[u8]) -> bool {
+    let n = w.len();
+    n >= 2 && w[n - 1] == w[n - 2] && consonant(w, n - 1)
+}
+
+/// Ends consonant-vowel-consonant, the last not w, x or y
+fn cvc(w: &[u8]) -> bool {
+    let n = w.len();
+    n >= 3
+        && consonant(w, n - 3)
+        && !consonant(w, n - 2)
+        && consonant(w, n - 1)
+        && !matches!(w[n - 1], b'w' | b'x' | b'y')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_porter_stemmer() {
+        let cases = [
+            ("caresses", "caress"), ("ponies", "poni"), ("cats", "cat"), ("feed", "feed"),
+            ("agreed", "agre"), ("plastered", "plaster"), ("motoring", "motor"), ("sing", "sing"),
+            ("hopping", "hop"), ("falling", "fall"), ("filing", "file"), ("happy", "happi"),
+            ("relational", "relat"), ("conditional", "condit"), ("generalization", "gener"),
+            ("running", "run"), ("runs", "run"), ("controll", "control"), ("naïve", "naïve"),
+        ];
+        for (word, stem) in cases {
+            assert_eq!(porter(word), stem, "stem of {}", word);
+        }
+    }
+
+    #[test]
+    fn test_stop_words_and_stemming() {
+        let tokenizer = Tokenizer {
+            stemming: true,
+            stop_words: true,
+            extra_stop_words: vec!["Vault".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(tokenizer.tokenize("The vault is Running the queries"), vec!["run", "queri"]);
+        assert_eq!(Tokenizer::default().tokenize("The Vault"), vec!["the", "vault"]);
+
+        let german = Tokenizer {
+            language: Language::German,
+            stemming: true,
+            stop_words: true,
+            ..Default::default()
+        };
+        assert_eq!(german.tokenize("Die Zeitungen und die Zeitung"), vec!["zeit", "zeit"]);
+    }
+
+    #[test]
+    fn test_cjk_ngrams() {
+        let tokenizer = Tokenizer {
+            cjk_ngrams: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(tokenizer.tokenize("数据库Rust检索"), vec!["数据", "据库", "rust", "检索"]);
+        assert_eq!(tokenizer.tokenize("中"), vec!["中"]);
+        // Without n-grams a run of ideographs is one term
+        assert_eq!(Tokenizer::default().tokenize("数据库"), vec!["数据库"]);
+
+        let spans = tokenizer.spans("新数据 db");
+        assert_eq!(spans[1], ("数据".to_string(), 3..9));
+        assert_eq!(spans[2], ("db".to_string(), 10..12));
+    }
+}
@@ -0,0 +1,704 @@
+use crate::error::{NeuralVaultError, NVResult};
+use crate::index::quantization::{Codebook, Encoded, Quantization};
+use crate::models::{NVDocument, NVValue};
+use crate::sampling::Rng;
+use crate::storage::index_file::checksum;
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Magic bytes at the start of a vector index file
+const VECTOR_MAGIC: &[u8; 4] = b"NVVX";
+
+/// Current vector index file format version
+const VECTOR_VERSION: u8 = 2;
+
+/// Links kept per node on every layer but the bottom one
+const M: usize = 16;
+
+/// Candidates considered when linking a new node
+const EF_CONSTRUCTION: usize = 100;
+
+/// Candidates considered when searching
+const EF_SEARCH: usize = 64;
+
+/// Highest layer a node can be placed on
+const MAX_LEVEL: usize = 16;
+
+/// Tombstones tolerated before the graph is rebuilt from live nodes
+const MIN_TOMBSTONES: usize = 64;
+
+/// Vectors needed before product quantization centroids are trained
+const PQ_TRAINING_SIZE: usize = 1024;
+
+/// How the distance between two vectors is measured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorMetric {
+    /// `1 - cosine similarity`
+    #[default]
+    Cosine,
+    /// Straight-line distance
+    Euclidean,
+    /// Negated dot product, so larger products rank first
+    Dot,
+}
+
+/// Definition of a vector index over an array-of-numbers field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorIndexDefinition {
+    pub name: String,
+    pub collection: String,
+    pub field: String,
+    /// Only vectors of exactly this length are indexed
+    pub dimensions: usize,
+    #[serde(default)]
+    pub metric: VectorMetric,
+    #[serde(default)]
+    pub quantization: Quantization,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Node {
+    id: String,
+    vector: Encoded,
+    /// Neighbours on each layer the node is part of, bottom layer first
+    links: Vec<Vec<u32>>,
+    deleted: bool,
+}
+
+/// Approximate nearest-neighbour index (HNSW) over one field of a collection
+///
+/// Removed documents are tombstoned rather than unlinked, so they keep the
+/// graph navigable; once tombstones outnumber live nodes the graph is
+/// rebuilt. A node's layer is derived from its document ID, which keeps
+/// rebuilds and replays deterministic.
+///
+/// With quantization, vectors are compressed once linked into the graph;
+/// searches compare the exact query against the compressed vectors.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VectorIndex {
+    definition: VectorIndexDefinition,
+    nodes: Vec<Node>,
+    entry: Option<u32>,
+    tombstones: usize,
+    /// Product quantization centroids, once trained
+    codebook: Option<Codebook>,
+    /// Document ID to live node, rebuilt when loaded
+    #[serde(skip)]
+    ids: HashMap<String, u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate(f32, u32);
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+impl VectorIndex {
+    pub fn new(definition: VectorIndexDefinition) -> Self {
+        Self {
+            definition,
+            nodes: Vec::new(),
+            entry: None,
+            tombstones: 0,
+            codebook: None,
+            ids: HashMap::new(),
+        }
+    }
+
+    pub fn definition(&self) -> &VectorIndexDefinition {
+        &self.definition
+    }
+
+    /// Number of indexed documents
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Bytes taken by the stored vectors, tombstones included
+    pub fn vector_bytes(&self) -> usize {
+        self.nodes.iter().map(|node| node.vector.size()).sum()
+    }
+
+    /// The vector a document contributes to this index, if any
+    fn vector_of(&self, document: &NVDocument) -> Option<Vec<f32>> {
+        if document.collection != self.definition.collection || document.deleted {
+            return None;
+        }
+        let NVValue::Array(values) = document.get(&self.definition.field)? else {
+            return None;
+        };
+        let vector = values
+            .iter()
+            .map(|value| match value {
+                NVValue::Number(n) => Some(*n as f32),
+                _ => None,
+            })
+            .collect::<Option<Vec<f32>>>()?;
+        if vector.len() != self.definition.dimensions {
+            return None;
+        }
+        self.prepare(vector)
+    }
+
+    /// Normalize a vector for the cosine metric; zero vectors have no direction
+    fn prepare(&self, mut vector: Vec<f32>) -> Option<Vec<f32>> {
+        if vector.iter().any(|x| !x.is_finite()) {
+            return None;
+        }
+        if self.definition.metric == VectorMetric::Cosine {
+            let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+            if norm == 0.0 {
+                return None;
+            }
+            vector.iter_mut().for_each(|x| *x /= norm);
+        }
+        Some(vector)
+    }
+
+    /// Index the current version of a document, or drop it if it no longer
+    /// has a usable vector
+    pub fn add(&mut self, document: &NVDocument) {
+        match self.vector_of(document) {
+            Some(vector) => self.insert(document.id.clone(), vector),
+            None => self.remove(&document.id),
+        }
+    }
+
+    /// Remove a document from the index
+    pub fn remove(&mut self, id: &str) {
+        let Some(node) = self.ids.remove(id) else {
+            return;
+        };
+        self.nodes[node as usize].deleted = true;
+        self.tombstones += 1;
+        if self.tombstones >= MIN_TOMBSTONES && self.tombstones > self.ids.len() {
+            self.rebuild();
+        }
+    }
+
+    /// Up to `k` `(document ID, distance)` pairs nearest to `vector`,
+    /// closest first
+    pub fn search(&self, vector: &[f32], k: usize) -> NVResult<Vec<(String, f32)>> {
+        let query = self.prepare_query(vector)?;
+        let Some(entry) = self.entry else {
+            return Ok(Vec::new());
+        };
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut nearest = vec![entry];
+        for level in (1..self.nodes[entry as usize].links.len()).rev() {
+            nearest = vec![self.search_layer(&query, &nearest, 1, level)[0].1];
+        }
+
+        // Tombstones still take up candidate slots, so widen the search
+        let ef = (k + self.tombstones).max(EF_SEARCH);
+        Ok(self
+            .search_layer(&query, &nearest, ef, 0)
+            .into_iter()
+            .filter(|candidate| !self.nodes[candidate.1 as usize].deleted)
+            .take(k)
+            .map(|Candidate(distance, node)| (self.nodes[node as usize].id.clone(), self.reported(distance)))
+            .collect())
+    }
+
+    /// Rank the given documents by distance to `vector` without the graph,
+    /// returning up to `k` closest first
+    ///
+    /// Documents that aren't indexed are skipped.
+    pub fn rank<'a>(
+        &self,
+        vector: &[f32],
+        ids: impl IntoIterator<Item = &'a str>,
+        k: usize,
+    ) -> NVResult<Vec<(String, f32)>> {
+        let query = self.prepare_query(vector)?;
+        let mut ranked: Vec<Candidate> = ids
+            .into_iter()
+            .filter_map(|id| self.ids.get(id))
+            .map(|&node| Candidate(self.distance_to(&query, node), node))
+            .collect();
+        ranked.sort();
+        Ok(ranked
+            .into_iter()
+            .take(k)
+            .map(|Candidate(distance, node)| (self.nodes[node as usize].id.clone(), self.reported(distance)))
+            .collect())
+    }
+
+    /// Check and prepare a query vector like an indexed one
+    fn prepare_query(&self, vector: &[f32]) -> NVResult<Vec<f32>> {
+        if vector.len() != self.definition.dimensions {
+            return Err(NeuralVaultError::InvalidQuery(format!(
+                "Vector index '{}' expects {} dimensions, got {}",
+                self.definition.name,
+                self.definition.dimensions,
+                vector.len()
+            )));
+        }
+        self.prepare(vector.to_vec()).ok_or_else(|| {
+            NeuralVaultError::InvalidQuery("Query vector must be finite and non-zero".to_string())
+        })
+    }
+
+    /// A graph distance as reported to callers
+    fn reported(&self, distance: f32) -> f32 {
+        match self.definition.metric {
+            VectorMetric::Euclidean => distance.sqrt(),
+            _ => distance,
+        }
+    }
+
+    fn insert(&mut self, id: String, query: Vec<f32>) {
+        let vector = self.encode(&query);
+        if let Some(&node) = self.ids.get(&id) {
+            if self.nodes[node as usize].vector == vector {
+                return;
+            }
+            self.remove(&id);
+        }
+
+        let level = level_for(&id);
+        let node = self.nodes.len() as u32;
+        self.nodes.push(Node {
+            id: id.clone(),
+            vector,
+            links: vec![Vec::new(); level + 1],
+            deleted: false,
+        });
+        self.ids.insert(id, node);
+        self.link_node(node, level, &query);
+
+        let untrained = matches!(self.definition.quantization, Quantization::Product { .. })
+            && self.codebook.is_none();
+        if untrained && self.ids.len() >= PQ_TRAINING_SIZE {
+            self.train();
+        }
+    }
+
+    /// Connect a new node on every layer up to `level`
+    fn link_node(&mut self, node: u32, level: usize, query: &[f32]) {
+        let Some(entry) = self.entry else {
+            self.entry = Some(node);
+            return;
+        };
+        let top = self.nodes[entry as usize].links.len() - 1;
+
+        let mut nearest = vec![entry];
+        for layer in (level + 1..=top).rev() {
+            nearest = vec![self.search_layer(query, &nearest, 1, layer)[0].1];
+        }
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(query, &nearest, EF_CONSTRUCTION, layer);
+            let links: Vec<u32> = found.iter().take(max_links(layer)).map(|c| c.1).collect();
+            for &neighbour in &links {
+                self.link(neighbour, node, layer);
+            }
+            self.nodes[node as usize].links[layer] = links;
+            nearest = found.into_iter().map(|c| c.1).collect();
+        }
+
+        if level > top {
+            self.entry = Some(node);
+        }
+    }
+
+    /// Add a link, keeping only the closest neighbours once full
+    fn link(&mut self, from: u32, to: u32, layer: usize) {
+        let links = &mut self.nodes[from as usize].links[layer];
+        links.push(to);
+        if links.len() <= max_links(layer) {
+            return;
+        }
+
+        let origin = self.decode(from);
+        let mut scored: Vec<Candidate> = self.nodes[from as usize].links[layer]
+            .iter()
+            .map(|&n| Candidate(self.distance_to(&origin, n), n))
+            .collect();
+        scored.sort();
+        scored.truncate(max_links(layer));
+        self.nodes[from as usize].links[layer] = scored.into_iter().map(|c| c.1).collect();
+    }
+
+    /// Best-first search of one layer, returning up to `ef` nodes closest first
+    fn search_layer(&self, query: &[f32], entry: &[u32], ef: usize, layer: usize) -> Vec<Candidate> {
+        let mut visited: HashSet<u32> = entry.iter().copied().collect();
+        let mut candidates = BinaryHeap::new();
+        let mut nearest = BinaryHeap::new();
+        for &node in entry {
+            let candidate = Candidate(self.distance_to(query, node), node);
+            candidates.push(Reverse(candidate));
+            nearest.push(candidate);
+        }
+
+        while let Some(Reverse(Candidate(distance, node))) = candidates.pop() {
+            let furthest = nearest.peek().map_or(f32::INFINITY, |c: &Candidate| c.0);
+            if nearest.len() >= ef && distance > furthest {
+                break;
+            }
+            let Some(links) = self.nodes[node as usize].links.get(layer) else {
+                continue;
+            };
+            for &neighbour in links {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let candidate = Candidate(self.distance_to(query, neighbour), neighbour);
+                let furthest = nearest.peek().map_or(f32::INFINITY, |c: &Candidate| c.0);
+                if nearest.len() < ef || candidate.0 < furthest {
+                    candidates.push(Reverse(candidate));
+                    nearest.push(candidate);
+                    if nearest.len() > ef {
+                        nearest.pop();
+                    }
+                }
+            }
+        }
+
+        nearest.into_sorted_vec()
+    }
+
+    /// Distance from `query` to a stored vector; Euclidean stays squared
+    /// until reported
+    fn distance_to(&self, query: &[f32], node: u32) -> f32 {
+        match &self.nodes[node as usize].vector {
+            Encoded::Full(vector) => self.distance(query, vector.iter().copied()),
+            Encoded::Int8 { scale, codes } => {
+                self.distance(query, codes.iter().map(|&code| code as f32 * scale))
+            }
+            Encoded::Product(codes) => match &self.codebook {
+                Some(codebook) => self.distance(query, codebook.decode(codes)),
+                None => f32::INFINITY,
+            },
+        }
+    }
+
+    fn distance(&self, a: &[f32], b: impl Iterator<Item = f32>) -> f32 {
+        let pairs = a.iter().zip(b);
+        match self.definition.metric {
+            VectorMetric::Cosine => 1.0 - pairs.map(|(x, y)| x * y).sum::<f32>(),
+            VectorMetric::Euclidean => pairs.map(|(x, y)| (x - y) * (x - y)).sum(),
+            VectorMetric::Dot => -pairs.map(|(x, y)| x * y).sum::<f32>(),
+        }
+    }
+
+    /// Compress a vector as the index is configured to
+    fn encode(&self, vector: &[f32]) -> Encoded {
+        match (self.definition.quantization, &self.codebook) {
+            (Quantization::Int8, _) => Encoded::int8(vector),
+            (Quantization::Product { .. }, Some(codebook)) => codebook.encode(vector),
+            _ => Encoded::Full(vector.to_vec()),
+        }
+    }
+
+    /// A stored vector's (approximate) components
+    fn decode(&self, node: u32) -> Vec<f32> {
+        match &self.nodes[node as usize].vector {
+            Encoded::Full(vector) => vector.clone(),
+            Encoded::Int8 { scale, codes } => codes.iter().map(|&code| code as f32 * scale).collect(),
+            Encoded::Product(codes) => match &self.codebook {
+                Some(codebook) => codebook.decode(codes).collect(),
+                None => Vec::new(),
+            },
+        }
+    }
+
+    /// Train product quantization centroids on the live vectors and
+    /// compress every stored vector with them
+    fn train(&mut self) {
+        let Quantization::Product { subvectors } = self.definition.quantization else {
+            return;
+        };
+        let training: Vec<&[f32]> = self
+            .nodes
+            .iter()
+            .filter(|node| !node.deleted)
+            .filter_map(|node| match &node.vector {
+                Encoded::Full(vector) => Some(vector.as_slice()),
+                _ => None,
+            })
+            .collect();
+        if training.is_empty() {
+            return;
+        }
+        let codebook = Codebook::train(&training, subvectors);
+        for node in &mut self.nodes {
+            if let Encoded::Full(vector) = &node.vector {
+                node.vector = codebook.encode(vector);
+            }
+        }
+        self.codebook = Some(codebook);
+    }
+
+    /// Rebuild the graph from live nodes, dropping tombstones
+    fn rebuild(&mut self) {
+        let live: Vec<(String, Vec<f32>)> = (0..self.nodes.len() as u32)
+            .filter(|&node| !self.nodes[node as usize].deleted)
+            .map(|node| (self.nodes[node as usize].id.clone(), self.decode(node)))
+            .collect();
+        self.nodes.clear();
+        self.ids.clear();
+        self.entry = None;
+        self.tombstones = 0;
+        for (id, vector) in live {
+            self.insert(id, vector);
+        }
+    }
+
+    /// Restore the ID lookup after deserializing
+    fn restore_ids(&mut self) {
+        self.ids = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| !node.deleted)
+            .map(|(i, node)| (node.id.clone(), i as u32))
+            .collect();
+    }
+}
+
+fn max_links(layer: usize) -> usize {
+    if layer == 0 {
+        2 * M
+    } else {
+        M
+    }
+}
+
+/// Layer for a document, drawn from an exponential distribution seeded by
+/// its ID
+fn level_for(id: &str) -> usize {
+    let mut rng = Rng::with_seed(checksum(id.as_bytes()));
+    let uniform = ((rng.next_u64() >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+    ((-uniform.ln() / (M as f64).ln()) as usize).min(MAX_LEVEL)
+}
+
+/// A vector index saved to disk, with the point in the data file it covers
+///
+/// Writes after `data_len` in the log identified by `log_id` are replayed
+/// on top of it when it is loaded.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VectorCheckpoint {
+    pub log_id: String,
+    pub data_len: u64,
+    pub index: VectorIndex,
+}
+
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    log_id: &'a str,
+    data_len: u64,
+    index: &'a VectorIndex,
+}
+
+/// Write a vector index checkpoint atomically (temp file + rename)
+pub fn save(path: &Path, log_id: &str, data_len: u64, index: &VectorIndex) -> NVResult<()> {
+    let payload = bincode::serialize(&CheckpointRef { log_id, data_len, index })?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp_path = path.with_extension("nvvec.tmp");
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(VECTOR_MAGIC)?;
+        file.write_all(&[VECTOR_VERSION])?;
+        file.write_all(&checksum(&payload).to_le_bytes())?;
+        file.write_all(&payload)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+/// Load a vector index checkpoint
+///
+/// Returns `Ok(None)` if there is no checkpoint or it can't be used, in which
+/// case the caller should rebuild from the collection.
+pub fn load(path: &Path) -> NVResult<Option<VectorCheckpoint>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+
+    let header_len = VECTOR_MAGIC.len() + 1 + 8;
+    if bytes.len() < header_len || &bytes[..4] != VECTOR_MAGIC || bytes[4] != VECTOR_VERSION {
+        return Ok(None);
+    }
+
+    let expected = u64::from_le_bytes(bytes[5..13].try_into().unwrap());
+    let payload = &bytes[header_len..];
+    if checksum(payload) != expected {
+        return Ok(None);
+    }
+
+    Ok(bincode::deserialize::<VectorCheckpoint>(payload).ok().map(|mut checkpoint| {
+        checkpoint.index.restore_ids();
+        checkpoint
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(metric: VectorMetric) -> VectorIndexDefinition {
+        VectorIndexDefinition {
+            name: "embeddings".to_string(),
+            collection: "notes".to_string(),
+            field: "embedding".to_string(),
+            dimensions: 2,
+            metric,
+            quantization: Quantization::None,
+        }
+    }
+
+    fn note(id: &str, vector: &[f64]) -> NVDocument {
+        let mut document = NVDocument::new(id.to_string(), "notes".to_string(), HashMap::new());
+        document.set(
+            "embedding".to_string(),
+            NVValue::Array(vector.iter().map(|&x| NVValue::Number(x)).collect()),
+        );
+        document
+    }
+
+    /// Points spread around the unit circle, so cosine neighbours are known
+    fn circle(index: &mut VectorIndex, n: usize) {
+        for i in 0..n {
+            let angle = i as f64 / n as f64 * std::f64::consts::TAU;
+            index.add(&note(&format!("n{}", i), &[angle.cos(), angle.sin()]));
+        }
+    }
+
+    #[test]
+    fn test_search_finds_nearest_neighbours() {
+        let mut index = VectorIndex::new(definition(VectorMetric::Cosine));
+        circle(&mut index, 500);
+        assert_eq!(index.len(), 500);
+
+        let results = index.search(&[1.0, 0.0], 3).unwrap();
+        let ids: Vec<&str> = results.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids[0], "n0");
+        assert!(ids.contains(&"n1") && ids.contains(&"n499"));
+        assert!(results[0].1.abs() < 1e-6);
+
+        let euclidean = {
+            let mut index = VectorIndex::new(definition(VectorMetric::Euclidean));
+            index.add(&note("a", &[0.0, 0.0]));
+            index.add(&note("b", &[3.0, 4.0]));
+            index.search(&[0.0, 0.0], 2).unwrap()
+        };
+        assert_eq!(euclidean[1], ("b".to_string(), 5.0));
+    }
+
+    #[test]
+    fn test_incremental_updates_and_removal() {
+        let mut index = VectorIndex::new(definition(VectorMetric::Cosine));
+        circle(&mut index, 200);
+
+        index.remove("n0");
+        index.add(&note("n50", &[1.0, 0.01]));
+        let results = index.search(&[1.0, 0.0], 1).unwrap();
+        assert_eq!(results[0].0, "n50");
+
+        // Documents without a usable vector drop out
+        index.add(&note("n50", &[1.0, 0.0, 0.0]));
+        assert_eq!(index.len(), 198);
+
+        // Enough removals rebuild the graph without tombstones
+        for i in 1..150 {
+            index.remove(&format!("n{}", i));
+        }
+        assert_eq!(index.len(), 50);
+        assert!(index.tombstones < 64);
+        assert_eq!(index.search(&[1.0, 0.0], 100).unwrap().len(), 50);
+
+        assert!(index.search(&[1.0], 1).is_err());
+
+        // Exact ranking of a chosen few
+        let ranked = index.rank(&[1.0, 0.0], ["n199", "n150", "missing"], 5).unwrap();
+        let ids: Vec<&str> = ranked.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, vec!["n199", "n150"]);
+    }
+
+    #[test]
+    fn test_quantized_indexes() {
+        let mut full = VectorIndex::new(definition(VectorMetric::Cosine));
+        circle(&mut full, 1200);
+
+        for quantization in [Quantization::Int8, Quantization::Product { subvectors: 2 }] {
+            let mut index = VectorIndex::new(VectorIndexDefinition {
+                quantization,
+                ..definition(VectorMetric::Cosine)
+            });
+            circle(&mut index, 1200);
+            assert!(index.vector_bytes() < full.vector_bytes());
+
+            // Neighbours are approximate but still close to the query
+            let results = index.search(&[1.0, 0.0], 5).unwrap();
+            let ids: Vec<&str> = results.iter().map(|(id, _)| id.as_str()).collect();
+            assert!(ids.contains(&"n0") || ids.contains(&"n1") || ids.contains(&"n1199"));
+            assert!(results.iter().all(|(_, distance)| *distance < 0.01));
+
+            // Removal and rebuild keep working on compressed vectors
+            for i in 0..700 {
+                index.remove(&format!("n{}", i));
+            }
+            assert_eq!(index.len(), 500);
+            assert!(index.search(&[0.0, -1.0], 1).unwrap()[0].1 < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_save_and_load_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vectors").join("embeddings.nvvec");
+
+        let mut index = VectorIndex::new(definition(VectorMetric::Cosine));
+        circle(&mut index, 100);
+        index.remove("n3");
+        save(&path, "log", 42, &index).unwrap();
+
+        let checkpoint = load(&path).unwrap().unwrap();
+        assert_eq!((checkpoint.log_id.as_str(), checkpoint.data_len), ("log", 42));
+        assert_eq!(checkpoint.index.len(), 99);
+        assert_eq!(
+            checkpoint.index.search(&[0.3, 0.7], 5).unwrap(),
+            index.search(&[0.3, 0.7], 5).unwrap()
+        );
+
+        // A damaged file is ignored rather than trusted
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+        assert!(load(&path).unwrap().is_none());
+    }
+}
@@ -0,0 +1,67 @@
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::{NVDocument, NVValue};
+use crate::validation::MAX_DOCUMENT_ID_LEN;
+use std::collections::HashMap;
+
+/// Reserved collection holding key-value entries
+pub const KV_COLLECTION: &str = "_nv_kv";
+
+/// Prefix of entry IDs; reserved, so entries can't clash with documents
+const ID_PREFIX: &str = "_nv_kv:";
+
+/// Check that a key is usable
+pub fn validate_key(key: &str) -> NVResult<()> {
+    if key.is_empty() {
+        return Err(NeuralVaultError::ValidationError("Key must not be empty".to_string()));
+    }
+    if ID_PREFIX.len() + key.len() > MAX_DOCUMENT_ID_LEN {
+        return Err(NeuralVaultError::ValidationError(format!(
+            "Key '{}' exceeds {} bytes",
+            key,
+            MAX_DOCUMENT_ID_LEN - ID_PREFIX.len()
+        )));
+    }
+    if key.chars().any(char::is_control) {
+        return Err(NeuralVaultError::ValidationError(format!(
+            "Key {:?} contains control characters",
+            key
+        )));
+    }
+    Ok(())
+}
+
+/// ID of the document holding a key's entry
+pub fn entry_id(key: &str) -> String {
+    format!("{}{}", ID_PREFIX, key)
+}
+
+/// Build the document holding a key's entry
+pub fn entry(key: &str, value: NVValue) -> NVDocument {
+    let mut data = HashMap::new();
+    data.insert("key".to_string(), NVValue::String(key.to_string()));
+    data.insert("value".to_string(), value);
+    NVDocument::new(entry_id(key), KV_COLLECTION.to_string(), data)
+}
+
+/// Key and value of an entry document
+pub fn key_value(document: &NVDocument) -> Option<(String, NVValue)> {
+    let key = document.id.strip_prefix(ID_PREFIX)?;
+    Some((key.to_string(), document.get("value").cloned().unwrap_or(NVValue::Null)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_round_trip() {
+        let document = entry("theme", NVValue::String("dark".to_string()));
+        assert_eq!(document.id, "_nv_kv:theme");
+        assert_eq!(key_value(&document), Some(("theme".to_string(), NVValue::String("dark".to_string()))));
+
+        assert!(validate_key("flags.beta").is_ok());
+        assert!(validate_key("").is_err());
+        assert!(validate_key("a\nb").is_err());
+        assert!(validate_key(&"k".repeat(MAX_DOCUMENT_ID_LEN)).is_err());
+    }
+}
@@ -1,16 +1,81 @@
+// Lets `#[derive(NVEntity)]` output name this crate from inside it too
+extern crate self as neural_vault_core;
+
+pub mod aggregate;
 pub mod api;
+pub mod audit;
+pub mod auth;
+pub mod authorized;
+pub mod capped;
+pub mod chunking;
+pub mod crdt;
+pub mod crypto;
 pub mod database;
+pub mod embedding;
+pub mod entity;
 pub mod error;
+pub mod export;
+pub mod expression;
+pub mod hooks;
+pub mod ids;
+pub mod import;
+pub mod index;
+pub mod kv;
+pub mod migration;
 pub mod models;
+pub mod object;
+pub mod pipeline;
 pub mod query;
+pub mod replication;
+pub mod sampling;
+pub mod schema;
+pub mod scoped;
+pub mod search;
+pub mod snapshot;
 pub mod storage;
+pub mod sync;
+pub mod system;
+pub mod timeseries;
+pub mod transaction;
+pub mod validation;
+pub mod wire;
 
 // Re-export main types
+pub use aggregate::{Aggregate, AggregateDefinition};
+pub use audit::AUDIT_COLLECTION;
+pub use kv::KV_COLLECTION;
+pub use auth::{Access, Role, User};
+pub use authorized::AuthorizedVault;
+pub use capped::CollectionCap;
+pub use chunking::{ChunkBoundary, ChunkOptions};
 pub use database::{DatabaseStats, NeuralVault};
+pub use embedding::Embedder;
+pub use entity::{Entities, NVEntity, NVField};
+#[cfg(feature = "derive")]
+pub use neural_vault_derive::NVEntity;
+pub use hooks::Middleware;
+pub use migration::Migration;
+pub use object::NVObject;
 pub use error::{NeuralVaultError, NVResult};
+pub use index::{
+    CompletionIndexDefinition, Highlight, HighlightOptions, IndexDefinition, Language, Quantization, TextIndexDefinition, Tokenizer, VectorIndexDefinition, VectorMetric,
+};
+pub use query::{CancellationToken, Cursor, IndexSuggestion, QueryParseError, QueryPlan, QueryStats, QueryTemplate, QueryWarning};
+pub use schema::{CollectionSchema, FieldDefault, FieldSchema};
+pub use scoped::ScopedVault;
+pub use snapshot::Snapshot;
+pub use timeseries::TimeSeriesOptions;
+pub use storage::{AttachmentInfo, AttachmentReader, BackupInfo, BackupKey, CollectionUsage, CompactionProgress, CorruptRange, DiskUsage, RecoveryReport};
+pub use transaction::{Savepoint, Transaction};
+pub use replication::{Change, ChangeSet, Follower, FollowerHandle, OplogPage, OplogPosition, ReplicationLeader};
+pub use sync::{Conflict, ConflictResolver, SyncPeer, SyncReport, SyncWrite};
+pub use wire::WireFormat;
+pub use expression::{DateUnit, Expression};
+pub use pipeline::{Bucket, Facet, Interval, Row, Stage};
+pub use search::{Fusion, HybridQuery};
 pub use models::{
-    DatabaseConfig, LogicalOperator, NVDocument, NVQuery, NVValue, QueryCondition, QueryOperator,
-    UpdateOperation,
+    COLLECTION_FIELD, CREATED_AT_FIELD, DELETED_FIELD, ID_FIELD, METADATA_FIELDS, UPDATED_AT_FIELD, VERSION_FIELD, DatabaseConfig, IdStrategy, LogicalOperator, NVDocument, NVQuery, NVValue, OnDelete, QueryCondition,
+    QueryOperator, QuotaPolicy, Reference, Subquery, UpdateKind, UpdateOperation, WriteOp,
 };
 
 // Re-export API functions for FFI
@@ -94,10 +159,10 @@ mod tests {
         let id = db.create("users".to_string(), data).unwrap();
 
         // Update
-        let updates = vec![UpdateOperation {
-            field: "status".to_string(),
-            value: NVValue::String("inactive".to_string()),
-        }];
+        let updates = vec![UpdateOperation::set(
+            "status",
+            NVValue::String("inactive".to_string()),
+        )];
         db.update_by_id(&id, updates).unwrap();
 
         // Verify update
@@ -113,4 +178,3823 @@ mod tests {
         // Verify deletion
         assert!(db.find_by_id(&id).is_err());
     }
+
+    #[test]
+    fn test_system_metadata() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+
+        {
+            let db = NeuralVault::new(config.clone()).unwrap();
+            assert_eq!(db.format_version().unwrap(), Some(system::FORMAT_VERSION));
+            assert!(db.created_at().unwrap().is_some());
+
+            db.set_metadata("theme", NVValue::String("dark".to_string())).unwrap();
+            db.set_metadata("theme", NVValue::String("light".to_string())).unwrap();
+            db.set_metadata("sync_token", NVValue::Number(42.0)).unwrap();
+            assert!(db.remove_metadata("sync_token").unwrap());
+
+            // Metadata is not a user collection, nor are key/value entries
+            db.kv_set("session", NVValue::String("abc".to_string())).unwrap();
+            assert!(db.collections().unwrap().is_empty());
+            assert_eq!(db.stats().unwrap().total_documents, 0);
+        }
+
+        let db = NeuralVault::new(config).unwrap();
+        assert_eq!(
+            db.get_metadata("theme").unwrap(),
+            Some(NVValue::String("light".to_string()))
+        );
+        assert_eq!(db.get_metadata("sync_token").unwrap(), None);
+        assert_eq!(db.list_metadata().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_data_files_from_before_the_record_format() {
+        // Records were `[len(4)][checksum(8)][bincode document][tombstone(1)]`
+        let write_legacy = |path: &std::path::Path, documents: &[(NVDocument, bool)]| {
+            let mut file = Vec::new();
+            for (document, tombstoned) in documents {
+                let payload = bincode::serialize(document).unwrap();
+                file.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+                file.extend_from_slice(&storage::index_file::checksum(&payload).to_le_bytes());
+                file.extend_from_slice(&payload);
+                file.push(*tombstoned as u8);
+            }
+            std::fs::write(path.join("data.nvdb"), &file).unwrap();
+            file
+        };
+        let document = |id: &str| NVDocument::new(id.to_string(), "notes".to_string(), HashMap::new());
+
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        write_legacy(dir.path(), &[(document("a"), true), (document("a"), false), (document("b"), false)]);
+        {
+            let db = NeuralVault::new(config.clone()).unwrap();
+            assert_eq!(db.format_version().unwrap(), Some(system::FORMAT_VERSION));
+            assert_eq!(db.collections().unwrap(), vec!["notes".to_string()]);
+            assert_eq!(db.find_by_id("a").unwrap().collection, "notes");
+        }
+        // Opening rewrote the records in the current format
+        let data = std::fs::read(dir.path().join("data.nvdb")).unwrap();
+        assert_eq!(&data[12..14], b"NV");
+        let db = NeuralVault::new(config).unwrap();
+        assert_eq!(db.stats().unwrap().total_documents, 2);
+        drop(db);
+
+        // Fields were written without type tags; refuse rather than lose them
+        let dir = tempdir().unwrap();
+        let mut with_fields = document("c");
+        with_fields.data.insert("title".to_string(), NVValue::String("Draft".to_string()));
+        let original = write_legacy(dir.path(), &[(document("a"), false), (with_fields, false)]);
+        let error = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .err()
+        .unwrap();
+        assert!(error.to_string().contains("predates the record format"), "{}", error);
+        assert_eq!(std::fs::read(dir.path().join("data.nvdb")).unwrap(), original);
+    }
+
+    #[test]
+    fn test_id_strategies() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+
+        {
+            let db = NeuralVault::new(config.clone()).unwrap();
+            db.set_id_strategy("events", IdStrategy::Ulid).unwrap();
+            db.set_id_strategy("orders", IdStrategy::AutoIncrement).unwrap();
+            db.set_id_strategy("users", IdStrategy::Provided).unwrap();
+
+            let first = db.create("events".to_string(), HashMap::new()).unwrap();
+            let second = db.create("events".to_string(), HashMap::new()).unwrap();
+            assert_eq!(first.len(), 26);
+            assert!(first < second);
+
+            assert_eq!(db.create("orders".to_string(), HashMap::new()).unwrap(), "1");
+            assert_eq!(db.create("orders".to_string(), HashMap::new()).unwrap(), "2");
+
+            // IDs are unique across collections, so a second one carries on
+            db.set_id_strategy("invoices", IdStrategy::AutoIncrement).unwrap();
+            assert_eq!(db.create("invoices".to_string(), HashMap::new()).unwrap(), "3");
+            assert_eq!(db.create("orders".to_string(), HashMap::new()).unwrap(), "4");
+
+            let mut data = HashMap::new();
+            data.insert("id".to_string(), NVValue::String("alice".to_string()));
+            assert_eq!(db.create("users".to_string(), data.clone()).unwrap(), "alice");
+            assert!(matches!(
+                db.create("users".to_string(), data),
+                Err(NeuralVaultError::DuplicateId(_))
+            ));
+            assert!(matches!(
+                db.create("users".to_string(), HashMap::new()),
+                Err(NeuralVaultError::ValidationError(_))
+            ));
+        }
+
+        // Strategies and counters survive a reopen
+        let db = NeuralVault::new(config).unwrap();
+        assert_eq!(db.id_strategy("orders"), IdStrategy::AutoIncrement);
+        assert_eq!(db.create("orders".to_string(), HashMap::new()).unwrap(), "5");
+        assert_eq!(db.id_strategy("other"), IdStrategy::Uuid);
+    }
+
+    #[test]
+    fn test_create_with_id() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+
+        let mut data = HashMap::new();
+        data.insert("title".to_string(), NVValue::String("Draft".to_string()));
+
+        let id = db
+            .create_with_id("server-17".to_string(), "notes".to_string(), data.clone())
+            .unwrap();
+        assert_eq!(id, "server-17");
+        assert_eq!(db.find_by_id("server-17").unwrap().data, data);
+
+        assert!(matches!(
+            db.create_with_id("server-17".to_string(), "notes".to_string(), HashMap::new()),
+            Err(NeuralVaultError::DuplicateId(_))
+        ));
+
+        // A deleted ID can be reused
+        db.kill_by_id("server-17").unwrap();
+        db.create_with_id("server-17".to_string(), "notes".to_string(), HashMap::new())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_bulk_write() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+
+        let mut data = HashMap::new();
+        data.insert("name".to_string(), NVValue::String("John".to_string()));
+        let existing = db.create("users".to_string(), data.clone()).unwrap();
+
+        let results = db
+            .bulk_write(vec![
+                WriteOp::Insert {
+                    id: Some("a".to_string()),
+                    collection: "users".to_string(),
+                    data: data.clone(),
+                },
+                WriteOp::Insert {
+                    id: Some("a".to_string()),
+                    collection: "users".to_string(),
+                    data: data.clone(),
+                },
+                WriteOp::Update {
+                    id: "a".to_string(),
+                    updates: vec![UpdateOperation::set("age", NVValue::Number(30.0))],
+                },
+                WriteOp::Upsert {
+                    id: "b".to_string(),
+                    collection: "users".to_string(),
+                    data: HashMap::new(),
+                },
+                WriteOp::Delete { id: existing.clone() },
+                WriteOp::Delete { id: "missing".to_string() },
+            ])
+            .unwrap();
+
+        assert_eq!(results[0].as_deref().unwrap(), "a");
+        assert!(matches!(results[1], Err(NeuralVaultError::DuplicateId(_))));
+        assert!(results[2].is_ok() && results[3].is_ok() && results[4].is_ok());
+        assert!(matches!(results[5], Err(NeuralVaultError::DocumentNotFound(_))));
+
+        assert_eq!(db.find_by_id("a").unwrap().get("age"), Some(&NVValue::Number(30.0)));
+        assert!(db.find_by_id("b").is_ok());
+        assert!(db.find_by_id(&existing).is_err());
+        assert_eq!(db.count("users").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_deep_merge_update() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+
+        let profile: NVValue = serde_json::json!({
+            "name": "John",
+            "address": { "city": "Oslo", "zip": "0150" }
+        })
+        .into();
+        let mut data = HashMap::new();
+        data.insert("profile".to_string(), profile);
+        let id = db.create("users".to_string(), data).unwrap();
+
+        let patch: NVValue = serde_json::json!({ "address": { "city": "Bergen" }, "age": 30 }).into();
+        db.update_by_id(&id, vec![UpdateOperation::merge("profile", patch)])
+            .unwrap();
+
+        let profile = serde_json::Value::from(db.find_by_id(&id).unwrap().data["profile"].clone());
+        assert_eq!(
+            profile,
+            serde_json::json!({
+                "name": "John",
+                "age": 30.0,
+                "address": { "city": "Bergen", "zip": "0150" }
+            })
+        );
+    }
+
+    #[test]
+    fn test_unset_update() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+
+        let mut data = HashMap::new();
+        data.insert("name".to_string(), NVValue::String("John".to_string()));
+        data.insert("nickname".to_string(), NVValue::String("Johnny".to_string()));
+        let id = db.create("users".to_string(), data).unwrap();
+        let before = db.find_by_id(&id).unwrap();
+
+        db.update_by_id(&id, vec![UpdateOperation::unset("nickname")])
+            .unwrap();
+
+        let after = db.find_by_id(&id).unwrap();
+        assert!(after.get("nickname").is_none());
+        assert!(after.get("name").is_some());
+        assert!(after.updated_at >= before.updated_at);
+    }
+
+    #[test]
+    fn test_update_if() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+
+        let mut data = HashMap::new();
+        data.insert("status".to_string(), NVValue::String("packed".to_string()));
+        let id = db.create("orders".to_string(), data).unwrap();
+
+        let packed = QueryCondition {
+            field: "status".to_string(),
+            operator: QueryOperator::Equals,
+            value: NVValue::String("packed".to_string()),
+        };
+        let ship = vec![UpdateOperation::set("status", NVValue::String("shipped".to_string()))];
+
+        assert!(db.update_if(&id, packed.clone(), ship.clone()).unwrap());
+        // The order is no longer packed, so the second transition is refused
+        assert!(!db.update_if(&id, packed, ship).unwrap());
+        assert_eq!(
+            db.find_by_id(&id).unwrap().get("status"),
+            Some(&NVValue::String("shipped".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_lifecycle_hooks() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+
+        // Derive a field on create and veto creates without a name
+        db.on_before_create(
+            "users",
+            Box::new(|doc| match doc.get("name").cloned() {
+                Some(NVValue::String(name)) => {
+                    doc.set("name_lower".to_string(), NVValue::String(name.to_lowercase()));
+                    Ok(())
+                }
+                _ => Err(NeuralVaultError::ValidationError("name is required".to_string())),
+            }),
+        );
+
+        let updates = Arc::new(AtomicUsize::new(0));
+        let counter = updates.clone();
+        db.on_after_update(
+            "users",
+            Box::new(move |_, _| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }),
+        );
+
+        db.on_before_delete(
+            "users",
+            Box::new(|doc| match doc.get("admin") {
+                Some(NVValue::Bool(true)) => {
+                    Err(NeuralVaultError::ValidationError("admins can't be deleted".to_string()))
+                }
+                _ => Ok(()),
+            }),
+        );
+
+        let mut data = HashMap::new();
+        data.insert("name".to_string(), NVValue::String("Ada".to_string()));
+        let id = db.create("users".to_string(), data).unwrap();
+        assert_eq!(
+            db.find_by_id(&id).unwrap().get("name_lower"),
+            Some(&NVValue::String("ada".to_string()))
+        );
+        assert!(db.create("users".to_string(), HashMap::new()).is_err());
+
+        db.update_by_id(&id, vec![UpdateOperation::set("admin", NVValue::Bool(true))])
+            .unwrap();
+        assert_eq!(updates.load(Ordering::SeqCst), 1);
+
+        assert!(db.kill_by_id(&id).is_err());
+        assert!(db.find_by_id(&id).is_ok());
+
+        // A veto on any match leaves every match in place
+        let mut data = HashMap::new();
+        data.insert("name".to_string(), NVValue::String("Bob".to_string()));
+        db.create("users".to_string(), data).unwrap();
+        assert!(db.kill(NVQuery::new("users".to_string())).is_err());
+        assert_eq!(db.count("users").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_reference_actions_on_delete() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config.clone()).unwrap();
+
+        let reference = |collection: &str, field: &str, target: &str, on_delete| Reference {
+            collection: collection.to_string(),
+            field: field.to_string(),
+            target: target.to_string(),
+            on_delete,
+        };
+        db.add_reference(reference("posts", "author_id", "users", OnDelete::Cascade)).unwrap();
+        db.add_reference(reference("comments", "post_id", "posts", OnDelete::Cascade)).unwrap();
+        db.add_reference(reference("likes", "user_id", "users", OnDelete::SetNull)).unwrap();
+        db.add_reference(reference("invoices", "user_id", "users", OnDelete::Restrict)).unwrap();
+
+        let child = |field: &str, parent: &str| {
+            let mut data = HashMap::new();
+            data.insert(field.to_string(), NVValue::String(parent.to_string()));
+            data
+        };
+
+        let alice = db.create("users".to_string(), HashMap::new()).unwrap();
+        let post = db.create("posts".to_string(), child("author_id", &alice)).unwrap();
+        let comment = db.create("comments".to_string(), child("post_id", &post)).unwrap();
+        let like = db.create("likes".to_string(), child("user_id", &alice)).unwrap();
+
+        db.kill_by_id(&alice).unwrap();
+        assert!(db.find_by_id(&post).is_err());
+        assert!(db.find_by_id(&comment).is_err());
+        assert_eq!(db.find_by_id(&like).unwrap().get("user_id"), Some(&NVValue::Null));
+
+        let bob = db.create("users".to_string(), HashMap::new()).unwrap();
+        db.create("invoices".to_string(), child("user_id", &bob)).unwrap();
+        assert!(matches!(
+            db.kill_by_id(&bob),
+            Err(NeuralVaultError::ValidationError(_))
+        ));
+        assert!(db.find_by_id(&bob).is_ok());
+
+        // Declarations are persisted
+        drop(db);
+        let db = NeuralVault::new(config).unwrap();
+        assert_eq!(db.references().len(), 4);
+    }
+
+    #[test]
+    fn test_populate_references() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+
+        let mut user = HashMap::new();
+        user.insert("name".to_string(), NVValue::String("Ada".to_string()));
+        let ada = db.create("users".to_string(), user).unwrap();
+
+        let mut post = HashMap::new();
+        post.insert("author_id".to_string(), NVValue::String(ada.clone()));
+        post.insert(
+            "editors".to_string(),
+            NVValue::Array(vec![NVValue::String(ada.clone()), NVValue::String("gone".to_string())]),
+        );
+        db.create("posts".to_string(), post).unwrap();
+
+        let mut query = NVQuery::new("posts".to_string());
+        query.populate = vec!["author_id:users".to_string(), "editors:users".to_string()];
+        let results = db.find(query).unwrap();
+
+        let author = match results[0].get("author_id") {
+            Some(NVValue::Object(author)) => author.clone(),
+            other => panic!("author not populated: {:?}", other),
+        };
+        assert_eq!(author.get("name"), Some(&NVValue::String("Ada".to_string())));
+        assert_eq!(author.get("id"), Some(&NVValue::String(ada)));
+
+        match results[0].get("editors") {
+            Some(NVValue::Array(editors)) => {
+                assert!(matches!(editors[0], NVValue::Object(_)));
+                assert_eq!(editors[1], NVValue::Null);
+            }
+            other => panic!("editors not populated: {:?}", other),
+        }
+
+        // Without a declared reference the target collection must be given
+        let mut query = NVQuery::new("posts".to_string());
+        query.populate = vec!["author_id".to_string()];
+        assert!(matches!(db.find(query), Err(NeuralVaultError::InvalidQuery(_))));
+    }
+
+    #[test]
+    fn test_saved_views() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+
+        {
+            let db = NeuralVault::new(config.clone()).unwrap();
+            for (name, status, age) in [("a", "active", 20.0), ("b", "active", 40.0), ("c", "banned", 50.0)] {
+                let mut data = HashMap::new();
+                data.insert("name".to_string(), NVValue::String(name.to_string()));
+                data.insert("status".to_string(), NVValue::String(status.to_string()));
+                data.insert("age".to_string(), NVValue::Number(age));
+                db.create("users".to_string(), data).unwrap();
+            }
+
+            let mut active = NVQuery::new("users".to_string());
+            active.add_condition(
+                "status".to_string(),
+                QueryOperator::Equals,
+                NVValue::String("active".to_string()),
+                None,
+            );
+            db.create_view("active_users", active).unwrap();
+
+            // A view can't be built on itself
+            assert!(db
+                .create_view("loop", NVQuery::new("loop".to_string()))
+                .is_err());
+
+            // Views and collections don't share names
+            assert!(matches!(
+                db.create_view("users", NVQuery::new("users".to_string())),
+                Err(NeuralVaultError::ValidationError(_))
+            ));
+            assert!(matches!(
+                db.create("active_users".to_string(), HashMap::new()),
+                Err(NeuralVaultError::ValidationError(_))
+            ));
+        }
+
+        let db = NeuralVault::new(config).unwrap();
+        assert_eq!(db.views(), vec!["active_users".to_string()]);
+        assert_eq!(db.find(NVQuery::new("active_users".to_string())).unwrap().len(), 2);
+
+        // The view's results can be filtered further with an OR query
+        let mut query = NVQuery::new("active_users".to_string());
+        query.add_condition("age".to_string(), QueryOperator::GreaterThan, NVValue::Number(30.0), None);
+        query.add_condition(
+            "name".to_string(),
+            QueryOperator::Equals,
+            NVValue::String("c".to_string()),
+            Some(LogicalOperator::Or),
+        );
+        let results = db.find(query).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].get("name"), Some(&NVValue::String("b".to_string())));
+    }
+
+    #[test]
+    fn test_materialized_aggregate_view() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+
+        let order = |status: &str, total: f64| {
+            let mut data = HashMap::new();
+            data.insert("status".to_string(), NVValue::String(status.to_string()));
+            data.insert("total".to_string(), NVValue::Number(total));
+            data
+        };
+        let row_for = |rows: &[HashMap<String, NVValue>], status: &str| {
+            rows.iter()
+                .find(|row| row["status"] == NVValue::String(status.to_string()))
+                .cloned()
+        };
+
+        {
+            let db = NeuralVault::new(config.clone()).unwrap();
+            let first = db.create("orders".to_string(), order("open", 10.0)).unwrap();
+            db.create_aggregate_view(
+                "orders_by_status",
+                AggregateDefinition {
+                    collection: "orders".to_string(),
+                    group_by: Some("status".to_string()),
+                    aggregates: vec![
+                        ("count".to_string(), Aggregate::Count),
+                        ("revenue".to_string(), Aggregate::Sum("total".to_string())),
+                    ],
+                },
+            )
+            .unwrap();
+
+            db.create("orders".to_string(), order("open", 5.0)).unwrap();
+            db.update_by_id(&first, vec![UpdateOperation::set("status", NVValue::String("paid".to_string()))])
+                .unwrap();
+
+            let rows = db.aggregate_view("orders_by_status").unwrap();
+            assert_eq!(row_for(&rows, "open").unwrap()["revenue"], NVValue::Number(5.0));
+            assert_eq!(row_for(&rows, "paid").unwrap()["count"], NVValue::Number(1.0));
+
+            db.kill_by_id(&first).unwrap();
+            assert!(row_for(&db.aggregate_view("orders_by_status").unwrap(), "paid").is_none());
+        }
+
+        // The view is rebuilt on reopen
+        let db = NeuralVault::new(config).unwrap();
+        let rows = db.aggregate_view("orders_by_status").unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(row_for(&rows, "open").unwrap()["count"], NVValue::Number(1.0));
+    }
+
+    #[test]
+    fn test_reopen_rebuilds_every_structure_on_a_collection() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let note = |topic: &str, body: &str| {
+            let mut data = HashMap::new();
+            data.insert("topic".to_string(), NVValue::String(topic.to_string()));
+            data.insert("body".to_string(), NVValue::String(body.to_string()));
+            data
+        };
+
+        {
+            let db = NeuralVault::new(config.clone()).unwrap();
+            db.create("notes".to_string(), note("rust", "borrow checker notes")).unwrap();
+            db.create("notes".to_string(), note("rust", "rustup toolchains")).unwrap();
+            db.create("notes".to_string(), note("sql", "window functions")).unwrap();
+            db.create_aggregate_view(
+                "notes_by_topic",
+                AggregateDefinition {
+                    collection: "notes".to_string(),
+                    group_by: Some("topic".to_string()),
+                    aggregates: vec![("count".to_string(), Aggregate::Count)],
+                },
+            )
+            .unwrap();
+            db.create_index(IndexDefinition {
+                name: "by_topic".to_string(),
+                collection: "notes".to_string(),
+                fields: vec!["topic".to_string()],
+                filter: Vec::new(),
+            })
+            .unwrap();
+            db.create_text_index(TextIndexDefinition {
+                name: "note_text".to_string(),
+                collection: "notes".to_string(),
+                fields: vec!["body".to_string()],
+                tokenizer: Tokenizer::default(),
+            })
+            .unwrap();
+            db.create_completion_index("notes", "topic").unwrap();
+        }
+
+        let db = NeuralVault::new(config).unwrap();
+        let rows = db.aggregate_view("notes_by_topic").unwrap();
+        let rust = rows.iter().find(|row| row["topic"] == NVValue::String("rust".to_string())).unwrap();
+        assert_eq!(rust["count"], NVValue::Number(2.0));
+
+        assert_eq!(db.indexes()[0].1, 3);
+        let mut query = NVQuery::new("notes".to_string());
+        query.add_condition("topic".to_string(), QueryOperator::Equals, NVValue::String("rust".to_string()), None);
+        assert_eq!(db.explain(&query).unwrap().index(), Some("by_topic"));
+        assert_eq!(db.find(query).unwrap().len(), 2);
+
+        assert_eq!(db.search_text("note_text", "window", 10).unwrap().len(), 1);
+        assert_eq!(db.suggest("notes", "topic", "ru", 5).unwrap()[0], ("rust".to_string(), 2));
+    }
+
+    #[test]
+    fn test_index_reads_report_corrupt_records() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        db.create_index(IndexDefinition {
+            name: "by_tag".to_string(),
+            collection: "notes".to_string(),
+            fields: vec!["tag".to_string()],
+            filter: Vec::new(),
+        })
+        .unwrap();
+        let mut data = HashMap::new();
+        data.insert("tag".to_string(), NVValue::String("red".to_string()));
+        data.insert("body".to_string(), NVValue::String("needle-in-the-record".to_string()));
+        db.create("notes".to_string(), data).unwrap();
+
+        // Flip a byte of the stored record behind the database's back
+        let path = dir.path().join("data.nvdb");
+        let bytes = std::fs::read(&path).unwrap();
+        let at = bytes.windows(6).position(|window| window == b"needle").unwrap();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        storage::positioned::write_all_at(&file, b"N", at as u64).unwrap();
+
+        let mut query = NVQuery::new("notes".to_string());
+        query.add_condition("tag".to_string(), QueryOperator::Equals, NVValue::String("red".to_string()), None);
+        assert_eq!(db.explain(&query).unwrap().index(), Some("by_tag"));
+        assert!(db.find(query).is_err());
+    }
+
+    #[test]
+    fn test_partial_index_maintenance() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+
+        let task = |status: &str| {
+            let mut data = HashMap::new();
+            data.insert("status".to_string(), NVValue::String(status.to_string()));
+            data
+        };
+
+        {
+            let db = NeuralVault::new(config.clone()).unwrap();
+            let open = db.create("tasks".to_string(), task("open")).unwrap();
+            db.create("tasks".to_string(), task("done")).unwrap();
+
+            db.create_index(IndexDefinition {
+                name: "open_tasks".to_string(),
+                collection: "tasks".to_string(),
+                fields: vec!["status".to_string()],
+                filter: vec![QueryCondition {
+                    field: "status".to_string(),
+                    operator: QueryOperator::Equals,
+                    value: NVValue::String("open".to_string()),
+                }],
+            })
+            .unwrap();
+            assert_eq!(db.indexes()[0].1, 1);
+
+            db.create("tasks".to_string(), task("open")).unwrap();
+            db.create("tasks".to_string(), task("done")).unwrap();
+            assert_eq!(db.indexes()[0].1, 2);
+
+            // Leaving the filter removes the document from the index
+            db.update_by_id(&open, vec![UpdateOperation::set("status", NVValue::String("done".to_string()))])
+                .unwrap();
+            assert_eq!(db.indexes()[0].1, 1);
+        }
+
+        let db = NeuralVault::new(config).unwrap();
+        let (definition, entries) = db.indexes().remove(0);
+        assert!(definition.is_partial());
+        assert_eq!(entries, 1);
+    }
+
+    #[test]
+    fn test_find_uses_index() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+
+        for age in 0..50 {
+            let mut data = HashMap::new();
+            data.insert("age".to_string(), NVValue::Number(age as f64));
+            db.create("users".to_string(), data).unwrap();
+        }
+
+        let mut query = NVQuery::new("users".to_string());
+        query.add_condition("age".to_string(), QueryOperator::GreaterThanOrEqual, NVValue::Number(45.0), None);
+        assert!(matches!(db.explain(&query).unwrap(), QueryPlan::FullScan { .. }));
+        let scanned = db.find(query.clone()).unwrap().len();
+
+        db.create_index(IndexDefinition {
+            name: "by_age".to_string(),
+            collection: "users".to_string(),
+            fields: vec!["age".to_string()],
+            filter: Vec::new(),
+        })
+        .unwrap();
+
+        assert_eq!(
+            db.explain(&query).unwrap(),
+            QueryPlan::IndexScan {
+                index: "by_age".to_string(),
+                field: "age".to_string(),
+                candidates: 5,
+            }
+        );
+        assert_eq!(db.find(query).unwrap().len(), scanned);
+
+        // Exact numeric equality through the index
+        let mut query = NVQuery::new("users".to_string());
+        query.add_condition("age".to_string(), QueryOperator::Equals, NVValue::Number(7.0), None);
+        assert_eq!(db.find(query).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_order_by_limit_fast_path() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+
+        for i in 0..30 {
+            let mut data = HashMap::new();
+            data.insert("created".to_string(), NVValue::Number(i as f64));
+            data.insert("kind".to_string(), NVValue::String(if i % 2 == 0 { "even" } else { "odd" }.to_string()));
+            db.create("feed".to_string(), data).unwrap();
+        }
+        // A document without the sort field sorts last
+        db.create("feed".to_string(), HashMap::new()).unwrap();
+
+        let mut query = NVQuery::new("feed".to_string());
+        query.add_condition("kind".to_string(), QueryOperator::Equals, NVValue::String("odd".to_string()), None);
+        query.order_by = Some("created".to_string());
+        query.order_desc = true;
+        query.skip = Some(1);
+        query.limit = Some(3);
+        let expected = db.find(query.clone()).unwrap();
+
+        db.create_index(IndexDefinition {
+            name: "feed_by_created".to_string(),
+            collection: "feed".to_string(),
+            fields: vec!["created".to_string()],
+            filter: Vec::new(),
+        })
+        .unwrap();
+        assert!(matches!(db.explain(&query).unwrap(), QueryPlan::IndexOrder { .. }));
+
+        let streamed = db.find(query).unwrap();
+        let created = |docs: &[NVDocument]| docs.iter().map(|d| d.get("created").cloned()).collect::<Vec<_>>();
+        assert_eq!(created(&streamed), created(&expected));
+        assert_eq!(streamed[0].get("created"), Some(&NVValue::Number(27.0)));
+    }
+
+    #[test]
+    fn test_index_order_breaks_ties_like_a_full_scan() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        for i in 0..600 {
+            let mut data = HashMap::new();
+            data.insert("rank".to_string(), NVValue::Number((i % 3) as f64));
+            db.create("scores".to_string(), data).unwrap();
+        }
+
+        let mut query = NVQuery::new("scores".to_string());
+        query.order_by = Some("rank".to_string());
+        query.order_desc = true;
+        query.skip = Some(250);
+        query.limit = Some(20);
+        let expected = db.find(query.clone()).unwrap();
+
+        db.create_index(IndexDefinition {
+            name: "scores_by_rank".to_string(),
+            collection: "scores".to_string(),
+            fields: vec!["rank".to_string()],
+            filter: Vec::new(),
+        })
+        .unwrap();
+        assert!(matches!(db.explain(&query).unwrap(), QueryPlan::IndexOrder { .. }));
+        let ids = |docs: Vec<NVDocument>| docs.into_iter().map(|d| d.id).collect::<Vec<_>>();
+        assert_eq!(ids(db.find(query.clone()).unwrap()), ids(expected));
+
+        // Once strings and numbers mix, only a full scan sorts the way it always has
+        let mut data = HashMap::new();
+        data.insert("rank".to_string(), NVValue::String("top".to_string()));
+        db.create("scores".to_string(), data).unwrap();
+        assert!(matches!(db.explain(&query).unwrap(), QueryPlan::FullScan { .. }));
+    }
+
+    #[test]
+    fn test_covered_query() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+
+        for (title, status) in [("a", "open"), ("b", "done"), ("c", "open")] {
+            let mut data = HashMap::new();
+            data.insert("title".to_string(), NVValue::String(title.to_string()));
+            data.insert("status".to_string(), NVValue::String(status.to_string()));
+            data.insert("body".to_string(), NVValue::String("long text".to_string()));
+            db.create("notes".to_string(), data).unwrap();
+        }
+        db.create_index(IndexDefinition {
+            name: "notes_by_status".to_string(),
+            collection: "notes".to_string(),
+            fields: vec!["status".to_string(), "title".to_string()],
+            filter: Vec::new(),
+        })
+        .unwrap();
+
+        let mut query = NVQuery::new("notes".to_string());
+        query.add_condition("status".to_string(), QueryOperator::Equals, NVValue::String("open".to_string()), None);
+        query.order_by = Some("title".to_string());
+        query.projection = Some(vec!["title".to_string()]);
+        assert_eq!(
+            db.explain(&query).unwrap(),
+            QueryPlan::Covered { index: "notes_by_status".to_string() }
+        );
+
+        let results = db.find(query.clone()).unwrap();
+        let titles: Vec<_> = results.iter().map(|d| d.get("title").cloned()).collect();
+        assert_eq!(titles, vec![Some(NVValue::String("a".to_string())), Some(NVValue::String("c".to_string()))]);
+        assert!(results.iter().all(|d| d.data.len() == 1));
+        assert_eq!(results[0].created_at, db.find_by_id(&results[0].id).unwrap().created_at);
+
+        // Projecting an unindexed field needs the documents themselves
+        query.projection = Some(vec!["body".to_string()]);
+        assert!(!matches!(db.explain(&query).unwrap(), QueryPlan::Covered { .. }));
+        assert_eq!(db.find(query).unwrap()[0].get("body"), Some(&NVValue::String("long text".to_string())));
+    }
+
+    #[test]
+    fn test_field_encryption() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_string();
+        let key = [42u8; 32];
+        let ssn = "123-45-6789";
+
+        let id = {
+            let db = NeuralVault::new(DatabaseConfig { path: path.clone(), ..Default::default() }).unwrap();
+
+            let mut data = HashMap::new();
+            data.insert("name".to_string(), NVValue::String("Asha".to_string()));
+            data.insert("ssn".to_string(), NVValue::String(ssn.to_string()));
+            let id = db.create("users".to_string(), data).unwrap();
+
+            // Encrypting needs the key
+            assert!(db.encrypt_field("users", "ssn").is_err());
+            db.unlock_fields(&key).unwrap();
+            db.encrypt_field("users", "ssn").unwrap();
+
+            // Transparent for a handle with the key, including queries
+            let mut query = NVQuery::new("users".to_string());
+            query.add_condition("ssn".to_string(), QueryOperator::Equals, NVValue::String(ssn.to_string()), None);
+            assert_eq!(db.find(query).unwrap().len(), 1);
+            assert!(db
+                .create_index(IndexDefinition {
+                    name: "users_by_ssn".to_string(),
+                    collection: "users".to_string(),
+                    fields: vec!["ssn".to_string()],
+                    filter: Vec::new(),
+                })
+                .is_err());
+            id
+        };
+
+        // Encrypting compacted away the plaintext written before it
+        let raw = std::fs::read(dir.path().join("data.nvdb")).unwrap();
+        assert!(!raw.windows(ssn.len()).any(|window| window == ssn.as_bytes()));
+
+        let db = NeuralVault::new(DatabaseConfig { path, ..Default::default() }).unwrap();
+        assert_eq!(db.encrypted_fields().unwrap(), vec![("users".to_string(), "ssn".to_string())]);
+
+        // Without the key the field is hidden and the collection read-only
+        let locked = db.find_by_id(&id).unwrap();
+        assert_eq!(locked.get("ssn"), None);
+        assert_eq!(locked.get("name"), Some(&NVValue::String("Asha".to_string())));
+        assert!(db.update_by_id(&id, vec![UpdateOperation::set("name", NVValue::String("A".to_string()))]).is_err());
+
+        assert!(db.unlock_fields(&[0u8; 32]).is_err());
+        db.unlock_fields(&key).unwrap();
+        assert_eq!(db.find_by_id(&id).unwrap().get("ssn"), Some(&NVValue::String(ssn.to_string())));
+    }
+
+    #[test]
+    fn test_rotate_encryption_key() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_string();
+        let (old_key, new_key) = ([1u8; 32], [2u8; 32]);
+        let secret = "4111-1111-1111-1111";
+
+        let id = {
+            let db = NeuralVault::new(DatabaseConfig { path: path.clone(), ..Default::default() }).unwrap();
+            let mut data = HashMap::new();
+            data.insert("card".to_string(), NVValue::String(secret.to_string()));
+            let id = db.create("payments".to_string(), data).unwrap();
+
+            db.unlock_fields(&old_key).unwrap();
+            db.encrypt_field("payments", "card").unwrap();
+
+            assert!(db.rotate_encryption_key(&new_key, &new_key).is_err());
+            db.rotate_encryption_key(&old_key, &new_key).unwrap();
+            assert_eq!(db.find_by_id(&id).unwrap().get("card"), Some(&NVValue::String(secret.to_string())));
+            id
+        };
+
+        // Compaction dropped the plaintext written before encryption
+        let raw = std::fs::read(dir.path().join("data.nvdb")).unwrap();
+        assert!(!raw.windows(secret.len()).any(|window| window == secret.as_bytes()));
+
+        let db = NeuralVault::new(DatabaseConfig { path, ..Default::default() }).unwrap();
+        assert!(db.unlock_fields(&old_key).is_err());
+        db.unlock_fields(&new_key).unwrap();
+        assert_eq!(db.find_by_id(&id).unwrap().get("card"), Some(&NVValue::String(secret.to_string())));
+    }
+
+    #[test]
+    fn test_config_from_partial_json() {
+        let config: DatabaseConfig = serde_json::from_str(
+            r#"{"path": "data", "kdf_memory_kib": 8192, "flush_interval_ms": 50, "quota_policy": "EvictOldest"}"#,
+        )
+        .unwrap();
+        assert_eq!(config.path, "data");
+        assert_eq!(config.kdf_memory_kib, 8192);
+        assert_eq!(config.flush_interval_ms, 50);
+        assert_eq!(config.quota_policy, QuotaPolicy::EvictOldest);
+        assert_eq!(config.transaction_retries, DatabaseConfig::default().transaction_retries);
+    }
+
+    #[test]
+    fn test_change_password() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            kdf_memory_kib: 64,
+            kdf_iterations: 1,
+            ..Default::default()
+        };
+
+        let id = {
+            let db = NeuralVault::new(config.clone()).unwrap();
+            assert!(db.change_password("first", "second").is_err());
+            db.unlock_with_password("first").unwrap();
+            db.encrypt_field("notes", "body").unwrap();
+
+            let mut data = HashMap::new();
+            data.insert("body".to_string(), NVValue::String("private".to_string()));
+            let id = db.create("notes".to_string(), data).unwrap();
+
+            assert!(db.change_password("wrong", "second").is_err());
+            db.change_password("first", "second").unwrap();
+            assert!(db.rotate_encryption_key(&[0u8; 32], &[1u8; 32]).is_err());
+            db.rotate_master_key("second").unwrap();
+            id
+        };
+
+        let db = NeuralVault::new(config).unwrap();
+        assert!(db.unlock_with_password("first").is_err());
+        db.unlock_with_password("second").unwrap();
+        assert_eq!(db.find_by_id(&id).unwrap().get("body"), Some(&NVValue::String("private".to_string())));
+    }
+
+    #[test]
+    fn test_access_control_persists() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+
+        let token = {
+            let db = NeuralVault::new(config.clone()).unwrap();
+            db.create_role(Role {
+                name: "reader".to_string(),
+                permissions: HashMap::from([("posts".to_string(), Access::Read)]),
+            })
+            .unwrap();
+            assert!(db.create_user("ann", vec!["missing".to_string()]).is_err());
+            db.create_user("ann", vec!["reader".to_string()]).unwrap();
+            db.issue_token("ann").unwrap()
+        };
+
+        let db = NeuralVault::new(config).unwrap();
+        assert_eq!(db.authorize(&token, "posts", Access::Read).unwrap(), "ann");
+        assert!(matches!(
+            db.authorize(&token, "posts", Access::Write),
+            Err(NeuralVaultError::AccessDenied(_))
+        ));
+
+        assert!(db.revoke_token(&token).unwrap());
+        assert!(db.authorize(&token, "posts", Access::Read).is_err());
+    }
+
+    #[test]
+    fn test_authorized_handle() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        db.create_role(Role {
+            name: "author".to_string(),
+            permissions: HashMap::from([
+                ("posts".to_string(), Access::Write),
+                ("authors".to_string(), Access::Read),
+            ]),
+        })
+        .unwrap();
+        db.create_user("ann", vec!["author".to_string()]).unwrap();
+        let token = db.issue_token("ann").unwrap();
+        assert!(matches!(db.authorized("bogus"), Err(NeuralVaultError::AccessDenied(_))));
+
+        let mut data = HashMap::new();
+        data.insert("name".to_string(), NVValue::String("Ann".to_string()));
+        let author = db.create("authors".to_string(), data).unwrap();
+        let secret = db.create("secrets".to_string(), HashMap::new()).unwrap();
+
+        let handle = db.authorized(&token).unwrap();
+        assert_eq!(handle.user(), "ann");
+        let mut data = HashMap::new();
+        data.insert("author".to_string(), NVValue::String(author.clone()));
+        let post = handle.create("posts", data).unwrap();
+        let denied = |result: NVResult<()>| matches!(result, Err(NeuralVaultError::AccessDenied(_)));
+        assert!(denied(handle.create("authors", HashMap::new()).map(|_| ())));
+        assert!(denied(handle.find_by_id(&secret).map(|_| ())));
+        assert!(denied(handle.kill_by_id(&author)));
+        assert!(denied(handle.count("secrets").map(|_| ())));
+
+        // Every collection a query reads is checked
+        let mut query = NVQuery::new("posts".to_string());
+        query.populate = vec!["author:authors".to_string()];
+        assert_eq!(handle.find(query.clone()).unwrap().len(), 1);
+        query.populate = vec!["author:secrets".to_string()];
+        assert!(denied(handle.find(query).map(|_| ())));
+        assert!(denied(handle.find(NVQuery::new("posts,secrets".to_string())).map(|_| ())));
+
+        // Revoking the token stops the handle
+        assert!(db.revoke_token(&token).unwrap());
+        assert!(denied(handle.find_by_id(&post).map(|_| ())));
+        assert!(db.find_by_id(&post).is_ok());
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+
+        // Writes before the log is enabled aren't recorded
+        db.create("notes".to_string(), HashMap::new()).unwrap();
+        db.enable_audit_log(true).unwrap();
+
+        let mut data = HashMap::new();
+        data.insert("text".to_string(), NVValue::String("hi".to_string()));
+        // The actor belongs to the handle, so it applies on other threads
+        db.set_actor(Some("alice".to_string()));
+        let id = std::thread::scope(|scope| {
+            scope
+                .spawn(|| db.create("notes".to_string(), data))
+                .join()
+                .unwrap()
+        })
+        .unwrap();
+        assert_eq!(db.set_actor(None), Some("alice".to_string()));
+        db.update_by_id(&id, vec![UpdateOperation::set("text", NVValue::Null)])
+            .unwrap();
+        db.kill_by_id(&id).unwrap();
+
+        let mut query = NVQuery::new(AUDIT_COLLECTION.to_string());
+        query.add_condition(
+            "document_id".to_string(),
+            QueryOperator::Equals,
+            NVValue::String(id),
+            None,
+        );
+        // ULID entry IDs sort in write order, even within a millisecond
+        let mut entries = db.find(query).unwrap();
+        entries.sort_by(|a, b| a.id.cmp(&b.id));
+        let actions: Vec<_> = entries.iter().filter_map(|e| e.get("action")).collect();
+        assert_eq!(actions.len(), 3);
+        assert_eq!(actions[0], &NVValue::String("create".to_string()));
+        assert_eq!(entries[0].get("actor"), Some(&NVValue::String("alice".to_string())));
+        assert_eq!(entries[1].get("actor"), Some(&NVValue::Null));
+        assert_eq!(actions[2], &NVValue::String("delete".to_string()));
+
+        // Entries are append-only and hidden from the collection list
+        assert!(db.kill_by_id(&entries[0].id).is_err());
+        assert_eq!(db.collections().unwrap(), vec!["notes".to_string()]);
+    }
+
+    #[test]
+    fn test_replication() {
+        use std::sync::Arc;
+
+        let leader_dir = tempdir().unwrap();
+        let follower_dir = tempdir().unwrap();
+        let open = |dir: &tempfile::TempDir| {
+            Arc::new(
+                NeuralVault::new(DatabaseConfig {
+                    path: dir.path().to_str().unwrap().to_string(),
+                    ..Default::default()
+                })
+                .unwrap(),
+            )
+        };
+        let leader_db = open(&leader_dir);
+        let follower_db = open(&follower_dir);
+
+        leader_db
+            .create_role(Role {
+                name: "replica".to_string(),
+                permissions: HashMap::from([(auth::ALL_COLLECTIONS.to_string(), Access::Read)]),
+            })
+            .unwrap();
+        leader_db.create_user("follower", vec!["replica".to_string()]).unwrap();
+        let token = leader_db.issue_token("follower").unwrap();
+
+        let leader = ReplicationLeader::start(leader_db.clone(), "127.0.0.1:0").unwrap();
+        let address = leader.address().to_string();
+        let follower = Follower::new(follower_db.clone(), address.clone(), token.clone()).with_batch_size(2);
+        assert!(matches!(
+            Follower::new(follower_db.clone(), address, "bogus").sync(),
+            Err(NeuralVaultError::ReplicationError(_))
+        ));
+
+        let mut data = HashMap::new();
+        data.insert("n".to_string(), NVValue::Number(1.0));
+        let kept = leader_db.create("items".to_string(), data.clone()).unwrap();
+        let removed = leader_db.create("items".to_string(), data).unwrap();
+        leader_db
+            .update_by_id(&kept, vec![UpdateOperation::set("n", NVValue::Number(2.0))])
+            .unwrap();
+        leader_db.kill_by_id(&removed).unwrap();
+
+        assert!(follower.sync().unwrap() > 0);
+        assert_eq!(follower_db.find_by_id(&kept).unwrap().get("n"), Some(&NVValue::Number(2.0)));
+        assert!(follower_db.find_by_id(&removed).is_err());
+        assert_eq!(follower.sync().unwrap(), 0);
+
+        // Encrypted fields travel sealed, and credentials stay on the leader
+        let key = vec![7u8; 32];
+        let secret = "123-45-6789";
+        leader_db.unlock_fields(&key).unwrap();
+        leader_db.encrypt_field("users", "ssn").unwrap();
+        let mut data = HashMap::new();
+        data.insert("ssn".to_string(), NVValue::String(secret.to_string()));
+        let user = leader_db.create("users".to_string(), data).unwrap();
+        let page = leader_db.oplog(None, usize::MAX).unwrap();
+        assert!(page
+            .entries
+            .iter()
+            .all(|entry| !entry.record.windows(secret.len()).any(|window| window == secret.as_bytes())));
+
+        follower.sync().unwrap();
+        assert!(matches!(follower_db.authorized(&token), Err(NeuralVaultError::AccessDenied(_))));
+        assert!(follower_db.find_by_id(&user).unwrap().get("ssn").is_none());
+        follower_db.unlock_fields(&key).unwrap();
+        assert_eq!(
+            follower_db.find_by_id(&user).unwrap().get("ssn"),
+            Some(&NVValue::String(secret.to_string()))
+        );
+
+        // After compaction the follower re-reads the new log and drops
+        // documents deleted meanwhile
+        let mut data = HashMap::new();
+        data.insert("n".to_string(), NVValue::Number(3.0));
+        let later = leader_db.create("items".to_string(), data).unwrap();
+        follower.sync().unwrap();
+        leader_db.kill_by_id(&later).unwrap();
+        leader_db.compact().unwrap();
+
+        follower.sync().unwrap();
+        assert!(follower_db.find_by_id(&later).is_err());
+        assert_eq!(follower_db.count("items").unwrap(), 1);
+        assert!(follower_db.find_by_id(&user).is_ok());
+
+        // Stopping the leader closes its connections and lets go of the database
+        drop(leader);
+        assert_eq!(Arc::strong_count(&leader_db), 1);
+    }
+
+    #[test]
+    fn test_changes_since() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config.clone()).unwrap();
+
+        let first = db.create("items".to_string(), HashMap::new()).unwrap();
+        let initial = db.changes_since(0).unwrap();
+        assert_eq!(initial.changes.len(), 1);
+        assert_eq!(initial.changes[0].id, first);
+
+        db.set_metadata("ignored", NVValue::Bool(true)).unwrap();
+        let second = db.create("items".to_string(), HashMap::new()).unwrap();
+        db.kill_by_id(&first).unwrap();
+
+        let changes = db.changes_since(initial.next).unwrap();
+        let ids: Vec<_> = changes.changes.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, [second.as_str(), first.as_str()]);
+        assert_eq!(changes.changes[1].collection, "items");
+        assert!(changes.changes[1].document.is_none());
+        assert!(!changes.reset);
+
+        // Sequence numbers survive reopening and keep growing past compaction
+        drop(db);
+        let db = NeuralVault::new(config).unwrap();
+        assert!(db.changes_since(changes.next).unwrap().changes.is_empty());
+        db.compact().unwrap();
+
+        let after = db.changes_since(changes.next).unwrap();
+        assert!(after.reset);
+        assert_eq!(after.changes.len(), 1);
+        assert!(after.next > changes.next);
+        assert!(db.changes_since(after.next).unwrap().changes.is_empty());
+    }
+
+    #[test]
+    fn test_offline_sync() {
+        let open = |dir: &tempfile::TempDir| {
+            NeuralVault::new(DatabaseConfig {
+                path: dir.path().to_str().unwrap().to_string(),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let (phone_dir, server_dir) = (tempdir().unwrap(), tempdir().unwrap());
+        let phone = open(&phone_dir);
+        let server = open(&server_dir);
+
+        let note = |text: &str| {
+            let mut data = HashMap::new();
+            data.insert("text".to_string(), NVValue::String(text.to_string()));
+            data
+        };
+        let shared = phone
+            .create_with_id("shared".to_string(), "notes".to_string(), note("draft"))
+            .unwrap();
+        server.create("notes".to_string(), note("from server")).unwrap();
+
+        let report = sync::sync(&phone, &server, &ConflictResolver::LastWriterWins).unwrap();
+        assert_eq!((report.pushed, report.pulled, report.conflicts), (1, 1, 0));
+        assert_eq!(phone.count("notes").unwrap(), 2);
+        assert_eq!(server.count("notes").unwrap(), 2);
+
+        // Nothing is echoed back
+        let report = sync::sync(&phone, &server, &ConflictResolver::LastWriterWins).unwrap();
+        assert_eq!(report, SyncReport::default());
+
+        // Concurrent edits: the later one wins on both sides
+        let edit = |db: &NeuralVault, text: &str| {
+            db.update_by_id(&shared, vec![UpdateOperation::set("text", NVValue::String(text.to_string()))])
+                .unwrap();
+        };
+        edit(&phone, "phone edit");
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        edit(&server, "server edit");
+
+        let report = sync::sync(&phone, &server, &ConflictResolver::LastWriterWins).unwrap();
+        assert_eq!(report.conflicts, 1);
+        let text = |db: &NeuralVault| db.find_by_id(&shared).unwrap().get("text").cloned();
+        assert_eq!(text(&phone), Some(NVValue::String("server edit".to_string())));
+        assert_eq!(text(&server), text(&phone));
+
+        // A custom merge combines both versions
+        edit(&phone, "a");
+        edit(&server, "b");
+        let merge = ConflictResolver::Merge(Box::new(|conflict: &Conflict| {
+            let text = |doc: Option<&NVDocument>| match doc.and_then(|doc| doc.get("text")) {
+                Some(NVValue::String(text)) => text.clone(),
+                _ => String::new(),
+            };
+            let combined = format!("{}+{}", text(conflict.local), text(conflict.remote));
+            let mut merged = conflict.local.unwrap().clone();
+            merged.set("text".to_string(), NVValue::String(combined));
+            Ok(Some(merged))
+        }));
+        sync::sync(&phone, &server, &merge).unwrap();
+        assert_eq!(text(&phone), Some(NVValue::String("a+b".to_string())));
+        assert_eq!(text(&server), text(&phone));
+    }
+
+    #[test]
+    fn test_crdt_collection_sync() {
+        let open = |dir: &tempfile::TempDir| {
+            let db = NeuralVault::new(DatabaseConfig {
+                path: dir.path().to_str().unwrap().to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+            db.enable_crdt("tasks").unwrap();
+            db
+        };
+        let (phone_dir, tablet_dir) = (tempdir().unwrap(), tempdir().unwrap());
+        let phone = open(&phone_dir);
+        let tablet = open(&tablet_dir);
+
+        let id = phone.create("tasks".to_string(), HashMap::new()).unwrap();
+        sync::sync(&phone, &tablet, &ConflictResolver::LastWriterWins).unwrap();
+
+        // Offline edits to different fields on each device
+        phone
+            .update_by_id(&id, vec![UpdateOperation::set("title", NVValue::String("Buy milk".to_string()))])
+            .unwrap();
+        tablet
+            .update_by_id(&id, vec![UpdateOperation::set("done", NVValue::Bool(true))])
+            .unwrap();
+
+        let report = sync::sync(&phone, &tablet, &ConflictResolver::LastWriterWins).unwrap();
+        assert_eq!((report.merged, report.conflicts), (1, 0));
+        for db in [&phone, &tablet] {
+            let task = db.find_by_id(&id).unwrap();
+            assert_eq!(task.get("title"), Some(&NVValue::String("Buy milk".to_string())));
+            assert_eq!(task.get("done"), Some(&NVValue::Bool(true)));
+        }
+        assert_eq!(
+            sync::sync(&phone, &tablet, &ConflictResolver::LastWriterWins).unwrap(),
+            SyncReport::default()
+        );
+    }
+
+    #[test]
+    fn test_sync_writes_are_checked() {
+        let open = |dir: &tempfile::TempDir| {
+            NeuralVault::new(DatabaseConfig {
+                path: dir.path().to_str().unwrap().to_string(),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let (phone_dir, server_dir) = (tempdir().unwrap(), tempdir().unwrap());
+        let phone = open(&phone_dir);
+        let server = open(&server_dir);
+        phone.enable_audit_log(true).unwrap();
+        server.on_before_create(
+            "notes",
+            Box::new(|document: &mut NVDocument| match document.get("text") {
+                Some(NVValue::String(text)) if text == "spam" => {
+                    Err(NeuralVaultError::ValidationError("No spam".to_string()))
+                }
+                _ => Ok(()),
+            }),
+        );
+        let note = |text: &str| {
+            let mut data = HashMap::new();
+            data.insert("text".to_string(), NVValue::String(text.to_string()));
+            data
+        };
+
+        // Audit entries stay on the side that wrote them
+        let id = phone.create("notes".to_string(), note("hello")).unwrap();
+        sync::sync(&phone, &server, &ConflictResolver::LastWriterWins).unwrap();
+        let changes = server.changes_since(0).unwrap().changes;
+        assert!(changes.iter().all(|change| change.collection != audit::AUDIT_COLLECTION));
+
+        // The remote side's hooks can veto a write, failing the whole sync
+        phone.create("notes".to_string(), note("spam")).unwrap();
+        phone.create("notes".to_string(), note("fine")).unwrap();
+        assert!(sync::sync(&phone, &server, &ConflictResolver::LastWriterWins).is_err());
+        assert_eq!(server.count("notes").unwrap(), 1);
+
+        // A write made after the sync read the document is not overwritten
+        let read = SyncPeer::get(&server, &id).unwrap().unwrap();
+        server
+            .update_by_id(&id, vec![UpdateOperation::set("text", NVValue::String("newer".to_string()))])
+            .unwrap();
+        let mut stale = read.clone();
+        stale.set("text".to_string(), NVValue::String("older".to_string()));
+        let write = SyncWrite {
+            document: stale,
+            expected: Some(read.version),
+        };
+        assert!(matches!(SyncPeer::apply(&server, &[write]), Err(NeuralVaultError::WriteConflict(_))));
+        assert_eq!(
+            server.find_by_id(&id).unwrap().get("text"),
+            Some(&NVValue::String("newer".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_snapshot_isolation() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut data = HashMap::new();
+        data.insert("balance".to_string(), NVValue::Number(100.0));
+        let account = db.create("accounts".to_string(), data.clone()).unwrap();
+        let closed = db.create("accounts".to_string(), data.clone()).unwrap();
+
+        let snapshot = db.snapshot().unwrap();
+        db.update_by_id(&account, vec![UpdateOperation::set("balance", NVValue::Number(50.0))])
+            .unwrap();
+        db.kill_by_id(&closed).unwrap();
+        db.create("accounts".to_string(), data).unwrap();
+
+        assert_eq!(
+            snapshot.find_by_id(&account).unwrap().get("balance"),
+            Some(&NVValue::Number(100.0))
+        );
+        assert!(snapshot.find_by_id(&closed).is_ok());
+        assert_eq!(snapshot.count("accounts").unwrap(), 2);
+        assert_eq!(db.count("accounts").unwrap(), 2);
+        assert!(db.find_by_id(&closed).is_err());
+
+        // Compaction is refused while a snapshot is open
+        assert!(db.compact().is_err());
+        drop(snapshot);
+        db.compact().unwrap();
+    }
+
+    #[test]
+    fn test_transaction_conflict_writes_nothing() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let existing = db.create("items".to_string(), HashMap::new()).unwrap();
+
+        let mut tx = db.transaction();
+        tx.insert("items", HashMap::new());
+        tx.write(WriteOp::Insert {
+            id: Some(existing.clone()),
+            collection: "items".to_string(),
+            data: HashMap::new(),
+        });
+        assert!(matches!(tx.commit(), Err(NeuralVaultError::DuplicateId(_))));
+        assert_eq!(db.count("items").unwrap(), 1);
+
+        // Deleting first frees the ID within the same transaction
+        let mut tx = db.transaction();
+        tx.delete(&existing).write(WriteOp::Insert {
+            id: Some(existing.clone()),
+            collection: "items".to_string(),
+            data: HashMap::new(),
+        });
+        assert_eq!(tx.commit().unwrap(), vec![existing.clone(), existing]);
+        assert_eq!(db.count("items").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_transaction_veto_writes_nothing() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config.clone()).unwrap();
+        db.create_index(IndexDefinition {
+            name: "by_name".to_string(),
+            collection: "items".to_string(),
+            fields: vec!["name".to_string()],
+            filter: Vec::new(),
+        })
+        .unwrap();
+        db.on_before_create(
+            "items",
+            Box::new(|doc| match doc.get("name") {
+                Some(NVValue::String(name)) if name == "vetoed" => {
+                    Err(NeuralVaultError::ValidationError("vetoed".to_string()))
+                }
+                _ => Ok(()),
+            }),
+        );
+        let item = |name: &str| {
+            let mut data = HashMap::new();
+            data.insert("name".to_string(), NVValue::String(name.to_string()));
+            data
+        };
+        let named = |db: &NeuralVault, name: &str| {
+            let mut query = NVQuery::new("items".to_string());
+            query.add_condition("name".to_string(), QueryOperator::Equals, NVValue::String(name.to_string()), None);
+            db.find(query).unwrap().len()
+        };
+        let kept = db.create("items".to_string(), item("kept")).unwrap();
+        let removed = db.create("items".to_string(), item("removed")).unwrap();
+        let size = db.disk_usage().unwrap().total_bytes;
+
+        // The first insert, the update and the delete are all undone
+        let mut tx = db.transaction();
+        tx.update(&kept, vec![UpdateOperation::set("name", NVValue::String("renamed".to_string()))])
+            .delete(&removed)
+            .insert("items", item("first"))
+            .insert("items", item("vetoed"));
+        assert!(matches!(tx.commit(), Err(NeuralVaultError::ValidationError(_))));
+
+        assert_eq!(db.count("items").unwrap(), 2);
+        assert_eq!(named(&db, "first"), 0);
+        assert_eq!(named(&db, "renamed"), 0);
+        assert_eq!(named(&db, "kept"), 1);
+        assert_eq!(db.find_by_id(&removed).unwrap().get("name"), Some(&NVValue::String("removed".to_string())));
+        assert_eq!(db.disk_usage().unwrap().total_bytes, size);
+
+        // And stay undone once the data file is read back
+        drop(db);
+        let db = NeuralVault::new(config).unwrap();
+        assert_eq!(db.count("items").unwrap(), 2);
+        assert_eq!(named(&db, "first"), 0);
+        assert_eq!(db.find_by_id(&kept).unwrap().get("name"), Some(&NVValue::String("kept".to_string())));
+        assert!(db.find_by_id(&removed).is_ok());
+    }
+
+    #[test]
+    fn test_transact_retries_conflicts() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut data = HashMap::new();
+        data.insert("count".to_string(), NVValue::Number(0.0));
+        let counter = db.create("counters".to_string(), data).unwrap();
+
+        let increment = |db: &NeuralVault| {
+            db.transact(|tx| {
+                let count = match tx.read(&counter)?.get("count") {
+                    Some(NVValue::Number(n)) => *n,
+                    _ => 0.0,
+                };
+                tx.update(&counter, vec![UpdateOperation::set("count", NVValue::Number(count + 1.0))]);
+                Ok(())
+            })
+        };
+
+        // A write between the read and the commit forces one retry
+        let mut attempts = 0;
+        db.transact(|tx| {
+            attempts += 1;
+            tx.read(&counter)?;
+            if attempts == 1 {
+                increment(&db)?;
+            }
+            tx.update(&counter, vec![UpdateOperation::set("seen", NVValue::Bool(true))]);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(attempts, 2);
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..5 {
+                        increment(&db).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(
+            db.find_by_id(&counter).unwrap().get("count"),
+            Some(&NVValue::Number(21.0))
+        );
+    }
+
+    #[test]
+    fn test_archive_cold_documents() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config.clone()).unwrap();
+        for year in 2018..2024 {
+            let mut data = HashMap::new();
+            data.insert("year".to_string(), NVValue::Number(year as f64));
+            db.create("orders".to_string(), data).unwrap();
+        }
+
+        let mut old = NVQuery::new("orders".to_string());
+        old.add_condition("year".to_string(), QueryOperator::LessThan, NVValue::Number(2022.0), None);
+        assert_eq!(db.archive(old.clone()).unwrap(), 4);
+        assert_eq!(db.archive(old).unwrap(), 0);
+        assert_eq!(db.count("orders").unwrap(), 2);
+        drop(db);
+
+        let db = NeuralVault::new(config).unwrap();
+        let mut query = NVQuery::new("orders".to_string());
+        query.include_archived = true;
+        query.order_by = Some("year".to_string());
+        query.limit = Some(3);
+        let years: Vec<_> = db
+            .find(query)
+            .unwrap()
+            .iter()
+            .map(|doc| doc.get("year").cloned())
+            .collect();
+        assert_eq!(
+            years,
+            vec![
+                Some(NVValue::Number(2018.0)),
+                Some(NVValue::Number(2019.0)),
+                Some(NVValue::Number(2020.0))
+            ]
+        );
+        assert_eq!(db.find(NVQuery::new("orders".to_string())).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_archived_copies_stay_replaced() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config.clone()).unwrap();
+        let version = |n: f64| {
+            let mut data = HashMap::new();
+            data.insert("version".to_string(), NVValue::Number(n));
+            data
+        };
+        let all = || {
+            let mut query = NVQuery::new("orders".to_string());
+            query.include_archived = true;
+            query
+        };
+        db.create_with_id("a".to_string(), "orders".to_string(), version(1.0)).unwrap();
+        db.create_with_id("b".to_string(), "orders".to_string(), version(1.0)).unwrap();
+        assert_eq!(db.archive(NVQuery::new("orders".to_string())).unwrap(), 2);
+
+        // A new live "a" replaces the archived one, even once it's deleted
+        db.create_with_id("a".to_string(), "orders".to_string(), version(2.0)).unwrap();
+        db.kill_by_id("a").unwrap();
+        drop(db);
+        let db = NeuralVault::new(config).unwrap();
+        let ids: Vec<String> = db.find(all()).unwrap().into_iter().map(|doc| doc.id).collect();
+        assert_eq!(ids, vec!["b".to_string()]);
+
+        // Archiving a replacement makes its copy the one returned
+        db.create_with_id("b".to_string(), "orders".to_string(), version(3.0)).unwrap();
+        assert_eq!(db.archive(NVQuery::new("orders".to_string())).unwrap(), 1);
+        let found = db.find(all()).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].get("version"), Some(&NVValue::Number(3.0)));
+    }
+
+    #[test]
+    fn test_storage_quota() {
+        let payload = || {
+            let mut data = HashMap::new();
+            data.insert("blob".to_string(), NVValue::String("x".repeat(500)));
+            data
+        };
+
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            max_database_size: 32 * 1024,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut created = Vec::new();
+        let error = loop {
+            match db.create("notes".to_string(), payload()) {
+                Ok(id) => created.push(id),
+                Err(e) => break e,
+            }
+        };
+        assert!(matches!(error, NeuralVaultError::QuotaExceeded(_)));
+        assert!(!created.is_empty());
+        // Deletes still go through so space can be reclaimed
+        db.kill_by_id(&created[0]).unwrap();
+
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            max_database_size: 32 * 1024,
+            quota_policy: QuotaPolicy::EvictOldest,
+            cache_collections: vec!["thumbnails".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        // Evictions are deletes, so hooks can keep a document
+        db.on_before_delete(
+            "thumbnails",
+            Box::new(|doc| match doc.get("pinned") {
+                Some(NVValue::Bool(true)) => Err(NeuralVaultError::ValidationError("pinned".to_string())),
+                _ => Ok(()),
+            }),
+        );
+        let mut pinned = payload();
+        pinned.insert("pinned".to_string(), NVValue::Bool(true));
+        let pinned = db.create("thumbnails".to_string(), pinned).unwrap();
+
+        let kept = db.create("notes".to_string(), payload()).unwrap();
+        let thumbnails: Vec<_> = (0..200)
+            .map(|_| db.create("thumbnails".to_string(), payload()).unwrap())
+            .collect();
+        assert!(db.count("thumbnails").unwrap() < 200);
+        assert!(db.find_by_id(&thumbnails[0]).is_err());
+        assert!(db.find_by_id(&thumbnails[199]).is_ok());
+        assert!(db.find_by_id(&kept).is_ok());
+        assert!(db.find_by_id(&pinned).is_ok());
+
+        // An open snapshot refuses the compaction, not the writes
+        let snapshot = db.snapshot().unwrap();
+        for _ in 0..20 {
+            match db.create("thumbnails".to_string(), payload()) {
+                Ok(_) | Err(NeuralVaultError::QuotaExceeded(_)) => {}
+                Err(e) => panic!("write failed: {}", e),
+            }
+        }
+        drop(snapshot);
+        db.create("thumbnails".to_string(), payload()).unwrap();
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut data = HashMap::new();
+        data.insert("title".to_string(), NVValue::String("draft".to_string()));
+        let notes: Vec<_> = (0..4)
+            .map(|_| db.create("notes".to_string(), data.clone()).unwrap())
+            .collect();
+        db.create("tags".to_string(), data.clone()).unwrap();
+        db.update_by_id(&notes[0], vec![UpdateOperation::set("title", NVValue::String("final".to_string()))])
+            .unwrap();
+        db.kill_by_id(&notes[1]).unwrap();
+
+        let usage = db.disk_usage().unwrap();
+        let notes_usage = &usage.collections["notes"];
+        assert_eq!(notes_usage.documents, 3);
+        assert!(notes_usage.tombstone_bytes > 0);
+        assert!(notes_usage.fragmentation > 0.0 && notes_usage.fragmentation < 1.0);
+        assert_eq!(usage.collections["tags"].tombstone_bytes, 0);
+        assert!(usage.wal_bytes > 0);
+        assert!(usage.fragmentation > 0.0);
+
+        db.compact().unwrap();
+        let usage = db.disk_usage().unwrap();
+        assert_eq!(usage.tombstone_bytes, 0);
+        assert_eq!(usage.wal_bytes, 0);
+        assert_eq!(usage.collections["notes"].documents, 3);
+        assert!(usage.index_bytes > 0);
+    }
+
+    #[test]
+    fn test_compaction_progress_and_cancel() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let ids: Vec<_> = (0..600)
+            .map(|i| {
+                let mut data = HashMap::new();
+                data.insert("n".to_string(), NVValue::Number(i as f64));
+                db.create("items".to_string(), data).unwrap()
+            })
+            .collect();
+        for id in &ids[..300] {
+            db.kill_by_id(id).unwrap();
+        }
+        let size = db.stats().unwrap().storage_size_bytes;
+
+        // Cancelling after the first report leaves everything in place
+        let mut reports = 0;
+        let result = db.compact_with_progress(|_| {
+            reports += 1;
+            reports < 2
+        });
+        assert!(matches!(result, Err(NeuralVaultError::Cancelled(_))));
+        assert_eq!(db.stats().unwrap().storage_size_bytes, size);
+        assert_eq!(db.count("items").unwrap(), 300);
+        assert!(!dir.path().join("data.nvdb.compact").exists());
+
+        let mut last = CompactionProgress::default();
+        db.compact_with_progress(|progress| {
+            assert!(progress.documents_copied >= last.documents_copied);
+            last = *progress;
+            true
+        })
+        .unwrap();
+        assert_eq!(last.documents_copied, last.documents_total);
+        assert!(last.documents_total >= 300);
+        assert!(db.stats().unwrap().storage_size_bytes < size);
+        assert_eq!(db.count("items").unwrap(), 300);
+    }
+
+    #[test]
+    fn test_compaction_leaves_the_oplog_readable() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        for i in 0..300 {
+            let mut data = HashMap::new();
+            data.insert("n".to_string(), NVValue::Number(i as f64));
+            db.create("items".to_string(), data).unwrap();
+        }
+        let end_of_log = || {
+            let mut sequence = 0;
+            loop {
+                let next = db.changes_since(sequence).unwrap().next;
+                if next == sequence {
+                    return sequence;
+                }
+                sequence = next;
+            }
+        };
+
+        // The feed can be read while documents are copied, and the new log
+        // starts past the writes made meanwhile
+        let mut old_end = 0;
+        db.compact_with_progress(|progress| {
+            if progress.documents_copied == 0 {
+                db.create("items".to_string(), HashMap::new()).unwrap();
+                old_end = end_of_log();
+            }
+            true
+        })
+        .unwrap();
+        db.create("items".to_string(), HashMap::new()).unwrap();
+        assert!(db.changes_since(old_end).unwrap().reset);
+        assert!(end_of_log() > old_end);
+    }
+
+    #[test]
+    fn test_scheduled_backups_with_retention() {
+        let dir = tempdir().unwrap();
+        let backups = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            backup_dir: Some(backups.path().to_str().unwrap().to_string()),
+            backup_interval_secs: 1,
+            backup_keep: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut data = HashMap::new();
+        data.insert("title".to_string(), NVValue::String("draft".to_string()));
+        let id = db.create("notes".to_string(), data).unwrap();
+
+        let manual = db.backup(backups.path().to_str().unwrap()).unwrap();
+        let copy = NeuralVault::new(DatabaseConfig {
+            path: manual.path.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(copy.find_by_id(&id).is_ok());
+        drop(copy);
+
+        std::thread::sleep(std::time::Duration::from_millis(3500));
+        drop(db);
+        // Two scheduled backups are kept, and the manual one is never pruned
+        let kept = storage::backup::list(backups.path()).unwrap();
+        assert_eq!(kept.len(), 3);
+        assert!(kept.contains(&manual.path));
+    }
+
+
+    #[test]
+    fn test_incremental_backup_chain() {
+        let dir = tempdir().unwrap();
+        let backups = tempdir().unwrap();
+        let destination = backups.path().to_str().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let note = |title: &str| {
+            let mut data = HashMap::new();
+            data.insert("title".to_string(), NVValue::String(title.to_string()));
+            data
+        };
+        let kept = db.create("notes".to_string(), note("kept")).unwrap();
+        let deleted = db.create("notes".to_string(), note("deleted")).unwrap();
+        let full = db.backup(destination).unwrap();
+
+        let edited = db.create("notes".to_string(), note("draft")).unwrap();
+        db.kill_by_id(&deleted).unwrap();
+        let first = db.backup_incremental(destination, full.sequence).unwrap();
+        db.update_by_id(&edited, vec![UpdateOperation::set("title", NVValue::String("final".to_string()))])
+            .unwrap();
+        let second = db.backup_incremental(destination, first.sequence).unwrap();
+
+        db.compact().unwrap();
+        assert!(db.backup_incremental(destination, second.sequence).is_err());
+
+        let restored_dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: restored_dir.path().join("db").to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let chain: Vec<String> = [&first, &second]
+            .iter()
+            .map(|info| info.path.to_str().unwrap().to_string())
+            .collect();
+        let restored = NeuralVault::restore(config.clone(), full.path.to_str().unwrap(), &chain, None).unwrap();
+        assert_eq!(restored.count("notes").unwrap(), 2);
+        assert!(restored.find_by_id(&kept).is_ok());
+        assert!(restored.find_by_id(&deleted).is_err());
+        assert_eq!(
+            restored.find_by_id(&edited).unwrap().get("title"),
+            Some(&NVValue::String("final".to_string()))
+        );
+        drop(restored);
+
+        // A gap in the chain is rejected
+        let gap_dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: gap_dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        assert!(NeuralVault::restore(config, full.path.to_str().unwrap(), &chain[1..], None).is_err());
+    }
+
+
+    #[test]
+    fn test_encrypted_backups() {
+        let dir = tempdir().unwrap();
+        let backups = tempdir().unwrap();
+        let destination = backups.path().to_str().unwrap();
+        let config = |path: &std::path::Path| DatabaseConfig {
+            path: path.to_str().unwrap().to_string(),
+            kdf_memory_kib: 8,
+            kdf_iterations: 1,
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config(dir.path())).unwrap();
+        let mut data = HashMap::new();
+        data.insert("secret".to_string(), NVValue::String("launch codes".to_string()));
+        let first = db.create("notes".to_string(), data.clone()).unwrap();
+
+        let key = BackupKey::Passphrase("correct horse".to_string());
+        db.set_backup_key(Some(key.clone()));
+        let full = db.backup(destination).unwrap();
+        let second = db.create("notes".to_string(), data).unwrap();
+        let incremental = db.backup_incremental(destination, full.sequence).unwrap();
+
+        let data_file = std::fs::read(full.path.join("data.nvdb")).unwrap();
+        assert!(!data_file.windows(12).any(|window| window == b"launch codes"));
+
+        let full_path = full.path.to_str().unwrap();
+        let chain = vec![incremental.path.to_str().unwrap().to_string()];
+        let restore = |key: Option<&BackupKey>| {
+            let target = tempdir().unwrap();
+            let result = NeuralVault::restore(config(target.path()), full_path, &chain, key)
+                .map(|restored| restored.count("notes").unwrap());
+            (target, result)
+        };
+        assert!(restore(None).1.is_err());
+        assert!(restore(Some(&BackupKey::Passphrase("wrong".to_string()))).1.is_err());
+        assert!(restore(Some(&BackupKey::Key(vec![7; 32]))).1.is_err());
+        assert_eq!(restore(Some(&key)).1.unwrap(), 2);
+        assert!(db.find_by_id(&first).is_ok() && db.find_by_id(&second).is_ok());
+    }
+
+
+    #[test]
+    fn test_incremental_backup_keeps_fields_sealed_and_files() {
+        use crate::storage::backup;
+        use std::io::Read;
+
+        let dir = tempdir().unwrap();
+        let backups = tempdir().unwrap();
+        let destination = backups.path().to_str().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let key = vec![9u8; 32];
+        let ssn = "123-45-6789";
+        let mut data = HashMap::new();
+        data.insert("year".to_string(), NVValue::Number(2019.0));
+        db.create("orders".to_string(), data).unwrap();
+        db.unlock_fields(&key).unwrap();
+        db.encrypt_field("users", "ssn").unwrap();
+        let full = db.backup(destination).unwrap();
+
+        let mut data = HashMap::new();
+        data.insert("ssn".to_string(), NVValue::String(ssn.to_string()));
+        let user = db.create("users".to_string(), data).unwrap();
+        assert_eq!(db.archive(NVQuery::new("orders".to_string())).unwrap(), 1);
+        db.put_attachment(&user, "scan.pdf", &b"scanned"[..]).unwrap();
+        let first = db.backup_incremental(destination, full.sequence).unwrap();
+        let second = db.backup_incremental(destination, first.sequence).unwrap();
+
+        // Without a backup key, the encrypted field is still sealed
+        let incremental = backup::read_incremental(&first.path, None).unwrap();
+        assert!(!incremental.records.is_empty());
+        assert!(incremental
+            .records
+            .iter()
+            .all(|record| !record.windows(ssn.len()).any(|window| window == ssn.as_bytes())));
+
+        // The next incremental lists the segment without storing it again
+        let files = backup::read_incremental(&second.path, None).unwrap().files.unwrap();
+        let segment = files.iter().find(|file| file.name.starts_with("archive/")).unwrap();
+        assert!(segment.contents.is_none());
+
+        let restored_dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: restored_dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let chain: Vec<String> = [&first, &second]
+            .iter()
+            .map(|info| info.path.to_str().unwrap().to_string())
+            .collect();
+        let restored = NeuralVault::restore(config, full.path.to_str().unwrap(), &chain, None).unwrap();
+        restored.unlock_fields(&key).unwrap();
+        assert_eq!(
+            restored.find_by_id(&user).unwrap().get("ssn"),
+            Some(&NVValue::String(ssn.to_string()))
+        );
+        let mut query = NVQuery::new("orders".to_string());
+        query.include_archived = true;
+        assert_eq!(restored.find(query).unwrap().len(), 1);
+        let mut contents = Vec::new();
+        restored.get_attachment(&user, "scan.pdf").unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"scanned");
+    }
+
+
+    #[test]
+    fn test_cursor_returns_results_in_batches() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut ids = Vec::new();
+        for n in 0..25 {
+            let mut data = HashMap::new();
+            data.insert("n".to_string(), NVValue::Number(n as f64));
+            ids.push(db.create("items".to_string(), data).unwrap());
+        }
+
+        let mut query = NVQuery::new("items".to_string());
+        query.order_by = Some("n".to_string());
+        query.projection = Some(vec!["n".to_string(), "tag".to_string()]);
+        let mut cursor = db.open_cursor(query).unwrap();
+        assert_eq!(cursor.remaining(), 25);
+
+        // Batches are read when fetched, so they see later writes
+        db.bulk_write(vec![WriteOp::Delete { id: ids[3].clone() }]).unwrap();
+        db.update_by_id(&ids[4], vec![UpdateOperation::set("tag", NVValue::String("late".to_string()))])
+            .unwrap();
+
+        let mut seen = Vec::new();
+        loop {
+            let batch = db.next_batch(&mut cursor, 10).unwrap();
+            if batch.is_empty() {
+                break;
+            }
+            assert!(batch.len() <= 10);
+            for doc in batch {
+                assert!(doc.data.keys().all(|field| field == "n" || field == "tag"));
+                if doc.id == ids[4] {
+                    assert_eq!(doc.data["tag"], NVValue::String("late".to_string()));
+                }
+                seen.push(doc.data["n"].clone());
+            }
+        }
+        assert!(cursor.is_exhausted());
+        let expected: Vec<NVValue> = (0..25).filter(|n| *n != 3).map(|n| NVValue::Number(n as f64)).collect();
+        assert_eq!(seen, expected);
+
+        // Conditions, order, skip and limit pick the same results as `find`
+        let mut query = NVQuery::new("items".to_string());
+        query.add_condition("n".to_string(), QueryOperator::GreaterThan, NVValue::Number(5.0), None);
+        query.order_by = Some("n".to_string());
+        query.order_desc = true;
+        query.skip = Some(2);
+        query.limit = Some(4);
+        let found: Vec<String> = db.find(query.clone()).unwrap().into_iter().map(|doc| doc.id).collect();
+        let mut cursor = db.open_cursor(query).unwrap();
+        let read: Vec<String> = db.next_batch(&mut cursor, 10).unwrap().into_iter().map(|doc| doc.id).collect();
+        assert_eq!(read, found);
+        assert_eq!(read.len(), 4);
+    }
+
+    #[test]
+    fn test_in_subquery_semi_join() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut users = Vec::new();
+        for plan in ["pro", "free", "pro"] {
+            let mut data = HashMap::new();
+            data.insert("plan".to_string(), NVValue::String(plan.to_string()));
+            users.push(db.create("users".to_string(), data).unwrap());
+        }
+        for user in &users {
+            let mut data = HashMap::new();
+            data.insert("user_id".to_string(), NVValue::String(user.clone()));
+            db.create("orders".to_string(), data).unwrap();
+        }
+
+        let orders = db
+            .query_sql("SELECT * FROM orders WHERE user_id IN (SELECT id FROM users WHERE plan = 'pro')")
+            .unwrap();
+        let mut buyers: Vec<String> = orders
+            .into_iter()
+            .filter_map(|doc| match &doc.data["user_id"] {
+                NVValue::String(id) => Some(id.clone()),
+                _ => None,
+            })
+            .collect();
+        buyers.sort();
+        let mut expected = vec![users[0].clone(), users[2].clone()];
+        expected.sort();
+        assert_eq!(buyers, expected);
+
+        let mut query = NVQuery::new("orders".to_string());
+        let mut free = NVQuery::new("users".to_string());
+        free.add_condition("plan".to_string(), QueryOperator::Equals, NVValue::String("free".to_string()), None);
+        query.add_subquery("user_id".to_string(), QueryOperator::NotIn, None, free, None);
+        assert_eq!(db.find(query).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_computed_fields_survive_projection() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut data = HashMap::new();
+        data.insert("price".to_string(), NVValue::Number(4.0));
+        data.insert("quantity".to_string(), NVValue::Number(5.0));
+        data.insert("sku".to_string(), NVValue::String("A-1".to_string()));
+        let id = db.create("lines".to_string(), data).unwrap();
+
+        let mut query = NVQuery::new("lines".to_string());
+        query.projection = Some(vec!["sku".to_string()]);
+        query.computed = vec![(
+            "total".to_string(),
+            Expression::Multiply(vec![
+                Expression::Field("price".to_string()),
+                Expression::Field("quantity".to_string()),
+            ]),
+        )];
+        let results = db.find(query.clone()).unwrap();
+        assert_eq!(results[0].data.len(), 2);
+        assert_eq!(results[0].data["total"], NVValue::Number(20.0));
+
+        // Updates through a query with computed fields don't store them
+        db.update(query, vec![UpdateOperation::set("sku".to_string(), NVValue::String("A-2".to_string()))]).unwrap();
+        assert!(!db.find_by_id(&id).unwrap().data.contains_key("total"));
+    }
+
+    #[test]
+    fn test_sample_collection() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        for n in 0..10 {
+            let mut data = HashMap::new();
+            data.insert("n".to_string(), NVValue::Number(n as f64));
+            db.create("items".to_string(), data.clone()).unwrap();
+            db.create("other".to_string(), data).unwrap();
+        }
+
+        let sample = db.sample("items", 4).unwrap();
+        assert_eq!(sample.len(), 4);
+        assert!(sample.iter().all(|doc| doc.collection == "items"));
+        let mut ids: Vec<&str> = sample.iter().map(|doc| doc.id.as_str()).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 4);
+        assert_eq!(db.sample("items", 50).unwrap().len(), 10);
+    }
+
+    #[test]
+    fn test_vector_index_resumes_from_checkpoint() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let checkpoint = dir.path().join("vectors").join("note_embeddings.nvvec");
+        let stale = dir.path().join("stale.nvvec");
+
+        let note = |x: f64, y: f64| {
+            let mut data = HashMap::new();
+            data.insert(
+                "embedding".to_string(),
+                NVValue::Array(vec![NVValue::Number(x), NVValue::Number(y)]),
+            );
+            data
+        };
+        let nearest = |db: &NeuralVault, vector: &[f32]| -> Vec<String> {
+            db.find_similar("note_embeddings", vector, 2)
+                .unwrap()
+                .into_iter()
+                .map(|(document, _)| document.id)
+                .collect()
+        };
+
+        let (east, north, west);
+        {
+            let db = NeuralVault::new(config.clone()).unwrap();
+            east = db.create("notes".to_string(), note(1.0, 0.0)).unwrap();
+            north = db.create("notes".to_string(), note(0.0, 1.0)).unwrap();
+
+            db.create_vector_index(VectorIndexDefinition {
+                name: "note_embeddings".to_string(),
+                collection: "notes".to_string(),
+                field: "embedding".to_string(),
+                dimensions: 2,
+                metric: VectorMetric::Cosine,
+            quantization: Quantization::None,
+            })
+            .unwrap();
+            assert_eq!(nearest(&db, &[1.0, 0.1]), vec![east.clone(), north.clone()]);
+            std::fs::copy(&checkpoint, &stale).unwrap();
+
+            // Writes after the checkpoint are applied incrementally
+            west = db.create("notes".to_string(), note(-1.0, 0.0)).unwrap();
+            db.update_by_id(&north, vec![UpdateOperation::set("embedding", NVValue::Null)])
+                .unwrap();
+            db.kill_by_id(&east).unwrap();
+            assert_eq!(nearest(&db, &[1.0, 0.1]), vec![west.clone()]);
+        }
+
+        // Reopening from the stale checkpoint replays the writes made since
+        std::fs::copy(&stale, &checkpoint).unwrap();
+        {
+            let db = NeuralVault::new(config.clone()).unwrap();
+            assert_eq!(db.vector_indexes()[0].1, 1);
+            assert_eq!(nearest(&db, &[1.0, 0.1]), vec![west.clone()]);
+
+            db.create("notes".to_string(), note(0.0, -1.0)).unwrap();
+            db.compact().unwrap();
+        }
+
+        // A checkpoint from before a compaction is rebuilt rather than replayed
+        std::fs::copy(&stale, &checkpoint).unwrap();
+        let db = NeuralVault::new(config).unwrap();
+        assert_eq!(db.vector_indexes()[0].1, 2);
+        assert_eq!(nearest(&db, &[-1.0, 0.0])[0], west);
+
+        assert!(db.drop_vector_index("note_embeddings").unwrap());
+        assert!(!checkpoint.exists());
+        assert!(db.find_similar("note_embeddings", &[1.0, 0.0], 1).is_err());
+    }
+
+    #[test]
+    fn test_hybrid_search() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let note = |body: &str, x: f64, y: f64| {
+            let mut data = HashMap::new();
+            data.insert("body".to_string(), NVValue::String(body.to_string()));
+            data.insert(
+                "embedding".to_string(),
+                NVValue::Array(vec![NVValue::Number(x), NVValue::Number(y)]),
+            );
+            data
+        };
+        let keyword = db.create("notes".to_string(), note("notes on rust and storage, among other things", -1.0, 0.0)).unwrap();
+        let semantic = db.create("notes".to_string(), note("databases on disk", 1.0, 0.0)).unwrap();
+        let both = db.create("notes".to_string(), note("a storage engine in rust", 0.9, 0.1)).unwrap();
+
+        db.create_text_index(TextIndexDefinition {
+            name: "note_text".to_string(),
+            collection: "notes".to_string(),
+            fields: vec!["body".to_string()],
+            tokenizer: Tokenizer::default(),
+        })
+        .unwrap();
+        db.create_vector_index(VectorIndexDefinition {
+            name: "note_embeddings".to_string(),
+            collection: "notes".to_string(),
+            field: "embedding".to_string(),
+            dimensions: 2,
+            metric: VectorMetric::Cosine,
+            quantization: Quantization::None,
+        })
+        .unwrap();
+
+        let ids = |results: Vec<(NVDocument, f32)>| -> Vec<String> {
+            results.into_iter().map(|(document, _)| document.id).collect()
+        };
+        let text = db.search_text("note_text", "rust storage", 10).unwrap();
+        assert_eq!(text.len(), 2);
+
+        let mut query = HybridQuery {
+            text_index: "note_text".to_string(),
+            text: "rust storage".to_string(),
+            vector_index: "note_embeddings".to_string(),
+            vector: vec![1.0, 0.0],
+            limit: 3,
+            fusion: Fusion::default(),
+        };
+        assert_eq!(ids(db.hybrid_search(&query).unwrap())[0], both);
+
+        query.fusion = Fusion::Weighted { text: 0.0, vector: 1.0 };
+        assert_eq!(ids(db.hybrid_search(&query).unwrap())[0], semantic);
+        query.fusion = Fusion::Weighted { text: 1.0, vector: 0.0 };
+        assert_eq!(ids(db.hybrid_search(&query).unwrap())[0], both);
+
+        // Text indexes follow writes
+        db.update_by_id(&keyword, vec![UpdateOperation::set("body", NVValue::String("gardening".to_string()))])
+            .unwrap();
+        assert_eq!(ids(db.search_text("note_text", "rust", 10).unwrap()), vec![both.clone()]);
+
+        query.vector_index = "missing".to_string();
+        assert!(db.hybrid_search(&query).is_err());
+    }
+
+    #[test]
+    fn test_registered_embedder_keeps_vectors_in_sync() {
+        /// Places text on the unit circle by whether it mentions cats or dogs
+        struct PetEmbedder;
+        impl Embedder for PetEmbedder {
+            fn dimensions(&self) -> usize {
+                2
+            }
+            fn embed(&self, text: &str) -> NVResult<Vec<f32>> {
+                Ok(vec![text.contains("cat") as u8 as f32, text.contains("dog") as u8 as f32 + 0.1])
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        db.register_embedder("notes", "text", "embedding", std::sync::Arc::new(PetEmbedder))
+            .unwrap();
+        assert!(db
+            .register_embedder("notes", "text", "text", std::sync::Arc::new(PetEmbedder))
+            .is_err());
+
+        let note = |text: &str| {
+            let mut data = HashMap::new();
+            data.insert("text".to_string(), NVValue::String(text.to_string()));
+            data
+        };
+        let cats = db.create("notes".to_string(), note("my cat sleeps")).unwrap();
+        let dogs = db.create("notes".to_string(), note("walking the dog")).unwrap();
+        assert!(db.find_by_id(&cats).unwrap().get("embedding").is_some());
+
+        db.create_vector_index(VectorIndexDefinition {
+            name: "note_embeddings".to_string(),
+            collection: "notes".to_string(),
+            field: "embedding".to_string(),
+            dimensions: 2,
+            metric: VectorMetric::Cosine,
+            quantization: Quantization::None,
+        })
+        .unwrap();
+        let nearest = |text: &str| db.find_similar_text("note_embeddings", text, 1).unwrap()[0].0.id.clone();
+        assert_eq!(nearest("a cat"), cats);
+        assert_eq!(nearest("a dog"), dogs);
+
+        // Editing the text recomputes the vector
+        db.update_by_id(&cats, vec![UpdateOperation::set("text", NVValue::String("a dog too".to_string()))])
+            .unwrap();
+        let both = db.find_similar_text("note_embeddings", "a dog", 2).unwrap();
+        assert!(both.iter().all(|(_, distance)| *distance < 1e-6));
+
+        assert!(db.unregister_embedder("notes", "embedding"));
+        assert!(db.find_similar_text("note_embeddings", "a cat", 1).is_err());
+    }
+
+    #[test]
+    fn test_filtered_vector_search() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // 300 notes around the unit circle, spread over three notebooks
+        for i in 0..300 {
+            let angle = i as f64 / 300.0 * std::f64::consts::TAU;
+            let mut data = HashMap::new();
+            data.insert("notebook".to_string(), NVValue::Number((i % 3) as f64));
+            data.insert("position".to_string(), NVValue::Number(i as f64));
+            data.insert(
+                "embedding".to_string(),
+                NVValue::Array(vec![NVValue::Number(angle.cos()), NVValue::Number(angle.sin())]),
+            );
+            db.create_with_id(format!("n{}", i), "notes".to_string(), data).unwrap();
+        }
+        db.create_vector_index(VectorIndexDefinition {
+            name: "note_embeddings".to_string(),
+            collection: "notes".to_string(),
+            field: "embedding".to_string(),
+            dimensions: 2,
+            metric: VectorMetric::Cosine,
+            quantization: Quantization::None,
+        })
+        .unwrap();
+        db.create_index(IndexDefinition {
+            name: "by_position".to_string(),
+            collection: "notes".to_string(),
+            fields: vec!["position".to_string()],
+            filter: Vec::new(),
+        })
+        .unwrap();
+
+        let ids = |results: Vec<(NVDocument, f32)>| -> Vec<String> {
+            results.into_iter().map(|(document, _)| document.id).collect()
+        };
+        let filter = |field: &str, operator, value: f64| {
+            let mut query = NVQuery::new("notes".to_string());
+            query.add_condition(field.to_string(), operator, NVValue::Number(value), None);
+            query
+        };
+
+        // An unindexed filter is applied after the vector search
+        let in_notebook = filter("notebook", QueryOperator::Equals, 1.0);
+        let nearest = ids(db.find_similar_where("note_embeddings", &[1.0, 0.0], 2, in_notebook).unwrap());
+        assert_eq!(nearest, vec!["n1".to_string(), "n298".to_string()]);
+
+        // A selective indexed filter ranks its few matches exactly
+        let late = filter("position", QueryOperator::GreaterThanOrEqual, 290.0);
+        let nearest = ids(db.find_similar_where("note_embeddings", &[1.0, 0.0], 3, late).unwrap());
+        assert_eq!(nearest, vec!["n299".to_string(), "n298".to_string(), "n297".to_string()]);
+
+        let nothing = filter("notebook", QueryOperator::Equals, 7.0);
+        assert!(db.find_similar_where("note_embeddings", &[1.0, 0.0], 3, nothing).unwrap().is_empty());
+
+        let other = NVQuery::new("tasks".to_string());
+        assert!(db.find_similar_where("note_embeddings", &[1.0, 0.0], 3, other).is_err());
+    }
+    #[test]
+    fn test_ingest_chunks() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        db.create_text_index(TextIndexDefinition {
+            name: "chunk_text".to_string(),
+            collection: "chunks".to_string(),
+            fields: vec!["text".to_string()],
+            tokenizer: Tokenizer::default(),
+        })
+        .unwrap();
+
+        let mut data = HashMap::new();
+        data.insert(
+            "body".to_string(),
+            NVValue::String("Rust is fast. Tokio runs async tasks. Serde handles JSON.".to_string()),
+        );
+        data.insert("title".to_string(), NVValue::Number(1.0));
+        let article = db.create("articles".to_string(), data).unwrap();
+
+        let options = ChunkOptions {
+            max_tokens: 4,
+            overlap: 0,
+            by: ChunkBoundary::Sentences,
+        };
+        let ids = db.ingest_chunks(&article, "body", "chunks", &options).unwrap();
+        assert_eq!(ids.len(), 3);
+        let second = db.find_by_id(&ids[1]).unwrap();
+        assert_eq!(second.get("text"), Some(&NVValue::String("Tokio runs async tasks.".to_string())));
+        assert_eq!(second.get("source_id"), Some(&NVValue::String(article.clone())));
+        assert_eq!(second.get("chunk"), Some(&NVValue::Number(1.0)));
+        assert_eq!(second.get("start"), Some(&NVValue::Number(14.0)));
+        let found = db.search_text("chunk_text", "async", 10).unwrap();
+        assert_eq!(found[0].0.id, ids[1]);
+
+        // Re-ingesting replaces the earlier chunks
+        let whole = ChunkOptions {
+            max_tokens: 50,
+            ..options
+        };
+        let ids = db.ingest_chunks(&article, "body", "chunks", &whole).unwrap();
+        assert_eq!(ids.len(), 1);
+        assert_eq!(db.find(NVQuery::new("chunks".to_string())).unwrap().len(), 1);
+        assert!(db.ingest_chunks(&article, "title", "chunks", &whole).is_err());
+
+        // Deleting the source cascades to its chunks
+        db.kill_by_id(&article).unwrap();
+        assert!(db.find(NVQuery::new("chunks".to_string())).unwrap().is_empty());
+        assert!(db.search_text("chunk_text", "async", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_text_index_tokenizer_survives_reopen() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let definition = TextIndexDefinition {
+            name: "note_text".to_string(),
+            collection: "notes".to_string(),
+            fields: vec!["body".to_string()],
+            tokenizer: Tokenizer {
+                stemming: true,
+                stop_words: true,
+                cjk_ngrams: Some(2),
+                ..Default::default()
+            },
+        };
+        {
+            let db = NeuralVault::new(config.clone()).unwrap();
+            let mut data = HashMap::new();
+            data.insert("body".to_string(), NVValue::String("Indexing 数据库 documents".to_string()));
+            db.create("notes".to_string(), data).unwrap();
+            assert!(db
+                .create_text_index(TextIndexDefinition {
+                    tokenizer: Tokenizer {
+                        cjk_ngrams: Some(0),
+                        ..Default::default()
+                    },
+                    ..definition.clone()
+                })
+                .is_err());
+            db.create_text_index(definition.clone()).unwrap();
+            assert_eq!(db.search_text("note_text", "indexed", 10).unwrap().len(), 1);
+        }
+
+        let db = NeuralVault::new(config).unwrap();
+        assert_eq!(db.text_indexes()[0].0.tokenizer, definition.tokenizer);
+        assert_eq!(db.search_text("note_text", "the document", 10).unwrap().len(), 1);
+        assert_eq!(db.search_text("note_text", "数据", 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_fuzzy_matching() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let note = |title: &str| {
+            let mut data = HashMap::new();
+            data.insert("title".to_string(), NVValue::String(title.to_string()));
+            data
+        };
+        let receive = db.create("notes".to_string(), note("How to Receive payments")).unwrap();
+        db.create("notes".to_string(), note("Sending invoices")).unwrap();
+
+        let mut query = NVQuery::new("notes".to_string());
+        query.add_condition(
+            "title".to_string(),
+            QueryOperator::Fuzzy { max_distance: 1 },
+            NVValue::String("recieve".to_string()),
+            None,
+        );
+        let results = db.find(query.clone()).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, receive);
+        query.conditions[0].operator = QueryOperator::Fuzzy { max_distance: 0 };
+        assert!(db.find(query).unwrap().is_empty());
+
+        db.create_text_index(TextIndexDefinition {
+            name: "note_text".to_string(),
+            collection: "notes".to_string(),
+            fields: vec!["title".to_string()],
+            tokenizer: Tokenizer::default(),
+        })
+        .unwrap();
+        assert!(db.search_text("note_text", "recieve", 10).unwrap().is_empty());
+        assert_eq!(db.search_text_fuzzy("note_text", "recieve", 10, 1).unwrap()[0].0.id, receive);
+
+        let options = HighlightOptions {
+            max_distance: 1,
+            ..Default::default()
+        };
+        let results = db.search_text_highlighted("note_text", "recieve", 10, &options).unwrap();
+        assert_eq!(results[0].2[0].snippet, "How to **Receive** payments");
+        assert_eq!(results[0].2[0].matches, vec![(7, 14)]);
+    }
+
+    #[test]
+    fn test_autocomplete() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let city = |name: &str| {
+            let mut data = HashMap::new();
+            data.insert("city".to_string(), NVValue::String(name.to_string()));
+            data
+        };
+        {
+            let db = NeuralVault::new(config.clone()).unwrap();
+            db.create("users".to_string(), city("Berlin")).unwrap();
+            db.create_completion_index("users", "city").unwrap();
+            let bern = db.create("users".to_string(), city("Bern")).unwrap();
+            db.create("users".to_string(), city("berlin")).unwrap();
+            db.create("users".to_string(), city("Boston")).unwrap();
+
+            assert_eq!(
+                db.suggest("users", "city", "ber", 10).unwrap(),
+                vec![("Berlin".to_string(), 2), ("Bern".to_string(), 1)]
+            );
+            db.update_by_id(&bern, vec![UpdateOperation::set("city", NVValue::String("Paris".to_string()))])
+                .unwrap();
+            assert_eq!(db.suggest("users", "city", "bern", 10).unwrap(), vec![]);
+            assert!(db.suggest("users", "name", "b", 10).is_err());
+        }
+
+        // Rebuilt on open
+        let db = NeuralVault::new(config).unwrap();
+        assert_eq!(db.completion_indexes()[0].1, 3);
+        assert_eq!(db.suggest("users", "city", "B", 1).unwrap(), vec![("Berlin".to_string(), 2)]);
+        assert!(db.drop_completion_index("users", "city").unwrap());
+        assert!(db.suggest("users", "city", "b", 10).is_err());
+    }
+
+    #[test]
+    fn test_query_cancellation_and_timeout() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        for i in 0..10 {
+            let mut data = HashMap::new();
+            data.insert("n".to_string(), NVValue::Number(i as f64));
+            db.create("items".to_string(), data).unwrap();
+        }
+
+        let token = CancellationToken::new();
+        let query = NVQuery::new("items".to_string());
+        assert_eq!(db.find_cancellable(query.clone(), &token).unwrap().len(), 10);
+
+        token.clone().cancel();
+        assert!(matches!(db.find_cancellable(query.clone(), &token), Err(NeuralVaultError::Cancelled(_))));
+        assert!(matches!(
+            db.aggregate_cancellable("items", vec![Stage::Limit(1)], &token),
+            Err(NeuralVaultError::Cancelled(_))
+        ));
+
+        let mut timed = query.clone();
+        timed.timeout_ms = Some(0);
+        assert!(matches!(db.find(timed), Err(NeuralVaultError::Cancelled(_))));
+        let mut generous = query;
+        generous.timeout_ms = Some(60_000);
+        assert_eq!(db.find(generous).unwrap().len(), 10);
+    }
+
+    #[test]
+    fn test_query_stats() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        for i in 0..20 {
+            let mut data = HashMap::new();
+            data.insert("n".to_string(), NVValue::Number(i as f64));
+            db.create("items".to_string(), data).unwrap();
+        }
+
+        let mut query = NVQuery::new("items".to_string());
+        query.add_condition("n".to_string(), QueryOperator::GreaterThanOrEqual, NVValue::Number(15.0), None);
+        let (documents, stats) = db.find_with_stats(query.clone(), &CancellationToken::new()).unwrap();
+        assert_eq!(documents.len(), 5);
+        // A full scan reads every stored record, system ones included
+        assert!(stats.scanned >= 20);
+        assert_eq!(stats.returned, 5);
+        assert_eq!(stats.index, None);
+        assert!(matches!(stats.plan, Some(QueryPlan::FullScan { .. })));
+
+        db.create_index(IndexDefinition {
+            name: "by_n".to_string(),
+            collection: "items".to_string(),
+            fields: vec!["n".to_string()],
+            filter: Vec::new(),
+        })
+        .unwrap();
+        let (documents, stats) = db.find_with_stats(query, &CancellationToken::new()).unwrap();
+        assert_eq!(documents.len(), 5);
+        assert_eq!((stats.scanned, stats.returned), (5, 5));
+        assert_eq!(stats.index.as_deref(), Some("by_n"));
+        assert!(stats.elapsed_ms >= 0.0);
+    }
+
+    #[test]
+    fn test_tenant_middleware() {
+        struct Tenant(&'static str);
+
+        impl Middleware for Tenant {
+            fn on_query(&self, query: &mut NVQuery) -> NVResult<()> {
+                query.add_condition(
+                    "tenant_id".to_string(),
+                    QueryOperator::Equals,
+                    NVValue::String(self.0.to_string()),
+                    None,
+                );
+                Ok(())
+            }
+
+            fn on_read(&self, document: &mut NVDocument) -> NVResult<()> {
+                document.data.remove("secret");
+                Ok(())
+            }
+
+            fn on_create(&self, document: &mut NVDocument) -> NVResult<()> {
+                document.set("tenant_id".to_string(), NVValue::String(self.0.to_string()));
+                Ok(())
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut other = HashMap::new();
+        other.insert("tenant_id".to_string(), NVValue::String("globex".to_string()));
+        db.create("notes".to_string(), other).unwrap();
+
+        db.use_middleware(Box::new(Tenant("acme")));
+        let mut data = HashMap::new();
+        data.insert("secret".to_string(), NVValue::String("hunter2".to_string()));
+        let id = db.create("notes".to_string(), data).unwrap();
+
+        let notes = db.find(NVQuery::new("notes".to_string())).unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].get("tenant_id"), Some(&NVValue::String("acme".to_string())));
+        assert_eq!(notes[0].get("secret"), None);
+        assert_eq!(db.find_by_id(&id).unwrap().get("secret"), None);
+
+        // Writes by query only reach the tenant's documents, whole
+        assert_eq!(db.kill(NVQuery::new("notes".to_string())).unwrap(), 1);
+        assert_eq!(db.count("notes").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_scoped_tenants() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let acme = db.scoped("acme").unwrap();
+        let globex = db.scoped("globex").unwrap();
+        assert!(db.scoped("a__b").is_err());
+
+        let note = |title: &str| {
+            let mut data = HashMap::new();
+            data.insert("title".to_string(), NVValue::String(title.to_string()));
+            data
+        };
+        let id = acme.create("notes", note("plan")).unwrap();
+        acme.create("notes", note("launch")).unwrap();
+        globex.create("notes", note("rival")).unwrap();
+
+        assert_eq!(acme.count("notes").unwrap(), 2);
+        assert_eq!(globex.count("notes").unwrap(), 1);
+        assert_eq!(acme.find(NVQuery::new("notes".to_string())).unwrap()[0].collection, "notes");
+        assert_eq!(acme.collections().unwrap(), vec!["notes".to_string()]);
+        assert_eq!(db.count("acme__notes").unwrap(), 2);
+
+        assert_eq!(acme.find_by_id(&id).unwrap().collection, "notes");
+        assert!(matches!(globex.find_by_id(&id), Err(NeuralVaultError::DocumentNotFound(_))));
+        assert!(globex.kill_by_id(&id).is_err());
+        assert_eq!(globex.kill(NVQuery::new("notes".to_string())).unwrap(), 1);
+        assert_eq!(acme.count("notes").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_scoped_populate_stays_in_tenant() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let alice = db.scoped("alice").unwrap();
+        let bob = db.scoped("bob").unwrap();
+
+        let mut user = HashMap::new();
+        user.insert("ssn".to_string(), NVValue::String("123-45-6789".to_string()));
+        let bob_user = bob.create("users", user.clone()).unwrap();
+        let alice_user = alice.create("users", user).unwrap();
+
+        let mut post = HashMap::new();
+        post.insert("owner".to_string(), NVValue::String(bob_user.clone()));
+        post.insert("author".to_string(), NVValue::String(alice_user));
+        alice.create("posts", post).unwrap();
+
+        // Neither a namespaced nor a plain target reaches bob's users
+        for spec in ["owner:bob__users", "owner:users"] {
+            let mut query = NVQuery::new("posts".to_string());
+            query.populate = vec![spec.to_string()];
+            let results = alice.find(query).unwrap();
+            assert_eq!(results[0].get("owner"), Some(&NVValue::Null), "{}", spec);
+        }
+
+        // A declared reference to another tenant's collection resolves to null
+        db.add_reference(Reference {
+            collection: "alice__posts".to_string(),
+            field: "owner".to_string(),
+            target: "bob__users".to_string(),
+            on_delete: OnDelete::Restrict,
+        })
+        .unwrap();
+        let mut query = NVQuery::new("posts".to_string());
+        query.populate = vec!["owner".to_string(), "author:users".to_string()];
+        let results = alice.find(query).unwrap();
+        assert_eq!(results[0].get("owner"), Some(&NVValue::Null));
+        assert!(matches!(results[0].get("author"), Some(NVValue::Object(_))));
+    }
+
+    #[test]
+    fn test_capped_collection() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_string();
+        let log = |db: &NeuralVault, n: usize| {
+            let mut data = HashMap::new();
+            data.insert("n".to_string(), NVValue::Number(n as f64));
+            db.create("logs".to_string(), data).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        };
+        let numbers = |db: &NeuralVault| {
+            let mut query = NVQuery::new("logs".to_string());
+            query.order_by = Some("n".to_string());
+            db.find(query)
+                .unwrap()
+                .iter()
+                .map(|doc| doc.get("n").cloned().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        {
+            let db = NeuralVault::new(DatabaseConfig {
+                path: path.clone(),
+                ..Default::default()
+            })
+            .unwrap();
+            for n in 0..5 {
+                log(&db, n);
+            }
+            let cap = CollectionCap {
+                max_documents: Some(3),
+                max_bytes: None,
+            };
+            db.set_capped("logs", cap).unwrap();
+            assert_eq!(db.count("logs").unwrap(), 3);
+            assert_eq!(db.collection_cap("logs"), Some(cap));
+
+            log(&db, 5);
+            assert_eq!(numbers(&db), vec![NVValue::Number(3.0), NVValue::Number(4.0), NVValue::Number(5.0)]);
+        }
+
+        // The cap and the documents' ages survive reopening
+        let db = NeuralVault::new(DatabaseConfig {
+            path,
+            ..Default::default()
+        })
+        .unwrap();
+        log(&db, 6);
+        assert_eq!(numbers(&db), vec![NVValue::Number(4.0), NVValue::Number(5.0), NVValue::Number(6.0)]);
+
+        assert!(db.uncap("logs").unwrap());
+        log(&db, 7);
+        assert_eq!(db.count("logs").unwrap(), 4);
+    }
+
+    #[test]
+    fn test_time_series() {
+        use chrono::{DateTime, Duration as Span, Utc};
+
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let now = Utc::now();
+        let reading = |at: DateTime<Utc>, value: f64| {
+            let mut data = HashMap::new();
+            data.insert("at".to_string(), NVValue::String(at.to_rfc3339()));
+            data.insert("value".to_string(), NVValue::Number(value));
+            data
+        };
+        // One stale reading, then one a minute for the last ten minutes
+        db.create("readings".to_string(), reading(now - Span::days(3), 0.0)).unwrap();
+        for minute in 1..=10 {
+            db.create("readings".to_string(), reading(now - Span::minutes(minute), minute as f64))
+                .unwrap();
+        }
+
+        db.set_time_series(
+            "readings",
+            TimeSeriesOptions {
+                retention_secs: Some(86_400),
+                ..TimeSeriesOptions::new("at")
+            },
+        )
+        .unwrap();
+        assert_eq!(db.count("readings").unwrap(), 10);
+
+        let recent = db
+            .find_time_range("readings", now - Span::seconds(330), now)
+            .unwrap();
+        let values: Vec<_> = recent.iter().map(|doc| doc.get("value").cloned().unwrap()).collect();
+        assert_eq!(values, (1..=5).rev().map(|n| NVValue::Number(n as f64)).collect::<Vec<_>>());
+        assert!(db.find_time_range("other", now - Span::days(1), now).is_err());
+
+        let rows = db
+            .downsample(
+                "readings",
+                now - Span::hours(1),
+                now,
+                Interval::Hours(1),
+                &[("n".to_string(), Aggregate::Count), ("total".to_string(), Aggregate::Sum("value".to_string()))],
+            )
+            .unwrap();
+        let total: f64 = rows
+            .iter()
+            .map(|row| match row["total"] {
+                NVValue::Number(total) => total,
+                _ => 0.0,
+            })
+            .sum();
+        assert_eq!(total, 55.0);
+
+        // New events past retention are dropped as they arrive
+        db.create("readings".to_string(), reading(now - Span::days(2), 0.0)).unwrap();
+        assert_eq!(db.count("readings").unwrap(), 10);
+        assert_eq!(db.apply_retention().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_key_value_store() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_string();
+        {
+            let db = NeuralVault::new(DatabaseConfig {
+                path: path.clone(),
+                ..Default::default()
+            })
+            .unwrap();
+            assert_eq!(db.kv_get("theme").unwrap(), None);
+            db.kv_set("theme", NVValue::String("light".to_string())).unwrap();
+            db.kv_set("theme", NVValue::String("dark".to_string())).unwrap();
+            db.kv_set("flags.beta", NVValue::Bool(true)).unwrap();
+            db.kv_set("flags.search", NVValue::Bool(false)).unwrap();
+            assert!(db.kv_set("", NVValue::Null).is_err());
+
+            assert!(db.kv_delete("flags.search").unwrap());
+            assert!(!db.kv_delete("flags.search").unwrap());
+            // Entries stay out of the way of documents
+            assert!(db.collections().unwrap().is_empty());
+        }
+
+        let db = NeuralVault::new(DatabaseConfig {
+            path,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(db.kv_get("theme").unwrap(), Some(NVValue::String("dark".to_string())));
+        assert_eq!(db.kv_keys("flags.").unwrap(), vec!["flags.beta".to_string()]);
+        assert_eq!(db.kv_keys("").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_attachments() {
+        use std::io::Read;
+
+        let dir = tempdir().unwrap();
+        let backups = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+        let id = db.create("notes".to_string(), HashMap::new()).unwrap();
+        let scan: Vec<u8> = (0..3_000_000).map(|i| (i % 251) as u8).collect();
+
+        let info = db.put_attachment(&id, "scan.pdf", scan.as_slice()).unwrap();
+        assert_eq!(info.size, scan.len() as u64);
+        assert!(db.put_attachment("missing", "scan.pdf", scan.as_slice()).is_err());
+        assert!(db.put_attachment(&id, "", &b"x"[..]).is_err());
+
+        let mut read = Vec::new();
+        db.get_attachment(&id, "scan.pdf").unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read, scan);
+        assert!(db.disk_usage().unwrap().attachment_bytes >= scan.len() as u64);
+
+        // Backups carry attachments along
+        let full = db.backup(backups.path().to_str().unwrap()).unwrap();
+        let target = tempdir().unwrap();
+        let restored = NeuralVault::restore(
+            DatabaseConfig {
+                path: target.path().to_str().unwrap().to_string(),
+                ..Default::default()
+            },
+            full.path.to_str().unwrap(),
+            &[],
+            None,
+        )
+        .unwrap();
+        assert_eq!(restored.attachments(&id).unwrap()[0].name, "scan.pdf");
+
+        db.kill_by_id(&id).unwrap();
+        assert!(db.attachments(&id).unwrap().is_empty());
+        assert!(matches!(db.get_attachment(&id, "scan.pdf"), Err(NeuralVaultError::DocumentNotFound(_))));
+
+        // Every delete path takes the attachments along, so a new document
+        // with the same ID starts without any
+        let other = db.create("notes".to_string(), HashMap::new()).unwrap();
+        db.put_attachment(&other, "a.txt", &b"a"[..]).unwrap();
+        db.bulk_write(vec![WriteOp::Delete { id: other.clone() }]).unwrap();
+        db.create_with_id(other.clone(), "notes".to_string(), HashMap::new()).unwrap();
+        assert!(db.attachments(&other).unwrap().is_empty());
+
+        // Compaction reclaims the chunks of removed attachments
+        db.compact().unwrap();
+        assert_eq!(db.disk_usage().unwrap().attachment_bytes, 0);
+    }
+
+    #[test]
+    fn test_timestamp_fields_queryable() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+        let first = db.create("notes".to_string(), HashMap::new()).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let second = db.create("notes".to_string(), HashMap::new()).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let since = chrono::Utc::now().timestamp_millis();
+        db.update_by_id(
+            &first,
+            vec![UpdateOperation::set("title".to_string(), NVValue::String("edited".to_string()))],
+        )
+        .unwrap();
+
+        // Recently modified, newest first
+        let mut query = NVQuery::new("notes".to_string());
+        query.order_by = Some(UPDATED_AT_FIELD.to_string());
+        query.order_desc = true;
+        let ids: Vec<String> = db.find(query.clone()).unwrap().into_iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec![first.clone(), second.clone()]);
+
+        query.add_condition(
+            UPDATED_AT_FIELD.to_string(),
+            QueryOperator::GreaterThanOrEqual,
+            NVValue::Number(since as f64),
+            None,
+        );
+        let ids: Vec<String> = db.find(query).unwrap().into_iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec![first.clone()]);
+
+        // Creation order is unaffected by the update
+        let mut query = NVQuery::new("notes".to_string());
+        query.order_by = Some(CREATED_AT_FIELD.to_string());
+        let ids: Vec<String> = db.find(query).unwrap().into_iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec![first, second]);
+    }
+
+    #[test]
+    fn test_metadata_fields() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+        let mut data = HashMap::new();
+        data.insert("title".to_string(), NVValue::String("Draft".to_string()));
+        data.insert("body".to_string(), NVValue::String("...".to_string()));
+        let id = db.create_with_id("note-1".to_string(), "notes".to_string(), data).unwrap();
+        db.create_with_id("note-2".to_string(), "notes".to_string(), HashMap::new()).unwrap();
+
+        let mut query = NVQuery::new("notes".to_string());
+        query.add_condition(
+            ID_FIELD.to_string(),
+            QueryOperator::In,
+            NVValue::Array(vec![NVValue::String(id.clone()), NVValue::String("other".to_string())]),
+            None,
+        );
+        query.projection = Some(vec!["title".to_string(), ID_FIELD.to_string(), COLLECTION_FIELD.to_string()]);
+        let documents = db.find(query).unwrap();
+        assert_eq!(documents.len(), 1);
+        let fields = &documents[0].data;
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields.get(ID_FIELD), Some(&NVValue::String(id.clone())));
+        assert_eq!(fields.get(COLLECTION_FIELD), Some(&NVValue::String("notes".to_string())));
+
+        // Equality on pseudo-fields isn't ruled out by the bloom filters
+        for (field, value) in [
+            (ID_FIELD, NVValue::String(id.clone())),
+            (COLLECTION_FIELD, NVValue::String("notes".to_string())),
+            (DELETED_FIELD, NVValue::Bool(false)),
+        ] {
+            let mut query = NVQuery::new("notes".to_string());
+            query.add_condition(field.to_string(), QueryOperator::Equals, value, None);
+            let expected = if field == ID_FIELD { 1 } else { 2 };
+            assert_eq!(db.find(query).unwrap().len(), expected, "{} == ...", field);
+        }
+
+        // Metadata names are reserved in document data
+        let mut data = HashMap::new();
+        data.insert(ID_FIELD.to_string(), NVValue::String("spoofed".to_string()));
+        assert!(matches!(
+            db.create("notes".to_string(), data),
+            Err(NeuralVaultError::ValidationError(_))
+        ));
+        assert!(db
+            .update_by_id(&id, vec![UpdateOperation::set(DELETED_FIELD.to_string(), NVValue::Bool(true))])
+            .is_err());
+
+        // A version matches until the document is written again
+        let version = db.find_by_id(&id).unwrap().version;
+        let by_version = |version: u64| {
+            let mut query = NVQuery::new("notes".to_string());
+            query.add_condition(VERSION_FIELD.to_string(), QueryOperator::Equals, NVValue::Number(version as f64), None);
+            query.projection = Some(vec![VERSION_FIELD.to_string()]);
+            db.find(query).unwrap()
+        };
+        let documents = by_version(version);
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].data.get(VERSION_FIELD), Some(&NVValue::Number(version as f64)));
+        db.update_by_id(&id, vec![UpdateOperation::set("title".to_string(), NVValue::String("Final".to_string()))])
+            .unwrap();
+        assert!(by_version(version).is_empty());
+        assert!(db.find_by_id(&id).unwrap().version > version);
+
+        assert!(db
+            .create_index(IndexDefinition {
+                name: "by_version".to_string(),
+                collection: "notes".to_string(),
+                fields: vec![VERSION_FIELD.to_string()],
+                filter: Vec::new(),
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_cross_collection_query() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+        for (collection, title, rank) in [
+            ("notes", "n1", 4.0),
+            ("tasks", "t1", 1.0),
+            ("events", "e1", 3.0),
+            ("notes", "n2", 2.0),
+            ("archive", "a1", 5.0),
+        ] {
+            let mut data = HashMap::new();
+            data.insert("title".to_string(), NVValue::String(title.to_string()));
+            data.insert("rank".to_string(), NVValue::Number(rank));
+            db.create(collection.to_string(), data).unwrap();
+        }
+        let titles = |documents: &[NVDocument]| -> Vec<String> {
+            documents
+                .iter()
+                .map(|d| match d.get("title") {
+                    Some(NVValue::String(title)) => title.clone(),
+                    _ => String::new(),
+                })
+                .collect()
+        };
+
+        // Merged, sorted and paged across collections
+        let mut query = NVQuery::across(&["notes", "tasks", "events"]);
+        query.order_by = Some("rank".to_string());
+        query.order_desc = true;
+        query.skip = Some(1);
+        query.limit = Some(2);
+        query.projection = Some(vec!["title".to_string(), COLLECTION_FIELD.to_string()]);
+        let (documents, stats) = db.find_with_stats(query, &CancellationToken::new()).unwrap();
+        assert_eq!(titles(&documents), vec!["e1", "n2"]);
+        assert_eq!(documents[0].collection, "events");
+        assert_eq!(documents[1].get(COLLECTION_FIELD), Some(&NVValue::String("notes".to_string())));
+        assert!(matches!(stats.plan, Some(QueryPlan::Union { ref parts }) if parts.len() == 3));
+
+        // Patterns expand to the existing collections
+        let mut query = NVQuery::new("*".to_string());
+        query.add_condition("rank".to_string(), QueryOperator::GreaterThan, NVValue::Number(3.0), None);
+        query.order_by = Some("rank".to_string());
+        assert_eq!(titles(&db.find(query).unwrap()), vec!["n1", "a1"]);
+
+        // Tenant handles scope every collection
+        let acme = db.scoped("acme").unwrap();
+        acme.create("notes", HashMap::new()).unwrap();
+        acme.create("tasks", HashMap::new()).unwrap();
+        let documents = acme.find(NVQuery::across(&["notes", "tasks"])).unwrap();
+        let mut collections: Vec<&str> = documents.iter().map(|d| d.collection.as_str()).collect();
+        collections.sort();
+        assert_eq!(collections, vec!["notes", "tasks"]);
+        assert_eq!(acme.find(NVQuery::new("*".to_string())).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_migrations_run_once() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let register = |db: &NeuralVault| {
+            // Rename `name` to `title`
+            db.register_migration(1, |db, tx| {
+                let query = NVQuery::new("notes".to_string());
+                for document in db.find(query)? {
+                    if let Some(name) = document.get("name") {
+                        tx.update(
+                            &document.id,
+                            vec![
+                                UpdateOperation::set("title".to_string(), name.clone()),
+                                UpdateOperation::unset("name".to_string()),
+                            ],
+                        );
+                    }
+                }
+                Ok(())
+            })
+            .unwrap();
+            db.register_migration(2, |_, tx| {
+                tx.insert("notes", HashMap::new());
+                Ok(())
+            })
+            .unwrap();
+        };
+
+        {
+            let db = NeuralVault::new(config.clone()).unwrap();
+            let mut data = HashMap::new();
+            data.insert("name".to_string(), NVValue::String("Groceries".to_string()));
+            let id = db.create("notes".to_string(), data).unwrap();
+
+            register(&db);
+            assert!(db.register_migration(2, |_, _| Ok(())).is_err());
+            assert_eq!(db.migrate().unwrap(), vec![1, 2]);
+            assert_eq!(db.schema_version().unwrap(), 2);
+            assert_eq!(db.find_by_id(&id).unwrap().get("title"), Some(&NVValue::String("Groceries".to_string())));
+            assert!(db.migrate().unwrap().is_empty());
+        }
+
+        // Reopening runs only the migrations added since
+        let db = NeuralVault::new(config).unwrap();
+        register(&db);
+        // A migration older than the applied ones would never run
+        let late = NeuralVault::new(DatabaseConfig {
+            path: dir.path().join("late").to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        late.register_migration(2, |_, _| Ok(())).unwrap();
+        late.migrate().unwrap();
+        assert!(late.register_migration(1, |_, _| Ok(())).is_err());
+
+        // A migration's writes and its version are committed together, so
+        // a failure leaves neither behind
+        db.register_migration(3, |_, tx| {
+            tx.insert("notes", HashMap::new());
+            Err(NeuralVaultError::ValidationError("bad data".to_string()))
+        })
+        .unwrap();
+        assert!(matches!(db.migrate(), Err(NeuralVaultError::MigrationFailed(3, _))));
+        assert_eq!(db.schema_version().unwrap(), 2);
+        assert_eq!(db.count("notes").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_schema_defaults() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let schema = CollectionSchema::new()
+            .with_default("status", FieldDefault::Value(NVValue::String("open".to_string())))
+            .with_default("number", FieldDefault::Sequence)
+            .with_default("opened", FieldDefault::Now);
+        {
+            let db = NeuralVault::new(config.clone()).unwrap();
+            db.set_schema("tickets", schema.clone()).unwrap();
+
+            let first = db.create("tickets".to_string(), HashMap::new()).unwrap();
+            let document = db.find_by_id(&first).unwrap();
+            assert_eq!(document.get("status"), Some(&NVValue::String("open".to_string())));
+            assert_eq!(document.get("number"), Some(&NVValue::Number(1.0)));
+            assert!(matches!(document.get("opened"), Some(NVValue::String(_))));
+
+            // Given values win over defaults
+            let mut data = HashMap::new();
+            data.insert("status".to_string(), NVValue::String("closed".to_string()));
+            let second = db.create("tickets".to_string(), data).unwrap();
+            let document = db.find_by_id(&second).unwrap();
+            assert_eq!(document.get("status"), Some(&NVValue::String("closed".to_string())));
+            assert_eq!(document.get("number"), Some(&NVValue::Number(2.0)));
+        }
+
+        // Schemas and counters survive reopening, and apply to bulk inserts
+        let db = NeuralVault::new(config).unwrap();
+        assert_eq!(db.schema("tickets"), Some(schema));
+        let ids: Vec<String> = db
+            .bulk_write(vec![WriteOp::Insert {
+                id: None,
+                collection: "tickets".to_string(),
+                data: HashMap::new(),
+            }])
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(db.find_by_id(&ids[0]).unwrap().get("number"), Some(&NVValue::Number(3.0)));
+
+        assert!(db.remove_schema("tickets").unwrap());
+        let id = db.create("tickets".to_string(), HashMap::new()).unwrap();
+        assert!(db.find_by_id(&id).unwrap().data.is_empty());
+    }
+
+    #[test]
+    fn test_sensitive_fields_redacted() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+        db.set_schema("users", CollectionSchema::new().with_sensitive("email")).unwrap();
+        let mut data = HashMap::new();
+        data.insert("name".to_string(), NVValue::String("Ada".to_string()));
+        data.insert("email".to_string(), NVValue::String("ada@example.com".to_string()));
+        let id = db.create("users".to_string(), data).unwrap();
+
+        // Sensitive fields can be filtered on but aren't returned
+        let mut query = NVQuery::new("users".to_string());
+        query.add_condition(
+            "email".to_string(),
+            QueryOperator::EndsWith,
+            NVValue::String("@example.com".to_string()),
+            None,
+        );
+        let documents = db.find(query.clone()).unwrap();
+        assert_eq!(documents.len(), 1);
+        assert!(documents[0].get("email").is_none());
+        assert!(documents[0].get("name").is_some());
+        assert!(db.find_by_id(&id).unwrap().get("email").is_none());
+
+        query.include_sensitive = true;
+        assert!(db.find(query).unwrap()[0].get("email").is_some());
+        assert!(db.find_by_id_unredacted(&id).unwrap().get("email").is_some());
+
+        // Updates keep the fields they don't touch
+        db.update_by_id(&id, vec![UpdateOperation::set("name".to_string(), NVValue::String("Ada L".to_string()))])
+            .unwrap();
+        assert!(db.find_by_id_unredacted(&id).unwrap().get("email").is_some());
+    }
+
+    #[test]
+    fn test_sensitive_fields_redacted_from_snapshots_and_populate() {
+        let dir = tempdir().unwrap();
+        let config = DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let db = NeuralVault::new(config).unwrap();
+        db.set_schema("people", CollectionSchema::new().with_sensitive("ssn")).unwrap();
+        let mut data = HashMap::new();
+        data.insert("name".to_string(), NVValue::String("Ada".to_string()));
+        data.insert("ssn".to_string(), NVValue::String("123-45-6789".to_string()));
+        let id = db.create("people".to_string(), data).unwrap();
+        let mut data = HashMap::new();
+        data.insert("who".to_string(), NVValue::String(id.clone()));
+        db.create("visits".to_string(), data).unwrap();
+
+        {
+            let snapshot = db.snapshot().unwrap();
+            assert!(snapshot.find_by_id(&id).unwrap().get("ssn").is_none());
+            let mut query = NVQuery::new("people".to_string());
+            assert!(snapshot.find(query.clone()).unwrap()[0].get("ssn").is_none());
+            query.include_sensitive = true;
+            assert!(snapshot.find(query).unwrap()[0].get("ssn").is_some());
+        }
+        let sampled = db.sample("people", 5).unwrap();
+        assert_eq!(sampled.len(), 1);
+        assert!(sampled[0].get("ssn").is_none());
+
+        let populated = |include_sensitive: bool| {
+            let mut query = NVQuery::new("visits".to_string());
+            query.populate = vec!["who:people".to_string()];
+            query.include_sensitive = include_sensitive;
+            match db.find(query).unwrap()[0].get("who") {
+                Some(NVValue::Object(person)) => person.clone(),
+                other => panic!("who not populated: {:?}", other),
+            }
+        };
+        let person = populated(false);
+        assert!(person.get("ssn").is_none());
+        assert!(person.get("name").is_some());
+        assert!(populated(true).get("ssn").is_some());
+    }
+
+
+    #[test]
+    fn test_imported_mongo_dates_compare_by_time() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let export = r#"
+{"_id":1,"at":{"$date":"2024-01-15T00:00:00Z"}}
+{"_id":2,"at":{"$date":{"$numberLong":"1717200000000"}}}
+"#;
+        assert!(db.import_mongo("events", export).unwrap().iter().all(Result::is_ok));
+
+        // After 2024-03-01
+        let mut query = NVQuery::new("events".to_string());
+        query.add_condition("at".to_string(), QueryOperator::GreaterThan, NVValue::Number(1709251200000.0), None);
+        let found = db.find(query).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, "2");
+    }
+
+    #[test]
+    fn test_index_advisor_counts_the_queried_collection() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        for i in 0..20 {
+            let mut data = HashMap::new();
+            data.insert("email".to_string(), NVValue::String(format!("u{i}@example.com")));
+            data.insert("active".to_string(), NVValue::Bool(true));
+            db.create("users".to_string(), data).unwrap();
+        }
+        for _ in 0..50 {
+            db.create("events".to_string(), HashMap::new()).unwrap();
+        }
+
+        let mut query = NVQuery::new("users".to_string());
+        query.add_condition("email".to_string(), QueryOperator::Equals, NVValue::String("u3@example.com".to_string()), None);
+        query.add_condition("active".to_string(), QueryOperator::Equals, NVValue::Bool(true), Some(LogicalOperator::And));
+        assert_eq!(db.find(query).unwrap().len(), 1);
+
+        // Only the 20 users are read, and `active` narrows nothing
+        let suggestions = db.suggest_indexes();
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].definition.fields, vec!["email".to_string()]);
+        assert_eq!(suggestions[0].estimated_benefit, 19);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_import_sqlite_reports_failed_rows() {
+        let temp_dir = tempdir().unwrap();
+        let source = temp_dir.path().join("source.db");
+        let connection = rusqlite::Connection::open(&source).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);
+                 INSERT INTO users VALUES (1, 'Ada'), (2, 'Grace'), (3, 'Edsger');
+                 CREATE TABLE teams (id INTEGER PRIMARY KEY, name TEXT);
+                 INSERT INTO teams VALUES (1, 'Compilers'), (2, 'Kernels');",
+            )
+            .unwrap();
+        drop(connection);
+
+        let db = NeuralVault::new(DatabaseConfig {
+            path: temp_dir.path().join("db").to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        db.bulk_write(vec![WriteOp::Insert {
+            id: Some("people:2".to_string()),
+            collection: "people".to_string(),
+            data: HashMap::new(),
+        }])
+        .unwrap();
+
+        let options = crate::import::SqliteImportOptions {
+            collections: HashMap::from([("users".to_string(), "people".to_string())]),
+            ..Default::default()
+        };
+        let tables = db.import_sqlite(&source, options).unwrap();
+        assert_eq!(tables.len(), 2);
+        assert_eq!((tables[1].table.as_str(), tables[1].collection.as_str()), ("users", "people"));
+        assert_eq!(tables[1].imported, 2);
+        assert_eq!(tables[1].errors.len(), 1);
+
+        // Both tables number their rows from 1 without colliding
+        assert_eq!((tables[0].table.as_str(), tables[0].imported), ("teams", 2));
+        assert_eq!(db.find_by_id("teams:1").unwrap().collection, "teams");
+
+        let ada = db.find_by_id("people:1").unwrap();
+        assert_eq!(ada.collection, "people");
+        assert_eq!(ada.get("name"), Some(&NVValue::String("Ada".to_string())));
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derived_entities() {
+        #[derive(Debug, PartialEq, NVEntity)]
+        #[nv(collection = "users")]
+        struct User {
+            #[nv(id)]
+            id: Option<String>,
+            name: String,
+            age: u32,
+            #[nv(rename = "e-mail")]
+            email: Option<String>,
+            tags: Vec<String>,
+            #[nv(skip)]
+            cached: bool,
+        }
+
+        let temp_dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: temp_dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let users = db.entities::<User>();
+
+        let mut ada = User {
+            id: None,
+            name: "Ada".to_string(),
+            age: 36,
+            email: Some("ada@example.com".to_string()),
+            tags: vec!["math".to_string()],
+            cached: true,
+        };
+        let id = users.insert(&ada).unwrap();
+        let document = db.find_by_id(&id).unwrap();
+        assert_eq!(document.collection, "users");
+        assert_eq!(document.get("e-mail"), Some(&NVValue::String("ada@example.com".to_string())));
+        assert!(document.get("cached").is_none());
+
+        ada.id = Some(id.clone());
+        ada.cached = false;
+        assert_eq!(users.get(&id).unwrap(), ada);
+
+        ada.age = 37;
+        users.save(&ada).unwrap();
+        let mut data = HashMap::new();
+        data.insert("name".to_string(), NVValue::String("Grace".to_string()));
+        data.insert("age".to_string(), NVValue::Number(45.0));
+        data.insert("tags".to_string(), NVValue::Array(vec![]));
+        db.create("users".to_string(), data).unwrap();
+
+        let mut query = users.query();
+        query.add_condition("age".to_string(), QueryOperator::GreaterThan, NVValue::Number(40.0), None);
+        let older = users.find(query).unwrap();
+        assert_eq!(older.len(), 1);
+        assert_eq!(older[0].name, "Grace");
+        assert_eq!(older[0].email, None);
+        assert_eq!(users.get(&id).unwrap().age, 37);
+
+        users.delete(&id).unwrap();
+        assert_eq!(users.all().unwrap().len(), 1);
+    }
+
+
+    #[test]
+    fn test_typed_create_and_find() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Task {
+            #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+            id: Option<String>,
+            title: String,
+            priority: u8,
+            #[serde(default)]
+            labels: Vec<String>,
+        }
+
+        let temp_dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: temp_dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut task = Task {
+            id: None,
+            title: "Write docs".to_string(),
+            priority: 2,
+            labels: vec!["docs".to_string()],
+        };
+        let id = db.create_typed("tasks", &task).unwrap();
+        let document = db.find_by_id(&id).unwrap();
+        assert_eq!(document.get("priority"), Some(&NVValue::Number(2.0)));
+        assert!(document.get("_id").is_none());
+
+        task.id = Some("natural-key".to_string());
+        assert_eq!(db.create_typed("tasks", &task).unwrap(), "natural-key");
+        assert!(db.create_typed("tasks", &task).is_err());
+        assert!(db.create_typed("tasks", &42).is_err());
+
+        let tasks: Vec<Task> = db.find_typed(NVQuery::new("tasks".to_string())).unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert!(tasks.iter().any(|found| found.id.as_deref() == Some(id.as_str())));
+        assert!(tasks.contains(&task));
+
+        db.create("tasks".to_string(), HashMap::from([("title".to_string(), NVValue::Bool(true))])).unwrap();
+        assert!(db.find_typed::<Task>(NVQuery::new("tasks".to_string())).is_err());
+    }
+
+
+    #[test]
+    fn test_value_builders_and_accessors() {
+        let temp_dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: temp_dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let city = String::from("London");
+        let data = nv!({
+            "name": "Ada",
+            "age": 36,
+            "address": { "city": city, "zip": null },
+            "tags": ["math", "poetry"],
+            "score": NVValue::from(9.5),
+        })
+        .into_object()
+        .unwrap();
+        let id = db.create("people".to_string(), data).unwrap();
+        let person = db.find_by_id(&id).unwrap();
+
+        assert_eq!(person.get_str("name"), Some("Ada"));
+        assert_eq!(person.get_f64("age"), Some(36.0));
+        assert_eq!(person.get("age").and_then(NVValue::as_i64), Some(36));
+        assert_eq!(person.get_path("address.city").and_then(NVValue::as_str), Some("London"));
+        assert!(person.get_path("address.zip").is_some_and(NVValue::is_null));
+        assert_eq!(person.get_path("tags.1"), Some(&NVValue::from("poetry")));
+        assert_eq!(person.get_path("tags.2"), None);
+        assert_eq!(person.get_path("name.first"), None);
+        assert_eq!(person.get_f64("score"), Some(9.5));
+
+        assert_eq!(NVValue::from(vec![1u8, 2]), nv!([1, 2]));
+        assert_eq!(NVValue::from(None::<&str>), NVValue::Null);
+        assert_eq!(NVValue::from(HashMap::from([("ok", true)])), nv!({ "ok": true }));
+        assert_eq!(NVValue::from(1.5).as_i64(), None);
+    }
+
+
+    #[test]
+    fn test_object_order_and_deterministic_serialization() {
+        let temp_dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: temp_dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let data = nv!({ "settings": { "zoom": 2, "alpha": true, "mode": "dark" }, "b": 1, "a": 2 })
+            .into_object()
+            .unwrap();
+        let id = db.create("prefs".to_string(), data).unwrap();
+        let document = db.find_by_id(&id).unwrap();
+
+        let settings = document.get("settings").and_then(NVValue::as_object).unwrap();
+        assert_eq!(settings.keys().collect::<Vec<_>>(), ["zoom", "alpha", "mode"]);
+        let json = serde_json::to_string(&document).unwrap();
+        assert!(json.contains(r#""data":{"a":2.0,"b":1.0,"settings":{"zoom":2.0,"alpha":true,"mode":"dark"}}"#));
+
+        let mut reordered = document.clone();
+        reordered.data = HashMap::with_capacity(64);
+        reordered.data.extend(document.data.clone());
+        assert_eq!(reordered, document);
+        assert_eq!(serde_json::to_string(&reordered).unwrap(), json);
+    }
+
+
+    #[test]
+    fn test_validate_query() {
+        let temp_dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: temp_dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        db.set_schema(
+            "tickets",
+            CollectionSchema::new()
+                .with_default("number", FieldDefault::Sequence)
+                .with_default("status", FieldDefault::Value(NVValue::String("open".to_string())))
+                .with_sensitive("secret"),
+        )
+        .unwrap();
+
+        let clean = r#"{"conditions": [{"field": "number", "operator": ">", "value": 3},
+            {"field": "status", "operator": "in", "value": ["open", "closed"]},
+            {"field": "title", "operator": "contains", "value": "bug"}]}"#;
+        assert!(db.validate_query("tickets", clean).unwrap().is_empty());
+
+        let query = r#"{"conditions": [{"field": "age", "operator": ">", "value": "30"},
+            {"field": "status", "operator": "in", "value": "open"},
+            {"field": "number", "operator": "==", "value": "7"},
+            {"field": "_deleted", "operator": "==", "value": 1}],
+            "projection": ["title", "secret"]}"#;
+        let warnings = db.validate_query("tickets", query).unwrap();
+        let located: Vec<(Option<usize>, Option<&str>)> =
+            warnings.iter().map(|w| (w.condition, w.field.as_deref())).collect();
+        assert_eq!(
+            located,
+            vec![
+                (Some(0), Some("age")),
+                (Some(1), Some("status")),
+                (Some(2), Some("number")),
+                (Some(3), Some("_deleted")),
+                (None, Some("secret")),
+            ]
+        );
+        assert_eq!(warnings[0].message, "'>' only matches numbers, but the value is a string; it never matches");
+        assert_eq!(warnings[2].message, "'number' holds a number, but is compared with a string");
+
+        let subquery = r#"{"conditions": [{"field": "owner", "operator": "in",
+            "subquery": {"collection": "users", "query": {"conditions": [{"field": "name", "operator": "starts_with", "value": 1}]}}}]}"#;
+        let warnings = db.validate_query("tickets", subquery).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].condition, Some(0));
+        assert!(warnings[0].message.starts_with("In subquery: Condition 0: 'starts_with' only matches strings"));
+
+        assert!(matches!(
+            db.validate_query("tickets", r#"{"conditions": [{"field": "a"}]}"#),
+            Err(NeuralVaultError::InvalidQuery(_))
+        ));
+    }
+
 }
@@ -0,0 +1,72 @@
+use crate::database::NeuralVault;
+use crate::error::{NeuralVaultError, NVResult};
+use crate::transaction::Transaction;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// A data transformation run once per database by `NeuralVault::migrate`
+///
+/// It reads through the database and writes through the transaction,
+/// which is committed together with the record that it ran.
+pub type Migration = Arc<dyn Fn(&NeuralVault, &mut Transaction) -> NVResult<()> + Send + Sync>;
+
+/// Registered migrations, by version
+#[derive(Default)]
+pub struct MigrationRegistry {
+    migrations: BTreeMap<u32, Migration>,
+}
+
+impl MigrationRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a migration; versions must be positive and unique
+    pub fn register(&mut self, version: u32, migration: Migration) -> NVResult<()> {
+        if version == 0 {
+            return Err(NeuralVaultError::ValidationError(
+                "Migration versions start at 1".to_string(),
+            ));
+        }
+        if self.migrations.contains_key(&version) {
+            return Err(NeuralVaultError::ValidationError(format!(
+                "Migration {} is already registered",
+                version
+            )));
+        }
+        self.migrations.insert(version, migration);
+        Ok(())
+    }
+
+    /// Migrations newer than `applied`, oldest first
+    pub fn pending(&self, applied: u32) -> Vec<(u32, Migration)> {
+        self.migrations
+            .range((Bound::Excluded(applied), Bound::Unbounded))
+            .map(|(version, migration)| (*version, migration.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noop() -> Migration {
+        Arc::new(|_, _| Ok(()))
+    }
+
+    #[test]
+    fn test_pending_in_version_order() {
+        let mut registry = MigrationRegistry::new();
+        for version in [3, 1, 2] {
+            registry.register(version, noop()).unwrap();
+        }
+        assert!(registry.register(2, noop()).is_err());
+        assert!(registry.register(0, noop()).is_err());
+
+        let pending: Vec<u32> = registry.pending(1).into_iter().map(|(version, _)| version).collect();
+        assert_eq!(pending, vec![2, 3]);
+        assert!(registry.pending(3).is_empty());
+    }
+}
@@ -1,6 +1,71 @@
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use chrono::{DateTime, Utc};
+use crate::expression::Expression;
+use crate::object::NVObject;
+
+/// Pseudo-field holding a document's ID in queries
+pub const ID_FIELD: &str = "_id";
+
+/// Pseudo-field holding a document's collection in queries
+pub const COLLECTION_FIELD: &str = "_collection";
+
+/// Pseudo-field holding a document's soft delete flag in queries
+pub const DELETED_FIELD: &str = "_deleted";
+
+/// Pseudo-field holding a document's creation time in queries, as
+/// milliseconds since the epoch
+pub const CREATED_AT_FIELD: &str = "_created_at";
+
+/// Pseudo-field holding a document's last update time in queries, as
+/// milliseconds since the epoch
+pub const UPDATED_AT_FIELD: &str = "_updated_at";
+
+/// Pseudo-field holding the stored version of a document in queries; see
+/// `NVDocument::version`
+pub const VERSION_FIELD: &str = "_version";
+
+/// Pseudo-fields exposing document metadata to conditions, sorting and
+/// projections; data fields can't use these names
+pub const METADATA_FIELDS: [&str; 6] =
+    [ID_FIELD, COLLECTION_FIELD, DELETED_FIELD, CREATED_AT_FIELD, UPDATED_AT_FIELD, VERSION_FIELD];
+
+/// Whether `field` names a metadata pseudo-field
+pub fn is_metadata_field(field: &str) -> bool {
+    METADATA_FIELDS.contains(&field)
+}
+
+/// Whether `field` names a timestamp pseudo-field
+pub fn is_timestamp_field(field: &str) -> bool {
+    field == CREATED_AT_FIELD || field == UPDATED_AT_FIELD
+}
+
+/// The metadata of a document, as seen through its pseudo-fields
+#[derive(Debug, Clone, Copy)]
+pub struct DocumentMeta<'a> {
+    pub id: &'a str,
+    pub collection: &'a str,
+    pub deleted: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub version: u64,
+}
+
+impl DocumentMeta<'_> {
+    /// Value of a metadata pseudo-field, or `None` for any other field
+    pub fn field(&self, field: &str) -> Option<NVValue> {
+        match field {
+            ID_FIELD => Some(NVValue::String(self.id.to_string())),
+            COLLECTION_FIELD => Some(NVValue::String(self.collection.to_string())),
+            DELETED_FIELD => Some(NVValue::Bool(self.deleted)),
+            CREATED_AT_FIELD => Some(NVValue::Number(self.created_at.timestamp_millis() as f64)),
+            UPDATED_AT_FIELD => Some(NVValue::Number(self.updated_at.timestamp_millis() as f64)),
+            VERSION_FIELD => Some(NVValue::Number(self.version as f64)),
+            _ => None,
+        }
+    }
+}
 
 /// Core data types supported by NeuralVault
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -11,7 +76,231 @@ pub enum NVValue {
     Number(f64),
     String(String),
     Array(Vec<NVValue>),
-    Object(HashMap<String, NVValue>),
+    Object(NVObject),
+}
+
+impl NVValue {
+    /// Recursively merge `patch` into this value
+    ///
+    /// Objects are merged key by key; any other combination replaces this
+    /// value with the patch.
+    pub fn deep_merge(&mut self, patch: NVValue) {
+        match (self, patch) {
+            (NVValue::Object(target), NVValue::Object(patch)) => {
+                for (key, value) in patch {
+                    match target.get_mut(&key) {
+                        Some(existing) => existing.deep_merge(value),
+                        None => {
+                            target.insert(key, value);
+                        }
+                    }
+                }
+            }
+            (target, patch) => *target = patch,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, NVValue::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            NVValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            NVValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The number, if it's whole and fits an `i64`
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            NVValue::Number(n) if n.fract() == 0.0 && *n >= i64::MIN as f64 && *n < i64::MAX as f64 => Some(*n as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            NVValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<NVValue>> {
+        match self {
+            NVValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&NVObject> {
+        match self {
+            NVValue::Object(entries) => Some(entries),
+            _ => None,
+        }
+    }
+
+    /// The entries of an object, e.g. to use an `nv!` object as document data
+    pub fn into_object(self) -> Option<HashMap<String, NVValue>> {
+        match self {
+            NVValue::Object(entries) => Some(entries.into_iter().collect()),
+            _ => None,
+        }
+    }
+
+    /// An object's entry or an array's element by index
+    pub fn get(&self, key: &str) -> Option<&NVValue> {
+        match self {
+            NVValue::Object(entries) => entries.get(key),
+            NVValue::Array(items) => items.get(key.parse::<usize>().ok()?),
+            _ => None,
+        }
+    }
+
+    /// A nested value by dotted path, such as `"address.city"` or `"tags.0"`
+    pub fn get_path(&self, path: &str) -> Option<&NVValue> {
+        path.split('.').try_fold(self, |value, key| value.get(key))
+    }
+}
+
+/// Build an `NVValue` with `serde_json::json!` syntax
+///
+/// ```
+/// use neural_vault_core::{nv, NVValue};
+///
+/// let name = "Ada";
+/// let user = nv!({ "name": name, "age": 36, "tags": ["math"], "manager": null });
+/// assert_eq!(user.get_path("tags.0").and_then(NVValue::as_str), Some("math"));
+/// assert_eq!(nv!{ "a": 1, "b": [] }, nv!({ "a": 1, "b": [] }));
+/// ```
+///
+/// Objects keep their keys in the order written. Keys are string literals
+/// or parenthesized expressions; values are anything `Into<NVValue>`.
+#[macro_export]
+macro_rules! nv {
+    () => {
+        $crate::NVValue::Object($crate::NVObject::new())
+    };
+    (null) => {
+        $crate::NVValue::Null
+    };
+    ([ $($items:tt)* ]) => {
+        $crate::NVValue::Array($crate::nv!(@array [] $($items)*))
+    };
+    ({ $($entries:tt)* }) => {
+        $crate::NVValue::Object({
+            let mut object = $crate::NVObject::new();
+            $crate::nv!(@object object ($($entries)*));
+            object
+        })
+    };
+
+    (@array [$($done:expr,)*]) => {
+        vec![$($done,)*]
+    };
+    (@array [$($done:expr,)*] null $(, $($rest:tt)*)?) => {
+        $crate::nv!(@array [$($done,)* $crate::nv!(null),] $($($rest)*)?)
+    };
+    (@array [$($done:expr,)*] [$($items:tt)*] $(, $($rest:tt)*)?) => {
+        $crate::nv!(@array [$($done,)* $crate::nv!([$($items)*]),] $($($rest)*)?)
+    };
+    (@array [$($done:expr,)*] {$($entries:tt)*} $(, $($rest:tt)*)?) => {
+        $crate::nv!(@array [$($done,)* $crate::nv!({$($entries)*}),] $($($rest)*)?)
+    };
+    (@array [$($done:expr,)*] $next:expr $(, $($rest:tt)*)?) => {
+        $crate::nv!(@array [$($done,)* $crate::NVValue::from($next),] $($($rest)*)?)
+    };
+
+    (@object $object:ident ()) => {};
+    (@object $object:ident ($key:tt : null $(, $($rest:tt)*)?)) => {
+        $object.insert(::std::string::String::from($key), $crate::nv!(null));
+        $crate::nv!(@object $object ($($($rest)*)?));
+    };
+    (@object $object:ident ($key:tt : [$($items:tt)*] $(, $($rest:tt)*)?)) => {
+        $object.insert(::std::string::String::from($key), $crate::nv!([$($items)*]));
+        $crate::nv!(@object $object ($($($rest)*)?));
+    };
+    (@object $object:ident ($key:tt : {$($entries:tt)*} $(, $($rest:tt)*)?)) => {
+        $object.insert(::std::string::String::from($key), $crate::nv!({$($entries)*}));
+        $crate::nv!(@object $object ($($($rest)*)?));
+    };
+    (@object $object:ident ($key:tt : $value:expr $(, $($rest:tt)*)?)) => {
+        $object.insert(::std::string::String::from($key), $crate::NVValue::from($value));
+        $crate::nv!(@object $object ($($($rest)*)?));
+    };
+
+    ($key:literal : $($rest:tt)*) => {
+        $crate::nv!({ $key : $($rest)* })
+    };
+    ($other:expr) => {
+        $crate::NVValue::from($other)
+    };
+}
+
+impl From<bool> for NVValue {
+    fn from(value: bool) -> Self {
+        NVValue::Bool(value)
+    }
+}
+
+impl From<String> for NVValue {
+    fn from(value: String) -> Self {
+        NVValue::String(value)
+    }
+}
+
+impl From<&str> for NVValue {
+    fn from(value: &str) -> Self {
+        NVValue::String(value.to_string())
+    }
+}
+
+/// Numbers are all stored as `f64`
+macro_rules! number_from {
+    ($($ty:ty),*) => {$(
+        impl From<$ty> for NVValue {
+            fn from(value: $ty) -> Self {
+                NVValue::Number(value as f64)
+            }
+        }
+    )*};
+}
+
+number_from!(f32, f64, i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// `None` becomes null
+impl<T: Into<NVValue>> From<Option<T>> for NVValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(NVValue::Null, Into::into)
+    }
+}
+
+impl<T: Into<NVValue>> From<Vec<T>> for NVValue {
+    fn from(items: Vec<T>) -> Self {
+        NVValue::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Keys are sorted, since a `HashMap` has no order to keep
+impl<K: Into<String>, V: Into<NVValue>> From<HashMap<K, V>> for NVValue {
+    fn from(entries: HashMap<K, V>) -> Self {
+        let entries: HashMap<String, NVValue> =
+            entries.into_iter().map(|(key, value)| (key.into(), value.into())).collect();
+        NVValue::Object(NVObject::from(entries))
+    }
+}
+
+impl From<NVObject> for NVValue {
+    fn from(object: NVObject) -> Self {
+        NVValue::Object(object)
+    }
 }
 
 impl From<serde_json::Value> for NVValue {
@@ -55,13 +344,14 @@ impl From<NVValue> for serde_json::Value {
 }
 
 /// A document record in the database
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NVDocument {
     /// Unique identifier
     pub id: String,
     /// Collection name
     pub collection: String,
-    /// Document data
+    /// Document data, serialized with its fields sorted by name
+    #[serde(serialize_with = "serialize_sorted")]
     pub data: HashMap<String, NVValue>,
     /// Creation timestamp
     pub created_at: DateTime<Utc>,
@@ -70,6 +360,18 @@ pub struct NVDocument {
     /// Soft delete flag
     #[serde(default)]
     pub deleted: bool,
+    /// Stored version the document was read at, as `FileManager::version`;
+    /// 0 for documents that weren't read from storage
+    #[serde(skip)]
+    pub version: u64,
+}
+
+/// Serialize document data in field name order, so the same document
+/// always serializes the same way
+fn serialize_sorted<S: serde::Serializer>(data: &HashMap<String, NVValue>, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut fields: Vec<(&String, &NVValue)> = data.iter().collect();
+    fields.sort_by_key(|(name, _)| *name);
+    serializer.collect_map(fields)
 }
 
 impl NVDocument {
@@ -82,6 +384,7 @@ impl NVDocument {
             created_at: now,
             updated_at: now,
             deleted: false,
+            version: 0,
         }
     }
 
@@ -89,14 +392,86 @@ impl NVDocument {
         self.data.get(field)
     }
 
+    /// A nested data value by dotted path; see `NVValue::get_path`
+    pub fn get_path(&self, path: &str) -> Option<&NVValue> {
+        let (field, rest) = match path.split_once('.') {
+            Some((field, rest)) => (field, Some(rest)),
+            None => (path, None),
+        };
+        let value = self.data.get(field)?;
+        match rest {
+            Some(rest) => value.get_path(rest),
+            None => Some(value),
+        }
+    }
+
+    pub fn get_str(&self, field: &str) -> Option<&str> {
+        self.get(field).and_then(NVValue::as_str)
+    }
+
+    pub fn get_f64(&self, field: &str) -> Option<f64> {
+        self.get(field).and_then(NVValue::as_f64)
+    }
+
+    pub fn meta(&self) -> DocumentMeta<'_> {
+        DocumentMeta {
+            id: &self.id,
+            collection: &self.collection,
+            deleted: self.deleted,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            version: self.version,
+        }
+    }
+
+    /// Value of a data field or metadata pseudo-field, as queries see it
+    pub fn field(&self, field: &str) -> Option<Cow<'_, NVValue>> {
+        match self.meta().field(field) {
+            Some(value) => Some(Cow::Owned(value)),
+            None => self.data.get(field).map(Cow::Borrowed),
+        }
+    }
+
     pub fn set(&mut self, field: String, value: NVValue) {
         self.data.insert(field, value);
         self.updated_at = Utc::now();
     }
+
+    /// Keep only the given data fields
+    ///
+    /// Metadata pseudo-fields among `fields` are added to the data.
+    pub fn project(&mut self, fields: &[String]) {
+        self.data.retain(|field, _| fields.contains(field));
+        let meta: Vec<(String, NVValue)> = fields
+            .iter()
+            .filter_map(|field| Some((field.clone(), self.meta().field(field)?)))
+            .collect();
+        self.data.extend(meta);
+    }
+
+    /// Apply an update operation
+    pub fn apply(&mut self, update: UpdateOperation) {
+        match update.kind {
+            UpdateKind::Set => self.set(update.field, update.value),
+            UpdateKind::DeepMerge => {
+                match self.data.get_mut(&update.field) {
+                    Some(current) => current.deep_merge(update.value),
+                    None => {
+                        self.data.insert(update.field, update.value);
+                    }
+                }
+                self.updated_at = Utc::now();
+            }
+            UpdateKind::Unset => {
+                self.data.remove(&update.field);
+                self.updated_at = Utc::now();
+            }
+        }
+    }
 }
 
 /// Query operators
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum QueryOperator {
     Equals,
     NotEquals,
@@ -109,10 +484,13 @@ pub enum QueryOperator {
     EndsWith,
     In,
     NotIn,
+    /// String within `max_distance` edits of the value, as a whole or in
+    /// any of its words, ignoring case
+    Fuzzy { max_distance: u32 },
 }
 
 /// Query condition
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct QueryCondition {
     pub field: String,
     pub operator: QueryOperator,
@@ -120,7 +498,7 @@ pub struct QueryCondition {
 }
 
 /// Logical operators for combining conditions
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum LogicalOperator {
     And,
     Or,
@@ -136,6 +514,40 @@ pub struct NVQuery {
     pub order_desc: bool,
     pub limit: Option<usize>,
     pub skip: Option<usize>,
+    /// Reference fields to replace with the documents they point to, as
+    /// `"field"` (using a declared reference) or `"field:collection"`
+    #[serde(default)]
+    pub populate: Vec<String>,
+    /// Data fields to return; `None` returns whole documents
+    #[serde(default)]
+    pub projection: Option<Vec<String>>,
+    /// Also search documents moved to archive segments by `archive`
+    #[serde(default)]
+    pub include_archived: bool,
+    /// Return fields the collection's schema marks sensitive
+    #[serde(default)]
+    pub include_sensitive: bool,
+    /// Fields added to each result, computed from its data before any
+    /// projection is applied
+    #[serde(default)]
+    pub computed: Vec<(String, Expression)>,
+    /// Queries run first to fill in the values of `In` and `NotIn`
+    /// conditions
+    #[serde(default)]
+    pub subqueries: Vec<Subquery>,
+    /// Fail with `Cancelled` if the query runs longer than this
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+/// A query whose results become the value of an `In` or `NotIn` condition
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subquery {
+    /// Index of the condition in the outer query's `conditions`
+    pub condition: usize,
+    /// Field of each result to collect; `None` collects document IDs
+    pub select: Option<String>,
+    pub query: NVQuery,
 }
 
 impl NVQuery {
@@ -148,9 +560,24 @@ impl NVQuery {
             order_desc: false,
             limit: None,
             skip: None,
+            populate: Vec::new(),
+            projection: None,
+            include_archived: false,
+            include_sensitive: false,
+            computed: Vec::new(),
+            subqueries: Vec::new(),
+            timeout_ms: None,
         }
     }
 
+    /// A query over several collections, each a name or a pattern where
+    /// `*` matches any characters
+    ///
+    /// Results from all of them are merged, then sorted and paged together.
+    pub fn across(collections: &[&str]) -> Self {
+        Self::new(collections.join(","))
+    }
+
     pub fn add_condition(
         &mut self,
         field: String,
@@ -167,10 +594,32 @@ impl NVQuery {
             value,
         });
     }
+
+    /// Add an `In` or `NotIn` condition matching the `select` field of
+    /// `query`'s results (or their IDs, for `None`)
+    pub fn add_subquery(
+        &mut self,
+        field: String,
+        operator: QueryOperator,
+        select: Option<String>,
+        query: NVQuery,
+        logical_op: Option<LogicalOperator>,
+    ) {
+        self.subqueries.push(Subquery {
+            condition: self.conditions.len(),
+            select,
+            query,
+        });
+        self.add_condition(field, operator, NVValue::Array(Vec::new()), logical_op);
+    }
 }
 
 /// Database configuration
+///
+/// Deserializing fills in defaults for missing fields, so JSON like
+/// `{"path": "data", "flush_interval_ms": 50}` is a complete config.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct DatabaseConfig {
     /// Path to database directory
     pub path: String,
@@ -182,6 +631,47 @@ pub struct DatabaseConfig {
     pub enable_encryption: bool,
     /// Auto-compact threshold (ratio of dead data)
     pub auto_compact_threshold: f32,
+    /// Worker threads for parallel query execution (0 = one per CPU core)
+    pub query_threads: usize,
+    /// Minimum number of records before a query is executed in parallel
+    pub parallel_query_threshold: usize,
+    /// Writes between automatic index checkpoints (0 = only on close)
+    pub checkpoint_interval: usize,
+    /// Open an existing database without write access
+    pub read_only: bool,
+    /// Maximum encoded document size in bytes (0 = unlimited)
+    pub max_document_size: usize,
+    /// Field values larger than this many bytes are stored out of line
+    pub overflow_threshold: usize,
+    /// ID strategy for collections without their own
+    pub id_strategy: IdStrategy,
+    /// Argon2id memory cost in KiB for password-derived keys
+    pub kdf_memory_kib: u32,
+    /// Argon2id passes for password-derived keys
+    pub kdf_iterations: u32,
+    /// Times `transact` retries a transaction that hit a write conflict
+    pub transaction_retries: usize,
+    /// Microseconds a write waits for concurrent writes to share its fsync
+    pub commit_window_micros: u64,
+    /// Milliseconds between background flushes; writes return before they're
+    /// synced (0 = sync every write before returning)
+    pub flush_interval_ms: u64,
+    /// Unsynced bytes that trigger an early background flush (0 = only on
+    /// the interval)
+    pub flush_threshold_bytes: u64,
+    /// Largest the data and overflow files may grow, in bytes (0 = unlimited)
+    pub max_database_size: u64,
+    /// What happens when a write would exceed `max_database_size`
+    pub quota_policy: QuotaPolicy,
+    /// Collections whose oldest documents `QuotaPolicy::EvictOldest` removes
+    pub cache_collections: Vec<String>,
+    /// Directory scheduled backups are written to (None = no scheduled
+    /// backups)
+    pub backup_dir: Option<String>,
+    /// Seconds between scheduled backups
+    pub backup_interval_secs: u64,
+    /// Scheduled backups to keep; older ones are deleted (0 = keep all)
+    pub backup_keep: usize,
 }
 
 impl Default for DatabaseConfig {
@@ -192,6 +682,74 @@ impl Default for DatabaseConfig {
             cache_size_mb: 100,
             enable_encryption: false,
             auto_compact_threshold: 0.3,
+            query_threads: 0,
+            parallel_query_threshold: 1000,
+            checkpoint_interval: 1000,
+            read_only: false,
+            max_document_size: 16 * 1024 * 1024,
+            overflow_threshold: 64 * 1024,
+            id_strategy: IdStrategy::Uuid,
+            kdf_memory_kib: 19 * 1024,
+            kdf_iterations: 2,
+            transaction_retries: 10,
+            commit_window_micros: 0,
+            flush_interval_ms: 0,
+            flush_threshold_bytes: 4 * 1024 * 1024,
+            max_database_size: 0,
+            quota_policy: QuotaPolicy::RejectWrites,
+            cache_collections: Vec::new(),
+            backup_dir: None,
+            backup_interval_secs: 24 * 60 * 60,
+            backup_keep: 7,
+        }
+    }
+}
+
+/// How a database at its size quota handles new writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum QuotaPolicy {
+    /// Fail writes with `QuotaExceeded`; deletes still succeed
+    #[default]
+    RejectWrites,
+    /// Delete the least recently updated documents of the cache collections
+    /// and compact, rejecting writes only if that doesn't free enough space
+    EvictOldest,
+}
+
+/// How document IDs are assigned on create
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum IdStrategy {
+    /// Random UUIDv4
+    #[default]
+    Uuid,
+    /// Lexicographically sortable ULID
+    Ulid,
+    /// Monotonic integer starting at 1, drawn from one counter for every
+    /// collection so IDs stay unique
+    AutoIncrement,
+    /// Taken from the document's string `id` field
+    Provided,
+}
+
+impl IdStrategy {
+    /// Stable name used when persisting the strategy
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IdStrategy::Uuid => "uuid",
+            IdStrategy::Ulid => "ulid",
+            IdStrategy::AutoIncrement => "auto_increment",
+            IdStrategy::Provided => "provided",
+        }
+    }
+
+    /// Parse a name produced by `as_str`
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "uuid" => Some(IdStrategy::Uuid),
+            "ulid" => Some(IdStrategy::Ulid),
+            "auto_increment" => Some(IdStrategy::AutoIncrement),
+            "provided" => Some(IdStrategy::Provided),
+            _ => None,
         }
     }
 }
@@ -201,4 +759,104 @@ impl Default for DatabaseConfig {
 pub struct UpdateOperation {
     pub field: String,
     pub value: NVValue,
+    /// How `value` is applied to the field
+    #[serde(default)]
+    pub kind: UpdateKind,
+}
+
+/// How an update operation changes a field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum UpdateKind {
+    /// Replace the field's value
+    #[default]
+    Set,
+    /// Recursively merge an object into the field's current value
+    DeepMerge,
+    /// Remove the field; the operation's value is ignored
+    Unset,
+}
+
+impl UpdateOperation {
+    /// Replace `field` with `value`
+    pub fn set(field: impl Into<String>, value: NVValue) -> Self {
+        Self {
+            field: field.into(),
+            value,
+            kind: UpdateKind::Set,
+        }
+    }
+
+    /// Remove `field` from the document
+    pub fn unset(field: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            value: NVValue::Null,
+            kind: UpdateKind::Unset,
+        }
+    }
+
+    /// Merge the object `value` into `field`, keeping keys it doesn't mention
+    pub fn merge(field: impl Into<String>, value: NVValue) -> Self {
+        Self {
+            field: field.into(),
+            value,
+            kind: UpdateKind::DeepMerge,
+        }
+    }
+}
+
+/// What happens to referencing documents when their target is deleted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OnDelete {
+    /// Delete the referencing documents too
+    Cascade,
+    /// Set the referencing field to null
+    SetNull,
+    /// Refuse to delete a document that is still referenced
+    Restrict,
+}
+
+impl OnDelete {
+    /// Parse "cascade", "set_null" or "restrict"
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "cascade" => Some(OnDelete::Cascade),
+            "set_null" => Some(OnDelete::SetNull),
+            "restrict" => Some(OnDelete::Restrict),
+            _ => None,
+        }
+    }
+}
+
+/// Declares that `collection.field` holds IDs of documents in `target`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reference {
+    pub collection: String,
+    pub field: String,
+    pub target: String,
+    pub on_delete: OnDelete,
+}
+
+/// One operation of a bulk write
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WriteOp {
+    /// Create a document; `id: None` uses the collection's ID strategy
+    Insert {
+        id: Option<String>,
+        collection: String,
+        data: HashMap<String, NVValue>,
+    },
+    /// Apply updates to an existing document
+    Update {
+        id: String,
+        updates: Vec<UpdateOperation>,
+    },
+    /// Delete an existing document
+    Delete { id: String },
+    /// Replace a document's data, creating it if it doesn't exist
+    Upsert {
+        id: String,
+        collection: String,
+        data: HashMap<String, NVValue>,
+    },
 }
@@ -0,0 +1,227 @@
+use crate::models::NVValue;
+use serde::de::{MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Index;
+
+/// The entries of `NVValue::Object`, kept in insertion order
+///
+/// Objects keep their field order through storage and serialization, so
+/// the same value always encodes to the same bytes. Equality ignores order.
+#[derive(Clone, Default)]
+pub struct NVObject {
+    entries: Vec<(String, NVValue)>,
+    positions: HashMap<String, usize>,
+}
+
+impl NVObject {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+            positions: HashMap::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&NVValue> {
+        self.positions.get(key).map(|&at| &self.entries[at].1)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut NVValue> {
+        self.positions.get(key).map(|&at| &mut self.entries[at].1)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    /// Set a key's value, returning the old one
+    ///
+    /// A new key goes last; an existing key keeps its place.
+    pub fn insert(&mut self, key: String, value: NVValue) -> Option<NVValue> {
+        match self.positions.get(&key) {
+            Some(&at) => Some(std::mem::replace(&mut self.entries[at].1, value)),
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    /// Remove a key, keeping the order of the rest
+    pub fn remove(&mut self, key: &str) -> Option<NVValue> {
+        let at = self.positions.remove(key)?;
+        let (_, value) = self.entries.remove(at);
+        for (key, _) in &self.entries[at..] {
+            *self.positions.get_mut(key).expect("indexed key") -= 1;
+        }
+        Some(value)
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter(self.entries.iter())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &NVValue> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+}
+
+/// Iterator over an object's entries in order
+pub struct Iter<'a>(std::slice::Iter<'a, (String, NVValue)>);
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a String, &'a NVValue);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, value)| (key, value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl<'a> IntoIterator for &'a NVObject {
+    type Item = (&'a String, &'a NVValue);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl IntoIterator for NVObject {
+    type Item = (String, NVValue);
+    type IntoIter = std::vec::IntoIter<(String, NVValue)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+/// Later duplicates replace earlier values in the earlier place
+impl FromIterator<(String, NVValue)> for NVObject {
+    fn from_iter<I: IntoIterator<Item = (String, NVValue)>>(iter: I) -> Self {
+        let mut object = NVObject::new();
+        object.extend(iter);
+        object
+    }
+}
+
+impl Extend<(String, NVValue)> for NVObject {
+    fn extend<I: IntoIterator<Item = (String, NVValue)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+/// A `HashMap` has no order to keep, so its keys are sorted
+impl From<HashMap<String, NVValue>> for NVObject {
+    fn from(map: HashMap<String, NVValue>) -> Self {
+        let mut entries: Vec<(String, NVValue)> = map.into_iter().collect();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        entries.into_iter().collect()
+    }
+}
+
+impl Index<&str> for NVObject {
+    type Output = NVValue;
+
+    fn index(&self, key: &str) -> &NVValue {
+        self.get(key).unwrap_or_else(|| panic!("no key '{}' in object", key))
+    }
+}
+
+impl PartialEq for NVObject {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl fmt::Debug for NVObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl Serialize for NVObject {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (key, value) in self {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for NVObject {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ObjectVisitor;
+
+        impl<'de> Visitor<'de> for ObjectVisitor {
+            type Value = NVObject;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a map")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<NVObject, A::Error> {
+                let mut object = NVObject::with_capacity(access.size_hint().unwrap_or(0).min(1024));
+                while let Some((key, value)) = access.next_entry()? {
+                    object.insert(key, value);
+                }
+                Ok(object)
+            }
+        }
+
+        deserializer.deserialize_map(ObjectVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keeps_insertion_order() {
+        let mut object: NVObject = ["zeta", "alpha", "mid"]
+            .iter()
+            .map(|key| (key.to_string(), NVValue::String(key.to_string())))
+            .collect();
+        object.insert("alpha".to_string(), NVValue::Null);
+        object.insert("last".to_string(), NVValue::Bool(true));
+        assert_eq!(object.remove("zeta"), Some(NVValue::String("zeta".to_string())));
+        assert_eq!(object.keys().collect::<Vec<_>>(), ["alpha", "mid", "last"]);
+        assert_eq!(object["mid"], NVValue::String("mid".to_string()));
+
+        let json = serde_json::to_string(&object).unwrap();
+        assert_eq!(json, r#"{"alpha":null,"mid":"mid","last":true}"#);
+        let parsed: NVObject = serde_json::from_str(r#"{"b":1,"a":2}"#).unwrap();
+        assert_eq!(parsed.keys().collect::<Vec<_>>(), ["b", "a"]);
+
+        let reordered: NVObject = parsed.clone().into_iter().rev().collect();
+        assert_eq!(reordered, parsed);
+    }
+}
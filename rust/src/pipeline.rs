@@ -0,0 +1,647 @@
+use crate::aggregate::Aggregate;
+use crate::expression::{self, Expression};
+use chrono::{DateTime, Utc};
+use crate::models::{LogicalOperator, NVQuery, NVValue, QueryCondition};
+use crate::object::NVObject;
+use crate::query::QueryProcessor;
+use crate::sampling::{self, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// A row flowing through a pipeline: a document's data, or a group
+pub type Row = HashMap<String, NVValue>;
+
+/// One step of an aggregation pipeline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Stage {
+    /// Keep rows matching the conditions, combined as in a query
+    Match {
+        conditions: Vec<QueryCondition>,
+        #[serde(default)]
+        logical_operators: Vec<LogicalOperator>,
+    },
+    /// Keep only the listed fields
+    Project(Vec<String>),
+    /// Add fields computed from each row's other fields
+    Compute(Vec<(String, Expression)>),
+    /// Replace each row with one per element of the array in `field`,
+    /// holding that element in its place
+    ///
+    /// A value that isn't an array is kept as a single element. Rows where
+    /// the field is missing, null or an empty array are dropped unless
+    /// `preserve_empty` is set, in which case they pass through unchanged.
+    /// `index_field`, if given, receives each element's position.
+    Unwind {
+        field: String,
+        #[serde(default)]
+        preserve_empty: bool,
+        #[serde(default)]
+        index_field: Option<String>,
+    },
+    /// Replace the rows with one per distinct value of `by` (or a single
+    /// row for `None`), holding that value under `by` and each aggregate
+    /// under its output name
+    Group {
+        by: Option<String>,
+        aggregates: Vec<(String, Aggregate)>,
+    },
+    /// Replace the rows with one per `interval` of the date in `field`,
+    /// holding the window's start (RFC 3339, UTC) under `field` and each
+    /// aggregate under its output name, oldest first
+    ///
+    /// Windows are aligned to the epoch, so days start at midnight UTC.
+    /// Windows without rows are left out, as are rows whose field doesn't
+    /// hold a date.
+    Window {
+        field: String,
+        interval: Interval,
+        aggregates: Vec<(String, Aggregate)>,
+    },
+    /// Order rows by a field; missing values sort last
+    Sort {
+        field: String,
+        #[serde(default)]
+        descending: bool,
+    },
+    Skip(usize),
+    Limit(usize),
+    /// Keep this many rows, chosen uniformly at random
+    Sample(usize),
+    /// Keep the first `limit` rows of each distinct value of `by` when
+    /// ordered by `sort`, e.g. the latest 3 messages per conversation
+    ///
+    /// Rows come out grouped, in group value order.
+    TopPerGroup {
+        by: String,
+        sort: String,
+        #[serde(default)]
+        descending: bool,
+        limit: usize,
+    },
+    /// Replace the rows with a single row holding, under each facet's
+    /// name, an array of `{"value": ..., "count": ...}` objects, most
+    /// frequent first
+    Facet(Vec<Facet>),
+}
+
+/// Value counts of one field, computed by a `Facet` stage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Facet {
+    /// Field of the output row holding the counts
+    pub name: String,
+    pub field: String,
+    #[serde(default)]
+    pub bucket: Bucket,
+}
+
+/// How a facet groups values before counting them
+///
+/// Array values count once per element. Rows where the field is missing
+/// or null, or (for dates) doesn't hold a date, aren't counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Bucket {
+    /// Each distinct value
+    #[default]
+    Value,
+    /// `YYYY` of a date
+    Year,
+    /// `YYYY-MM` of a date
+    Month,
+    /// `YYYY-MM-DD` of a date
+    Day,
+}
+
+impl Bucket {
+    fn key(self, value: &NVValue) -> Option<NVValue> {
+        let format = match self {
+            Bucket::Value => return Some(value.clone()),
+            Bucket::Year => "%Y",
+            Bucket::Month => "%Y-%m",
+            Bucket::Day => "%Y-%m-%d",
+        };
+        let date = DateTime::<Utc>::from_timestamp_millis(expression::timestamp(value)?)?;
+        Some(NVValue::String(date.format(format).to_string()))
+    }
+}
+
+/// Width of the windows of a `Window` stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interval {
+    Seconds(u64),
+    Minutes(u64),
+    Hours(u64),
+    Days(u64),
+}
+
+impl Interval {
+    /// Width in milliseconds, at least one
+    fn millis(self) -> i64 {
+        let seconds = match self {
+            Interval::Seconds(n) => n,
+            Interval::Minutes(n) => n * 60,
+            Interval::Hours(n) => n * 3600,
+            Interval::Days(n) => n * 86_400,
+        };
+        (seconds as i64 * 1000).max(1)
+    }
+}
+
+/// Run `stages` over `rows` in order
+pub fn run(mut rows: Vec<Row>, stages: &[Stage], processor: &QueryProcessor) -> Vec<Row> {
+    for stage in stages {
+        rows = match stage {
+            Stage::Match { conditions, logical_operators } => {
+                let mut query = NVQuery::new(String::new());
+                query.conditions = conditions.clone();
+                query.logical_operators = logical_operators.clone();
+                rows.into_iter().filter(|row| processor.matches_row(row, &query)).collect()
+            }
+            Stage::Project(fields) => {
+                rows.iter_mut().for_each(|row| row.retain(|field, _| fields.contains(field)));
+                rows
+            }
+            Stage::Compute(fields) => {
+                rows.iter_mut().for_each(|row| expression::compute(row, fields, processor));
+                rows
+            }
+            Stage::Unwind { field, preserve_empty, index_field } => rows
+                .into_iter()
+                .flat_map(|row| unwind(row, field, *preserve_empty, index_field.as_deref()))
+                .collect(),
+            Stage::Group { by, aggregates } => group(rows, by.as_deref(), aggregates),
+            Stage::Window { field, interval, aggregates } => window(rows, field, *interval, aggregates),
+            Stage::Sort { field, descending } => {
+                rows.sort_by(|a, b| {
+                    let ordering = QueryProcessor::compare_field(a.get(field), b.get(field));
+                    if *descending {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    }
+                });
+                rows
+            }
+            Stage::Skip(n) => rows.into_iter().skip(*n).collect(),
+            Stage::Limit(n) => {
+                rows.truncate(*n);
+                rows
+            }
+            Stage::Sample(n) => sampling::reservoir(rows, *n, &mut Rng::new()),
+            Stage::Facet(facets) => vec![facet(&rows, facets)],
+            Stage::TopPerGroup { by, sort, descending, limit } => {
+                top_per_group(rows, by, sort, *descending, *limit)
+            }
+        };
+    }
+    rows
+}
+
+/// The rows `row` unwinds to
+fn unwind(mut row: Row, field: &str, preserve_empty: bool, index_field: Option<&str>) -> Vec<Row> {
+    let elements = match row.remove(field) {
+        Some(NVValue::Array(elements)) if !elements.is_empty() => elements,
+        Some(value @ (NVValue::Array(_) | NVValue::Null)) if preserve_empty => {
+            row.insert(field.to_string(), value);
+            return vec![row];
+        }
+        None if preserve_empty => return vec![row],
+        Some(NVValue::Array(_) | NVValue::Null) | None => return Vec::new(),
+        Some(value) => vec![value],
+    };
+
+    elements
+        .into_iter()
+        .enumerate()
+        .map(|(i, element)| {
+            let mut unwound = row.clone();
+            unwound.insert(field.to_string(), element);
+            if let Some(index_field) = index_field {
+                unwound.insert(index_field.to_string(), NVValue::Number(i as f64));
+            }
+            unwound
+        })
+        .collect()
+}
+
+/// The first `limit` rows of each group of `rows` when sorted by `sort`
+fn top_per_group(rows: Vec<Row>, by: &str, sort: &str, descending: bool, limit: usize) -> Vec<Row> {
+    // Keyed by the value's JSON text, ordered by the value itself
+    let mut groups: HashMap<String, (NVValue, Vec<Row>)> = HashMap::new();
+    for row in rows {
+        let key = row.get(by).cloned().unwrap_or(NVValue::Null);
+        let id = serde_json::Value::from(key.clone()).to_string();
+        groups.entry(id).or_insert_with(|| (key, Vec::new())).1.push(row);
+    }
+    let mut groups: Vec<(String, (NVValue, Vec<Row>))> = groups.into_iter().collect();
+    groups.sort_by(|(a_id, (a, _)), (b_id, (b, _))| {
+        QueryProcessor::compare_field(Some(a), Some(b)).then_with(|| a_id.cmp(b_id))
+    });
+
+    groups
+        .into_iter()
+        .flat_map(|(_, (_, mut group))| {
+            group.sort_by(|a, b| {
+                let ordering = QueryProcessor::compare_field(a.get(sort), b.get(sort));
+                if descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+            group.truncate(limit);
+            group
+        })
+        .collect()
+}
+
+/// Count the values of every facet in one pass over `rows`
+fn facet(rows: &[Row], facets: &[Facet]) -> Row {
+    // Per facet: counts by the value's JSON text, with the value itself
+    let mut counts: Vec<HashMap<String, (NVValue, usize)>> = vec![HashMap::new(); facets.len()];
+    for row in rows {
+        for (facet, counts) in facets.iter().zip(counts.iter_mut()) {
+            let values = match row.get(&facet.field) {
+                Some(NVValue::Array(elements)) => elements.as_slice(),
+                Some(value) => std::slice::from_ref(value),
+                None => &[],
+            };
+            for value in values.iter().filter(|value| !matches!(value, NVValue::Null)) {
+                if let Some(key) = facet.bucket.key(value) {
+                    let id = serde_json::Value::from(key.clone()).to_string();
+                    counts.entry(id).or_insert((key, 0)).1 += 1;
+                }
+            }
+        }
+    }
+
+    facets
+        .iter()
+        .zip(counts)
+        .map(|(facet, counts)| {
+            let mut counts: Vec<(String, (NVValue, usize))> = counts.into_iter().collect();
+            counts.sort_by(|(a, (_, a_count)), (b, (_, b_count))| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+            let buckets = counts
+                .into_iter()
+                .map(|(_, (value, count))| {
+                    let mut bucket = NVObject::new();
+                    bucket.insert("value".to_string(), value);
+                    bucket.insert("count".to_string(), NVValue::Number(count as f64));
+                    NVValue::Object(bucket)
+                })
+                .collect();
+            (facet.name.clone(), NVValue::Array(buckets))
+        })
+        .collect()
+}
+
+/// Group rows by the window of `interval` their date in `field` falls in
+fn window(rows: Vec<Row>, field: &str, interval: Interval, aggregates: &[(String, Aggregate)]) -> Vec<Row> {
+    let width = interval.millis();
+    let rows = rows
+        .into_iter()
+        .filter_map(|mut row| {
+            let start = expression::timestamp(row.get(field)?)?.div_euclid(width) * width;
+            row.insert(field.to_string(), NVValue::Number(start as f64));
+            Some(row)
+        })
+        .collect();
+
+    // Groups come out in key text order, so order the numeric starts first
+    let mut windows = group(rows, Some(field), aggregates);
+    windows.sort_by(|a, b| QueryProcessor::compare_field(a.get(field), b.get(field)));
+    for row in &mut windows {
+        if let Some(NVValue::Number(start)) = row.get(field) {
+            let start = DateTime::<Utc>::from_timestamp_millis(*start as i64).unwrap_or_default();
+            row.insert(field.to_string(), NVValue::String(start.to_rfc3339()));
+        }
+    }
+    windows
+}
+
+/// Running totals of one group
+struct Group {
+    key: NVValue,
+    count: usize,
+    /// Per aggregate, over the numeric values of its field
+    totals: Vec<Totals>,
+}
+
+#[derive(Clone, Copy)]
+struct Totals {
+    sum: f64,
+    n: usize,
+    min: f64,
+    max: f64,
+}
+
+impl Default for Totals {
+    fn default() -> Self {
+        Self {
+            sum: 0.0,
+            n: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+/// Group rows by the value of `by`, in group value order
+fn group(rows: Vec<Row>, by: Option<&str>, aggregates: &[(String, Aggregate)]) -> Vec<Row> {
+    let mut groups: BTreeMap<String, Group> = BTreeMap::new();
+    for row in &rows {
+        let key = by.and_then(|field| row.get(field)).cloned().unwrap_or(NVValue::Null);
+        let group = groups
+            .entry(serde_json::Value::from(key.clone()).to_string())
+            .or_insert_with(|| Group {
+                key,
+                count: 0,
+                totals: vec![Totals::default(); aggregates.len()],
+            });
+
+        group.count += 1;
+        for ((_, aggregate), totals) in aggregates.iter().zip(group.totals.iter_mut()) {
+            if let Aggregate::Sum(field) | Aggregate::Avg(field) | Aggregate::Min(field) | Aggregate::Max(field) =
+                aggregate
+            {
+                if let Some(NVValue::Number(value)) = row.get(field) {
+                    totals.sum += value;
+                    totals.n += 1;
+                    totals.min = totals.min.min(*value);
+                    totals.max = totals.max.max(*value);
+                }
+            }
+        }
+    }
+
+    groups
+        .into_values()
+        .map(|group| {
+            let mut row = Row::new();
+            if let Some(field) = by {
+                row.insert(field.to_string(), group.key);
+            }
+            for ((name, aggregate), totals) in aggregates.iter().zip(group.totals) {
+                let value = match aggregate {
+                    Aggregate::Count => NVValue::Number(group.count as f64),
+                    Aggregate::Sum(_) => NVValue::Number(totals.sum),
+                    _ if totals.n == 0 => NVValue::Null,
+                    Aggregate::Avg(_) => NVValue::Number(totals.sum / totals.n as f64),
+                    Aggregate::Min(_) => NVValue::Number(totals.min),
+                    Aggregate::Max(_) => NVValue::Number(totals.max),
+                };
+                row.insert(name.clone(), value);
+            }
+            row
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::QueryOperator;
+
+    fn order(status: &str, total: f64) -> Row {
+        let mut row = Row::new();
+        row.insert("status".to_string(), NVValue::String(status.to_string()));
+        row.insert("total".to_string(), NVValue::Number(total));
+        row
+    }
+
+    #[test]
+    fn test_match_group_sort_limit() {
+        let rows = vec![
+            order("open", 10.0),
+            order("paid", 30.0),
+            order("open", 5.0),
+            order("void", 1.0),
+            order("paid", 20.0),
+        ];
+        let stages = vec![
+            Stage::Match {
+                conditions: vec![QueryCondition {
+                    field: "status".to_string(),
+                    operator: QueryOperator::NotEquals,
+                    value: NVValue::String("void".to_string()),
+                }],
+                logical_operators: Vec::new(),
+            },
+            Stage::Project(vec!["status".to_string(), "total".to_string()]),
+            Stage::Group {
+                by: Some("status".to_string()),
+                aggregates: vec![
+                    ("orders".to_string(), Aggregate::Count),
+                    ("revenue".to_string(), Aggregate::Sum("total".to_string())),
+                ],
+            },
+            Stage::Sort { field: "revenue".to_string(), descending: true },
+            Stage::Limit(1),
+        ];
+
+        let rows = run(rows, &stages, &QueryProcessor::new());
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["status"], NVValue::String("paid".to_string()));
+        assert_eq!(rows[0]["orders"], NVValue::Number(2.0));
+        assert_eq!(rows[0]["revenue"], NVValue::Number(50.0));
+    }
+
+    #[test]
+    fn test_unwind_counts_tags() {
+        let post = |tags: Option<NVValue>| {
+            let mut row = Row::new();
+            if let Some(tags) = tags {
+                row.insert("tags".to_string(), tags);
+            }
+            row
+        };
+        let tag = |name: &str| NVValue::String(name.to_string());
+        let rows = vec![
+            post(Some(NVValue::Array(vec![tag("rust"), tag("db")]))),
+            post(Some(NVValue::Array(vec![tag("rust")]))),
+            post(Some(tag("db"))),
+            post(Some(NVValue::Array(Vec::new()))),
+            post(None),
+        ];
+
+        let stages = vec![
+            Stage::Unwind { field: "tags".to_string(), preserve_empty: false, index_field: None },
+            Stage::Group {
+                by: Some("tags".to_string()),
+                aggregates: vec![("posts".to_string(), Aggregate::Count)],
+            },
+        ];
+        let counts = run(rows.clone(), &stages, &QueryProcessor::new());
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[0]["tags"], tag("db"));
+        assert_eq!(counts[0]["posts"], NVValue::Number(2.0));
+        assert_eq!(counts[1]["tags"], tag("rust"));
+        assert_eq!(counts[1]["posts"], NVValue::Number(2.0));
+
+        let stages = vec![Stage::Unwind {
+            field: "tags".to_string(),
+            preserve_empty: true,
+            index_field: Some("position".to_string()),
+        }];
+        let unwound = run(rows, &stages, &QueryProcessor::new());
+        assert_eq!(unwound.len(), 6);
+        assert_eq!(unwound[1]["position"], NVValue::Number(1.0));
+        assert_eq!(unwound[4]["tags"], NVValue::Array(Vec::new()));
+        assert!(!unwound[5].contains_key("tags"));
+    }
+
+    #[test]
+    fn test_facet_counts_several_fields_at_once() {
+        let mut rows = vec![order("open", 10.0), order("paid", 30.0), order("open", 5.0)];
+        let dates = ["2024-01-05T10:00:00Z", "2024-02-01T00:00:00Z", "2024-01-31T23:59:59Z"];
+        for (row, date) in rows.iter_mut().zip(dates) {
+            row.insert("placed".to_string(), NVValue::String(date.to_string()));
+        }
+        rows[0].insert("tags".to_string(), NVValue::Array(vec![
+            NVValue::String("gift".to_string()),
+            NVValue::String("rush".to_string()),
+        ]));
+        rows[1].insert("tags".to_string(), NVValue::String("gift".to_string()));
+
+        let stages = vec![Stage::Facet(vec![
+            Facet { name: "by_status".to_string(), field: "status".to_string(), bucket: Bucket::Value },
+            Facet { name: "by_month".to_string(), field: "placed".to_string(), bucket: Bucket::Month },
+            Facet { name: "by_tag".to_string(), field: "tags".to_string(), bucket: Bucket::Value },
+        ])];
+        let rows = run(rows, &stages, &QueryProcessor::new());
+        assert_eq!(rows.len(), 1);
+
+        let counts = |name: &str| -> Vec<(NVValue, NVValue)> {
+            match &rows[0][name] {
+                NVValue::Array(buckets) => buckets
+                    .iter()
+                    .map(|bucket| match bucket {
+                        NVValue::Object(bucket) => (bucket["value"].clone(), bucket["count"].clone()),
+                        _ => panic!("bucket isn't an object"),
+                    })
+                    .collect(),
+                _ => panic!("facet isn't an array"),
+            }
+        };
+        let s = |s: &str| NVValue::String(s.to_string());
+        let n = |n: f64| NVValue::Number(n);
+        assert_eq!(counts("by_status"), vec![(s("open"), n(2.0)), (s("paid"), n(1.0))]);
+        assert_eq!(counts("by_month"), vec![(s("2024-01"), n(2.0)), (s("2024-02"), n(1.0))]);
+        assert_eq!(counts("by_tag"), vec![(s("gift"), n(2.0)), (s("rush"), n(1.0))]);
+    }
+
+    #[test]
+    fn test_top_per_group() {
+        let message = |conversation: &str, sent: f64| {
+            let mut row = Row::new();
+            row.insert("conversation".to_string(), NVValue::String(conversation.to_string()));
+            row.insert("sent".to_string(), NVValue::Number(sent));
+            row
+        };
+        let rows = vec![
+            message("b", 1.0),
+            message("a", 1.0),
+            message("a", 4.0),
+            message("b", 3.0),
+            message("a", 2.0),
+            message("a", 3.0),
+        ];
+        let stages = vec![Stage::TopPerGroup {
+            by: "conversation".to_string(),
+            sort: "sent".to_string(),
+            descending: true,
+            limit: 2,
+        }];
+
+        let top: Vec<(NVValue, NVValue)> = run(rows, &stages, &QueryProcessor::new())
+            .into_iter()
+            .map(|row| (row["conversation"].clone(), row["sent"].clone()))
+            .collect();
+        let s = |s: &str| NVValue::String(s.to_string());
+        assert_eq!(top, vec![
+            (s("a"), NVValue::Number(4.0)),
+            (s("a"), NVValue::Number(3.0)),
+            (s("b"), NVValue::Number(3.0)),
+            (s("b"), NVValue::Number(1.0)),
+        ]);
+
+        // Numeric groups come out in numeric order, 9 before 10
+        let rows = vec![message("x", 1.0), message("y", 2.0)]
+            .into_iter()
+            .zip([10.0, 9.0])
+            .map(|(mut row, shard)| {
+                row.insert("shard".to_string(), NVValue::Number(shard));
+                row
+            })
+            .collect();
+        let stages = vec![Stage::TopPerGroup {
+            by: "shard".to_string(),
+            sort: "sent".to_string(),
+            descending: false,
+            limit: 1,
+        }];
+        let shards: Vec<NVValue> = run(rows, &stages, &QueryProcessor::new())
+            .into_iter()
+            .map(|row| row["shard"].clone())
+            .collect();
+        assert_eq!(shards, vec![NVValue::Number(9.0), NVValue::Number(10.0)]);
+    }
+
+    #[test]
+    fn test_skip_and_project() {
+        let rows = vec![order("a", 1.0), order("b", 2.0), order("c", 3.0)];
+        let stages = vec![
+            Stage::Sort { field: "total".to_string(), descending: false },
+            Stage::Skip(1),
+            Stage::Project(vec!["total".to_string()]),
+        ];
+
+        let rows = run(rows, &stages, &QueryProcessor::new());
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].len(), 1);
+        assert_eq!(rows[0]["total"], NVValue::Number(2.0));
+    }
+
+    #[test]
+    fn test_window_min_max_per_hour() {
+        let reading = |at: &str, total: f64| {
+            let mut row = order("sensor", total);
+            row.insert("at".to_string(), NVValue::String(at.to_string()));
+            row
+        };
+        let rows = vec![
+            reading("2024-03-01T10:59:00Z", 4.0),
+            reading("2024-03-01T09:15:00Z", 2.0),
+            reading("2024-03-01T10:05:00Z", 8.0),
+            reading("2024-03-01T09:45:00Z", 6.0),
+            order("undated", 100.0),
+        ];
+        let stages = vec![Stage::Window {
+            field: "at".to_string(),
+            interval: Interval::Hours(1),
+            aggregates: vec![
+                ("low".to_string(), Aggregate::Min("total".to_string())),
+                ("high".to_string(), Aggregate::Max("total".to_string())),
+                ("mean".to_string(), Aggregate::Avg("total".to_string())),
+            ],
+        }];
+
+        let rows = run(rows, &stages, &QueryProcessor::new());
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["at"], NVValue::String("2024-03-01T09:00:00+00:00".to_string()));
+        assert_eq!((&rows[0]["low"], &rows[0]["high"]), (&NVValue::Number(2.0), &NVValue::Number(6.0)));
+        assert_eq!(rows[1]["at"], NVValue::String("2024-03-01T10:00:00+00:00".to_string()));
+        assert_eq!(rows[1]["mean"], NVValue::Number(6.0));
+
+        let daily = run(
+            vec![reading("2024-03-01T23:30:00Z", 1.0), reading("2024-03-02T00:30:00Z", 1.0)],
+            &[Stage::Window {
+                field: "at".to_string(),
+                interval: Interval::Days(1),
+                aggregates: vec![("n".to_string(), Aggregate::Count)],
+            }],
+            &QueryProcessor::new(),
+        );
+        assert_eq!(daily.len(), 2);
+    }
+
+}
@@ -0,0 +1,192 @@
+use crate::index::{IndexDefinition, SecondaryIndex};
+use crate::models::NVQuery;
+use crate::query::{planner, QueryProcessor};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// An index the advisor recommends creating
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexSuggestion {
+    pub definition: IndexDefinition,
+    /// Full scans that could have used the index
+    pub queries: usize,
+    /// Document reads the index would have saved across those scans
+    pub estimated_benefit: usize,
+}
+
+#[derive(Debug, Default)]
+struct FieldStats {
+    queries: usize,
+    scanned: usize,
+    matched: usize,
+}
+
+/// What a full scan saw: how many live records of the queried collection
+/// it read, and how many of those satisfied each condition on its own
+#[derive(Debug)]
+pub struct ScanTally {
+    scanned: AtomicUsize,
+    matched: Vec<AtomicUsize>,
+}
+
+impl ScanTally {
+    pub fn new(query: &NVQuery) -> Self {
+        Self {
+            scanned: AtomicUsize::new(0),
+            matched: query.conditions.iter().map(|_| AtomicUsize::new(0)).collect(),
+        }
+    }
+
+    /// Count a record of the queried collection
+    pub(crate) fn scanned(&self) {
+        self.scanned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a record that satisfied the condition at `index`
+    pub(crate) fn matched(&self, index: usize) {
+        if let Some(count) = self.matched.get(index) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Tracks which fields full scans filter and sort on, and how selective
+/// they turned out to be
+#[derive(Debug, Default)]
+pub struct IndexAdvisor {
+    fields: Mutex<HashMap<(String, String), FieldStats>>,
+}
+
+impl IndexAdvisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a full scan of `query`'s collection
+    ///
+    /// Each field is credited with the selectivity of its own conditions,
+    /// the most selective one if it has several; a sort field with a limit
+    /// is credited with the page an ordered read would stop after.
+    pub fn record_scan(&self, query: &NVQuery, processor: &QueryProcessor, tally: &ScanTally) {
+        let scanned = tally.scanned.load(Ordering::Relaxed);
+        let mut fields: HashMap<&String, usize> = HashMap::new();
+        for condition in processor.required_conditions(query) {
+            if !planner::indexable(condition) {
+                continue;
+            }
+            let Some(index) = query.conditions.iter().position(|c| std::ptr::eq(c, condition)) else {
+                continue;
+            };
+            let matched = tally.matched[index].load(Ordering::Relaxed);
+            fields.entry(&condition.field).and_modify(|m| *m = (*m).min(matched)).or_insert(matched);
+        }
+        if let (Some(limit), Some(field)) = (query.limit, &query.order_by) {
+            let page = limit.saturating_add(query.skip.unwrap_or(0)).min(scanned);
+            fields.entry(field).and_modify(|m| *m = (*m).min(page)).or_insert(page);
+        }
+
+        let mut stats = self.fields.lock();
+        for (field, matched) in fields {
+            let entry = stats
+                .entry((query.collection.clone(), field.clone()))
+                .or_default();
+            entry.queries += 1;
+            entry.scanned += scanned;
+            entry.matched += matched;
+        }
+    }
+
+    /// Suggested single-field indexes, most beneficial first
+    ///
+    /// Fields that already lead an existing index are skipped.
+    pub fn suggest(&self, indexes: &HashMap<String, SecondaryIndex>) -> Vec<IndexSuggestion> {
+        let mut suggestions: Vec<IndexSuggestion> = self
+            .fields
+            .lock()
+            .iter()
+            .filter(|((collection, field), _)| {
+                !indexes.values().any(|index| {
+                    index.definition().collection == *collection
+                        && index.definition().fields[0] == *field
+                })
+            })
+            .map(|((collection, field), stats)| IndexSuggestion {
+                definition: IndexDefinition {
+                    name: format!("{}_by_{}", collection, field),
+                    collection: collection.clone(),
+                    fields: vec![field.clone()],
+                    filter: Vec::new(),
+                },
+                queries: stats.queries,
+                estimated_benefit: stats.scanned.saturating_sub(stats.matched),
+            })
+            .filter(|suggestion| suggestion.estimated_benefit > 0)
+            .collect();
+
+        suggestions.sort_by(|a, b| {
+            b.estimated_benefit
+                .cmp(&a.estimated_benefit)
+                .then_with(|| a.definition.name.cmp(&b.definition.name))
+        });
+        suggestions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{LogicalOperator, NVValue, QueryOperator};
+
+    #[test]
+    fn test_suggests_selective_fields() {
+        let processor = QueryProcessor::new();
+        let advisor = IndexAdvisor::new();
+        let tally = |query: &NVQuery, scanned: usize, matched: &[usize]| {
+            let tally = ScanTally::new(query);
+            tally.scanned.store(scanned, Ordering::Relaxed);
+            for (count, matched) in tally.matched.iter().zip(matched) {
+                count.store(*matched, Ordering::Relaxed);
+            }
+            tally
+        };
+
+        let mut by_email = NVQuery::new("users".to_string());
+        by_email.add_condition(
+            "email".to_string(),
+            QueryOperator::Equals,
+            NVValue::String("a@example.com".to_string()),
+            None,
+        );
+        advisor.record_scan(&by_email, &processor, &tally(&by_email, 1000, &[1]));
+        advisor.record_scan(&by_email, &processor, &tally(&by_email, 1000, &[1]));
+
+        // Each field is credited with its own selectivity, not the query's
+        let mut by_plan = NVQuery::new("users".to_string());
+        by_plan.add_condition("plan".to_string(), QueryOperator::Equals, NVValue::String("pro".to_string()), None);
+        by_plan.add_condition(
+            "age".to_string(),
+            QueryOperator::GreaterThan,
+            NVValue::Number(30.0),
+            Some(LogicalOperator::And),
+        );
+        advisor.record_scan(&by_plan, &processor, &tally(&by_plan, 1000, &[100, 1000]));
+
+        let mut by_name = NVQuery::new("users".to_string());
+        by_name.add_condition(
+            "name".to_string(),
+            QueryOperator::Contains,
+            NVValue::String("a".to_string()),
+            None,
+        );
+        advisor.record_scan(&by_name, &processor, &tally(&by_name, 1000, &[10]));
+
+        let suggestions = advisor.suggest(&HashMap::new());
+        let benefits: Vec<(&str, usize, usize)> = suggestions
+            .iter()
+            .map(|s| (s.definition.fields[0].as_str(), s.queries, s.estimated_benefit))
+            .collect();
+        assert_eq!(benefits, vec![("email", 2, 1998), ("plan", 1, 900)]);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::models::NVQuery;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Parsed queries keyed by their normalized text, least recently used
+/// evicted first
+pub struct QueryCache {
+    capacity: usize,
+    entries: BTreeMap<String, (NVQuery, u64)>,
+    /// Incremented on every lookup; entries remember when they were last used
+    tick: u64,
+    stats: QueryCacheStats,
+}
+
+/// Lookups served from and missed by a `QueryCache`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct QueryCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+impl QueryCache {
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: BTreeMap::new(),
+            tick: 0,
+            stats: QueryCacheStats { hits: 0, misses: 0, entries: 0 },
+        }
+    }
+
+    /// The cache key for a query on `collection` written as `text`
+    pub fn key(collection: &str, text: &str) -> String {
+        format!("{}\0{}", collection, normalize(text))
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<NVQuery> {
+        self.tick += 1;
+        match self.entries.get_mut(key) {
+            Some((query, used)) => {
+                *used = self.tick;
+                self.stats.hits += 1;
+                Some(query.clone())
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, key: String, query: NVQuery) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(key, (query, self.tick));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn stats(&self) -> QueryCacheStats {
+        QueryCacheStats {
+            entries: self.entries.len(),
+            ..self.stats
+        }
+    }
+}
+
+/// `text` with whitespace outside of JSON strings removed
+fn normalize(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in text.chars() {
+        if in_string {
+            normalized.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if !c.is_whitespace() {
+            in_string = c == '"';
+            normalized.push(c);
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_ignores_whitespace_outside_strings() {
+        assert_eq!(
+            QueryCache::key("users", r#"{ "order_by": "first name" }"#),
+            QueryCache::key("users", "{\"order_by\":\n\"first name\"}"),
+        );
+        assert_ne!(
+            QueryCache::key("users", r#"{"order_by": "first name"}"#),
+            QueryCache::key("users", r#"{"order_by": "firstname"}"#),
+        );
+        assert_eq!(normalize(r#"{ "a\" b" : 1 }"#), r#"{"a\" b":1}"#);
+        assert_ne!(QueryCache::key("users", "{}"), QueryCache::key("teams", "{}"));
+    }
+
+    #[test]
+    fn test_least_recently_used_entry_is_evicted() {
+        let mut cache = QueryCache::new(2);
+        cache.insert("a".to_string(), NVQuery::new("a".to_string()));
+        cache.insert("b".to_string(), NVQuery::new("b".to_string()));
+        assert!(cache.get("a").is_some());
+        cache.insert("c".to_string(), NVQuery::new("c".to_string()));
+
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("c").is_some());
+        assert_eq!(cache.stats(), QueryCacheStats { hits: 3, misses: 1, entries: 2 });
+    }
+}
@@ -0,0 +1,77 @@
+use crate::error::{NeuralVaultError, NVResult};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Stops a running query when cancelled from another thread or once its
+/// deadline passes
+///
+/// Clones share the cancellation, so a UI thread can keep one and cancel
+/// a query running elsewhere with another. Queries check the token between
+/// documents and fail with `Cancelled`.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token that also trips `timeout` from now
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self::new().limited_to(timeout)
+    }
+
+    /// A clone sharing this token's cancellation whose deadline is at most
+    /// `timeout` from now
+    pub fn limited_to(&self, timeout: Duration) -> Self {
+        let deadline = Instant::now() + timeout;
+        Self {
+            cancelled: self.cancelled.clone(),
+            deadline: Some(self.deadline.map_or(deadline, |current| current.min(deadline))),
+        }
+    }
+
+    /// Stop every query using this token or its clones
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed) || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Fail with `Cancelled` if the query should stop
+    pub fn check(&self) -> NVResult<()> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(NeuralVaultError::Cancelled("Query was cancelled".to_string()));
+        }
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(NeuralVaultError::Cancelled("Query timed out".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_and_timeout() {
+        let token = CancellationToken::new();
+        let limited = token.limited_to(Duration::from_secs(60));
+        assert!(limited.check().is_ok());
+        token.clone().cancel();
+        assert!(limited.is_cancelled());
+        assert!(matches!(limited.check(), Err(NeuralVaultError::Cancelled(_))));
+
+        let expired = CancellationToken::with_timeout(Duration::ZERO);
+        assert!(expired.is_cancelled());
+        // A later deadline doesn't extend an earlier one
+        assert!(expired.limited_to(Duration::from_secs(60)).is_cancelled());
+    }
+}
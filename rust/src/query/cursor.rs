@@ -0,0 +1,44 @@
+use crate::models::{NVDocument, NVQuery};
+use std::collections::VecDeque;
+
+/// Results of a query, handed out a batch at a time
+///
+/// Created by `NeuralVault::open_cursor` and read with
+/// `NeuralVault::next_batch`. Only the IDs of the matching documents are
+/// kept while the cursor is open; each batch reads its documents when it's
+/// fetched, so it sees updates made since and skips documents deleted
+/// since. Archived documents, which can't be read back by ID, are held
+/// whole.
+pub struct Cursor {
+    pending: VecDeque<Pending>,
+    /// Projection, computed fields and populate specs applied to each batch
+    pub(crate) shape: NVQuery,
+}
+
+/// A result not yet returned
+pub(crate) enum Pending {
+    Stored(String),
+    Held(NVDocument),
+}
+
+impl Cursor {
+    pub(crate) fn new(pending: VecDeque<Pending>, shape: NVQuery) -> Self {
+        Self { pending, shape }
+    }
+
+    /// Take the next result
+    pub(crate) fn pop(&mut self) -> Option<Pending> {
+        self.pending.pop_front()
+    }
+
+    /// Number of results not yet returned, counting any deleted since the
+    /// cursor opened
+    pub fn remaining(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Whether every result has been returned
+    pub fn is_exhausted(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
@@ -0,0 +1,66 @@
+/// Edit distance between two strings, counting insertions, deletions,
+/// substitutions and swaps of adjacent characters as one edit each, or
+/// `None` if it's more than `max`
+///
+/// Swaps count once so common typos like "recieve" stay close to
+/// "receive". Gives up as soon as every alignment is over `max`.
+pub fn distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+
+    // Rows of the optimal string alignment table
+    let mut before: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = (a[i - 1] != b[j - 1]) as usize;
+            row[j] = (previous[j] + 1).min(row[j - 1] + 1).min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        if row.iter().all(|&d| d > max) {
+            return None;
+        }
+        before = std::mem::replace(&mut previous, row);
+    }
+    Some(previous[b.len()]).filter(|&d| d <= max)
+}
+
+/// Whether `text`, or any word in it, is within `max` edits of `term`,
+/// ignoring case
+pub fn matches(text: &str, term: &str, max: usize) -> bool {
+    let text = text.to_lowercase();
+    let term = term.to_lowercase();
+    distance(&text, &term, max).is_some()
+        || text
+            .split(|c: char| !c.is_alphanumeric())
+            .any(|word| !word.is_empty() && distance(word, &term, max).is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance("receive", "receive", 2), Some(0));
+        assert_eq!(distance("recieve", "receive", 2), Some(1));
+        assert_eq!(distance("kitten", "sitting", 3), Some(3));
+        assert_eq!(distance("kitten", "sitting", 2), None);
+        assert_eq!(distance("", "abc", 3), Some(3));
+        assert_eq!(distance("naïve", "naive", 1), Some(1));
+        assert_eq!(distance("a", "abcdef", 2), None);
+    }
+
+    #[test]
+    fn test_matches_words() {
+        assert!(matches("How to Receive payments", "recieve", 1));
+        assert!(matches("Colour", "color", 1));
+        assert!(!matches("How to send payments", "recieve", 1));
+    }
+}
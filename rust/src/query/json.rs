@@ -0,0 +1,363 @@
+use crate::error::NeuralVaultError;
+use crate::models::{self, LogicalOperator, NVQuery, NVValue, QueryCondition, QueryOperator};
+use chrono::DateTime;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+
+/// Operator names accepted in JSON conditions, in the order they're listed
+/// in errors
+pub const OPERATORS: [&str; 12] = [
+    "==", "!=", ">", ">=", "<", "<=", "contains", "starts_with", "ends_with", "in", "not_in", "fuzzy",
+];
+
+/// Longest snippet of the offending JSON kept in an error
+const SNIPPET_LEN: usize = 80;
+
+/// Why a JSON query couldn't be parsed, and where
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueryParseError {
+    pub message: String,
+    /// Index of the condition in `conditions`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub condition: Option<usize>,
+    /// Key the condition or query is missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub missing_key: Option<String>,
+    /// Operators that would have been accepted
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub expected: Vec<String>,
+    /// The offending part of the JSON, shortened
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+}
+
+impl QueryParseError {
+    fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            condition: None,
+            missing_key: None,
+            expected: Vec::new(),
+            snippet: None,
+        }
+    }
+
+    fn missing(key: &str, what: &str) -> Self {
+        Self {
+            missing_key: Some(key.to_string()),
+            ..Self::new(format!("Missing '{}' in {}", key, what))
+        }
+    }
+
+    fn expecting_operators(mut self) -> Self {
+        self.expected = OPERATORS.iter().map(|op| op.to_string()).collect();
+        self
+    }
+
+    /// Locate the error at condition `index`, quoting it unless a more
+    /// specific snippet is already set
+    fn at(mut self, index: usize, condition: &Value) -> Self {
+        self.condition = Some(index);
+        if self.snippet.is_none() {
+            self.snippet = Some(snippet(&condition.to_string()));
+        }
+        self
+    }
+}
+
+impl fmt::Display for QueryParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(index) = self.condition {
+            write!(f, "Condition {}: ", index)?;
+        }
+        write!(f, "{}", self.message)?;
+        if !self.expected.is_empty() {
+            write!(f, " (expected one of {})", self.expected.join(", "))?;
+        }
+        if let Some(snippet) = &self.snippet {
+            write!(f, " in {}", snippet)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for QueryParseError {}
+
+impl From<QueryParseError> for NeuralVaultError {
+    fn from(error: QueryParseError) -> Self {
+        NeuralVaultError::InvalidQuery(error.to_string())
+    }
+}
+
+/// Parse a JSON query over `collection`
+///
+/// `{"conditions": [{"field": "age", "operator": ">", "value": 30,
+/// "logical": "and"}], "order_by": "age", "order_desc": true, "limit": 10}`;
+/// an empty string or `{}` matches every document.
+pub fn parse_query(collection: String, query_json: &str) -> Result<NVQuery, QueryParseError> {
+    if query_json.is_empty() || query_json == "{}" {
+        return Ok(NVQuery::new(collection));
+    }
+
+    let json: Value = serde_json::from_str(query_json).map_err(|e| QueryParseError {
+        snippet: Some(snippet_around(query_json, e.line(), e.column())),
+        ..QueryParseError::new(format!("Invalid query JSON: {}", e))
+    })?;
+    parse_query_value(collection, &json)
+}
+
+fn parse_query_value(collection: String, json: &Value) -> Result<NVQuery, QueryParseError> {
+    let mut query = NVQuery::new(collection);
+
+    // Parse conditions
+    if let Some(conditions) = json.get("conditions") {
+        let conditions = conditions.as_array().ok_or_else(|| QueryParseError {
+            snippet: Some(snippet(&conditions.to_string())),
+            ..QueryParseError::new("'conditions' must be an array")
+        })?;
+        for (i, cond) in conditions.iter().enumerate() {
+            let QueryCondition { field, operator, value } = parse_condition(cond).map_err(|e| e.at(i, cond))?;
+
+            let logical_op = if i > 0 {
+                let op_str = cond.get("logical").and_then(|v| v.as_str()).unwrap_or("and");
+                Some(parse_logical_operator(op_str).map_err(|e| e.at(i, cond))?)
+            } else {
+                None
+            };
+
+            // {"subquery": {"collection": "users", "select": "id", "query": {...}}}
+            // matches the selected field of another query's results
+            match cond.get("subquery") {
+                Some(subquery) => {
+                    let (select, subquery) = parse_subquery(subquery).map_err(|e| e.at(i, cond))?;
+                    query.add_subquery(field, operator, select, subquery, logical_op);
+                }
+                None => query.add_condition(field, operator, value, logical_op),
+            }
+        }
+    }
+
+    // Parse order_by
+    if let Some(order_by) = json.get("order_by").and_then(|v| v.as_str()) {
+        query.order_by = Some(order_by.to_string());
+        query.order_desc = json.get("order_desc").and_then(|v| v.as_bool()).unwrap_or(false);
+    }
+
+    // Parse populate, e.g. ["author_id:users"]
+    if let Some(populate) = json.get("populate").and_then(|v| v.as_array()) {
+        query.populate = strings(populate, "populate")?;
+    }
+
+    // Parse projection, e.g. ["title", "status"]
+    if let Some(projection) = json.get("projection").and_then(|v| v.as_array()) {
+        query.projection = Some(strings(projection, "projection")?);
+    }
+
+    // Parse computed fields, e.g. {"total": {"Multiply": [{"Field": "price"}, {"Field": "qty"}]}}
+    if let Some(computed) = json.get("computed").and_then(|v| v.as_object()) {
+        query.computed = computed
+            .iter()
+            .map(|(name, expression)| {
+                serde_json::from_value(expression.clone())
+                    .map(|expression| (name.clone(), expression))
+                    .map_err(|e| QueryParseError {
+                        snippet: Some(snippet(&expression.to_string())),
+                        ..QueryParseError::new(format!("Invalid expression for '{}': {}", name, e))
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+    }
+
+    query.include_archived = json.get("include_archived").and_then(|v| v.as_bool()).unwrap_or(false);
+    query.include_sensitive = json.get("include_sensitive").and_then(|v| v.as_bool()).unwrap_or(false);
+
+    // Parse limit and skip
+    query.limit = json.get("limit").and_then(|v| v.as_u64()).map(|v| v as usize);
+    query.skip = json.get("skip").and_then(|v| v.as_u64()).map(|v| v as usize);
+    query.timeout_ms = json.get("timeout_ms").and_then(|v| v.as_u64());
+
+    Ok(query)
+}
+
+/// Parse a single `{"field", "operator", "value"}` condition
+pub fn parse_condition(cond: &Value) -> Result<QueryCondition, QueryParseError> {
+    if !cond.is_object() {
+        return Err(QueryParseError::new("A condition must be an object"));
+    }
+
+    let field = cond
+        .get("field")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| QueryParseError::missing("field", "condition"))?
+        .to_string();
+
+    let operator_str = cond
+        .get("operator")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| QueryParseError::missing("operator", "condition").expecting_operators())?;
+
+    // {"operator": "fuzzy", "value": "recieve", "max_distance": 2}
+    let operator = match parse_operator(operator_str)? {
+        QueryOperator::Fuzzy { max_distance } => QueryOperator::Fuzzy {
+            max_distance: cond.get("max_distance").and_then(|v| v.as_u64()).map_or(max_distance, |v| v as u32),
+        },
+        operator => operator,
+    };
+
+    let value = match cond.get("value") {
+        Some(value) => value.clone(),
+        None if cond.get("subquery").is_some() => Value::Null,
+        None => return Err(QueryParseError::missing("value", "condition")),
+    };
+
+    // Timestamp pseudo-fields compare as milliseconds; accept RFC 3339 too
+    let value = match value {
+        Value::String(date) if models::is_timestamp_field(&field) => {
+            let date = DateTime::parse_from_rfc3339(&date).map_err(|e| QueryParseError {
+                snippet: Some(snippet(&date)),
+                ..QueryParseError::new(format!("Invalid timestamp for '{}': {}", field, e))
+            })?;
+            Value::from(date.timestamp_millis())
+        }
+        value => value,
+    };
+
+    Ok(QueryCondition {
+        field,
+        operator,
+        value: NVValue::from(value),
+    })
+}
+
+/// The selected field (`None` for document IDs) and query of a subquery
+fn parse_subquery(subquery: &Value) -> Result<(Option<String>, NVQuery), QueryParseError> {
+    let collection = subquery
+        .get("collection")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| QueryParseError::missing("collection", "subquery"))?
+        .to_string();
+
+    let select = match subquery.get("select").and_then(|v| v.as_str()) {
+        None | Some("id") => None,
+        Some(field) => Some(field.to_string()),
+    };
+
+    let query = match subquery.get("query") {
+        Some(query) => parse_query_value(collection, query),
+        None => Ok(NVQuery::new(collection)),
+    };
+    // Errors inside the subquery are reported against the outer condition
+    let query = query.map_err(|e| QueryParseError {
+        message: format!("In subquery: {}", e.message),
+        condition: None,
+        ..e
+    })?;
+    Ok((select, query))
+}
+
+pub fn parse_operator(op: &str) -> Result<QueryOperator, QueryParseError> {
+    match op {
+        "==" | "equals" => Ok(QueryOperator::Equals),
+        "!=" | "not_equals" => Ok(QueryOperator::NotEquals),
+        ">" | "greater_than" => Ok(QueryOperator::GreaterThan),
+        ">=" | "greater_than_or_equal" => Ok(QueryOperator::GreaterThanOrEqual),
+        "<" | "less_than" => Ok(QueryOperator::LessThan),
+        "<=" | "less_than_or_equal" => Ok(QueryOperator::LessThanOrEqual),
+        "contains" => Ok(QueryOperator::Contains),
+        "starts_with" => Ok(QueryOperator::StartsWith),
+        "ends_with" => Ok(QueryOperator::EndsWith),
+        "in" => Ok(QueryOperator::In),
+        "not_in" => Ok(QueryOperator::NotIn),
+        "fuzzy" => Ok(QueryOperator::Fuzzy { max_distance: 1 }),
+        _ => Err(QueryParseError::new(format!("Unknown operator '{}'", op)).expecting_operators()),
+    }
+}
+
+pub fn parse_logical_operator(op: &str) -> Result<LogicalOperator, QueryParseError> {
+    match op.to_lowercase().as_str() {
+        "and" | "&&" => Ok(LogicalOperator::And),
+        "or" | "||" => Ok(LogicalOperator::Or),
+        _ => Err(QueryParseError {
+            expected: vec!["and".to_string(), "or".to_string()],
+            ..QueryParseError::new(format!("Unknown logical operator '{}'", op))
+        }),
+    }
+}
+
+fn strings(values: &[Value], key: &str) -> Result<Vec<String>, QueryParseError> {
+    values
+        .iter()
+        .map(|v| {
+            v.as_str().map(str::to_string).ok_or_else(|| QueryParseError {
+                snippet: Some(snippet(&v.to_string())),
+                ..QueryParseError::new(format!("{} expects strings", key))
+            })
+        })
+        .collect()
+}
+
+/// `text`, cut to `SNIPPET_LEN` characters
+fn snippet(text: &str) -> String {
+    match text.char_indices().nth(SNIPPET_LEN) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// The part of `text` around a 1-based line and column
+fn snippet_around(text: &str, line: usize, column: usize) -> String {
+    let line = text.lines().nth(line.saturating_sub(1)).unwrap_or_default();
+    let chars: Vec<char> = line.chars().collect();
+    let at = column.saturating_sub(1).min(chars.len());
+    let start = at.saturating_sub(SNIPPET_LEN / 2);
+    let end = (start + SNIPPET_LEN).min(chars.len());
+    let mut snippet: String = chars[start..end].iter().collect();
+    if start > 0 {
+        snippet.insert_str(0, "...");
+    }
+    if end < chars.len() {
+        snippet.push_str("...");
+    }
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(query_json: &str) -> QueryParseError {
+        parse_query("notes".to_string(), query_json).unwrap_err()
+    }
+
+    #[test]
+    fn test_errors_locate_the_condition() {
+        let e = error(r#"{"conditions": [{"field": "a", "operator": "==", "value": 1}, {"field": "b", "value": 2}]}"#);
+        assert_eq!(e.condition, Some(1));
+        assert_eq!(e.missing_key.as_deref(), Some("operator"));
+        assert_eq!(e.expected.len(), OPERATORS.len());
+        assert_eq!(e.snippet.as_deref(), Some(r#"{"field":"b","value":2}"#));
+        assert!(e.to_string().starts_with("Condition 1: Missing 'operator' in condition (expected one of ==, !=,"));
+
+        let e = error(r#"{"conditions": [{"field": "a", "operator": "=>", "value": 1}]}"#);
+        assert_eq!((e.condition, e.missing_key.as_deref()), (Some(0), None));
+        assert_eq!(e.message, "Unknown operator '=>'");
+        assert!(e.expected.contains(&">=".to_string()));
+
+        let e = error(r#"{"conditions": [{"field": "a", "operator": "in", "subquery": {"query": {}}}]}"#);
+        assert_eq!(e.missing_key.as_deref(), Some("collection"));
+        assert_eq!(e.message, "Missing 'collection' in subquery");
+        let e = error(r#"{"conditions": [{"field": "a", "operator": "in", "subquery": {"collection": "users", "query": {"conditions": [{"field": "b"}]}}}]}"#);
+        assert_eq!((e.condition, e.missing_key.as_deref()), (Some(0), Some("operator")));
+        assert_eq!(e.message, "In subquery: Missing 'operator' in condition");
+
+        let e = error(r#"{"conditions": [{"field": "a", "operator": "==", "value": 1},]}"#);
+        assert_eq!(e.condition, None);
+        assert!(e.message.starts_with("Invalid query JSON"));
+        assert!(e.snippet.unwrap().contains("1},]"));
+
+        for operator in OPERATORS {
+            assert!(parse_operator(operator).is_ok(), "{}", operator);
+        }
+    }
+}
@@ -1,3 +1,22 @@
+pub mod advisor;
+pub mod cache;
+pub mod cancellation;
+pub mod cursor;
+pub mod fuzzy;
+pub mod json;
+pub mod planner;
 pub mod processor;
+pub mod sql;
+pub mod targets;
+pub mod template;
+pub mod validate;
 
+pub use advisor::{IndexAdvisor, IndexSuggestion, ScanTally};
+pub use cache::{QueryCache, QueryCacheStats};
+pub use cancellation::CancellationToken;
+pub use cursor::Cursor;
+pub use json::QueryParseError;
+pub use planner::{QueryPlan, QueryStats, VectorFilterPlan};
 pub use processor::QueryProcessor;
+pub use template::QueryTemplate;
+pub use validate::QueryWarning;
@@ -0,0 +1,397 @@
+use crate::index::{IndexKey, SecondaryIndex};
+use crate::models::{NVDocument, NVQuery, NVValue, QueryCondition, QueryOperator};
+use crate::query::QueryProcessor;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ops::Bound;
+
+/// Index candidates always few enough to rank exactly
+const PREFILTER_CANDIDATES: usize = 1000;
+
+/// How a query is executed, as reported by `explain`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum QueryPlan {
+    /// Bloom filters prove nothing can match
+    RuledOut,
+    /// Decode and filter every record
+    FullScan { collection: String },
+    /// Read only documents found through an index, then filter them
+    IndexScan {
+        index: String,
+        field: String,
+        candidates: usize,
+    },
+    /// Walk an index in `order_by` order, stopping once `limit` documents match
+    IndexOrder { index: String, field: String },
+    /// Answer from index keys alone without reading any documents
+    Covered { index: String },
+    /// Query the results of a saved view
+    View { name: String, source: Box<QueryPlan> },
+    /// Query several collections and merge the results
+    Union { parts: Vec<(String, QueryPlan)> },
+}
+
+impl QueryPlan {
+    /// Name of the index the plan reads, if any
+    pub fn index(&self) -> Option<&str> {
+        match self {
+            QueryPlan::IndexScan { index, .. } | QueryPlan::IndexOrder { index, .. } | QueryPlan::Covered { index } => {
+                Some(index)
+            }
+            QueryPlan::View { source, .. } => source.index(),
+            QueryPlan::Union { parts } => parts.iter().find_map(|(_, plan)| plan.index()),
+            QueryPlan::RuledOut | QueryPlan::FullScan { .. } => None,
+        }
+    }
+}
+
+/// What running a query took, as reported by `find_with_stats`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct QueryStats {
+    /// Documents or records read and checked against the conditions,
+    /// including those read for subqueries
+    pub scanned: usize,
+    pub returned: usize,
+    pub elapsed_ms: f64,
+    /// Index read, if any
+    pub index: Option<String>,
+    /// How the query ran
+    pub plan: Option<QueryPlan>,
+}
+
+/// A chosen plan with the document IDs to read for index plans
+pub struct PlannedQuery {
+    pub plan: QueryPlan,
+    pub candidates: Option<Vec<String>>,
+    /// Index to walk in the query's order, reading until enough documents
+    /// match
+    pub ordered: Option<String>,
+    /// Documents rebuilt from a covering index, to be filtered in place of a scan
+    pub documents: Option<Vec<NVDocument>>,
+}
+
+/// Pick the index that yields the fewest candidates, or fall back to a scan
+///
+/// An index is usable when the query has a required equality or range
+/// condition on its first field. A partial index is only usable when every
+/// condition of its filter is also required by the query, since otherwise
+/// matching documents might not be indexed.
+///
+/// A query with a projection is answered from the index alone when the
+/// index's fields include every projected, filtered and sorted field.
+pub fn plan(
+    query: &NVQuery,
+    indexes: &HashMap<String, SecondaryIndex>,
+    processor: &QueryProcessor,
+) -> PlannedQuery {
+    let required = processor.required_conditions(query);
+
+    let usable = indexes
+        .values()
+        .filter(|index| index.definition().collection == query.collection)
+        .filter(|index| {
+            index
+                .definition()
+                .filter
+                .iter()
+                .all(|condition| required.contains(&condition))
+        });
+
+    if let Some(planned) = covered(query, usable.clone()) {
+        return planned;
+    }
+
+    let best = usable
+        .clone()
+        .filter_map(|index| {
+            let field = &index.definition().fields[0];
+            let (lower, upper) = key_bounds(field, &required)?;
+            Some((index, index.range(lower, upper)))
+        })
+        .min_by_key(|(_, candidates)| candidates.len());
+
+    match best {
+        Some((index, candidates)) => PlannedQuery {
+            plan: QueryPlan::IndexScan {
+                index: index.definition().name.clone(),
+                field: index.definition().fields[0].clone(),
+                candidates: candidates.len(),
+            },
+            candidates: Some(candidates),
+            ordered: None,
+            documents: None,
+        },
+        None => {
+            // "Latest N" queries: stream from an index on the sort field
+            let ordering = match (&query.order_by, query.limit) {
+                (Some(order_by), Some(_)) => usable
+                    .clone()
+                    .find(|index| index.definition().fields[0] == *order_by && index.orders_like_processor()),
+                _ => None,
+            };
+
+            match ordering {
+                Some(index) => PlannedQuery {
+                    plan: QueryPlan::IndexOrder {
+                        index: index.definition().name.clone(),
+                        field: index.definition().fields[0].clone(),
+                    },
+                    candidates: None,
+                    ordered: Some(index.definition().name.clone()),
+                    documents: None,
+                },
+                None => PlannedQuery {
+                    plan: QueryPlan::FullScan {
+                        collection: query.collection.clone(),
+                    },
+                    candidates: None,
+                    ordered: None,
+                    documents: None,
+                },
+            }
+        }
+    }
+}
+
+/// How a vector search restricted by a query's conditions is executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum VectorFilterPlan {
+    /// Bloom filters prove nothing can match
+    RuledOut,
+    /// Find the matching documents first and rank them exactly
+    PreFilter,
+    /// Walk the vector index, dropping neighbours that don't match
+    PostFilter,
+}
+
+/// Pre-filter when an index narrows the conditions down to few documents
+/// (at most `PREFILTER_CANDIDATES`, or a tenth of the `vectors`), and
+/// post-filter otherwise
+pub fn plan_vector_filter(plan: &QueryPlan, vectors: usize) -> VectorFilterPlan {
+    match plan {
+        QueryPlan::RuledOut => VectorFilterPlan::RuledOut,
+        QueryPlan::IndexScan { candidates, .. }
+            if *candidates <= PREFILTER_CANDIDATES.max(vectors / 10) =>
+        {
+            VectorFilterPlan::PreFilter
+        }
+        _ => VectorFilterPlan::PostFilter,
+    }
+}
+
+/// Plan for answering a projected query from the smallest covering index
+fn covered<'a>(
+    query: &NVQuery,
+    usable: impl Iterator<Item = &'a SecondaryIndex>,
+) -> Option<PlannedQuery> {
+    let projection = query.projection.as_ref()?;
+    if !query.populate.is_empty() {
+        return None;
+    }
+
+    let needed: Vec<&String> = projection
+        .iter()
+        .chain(query.conditions.iter().map(|c| &c.field))
+        .chain(query.order_by.iter())
+        .collect();
+
+    let index = usable
+        .filter(|index| needed.iter().all(|field| index.definition().fields.contains(field)))
+        .min_by_key(|index| index.len())?;
+
+    Some(PlannedQuery {
+        plan: QueryPlan::Covered {
+            index: index.definition().name.clone(),
+        },
+        candidates: None,
+        ordered: None,
+        documents: Some(index.documents()?),
+    })
+}
+
+/// Whether an index on the condition's field could serve it
+pub fn indexable(condition: &QueryCondition) -> bool {
+    key_bounds(&condition.field, &[condition]).is_some()
+}
+
+/// Index key range implied by the required conditions on `field`
+///
+/// Returns `None` if no condition on the field can be served by an index.
+/// The range may be wider than the conditions (numeric equality is widened
+/// by the processor's tolerance); results are always re-filtered.
+fn key_bounds(field: &str, required: &[&QueryCondition]) -> Option<(Bound<IndexKey>, Bound<IndexKey>)> {
+    // Bounds that keep a numeric range from spilling into other types
+    let below_numbers = || Bound::Excluded(IndexKey::Bool(true));
+    let above_numbers = || Bound::Excluded(IndexKey::String(String::new()));
+
+    let mut lower = Bound::Unbounded;
+    let mut upper = Bound::Unbounded;
+    let mut usable = false;
+
+    for condition in required.iter().filter(|c| c.field == field) {
+        let key = |value: &NVValue| IndexKey::from_value(Some(value));
+        match (&condition.operator, &condition.value) {
+            (QueryOperator::Equals, NVValue::Number(n)) => {
+                let tolerance = f64::EPSILON * n.abs().max(1.0);
+                lower = tighter_lower(lower, Bound::Included(key(&NVValue::Number(n - tolerance))));
+                upper = tighter_upper(upper, Bound::Included(key(&NVValue::Number(n + tolerance))));
+            }
+            (QueryOperator::Equals, value @ (NVValue::Null | NVValue::Bool(_) | NVValue::String(_))) => {
+                lower = tighter_lower(lower, Bound::Included(key(value)));
+                upper = tighter_upper(upper, Bound::Included(key(value)));
+            }
+            (QueryOperator::GreaterThan, value @ NVValue::Number(_)) => {
+                lower = tighter_lower(lower, Bound::Excluded(key(value)));
+                upper = tighter_upper(upper, above_numbers());
+            }
+            (QueryOperator::GreaterThanOrEqual, value @ NVValue::Number(_)) => {
+                lower = tighter_lower(lower, Bound::Included(key(value)));
+                upper = tighter_upper(upper, above_numbers());
+            }
+            (QueryOperator::LessThan, value @ NVValue::Number(_)) => {
+                lower = tighter_lower(lower, below_numbers());
+                upper = tighter_upper(upper, Bound::Excluded(key(value)));
+            }
+            (QueryOperator::LessThanOrEqual, value @ NVValue::Number(_)) => {
+                lower = tighter_lower(lower, below_numbers());
+                upper = tighter_upper(upper, Bound::Included(key(value)));
+            }
+            (QueryOperator::StartsWith, NVValue::String(prefix)) => {
+                lower = tighter_lower(lower, Bound::Included(IndexKey::String(prefix.clone())));
+                upper = tighter_upper(upper, above_numbers_and_strings());
+            }
+            _ => continue,
+        }
+        usable = true;
+    }
+
+    usable.then_some((lower, upper))
+}
+
+/// Upper bound just past every string key
+fn above_numbers_and_strings() -> Bound<IndexKey> {
+    Bound::Excluded(IndexKey::Other)
+}
+
+fn tighter_lower(a: Bound<IndexKey>, b: Bound<IndexKey>) -> Bound<IndexKey> {
+    match (&a, &b) {
+        (Bound::Unbounded, _) => b,
+        (_, Bound::Unbounded) => a,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+            if y > x || (y == x && matches!(b, Bound::Excluded(_))) {
+                b
+            } else {
+                a
+            }
+        }
+    }
+}
+
+fn tighter_upper(a: Bound<IndexKey>, b: Bound<IndexKey>) -> Bound<IndexKey> {
+    match (&a, &b) {
+        (Bound::Unbounded, _) => b,
+        (_, Bound::Unbounded) => a,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+            if y < x || (y == x && matches!(b, Bound::Excluded(_))) {
+                b
+            } else {
+                a
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::IndexDefinition;
+    use crate::models::NVDocument;
+
+    fn indexes() -> HashMap<String, SecondaryIndex> {
+        let processor = QueryProcessor::new();
+        let mut index = SecondaryIndex::new(IndexDefinition {
+            name: "by_age".to_string(),
+            collection: "users".to_string(),
+            fields: vec!["age".to_string()],
+            filter: Vec::new(),
+        });
+        for age in 0..100 {
+            let mut data = HashMap::new();
+            data.insert("age".to_string(), NVValue::Number(age as f64));
+            index.add(&NVDocument::new(age.to_string(), "users".to_string(), data), &processor);
+        }
+
+        let mut indexes = HashMap::new();
+        indexes.insert("by_age".to_string(), index);
+        indexes
+    }
+
+    #[test]
+    fn test_vector_filter_plan() {
+        let scan = |candidates| QueryPlan::IndexScan {
+            index: "by_age".to_string(),
+            field: "age".to_string(),
+            candidates,
+        };
+        assert_eq!(plan_vector_filter(&scan(5), 100), VectorFilterPlan::PreFilter);
+        assert_eq!(plan_vector_filter(&scan(5_000), 100_000), VectorFilterPlan::PreFilter);
+        assert_eq!(plan_vector_filter(&scan(50_000), 100_000), VectorFilterPlan::PostFilter);
+        let full_scan = QueryPlan::FullScan { collection: "users".to_string() };
+        assert_eq!(plan_vector_filter(&full_scan, 10), VectorFilterPlan::PostFilter);
+        assert_eq!(plan_vector_filter(&QueryPlan::RuledOut, 10), VectorFilterPlan::RuledOut);
+    }
+
+    #[test]
+    fn test_range_uses_index() {
+        let mut query = NVQuery::new("users".to_string());
+        query.add_condition("age".to_string(), QueryOperator::GreaterThanOrEqual, NVValue::Number(90.0), None);
+        query.add_condition(
+            "age".to_string(),
+            QueryOperator::LessThan,
+            NVValue::Number(95.0),
+            Some(crate::models::LogicalOperator::And),
+        );
+
+        let planned = plan(&query, &indexes(), &QueryProcessor::new());
+        assert_eq!(
+            planned.plan,
+            QueryPlan::IndexScan {
+                index: "by_age".to_string(),
+                field: "age".to_string(),
+                candidates: 5
+            }
+        );
+    }
+
+    #[test]
+    fn test_unindexed_or_query_scans() {
+        let mut query = NVQuery::new("users".to_string());
+        query.add_condition("age".to_string(), QueryOperator::Equals, NVValue::Number(1.0), None);
+        query.add_condition(
+            "name".to_string(),
+            QueryOperator::Equals,
+            NVValue::String("x".to_string()),
+            Some(crate::models::LogicalOperator::Or),
+        );
+
+        let planned = plan(&query, &indexes(), &QueryProcessor::new());
+        assert!(matches!(planned.plan, QueryPlan::FullScan { .. }));
+        assert!(planned.candidates.is_none());
+    }
+
+    #[test]
+    fn test_order_by_with_limit_uses_index() {
+        let mut query = NVQuery::new("users".to_string());
+        query.order_by = Some("age".to_string());
+        query.order_desc = true;
+        query.limit = Some(3);
+
+        let planned = plan(&query, &indexes(), &QueryProcessor::new());
+        assert_eq!(planned.ordered.as_deref(), Some("by_age"));
+        assert!(planned.candidates.is_none());
+
+        // Without a limit the whole collection is needed anyway
+        query.limit = None;
+        assert!(plan(&query, &indexes(), &QueryProcessor::new()).ordered.is_none());
+    }
+}
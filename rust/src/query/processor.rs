@@ -1,12 +1,51 @@
 use crate::error::{NeuralVaultError, NVResult};
 use crate::models::{LogicalOperator, NVDocument, NVQuery, NVValue, QueryCondition, QueryOperator};
+use crate::storage::record::{FieldOpener, OverflowResolver};
+use crate::storage::VersionedRecord;
+use super::fuzzy;
+use super::advisor::ScanTally;
+use super::CancellationToken;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 
 /// Query processor for filtering and sorting documents
-pub struct QueryProcessor;
+pub struct QueryProcessor {
+    /// Worker pool for parallel execution (None = always sequential)
+    pool: Option<ThreadPool>,
+    /// Minimum input size before the pool is used
+    parallel_threshold: usize,
+}
 
 impl QueryProcessor {
     pub fn new() -> Self {
-        Self
+        Self {
+            pool: None,
+            parallel_threshold: usize::MAX,
+        }
+    }
+
+    /// Create a processor that evaluates large inputs on a rayon pool
+    ///
+    /// `threads == 0` sizes the pool to the number of CPU cores.
+    pub fn with_parallelism(threads: usize, parallel_threshold: usize) -> NVResult<Self> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("neural-vault-query-{}", i))
+            .build()
+            .map_err(|e| NeuralVaultError::InvalidConfiguration(e.to_string()))?;
+
+        Ok(Self {
+            pool: Some(pool),
+            parallel_threshold,
+        })
+    }
+
+    /// Pool to use for an input of `len` items, if parallelism is worthwhile
+    fn pool_for(&self, len: usize) -> Option<&ThreadPool> {
+        self.pool.as_ref().filter(|_| len >= self.parallel_threshold)
     }
 
     /// Filter documents based on query conditions
@@ -15,13 +54,107 @@ impl QueryProcessor {
             return Ok(Vec::new());
         }
 
-        let mut results: Vec<NVDocument> = documents
-            .into_iter()
-            .filter(|doc| self.matches_query(doc, query))
-            .collect();
+        let results: Vec<NVDocument> = match self.pool_for(documents.len()) {
+            Some(pool) => pool.install(|| {
+                documents
+                    .into_par_iter()
+                    .filter(|doc| self.matches_query(doc, query))
+                    .collect()
+            }),
+            None => documents
+                .into_iter()
+                .filter(|doc| self.matches_query(doc, query))
+                .collect(),
+        };
+
+        self.finish(results, query)
+    }
 
+    /// Filter raw storage records, decoding only what the query needs
+    ///
+    /// Conditions are evaluated against a lazy record view and only matching
+    /// records are fully materialized. Values spilled out of line are loaded
+    /// through `overflow` and encrypted ones decrypted through `opener`, only
+    /// when needed. Records that fail to decode,
+    /// belong to another collection or are soft-deleted are skipped.
+    pub fn filter_records(
+        &self,
+        records: Vec<VersionedRecord>,
+        query: &NVQuery,
+        overflow: OverflowResolver,
+        opener: FieldOpener,
+    ) -> NVResult<Vec<NVDocument>> {
+        self.filter_records_cancellable(records, query, overflow, opener, &CancellationToken::new(), None)
+    }
+
+    /// Like `filter_records`, giving up with `Cancelled` once `cancel` trips
+    ///
+    /// `tally`, if given, counts the collection's records and how many
+    /// satisfy each condition, for the index advisor.
+    pub fn filter_records_cancellable(
+        &self,
+        records: Vec<VersionedRecord>,
+        query: &NVQuery,
+        overflow: OverflowResolver,
+        opener: FieldOpener,
+        cancel: &CancellationToken,
+        tally: Option<&ScanTally>,
+    ) -> NVResult<Vec<NVDocument>> {
+        if records.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let decode_and_match = |record: VersionedRecord| -> Option<NVDocument> {
+            // Skip the rest once cancelled; the error is raised below
+            if cancel.is_cancelled() {
+                return None;
+            }
+            self.match_record(&record, query, overflow, opener, tally)
+        };
+
+        let results: Vec<NVDocument> = match self.pool_for(records.len()) {
+            Some(pool) => pool.install(|| records.into_par_iter().filter_map(decode_and_match).collect()),
+            None => records.into_iter().filter_map(decode_and_match).collect(),
+        };
+
+        cancel.check()?;
+        self.finish(results, query)
+    }
+
+    /// Decode a raw record if it belongs to the query's collection and
+    /// matches its conditions
+    pub(crate) fn match_record(
+        &self,
+        record: &VersionedRecord,
+        query: &NVQuery,
+        overflow: OverflowResolver,
+        opener: FieldOpener,
+        tally: Option<&ScanTally>,
+    ) -> Option<NVDocument> {
+        let view = record.view().ok()?.with_overflow(overflow).with_opener(opener);
+        if view.collection() != query.collection || view.deleted() {
+            return None;
+        }
+        if let Some(tally) = tally {
+            tally.scanned();
+        }
+        let lookup = |field: &str| {
+            view.meta()
+                .field(field)
+                .or_else(|| view.field(field).ok().flatten())
+                .map(Cow::Owned)
+        };
+        if self.matches_counting(query, lookup, tally) {
+            view.materialize().ok()
+        } else {
+            None
+        }
+    }
+
+    /// Apply ordering, skip and limit to filtered results
+    pub(crate) fn finish(&self, mut results: Vec<NVDocument>, query: &NVQuery) -> NVResult<Vec<NVDocument>> {
         // Apply ordering
-        if let Some(order_field ) = &query.order_by {
+        if let Some(order_field) = &query.order_by {
             self.sort_documents(&mut results, order_field, query.order_desc);
         }
 
@@ -38,14 +171,74 @@ impl QueryProcessor {
         Ok(results)
     }
 
+    /// Conditions that every matching document must satisfy
+    ///
+    /// Conditions are folded left to right, so a condition is required when
+    /// it and everything after it are joined with AND. Conditions past the
+    /// last logical operator are never evaluated and are not returned.
+    pub fn required_conditions<'a>(&self, query: &'a NVQuery) -> Vec<&'a QueryCondition> {
+        let evaluated = query.conditions.len().min(query.logical_operators.len() + 1);
+
+        // Index of the first condition after the last OR
+        let first_required = query
+            .logical_operators
+            .iter()
+            .take(evaluated.saturating_sub(1))
+            .rposition(|op| matches!(op, LogicalOperator::Or))
+            .map_or(0, |i| i + 2);
+
+        query.conditions[..evaluated]
+            .iter()
+            .skip(first_required)
+            .collect()
+    }
+
+    /// Check if a document matches a query's conditions
+    pub fn matches(&self, document: &NVDocument, query: &NVQuery) -> bool {
+        self.matches_query(document, query)
+    }
+
+    /// Check if a document satisfies a single condition
+    pub fn matches_condition(&self, document: &NVDocument, condition: &QueryCondition) -> bool {
+        self.evaluate_condition(&|field: &str| document.field(field), condition)
+    }
+
+    /// Check if a row of field values matches all query conditions
+    pub fn matches_row(&self, row: &HashMap<String, NVValue>, query: &NVQuery) -> bool {
+        self.matches_with(query, |field| row.get(field).map(Cow::Borrowed))
+    }
+
     /// Check if a document matches all query conditions
     fn matches_query(&self, document: &NVDocument, query: &NVQuery) -> bool {
+        self.matches_with(query, |field| document.field(field))
+    }
+
+    /// Evaluate query conditions using `lookup` to resolve field values
+    fn matches_with<'v, F>(&self, query: &NVQuery, lookup: F) -> bool
+    where
+        F: Fn(&str) -> Option<Cow<'v, NVValue>>,
+    {
+        self.matches_counting(query, lookup, None)
+    }
+
+    /// `matches_with`, counting each condition that holds in `tally`
+    fn matches_counting<'v, F>(&self, query: &NVQuery, lookup: F, tally: Option<&ScanTally>) -> bool
+    where
+        F: Fn(&str) -> Option<Cow<'v, NVValue>>,
+    {
         if query.conditions.is_empty() {
             return true;
         }
+        let evaluate = |index: usize| {
+            let holds = self.evaluate_condition(&lookup, &query.conditions[index]);
+            if let (true, Some(tally)) = (holds, tally) {
+                tally.matched(index);
+            }
+            holds
+        };
 
         // Start with the first condition
-        let mut result = self.evaluate_condition(document, &query.conditions[0]);
+        let mut result = evaluate(0);
 
         // Apply logical operators
         for (i, logical_op) in query.logical_operators.iter().enumerate() {
@@ -54,7 +247,7 @@ impl QueryProcessor {
                 break;
             }
 
-            let next_result = self.evaluate_condition(document, &query.conditions[next_condition_idx]);
+            let next_result = evaluate(next_condition_idx);
 
             result = match logical_op {
                 LogicalOperator::And => result && next_result,
@@ -66,13 +259,16 @@ impl QueryProcessor {
     }
 
     /// Evaluate a single condition
-    fn evaluate_condition(&self, document: &NVDocument, condition: &QueryCondition) -> bool {
-        let field_value = match document.get(&condition.field) {
+    fn evaluate_condition<'v, F>(&self, lookup: &F, condition: &QueryCondition) -> bool
+    where
+        F: Fn(&str) -> Option<Cow<'v, NVValue>>,
+    {
+        let field_value = match lookup(&condition.field) {
             Some(v) => v,
             None => return false,
         };
 
-        self.compare_values(field_value, &condition.value, &condition.operator)
+        self.compare_values(&field_value, &condition.value, &condition.operator)
     }
 
     /// Compare two values based on the operator
@@ -89,6 +285,12 @@ impl QueryProcessor {
             QueryOperator::EndsWith => self.string_ends_with(left, right),
             QueryOperator::In => self.value_in_array(left, right),
             QueryOperator::NotIn => !self.value_in_array(left, right),
+            QueryOperator::Fuzzy { max_distance } => match (left, right) {
+                (NVValue::String(text), NVValue::String(term)) => {
+                    fuzzy::matches(text, term, *max_distance as usize)
+                }
+                _ => false,
+            },
         }
     }
 
@@ -148,27 +350,35 @@ impl QueryProcessor {
 
     /// Sort documents by field
     fn sort_documents(&self, documents: &mut [NVDocument], field: &str, descending: bool) {
-        documents.sort_by(|a, b| {
-            let a_val = a.get(field);
-            let b_val = b.get(field);
-
-            let ordering = match (a_val, b_val) {
-                (Some(NVValue::Number(a)), Some(NVValue::Number(b))) => {
-                    a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal)
-                }
-                (Some(NVValue::String(a)), Some(NVValue::String(b))) => a.cmp(b),
-                (Some(NVValue::Bool(a)), Some(NVValue::Bool(b))) => a.cmp(b),
-                (Some(_), None) => std::cmp::Ordering::Less,
-                (None, Some(_)) => std::cmp::Ordering::Greater,
-                _ => std::cmp::Ordering::Equal,
-            };
-
+        // Ties go by ID so every plan returns them in the same order
+        let compare = |a: &NVDocument, b: &NVDocument| {
+            let ordering = Self::compare_field(a.field(field).as_deref(), b.field(field).as_deref())
+                .then_with(|| a.id.cmp(&b.id));
             if descending {
                 ordering.reverse()
             } else {
                 ordering
             }
-        });
+        };
+
+        match self.pool_for(documents.len()) {
+            Some(pool) => pool.install(|| documents.par_sort_by(compare)),
+            None => documents.sort_by(compare),
+        }
+    }
+
+    /// Ordering between two field values (missing values sort last)
+    pub(crate) fn compare_field(a: Option<&NVValue>, b: Option<&NVValue>) -> Ordering {
+        match (a, b) {
+            (Some(NVValue::Number(a)), Some(NVValue::Number(b))) => {
+                a.partial_cmp(b).unwrap_or(Ordering::Equal)
+            }
+            (Some(NVValue::String(a)), Some(NVValue::String(b))) => a.cmp(b),
+            (Some(NVValue::Bool(a)), Some(NVValue::Bool(b))) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            _ => Ordering::Equal,
+        }
     }
 }
 
@@ -181,6 +391,7 @@ impl Default for QueryProcessor {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::{DateTime, Utc};
     use std::collections::HashMap;
 
     #[test]
@@ -212,4 +423,78 @@ mod tests {
         
         assert!(processor.compare_values(&left, &right, &QueryOperator::Contains));
     }
+
+    #[test]
+    fn test_parallel_filter_matches_sequential() {
+        let sequential = QueryProcessor::new();
+        let parallel = QueryProcessor::with_parallelism(4, 1).unwrap();
+
+        let documents: Vec<NVDocument> = (0..200)
+            .map(|i| {
+                let mut data = HashMap::new();
+                data.insert("n".to_string(), NVValue::Number(i as f64));
+                NVDocument::new(format!("doc{}", i), "items".to_string(), data)
+            })
+            .collect();
+
+        let mut query = NVQuery::new("items".to_string());
+        query.add_condition(
+            "n".to_string(),
+            QueryOperator::GreaterThanOrEqual,
+            NVValue::Number(150.0),
+            None,
+        );
+        query.order_by = Some("n".to_string());
+        query.order_desc = true;
+        query.limit = Some(10);
+
+        let expected = sequential.filter(documents.clone(), &query).unwrap();
+        let actual = parallel.filter(documents, &query).unwrap();
+
+        let ids = |docs: &[NVDocument]| docs.iter().map(|d| d.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&actual), ids(&expected));
+        assert_eq!(actual[0].id, "doc199");
+    }
+
+    #[test]
+    fn test_timestamp_pseudo_fields() {
+        let processor = QueryProcessor::new();
+        let documents: Vec<NVDocument> = (0..3)
+            .map(|i| {
+                let mut document = NVDocument::new(format!("doc{}", i), "notes".to_string(), HashMap::new());
+                document.updated_at = DateTime::<Utc>::UNIX_EPOCH + chrono::Duration::seconds(i);
+                document
+            })
+            .collect();
+
+        let mut query = NVQuery::new("notes".to_string());
+        query.add_condition(
+            crate::models::UPDATED_AT_FIELD.to_string(),
+            QueryOperator::GreaterThanOrEqual,
+            NVValue::Number(1000.0),
+            None,
+        );
+        query.order_by = Some(crate::models::UPDATED_AT_FIELD.to_string());
+        query.order_desc = true;
+
+        let ids: Vec<String> = processor.filter(documents, &query).unwrap().into_iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec!["doc2", "doc1"]);
+    }
+
+    #[test]
+    fn test_required_conditions() {
+        let processor = QueryProcessor::new();
+        let mut query = NVQuery::new("users".to_string());
+        for (field, op) in [("a", None), ("b", Some(LogicalOperator::Or)), ("c", Some(LogicalOperator::And))] {
+            query.add_condition(field.to_string(), QueryOperator::Equals, NVValue::Null, op);
+        }
+
+        // (a OR b) AND c: only c must hold for every match
+        let required: Vec<&str> = processor
+            .required_conditions(&query)
+            .iter()
+            .map(|c| c.field.as_str())
+            .collect();
+        assert_eq!(required, vec!["c"]);
+    }
 }
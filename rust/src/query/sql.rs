@@ -0,0 +1,442 @@
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::{LogicalOperator, NVQuery, NVValue, QueryOperator};
+
+/// Parse a SQL `SELECT` into a query
+///
+/// Supports
+/// `SELECT * | field, ... FROM collection [WHERE condition {AND | OR}
+/// condition ...] [ORDER BY field [ASC | DESC]] [LIMIT n] [OFFSET n]`,
+/// where a condition is `field op value` with op one of `= != <> > >= <
+/// <=`, `field [NOT] IN (value, ...)` or `field LIKE 'pattern'`. LIKE
+/// patterns may only have `%` at the start, the end or both. IN also takes
+/// a subquery selecting one field, `field IN (SELECT field FROM ...)`,
+/// where selecting `id` collects document IDs. Values are
+/// numbers, 'strings' (`''` for a quote), TRUE, FALSE and NULL. Keywords
+/// are case-insensitive; identifiers can be quoted with `"` or backticks.
+/// There are no parentheses, so a WHERE clause joins all its conditions
+/// with AND or all with OR; mixing the two is rejected rather than read
+/// with a precedence SQL users wouldn't expect.
+pub fn parse_sql(sql: &str) -> NVResult<NVQuery> {
+    let tokens = tokenize(sql)?;
+    let mut parser = Parser { tokens, position: 0 };
+    let query = parser.select()?;
+    match parser.tokens.get(parser.position) {
+        None => Ok(query),
+        Some(token) => Err(invalid(format!("Unexpected {}", describe(token)))),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// A bare word: a keyword or an unquoted identifier
+    Word(String),
+    /// A quoted identifier
+    Identifier(String),
+    String(String),
+    Number(f64),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 12] = ["<>", "!=", ">=", "<=", "==", "=", ">", "<", "*", ",", "(", ")"];
+
+fn tokenize(sql: &str) -> NVResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = sql.trim_end_matches(|c: char| c == ';' || c.is_whitespace());
+
+    loop {
+        rest = rest.trim_start();
+        let Some(c) = rest.chars().next() else { break };
+
+        if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else if c == '\'' {
+            let (text, after) = quoted(rest, '\'')?;
+            tokens.push(Token::String(text));
+            rest = after;
+        } else if c == '"' || c == '`' {
+            let (text, after) = quoted(rest, c)?;
+            tokens.push(Token::Identifier(text));
+            rest = after;
+        } else if c.is_ascii_digit() || c == '-' || c == '.' {
+            let end = rest[1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '+' || c == '-'))
+                .map_or(rest.len(), |i| i + 1);
+            let number = rest[..end]
+                .parse()
+                .map_err(|_| invalid(format!("Invalid number '{}'", &rest[..end])))?;
+            tokens.push(Token::Number(number));
+            rest = &rest[end..];
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            return Err(invalid(format!("Unexpected character '{}'", c)));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Split a string quoted with `quote` (doubled to escape it) off `input`
+fn quoted(input: &str, quote: char) -> NVResult<(String, &str)> {
+    let mut text = String::new();
+    let mut chars = input.char_indices().skip(1).peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != quote {
+            text.push(c);
+        } else if chars.peek().is_some_and(|(_, next)| *next == quote) {
+            text.push(quote);
+            chars.next();
+        } else {
+            return Ok((text, &input[i + 1..]));
+        }
+    }
+    Err(invalid(format!("Unterminated {}", if quote == '\'' { "string" } else { "identifier" })))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn select(&mut self) -> NVResult<NVQuery> {
+        self.expect_keyword("SELECT")?;
+        let projection = if self.accept_symbol("*") {
+            None
+        } else {
+            let mut fields = vec![self.identifier()?];
+            while self.accept_symbol(",") {
+                fields.push(self.identifier()?);
+            }
+            Some(fields)
+        };
+
+        self.expect_keyword("FROM")?;
+        let mut query = NVQuery::new(self.identifier()?);
+        query.projection = projection;
+
+        if self.accept_keyword("WHERE") {
+            self.condition(&mut query, None)?;
+            loop {
+                let logical = if self.accept_keyword("AND") {
+                    LogicalOperator::And
+                } else if self.accept_keyword("OR") {
+                    LogicalOperator::Or
+                } else {
+                    break;
+                };
+                if query.logical_operators.first().is_some_and(|first| *first != logical) {
+                    return Err(invalid(
+                        "AND and OR can't be mixed in one WHERE clause; there are no parentheses to group them"
+                            .to_string(),
+                    ));
+                }
+                self.condition(&mut query, Some(logical))?;
+            }
+        }
+
+        if self.accept_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            query.order_by = Some(self.identifier()?);
+            if self.accept_keyword("DESC") {
+                query.order_desc = true;
+            } else {
+                self.accept_keyword("ASC");
+            }
+        }
+        if self.accept_keyword("LIMIT") {
+            query.limit = Some(self.count()?);
+        }
+        if self.accept_keyword("OFFSET") {
+            query.skip = Some(self.count()?);
+        }
+        Ok(query)
+    }
+
+    fn condition(&mut self, query: &mut NVQuery, logical: Option<LogicalOperator>) -> NVResult<()> {
+        let field = self.identifier()?;
+
+        if self.accept_keyword("NOT") {
+            self.expect_keyword("IN")?;
+            return self.in_condition(query, field, QueryOperator::NotIn, logical);
+        }
+        if self.accept_keyword("IN") {
+            return self.in_condition(query, field, QueryOperator::In, logical);
+        }
+
+        let (operator, value) = if self.accept_keyword("LIKE") {
+            like(self.string()?)?
+        } else {
+            let operator = match self.next() {
+                Some(Token::Symbol("=" | "==")) => QueryOperator::Equals,
+                Some(Token::Symbol("!=" | "<>")) => QueryOperator::NotEquals,
+                Some(Token::Symbol(">")) => QueryOperator::GreaterThan,
+                Some(Token::Symbol(">=")) => QueryOperator::GreaterThanOrEqual,
+                Some(Token::Symbol("<")) => QueryOperator::LessThan,
+                Some(Token::Symbol("<=")) => QueryOperator::LessThanOrEqual,
+                other => return Err(expected("a comparison operator", other.as_ref())),
+            };
+            (operator, self.value()?)
+        };
+
+        query.add_condition(field, operator, value, logical);
+        Ok(())
+    }
+
+    /// The rest of an IN or NOT IN condition: a list or a subquery
+    fn in_condition(
+        &mut self,
+        query: &mut NVQuery,
+        field: String,
+        operator: QueryOperator,
+        logical: Option<LogicalOperator>,
+    ) -> NVResult<()> {
+        self.expect_symbol("(")?;
+        if !matches!(self.tokens.get(self.position), Some(Token::Word(word)) if word.eq_ignore_ascii_case("SELECT")) {
+            let values = self.list()?;
+            query.add_condition(field, operator, values, logical);
+            return Ok(());
+        }
+
+        let mut subquery = self.select()?;
+        self.expect_symbol(")")?;
+        let select = match subquery.projection.take().as_deref() {
+            Some([selected]) if selected == "id" => None,
+            Some([selected]) => Some(selected.clone()),
+            _ => return Err(invalid("A subquery must select exactly one field".to_string())),
+        };
+        query.add_subquery(field, operator, select, subquery, logical);
+        Ok(())
+    }
+
+    /// A comma-separated list of values, up to its closing parenthesis
+    fn list(&mut self) -> NVResult<NVValue> {
+        let mut values = vec![self.value()?];
+        while self.accept_symbol(",") {
+            values.push(self.value()?);
+        }
+        self.expect_symbol(")")?;
+        Ok(NVValue::Array(values))
+    }
+
+    fn value(&mut self) -> NVResult<NVValue> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(NVValue::Number(n)),
+            Some(Token::String(s)) => Ok(NVValue::String(s)),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("TRUE") => Ok(NVValue::Bool(true)),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("FALSE") => Ok(NVValue::Bool(false)),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("NULL") => Ok(NVValue::Null),
+            other => Err(expected("a value", other.as_ref())),
+        }
+    }
+
+    fn string(&mut self) -> NVResult<String> {
+        match self.next() {
+            Some(Token::String(s)) => Ok(s),
+            other => Err(expected("a string", other.as_ref())),
+        }
+    }
+
+    fn identifier(&mut self) -> NVResult<String> {
+        match self.next() {
+            Some(Token::Identifier(name)) => Ok(name),
+            Some(Token::Word(word)) if !is_keyword(&word) => Ok(word),
+            other => Err(expected("a name", other.as_ref())),
+        }
+    }
+
+    fn count(&mut self) -> NVResult<usize> {
+        match self.next() {
+            Some(Token::Number(n)) if n >= 0.0 && n.fract() == 0.0 => Ok(n as usize),
+            other => Err(expected("a non-negative integer", other.as_ref())),
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        let matched = matches!(self.tokens.get(self.position), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword));
+        if matched {
+            self.position += 1;
+        }
+        matched
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> NVResult<()> {
+        if self.accept_keyword(keyword) {
+            return Ok(());
+        }
+        Err(expected(keyword, self.tokens.get(self.position)))
+    }
+
+    fn accept_symbol(&mut self, symbol: &'static str) -> bool {
+        let matched = self.tokens.get(self.position) == Some(&Token::Symbol(symbol));
+        if matched {
+            self.position += 1;
+        }
+        matched
+    }
+
+    fn expect_symbol(&mut self, symbol: &'static str) -> NVResult<()> {
+        if self.accept_symbol(symbol) {
+            return Ok(());
+        }
+        Err(expected(&format!("'{}'", symbol), self.tokens.get(self.position)))
+    }
+}
+
+/// The operator and value for a LIKE pattern
+fn like(pattern: String) -> NVResult<(QueryOperator, NVValue)> {
+    let starts = pattern.starts_with('%');
+    let ends = pattern.len() > 1 && pattern.ends_with('%');
+    let inner = pattern[usize::from(starts)..pattern.len() - usize::from(ends)].to_string();
+    if inner.contains('%') || inner.contains('_') {
+        return Err(invalid(format!("Unsupported LIKE pattern '{}'", pattern)));
+    }
+
+    let operator = match (starts, ends) {
+        (true, true) => QueryOperator::Contains,
+        (false, true) => QueryOperator::StartsWith,
+        (true, false) => QueryOperator::EndsWith,
+        (false, false) => QueryOperator::Equals,
+    };
+    Ok((operator, NVValue::String(inner)))
+}
+
+const KEYWORDS: [&str; 16] = [
+    "SELECT", "FROM", "WHERE", "AND", "OR", "NOT", "IN", "LIKE", "ORDER", "BY", "ASC", "DESC", "LIMIT", "OFFSET",
+    "TRUE", "FALSE",
+];
+
+fn is_keyword(word: &str) -> bool {
+    word.eq_ignore_ascii_case("NULL") || KEYWORDS.iter().any(|keyword| word.eq_ignore_ascii_case(keyword))
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Word(word) => format!("'{}'", word),
+        Token::Identifier(name) => format!("\"{}\"", name),
+        Token::String(s) => format!("string '{}'", s),
+        Token::Number(n) => format!("number {}", n),
+        Token::Symbol(symbol) => format!("'{}'", symbol),
+    }
+}
+
+fn expected(what: &str, found: Option<&Token>) -> NeuralVaultError {
+    match found {
+        Some(token) => invalid(format!("Expected {}, found {}", what, describe(token))),
+        None => invalid(format!("Expected {} at end of query", what)),
+    }
+}
+
+fn invalid(message: String) -> NeuralVaultError {
+    NeuralVaultError::InvalidQuery(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_full_select() {
+        let query = parse_sql(
+            "select name, \"first name\" FROM users WHERE age > 22 and team IN ('core', 'infra') \
+             AND title LIKE 'Dr%' AND active = TRUE ORDER BY name DESC LIMIT 10 OFFSET 5;",
+        )
+        .unwrap();
+
+        assert_eq!(query.collection, "users");
+        assert_eq!(query.projection, Some(vec!["name".to_string(), "first name".to_string()]));
+        let conditions: Vec<(&str, QueryOperator, &NVValue)> = query
+            .conditions
+            .iter()
+            .map(|c| (c.field.as_str(), c.operator.clone(), &c.value))
+            .collect();
+        assert_eq!(conditions, vec![
+            ("age", QueryOperator::GreaterThan, &NVValue::Number(22.0)),
+            ("team", QueryOperator::In, &NVValue::Array(vec![
+                NVValue::String("core".to_string()),
+                NVValue::String("infra".to_string()),
+            ])),
+            ("title", QueryOperator::StartsWith, &NVValue::String("Dr".to_string())),
+            ("active", QueryOperator::Equals, &NVValue::Bool(true)),
+        ]);
+        assert!(matches!(
+            query.logical_operators.as_slice(),
+            [LogicalOperator::And, LogicalOperator::And, LogicalOperator::And]
+        ));
+        assert_eq!(query.order_by.as_deref(), Some("name"));
+        assert!(query.order_desc);
+        assert_eq!(query.limit, Some(10));
+        assert_eq!(query.skip, Some(5));
+    }
+
+    #[test]
+    fn test_parse_values_and_like_patterns() {
+        let query = parse_sql(
+            "SELECT * FROM notes WHERE body LIKE '%it''s%' AND score <> -1.5 AND tag NOT IN (NULL) AND name LIKE '%.md'",
+        )
+        .unwrap();
+        assert_eq!(query.projection, None);
+        assert_eq!(query.conditions[0].operator, QueryOperator::Contains);
+        assert_eq!(query.conditions[0].value, NVValue::String("it's".to_string()));
+        assert_eq!(query.conditions[1].operator, QueryOperator::NotEquals);
+        assert_eq!(query.conditions[1].value, NVValue::Number(-1.5));
+        assert_eq!(query.conditions[2].operator, QueryOperator::NotIn);
+        assert_eq!(query.conditions[3].operator, QueryOperator::EndsWith);
+    }
+
+    #[test]
+    fn test_parse_subquery() {
+        let query = parse_sql(
+            "SELECT * FROM orders WHERE total > 10 AND user_id IN (SELECT id FROM users WHERE plan == 'pro') \
+             AND region NOT IN (SELECT name FROM regions WHERE closed = TRUE)",
+        )
+        .unwrap();
+
+        assert_eq!(query.conditions.len(), 3);
+        assert_eq!(query.subqueries.len(), 2);
+        assert_eq!(query.subqueries[0].condition, 1);
+        assert_eq!(query.subqueries[0].select, None);
+        assert_eq!(query.subqueries[0].query.collection, "users");
+        assert_eq!(query.subqueries[0].query.conditions[0].value, NVValue::String("pro".to_string()));
+        assert_eq!(query.subqueries[1].condition, 2);
+        assert_eq!(query.subqueries[1].select.as_deref(), Some("name"));
+        assert_eq!(query.conditions[2].operator, QueryOperator::NotIn);
+
+        assert!(parse_sql("SELECT * FROM orders WHERE user_id IN (SELECT * FROM users)").is_err());
+    }
+
+    #[test]
+    fn test_parse_or_chain() {
+        let query = parse_sql("SELECT * FROM users WHERE a = 1 OR b = 2 OR c = 3").unwrap();
+        assert_eq!(query.conditions.len(), 3);
+        assert!(matches!(query.logical_operators.as_slice(), [LogicalOperator::Or, LogicalOperator::Or]));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for sql in [
+            "SELECT FROM users",
+            "SELECT * users",
+            "SELECT * FROM users WHERE age >",
+            "SELECT * FROM users WHERE name = 'open",
+            "SELECT * FROM users LIMIT -1",
+            "SELECT * FROM users WHERE name LIKE 'a%b'",
+            "SELECT * FROM users extra",
+            "DELETE FROM users",
+            "SELECT * FROM users WHERE a = 1 OR b = 2 AND c = 3",
+            "SELECT * FROM users WHERE a = 1 AND b = 2 OR c = 3",
+        ] {
+            assert!(matches!(parse_sql(sql), Err(NeuralVaultError::InvalidQuery(_))), "{}", sql);
+        }
+    }
+}
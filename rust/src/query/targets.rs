@@ -0,0 +1,78 @@
+/// Separates the collections of a cross-collection query
+pub const SEPARATOR: char = ',';
+
+/// Matches any run of characters in a collection pattern
+pub const WILDCARD: char = '*';
+
+/// Whether a query's collection names several collections or a pattern
+///
+/// Neither character is allowed in collection names, so a plain name is
+/// never mistaken for a cross-collection target.
+pub fn is_multi(target: &str) -> bool {
+    target.contains([SEPARATOR, WILDCARD])
+}
+
+/// Whether a target needs the list of existing collections to resolve
+pub fn has_pattern(target: &str) -> bool {
+    target.contains(WILDCARD)
+}
+
+/// The collections a target names, in the order given and without
+/// duplicates; patterns expand to the matching `existing` collections
+pub fn resolve(target: &str, existing: &[String]) -> Vec<String> {
+    let mut collections: Vec<String> = Vec::new();
+    for part in target.split(SEPARATOR).map(str::trim).filter(|part| !part.is_empty()) {
+        let matched: Vec<String> = if has_pattern(part) {
+            existing.iter().filter(|name| matches(part, name)).cloned().collect()
+        } else {
+            vec![part.to_string()]
+        };
+        for collection in matched {
+            if !collections.contains(&collection) {
+                collections.push(collection);
+            }
+        }
+    }
+    collections
+}
+
+/// Whether `name` matches a pattern where `*` stands for any characters
+fn matches(pattern: &str, name: &str) -> bool {
+    let mut pieces = pattern.split(WILDCARD);
+    let first = pieces.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut pieces: Vec<&str> = pieces.collect();
+    let last = pieces.pop().unwrap_or_default();
+    for piece in pieces {
+        match rest.find(piece) {
+            Some(at) => rest = &rest[at + piece.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_lists_and_patterns() {
+        let existing: Vec<String> = ["events", "notes", "acme__notes", "acme__tasks"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+
+        assert!(!is_multi("notes"));
+        assert!(is_multi("notes,tasks") && is_multi("acme__*"));
+
+        assert_eq!(resolve("tasks, notes,tasks", &existing), vec!["tasks", "notes"]);
+        assert_eq!(resolve("acme__*", &existing), vec!["acme__notes", "acme__tasks"]);
+        assert_eq!(resolve("*notes", &existing), vec!["notes", "acme__notes"]);
+        assert_eq!(resolve("a*_*s", &existing), vec!["acme__notes", "acme__tasks"]);
+        assert_eq!(resolve("*", &existing).len(), 4);
+        assert!(resolve("x*", &existing).is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::{NVQuery, NVValue};
+use std::collections::HashMap;
+
+/// A query with named placeholders, bound to values for each run
+///
+/// A condition whose value is a string of the form `:name` (a letter or
+/// underscore, then letters, digits or underscores) takes the parameter
+/// `name`. Strings of that form can't be used literally in a template;
+/// bind them as parameters instead.
+#[derive(Debug, Clone)]
+pub struct QueryTemplate {
+    query: NVQuery,
+    /// Condition index and parameter name of each placeholder
+    placeholders: Vec<(usize, String)>,
+}
+
+impl QueryTemplate {
+    pub fn new(query: NVQuery) -> Self {
+        let placeholders = query
+            .conditions
+            .iter()
+            .enumerate()
+            .filter_map(|(i, condition)| match &condition.value {
+                NVValue::String(value) => placeholder(value).map(|name| (i, name.to_string())),
+                _ => None,
+            })
+            .collect();
+        Self { query, placeholders }
+    }
+
+    /// The query with placeholders as written
+    pub fn query(&self) -> &NVQuery {
+        &self.query
+    }
+
+    /// Names of the parameters the template takes, sorted
+    pub fn parameters(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.placeholders.iter().map(|(_, name)| name.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// The query with every placeholder replaced by its parameter
+    ///
+    /// Fails if a parameter is missing or `params` names one the template
+    /// doesn't take.
+    pub fn bind(&self, params: &HashMap<String, NVValue>) -> NVResult<NVQuery> {
+        if let Some(unknown) = params.keys().find(|name| !self.placeholders.iter().any(|(_, p)| p == *name)) {
+            return Err(NeuralVaultError::InvalidQuery(format!("Unknown parameter ':{}'", unknown)));
+        }
+
+        let mut query = self.query.clone();
+        for (i, name) in &self.placeholders {
+            let value = params
+                .get(name)
+                .ok_or_else(|| NeuralVaultError::InvalidQuery(format!("Missing parameter ':{}'", name)))?;
+            query.conditions[*i].value = value.clone();
+        }
+        Ok(query)
+    }
+}
+
+/// The parameter name in `value`, if it is a placeholder
+fn placeholder(value: &str) -> Option<&str> {
+    let name = value.strip_prefix(':')?;
+    let mut chars = name.chars();
+    let first = chars.next()?;
+    ((first.is_ascii_alphabetic() || first == '_') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_'))
+        .then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{LogicalOperator, QueryOperator};
+
+    fn template() -> QueryTemplate {
+        let mut query = NVQuery::new("users".to_string());
+        query.add_condition("age".to_string(), QueryOperator::GreaterThan, NVValue::String(":min_age".to_string()), None);
+        query.add_condition(
+            "team".to_string(),
+            QueryOperator::Equals,
+            NVValue::String(":team".to_string()),
+            Some(LogicalOperator::And),
+        );
+        query.add_condition(
+            "note".to_string(),
+            QueryOperator::NotEquals,
+            NVValue::String(":-)".to_string()),
+            Some(LogicalOperator::And),
+        );
+        QueryTemplate::new(query)
+    }
+
+    #[test]
+    fn test_bind_replaces_placeholders() {
+        let template = template();
+        assert_eq!(template.parameters(), vec!["min_age", "team"]);
+
+        let mut params = HashMap::new();
+        params.insert("min_age".to_string(), NVValue::Number(22.0));
+        params.insert("team".to_string(), NVValue::String("core".to_string()));
+        let query = template.bind(&params).unwrap();
+        assert_eq!(query.conditions[0].value, NVValue::Number(22.0));
+        assert_eq!(query.conditions[1].value, NVValue::String("core".to_string()));
+        // Not a placeholder, so left as written
+        assert_eq!(query.conditions[2].value, NVValue::String(":-)".to_string()));
+    }
+
+    #[test]
+    fn test_bind_rejects_missing_and_unknown_parameters() {
+        let template = template();
+        let mut params = HashMap::new();
+        params.insert("min_age".to_string(), NVValue::Number(22.0));
+        assert!(template.bind(&params).is_err());
+
+        params.insert("team".to_string(), NVValue::Null);
+        params.insert("max_age".to_string(), NVValue::Number(65.0));
+        assert!(template.bind(&params).is_err());
+    }
+}
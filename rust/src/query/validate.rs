@@ -0,0 +1,153 @@
+use crate::models::{self, NVQuery, NVValue, QueryOperator};
+use crate::schema::{CollectionSchema, FieldDefault};
+use serde::Serialize;
+use std::fmt;
+
+/// Something about a query that parses but likely doesn't do what was meant
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueryWarning {
+    /// Index of the condition in `conditions`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub condition: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for QueryWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(index) = self.condition {
+            write!(f, "Condition {}: ", index)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Check a query's conditions against what their operators can match and
+/// against the schemas `schema_of` returns for its collections
+///
+/// Schemas only declare some fields, so fields they don't mention aren't
+/// reported; declared fields are checked against the type of their default.
+pub fn check(query: &NVQuery, schema_of: &dyn Fn(&str) -> Option<CollectionSchema>) -> Vec<QueryWarning> {
+    let schema = schema_of(&query.collection);
+    let mut warnings = Vec::new();
+
+    for (i, condition) in query.conditions.iter().enumerate() {
+        let mut warn = |message: String| {
+            warnings.push(QueryWarning {
+                condition: Some(i),
+                field: Some(condition.field.clone()),
+                message,
+            })
+        };
+        let value = &condition.value;
+        let operator = operator_name(&condition.operator);
+
+        match &condition.operator {
+            QueryOperator::GreaterThan
+            | QueryOperator::GreaterThanOrEqual
+            | QueryOperator::LessThan
+            | QueryOperator::LessThanOrEqual
+                if !matches!(value, NVValue::Number(_)) =>
+            {
+                warn(format!("'{}' only matches numbers, but the value is {}; it never matches", operator, kind(value)))
+            }
+            QueryOperator::Contains
+            | QueryOperator::StartsWith
+            | QueryOperator::EndsWith
+            | QueryOperator::Fuzzy { .. }
+                if !matches!(value, NVValue::String(_)) =>
+            {
+                warn(format!("'{}' only matches strings, but the value is {}; it never matches", operator, kind(value)))
+            }
+            QueryOperator::In | QueryOperator::NotIn if !matches!(value, NVValue::Array(_)) => warn(format!(
+                "'{}' expects an array of values, but the value is {}; it {}",
+                operator,
+                kind(value),
+                if condition.operator == QueryOperator::In { "never matches" } else { "matches everything" }
+            )),
+            _ => {}
+        }
+
+        let Some(expected) = declared_kind(&condition.field, schema.as_ref()) else {
+            continue;
+        };
+        let compared: Vec<&NVValue> = match (&condition.operator, value) {
+            (QueryOperator::In | QueryOperator::NotIn, NVValue::Array(items)) => items.iter().collect(),
+            _ => vec![value],
+        };
+        if let Some(value) = compared.into_iter().find(|value| !value.is_null() && kind(value) != expected) {
+            warn(format!("'{}' holds {}, but is compared with {}", condition.field, expected, kind(value)));
+        }
+    }
+
+    if let Some(schema) = schema.as_ref().filter(|_| !query.include_sensitive) {
+        let returned = query.projection.iter().flatten().chain(&query.order_by);
+        for field in returned {
+            if schema.fields.get(field).is_some_and(|field| field.sensitive) {
+                warnings.push(QueryWarning {
+                    condition: None,
+                    field: Some(field.clone()),
+                    message: format!("'{}' is sensitive and left out of results unless include_sensitive is set", field),
+                });
+            }
+        }
+    }
+
+    for subquery in &query.subqueries {
+        warnings.extend(check(&subquery.query, schema_of).into_iter().map(|warning| QueryWarning {
+            condition: Some(subquery.condition),
+            message: format!("In subquery: {}", warning),
+            ..warning
+        }));
+    }
+
+    warnings
+}
+
+/// The type a field is known to hold: pseudo-fields always, data fields
+/// when the schema gives them a default
+fn declared_kind(field: &str, schema: Option<&CollectionSchema>) -> Option<&'static str> {
+    match field {
+        models::ID_FIELD | models::COLLECTION_FIELD => return Some("a string"),
+        models::DELETED_FIELD => return Some("a boolean"),
+        models::VERSION_FIELD => return Some("a number"),
+        _ if models::is_timestamp_field(field) => return Some("a number"),
+        _ => {}
+    }
+    match schema?.fields.get(field)?.default.as_ref()? {
+        FieldDefault::Value(NVValue::Null) => None,
+        FieldDefault::Value(value) => Some(kind(value)),
+        FieldDefault::Now | FieldDefault::Uuid => Some("a string"),
+        FieldDefault::Sequence => Some("a number"),
+    }
+}
+
+fn kind(value: &NVValue) -> &'static str {
+    match value {
+        NVValue::Null => "null",
+        NVValue::Bool(_) => "a boolean",
+        NVValue::Number(_) => "a number",
+        NVValue::String(_) => "a string",
+        NVValue::Array(_) => "an array",
+        NVValue::Object(_) => "an object",
+    }
+}
+
+/// The operator as written in JSON queries
+fn operator_name(operator: &QueryOperator) -> &'static str {
+    match operator {
+        QueryOperator::Equals => "==",
+        QueryOperator::NotEquals => "!=",
+        QueryOperator::GreaterThan => ">",
+        QueryOperator::GreaterThanOrEqual => ">=",
+        QueryOperator::LessThan => "<",
+        QueryOperator::LessThanOrEqual => "<=",
+        QueryOperator::Contains => "contains",
+        QueryOperator::StartsWith => "starts_with",
+        QueryOperator::EndsWith => "ends_with",
+        QueryOperator::In => "in",
+        QueryOperator::NotIn => "not_in",
+        QueryOperator::Fuzzy { .. } => "fuzzy",
+    }
+}
@@ -0,0 +1,411 @@
+use crate::auth::{Access, ALL_COLLECTIONS};
+use crate::database::NeuralVault;
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::NVDocument;
+use crate::storage::{record, RecordView};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Oplog entries requested per round trip
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// Most oplog entries a leader sends per round trip, whatever the
+/// follower asks for
+pub const MAX_BATCH_SIZE: usize = 10_000;
+
+/// Most followers a leader serves at once; further connections are closed
+pub const MAX_FOLLOWERS: usize = 16;
+
+/// How long a replication connection may wait on the other side before
+/// it's dropped
+pub const CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most changes returned by one `changes_since` call
+pub const CHANGES_PAGE_SIZE: usize = 1000;
+
+/// A document change in the changefeed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Change {
+    /// Position of the change; later changes have larger numbers
+    pub sequence: u64,
+    pub id: String,
+    pub collection: String,
+    /// The new version, or `None` if the document was deleted
+    pub document: Option<NVDocument>,
+}
+
+/// Changes returned by `changes_since`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeSet {
+    pub changes: Vec<Change>,
+    /// Sequence number to pass to the next call
+    pub next: u64,
+    /// The requested sequence predates the latest compaction, so `changes`
+    /// starts over with the current version of every document
+    pub reset: bool,
+}
+
+/// Where a reader is in a database's oplog
+///
+/// Offsets are positions in the data file, so they only mean something
+/// for the log they were read from; compaction starts a new log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OplogPosition {
+    pub log_id: String,
+    pub offset: u64,
+}
+
+/// A write recorded in the oplog
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OplogEntry {
+    pub offset: u64,
+    /// Record payload of the new version, or of a deletion marker, with
+    /// encrypted fields still sealed
+    #[serde(with = "hex_bytes")]
+    pub record: Vec<u8>,
+}
+
+impl OplogEntry {
+    /// Read the record's fields without decoding it
+    pub fn view(&self) -> NVResult<RecordView<'_>> {
+        RecordView::parse(&self.record)
+    }
+}
+
+/// Record payloads travel as hex strings rather than JSON arrays of numbers
+mod hex_bytes {
+    use crate::crypto;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&crypto::to_hex(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        crypto::from_hex(&String::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+/// A page of the oplog
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OplogPage {
+    pub log_id: String,
+    pub entries: Vec<OplogEntry>,
+    /// Offset to continue from
+    pub next: u64,
+    /// The requested position was from another log, so this page starts
+    /// over from the beginning of the current one
+    pub reset: bool,
+}
+
+/// A request on the replication protocol, sent as one line of JSON
+#[derive(Serialize, Deserialize)]
+struct OplogRequest {
+    /// API token of a user with read access to every collection
+    token: String,
+    position: Option<OplogPosition>,
+    limit: usize,
+}
+
+/// Serves the oplog to followers over TCP
+///
+/// The protocol is line-delimited JSON: each request line is answered
+/// with one line holding the page or an error. Every request must carry a
+/// token with read access to all collections (see `NeuralVault::authorize`).
+/// At most `MAX_FOLLOWERS` connections are served at once, and one that
+/// stalls for `CONNECTION_TIMEOUT` is dropped. Stops when dropped, closing
+/// every follower connection.
+pub struct ReplicationLeader {
+    address: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    connections: Arc<Mutex<Vec<Connection>>>,
+}
+
+/// A follower connection and the thread serving it
+struct Connection {
+    stream: TcpStream,
+    thread: JoinHandle<()>,
+}
+
+impl ReplicationLeader {
+    /// Listen for followers on `address` (e.g. "0.0.0.0:7070")
+    pub fn start(db: Arc<NeuralVault>, address: &str) -> NVResult<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let connections: Arc<Mutex<Vec<Connection>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let stopping = stop.clone();
+        let serving = connections.clone();
+        let thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopping.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let mut serving = serving.lock();
+                serving.retain(|connection| !connection.thread.is_finished());
+                if serving.len() >= MAX_FOLLOWERS {
+                    continue;
+                }
+                let Ok(handle) = configure(&stream).and_then(|_| stream.try_clone()) else {
+                    continue;
+                };
+                let db = db.clone();
+                let thread = thread::spawn(move || serve_follower(&db, stream));
+                serving.push(Connection { stream: handle, thread });
+            }
+        });
+
+        Ok(Self {
+            address,
+            stop,
+            thread: Some(thread),
+            connections,
+        })
+    }
+
+    /// Address the leader is listening on
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Drop for ReplicationLeader {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake the accept loop so it sees the flag
+        let _ = TcpStream::connect(self.address);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        // Their threads hold the database, so it stays open until they end
+        for connection in self.connections.lock().drain(..) {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+            let _ = connection.thread.join();
+        }
+    }
+}
+
+/// Set the timeouts of a replication connection
+fn configure(stream: &TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))
+}
+
+/// Answer oplog requests on one follower connection until it closes
+fn serve_follower(db: &NeuralVault, stream: TcpStream) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        let response: Result<OplogPage, String> = serde_json::from_str::<OplogRequest>(&line)
+            .map_err(|e| e.to_string())
+            .and_then(|request| {
+                db.authorize(&request.token, ALL_COLLECTIONS, Access::Read)
+                    .and_then(|_| db.oplog(request.position.as_ref(), request.limit.min(MAX_BATCH_SIZE)))
+                    .map_err(|e| e.to_string())
+            });
+
+        let sent = serde_json::to_string(&response)
+            .map_err(std::io::Error::other)
+            .and_then(|json| writeln!(writer, "{}", json));
+        if sent.is_err() {
+            return;
+        }
+    }
+}
+
+/// Keeps a database in step with a leader's oplog
+///
+/// The follower's position is stored in its own system catalog, so
+/// following resumes where it left off after a restart. When the leader has
+/// been compacted since, the follower re-reads the whole log and drops the
+/// documents the leader no longer has.
+///
+/// Catalog changes such as new indexes or views take effect on the follower
+/// when it is reopened. Encrypted fields are replicated sealed, so neither
+/// side needs to be unlocked; reading them on the follower takes the
+/// leader's field key. The leader's keys, roles and tokens aren't
+/// replicated.
+pub struct Follower {
+    db: Arc<NeuralVault>,
+    leader: String,
+    token: String,
+    batch_size: usize,
+}
+
+impl Follower {
+    /// Follow the leader at `leader`, authenticating with an API token
+    /// that has read access to every collection there
+    pub fn new(db: Arc<NeuralVault>, leader: impl Into<String>, token: impl Into<String>) -> Self {
+        Self {
+            db,
+            leader: leader.into(),
+            token: token.into(),
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    /// Set how many entries are requested per round trip, up to
+    /// `MAX_BATCH_SIZE`
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.clamp(1, MAX_BATCH_SIZE);
+        self
+    }
+
+    /// Apply everything the leader has written since the last sync,
+    /// returning the number of entries applied
+    pub fn sync(&self) -> NVResult<usize> {
+        let stream = TcpStream::connect(&self.leader)?;
+        configure(&stream)?;
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+
+        let mut position = self.db.replication_cursor()?;
+        // IDs live on the leader, collected while re-reading a new log
+        let mut resync: Option<HashSet<String>> = None;
+        let mut applied = 0;
+
+        loop {
+            let request = OplogRequest {
+                token: self.token.clone(),
+                position: position.clone(),
+                limit: self.batch_size,
+            };
+            writeln!(writer, "{}", serde_json::to_string(&request)?)?;
+
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            let page = serde_json::from_str::<Result<OplogPage, String>>(&line)?
+                .map_err(NeuralVaultError::ReplicationError)?;
+
+            if page.reset {
+                resync = Some(HashSet::new());
+            }
+            if let Some(live) = &mut resync {
+                for entry in &page.entries {
+                    let view = entry.view()?;
+                    if view.deleted() {
+                        live.remove(view.id());
+                    } else {
+                        live.insert(view.id().to_string());
+                    }
+                }
+            }
+
+            let records: Vec<Vec<u8>> = page.entries.into_iter().map(|entry| entry.record).collect();
+            self.db.apply_replicated(&records)?;
+            applied += records.len();
+
+            let next = Some(OplogPosition {
+                log_id: page.log_id,
+                offset: page.next,
+            });
+            // The leader leaves some entries out, so a short page isn't
+            // necessarily the end; one that gets nowhere is
+            let done = next == position;
+            position = next;
+            // A resync only counts once it has finished
+            if resync.is_none() {
+                self.db.set_replication_cursor(position.as_ref())?;
+            }
+            if done {
+                break;
+            }
+        }
+
+        if let Some(live) = resync {
+            let stale: Vec<Vec<u8>> = self
+                .db
+                .replicated_ids()?
+                .into_iter()
+                .filter(|id| !live.contains(id))
+                .map(|id| record::encode_document(&deletion_marker(&id)))
+                .collect();
+            applied += stale.len();
+            self.db.apply_replicated(&stale)?;
+            self.db.set_replication_cursor(position.as_ref())?;
+        }
+
+        Ok(applied)
+    }
+
+    /// Sync every `interval` on a background thread until stopped
+    pub fn start(self, interval: Duration) -> FollowerHandle {
+        let stop = Arc::new(AtomicBool::new(false));
+        let last_error = Arc::new(Mutex::new(None));
+
+        let stopping = stop.clone();
+        let errors = last_error.clone();
+        let thread = thread::spawn(move || {
+            while !stopping.load(Ordering::SeqCst) {
+                *errors.lock() = self.sync().err();
+                thread::park_timeout(interval);
+            }
+        });
+
+        FollowerHandle {
+            stop,
+            last_error,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// A follower syncing in the background
+pub struct FollowerHandle {
+    stop: Arc<AtomicBool>,
+    last_error: Arc<Mutex<Option<NeuralVaultError>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FollowerHandle {
+    /// Error from the most recent sync, if it failed
+    pub fn last_error(&self) -> Option<NeuralVaultError> {
+        self.last_error.lock().clone()
+    }
+
+    /// Stop following, e.g. to promote the follower after the leader fails
+    ///
+    /// Waits for a sync in progress to finish; the database can then take
+    /// writes of its own.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for FollowerHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Oplog representation of a delete
+pub(crate) fn deletion_marker(id: &str) -> NVDocument {
+    let mut marker = NVDocument::new(id.to_string(), String::new(), Default::default());
+    marker.deleted = true;
+    marker
+}
@@ -0,0 +1,93 @@
+use uuid::Uuid;
+
+/// A fast, non-cryptographic random number generator (SplitMix64)
+pub struct Rng(u64);
+
+impl Rng {
+    /// A generator seeded from the system's random source
+    pub fn new() -> Self {
+        Self(Uuid::new_v4().as_u64_pair().0)
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A uniformly random number below `bound`, which must be non-zero
+    pub fn below(&mut self, bound: u64) -> u64 {
+        // Multiply-shift maps the full range onto [0, bound) with bias
+        // below 2^-64 * bound, which is negligible here
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Choose `n` items uniformly at random in one pass (reservoir sampling),
+/// or all of them if there are no more than `n`
+///
+/// The chosen items are returned in random order.
+pub fn reservoir<T>(items: impl IntoIterator<Item = T>, n: usize, rng: &mut Rng) -> Vec<T> {
+    if n == 0 {
+        return Vec::new();
+    }
+
+    let mut chosen = Vec::with_capacity(n);
+    for (seen, item) in items.into_iter().enumerate() {
+        if chosen.len() < n {
+            chosen.push(item);
+        } else {
+            let slot = rng.below(seen as u64 + 1) as usize;
+            if slot < n {
+                chosen[slot] = item;
+            }
+        }
+    }
+
+    // The first items fill the reservoir in order; shuffle so the
+    // order says nothing about position in the input
+    for i in (1..chosen.len()).rev() {
+        chosen.swap(i, rng.below(i as u64 + 1) as usize);
+    }
+    chosen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reservoir_size_and_coverage() {
+        let mut rng = Rng::with_seed(7);
+        assert_eq!(reservoir(0..3, 10, &mut rng).len(), 3);
+        assert!(reservoir(0..3, 0, &mut rng).is_empty());
+
+        // Over many draws every item should be picked about n/len of the time
+        let mut hits = [0usize; 20];
+        for _ in 0..2000 {
+            let sample = reservoir(0..20, 5, &mut rng);
+            assert_eq!(sample.len(), 5);
+            let mut distinct = sample.clone();
+            distinct.sort_unstable();
+            distinct.dedup();
+            assert_eq!(distinct.len(), 5);
+            for i in sample {
+                hits[i] += 1;
+            }
+        }
+        // Expected 500 each
+        assert!(hits.iter().all(|&count| (400..600).contains(&count)), "{:?}", hits);
+    }
+}
@@ -0,0 +1,153 @@
+use crate::error::NVResult;
+use crate::models::{NVDocument, NVValue};
+use crate::validation;
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+/// Declarations about a collection's fields
+///
+/// Fields without a declaration, and documents with fields the schema
+/// doesn't mention, are still accepted.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CollectionSchema {
+    #[serde(default)]
+    pub fields: BTreeMap<String, FieldSchema>,
+}
+
+/// Declarations about one field
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FieldSchema {
+    /// Value given to the field when a document is created without it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<FieldDefault>,
+    /// Left out of reads unless the query asks for sensitive fields
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sensitive: bool,
+}
+
+/// Where a field's default value comes from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldDefault {
+    /// A fixed value
+    Value(NVValue),
+    /// The creation time, as an RFC 3339 string in UTC with milliseconds
+    Now,
+    /// A random UUID
+    Uuid,
+    /// The next number of a counter kept per collection and field, from 1
+    Sequence,
+}
+
+impl CollectionSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare a field's default
+    pub fn with_default(mut self, field: &str, default: FieldDefault) -> Self {
+        self.fields.entry(field.to_string()).or_default().default = Some(default);
+        self
+    }
+
+    /// Mark a field sensitive
+    pub fn with_sensitive(mut self, field: &str) -> Self {
+        self.fields.entry(field.to_string()).or_default().sensitive = true;
+        self
+    }
+
+    pub fn validate(&self) -> NVResult<()> {
+        self.fields.keys().try_for_each(|field| validation::validate_data_field(field))
+    }
+
+    /// Whether any field has a default
+    pub fn has_defaults(&self) -> bool {
+        self.fields.values().any(|field| field.default.is_some())
+    }
+
+    /// Whether any field is sensitive
+    pub fn has_sensitive(&self) -> bool {
+        self.fields.values().any(|field| field.sensitive)
+    }
+
+    /// Remove the sensitive fields from `data`
+    pub fn redact(&self, data: &mut HashMap<String, NVValue>) {
+        for (field, schema) in &self.fields {
+            if schema.sensitive {
+                data.remove(field);
+            }
+        }
+    }
+
+    /// Fill in the defaults of fields missing from `data`
+    ///
+    /// `next_sequence` hands out the next number of a field's counter.
+    pub fn apply_defaults(
+        &self,
+        data: &mut HashMap<String, NVValue>,
+        mut next_sequence: impl FnMut(&str) -> NVResult<u64>,
+    ) -> NVResult<()> {
+        for (field, schema) in &self.fields {
+            let Some(default) = &schema.default else { continue };
+            if data.contains_key(field) {
+                continue;
+            }
+            let value = match default {
+                FieldDefault::Value(value) => value.clone(),
+                FieldDefault::Now => NVValue::String(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+                FieldDefault::Uuid => NVValue::String(Uuid::new_v4().to_string()),
+                FieldDefault::Sequence => NVValue::Number(next_sequence(field)? as f64),
+            };
+            data.insert(field.clone(), value);
+        }
+        Ok(())
+    }
+}
+
+/// Remove the fields each document's collection schema marks sensitive
+pub fn redact_documents(schemas: &HashMap<String, CollectionSchema>, documents: &mut [NVDocument]) {
+    if !schemas.values().any(CollectionSchema::has_sensitive) {
+        return;
+    }
+    for document in documents {
+        if let Some(schema) = schemas.get(&document.collection) {
+            schema.redact(&mut document.data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_defaults_fills_missing_fields() {
+        let schema = CollectionSchema::new()
+            .with_default("status", FieldDefault::Value(NVValue::String("open".to_string())))
+            .with_default("created", FieldDefault::Now)
+            .with_default("token", FieldDefault::Uuid)
+            .with_default("number", FieldDefault::Sequence);
+
+        let mut data = HashMap::new();
+        data.insert("status".to_string(), NVValue::String("closed".to_string()));
+        let mut counter = 41;
+        schema
+            .apply_defaults(&mut data, |field| {
+                assert_eq!(field, "number");
+                counter += 1;
+                Ok(counter)
+            })
+            .unwrap();
+
+        assert_eq!(data.get("status"), Some(&NVValue::String("closed".to_string())));
+        assert_eq!(data.get("number"), Some(&NVValue::Number(42.0)));
+        assert!(matches!(data.get("created"), Some(NVValue::String(s)) if s.ends_with('Z')));
+        assert!(matches!(data.get("token"), Some(NVValue::String(s)) if s.len() == 36));
+
+        let json = serde_json::to_string(&schema).unwrap();
+        assert_eq!(serde_json::from_str::<CollectionSchema>(&json).unwrap(), schema);
+        assert!(CollectionSchema::new().with_default("_id", FieldDefault::Uuid).validate().is_err());
+    }
+}
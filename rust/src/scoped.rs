@@ -0,0 +1,153 @@
+use crate::database::NeuralVault;
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::{NVDocument, NVQuery, NVValue, UpdateOperation};
+use crate::pipeline::{Row, Stage};
+use crate::query::targets;
+use std::collections::HashMap;
+
+/// A handle to one tenant's collections
+///
+/// Created by `NeuralVault::scoped`. Collection names are namespaced as
+/// `<tenant>__<collection>`, so each tenant has its own documents, indexes
+/// and views while application code uses plain collection names; the
+/// documents returned carry the plain names too. Documents of other
+/// tenants are reported as not found.
+pub struct ScopedVault<'db> {
+    db: &'db NeuralVault,
+    prefix: String,
+}
+
+impl<'db> ScopedVault<'db> {
+    pub(crate) fn new(db: &'db NeuralVault, tenant: &str) -> Self {
+        Self {
+            db,
+            prefix: format!("{}__", tenant),
+        }
+    }
+
+    /// The tenant this handle is scoped to
+    pub fn tenant(&self) -> &str {
+        &self.prefix[..self.prefix.len() - 2]
+    }
+
+    /// The namespaced name of one of the tenant's collections
+    pub fn collection_name(&self, collection: &str) -> String {
+        format!("{}{}", self.prefix, collection)
+    }
+
+    pub fn create(&self, collection: &str, data: HashMap<String, NVValue>) -> NVResult<String> {
+        self.db.create(self.collection_name(collection), data)
+    }
+
+    pub fn create_with_id(&self, id: String, collection: &str, data: HashMap<String, NVValue>) -> NVResult<String> {
+        self.db.create_with_id(id, self.collection_name(collection), data)
+    }
+
+    pub fn find(&self, query: NVQuery) -> NVResult<Vec<NVDocument>> {
+        let mut documents = self.db.find(self.scope(query))?;
+        documents.iter_mut().for_each(|document| self.unscope(document));
+        Ok(documents)
+    }
+
+    pub fn find_by_id(&self, id: &str) -> NVResult<NVDocument> {
+        let mut document = self.own(id)?;
+        self.unscope(&mut document);
+        Ok(document)
+    }
+
+    pub fn update(&self, query: NVQuery, updates: Vec<UpdateOperation>) -> NVResult<usize> {
+        self.db.update(self.scope(query), updates)
+    }
+
+    pub fn update_by_id(&self, id: &str, updates: Vec<UpdateOperation>) -> NVResult<()> {
+        self.own(id)?;
+        self.db.update_by_id(id, updates)
+    }
+
+    pub fn kill(&self, query: NVQuery) -> NVResult<usize> {
+        self.db.kill(self.scope(query))
+    }
+
+    pub fn kill_by_id(&self, id: &str) -> NVResult<()> {
+        self.own(id)?;
+        self.db.kill_by_id(id)
+    }
+
+    pub fn count(&self, collection: &str) -> NVResult<usize> {
+        self.db.count(&self.collection_name(collection))
+    }
+
+    pub fn aggregate(&self, collection: &str, stages: Vec<Stage>) -> NVResult<Vec<Row>> {
+        self.db.aggregate(&self.collection_name(collection), stages)
+    }
+
+    /// The tenant's collections, by their plain names
+    pub fn collections(&self) -> NVResult<Vec<String>> {
+        Ok(self
+            .db
+            .collections()?
+            .into_iter()
+            .filter_map(|collection| collection.strip_prefix(&self.prefix).map(str::to_string))
+            .collect())
+    }
+
+    /// Namespace a query, its populated references and its subqueries
+    fn scope(&self, mut query: NVQuery) -> NVQuery {
+        query.collection = query
+            .collection
+            .split(targets::SEPARATOR)
+            .map(|collection| self.collection_name(collection.trim()))
+            .collect::<Vec<_>>()
+            .join(",");
+        query.populate = std::mem::take(&mut query.populate)
+            .into_iter()
+            .map(|spec| self.scope_populate(&query.collection, spec))
+            .collect();
+        query.subqueries = std::mem::take(&mut query.subqueries)
+            .into_iter()
+            .map(|mut subquery| {
+                subquery.query = self.scope(subquery.query);
+                subquery
+            })
+            .collect();
+        query
+    }
+
+    /// Point a populate spec at the tenant's collections
+    ///
+    /// Explicit targets are plain names like any other collection. Bare
+    /// specs use the reference declared on the namespaced collection, whose
+    /// target is kept only if it's one of the tenant's, so neither form can
+    /// embed another tenant's documents.
+    fn scope_populate(&self, collection: &str, spec: String) -> String {
+        if let Some((field, target)) = spec.split_once(':') {
+            return format!("{}:{}", field, self.collection_name(target.trim()));
+        }
+        let target = match self
+            .db
+            .references()
+            .into_iter()
+            .find(|r| r.collection == collection && r.field == spec)
+        {
+            Some(reference) if reference.target.starts_with(&self.prefix) => reference.target,
+            Some(reference) => self.collection_name(&reference.target),
+            None => return spec,
+        };
+        format!("{}:{}", spec, target)
+    }
+
+    fn unscope(&self, document: &mut NVDocument) {
+        if let Some(collection) = document.collection.strip_prefix(&self.prefix) {
+            document.collection = collection.to_string();
+        }
+    }
+
+    /// Read a document, failing as if it didn't exist unless it's the tenant's
+    fn own(&self, id: &str) -> NVResult<NVDocument> {
+        let document = self.db.find_by_id(id)?;
+        if !document.collection.starts_with(&self.prefix) {
+            return Err(NeuralVaultError::DocumentNotFound(id.to_string()));
+        }
+        Ok(document)
+    }
+}
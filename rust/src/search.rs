@@ -0,0 +1,134 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Candidates fetched from each index per requested result
+const CANDIDATES_PER_RESULT: usize = 4;
+
+/// Fewest candidates fetched from each index
+const MIN_CANDIDATES: usize = 50;
+
+/// How text and vector rankings are blended into one
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fusion {
+    /// Weighted sum of scores, each scaled to 0..1 over its candidates
+    Weighted { text: f32, vector: f32 },
+    /// Sum of `1 / (k + rank)` over both rankings
+    ReciprocalRank { k: f32 },
+}
+
+impl Default for Fusion {
+    fn default() -> Self {
+        Fusion::ReciprocalRank { k: 60.0 }
+    }
+}
+
+/// A search ranked by both a text index and a vector index over the same
+/// collection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HybridQuery {
+    pub text_index: String,
+    pub text: String,
+    pub vector_index: String,
+    pub vector: Vec<f32>,
+    pub limit: usize,
+    #[serde(default)]
+    pub fusion: Fusion,
+}
+
+impl HybridQuery {
+    /// How many candidates to take from each index
+    pub fn candidates(&self) -> usize {
+        (self.limit * CANDIDATES_PER_RESULT).max(MIN_CANDIDATES)
+    }
+}
+
+/// Blend BM25 matches (higher is better) with vector matches (distances,
+/// lower is better) into `(document ID, score)` pairs, best first
+pub fn fuse(
+    text: &[(String, f32)],
+    vector: &[(String, f32)],
+    fusion: Fusion,
+    limit: usize,
+) -> Vec<(String, f32)> {
+    let mut scores: HashMap<&str, f32> = HashMap::new();
+    match fusion {
+        Fusion::Weighted { text: text_weight, vector: vector_weight } => {
+            for (id, score) in normalize(text, false) {
+                *scores.entry(id).or_default() += text_weight * score;
+            }
+            for (id, score) in normalize(vector, true) {
+                *scores.entry(id).or_default() += vector_weight * score;
+            }
+        }
+        Fusion::ReciprocalRank { k } => {
+            for ranking in [text, vector] {
+                for (rank, (id, _)) in ranking.iter().enumerate() {
+                    *scores.entry(id.as_str()).or_default() += 1.0 / (k + rank as f32 + 1.0);
+                }
+            }
+        }
+    }
+
+    let mut ranked: Vec<(String, f32)> = scores
+        .into_iter()
+        .map(|(id, score)| (id.to_string(), score))
+        .collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    ranked.truncate(limit);
+    ranked
+}
+
+/// Min-max scale scores to 0..1 with 1 the best; a single distinct score
+/// scales to 1
+fn normalize(ranking: &[(String, f32)], lower_is_better: bool) -> Vec<(&str, f32)> {
+    let min = ranking.iter().map(|(_, s)| *s).fold(f32::INFINITY, f32::min);
+    let max = ranking.iter().map(|(_, s)| *s).fold(f32::NEG_INFINITY, f32::max);
+    ranking
+        .iter()
+        .map(|(id, score)| {
+            let scaled = match (max > min, lower_is_better) {
+                (false, _) => 1.0,
+                (true, false) => (score - min) / (max - min),
+                (true, true) => (max - score) / (max - min),
+            };
+            (id.as_str(), scaled)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranking(entries: &[(&str, f32)]) -> Vec<(String, f32)> {
+        entries.iter().map(|(id, s)| (id.to_string(), *s)).collect()
+    }
+
+    #[test]
+    fn test_reciprocal_rank_fusion() {
+        let text = ranking(&[("a", 9.0), ("b", 5.0), ("c", 1.0)]);
+        let vector = ranking(&[("d", 0.1), ("b", 0.2), ("c", 0.3)]);
+
+        let fused = fuse(&text, &vector, Fusion::default(), 10);
+        let ids: Vec<&str> = fused.iter().map(|(id, _)| id.as_str()).collect();
+        // Ranking well in both lists beats ranking first in only one
+        assert_eq!(ids, vec!["b", "c", "a", "d"]);
+        assert_eq!(fuse(&text, &vector, Fusion::default(), 2).len(), 2);
+    }
+
+    #[test]
+    fn test_weighted_fusion() {
+        let text = ranking(&[("a", 10.0), ("b", 0.0)]);
+        let vector = ranking(&[("b", 0.0), ("a", 2.0)]);
+
+        let by_text = fuse(&text, &vector, Fusion::Weighted { text: 0.8, vector: 0.2 }, 10);
+        assert_eq!(by_text[0], ("a".to_string(), 0.8));
+        let by_vector = fuse(&text, &vector, Fusion::Weighted { text: 0.2, vector: 0.8 }, 10);
+        assert_eq!(by_vector[0], ("b".to_string(), 0.8));
+
+        // A single candidate counts as the best of its ranking
+        let single = fuse(&[], &ranking(&[("x", 0.7)]), Fusion::Weighted { text: 0.5, vector: 0.5 }, 10);
+        assert_eq!(single, vec![("x".to_string(), 0.5)]);
+    }
+}
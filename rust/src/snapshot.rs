@@ -0,0 +1,71 @@
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::{NVDocument, NVQuery};
+use crate::query::QueryProcessor;
+use crate::schema::{self, CollectionSchema};
+use crate::storage::StorageSnapshot;
+use std::collections::HashMap;
+
+/// A read-only view of the database pinned to the moment it was taken
+///
+/// Created by `NeuralVault::snapshot`. Reads see every document as it was
+/// then, while writes continue on the database, so long exports and
+/// aggregations stay consistent. Queries scan the snapshot rather than
+/// using indexes, and can't populate references. Sensitive fields are
+/// left out as the schemas said when the snapshot was taken. Drop the
+/// snapshot when done: compaction is refused while it is open.
+pub struct Snapshot<'db> {
+    storage: StorageSnapshot,
+    query_processor: &'db QueryProcessor,
+    schemas: HashMap<String, CollectionSchema>,
+}
+
+impl<'db> Snapshot<'db> {
+    pub(crate) fn new(
+        storage: StorageSnapshot,
+        query_processor: &'db QueryProcessor,
+        schemas: HashMap<String, CollectionSchema>,
+    ) -> Self {
+        Self {
+            storage,
+            query_processor,
+            schemas,
+        }
+    }
+
+    /// Find a document by ID as of the snapshot, without its sensitive fields
+    pub fn find_by_id(&self, id: &str) -> NVResult<NVDocument> {
+        let mut document = self.storage.read(id)?;
+        schema::redact_documents(&self.schemas, std::slice::from_mut(&mut document));
+        Ok(document)
+    }
+
+    /// Find documents matching a query as of the snapshot
+    pub fn find(&self, query: NVQuery) -> NVResult<Vec<NVDocument>> {
+        if !query.populate.is_empty() {
+            return Err(NeuralVaultError::InvalidQuery(
+                "Snapshot queries can't populate references".to_string(),
+            ));
+        }
+
+        let records = self.storage.read_all_raw()?;
+        let manager = self.storage.manager();
+        let overflow = |offset, len| manager.read_overflow(offset, len);
+        let opener = |sealed: &[u8]| manager.open_sealed(sealed);
+        let mut documents = self
+            .query_processor
+            .filter_records(records, &query, &overflow, &opener)?;
+
+        if !query.include_sensitive {
+            schema::redact_documents(&self.schemas, &mut documents);
+        }
+        if let Some(projection) = &query.projection {
+            documents.iter_mut().for_each(|doc| doc.project(projection));
+        }
+        Ok(documents)
+    }
+
+    /// Count documents in a collection as of the snapshot
+    pub fn count(&self, collection: &str) -> NVResult<usize> {
+        Ok(self.find(NVQuery::new(collection.to_string()))?.len())
+    }
+}
@@ -0,0 +1,200 @@
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::NVDocument;
+use crate::storage::backup::BackupSink;
+use crate::storage::index_file::checksum;
+use crate::storage::record;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Magic bytes at the start of an archive segment
+const ARCHIVE_MAGIC: &[u8; 4] = b"NVAR";
+
+/// Current archive segment format version
+///
+/// Version 1 stored documents as JSON; version 2 stores them as records.
+const ARCHIVE_VERSION: u8 = 2;
+
+/// Extension of archive segment files
+const ARCHIVE_EXTENSION: &str = "nvarc";
+
+/// Write a read-only, compressed segment of documents from one collection
+///
+/// Segments are named by `name`, which must sort after every earlier
+/// segment's name; newer segments win when the same ID was archived twice.
+/// The segment is written to a temp file, synced and renamed into place.
+pub fn write(dir: &Path, name: &str, collection: &str, documents: &[NVDocument]) -> NVResult<PathBuf> {
+    fs::create_dir_all(dir)?;
+
+    let payload = miniz_oxide::deflate::compress_to_vec(&record::encode_documents(documents), 6);
+    let checksum = checksum(&payload);
+
+    let path = dir.join(name).with_extension(ARCHIVE_EXTENSION);
+    let tmp_path = path.with_extension("nvarc.tmp");
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(ARCHIVE_MAGIC)?;
+        file.write_all(&[ARCHIVE_VERSION])?;
+        file.write_all(&(collection.len() as u16).to_le_bytes())?;
+        file.write_all(collection.as_bytes())?;
+        file.write_all(&checksum.to_le_bytes())?;
+        file.write_all(&payload)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, &path)?;
+
+    Ok(path)
+}
+
+/// Every archived document of a collection, newest segment first
+///
+/// A document archived in several segments is returned once, from the
+/// newest. Segments of other collections are skipped without being
+/// decompressed.
+pub fn read_collection(dir: &Path, collection: &str) -> NVResult<Vec<NVDocument>> {
+    let mut documents = Vec::new();
+    let mut seen = HashSet::new();
+    for path in segments(dir)?.into_iter().rev() {
+        let Some(segment) = read_segment(&path, collection)? else {
+            continue;
+        };
+        documents.extend(segment.into_iter().filter(|doc| seen.insert(doc.id.clone())));
+    }
+    Ok(documents)
+}
+
+/// Total size of all archive segments in bytes
+pub fn size(dir: &Path) -> NVResult<u64> {
+    let mut total = 0;
+    for path in segments(dir)? {
+        total += fs::metadata(path)?.len();
+    }
+    Ok(total)
+}
+
+/// Write every segment in `dir` to a backup, under `archive/`
+pub fn export(dir: &Path, sink: &mut BackupSink) -> NVResult<()> {
+    for path in segments(dir)? {
+        let name = format!("archive/{}", path.file_name().unwrap().to_string_lossy());
+        sink(&name, &mut File::open(&path)?)?;
+    }
+    Ok(())
+}
+
+/// Paths of the segments in `dir`, oldest first
+fn segments(dir: &Path) -> NVResult<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == ARCHIVE_EXTENSION) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Load a segment, or `None` if it belongs to another collection
+///
+/// The header is read first, so a segment of another collection costs one
+/// short read.
+fn read_segment(path: &Path, collection: &str) -> NVResult<Option<Vec<NVDocument>>> {
+    let corrupt = || {
+        NeuralVaultError::StorageError(format!("Archive segment {} is corrupted", path.display()))
+    };
+    let mut file = File::open(path)?;
+    let mut read_exact = |buf: &mut [u8]| {
+        file.read_exact(buf).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => corrupt(),
+            _ => e.into(),
+        })
+    };
+
+    let mut header = [0u8; 7];
+    read_exact(&mut header)?;
+    if &header[..4] != ARCHIVE_MAGIC || header[4] == 0 {
+        return Err(corrupt());
+    }
+    let version = header[4];
+    if version > ARCHIVE_VERSION {
+        return Err(NeuralVaultError::StorageError(format!(
+            "Archive segment {} has version {}, newer than supported version {}",
+            path.display(),
+            version,
+            ARCHIVE_VERSION
+        )));
+    }
+    let mut name = vec![0u8; u16::from_le_bytes([header[5], header[6]]) as usize];
+    read_exact(&mut name)?;
+    if name != collection.as_bytes() {
+        return Ok(None);
+    }
+    let mut expected = [0u8; 8];
+    read_exact(&mut expected)?;
+
+    let mut payload = Vec::new();
+    file.read_to_end(&mut payload)?;
+    if checksum(&payload) != u64::from_le_bytes(expected) {
+        return Err(corrupt());
+    }
+    let contents = miniz_oxide::inflate::decompress_to_vec(&payload).map_err(|_| corrupt())?;
+    Ok(Some(match version {
+        1 => serde_json::from_slice(&contents)?,
+        _ => record::decode_documents(&contents)?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::NVValue;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_reads_json_segments_of_version_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut data = HashMap::new();
+        data.insert("title".to_string(), NVValue::String("old".to_string()));
+        let documents = vec![NVDocument::new("a".to_string(), "notes".to_string(), data)];
+
+        let payload = miniz_oxide::deflate::compress_to_vec(&serde_json::to_vec(&documents).unwrap(), 6);
+        let mut bytes = ARCHIVE_MAGIC.to_vec();
+        bytes.push(1);
+        bytes.extend_from_slice(&5u16.to_le_bytes());
+        bytes.extend_from_slice(b"notes");
+        bytes.extend_from_slice(&checksum(&payload).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        fs::write(dir.path().join("0001").with_extension(ARCHIVE_EXTENSION), &bytes).unwrap();
+
+        write(dir.path(), "0002", "notes", &[NVDocument::new("b".to_string(), "notes".to_string(), HashMap::new())]).unwrap();
+        let mut ids: Vec<String> = read_collection(dir.path(), "notes").unwrap().into_iter().map(|doc| doc.id).collect();
+        ids.sort();
+        assert_eq!(ids, ["a", "b"]);
+
+        bytes[4] = ARCHIVE_VERSION + 1;
+        fs::write(dir.path().join("0003").with_extension(ARCHIVE_EXTENSION), &bytes).unwrap();
+        assert!(read_collection(dir.path(), "notes").is_err());
+    }
+
+    #[test]
+    fn test_skips_other_collections_by_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(dir.path(), "0001", "logs", &[NVDocument::new("a".to_string(), "logs".to_string(), HashMap::new())]).unwrap();
+        write(dir.path(), "0002", "notes", &[NVDocument::new("b".to_string(), "notes".to_string(), HashMap::new())]).unwrap();
+
+        // A damaged payload is only noticed by readers of its collection
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(read_collection(dir.path(), "notes").unwrap().len(), 1);
+        assert!(read_collection(dir.path(), "logs").is_err());
+    }
+}
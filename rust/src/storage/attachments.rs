@@ -0,0 +1,384 @@
+use crate::error::{NeuralVaultError, NVResult};
+use crate::storage::backup::BackupSink;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Size of the chunks attachments are stored in
+pub const CHUNK_SIZE: usize = 1 << 20;
+
+/// Maximum attachment name length in bytes
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Extension of attachment manifests
+const MANIFEST_EXTENSION: &str = "json";
+
+/// Directory of the shared chunk store, beside the per-document directories
+const CHUNKS_DIR: &str = "chunks";
+
+/// A stored attachment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttachmentInfo {
+    pub document_id: String,
+    pub name: String,
+    /// Size in bytes
+    pub size: u64,
+    pub created_at: DateTime<Utc>,
+    /// SHA-256 of each chunk, naming it in the chunk store
+    chunks: Vec<String>,
+}
+
+/// Check that an attachment name is usable
+pub fn validate_name(name: &str) -> NVResult<()> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN || name.chars().any(char::is_control) {
+        return Err(NeuralVaultError::ValidationError(format!(
+            "Attachment name {:?} must be 1 to {} bytes without control characters",
+            name, MAX_NAME_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// Store an attachment from `contents`, replacing any with the same name
+///
+/// `contents` is copied a chunk at a time, so attachments of any size
+/// stream through a bounded buffer. Chunks are stored by content hash in a
+/// store shared by all documents, so identical chunks are kept once. They
+/// are synced before the manifest naming them is renamed into place, so a
+/// crash leaves either the old attachment or the new one. Chunks no longer
+/// referenced are removed by `collect_garbage`, which must not run during
+/// a `put`.
+pub fn put(dir: &Path, document_id: &str, name: &str, contents: &mut dyn Read) -> NVResult<AttachmentInfo> {
+    let document_dir = document_dir(dir, document_id);
+    let chunks_dir = dir.join(CHUNKS_DIR);
+    fs::create_dir_all(&document_dir)?;
+    fs::create_dir_all(&chunks_dir)?;
+    let stem = hash(name.as_bytes());
+
+    let mut info = AttachmentInfo {
+        document_id: document_id.to_string(),
+        name: name.to_string(),
+        size: 0,
+        created_at: Utc::now(),
+        chunks: Vec::new(),
+    };
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let len = read_chunk(contents, &mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        info.chunks.push(store_chunk(&chunks_dir, &buffer[..len])?);
+        info.size += len as u64;
+        if len < CHUNK_SIZE {
+            break;
+        }
+    }
+
+    let manifest = document_dir.join(&stem).with_extension(MANIFEST_EXTENSION);
+    let tmp_path = manifest.with_extension("json.tmp");
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&serde_json::to_vec(&info)?)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, &manifest)?;
+    Ok(info)
+}
+
+/// Open an attachment for streaming, or `None` if there's no such attachment
+pub fn open(dir: &Path, document_id: &str, name: &str) -> NVResult<Option<AttachmentReader>> {
+    let stem = hash(name.as_bytes());
+    Ok(read_manifest(&document_dir(dir, document_id), &stem)?.map(|info| AttachmentReader {
+        dir: dir.join(CHUNKS_DIR),
+        info,
+        next: 0,
+        chunk: Cursor::new(Vec::new()),
+    }))
+}
+
+/// Remove an attachment, returning whether it existed
+///
+/// Its chunks stay in the store until `collect_garbage`.
+pub fn remove(dir: &Path, document_id: &str, name: &str) -> NVResult<bool> {
+    let manifest = document_dir(dir, document_id)
+        .join(hash(name.as_bytes()))
+        .with_extension(MANIFEST_EXTENSION);
+    match fs::remove_file(manifest) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Remove every attachment of a document
+///
+/// Their chunks stay in the store until `collect_garbage`.
+pub fn remove_all(dir: &Path, document_id: &str) -> NVResult<()> {
+    match fs::remove_dir_all(document_dir(dir, document_id)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// The attachments of a document, sorted by name
+pub fn list(dir: &Path, document_id: &str) -> NVResult<Vec<AttachmentInfo>> {
+    let document_dir = document_dir(dir, document_id);
+    let mut attachments = Vec::new();
+    for path in files(&document_dir)? {
+        if path.extension().is_some_and(|ext| ext == MANIFEST_EXTENSION) {
+            attachments.push(serde_json::from_slice::<AttachmentInfo>(&fs::read(&path)?)?);
+        }
+    }
+    attachments.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(attachments)
+}
+
+/// Remove the chunks no attachment references, returning the bytes freed
+///
+/// Attachments of documents `live` says are gone are removed first. Counts
+/// the references to each chunk across every remaining manifest; chunks
+/// shared by several attachments stay until the last of them is gone.
+/// Interrupted chunk writes are cleaned up too.
+pub fn collect_garbage(dir: &Path, live: impl Fn(&str) -> bool) -> NVResult<u64> {
+    let mut references: HashMap<String, usize> = HashMap::new();
+    for document_dir in files(dir)? {
+        if document_dir.file_name().is_some_and(|name| name == CHUNKS_DIR) {
+            continue;
+        }
+        for path in files(&document_dir)? {
+            if path.extension().is_some_and(|ext| ext == MANIFEST_EXTENSION) {
+                let info: AttachmentInfo = serde_json::from_slice(&fs::read(&path)?)?;
+                if !live(&info.document_id) {
+                    fs::remove_file(&path)?;
+                    continue;
+                }
+                for chunk in info.chunks {
+                    *references.entry(chunk).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    let mut freed = 0;
+    for path in files(&dir.join(CHUNKS_DIR))? {
+        let name = path.file_name().unwrap().to_string_lossy();
+        if !references.contains_key(name.as_ref()) {
+            freed += fs::metadata(&path)?.len();
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(freed)
+}
+
+/// Total size of all attachment files in bytes
+pub fn size(dir: &Path) -> NVResult<u64> {
+    let mut total = 0;
+    for document_dir in files(dir)? {
+        for path in files(&document_dir)? {
+            total += fs::metadata(path)?.len();
+        }
+    }
+    Ok(total)
+}
+
+/// Write every attachment file in `dir` to a backup, under `attachments/`
+pub fn export(dir: &Path, sink: &mut BackupSink) -> NVResult<()> {
+    for document_dir in files(dir)? {
+        for path in files(&document_dir)? {
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                continue;
+            }
+            let name = format!(
+                "attachments/{}/{}",
+                document_dir.file_name().unwrap().to_string_lossy(),
+                path.file_name().unwrap().to_string_lossy()
+            );
+            sink(&name, &mut File::open(&path)?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether a file `export` wrote under `name` can be rewritten in place
+///
+/// Manifests are replaced along with their attachment; chunks are named by
+/// their contents and never change.
+pub fn is_mutable(name: &str) -> bool {
+    Path::new(name).extension().is_some_and(|ext| ext == MANIFEST_EXTENSION)
+}
+
+/// Streams an attachment's contents, checking each chunk as it's read
+pub struct AttachmentReader {
+    /// The chunk store
+    dir: PathBuf,
+    info: AttachmentInfo,
+    next: usize,
+    chunk: Cursor<Vec<u8>>,
+}
+
+impl AttachmentReader {
+    pub fn info(&self) -> &AttachmentInfo {
+        &self.info
+    }
+}
+
+impl Read for AttachmentReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let len = self.chunk.read(buf)?;
+            if len > 0 || buf.is_empty() || self.next == self.info.chunks.len() {
+                return Ok(len);
+            }
+
+            let chunk = &self.info.chunks[self.next];
+            let bytes = fs::read(self.dir.join(chunk))?;
+            if hash(&bytes) != *chunk {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Chunk {} of attachment {} is corrupted", self.next, self.info.name),
+                ));
+            }
+            self.chunk = Cursor::new(bytes);
+            self.next += 1;
+        }
+    }
+}
+
+/// Directory of a document's attachments, named by a hash of its ID so any
+/// ID makes a valid file name
+fn document_dir(dir: &Path, document_id: &str) -> PathBuf {
+    dir.join(hash(document_id.as_bytes()))
+}
+
+/// Store a chunk unless an identical one is already stored, returning its hash
+fn store_chunk(chunks_dir: &Path, data: &[u8]) -> NVResult<String> {
+    let chunk = hash(data);
+    let path = chunks_dir.join(&chunk);
+    if !path.exists() {
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+    }
+    Ok(chunk)
+}
+
+fn read_manifest(document_dir: &Path, stem: &str) -> NVResult<Option<AttachmentInfo>> {
+    match fs::read(document_dir.join(stem).with_extension(MANIFEST_EXTENSION)) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Fill `buffer` as far as `contents` allows, returning how much was read
+fn read_chunk(contents: &mut dyn Read, buffer: &mut [u8]) -> NVResult<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match contents.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(len) => filled += len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+/// Paths of the entries of a directory, sorted; none if it doesn't exist
+fn files(dir: &Path) -> NVResult<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut paths = Vec::new();
+    for entry in entries {
+        paths.push(entry?.path());
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn hash(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn stored_chunks(dir: &Path) -> usize {
+        files(&dir.join(CHUNKS_DIR)).unwrap().len()
+    }
+
+    #[test]
+    fn test_chunked_round_trip_and_replace() {
+        let dir = tempdir().unwrap();
+        let contents: Vec<u8> = (0..CHUNK_SIZE * 2 + 10).map(|i| (i % 251) as u8).collect();
+
+        let info = put(dir.path(), "doc", "scan.pdf", &mut contents.as_slice()).unwrap();
+        assert_eq!((info.size, info.chunks.len()), (contents.len() as u64, 3));
+
+        let mut read = Vec::new();
+        open(dir.path(), "doc", "scan.pdf").unwrap().unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read, contents);
+
+        // Replacing leaves the old chunks for garbage collection
+        put(dir.path(), "doc", "scan.pdf", &mut &b"small"[..]).unwrap();
+        put(dir.path(), "doc", "empty.txt", &mut io::empty()).unwrap();
+        assert_eq!(files(&document_dir(dir.path(), "doc")).unwrap().len(), 2);
+        assert_eq!(stored_chunks(dir.path()), 4);
+        assert_eq!(collect_garbage(dir.path(), |_| true).unwrap(), contents.len() as u64);
+        assert_eq!(stored_chunks(dir.path()), 1);
+        let names: Vec<String> = list(dir.path(), "doc").unwrap().into_iter().map(|info| info.name).collect();
+        assert_eq!(names, vec!["empty.txt", "scan.pdf"]);
+
+        let mut read = String::new();
+        open(dir.path(), "doc", "empty.txt").unwrap().unwrap().read_to_string(&mut read).unwrap();
+        assert_eq!(read, "");
+
+        assert!(remove(dir.path(), "doc", "scan.pdf").unwrap());
+        assert!(!remove(dir.path(), "doc", "scan.pdf").unwrap());
+        assert!(open(dir.path(), "doc", "scan.pdf").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_identical_chunks_stored_once() {
+        let dir = tempdir().unwrap();
+        let photo: Vec<u8> = (0..CHUNK_SIZE + 100).map(|i| (i % 7) as u8).collect();
+        put(dir.path(), "a", "photo.jpg", &mut photo.as_slice()).unwrap();
+        put(dir.path(), "b", "copy.jpg", &mut photo.as_slice()).unwrap();
+        assert_eq!(stored_chunks(dir.path()), 2);
+
+        // Shared chunks stay until their last reference is gone
+        remove_all(dir.path(), "a").unwrap();
+        assert_eq!(collect_garbage(dir.path(), |_| true).unwrap(), 0);
+        let mut read = Vec::new();
+        open(dir.path(), "b", "copy.jpg").unwrap().unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read, photo);
+
+        // Attachments of documents that are gone are removed too
+        assert_eq!(collect_garbage(dir.path(), |id| id != "b").unwrap(), photo.len() as u64);
+        assert!(list(dir.path(), "b").unwrap().is_empty());
+        assert_eq!(stored_chunks(dir.path()), 0);
+    }
+
+    #[test]
+    fn test_corrupt_chunk_is_detected() {
+        let dir = tempdir().unwrap();
+        let info = put(dir.path(), "doc", "a.bin", &mut &b"hello"[..]).unwrap();
+        fs::write(dir.path().join(CHUNKS_DIR).join(&info.chunks[0]), b"jello").unwrap();
+
+        let mut read = Vec::new();
+        let error = open(dir.path(), "doc", "a.bin").unwrap().unwrap().read_to_end(&mut read).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
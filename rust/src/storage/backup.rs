@@ -0,0 +1,679 @@
+use crate::crypto::{self, FieldCipher, KdfParams};
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::NVDocument;
+use crate::storage::{archive, attachments, record};
+use crate::storage::file_manager::FileManager;
+use crate::storage::index_file::checksum;
+use chrono::Utc;
+use parking_lot::{Condvar, Mutex};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Name prefix of backup directories
+const BACKUP_PREFIX: &str = "backup-";
+
+/// Name prefix of incremental backup files
+const INCREMENTAL_PREFIX: &str = "incremental-";
+
+/// Extension of incremental backup files
+const INCREMENTAL_EXTENSION: &str = "nvinc";
+
+/// Magic bytes at the start of an incremental backup
+const INCREMENTAL_MAGIC: &[u8; 4] = b"NVIB";
+
+/// Current incremental backup format version
+///
+/// Version 1 stored documents as JSON and version 2 as records; version 3
+/// stores sealed record payloads along with the archive and attachment
+/// files.
+const INCREMENTAL_VERSION: u8 = 3;
+
+/// File in a full backup recording the sequence number it covers up to
+const MANIFEST_FILE: &str = "backup.json";
+
+/// Plaintext bytes per encrypted chunk of a backup file
+const ENCRYPTED_CHUNK: usize = 1024 * 1024;
+
+/// Where a backup's files are written: each file's name, relative to the
+/// backup, and its contents
+pub type BackupSink<'a> = dyn FnMut(&str, &mut dyn Read) -> NVResult<()> + 'a;
+
+/// Key backups are encrypted with, independent of the database's own keys
+#[derive(Clone)]
+pub enum BackupKey {
+    /// A 32-byte key
+    Key(Vec<u8>),
+    /// A passphrase, stretched with Argon2id and a salt stored in the backup
+    Passphrase(String),
+}
+
+impl BackupKey {
+    /// Fresh encryption settings for a new backup
+    fn encryption(&self, kdf: KdfParams) -> Encryption {
+        Encryption {
+            salt: crypto::to_hex(&crypto::random_bytes(16)),
+            kdf: matches!(self, BackupKey::Passphrase(_)).then_some(kdf),
+        }
+    }
+
+    /// The cipher for a backup encrypted with `encryption`
+    fn cipher(&self, encryption: &Encryption) -> NVResult<FieldCipher> {
+        let key = match (self, &encryption.kdf) {
+            (BackupKey::Key(key), None) => key.clone(),
+            (BackupKey::Passphrase(passphrase), Some(kdf)) => {
+                kdf.derive(passphrase, &crypto::from_hex(&encryption.salt)?)?
+            }
+            (BackupKey::Key(_), Some(_)) => {
+                return Err(NeuralVaultError::EncryptionError(
+                    "Backup was encrypted with a passphrase, not a key".to_string(),
+                ))
+            }
+            (BackupKey::Passphrase(_), None) => {
+                return Err(NeuralVaultError::EncryptionError(
+                    "Backup was encrypted with a key, not a passphrase".to_string(),
+                ))
+            }
+        };
+        FieldCipher::new(&key)
+    }
+}
+
+// Never print key material
+impl fmt::Debug for BackupKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BackupKey { .. }")
+    }
+}
+
+/// How a backup was encrypted, without the key
+#[derive(Serialize, Deserialize)]
+struct Encryption {
+    salt: String,
+    /// Set when the key is derived from a passphrase
+    kdf: Option<KdfParams>,
+}
+
+/// A completed backup
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupInfo {
+    pub path: PathBuf,
+    /// Oplog sequence number the backup covers up to; the next incremental
+    /// backup starts from here
+    pub sequence: u64,
+    /// Archive segments and attachment files the backup holds or lists
+    #[serde(skip)]
+    pub(crate) files: BTreeSet<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    sequence: u64,
+    /// Set when the backup's files are encrypted
+    #[serde(default)]
+    encryption: Option<Encryption>,
+    /// Set when the backup was written by a `BackupScheduler`, which only
+    /// ever prunes its own backups
+    #[serde(default)]
+    scheduled: bool,
+}
+
+/// Write a full backup of the database into a new directory under
+/// `destination`
+///
+/// The backup is a complete database directory, named after the current
+/// UTC time so names sort oldest first, and can be opened like any other
+/// unless it's encrypted with `key`. It's assembled under a `.tmp` name and
+/// renamed into place once complete. `base` is the sequence number of the
+/// start of the data file; `key` comes with the KDF settings that stretch
+/// it if it's a passphrase. `scheduled` marks the backup as one
+/// `prune` may delete.
+pub fn create(
+    storage: &FileManager,
+    archive_dir: &Path,
+    attachments_dir: &Path,
+    destination: &Path,
+    base: u64,
+    key: Option<(&BackupKey, KdfParams)>,
+    scheduled: bool,
+) -> NVResult<BackupInfo> {
+    fs::create_dir_all(destination)?;
+
+    let name = format!("{}{}", BACKUP_PREFIX, timestamp());
+    let path = destination.join(&name);
+    let tmp_path = destination.join(format!("{}.tmp", name));
+    fs::create_dir(&tmp_path)?;
+
+    let encryption = key.map(|(key, kdf)| key.encryption(kdf));
+    let cipher = match (key.map(|(key, _)| key), &encryption) {
+        (Some(key), Some(encryption)) => Some(key.cipher(encryption)?),
+        _ => None,
+    };
+    // Plaintext never touches the destination when encrypting
+    let mut sink = |name: &str, contents: &mut dyn Read| -> NVResult<()> {
+        let path = tmp_path.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(&path)?;
+        match &cipher {
+            Some(cipher) => encrypt(cipher, name, contents, &mut file)?,
+            None => {
+                std::io::copy(contents, &mut file)?;
+            }
+        }
+        file.sync_all()?;
+        Ok(())
+    };
+    let data_len = storage.backup(&mut sink)?;
+    let mut files = BTreeSet::new();
+    let mut file_sink = |name: &str, contents: &mut dyn Read| -> NVResult<()> {
+        files.insert(name.to_string());
+        sink(name, contents)
+    };
+    archive::export(archive_dir, &mut file_sink)?;
+    attachments::export(attachments_dir, &mut file_sink)?;
+
+    let manifest = Manifest {
+        sequence: base + data_len,
+        encryption,
+        scheduled,
+    };
+    fs::write(tmp_path.join(MANIFEST_FILE), serde_json::to_vec(&manifest)?)?;
+    fs::rename(&tmp_path, &path)?;
+
+    Ok(BackupInfo {
+        path,
+        sequence: manifest.sequence,
+        files,
+    })
+}
+
+/// Sequence number a full backup covers up to
+pub fn sequence(backup: &Path) -> NVResult<u64> {
+    Ok(manifest(backup)?.sequence)
+}
+
+fn manifest(backup: &Path) -> NVResult<Manifest> {
+    Ok(serde_json::from_slice(&fs::read(backup.join(MANIFEST_FILE))?)?)
+}
+
+/// Copy a full backup's files into `target` to restore it there,
+/// decrypting them with `key` if the backup is encrypted
+pub fn restore_files(backup: &Path, target: &Path, key: Option<&BackupKey>) -> NVResult<()> {
+    if target.join("data.nvdb").exists() {
+        return Err(NeuralVaultError::StorageError(format!(
+            "Can't restore over the existing database at {}",
+            target.display()
+        )));
+    }
+    let cipher = match (manifest(backup)?.encryption, key) {
+        (Some(encryption), Some(key)) => Some(key.cipher(&encryption)?),
+        (Some(_), None) => return Err(missing_key()),
+        (None, _) => None,
+    };
+
+    let mut names = Vec::new();
+    for name in ["data.nvdb", "overflow.nvblob", "index.nvidx"] {
+        if backup.join(name).exists() {
+            names.push(name.to_string());
+        }
+    }
+    if let Ok(entries) = fs::read_dir(backup.join("archive")) {
+        for entry in entries {
+            names.push(format!("archive/{}", entry?.file_name().to_string_lossy()));
+        }
+    }
+
+    // Attachments sit one directory per document
+    if let Ok(entries) = fs::read_dir(backup.join("attachments")) {
+        for entry in entries {
+            let document_dir = entry?.file_name().to_string_lossy().to_string();
+            fs::create_dir_all(target.join("attachments").join(&document_dir))?;
+            for file in fs::read_dir(backup.join("attachments").join(&document_dir))? {
+                names.push(format!("attachments/{}/{}", document_dir, file?.file_name().to_string_lossy()));
+            }
+        }
+    }
+
+    fs::create_dir_all(target.join("archive"))?;
+    for name in names {
+        let mut from = File::open(backup.join(&name))?;
+        let mut to = File::create(target.join(&name))?;
+        match &cipher {
+            Some(cipher) => decrypt(cipher, &name, &mut from, &mut to)?,
+            None => {
+                std::io::copy(&mut from, &mut to)?;
+            }
+        }
+        to.sync_all()?;
+    }
+    Ok(())
+}
+
+/// The changes between two sequence numbers, from `read_incremental`
+pub struct Incremental {
+    pub from: u64,
+    pub to: u64,
+    /// Record payloads, deletion markers included, in the order they were
+    /// written
+    pub records: Vec<Vec<u8>>,
+    /// Decoded documents and deletion markers, in place of `records` in
+    /// backups from before version 3
+    pub documents: Vec<NVDocument>,
+    /// Every archive and attachment file when the backup was taken, or
+    /// `None` for backups from before version 3, which left them out
+    pub files: Option<Vec<IncrementalFile>>,
+}
+
+/// An archive segment or attachment file listed in an incremental backup
+#[derive(Serialize, Deserialize)]
+pub struct IncrementalFile {
+    /// Path relative to the database directory, as in a full backup
+    pub name: String,
+    /// Left out when the previous backup already holds the file and it
+    /// can't have changed since
+    pub contents: Option<Vec<u8>>,
+}
+
+/// Contents of a version 3 incremental backup
+#[derive(Serialize, Deserialize)]
+struct Changes {
+    records: Vec<Vec<u8>>,
+    files: Vec<IncrementalFile>,
+}
+
+/// List the archive segments and attachment files for an incremental
+/// backup
+///
+/// Every file is listed, so a restore can remove the ones deleted since.
+/// `previous` holds the files of the backup the incremental continues
+/// from, if known; files it holds that never change are listed without
+/// their contents.
+pub fn incremental_files(
+    archive_dir: &Path,
+    attachments_dir: &Path,
+    previous: Option<&BTreeSet<String>>,
+) -> NVResult<Vec<IncrementalFile>> {
+    let mut files = Vec::new();
+    let mut sink = |name: &str, contents: &mut dyn Read| -> NVResult<()> {
+        let unchanged = !attachments::is_mutable(name) && previous.is_some_and(|previous| previous.contains(name));
+        let contents = match unchanged {
+            true => None,
+            false => {
+                let mut bytes = Vec::new();
+                contents.read_to_end(&mut bytes)?;
+                Some(bytes)
+            }
+        };
+        files.push(IncrementalFile {
+            name: name.to_string(),
+            contents,
+        });
+        Ok(())
+    };
+    archive::export(archive_dir, &mut sink)?;
+    attachments::export(attachments_dir, &mut sink)?;
+    Ok(files)
+}
+
+/// Bring the archive and attachment files under the database directory
+/// `target` in line with the ones an incremental backup lists
+///
+/// Included files are written and files it doesn't list are removed. A
+/// listed file that's neither included nor already there means a backup
+/// is missing from the chain.
+pub fn restore_incremental_files(target: &Path, files: &[IncrementalFile]) -> NVResult<()> {
+    let mut listed = HashSet::new();
+    for file in files {
+        let mut components = Path::new(&file.name).components();
+        let known_dir = matches!(components.next(), Some(Component::Normal(dir)) if dir == "archive" || dir == "attachments");
+        if !known_dir || !components.all(|component| matches!(component, Component::Normal(_))) {
+            return Err(NeuralVaultError::StorageError(format!(
+                "Incremental backup lists an unexpected file {}",
+                file.name
+            )));
+        }
+
+        let path = target.join(&file.name);
+        match &file.contents {
+            Some(contents) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let mut to = File::create(&path)?;
+                to.write_all(contents)?;
+                to.sync_all()?;
+            }
+            None if !path.exists() => {
+                return Err(NeuralVaultError::StorageError(format!(
+                    "Incremental backup leaves out {}, which no earlier backup restored",
+                    file.name
+                )))
+            }
+            None => {}
+        }
+        listed.insert(file.name.as_str());
+    }
+
+    let mut removed = Vec::new();
+    let mut sink = |name: &str, _: &mut dyn Read| -> NVResult<()> {
+        if !listed.contains(name) {
+            removed.push(name.to_string());
+        }
+        Ok(())
+    };
+    archive::export(&target.join("archive"), &mut sink)?;
+    attachments::export(&target.join("attachments"), &mut sink)?;
+    for name in removed {
+        fs::remove_file(target.join(name))?;
+    }
+    Ok(())
+}
+
+/// Write the changes made from sequence `from` up to `to` as a new
+/// incremental backup file under `destination`, encrypted if `key` is set
+///
+/// `records` come from `FileManager::sealed_records_since`, so fields
+/// encrypted in the database stay encrypted in the backup even without a
+/// backup key; `files` from `incremental_files`.
+pub fn write_incremental(
+    destination: &Path,
+    from: u64,
+    to: u64,
+    records: Vec<Vec<u8>>,
+    files: Vec<IncrementalFile>,
+    key: Option<&BackupKey>,
+    kdf: KdfParams,
+) -> NVResult<BackupInfo> {
+    fs::create_dir_all(destination)?;
+
+    let names = files.iter().map(|file| file.name.clone()).collect();
+    let changes = Changes { records, files };
+    let payload = miniz_oxide::deflate::compress_to_vec(&bincode::serialize(&changes)?, 6);
+    let encryption = key.map(|key| key.encryption(kdf));
+    let body = match (key, &encryption) {
+        (Some(key), Some(encryption)) => {
+            let mut body = Vec::new();
+            encrypt(&key.cipher(encryption)?, &incremental_name(from, to), &mut payload.as_slice(), &mut body)?;
+            body
+        }
+        _ => {
+            let mut body = checksum(&payload).to_le_bytes().to_vec();
+            body.extend_from_slice(&payload);
+            body
+        }
+    };
+    let encryption = match &encryption {
+        Some(encryption) => serde_json::to_vec(encryption)?,
+        None => Vec::new(),
+    };
+
+    // Two incrementals can share a timestamp, but not a range too unless
+    // they're identical
+    let name = format!("{}{}-{}-{}", INCREMENTAL_PREFIX, timestamp(), from, to);
+    let path = destination.join(name).with_extension(INCREMENTAL_EXTENSION);
+    let tmp_path = path.with_extension("nvinc.tmp");
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(INCREMENTAL_MAGIC)?;
+        file.write_all(&[INCREMENTAL_VERSION])?;
+        file.write_all(&from.to_le_bytes())?;
+        file.write_all(&to.to_le_bytes())?;
+        file.write_all(&(encryption.len() as u16).to_le_bytes())?;
+        file.write_all(&encryption)?;
+        file.write_all(&body)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, &path)?;
+
+    Ok(BackupInfo {
+        path,
+        sequence: to,
+        files: names,
+    })
+}
+
+/// Load an incremental backup file, decrypting it with `key` if it's
+/// encrypted
+pub fn read_incremental(path: &Path, key: Option<&BackupKey>) -> NVResult<Incremental> {
+    let corrupt = || {
+        NeuralVaultError::StorageError(format!("Incremental backup {} is corrupted", path.display()))
+    };
+
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+
+    const HEADER_LEN: usize = 4 + 1 + 8 + 8 + 2;
+    if bytes.len() < HEADER_LEN || &bytes[..4] != INCREMENTAL_MAGIC || bytes[4] == 0 {
+        return Err(corrupt());
+    }
+    let version = bytes[4];
+    if version > INCREMENTAL_VERSION {
+        return Err(NeuralVaultError::StorageError(format!(
+            "Incremental backup {} has version {}, newer than supported version {}",
+            path.display(),
+            version,
+            INCREMENTAL_VERSION
+        )));
+    }
+    let from = u64::from_le_bytes(bytes[5..13].try_into().unwrap());
+    let to = u64::from_le_bytes(bytes[13..21].try_into().unwrap());
+    let encryption_len = u16::from_le_bytes(bytes[21..HEADER_LEN].try_into().unwrap()) as usize;
+    if bytes.len() < HEADER_LEN + encryption_len {
+        return Err(corrupt());
+    }
+    let body = &bytes[HEADER_LEN + encryption_len..];
+
+    let payload = if encryption_len > 0 {
+        let encryption: Encryption = serde_json::from_slice(&bytes[HEADER_LEN..HEADER_LEN + encryption_len])?;
+        let cipher = key.ok_or_else(missing_key)?.cipher(&encryption)?;
+        let mut payload = Vec::new();
+        decrypt(&cipher, &incremental_name(from, to), &mut &body[..], &mut payload)?;
+        payload
+    } else {
+        if body.len() < 8 {
+            return Err(corrupt());
+        }
+        let (expected, payload) = body.split_at(8);
+        if checksum(payload) != u64::from_le_bytes(expected.try_into().unwrap()) {
+            return Err(corrupt());
+        }
+        payload.to_vec()
+    };
+    let contents = miniz_oxide::inflate::decompress_to_vec(&payload).map_err(|_| corrupt())?;
+    let mut incremental = Incremental {
+        from,
+        to,
+        records: Vec::new(),
+        documents: Vec::new(),
+        files: None,
+    };
+    match version {
+        1 => incremental.documents = serde_json::from_slice(&contents)?,
+        2 => incremental.documents = record::decode_documents(&contents)?,
+        _ => {
+            let changes: Changes = bincode::deserialize(&contents)?;
+            incremental.records = changes.records;
+            incremental.files = Some(changes.files);
+        }
+    }
+    Ok(incremental)
+}
+
+/// Name an incremental's contents are encrypted under, binding its range
+fn incremental_name(from: u64, to: u64) -> String {
+    format!("incremental:{}:{}", from, to)
+}
+
+fn missing_key() -> NeuralVaultError {
+    NeuralVaultError::EncryptionError("Backup is encrypted; a backup key is required".to_string())
+}
+
+/// Encrypt `contents` as a sequence of `[len u32][sealed chunk]`
+///
+/// Each chunk is authenticated together with `name` and its index, so
+/// chunks can't be reordered or moved between files. Only the last chunk
+/// is shorter than `ENCRYPTED_CHUNK` (it may be empty), which makes a
+/// truncated file detectable.
+fn encrypt(cipher: &FieldCipher, name: &str, contents: &mut dyn Read, out: &mut dyn Write) -> NVResult<()> {
+    let mut chunk = vec![0u8; ENCRYPTED_CHUNK];
+    for index in 0u64.. {
+        let len = read_full(contents, &mut chunk)?;
+        let sealed = cipher.seal_with(&chunk[..len], &chunk_aad(name, index))?;
+        out.write_all(&(sealed.len() as u32).to_le_bytes())?;
+        out.write_all(&sealed)?;
+        if len < ENCRYPTED_CHUNK {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Decrypt `encrypt` output, failing on a wrong key, tampering or truncation
+fn decrypt(cipher: &FieldCipher, name: &str, contents: &mut dyn Read, out: &mut dyn Write) -> NVResult<()> {
+    let wrong_key = || NeuralVaultError::EncryptionError("Wrong backup key or corrupted backup".to_string());
+    for index in 0u64.. {
+        let mut len = [0u8; 4];
+        contents.read_exact(&mut len).map_err(|_| wrong_key())?;
+        let mut sealed = vec![0u8; u32::from_le_bytes(len) as usize];
+        contents.read_exact(&mut sealed).map_err(|_| wrong_key())?;
+        let chunk = cipher
+            .open_with(&sealed, &chunk_aad(name, index))
+            .map_err(|_| wrong_key())?;
+        out.write_all(&chunk)?;
+        if chunk.len() < ENCRYPTED_CHUNK {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn chunk_aad(name: &str, index: u64) -> Vec<u8> {
+    let mut aad = name.as_bytes().to_vec();
+    aad.extend_from_slice(&index.to_le_bytes());
+    aad
+}
+
+/// Read until `buf` is full or the reader is exhausted
+fn read_full(reader: &mut dyn Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Current UTC time for backup names, sorting in time order
+fn timestamp() -> String {
+    Utc::now().format("%Y%m%dT%H%M%S%3fZ").to_string()
+}
+
+/// Completed backups under `destination`, oldest first
+pub fn list(destination: &Path) -> NVResult<Vec<PathBuf>> {
+    let entries = match fs::read_dir(destination) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let complete = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(BACKUP_PREFIX) && !name.ends_with(".tmp"));
+        if complete && path.is_dir() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Delete all but the newest `keep` scheduled backups under `destination`
+///
+/// Backups taken by hand are never deleted, nor counted against `keep`.
+pub fn prune(destination: &Path, keep: usize) -> NVResult<()> {
+    let mut backups = list(destination)?;
+    backups.retain(|path| manifest(path).is_ok_and(|manifest| manifest.scheduled));
+    let excess = backups.len().saturating_sub(keep);
+    for path in &backups[..excess] {
+        fs::remove_dir_all(path)?;
+    }
+    Ok(())
+}
+
+/// Background thread that backs up the database on an interval
+///
+/// Each round runs `job`, which writes a new backup under `destination`,
+/// and then prunes all but the newest `keep` of the backups it wrote
+/// (0 = keep every backup). A failed round is retried on the next one.
+pub struct BackupScheduler {
+    shared: Arc<SchedulerShared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct SchedulerShared {
+    /// Set to stop the thread
+    stopped: Mutex<bool>,
+    wake: Condvar,
+}
+
+impl BackupScheduler {
+    /// Start running `job` every `interval`
+    pub fn start(
+        mut job: impl FnMut() -> NVResult<BackupInfo> + Send + 'static,
+        destination: PathBuf,
+        interval: Duration,
+        keep: usize,
+    ) -> Self {
+        let shared = Arc::new(SchedulerShared {
+            stopped: Mutex::new(false),
+            wake: Condvar::new(),
+        });
+
+        let thread_shared = shared.clone();
+        let thread = std::thread::spawn(move || loop {
+            {
+                let mut stopped = thread_shared.stopped.lock();
+                if !*stopped {
+                    thread_shared.wake.wait_for(&mut stopped, interval);
+                }
+                if *stopped {
+                    return;
+                }
+            }
+            if job().is_ok() && keep > 0 {
+                let _ = prune(&destination, keep);
+            }
+        });
+
+        Self {
+            shared,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for BackupScheduler {
+    fn drop(&mut self) {
+        *self.shared.stopped.lock() = true;
+        self.shared.wake.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
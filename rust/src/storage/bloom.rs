@@ -0,0 +1,177 @@
+use crate::models::{NVDocument, NVValue};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Fixed-size bloom filter using double hashing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Create a filter sized for `expected_items` at the given false-positive rate
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        let n = expected_items.max(1) as f64;
+        let p = false_positive_rate.clamp(1e-6, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+
+        let num_bits = (-(n * p.ln()) / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().clamp(1.0, 16.0) as u32;
+
+        Self {
+            bits: vec![0; num_bits.div_ceil(64)],
+            num_hashes,
+        }
+    }
+
+    /// Add a key to the filter
+    pub fn insert(&mut self, key: &[u8]) {
+        for bit in self.bit_positions(key) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Check whether a key may have been inserted (false means definitely not)
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.bit_positions(key)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    fn bit_positions(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        let num_bits = (self.bits.len() * 64) as u64;
+        let h1 = fnv1a(key, 0xcbf29ce484222325);
+        let h2 = fnv1a(key, 0x84222325cbf29ce4) | 1;
+        (0..self.num_hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+}
+
+fn fnv1a(data: &[u8], seed: u64) -> u64 {
+    let mut hash = seed;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Bloom filters over the IDs and scalar field values of one collection
+///
+/// Filters only ever grow, so deleted or updated documents leave stale
+/// entries behind; those only cost false positives, never false negatives.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionFilter {
+    ids: BloomFilter,
+    values: BloomFilter,
+}
+
+impl CollectionFilter {
+    pub const EXPECTED_DOCUMENTS: usize = 10_000;
+    const FIELDS_PER_DOCUMENT: usize = 8;
+    const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+    pub fn new() -> Self {
+        Self::with_capacity(Self::EXPECTED_DOCUMENTS)
+    }
+
+    /// Create filters sized for roughly `expected_documents` documents
+    pub fn with_capacity(expected_documents: usize) -> Self {
+        Self {
+            ids: BloomFilter::new(expected_documents, Self::FALSE_POSITIVE_RATE),
+            values: BloomFilter::new(
+                expected_documents * Self::FIELDS_PER_DOCUMENT,
+                Self::FALSE_POSITIVE_RATE,
+            ),
+        }
+    }
+
+    /// Record a document's ID and top-level scalar values
+    pub fn insert(&mut self, document: &NVDocument) {
+        self.ids.insert(document.id.as_bytes());
+        for (field, value) in &document.data {
+            if let Some(key) = value_key(field, value) {
+                self.values.insert(&key);
+            }
+        }
+    }
+
+    /// Whether a document with this ID may exist in the collection
+    pub fn may_contain_id(&self, id: &str) -> bool {
+        self.ids.may_contain(id.as_bytes())
+    }
+
+    /// Whether some document may have `field == value`
+    ///
+    /// Values that are not tracked always report a possible match.
+    pub fn may_contain_value(&self, field: &str, value: &NVValue) -> bool {
+        match value_key(field, value) {
+            Some(key) => self.values.may_contain(&key),
+            None => true,
+        }
+    }
+}
+
+impl Default for CollectionFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Per-collection filters keyed by collection name
+pub type CollectionFilters = HashMap<String, CollectionFilter>;
+
+/// Filter key for a field/value pair
+///
+/// Numbers are compared with a tolerance by the query processor, so exact
+/// bit patterns can't be used as keys and they are not tracked.
+fn value_key(field: &str, value: &NVValue) -> Option<Vec<u8>> {
+    let mut key = field.as_bytes().to_vec();
+    key.push(0);
+    match value {
+        NVValue::Null => key.push(b'n'),
+        NVValue::Bool(b) => key.extend_from_slice(if *b { b"t" } else { b"f" }),
+        NVValue::String(s) => {
+            key.push(b's');
+            key.extend_from_slice(s.as_bytes());
+        }
+        NVValue::Number(_) | NVValue::Array(_) | NVValue::Object(_) => return None,
+    }
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_has_no_false_negatives() {
+        let mut filter = BloomFilter::new(1000, 0.01);
+        for i in 0..1000 {
+            filter.insert(format!("key{}", i).as_bytes());
+        }
+        assert!((0..1000).all(|i| filter.may_contain(format!("key{}", i).as_bytes())));
+
+        let false_positives = (1000..11000)
+            .filter(|i| filter.may_contain(format!("key{}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 500);
+    }
+
+    #[test]
+    fn test_collection_filter_values() {
+        let mut data = HashMap::new();
+        data.insert("status".to_string(), NVValue::String("active".to_string()));
+        data.insert("age".to_string(), NVValue::Number(30.0));
+        let doc = NVDocument::new("a".to_string(), "users".to_string(), data);
+
+        let mut filter = CollectionFilter::new();
+        filter.insert(&doc);
+
+        assert!(filter.may_contain_id("a"));
+        assert!(filter.may_contain_value("status", &NVValue::String("active".to_string())));
+        assert!(!filter.may_contain_value("status", &NVValue::String("banned".to_string())));
+        // Untracked value types never rule anything out
+        assert!(filter.may_contain_value("age", &NVValue::Number(99.0)));
+    }
+}
@@ -1,275 +1,2921 @@
+use crate::crypto::FieldCipher;
 use crate::error::{NeuralVaultError, NVResult};
-use crate::models::NVDocument;
-use parking_lot::RwLock;
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
-use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use crate::models::{self, NVDocument, NVValue};
+use crate::sampling::{self, Rng};
+use crate::storage::backup::BackupSink;
+use crate::storage::bloom::{CollectionFilter, CollectionFilters};
+use crate::storage::flusher::Flusher;
+use crate::storage::group_commit::GroupCommit;
+use crate::storage::index_file::{self, PersistedIndex};
+use crate::storage::legacy;
+use crate::storage::positioned::{read_exact_at, write_all_at};
+use crate::storage::record::{self, VersionedRecord};
+use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Read;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 /// Position in the storage file
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct StoragePosition {
     pub file_offset: u64,
     pub length: u32,
 }
 
+/// A record as read from the data file
+struct RawRecord {
+    position: StoragePosition,
+    checksum: u64,
+    tombstoned: bool,
+    data: Vec<u8>,
+}
+
+impl RawRecord {
+    /// Offset just past this record
+    fn end(&self) -> u64 {
+        self.position.file_offset + 12 + self.position.length as u64 + 1
+    }
+}
+
+/// Documents copied between compaction progress reports
+pub const COMPACTION_PROGRESS_INTERVAL: usize = 256;
+
+/// How far a running compaction has got
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct CompactionProgress {
+    pub documents_copied: usize,
+    pub documents_total: usize,
+    /// Size of the new data file so far
+    pub bytes_written: u64,
+}
+
+/// Bytes read at a time while looking for the next intact record
+const RESYNC_CHUNK: usize = 64 * 1024;
+
+/// A byte range of the data file that couldn't be read as records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CorruptRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// What `FileManager::recover` salvaged
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RecoveryReport {
+    /// Documents copied to the new data file
+    pub documents_salvaged: usize,
+    /// Documents whose newest version was unreadable, restored from an
+    /// older one
+    pub documents_restored: usize,
+    /// Unreadable ranges of the replaced data file, including a torn tail
+    pub corrupt_ranges: Vec<CorruptRange>,
+    pub bytes_lost: u64,
+}
+
+/// Default limit on the encoded size of a single document
+pub const DEFAULT_MAX_DOCUMENT_SIZE: usize = 16 * 1024 * 1024;
+
+/// Default size above which field values are stored out of line
+pub const DEFAULT_OVERFLOW_THRESHOLD: usize = 64 * 1024;
+
+/// Records read from the data file with their offsets, and the offset to
+/// continue from
+pub type LogPage<T> = (Vec<(u64, T)>, u64);
+
 /// File-based storage manager
 pub struct FileManager {
     base_path: PathBuf,
     data_file: Arc<RwLock<File>>,
     index: Arc<RwLock<HashMap<String, StoragePosition>>>,
+    filters: Arc<RwLock<CollectionFilters>>,
+    /// Generation of the last index checkpoint
+    index_generation: AtomicU64,
+    /// Data file length covered by the last checkpoint
+    checkpoint_len: AtomicU64,
+    /// Writes since the last checkpoint
+    writes_since_checkpoint: AtomicUsize,
+    /// Completed compactions; positions read before one are stale after it
+    compactions: AtomicU64,
+    /// Whether records from before the record format have been read since
+    /// the last compaction, which rewrites them
+    legacy_records: AtomicBool,
+    /// Open snapshots, which compaction would invalidate
+    snapshots: AtomicUsize,
+    /// Held for the whole of a compaction, so only one runs at a time
+    compacting: Mutex<()>,
+    /// Writes between automatic checkpoints (0 = only on drop)
+    checkpoint_interval: usize,
+    /// Shares fsyncs of the data file between concurrent writers
+    commit: Arc<GroupCommit>,
+    /// Syncs writes in the background when flushing is deferred
+    flusher: Option<Flusher>,
+    /// Position and checksum of the last record in the file
+    last_record: RwLock<Option<(StoragePosition, u64)>>,
+    /// Unreadable ranges of the data file found by scans and reads
+    quarantine: RwLock<Vec<CorruptRange>>,
+    /// Out-of-line storage for large field values (`overflow.nvblob`)
+    overflow_file: Option<RwLock<File>>,
+    /// Largest the data and overflow files may grow (0 = unlimited)
+    max_size: u64,
+    /// Largest accepted encoded document, including spilled values (0 = unlimited)
+    max_document_size: usize,
+    /// Field values encoded larger than this are spilled to the overflow file
+    overflow_threshold: usize,
+    /// Fields stored encrypted, by collection
+    sealed_fields: RwLock<HashMap<String, HashSet<String>>>,
+    /// Key for sealed fields; without it they read as absent
+    field_cipher: RwLock<Option<FieldCipher>>,
+    /// Last write number handed out; see `version`
+    write_counter: AtomicU64,
+    /// Write number of each live document written since the database
+    /// opened, changed only while the index lock is held for writing
+    versions: Mutex<HashMap<String, u64>>,
+    /// Reject all writes
+    read_only: bool,
+    /// Advisory lock on `LOCK`, held for the lifetime of the manager
+    _lock_file: File,
 }
 
 impl FileManager {
     /// Create or open a file manager
+    ///
+    /// Takes an exclusive advisory lock on the directory, failing with
+    /// `AlreadyLocked` if another handle (in this or another process) has it
+    /// open.
     pub fn new(path: &str) -> NVResult<Self> {
+        Self::open(path, false)
+    }
+
+    /// Open an existing database for reading only
+    ///
+    /// Takes a shared lock, so any number of read-only handles can coexist
+    /// but not alongside a writer.
+    pub fn open_read_only(path: &str) -> NVResult<Self> {
+        Self::open(path, true)
+    }
+
+    fn open(path: &str, read_only: bool) -> NVResult<Self> {
         let base_path = PathBuf::from(path);
-        
-        // Create directory if it doesn't exist
-        std::fs::create_dir_all(&base_path)?;
+
+        if read_only {
+            if !base_path.join("data.nvdb").exists() {
+                return Err(NeuralVaultError::StorageError(format!(
+                    "No database found at {}",
+                    base_path.display()
+                )));
+            }
+        } else {
+            // Create directory if it doesn't exist
+            std::fs::create_dir_all(&base_path)?;
+        }
+
+        let lock_file = Self::acquire_lock(&base_path, read_only)?;
+        if !read_only {
+            Self::recover_compaction(&base_path)?;
+        }
 
         let data_file_path = base_path.join("data.nvdb");
-        
+
         // Open or create data file
         let data_file = OpenOptions::new()
-            .create(true)
+            .create(!read_only)
             .read(true)
-            .write(true)
+            .write(!read_only)
+            .truncate(false)
             .open(&data_file_path)?;
+        let commit = Arc::new(GroupCommit::new(data_file.try_clone()?, data_file.metadata()?.len()));
+
+        let overflow_path = base_path.join("overflow.nvblob");
+        let overflow_file = if read_only && !overflow_path.exists() {
+            None
+        } else {
+            let file = OpenOptions::new()
+                .create(!read_only)
+                .read(true)
+                .write(!read_only)
+                .truncate(false)
+                .open(&overflow_path)?;
+            Some(RwLock::new(file))
+        };
 
         Ok(Self {
             base_path,
             data_file: Arc::new(RwLock::new(data_file)),
             index: Arc::new(RwLock::new(HashMap::new())),
+            filters: Arc::new(RwLock::new(HashMap::new())),
+            index_generation: AtomicU64::new(0),
+            checkpoint_len: AtomicU64::new(0),
+            writes_since_checkpoint: AtomicUsize::new(0),
+            compactions: AtomicU64::new(0),
+            legacy_records: AtomicBool::new(false),
+            snapshots: AtomicUsize::new(0),
+            compacting: Mutex::new(()),
+            checkpoint_interval: 0,
+            commit,
+            flusher: None,
+            last_record: RwLock::new(None),
+            quarantine: RwLock::new(Vec::new()),
+            overflow_file,
+            max_size: 0,
+            max_document_size: DEFAULT_MAX_DOCUMENT_SIZE,
+            overflow_threshold: DEFAULT_OVERFLOW_THRESHOLD,
+            sealed_fields: RwLock::new(HashMap::new()),
+            field_cipher: RwLock::new(None),
+            write_counter: AtomicU64::new(0),
+            versions: Mutex::new(HashMap::new()),
+            read_only,
+            _lock_file: lock_file,
         })
     }
 
-    /// Append a document to storage
-    pub fn append(&self, document: &NVDocument) -> NVResult<StoragePosition> {
-        let mut file = self.data_file.write();
-        
-        // Serialize document
-        let data = bincode::serialize(document)?;
-        let data_len = data.len() as u32;
-
-        // Calculate checksum
-        let checksum = self.calculate_checksum(&data);
-
-        // Get current file position
-        let offset = file.seek(SeekFrom::End(0))?;
+    /// Finish or discard a compaction interrupted by a crash
+    ///
+    /// Compaction writes `*.compact` files, then renames the data file (the
+    /// commit point) and then the overflow file. A leftover data file means
+    /// the originals are intact; a leftover overflow file alone means the
+    /// new data file already references it.
+    fn recover_compaction(base_path: &std::path::Path) -> NVResult<()> {
+        let data = base_path.join("data.nvdb.compact");
+        let overflow = base_path.join("overflow.nvblob.compact");
 
-        // Write record: [length(4)][checksum(8)][data][tombstone(1)]
-        file.write_all(&data_len.to_le_bytes())?;
-        file.write_all(&checksum.to_le_bytes())?;
-        file.write_all(&data)?;
-        file.write_all(&[0u8])?; // Not deleted
+        if data.exists() {
+            std::fs::remove_file(&data)?;
+            if overflow.exists() {
+                std::fs::remove_file(&overflow)?;
+            }
+        } else if overflow.exists() {
+            std::fs::rename(&overflow, base_path.join("overflow.nvblob"))?;
+        }
+        Ok(())
+    }
 
-        file.sync_all()?;
+    /// Lock the `LOCK` file in the database directory
+    ///
+    /// The lock is advisory (flock/LockFileEx) and released by the OS when
+    /// the file is closed, so a crashed process never leaves a stale lock.
+    fn acquire_lock(base_path: &std::path::Path, shared: bool) -> NVResult<File> {
+        let lock_path = base_path.join("LOCK");
+        let lock_file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(&lock_path)?;
 
-        let position = StoragePosition {
-            file_offset: offset,
-            length: data_len,
+        let result = if shared {
+            lock_file.try_lock_shared()
+        } else {
+            lock_file.try_lock()
         };
 
-        // Update index
-        self.index.write().insert(document.id.clone(), position);
-
-        Ok(position)
+        match result {
+            Ok(()) => Ok(lock_file),
+            Err(TryLockError::WouldBlock) => Err(NeuralVaultError::AlreadyLocked(
+                base_path.display().to_string(),
+            )),
+            Err(TryLockError::Error(e)) => Err(e.into()),
+        }
     }
 
-    /// Read a document from storage
-    pub fn read(&self, id: &str) -> NVResult<NVDocument> {
-        let index = self.index.read();
-        let position = index
-            .get(id)
-            .ok_or_else(|| NeuralVaultError::DocumentNotFound(id.to_string()))?;
+    /// Whether this handle was opened read-only
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
 
-        self.read_at(*position)
+    fn ensure_writable(&self) -> NVResult<()> {
+        if self.read_only {
+            return Err(NeuralVaultError::ReadOnly);
+        }
+        // Writes after a failed sync could be lost with the ones before it
+        self.commit.check()
     }
 
-    /// Read document at specific position
-    fn read_at(&self, position: StoragePosition) -> NVResult<NVDocument> {
-        let mut file = self.data_file.write();
-        file.seek(SeekFrom::Start(position.file_offset))?;
+    /// Checkpoint the index automatically every `writes` mutations
+    pub fn with_checkpoint_interval(mut self, writes: usize) -> Self {
+        self.checkpoint_interval = writes;
+        self
+    }
 
-        // Read length
-        let mut len_buf = [0u8; 4];
-        file.read_exact(&mut len_buf)?;
-        let data_len = u32::from_le_bytes(len_buf);
+    /// Set how long a write waits for concurrent writes to share its fsync
+    pub fn with_commit_window(self, window: Duration) -> Self {
+        self.commit.set_window(window);
+        self
+    }
 
-        // Read checksum
-        let mut checksum_buf = [0u8; 8];
-        file.read_exact(&mut checksum_buf)?;
-        let expected_checksum = u64::from_le_bytes(checksum_buf);
+    /// Return from writes before they're synced, flushing them from a
+    /// background thread every `interval` or once `threshold` bytes are
+    /// buffered (0 = only on the interval)
+    ///
+    /// Writes that haven't been flushed are lost if the machine crashes;
+    /// `flush` makes them durable on demand. A zero interval keeps syncing
+    /// every write before it returns.
+    pub fn with_background_flush(mut self, interval: Duration, threshold: u64) -> Self {
+        if !self.read_only && !interval.is_zero() {
+            self.flusher = Some(Flusher::start(self.commit.clone(), interval, threshold));
+        }
+        self
+    }
 
-        // Read data
-        let mut data = vec![0u8; data_len as usize];
-        file.read_exact(&mut data)?;
+    /// Reject writes that would grow the files past `bytes` (0 = unlimited)
+    ///
+    /// Deletes are always accepted, so space can be reclaimed by deleting and
+    /// compacting.
+    pub fn with_max_size(mut self, bytes: u64) -> Self {
+        self.max_size = bytes;
+        self
+    }
 
-        // Read tombstone
-        let mut tombstone = [0u8; 1];
-        file.read_exact(&mut tombstone)?;
+    /// Set the document size limit and the out-of-line storage threshold
+    ///
+    /// `max_document_size == 0` disables the limit.
+    pub fn with_size_limits(mut self, max_document_size: usize, overflow_threshold: usize) -> Self {
+        self.max_document_size = max_document_size;
+        self.overflow_threshold = overflow_threshold;
+        self
+    }
 
-        // Verify checksum
-        let actual_checksum = self.calculate_checksum(&data);
-        if actual_checksum != expected_checksum {
-            return Err(NeuralVaultError::StorageError(
-                "Checksum mismatch - data corruption detected".to_string(),
-            ));
-        }
+    /// Set or clear the key used for encrypted fields
+    pub fn set_field_cipher(&self, cipher: Option<FieldCipher>) {
+        *self.field_cipher.write() = cipher;
+    }
 
-        // Check if deleted
-        if tombstone[0] == 1 {
-            return Err(NeuralVaultError::DocumentNotFound(
-                "Document has been deleted".to_string(),
-            ));
-        }
+    /// Whether encrypted fields can currently be read and written
+    pub fn has_field_cipher(&self) -> bool {
+        self.field_cipher.read().is_some()
+    }
 
-        // Deserialize
-        let document: NVDocument = bincode::deserialize(&data)?;
-        Ok(document)
+    /// Store `collection.field` encrypted from now on
+    pub fn seal_field(&self, collection: &str, field: &str) {
+        self.sealed_fields
+            .write()
+            .entry(collection.to_string())
+            .or_default()
+            .insert(field.to_string());
     }
 
-    /// Mark a document as deleted (soft delete)
-    pub fn mark_deleted(&self, id: &str) -> NVResult<()> {
-        let index = self.index.read();
-        let position = index
-            .get(id)
-            .ok_or_else(|| NeuralVaultError::DocumentNotFound(id.to_string()))?;
+    /// Whether `collection.field` is stored encrypted
+    pub fn is_sealed(&self, collection: &str, field: &str) -> bool {
+        self.sealed_fields
+            .read()
+            .get(collection)
+            .is_some_and(|fields| fields.contains(field))
+    }
 
-        let mut file = self.data_file.write();
-        
-        // Seek to tombstone byte (length(4) + checksum(8) + data + tombstone)
-        let tombstone_offset = position.file_offset + 4 + 8 + position.length as u64;
-        file.seek(SeekFrom::Start(tombstone_offset))?;
-        
-        // Write tombstone
-        file.write_all(&[1u8])?;
-        file.sync_all()?;
+    /// Decrypt a sealed value, or `None` if no key has been set
+    pub fn open_sealed(&self, sealed: &[u8]) -> NVResult<Option<Vec<u8>>> {
+        self.field_cipher
+            .read()
+            .as_ref()
+            .map(|cipher| cipher.open(sealed))
+            .transpose()
+    }
 
-        Ok(())
+    fn index_file_path(&self) -> PathBuf {
+        self.base_path.join("index.nvidx")
     }
 
-    /// Scan all non-deleted documents in a collection
-    pub fn scan_collection(&self, collection: &str) -> NVResult<Vec<NVDocument>> {
-        let mut documents = Vec::new();
-        let index = self.index.read().clone();
+    /// Load the index from the last checkpoint, or rebuild it from scratch
+    ///
+    /// The data file doubles as the write-ahead log: every mutation appends a
+    /// record (deletes append a marker), so records past the checkpoint's
+    /// `data_len` are replayed on top of it. A missing or unusable checkpoint
+    /// falls back to a full rebuild. Returns `true` when a checkpoint was used.
+    pub fn load_or_rebuild_index(&self) -> NVResult<bool> {
+        let data_len = self.data_file.read().metadata()?.len();
 
-        for position in index.values() {
-            match self.read_at(*position) {
-                Ok(doc) => {
-                    if doc.collection == collection && !doc.deleted {
-                        documents.push(doc);
-                    }
+        if let Some(checkpoint) = index_file::load(&self.index_file_path())? {
+            // The checkpoint must describe a prefix of this very file
+            if checkpoint.data_len <= data_len
+                && self.is_record_boundary(checkpoint.data_len, checkpoint.last_record)?
+            {
+                *self.index.write() = checkpoint.positions;
+                *self.filters.write() = checkpoint.filters;
+                self.index_generation.store(checkpoint.generation, Ordering::SeqCst);
+                self.checkpoint_len.store(checkpoint.data_len, Ordering::SeqCst);
+                *self.last_record.write() = checkpoint.last_record;
+
+                if self.replay_from(checkpoint.data_len).is_ok() {
+                    return Ok(true);
                 }
-                Err(_) => continue, // Skip corrupted or deleted documents
             }
         }
 
-        Ok(documents)
+        self.rebuild_index()?;
+        Ok(false)
     }
 
-    /// Get all documents (for rebuilding index)
-    pub fn scan_all(&self) -> NVResult<Vec<NVDocument>> {
-        let mut documents = Vec::new();
-        let mut file = self.data_file.write();
-        file.seek(SeekFrom::Start(0))?;
+    /// Apply records written after `offset` to the in-memory index
+    fn replay_from(&self, offset: u64) -> NVResult<()> {
+        let mut index = self.index.write();
+        let mut replayed = 0;
 
-        loop {
-            let offset = file.stream_position()?;
-
-            // Read length
-            let mut len_buf = [0u8; 4];
-            match file.read_exact(&mut len_buf) {
-                Ok(_) => {}
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(e.into()),
+        let valid_end = self.for_each_record_from(offset, |position, deleted, data| {
+            let doc = match self.decode(&data) {
+                Ok(doc) => doc,
+                Err(_) => return,
+            };
+            replayed += 1;
+
+            if !deleted {
+                index.insert(doc.id.clone(), position);
+                self.track_in_filters(&doc);
+            } else if doc.deleted {
+                // Deletion marker; superseded versions are covered by the
+                // newer record that follows them
+                index.remove(&doc.id);
+            }
+        })?;
+
+        self.writes_since_checkpoint.store(replayed, Ordering::SeqCst);
+        self.truncate_torn_tail(valid_end)
+    }
+
+    /// Rewrite the data and overflow files with only live documents
+    ///
+    /// Superseded versions, tombstones and deletion markers are dropped and
+    /// spilled values no longer referenced are reclaimed. Writes block for
+    /// the duration.
+    pub fn compact(&self) -> NVResult<()> {
+        self.compact_with(None, |_, _| {}, |_| true, || ())
+    }
+
+    /// Compact, passing each live document through `rewrite` first
+    ///
+    /// `rewrite` also receives the length of the data file being replaced.
+    /// That length is only final in the catch-up, which redoes the
+    /// documents `rewrite` changed if the file has grown since they were
+    /// copied. `progress` is called before the first document is copied,
+    /// then every `COMPACTION_PROGRESS_INTERVAL` documents and after the
+    /// last; returning false cancels the compaction with `Cancelled`,
+    /// leaving the original files in place.
+    ///
+    /// `lock` is called just before the catch-up takes the storage locks.
+    /// What it returns is held until the new files are in place and then
+    /// handed back, so the caller can swap state tied to the data file
+    /// before anyone sees the new one.
+    pub fn compact_rewriting<G>(
+        &self,
+        rewrite: impl FnMut(&mut NVDocument, u64),
+        progress: impl FnMut(&CompactionProgress) -> bool,
+        lock: impl FnOnce() -> G,
+    ) -> NVResult<G> {
+        self.compact_with(None, rewrite, progress, lock)
+    }
+
+    /// Compact while re-encrypting sealed fields under a new key
+    ///
+    /// Each live document is passed through `rewrite` before being written,
+    /// so related changes land atomically with the new key. The new key is
+    /// used for every read and write once this returns. `rewrite` and
+    /// `lock` work as in `compact_rewriting`.
+    pub fn compact_rekeyed<G>(
+        &self,
+        cipher: FieldCipher,
+        rewrite: impl FnMut(&mut NVDocument, u64),
+        lock: impl FnOnce() -> G,
+    ) -> NVResult<G> {
+        self.compact_with(Some(cipher), rewrite, |_| true, lock)
+    }
+
+    /// Copy live documents to new files and swap them in
+    ///
+    /// Writers keep going while documents are copied: the index is only
+    /// locked to list them and, at the end, for a catch-up that copies
+    /// whatever was written in the meantime and swaps the files.
+    fn compact_with<G>(
+        &self,
+        rekey: Option<FieldCipher>,
+        mut rewrite: impl FnMut(&mut NVDocument, u64),
+        mut progress: impl FnMut(&CompactionProgress) -> bool,
+        lock: impl FnOnce() -> G,
+    ) -> NVResult<G> {
+        self.ensure_writable()?;
+        let overflow_file = self.overflow_file.as_ref().ok_or(NeuralVaultError::ReadOnly)?;
+        let _compacting = self.compacting.lock();
+
+        let data_path = self.base_path.join("data.nvdb");
+        let overflow_path = self.base_path.join("overflow.nvblob");
+        let compact_data_path = self.base_path.join("data.nvdb.compact");
+        let compact_overflow_path = self.base_path.join("overflow.nvblob.compact");
+        let create = |path: &PathBuf| {
+            OpenOptions::new()
+                .create(true)
+                .read(true)
+                .write(true)
+                .truncate(true)
+                .open(path)
+        };
+        let discard = |files: (File, File), error: NeuralVaultError| -> NeuralVaultError {
+            drop(files);
+            let removed = std::fs::remove_file(&compact_data_path)
+                .and_then(|_| std::fs::remove_file(&compact_overflow_path));
+            match removed {
+                Ok(()) => error,
+                Err(e) => e.into(),
+            }
+        };
+        let snapshots_open = || {
+            NeuralVaultError::StorageError("Can't compact while snapshots are open".to_string())
+        };
+
+        // Live documents in file order keep related records together
+        let (live, copied_len) = {
+            let index = self.index.read();
+            if self.snapshots.load(Ordering::SeqCst) > 0 {
+                return Err(snapshots_open());
             }
+            let mut live: Vec<(String, StoragePosition)> =
+                index.iter().map(|(id, position)| (id.clone(), *position)).collect();
+            live.sort_by_key(|(_, position)| position.file_offset);
+            (live, self.data_file.read().metadata()?.len())
+        };
+
+        let new_file = create(&compact_data_path)?;
+        let new_overflow = create(&compact_overflow_path)?;
+        let current = self.field_cipher.read().clone();
+        let cipher = rekey.as_ref().or(current.as_ref());
 
-            let data_len = u32::from_le_bytes(len_buf);
+        // Copy one record to the new files, returning its new position
+        let mut last_record = None;
+        let mut copy = |file: &File, position: StoragePosition, replaced_len: u64| -> NVResult<_> {
+            let data = self.read_payload(file, position, true)?;
+            let original = self.decode(&data)?;
+            let mut document = original.clone();
+            rewrite(&mut document, replaced_len);
 
-            // Skip checksum
-            file.seek(SeekFrom::Current(8))?;
+            let encoded = self.encode_with(&document, cipher, &new_overflow, None)?;
+            let (new_position, checksum) = self.write_payload(&new_file, &encoded, false)?;
+            last_record = Some((new_position, checksum));
+            let rewritten = document.data != original.data;
+            Ok((new_position, document, rewritten))
+        };
+
+        // Old offset to new position of every record copied so far
+        let mut copied: HashMap<u64, StoragePosition> = HashMap::with_capacity(live.len());
+        let mut documents: HashMap<String, NVDocument> = HashMap::with_capacity(live.len());
+        // Documents `rewrite` changed, which depend on the replaced length
+        let mut rewritten = HashSet::new();
+        let mut report = CompactionProgress {
+            documents_copied: 0,
+            documents_total: live.len(),
+            bytes_written: 0,
+        };
+        let mut cancelled = !progress(&report);
+        for (id, position) in live {
+            if cancelled {
+                break;
+            }
+            let (new_position, document, changed) =
+                copy(&self.data_file.read(), position, copied_len)?;
+            copied.insert(position.file_offset, new_position);
+            if changed {
+                rewritten.insert(id.clone());
+            }
+            documents.insert(id, document);
 
-            // Read data
-            let mut data = vec![0u8; data_len as usize];
-            file.read_exact(&mut data)?;
+            report.documents_copied += 1;
+            report.bytes_written += 12 + new_position.length as u64 + 1;
+            let due = report.documents_copied.is_multiple_of(COMPACTION_PROGRESS_INTERVAL)
+                || report.documents_copied == report.documents_total;
+            cancelled = due && !progress(&report);
+        }
+        if cancelled {
+            let error = NeuralVaultError::Cancelled("Compaction was cancelled".to_string());
+            return Err(discard((new_file, new_overflow), error));
+        }
 
-            // Read tombstone
-            let mut tombstone = [0u8; 1];
-            file.read_exact(&mut tombstone)?;
+        let guard = lock();
+        let mut index = self.index.write();
+        if self.snapshots.load(Ordering::SeqCst) > 0 {
+            return Err(discard((new_file, new_overflow), snapshots_open()));
+        }
+        let mut file = self.data_file.write();
+        let replaced_len = file.metadata()?.len();
 
-            if tombstone[0] == 0 {
-                if let Ok(doc) = bincode::deserialize::<NVDocument>(&data) {
-                    documents.push(doc);
+        // Catch up: copy documents written since they were listed, and redo
+        // rewritten ones if the file has grown since
+        let mut positions = HashMap::with_capacity(index.len());
+        let mut pending = Vec::new();
+        for (id, position) in index.iter() {
+            let redo = replaced_len != copied_len && rewritten.contains(id);
+            match copied.get(&position.file_offset) {
+                Some(new_position) if !redo => {
+                    positions.insert(id.clone(), *new_position);
                 }
+                _ => pending.push((id.clone(), *position)),
             }
         }
+        pending.sort_by_key(|(_, position)| position.file_offset);
+        for (id, position) in pending {
+            let (new_position, document, _) = copy(&file, position, replaced_len)?;
+            positions.insert(id.clone(), new_position);
+            documents.insert(id, document);
+        }
 
-        Ok(documents)
+        // Copies that have since been superseded or deleted must not come
+        // back if the index is rebuilt from the new file
+        let current_offsets: HashSet<u64> = positions.values().map(|p| p.file_offset).collect();
+        for stale in copied.values().filter(|p| !current_offsets.contains(&p.file_offset)) {
+            self.write_tombstone(&new_file, *stale)?;
+        }
+        documents.retain(|id, _| positions.contains_key(id));
+
+        new_overflow.sync_all()?;
+        new_file.sync_all()?;
+
+        // The old checkpoint describes the old file; recovery rebuilds
+        let checkpoint = self.index_file_path();
+        if checkpoint.exists() {
+            std::fs::remove_file(&checkpoint)?;
+        }
+        std::fs::rename(&compact_data_path, &data_path)?;
+        std::fs::rename(&compact_overflow_path, &overflow_path)?;
+
+        self.commit.reset(new_file.try_clone()?, new_file.metadata()?.len());
+        *file = new_file;
+        *overflow_file.write() = new_overflow;
+        *index = positions;
+        *self.last_record.write() = last_record;
+        if rekey.is_some() {
+            *self.field_cipher.write() = rekey;
+        }
+        let documents: Vec<NVDocument> = documents.into_values().collect();
+        self.rebuild_filters(&documents);
+        self.compactions.fetch_add(1, Ordering::SeqCst);
+        self.legacy_records.store(false, Ordering::SeqCst);
+        drop(file);
+        drop(index);
+
+        self.checkpoint()?;
+        Ok(guard)
     }
 
-    /// Rebuild index from storage file
-    pub fn rebuild_index(&self) -> NVResult<()> {
-        let documents = self.scan_all()?;
-        let mut index = self.index.write();
-        index.clear();
+    /// Salvage every readable document into a new data file
+    ///
+    /// The file is rescanned from the start, skipping corrupted ranges, and
+    /// the newest readable version of each document is kept. A document
+    /// whose newest readable version was superseded (its replacement lies in
+    /// a corrupted range or a torn tail) is restored from that version; so is
+    /// a deleted one whose deletion marker was lost. The result is then
+    /// compacted into new files, passing each document through `rewrite` and
+    /// calling `lock` as `compact_rewriting` does.
+    pub fn recover<G>(
+        &self,
+        rewrite: impl FnMut(&mut NVDocument, u64),
+        lock: impl FnOnce() -> G,
+    ) -> NVResult<(RecoveryReport, G)> {
+        self.ensure_writable()?;
 
-        let mut file = self.data_file.write();
-        file.seek(SeekFrom::Start(0))?;
+        let report = {
+            let mut index = self.index.write();
+            self.quarantine.write().clear();
 
-        for doc in documents {
-            let offset = file.stream_position()?;
-            
-            // Calculate position
-            let data = bincode::serialize(&doc)?;
-            let data_len = data.len() as u32;
-            
-            let position = StoragePosition {
-                file_offset: offset,
-                length: data_len,
+            let mut slots: HashMap<String, usize> = HashMap::new();
+            // Newest record of each ID, and whether it was superseded
+            let mut records: Vec<(StoragePosition, NVDocument, bool)> = Vec::new();
+            let valid_end = self.for_each_record(|position, tombstoned, data| {
+                let Ok(doc) = self.decode(&data) else {
+                    return;
+                };
+                let record = (position, doc, tombstoned);
+                match slots.get(&record.1.id) {
+                    Some(&slot) => records[slot] = record,
+                    None => {
+                        slots.insert(record.1.id.clone(), records.len());
+                        records.push(record);
+                    }
+                }
+            })?;
+
+            // A deletion marker is the newest record of a deleted document
+            records.retain(|(_, doc, _)| !doc.deleted);
+            let mut corrupt_ranges = self.corrupt_ranges();
+            let file_len = self.data_file.read().metadata()?.len();
+            if file_len > valid_end {
+                corrupt_ranges.push(CorruptRange {
+                    start: valid_end,
+                    end: file_len,
+                });
+            }
+
+            let documents: Vec<NVDocument> = records.iter().map(|(_, doc, _)| doc.clone()).collect();
+            self.rebuild_filters(&documents);
+            let report = RecoveryReport {
+                documents_salvaged: records.len(),
+                documents_restored: records.iter().filter(|(_, _, tombstoned)| *tombstoned).count(),
+                bytes_lost: corrupt_ranges.iter().map(|range| range.end - range.start).sum(),
+                corrupt_ranges,
             };
+            *index = records
+                .into_iter()
+                .map(|(position, doc, _)| (doc.id, position))
+                .collect();
+            self.renumber_versions(&index);
+            report
+        };
 
-            index.insert(doc.id.clone(), position);
+        let guard = self.compact_with(None, rewrite, |_| true, lock)?;
+        // The ranges referred to the replaced file
+        self.quarantine.write().clear();
+        Ok((report, guard))
+    }
+
+    /// Unreadable ranges of the data file found so far, in file order
+    ///
+    /// Ranges are found when the file is scanned (on open without a usable
+    /// checkpoint, or by `recover`) and when a read fails its checksum.
+    pub fn corrupt_ranges(&self) -> Vec<CorruptRange> {
+        self.quarantine.read().clone()
+    }
 
-            // Skip to next record
-            let skip_bytes = 4 + 8 + data_len as i64 + 1;
-            file.seek(SeekFrom::Current(skip_bytes))?;
+    /// Remember an unreadable range, unless it's already known
+    fn quarantine(&self, range: CorruptRange) {
+        let mut quarantine = self.quarantine.write();
+        if quarantine.iter().any(|known| known.start <= range.start && range.end <= known.end) {
+            return;
         }
+        quarantine.retain(|known| !(range.start <= known.start && known.end <= range.end));
+        quarantine.push(range);
+        quarantine.sort_by_key(|range| range.start);
+    }
+
+    /// Write a checkpoint of the in-memory index to `index.nvidx`
+    ///
+    /// Runs automatically every `checkpoint_interval` writes and when the
+    /// file manager is dropped. The snapshot is replaced atomically, so a
+    /// crash at any point leaves either the old or the new checkpoint.
+    pub fn checkpoint(&self) -> NVResult<()> {
+        self.ensure_writable()?;
+
+        // Hold the index lock so no write can slip in between snapshot and save
+        let index = self.index.write();
+        // The checkpoint must not reference records that could still be lost
+        self.commit.flush()?;
+        let data_len = self.data_file.read().metadata()?.len();
+
+        let checkpoint = PersistedIndex {
+            generation: self.index_generation.load(Ordering::SeqCst) + 1,
+            data_len,
+            last_record: *self.last_record.read(),
+            positions: index.clone(),
+            filters: self.filters.read().clone(),
+        };
+        index_file::save(&self.index_file_path(), &checkpoint)?;
 
+        self.index_generation.store(checkpoint.generation, Ordering::SeqCst);
+        self.checkpoint_len.store(data_len, Ordering::SeqCst);
+        self.writes_since_checkpoint.store(0, Ordering::SeqCst);
         Ok(())
     }
 
-    /// Calculate simple checksum (FNV-1a hash)
-    fn calculate_checksum(&self, data: &[u8]) -> u64 {
-        let mut hash: u64 = 0xcbf29ce484222325;
-        for &byte in data {
-            hash ^= byte as u64;
-            hash = hash.wrapping_mul(0x100000001b3);
+    /// Write the data and overflow files to `sink`, with a checkpoint of
+    /// the index so the copy opens without a rebuild
+    ///
+    /// Writers wait while the files are copied; readers don't. Returns the
+    /// length of the copied data file.
+    pub fn backup(&self, sink: &mut BackupSink) -> NVResult<u64> {
+        // Writers and the compaction swap take the index lock exclusively
+        let index = self.index.read();
+        self.commit.flush()?;
+
+        let checkpoint = PersistedIndex {
+            generation: self.index_generation.load(Ordering::SeqCst),
+            data_len: self.data_file.read().metadata()?.len(),
+            last_record: *self.last_record.read(),
+            positions: index.clone(),
+            filters: self.filters.read().clone(),
+        };
+        let data = File::open(self.base_path.join("data.nvdb"))?;
+        sink("data.nvdb", &mut data.take(checkpoint.data_len))?;
+        let overflow_path = self.base_path.join("overflow.nvblob");
+        if overflow_path.exists() {
+            sink("overflow.nvblob", &mut File::open(overflow_path)?)?;
         }
-        hash
+        sink("index.nvidx", &mut index_file::encode(&checkpoint)?.as_slice())?;
+
+        Ok(checkpoint.data_len)
     }
 
-    /// Get storage statistics
-    pub fn statistics(&self) -> StorageStats {
-        let index_count = self.index.read().len();
-        let file_size = self
-            .data_file
-            .read()
-            .metadata()
-            .map(|m| m.len())
-            .unwrap_or(0);
+    /// Whether `last_record` is still the record that ends at `offset`
+    ///
+    /// Only the length and checksum are compared since tombstone bytes may
+    /// have changed since the checkpoint was taken.
+    fn is_record_boundary(&self, offset: u64, last_record: Option<(StoragePosition, u64)>) -> NVResult<bool> {
+        let (position, checksum) = match last_record {
+            Some(last) => last,
+            None => return Ok(offset == 0),
+        };
+        if position.file_offset + 12 + position.length as u64 + 1 != offset {
+            return Ok(false);
+        }
 
-        StorageStats {
-            document_count: index_count,
-            file_size_bytes: file_size,
+        let mut header = [0u8; 12];
+        read_exact_at(&self.data_file.read(), &mut header, position.file_offset)?;
+
+        Ok(header[..4] == position.length.to_le_bytes() && header[4..] == checksum.to_le_bytes())
+    }
+
+    /// Generation number of the most recently loaded or written checkpoint
+    pub fn index_generation(&self) -> u64 {
+        self.index_generation.load(Ordering::SeqCst)
+    }
+
+    /// Count a write and checkpoint if the interval has been reached
+    ///
+    /// Must be called after the index and file locks are released.
+    fn note_write(&self) -> NVResult<()> {
+        self.note_writes(1)
+    }
+
+    /// Count several writes and checkpoint if the interval has been reached
+    fn note_writes(&self, count: usize) -> NVResult<()> {
+        let writes = self.writes_since_checkpoint.fetch_add(count, Ordering::SeqCst) + count;
+        if self.checkpoint_interval > 0 && writes >= self.checkpoint_interval {
+            self.checkpoint()?;
         }
+        Ok(())
     }
-}
 
-#[derive(Debug)]
-pub struct StorageStats {
-    pub document_count: usize,
-    pub file_size_bytes: u64,
+    /// Append a document to storage
+    pub fn append(&self, document: &NVDocument) -> NVResult<StoragePosition> {
+        self.append_checked(document, false)
+    }
+
+    /// Append a document, failing with `DuplicateId` if its ID is live
+    ///
+    /// The check and the write happen under the index lock, so two
+    /// concurrent inserts of the same ID can't both succeed.
+    pub fn insert(&self, document: &NVDocument) -> NVResult<StoragePosition> {
+        self.append_checked(document, true)
+    }
+
+    fn append_checked(&self, document: &NVDocument, reject_existing: bool) -> NVResult<StoragePosition> {
+        self.ensure_writable()?;
+
+        let (position, end) = {
+            let mut index = self.index.write();
+            if reject_existing && index.contains_key(&document.id) {
+                return Err(NeuralVaultError::DuplicateId(document.id.clone()));
+            }
+
+            let file = self.data_file.write();
+
+            let position = self.write_record(&file, document, false)?;
+            let end = self.mark_written(&file)?;
+
+            // Update index
+            index.insert(document.id.clone(), position);
+            self.next_version(&document.id, true);
+            self.track_in_filters(document);
+            (position, end)
+        };
+
+        self.commit_write(end)?;
+        self.note_write()?;
+        Ok(position)
+    }
+
+    /// Apply several writes under one index/file lock and a single fsync
+    ///
+    /// `apply` receives a `WriteBatch` for reading and writing documents; the
+    /// batch is synced once after it returns and the locks are released.
+    pub fn write_batch<R>(&self, apply: impl FnOnce(&mut WriteBatch) -> R) -> NVResult<R> {
+        self.ensure_writable()?;
+
+        let (result, writes, end) = {
+            let mut batch = WriteBatch {
+                manager: self,
+                index: self.index.write(),
+                file: self.data_file.write(),
+                writes: 0,
+                undo: None,
+            };
+            let result = apply(&mut batch);
+            let end = if batch.writes > 0 {
+                Some(self.mark_written(&batch.file)?)
+            } else {
+                None
+            };
+            (result, batch.writes, end)
+        };
+
+        if let Some(end) = end {
+            self.commit_write(end)?;
+            self.note_writes(writes)?;
+        }
+        Ok(result)
+    }
+
+    /// Like `write_batch`, undoing every write of the batch if `apply` fails
+    ///
+    /// On failure the data and overflow files are truncated back to where
+    /// the batch started and the versions it superseded or deleted are made
+    /// live again. `revert` is called, still under the locks, with each
+    /// changed document as the batch left it and as it was before, so state
+    /// derived from documents can be put back too.
+    pub fn write_batch_atomic<R>(
+        &self,
+        apply: impl FnOnce(&mut WriteBatch) -> NVResult<R>,
+        mut revert: impl FnMut(Option<&NVDocument>, Option<&NVDocument>),
+    ) -> NVResult<R> {
+        self.ensure_writable()?;
+
+        let (result, writes, end) = {
+            let index = self.index.write();
+            let file = self.data_file.write();
+            let undo = BatchUndo {
+                data_len: file.metadata()?.len(),
+                overflow_len: self.overflow_size()?,
+                last_record: *self.last_record.read(),
+                index: Vec::new(),
+                tombstoned: Vec::new(),
+            };
+            let mut batch = WriteBatch {
+                manager: self,
+                index,
+                file,
+                writes: 0,
+                undo: Some(undo),
+            };
+            let result = apply(&mut batch);
+            if result.is_err() {
+                batch.roll_back(&mut revert)?;
+                return result;
+            }
+            let end = if batch.writes > 0 {
+                Some(self.mark_written(&batch.file)?)
+            } else {
+                None
+            };
+            (result, batch.writes, end)
+        };
+
+        if let Some(end) = end {
+            self.commit_write(end)?;
+            self.note_writes(writes)?;
+        }
+        result
+    }
+
+    /// Write a new version of a document and tombstone the previous one
+    ///
+    /// Both writes happen under the index and file locks and are synced
+    /// together, so readers never observe two live versions of the document.
+    pub fn replace(&self, document: &NVDocument) -> NVResult<StoragePosition> {
+        self.ensure_writable()?;
+
+        let (position, end) = {
+            let mut index = self.index.write();
+            let previous = *index
+                .get(&document.id)
+                .ok_or_else(|| NeuralVaultError::DocumentNotFound(document.id.clone()))?;
+
+            let file = self.data_file.write();
+            let position = self.write_record(&file, document, false)?;
+            self.write_tombstone(&file, previous)?;
+            let end = self.mark_written(&file)?;
+
+            index.insert(document.id.clone(), position);
+            self.next_version(&document.id, true);
+            self.track_in_filters(document);
+            (position, end)
+        };
+
+        self.commit_write(end)?;
+        self.note_write()?;
+        Ok(position)
+    }
+
+    /// Make all writes durable, including ones buffered for the background
+    /// flusher
+    pub fn flush(&self) -> NVResult<()> {
+        self.ensure_writable()?;
+        self.commit.flush()
+    }
+
+    /// Wait until a write registered with `mark_written` is durable, or hand
+    /// it to the background flusher
+    fn commit_write(&self, end: u64) -> NVResult<()> {
+        match &self.flusher {
+            Some(flusher) => {
+                flusher.note_buffered(self.commit.buffered());
+                Ok(())
+            }
+            None => self.commit.sync(end),
+        }
+    }
+
+    /// Register everything written to `file` so far with the group commit
+    ///
+    /// Called under the file lock once a write is complete; the caller then
+    /// releases its locks and passes the returned offset to `commit_write`,
+    /// so concurrent writers share one fsync. Other readers can see
+    /// the write before that returns, but its writer doesn't report success
+    /// until it's durable.
+    fn mark_written(&self, file: &File) -> NVResult<u64> {
+        let end = file.metadata()?.len();
+        self.commit.written(end);
+        Ok(end)
+    }
+
+    /// Write a record at the end of the file without syncing
+    fn write_record(
+        &self,
+        file: &File,
+        document: &NVDocument,
+        tombstoned: bool,
+    ) -> NVResult<StoragePosition> {
+        // Serialize document; deletion markers don't count toward the quota
+        let data = self.encode(document, (!tombstoned).then_some(file))?;
+
+        let (position, checksum) = self.write_payload(file, &data, tombstoned)?;
+        *self.last_record.write() = Some((position, checksum));
+        Ok(position)
+    }
+
+    /// Fail with `QuotaExceeded` if a record of `len` bytes, plus `spilled`
+    /// bytes of overflow, doesn't fit next to an overflow file of
+    /// `overflow_len` bytes
+    fn check_quota(&self, file: &File, overflow_len: u64, len: u64, spilled: u64) -> NVResult<()> {
+        if self.max_size == 0 {
+            return Ok(());
+        }
+        let size = file.metadata()?.len() + overflow_len + spilled;
+        if size + 12 + len + 1 > self.max_size {
+            return Err(NeuralVaultError::QuotaExceeded(format!(
+                "Writing {} bytes would exceed the {}-byte limit",
+                len, self.max_size
+            )));
+        }
+        Ok(())
+    }
+
+    fn overflow_size(&self) -> NVResult<u64> {
+        match &self.overflow_file {
+            Some(file) => Ok(file.read().metadata()?.len()),
+            None => Ok(0),
+        }
+    }
+
+    /// Break down the space used by the data file, per collection
+    ///
+    /// Walks the file as it was when called, without holding up writers.
+    /// A record is live if it's the current version of its document; every
+    /// other record (superseded versions, deleted documents and deletion
+    /// markers) counts as tombstone bytes, which compaction would reclaim.
+    /// `archive_bytes` and `attachment_bytes` are left at 0.
+    pub fn disk_usage(&self) -> NVResult<DiskUsage> {
+        loop {
+            let compactions = self.compactions.load(Ordering::SeqCst);
+            let data_len = self.data_file.read().metadata()?.len();
+            let mut usage = DiskUsage::default();
+            self.walk_records(data_len, |position, data| {
+                let Ok(view) = record::RecordView::parse(&data) else {
+                    return;
+                };
+                let size = 12 + position.length as u64 + 1;
+                let collection = usage.collections.entry(view.collection().to_string()).or_default();
+                let live = self
+                    .index
+                    .read()
+                    .get(view.id())
+                    .is_some_and(|current| current.file_offset == position.file_offset);
+                if live {
+                    collection.documents += 1;
+                    collection.live_bytes += size;
+                    usage.live_bytes += size;
+                } else {
+                    collection.tombstone_bytes += size;
+                    usage.tombstone_bytes += size;
+                }
+            })?;
+            // A compaction meanwhile moved every record; count again
+            if self.compactions.load(Ordering::SeqCst) != compactions {
+                continue;
+            }
+
+            for collection in usage.collections.values_mut() {
+                collection.fragmentation =
+                    fragmentation(collection.tombstone_bytes, collection.live_bytes + collection.tombstone_bytes);
+            }
+            usage.overflow_bytes = self.overflow_size()?;
+            usage.index_bytes = std::fs::metadata(self.index_file_path()).map(|m| m.len()).unwrap_or(0);
+            usage.wal_bytes = data_len.saturating_sub(self.checkpoint_len.load(Ordering::SeqCst));
+            usage.fragmentation = fragmentation(data_len.saturating_sub(usage.live_bytes), data_len);
+            usage.total_bytes = data_len + usage.overflow_bytes + usage.index_bytes;
+            return Ok(usage);
+        }
+    }
+
+    /// Combined size of the data and overflow files
+    pub fn size(&self) -> NVResult<u64> {
+        Ok(self.data_file.read().metadata()?.len() + self.overflow_size()?)
+    }
+
+    /// Append an encoded record to `file`, returning its position and checksum
+    fn write_payload(
+        &self,
+        file: &File,
+        data: &[u8],
+        tombstoned: bool,
+    ) -> NVResult<(StoragePosition, u64)> {
+        let data_len = data.len() as u32;
+
+        // Calculate checksum
+        let checksum = self.calculate_checksum(data);
+
+        // Records are appended; the caller's file lock keeps the end stable
+        let offset = file.metadata()?.len();
+
+        // Write record: [length(4)][checksum(8)][data][tombstone(1)] as one
+        // buffered write
+        let mut record = Vec::with_capacity(4 + 8 + data.len() + 1);
+        record.extend_from_slice(&data_len.to_le_bytes());
+        record.extend_from_slice(&checksum.to_le_bytes());
+        record.extend_from_slice(data);
+        record.push(tombstoned as u8);
+        write_all_at(file, &record, offset)?;
+
+        let position = StoragePosition {
+            file_offset: offset,
+            length: data_len,
+        };
+        Ok((position, checksum))
+    }
+
+    /// Encode a document, spilling large values to the overflow file
+    ///
+    /// The size limit, and the quota if the record is to be appended to
+    /// `quota_file`, are checked before anything is written, and spilled
+    /// values are synced before the record that references them is written.
+    /// Encrypted fields are sealed first; writing to a collection with
+    /// encrypted fields requires the key, since a document read without it
+    /// is missing those fields.
+    fn encode(&self, document: &NVDocument, quota_file: Option<&File>) -> NVResult<Vec<u8>> {
+        let overflow_file = self.overflow_file.as_ref().ok_or(NeuralVaultError::ReadOnly)?;
+        let cipher = self.field_cipher.read();
+        let overflow = overflow_file.write();
+
+        let overflow_len = overflow.metadata()?.len();
+        let data = self.encode_with(document, cipher.as_ref(), &overflow, quota_file)?;
+        if overflow.metadata()?.len() > overflow_len {
+            overflow.sync_data()?;
+        }
+        Ok(data)
+    }
+
+    /// Encode a document, sealing with `cipher` and spilling to `overflow`
+    /// without syncing it, once the record and its spilled values are known
+    /// to fit the quota next to `quota_file`, if given
+    fn encode_with(
+        &self,
+        document: &NVDocument,
+        cipher: Option<&FieldCipher>,
+        overflow: &File,
+        quota_file: Option<&File>,
+    ) -> NVResult<Vec<u8>> {
+        let sealed_fields = self.sealed_fields.read();
+        let sealed = sealed_fields.get(&document.collection);
+        if sealed.is_some() && cipher.is_none() {
+            return Err(NeuralVaultError::EncryptionError(format!(
+                "Collection {} has encrypted fields; writes require the field key",
+                document.collection
+            )));
+        }
+
+        let spill_start = overflow.metadata()?.len();
+        let mut overflow_end = spill_start;
+
+        let mut spilled: Vec<Vec<u8>> = Vec::new();
+        let seal = |name: &str, bytes: &[u8]| match (sealed, cipher) {
+            (Some(fields), Some(cipher)) if fields.contains(name) => cipher.seal(bytes).map(Some),
+            _ => Ok(None),
+        };
+        let spill = |bytes: &[u8]| {
+            let offset = overflow_end;
+            overflow_end += bytes.len() as u64;
+            spilled.push(bytes.to_vec());
+            Ok(offset)
+        };
+        let data = record::encode_document_sealing(document, self.overflow_threshold, seal, spill)?;
+
+        let total_size = data.len() + spilled.iter().map(Vec::len).sum::<usize>();
+        if self.max_document_size > 0 && total_size > self.max_document_size {
+            return Err(NeuralVaultError::ValidationError(format!(
+                "Document {} is {} bytes, exceeding the {}-byte limit",
+                document.id, total_size, self.max_document_size
+            )));
+        }
+
+        if let Some(file) = quota_file {
+            self.check_quota(file, spill_start, data.len() as u64, overflow_end - spill_start)?;
+        }
+        write_all_at(overflow, &spilled.concat(), spill_start)?;
+
+        Ok(data)
+    }
+
+    /// Convert a payload from before the record format to a current record
+    ///
+    /// Databases written before the record format hold bincode records.
+    /// They are decoded through `legacy` and left in place until the next
+    /// compaction rewrites them; one that can't be decoded is an error
+    /// rather than being skipped.
+    fn upgrade(&self, position: StoragePosition, data: Vec<u8>) -> NVResult<Vec<u8>> {
+        if record::is_record(&data) {
+            return Ok(data);
+        }
+        let document = legacy::decode_document(&data).map_err(|e| {
+            NeuralVaultError::StorageError(format!(
+                "Record at offset {} predates the record format and can't be read: {}",
+                position.file_offset, e
+            ))
+        })?;
+        self.legacy_records.store(true, Ordering::SeqCst);
+        Ok(record::encode_document(&document))
+    }
+
+    /// Whether the data file still holds records from before the record
+    /// format
+    ///
+    /// Walks the whole file, failing if any of them can't be decoded.
+    pub fn has_legacy_records(&self) -> NVResult<bool> {
+        self.for_each_record(|_, _, _| {})?;
+        Ok(self.legacy_records.load(Ordering::SeqCst))
+    }
+
+    /// Decode a record payload, loading spilled values from the overflow file
+    fn decode(&self, data: &[u8]) -> NVResult<NVDocument> {
+        record::RecordView::parse(data)?
+            .with_overflow(&|offset, len| self.read_overflow(offset, len))
+            .with_opener(&|sealed| self.open_sealed(sealed))
+            .materialize()
+    }
+
+    /// Decode a record payload along with its version
+    fn decode_versioned(&self, record: &VersionedRecord) -> NVResult<NVDocument> {
+        let mut document = self.decode(&record.data)?;
+        document.version = record.version;
+        Ok(document)
+    }
+
+    /// Read a value that was spilled to the overflow file
+    pub fn read_overflow(&self, offset: u64, len: u32) -> NVResult<Vec<u8>> {
+        let overflow_file = self.overflow_file.as_ref().ok_or_else(|| {
+            NeuralVaultError::StorageError("Overflow file is missing".to_string())
+        })?;
+
+        let mut bytes = vec![0u8; len as usize];
+        read_exact_at(&overflow_file.read(), &mut bytes, offset)?;
+        Ok(bytes)
+    }
+
+    /// Set the tombstone byte of the record at a position without syncing
+    fn write_tombstone(&self, file: &File, position: StoragePosition) -> NVResult<()> {
+        // Tombstone byte follows length(4) + checksum(8) + data
+        let tombstone_offset = position.file_offset + 4 + 8 + position.length as u64;
+        write_all_at(file, &[1u8], tombstone_offset)?;
+        Ok(())
+    }
+
+    /// Add a document to its collection's bloom filter
+    fn track_in_filters(&self, document: &NVDocument) {
+        let document = self.without_sealed_fields(document);
+        self.filters
+            .write()
+            .entry(document.collection.clone())
+            .or_default()
+            .insert(&document);
+    }
+
+    /// A document minus its encrypted fields, which must never reach the
+    /// (persisted) bloom filters
+    fn without_sealed_fields<'d>(&self, document: &'d NVDocument) -> Cow<'d, NVDocument> {
+        match self.sealed_fields.read().get(&document.collection) {
+            Some(fields) if fields.iter().any(|field| document.data.contains_key(field)) => {
+                let mut stripped = document.clone();
+                stripped.data.retain(|field, _| !fields.contains(field));
+                Cow::Owned(stripped)
+            }
+            _ => Cow::Borrowed(document),
+        }
+    }
+
+    /// Read a document from storage
+    pub fn read(&self, id: &str) -> NVResult<NVDocument> {
+        let index = self.index.read();
+        let position = index
+            .get(id)
+            .ok_or_else(|| NeuralVaultError::DocumentNotFound(id.to_string()))?;
+
+        let mut document = self.read_at(*position)?;
+        document.version = self.current_version(id);
+        Ok(document)
+    }
+
+    /// Whether a live document with this ID exists
+    pub fn contains(&self, id: &str) -> bool {
+        self.index.read().contains_key(id)
+    }
+
+    /// Identifies the stored version of a document
+    ///
+    /// Every write of a document gives it a number no version has had
+    /// before, so a version seen once never comes back, even when the
+    /// record lands where an earlier one was. Documents not written since
+    /// the database opened are at 0.
+    pub fn version(&self, id: &str) -> Option<u64> {
+        let index = self.index.read();
+        index.contains_key(id).then(|| self.current_version(id))
+    }
+
+    /// The version of a live document; the index lock must be held
+    fn current_version(&self, id: &str) -> u64 {
+        self.versions.lock().get(id).copied().unwrap_or(0)
+    }
+
+    /// Give a document a new version, or forget it once it's deleted,
+    /// returning the version it had; the index lock must be held for
+    /// writing
+    fn next_version(&self, id: &str, live: bool) -> Option<u64> {
+        let mut versions = self.versions.lock();
+        match live {
+            true => {
+                let version = self.write_counter.fetch_add(1, Ordering::SeqCst) + 1;
+                versions.insert(id.to_string(), version)
+            }
+            false => versions.remove(id),
+        }
+    }
+
+    /// Give every live document a new version, after the index is rebuilt
+    /// from the data file
+    fn renumber_versions(&self, index: &HashMap<String, StoragePosition>) {
+        let mut versions = self.versions.lock();
+        versions.clear();
+        for id in index.keys() {
+            versions.insert(id.clone(), self.write_counter.fetch_add(1, Ordering::SeqCst) + 1);
+        }
+    }
+
+    /// Read document at specific position
+    fn read_at(&self, position: StoragePosition) -> NVResult<NVDocument> {
+        let data = self.read_raw_at(position)?;
+
+        // Deserialize
+        self.decode(&data)
+    }
+
+    /// Read the verified, still-serialized payload of the record at a position
+    ///
+    /// Positioned reads don't move the file cursor, so readers share the
+    /// file lock and run in parallel; only writers take it exclusively.
+    fn read_raw_at(&self, position: StoragePosition) -> NVResult<Vec<u8>> {
+        self.read_raw_from(&self.data_file.read(), position)
+    }
+
+    /// Read a verified record payload through an already-locked file
+    fn read_raw_from(&self, file: &File, position: StoragePosition) -> NVResult<Vec<u8>> {
+        self.read_payload(file, position, false)
+    }
+
+    /// Read a verified record payload, optionally even if it has since
+    /// been superseded or deleted
+    fn read_payload(&self, file: &File, position: StoragePosition, superseded: bool) -> NVResult<Vec<u8>> {
+        // Read length and checksum
+        let mut header = [0u8; 12];
+        read_exact_at(file, &mut header, position.file_offset)?;
+        let data_len = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let expected_checksum = u64::from_le_bytes(header[4..].try_into().unwrap());
+
+        // Read data and tombstone
+        let mut data = vec![0u8; data_len as usize + 1];
+        read_exact_at(file, &mut data, position.file_offset + 12)?;
+        let tombstone = data.pop().unwrap_or(0);
+
+        // Verify checksum
+        let actual_checksum = self.calculate_checksum(&data);
+        if actual_checksum != expected_checksum {
+            self.quarantine(CorruptRange {
+                start: position.file_offset,
+                end: position.file_offset + 12 + data_len as u64 + 1,
+            });
+            return Err(NeuralVaultError::StorageError(
+                "Checksum mismatch - data corruption detected".to_string(),
+            ));
+        }
+
+        // Check if deleted
+        if tombstone == 1 && !superseded {
+            return Err(NeuralVaultError::DocumentNotFound(
+                "Document has been deleted".to_string(),
+            ));
+        }
+
+        self.upgrade(position, data)
+    }
+
+    /// Mark a document as deleted (soft delete)
+    ///
+    /// Besides setting the record's tombstone in place, a deletion marker is
+    /// appended so the delete can be replayed from the end of the file after
+    /// a checkpoint. The document is also dropped from the index so
+    /// statistics only count live documents.
+    pub fn mark_deleted(&self, id: &str) -> NVResult<()> {
+        self.ensure_writable()?;
+
+        let end = {
+            let mut index = self.index.write();
+            let position = *index
+                .get(id)
+                .ok_or_else(|| NeuralVaultError::DocumentNotFound(id.to_string()))?;
+
+            let file = self.data_file.write();
+            let marker = self.deletion_marker(&file, id, position)?;
+            self.write_tombstone(&file, position)?;
+            self.write_record(&file, &marker, true)?;
+            let end = self.mark_written(&file)?;
+
+            index.remove(id);
+            self.next_version(id, false);
+            end
+        };
+
+        self.commit_write(end)?;
+        self.note_write()
+    }
+
+    /// Record appended when the document at `position` is deleted
+    ///
+    /// Markers carry the ID and collection of the deleted document but no
+    /// data.
+    fn deletion_marker(&self, file: &File, id: &str, position: StoragePosition) -> NVResult<NVDocument> {
+        let data = self.read_raw_from(file, position)?;
+        let collection = record::RecordView::parse(&data)?.collection().to_string();
+        let mut marker = NVDocument::new(id.to_string(), collection, HashMap::new());
+        marker.deleted = true;
+        Ok(marker)
+    }
+
+    /// Freeze the current document positions for consistent reads
+    pub fn snapshot(self: &Arc<Self>) -> StorageSnapshot {
+        // Compaction checks the count under the index lock, so it can't
+        // start between the copy and the increment
+        let index = self.index.read();
+        self.snapshots.fetch_add(1, Ordering::SeqCst);
+        StorageSnapshot {
+            manager: self.clone(),
+            positions: index.clone(),
+            versions: self.versions.lock().clone(),
+        }
+    }
+
+    /// Whether any snapshot is open, which makes compaction fail
+    pub fn snapshots_open(&self) -> bool {
+        self.snapshots.load(Ordering::SeqCst) > 0
+    }
+
+    /// Scan all non-deleted documents in a collection
+    pub fn scan_collection(&self, collection: &str) -> NVResult<Vec<NVDocument>> {
+        Ok(self
+            .read_all_raw()?
+            .iter()
+            .filter_map(|record| self.decode_versioned(record).ok()) // Skip corrupted documents
+            .filter(|doc| doc.collection == collection && !doc.deleted)
+            .collect())
+    }
+
+    /// Whether a collection has any non-deleted documents, judged from
+    /// record headers alone
+    pub fn has_collection(&self, collection: &str) -> NVResult<bool> {
+        Ok(self.read_all_raw()?.iter().any(|record| {
+            record.view().is_ok_and(|view| view.collection() == collection && !view.deleted())
+        }))
+    }
+
+    /// Up to `n` non-deleted documents of a collection, chosen uniformly at
+    /// random
+    ///
+    /// Records are filtered by their header and only the chosen ones are
+    /// decoded.
+    pub fn sample_collection(&self, collection: &str, n: usize) -> NVResult<Vec<NVDocument>> {
+        let records = self.read_all_raw()?.into_iter().filter(|record| {
+            record.view().is_ok_and(|view| view.collection() == collection && !view.deleted())
+        });
+        sampling::reservoir(records, n, &mut Rng::new())
+            .iter()
+            .map(|record| self.decode_versioned(record))
+            .collect()
+    }
+
+    /// Read the serialized payloads of all indexed records
+    ///
+    /// Deserialization is left to the caller so it can be spread across
+    /// worker threads. Corrupted or deleted records are skipped. Positions
+    /// and versions are snapshotted together without blocking writers, so
+    /// the read is retried if a compaction moved every record in the
+    /// meantime.
+    pub fn read_all_raw(&self) -> NVResult<Vec<VersionedRecord>> {
+        loop {
+            let compactions = self.compactions.load(Ordering::SeqCst);
+            let positions: Vec<(StoragePosition, u64)> = {
+                let index = self.index.read();
+                let versions = self.versions.lock();
+                index
+                    .iter()
+                    .map(|(id, position)| (*position, versions.get(id).copied().unwrap_or(0)))
+                    .collect()
+            };
+
+            let records = positions
+                .into_iter()
+                .filter_map(|(position, version)| {
+                    Some(VersionedRecord { data: self.read_raw_at(position).ok()?, version })
+                })
+                .collect();
+            if self.compactions.load(Ordering::SeqCst) == compactions {
+                return Ok(records);
+            }
+        }
+    }
+
+    /// Walk every record in the data file in append order
+    ///
+    /// `visit` receives each record's position, tombstone flag and payload.
+    /// Returns the offset just past the last complete record.
+    fn for_each_record<F>(&self, visit: F) -> NVResult<u64>
+    where
+        F: FnMut(StoragePosition, bool, Vec<u8>),
+    {
+        self.for_each_record_from(0, visit)
+    }
+
+    /// Walk records starting at `offset`, which must be a record boundary
+    ///
+    /// A record that fails its checksum or runs past the end of the file
+    /// is skipped by resynchronizing on the next intact record, and the
+    /// skipped range is quarantined. If no intact record follows, the rest
+    /// of the file is a torn write and ends the walk. Returns the offset just
+    /// past the last intact record.
+    fn for_each_record_from<F>(&self, offset: u64, mut visit: F) -> NVResult<u64>
+    where
+        F: FnMut(StoragePosition, bool, Vec<u8>),
+    {
+        let file = self.data_file.read();
+        let file_len = file.metadata()?.len();
+
+        let mut offset = offset;
+        while offset < file_len {
+            let record = Self::read_record_at(&file, offset, file_len)?
+                .filter(|record| self.calculate_checksum(&record.data) == record.checksum);
+            let Some(record) = record else {
+                let Some(next) = self.resync(&file, offset + 1, file_len)? else {
+                    break;
+                };
+                self.quarantine(CorruptRange { start: offset, end: next });
+                offset = next;
+                continue;
+            };
+            offset = record.end();
+            *self.last_record.write() = Some((record.position, record.checksum));
+
+            let data = self.upgrade(record.position, record.data)?;
+            visit(record.position, record.tombstoned, data);
+        }
+        Ok(offset)
+    }
+
+    /// Walk the intact records that end by `end`, taking the data file
+    /// lock one record at a time
+    ///
+    /// Unlike `for_each_record`, this is only an observer: damaged ranges
+    /// are skipped without being quarantined, and the last record seen
+    /// isn't remembered as the end of the log.
+    fn walk_records<F>(&self, end: u64, mut visit: F) -> NVResult<()>
+    where
+        F: FnMut(StoragePosition, Vec<u8>),
+    {
+        let mut offset = 0;
+        while offset < end {
+            let file = self.data_file.read();
+            let record = Self::read_record_at(&file, offset, end)?
+                .filter(|record| self.calculate_checksum(&record.data) == record.checksum);
+            let Some(record) = record else {
+                match self.resync(&file, offset + 1, end)? {
+                    Some(next) => offset = next,
+                    None => break,
+                }
+                continue;
+            };
+            drop(file);
+            offset = record.end();
+
+            let data = self.upgrade(record.position, record.data)?;
+            visit(record.position, data);
+        }
+        Ok(())
+    }
+
+    /// Offset of the first intact record at or after `from`, if any
+    ///
+    /// Every offset is tried as a record header; one is accepted if the
+    /// record fits in the file and within `max_document_size`, its checksum
+    /// matches and its tombstone byte is 0 or 1. Oversized lengths are
+    /// rejected before anything is read, so a long run of garbage costs one
+    /// pass over it rather than a read per offset.
+    fn resync(&self, file: &File, from: u64, file_len: u64) -> NVResult<Option<u64>> {
+        let mut chunk = vec![0u8; RESYNC_CHUNK];
+        let mut start = from;
+        while start + 13 <= file_len {
+            let len = (file_len - start).min(RESYNC_CHUNK as u64) as usize;
+            read_exact_at(file, &mut chunk[..len], start)?;
+
+            for i in 0..=len - 13 {
+                let offset = start + i as u64;
+                let data_len = u32::from_le_bytes(chunk[i..i + 4].try_into().unwrap()) as usize;
+                if offset + 12 + data_len as u64 + 1 > file_len
+                    || (self.max_document_size > 0 && data_len > self.max_document_size)
+                {
+                    continue;
+                }
+                let checksum = u64::from_le_bytes(chunk[i + 4..i + 12].try_into().unwrap());
+
+                let intact = if i + 12 + data_len < len {
+                    let data = &chunk[i + 12..i + 12 + data_len];
+                    chunk[i + 12 + data_len] <= 1 && self.calculate_checksum(data) == checksum
+                } else {
+                    let mut record = vec![0u8; data_len + 1];
+                    read_exact_at(file, &mut record, offset + 12)?;
+                    let tombstone = record.pop().unwrap_or(0);
+                    tombstone <= 1 && self.calculate_checksum(&record) == checksum
+                };
+                if intact {
+                    return Ok(Some(offset));
+                }
+            }
+            start += (len - 12) as u64;
+        }
+        Ok(None)
+    }
+
+    /// Read the record starting at `offset`
+    ///
+    /// Returns `None` at the end of the file or at a torn write.
+    fn read_record_at(file: &File, offset: u64, file_len: u64) -> NVResult<Option<RawRecord>> {
+        // Read length and checksum
+        let mut header = [0u8; 12];
+        match read_exact_at(file, &mut header, offset) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        let data_len = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let checksum = u64::from_le_bytes(header[4..].try_into().unwrap());
+
+        // Torn write at the end of the file
+        if offset + 12 + data_len as u64 + 1 > file_len {
+            return Ok(None);
+        }
+
+        // Read data and tombstone
+        let mut data = vec![0u8; data_len as usize + 1];
+        read_exact_at(file, &mut data, offset + 12)?;
+        let tombstone = data.pop().unwrap_or(0);
+
+        Ok(Some(RawRecord {
+            position: StoragePosition {
+                file_offset: offset,
+                length: data_len,
+            },
+            checksum,
+            tombstoned: tombstone == 1,
+            data,
+        }))
+    }
+
+    /// Records written at or after `offset`, in append order
+    ///
+    /// Each record is returned with its offset: a new document version, or a
+    /// deletion marker (`deleted` set, no data) for a delete. At most
+    /// `limit` records are read; the second value is the offset to continue
+    /// from. `offset` must be 0, the end of the durable part of the file or
+    /// the start of a record. Records are only returned once they're
+    /// durable, so a crash can't take back what a reader has seen. Offsets
+    /// are only meaningful until the next compaction.
+    pub fn records_since(&self, offset: u64, limit: usize) -> NVResult<LogPage<NVDocument>> {
+        self.log_since(offset, limit, |data| self.decode(&data))
+    }
+
+    /// Like `records_since`, returning record payloads instead of documents
+    ///
+    /// Spilled values are loaded in line, so a payload stands on its own,
+    /// and encrypted fields stay sealed. They're for copies of the data
+    /// that may leave the database, such as incremental backups.
+    pub fn sealed_records_since(&self, offset: u64, limit: usize) -> NVResult<LogPage<Vec<u8>>> {
+        self.log_since(offset, limit, |data| {
+            record::RecordView::parse(&data)?
+                .with_overflow(&|offset, len| self.read_overflow(offset, len))
+                .inlined()
+        })
+    }
+
+    /// Read the log for `records_since`, converting each payload with `convert`
+    fn log_since<T>(
+        &self,
+        offset: u64,
+        limit: usize,
+        convert: impl Fn(Vec<u8>) -> NVResult<T>,
+    ) -> NVResult<LogPage<T>> {
+        let file = self.data_file.read();
+        let durable = self.commit.durable().min(file.metadata()?.len());
+
+        let not_a_record = || {
+            NeuralVaultError::StorageError(format!("Offset {} is not the start of a record", offset))
+        };
+        if offset > durable {
+            return Err(not_a_record());
+        }
+
+        let mut records = Vec::new();
+        let mut next = offset;
+        while records.len() < limit {
+            let Some(record) = Self::read_record_at(&file, next, durable)? else {
+                // Syncs end on record boundaries, so a partial record can
+                // only mean the offset is wrong
+                if next == offset && offset < durable {
+                    return Err(not_a_record());
+                }
+                break;
+            };
+            let valid = self.calculate_checksum(&record.data) == record.checksum;
+            if !valid && next == offset {
+                return Err(not_a_record());
+            }
+
+            next = record.end();
+            let converted = valid
+                .then(|| self.upgrade(record.position, record.data).and_then(&convert).ok())
+                .flatten();
+            if let Some(converted) = converted {
+                records.push((record.position.file_offset, converted));
+            }
+        }
+
+        Ok((records, next))
+    }
+
+    /// Cut a torn record off the end of the file
+    ///
+    /// Without this, new appends would land after the partial record and be
+    /// unreachable by the next scan.
+    fn truncate_torn_tail(&self, valid_end: u64) -> NVResult<()> {
+        // Read-only handles just ignore the partial record
+        if self.read_only {
+            return Ok(());
+        }
+
+        let file = self.data_file.write();
+        if file.metadata()?.len() > valid_end {
+            file.set_len(valid_end)?;
+            file.sync_all()?;
+            self.commit.reset(file.try_clone()?, valid_end);
+        }
+        Ok(())
+    }
+
+    /// Get the newest live version of every document
+    pub fn scan_all(&self) -> NVResult<Vec<NVDocument>> {
+        self.scan_all_versions(false)
+    }
+
+    /// Scan every document in the data file
+    ///
+    /// By default only the newest version of each ID is returned, and an ID
+    /// whose newest record is tombstoned is omitted even if older versions are
+    /// still live. With `include_history` every live record is returned in
+    /// append order, including superseded versions; this is intended for
+    /// recovery and debugging tools rather than normal reads.
+    pub fn scan_all_versions(&self, include_history: bool) -> NVResult<Vec<NVDocument>> {
+        if include_history {
+            let mut documents = Vec::new();
+            self.for_each_record(|_, deleted, data| {
+                if !deleted {
+                    if let Ok(doc) = self.decode(&data) {
+                        documents.push(doc);
+                    }
+                }
+            })?;
+            return Ok(documents);
+        }
+
+        Ok(self
+            .latest_records()?
+            .into_iter()
+            .map(|(_, doc)| doc)
+            .collect())
+    }
+
+    /// Newest live record per ID, in order of first appearance
+    ///
+    /// Records are visited in append order and the last one written for an
+    /// ID wins, whether it is live or tombstoned.
+    fn latest_records(&self) -> NVResult<Vec<(StoragePosition, NVDocument)>> {
+        self.latest_records_with_end().map(|(records, _)| records)
+    }
+
+    /// Like `latest_records`, also returning the end of the last complete record
+    fn latest_records_with_end(&self) -> NVResult<(Vec<(StoragePosition, NVDocument)>, u64)> {
+        let mut slots: HashMap<String, usize> = HashMap::new();
+        let mut records: Vec<Option<(StoragePosition, NVDocument)>> = Vec::new();
+
+        let valid_end = self.for_each_record(|position, deleted, data| {
+            let doc = match self.decode(&data) {
+                Ok(doc) => doc,
+                Err(_) => return,
+            };
+            let slot = *slots.entry(doc.id.clone()).or_insert_with(|| {
+                records.push(None);
+                records.len() - 1
+            });
+            records[slot] = if deleted { None } else { Some((position, doc)) };
+        })?;
+
+        Ok((records.into_iter().flatten().collect(), valid_end))
+    }
+
+    /// Rebuild index from storage file
+    ///
+    /// If several versions of a document exist the most recently written one
+    /// wins.
+    pub fn rebuild_index(&self) -> NVResult<()> {
+        let (records, valid_end) = self.latest_records_with_end()?;
+        self.truncate_torn_tail(valid_end)?;
+
+        let documents: Vec<NVDocument> = records.iter().map(|(_, doc)| doc.clone()).collect();
+        self.rebuild_filters(&documents);
+
+        let mut index = self.index.write();
+        *index = records
+            .into_iter()
+            .map(|(position, doc)| (doc.id, position))
+            .collect();
+        self.renumber_versions(&index);
+
+        Ok(())
+    }
+
+    /// Rebuild the per-collection bloom filters, sized to the current data
+    fn rebuild_filters(&self, documents: &[NVDocument]) {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for doc in documents {
+            *counts.entry(doc.collection.as_str()).or_default() += 1;
+        }
+
+        let mut filters: CollectionFilters = counts
+            .into_iter()
+            .map(|(collection, count)| {
+                // Leave headroom for growth before the false-positive rate degrades
+                let capacity = (count * 2).max(CollectionFilter::EXPECTED_DOCUMENTS);
+                (collection.to_string(), CollectionFilter::with_capacity(capacity))
+            })
+            .collect();
+
+        for doc in documents {
+            if let Some(filter) = filters.get_mut(&doc.collection) {
+                filter.insert(&self.without_sealed_fields(doc));
+            }
+        }
+
+        *self.filters.write() = filters;
+    }
+
+    /// Whether a collection may contain a document with this ID
+    ///
+    /// A `false` result is definitive and lets callers skip reading the file.
+    pub fn may_contain_id(&self, collection: &str, id: &str) -> bool {
+        self.filters
+            .read()
+            .get(collection)
+            .is_some_and(|filter| filter.may_contain_id(id))
+    }
+
+    /// Whether a collection may contain a document with `field == value`
+    ///
+    /// A `false` result is definitive and lets callers skip reading the file.
+    /// Encrypted fields and metadata pseudo-fields are not tracked and always
+    /// report a possible match.
+    pub fn may_contain_value(&self, collection: &str, field: &str, value: &NVValue) -> bool {
+        if models::is_metadata_field(field) || self.is_sealed(collection, field) {
+            return true;
+        }
+        self.filters
+            .read()
+            .get(collection)
+            .is_some_and(|filter| filter.may_contain_value(field, value))
+    }
+
+    /// Calculate simple checksum (FNV-1a hash)
+    fn calculate_checksum(&self, data: &[u8]) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for &byte in data {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+
+    /// Get storage statistics
+    pub fn statistics(&self) -> StorageStats {
+        let index_count = self.index.read().len();
+        let file_size = self
+            .data_file
+            .read()
+            .metadata()
+            .map(|m| m.len())
+            .unwrap_or(0);
+
+        StorageStats {
+            document_count: index_count,
+            file_size_bytes: file_size,
+        }
+    }
+}
+
+/// Writes applied while holding the index and data file locks
+///
+/// Created by `FileManager::write_batch`; nothing is synced until the batch
+/// completes.
+pub struct WriteBatch<'a> {
+    manager: &'a FileManager,
+    index: RwLockWriteGuard<'a, HashMap<String, StoragePosition>>,
+    file: RwLockWriteGuard<'a, File>,
+    writes: usize,
+    /// What to restore if an atomic batch fails
+    undo: Option<BatchUndo>,
+}
+
+/// The storage state an atomic batch started from, and its changes to it
+struct BatchUndo {
+    data_len: u64,
+    overflow_len: u64,
+    last_record: Option<(StoragePosition, u64)>,
+    /// Index entries in the order they were changed, with their old value
+    /// and version
+    index: Vec<(String, Option<StoragePosition>, Option<u64>)>,
+    /// Records the batch tombstoned
+    tombstoned: Vec<StoragePosition>,
+}
+
+impl WriteBatch<'_> {
+    /// Whether a live document with this ID exists
+    pub fn contains(&self, id: &str) -> bool {
+        self.index.contains_key(id)
+    }
+
+    /// Length of the data file, including this batch's writes so far
+    pub fn data_len(&self) -> NVResult<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    /// Identifies the stored version of a document, as `FileManager::version`
+    pub fn version(&self, id: &str) -> Option<u64> {
+        self.index.contains_key(id).then(|| self.manager.current_version(id))
+    }
+
+    /// Size of a document's current record in the data file
+    pub fn stored_size(&self, id: &str) -> Option<u64> {
+        self.index.get(id).map(|position| 12 + position.length as u64 + 1)
+    }
+
+    /// Bytes taken by the current versions of all documents, which is
+    /// roughly what compaction would shrink the data file to
+    pub fn live_size(&self) -> u64 {
+        self.index
+            .values()
+            .map(|position| 12 + position.length as u64 + 1)
+            .sum()
+    }
+
+    /// Read the current version of a document
+    pub fn read(&mut self, id: &str) -> NVResult<NVDocument> {
+        let position = *self
+            .index
+            .get(id)
+            .ok_or_else(|| NeuralVaultError::DocumentNotFound(id.to_string()))?;
+        let data = self.manager.read_raw_from(&self.file, position)?;
+        let mut document = self.manager.decode(&data)?;
+        document.version = self.manager.current_version(id);
+        Ok(document)
+    }
+
+    /// Read every live document in a collection
+    ///
+    /// Holding the batch's locks, the result is consistent with the writes
+    /// made in the same batch and can't be raced by other writers.
+    pub fn scan_collection(&mut self, collection: &str) -> NVResult<Vec<NVDocument>> {
+        let positions: Vec<StoragePosition> = self.index.values().copied().collect();
+        let mut documents = Vec::new();
+        for position in positions {
+            let data = self.manager.read_raw_from(&self.file, position)?;
+            let mut document = self.manager.decode(&data)?;
+            if document.collection == collection && !document.deleted {
+                document.version = self.manager.current_version(&document.id);
+                documents.push(document);
+            }
+        }
+        Ok(documents)
+    }
+
+    /// Write a new document, failing with `DuplicateId` if its ID is live
+    pub fn insert(&mut self, document: &NVDocument) -> NVResult<()> {
+        if self.contains(&document.id) {
+            return Err(NeuralVaultError::DuplicateId(document.id.clone()));
+        }
+        self.put(document)
+    }
+
+    /// Write a document, tombstoning its previous version if there is one
+    pub fn put(&mut self, document: &NVDocument) -> NVResult<()> {
+        let position = self.manager.write_record(&self.file, document, false)?;
+        self.index_put(document, position)
+    }
+
+    /// Write a record payload from `sealed_records_since` as it is,
+    /// tombstoning the document's previous version if there is one
+    ///
+    /// Encrypted fields are written still sealed, so restoring them doesn't
+    /// need the field key. Values stay in line however large they are.
+    pub fn put_record(&mut self, data: &[u8]) -> NVResult<()> {
+        let document = self.manager.decode(data)?;
+        if document.deleted {
+            return Err(NeuralVaultError::StorageError(format!(
+                "Record for {} is a deletion marker",
+                document.id
+            )));
+        }
+        let (position, checksum) = self.manager.write_payload(&self.file, data, false)?;
+        *self.manager.last_record.write() = Some((position, checksum));
+        self.index_put(&document, position)
+    }
+
+    /// Make a newly written record the current version of its document
+    fn index_put(&mut self, document: &NVDocument, position: StoragePosition) -> NVResult<()> {
+        let previous = self.index.insert(document.id.clone(), position);
+        let version = self.manager.next_version(&document.id, true);
+        if let Some(undo) = &mut self.undo {
+            undo.index.push((document.id.clone(), previous, version));
+            undo.tombstoned.extend(previous);
+        }
+        if let Some(previous) = previous {
+            self.manager.write_tombstone(&self.file, previous)?;
+        }
+        self.manager.track_in_filters(document);
+        self.writes += 1;
+        Ok(())
+    }
+
+    /// Delete a document, appending a deletion marker
+    pub fn delete(&mut self, id: &str) -> NVResult<()> {
+        let position = *self
+            .index
+            .get(id)
+            .ok_or_else(|| NeuralVaultError::DocumentNotFound(id.to_string()))?;
+
+        let marker = self.manager.deletion_marker(&self.file, id, position)?;
+        self.manager.write_tombstone(&self.file, position)?;
+        self.manager.write_record(&self.file, &marker, true)?;
+        self.index.remove(id);
+        let version = self.manager.next_version(id, false);
+        if let Some(undo) = &mut self.undo {
+            undo.index.push((id.to_string(), Some(position), version));
+            undo.tombstoned.push(position);
+        }
+        self.writes += 1;
+        Ok(())
+    }
+
+    /// Undo an atomic batch's writes, passing each changed document's
+    /// batch and original versions to `revert`
+    ///
+    /// Bloom filters keep what the batch added; they only ever over-report.
+    fn roll_back(&mut self, revert: &mut impl FnMut(Option<&NVDocument>, Option<&NVDocument>)) -> NVResult<()> {
+        let Some(undo) = self.undo.take() else {
+            return Ok(());
+        };
+
+        // The first change to each document holds its original position
+        let mut original: HashMap<&str, Option<StoragePosition>> = HashMap::new();
+        for (id, position, _) in &undo.index {
+            original.entry(id.as_str()).or_insert(*position);
+        }
+        for (id, position) in &original {
+            let written = match self.index.get(*id) {
+                Some(current) => Some(self.read_version(*current)?),
+                None => None,
+            };
+            let before = match position {
+                Some(position) => Some(self.read_version(*position)?),
+                None => None,
+            };
+            revert(written.as_ref(), before.as_ref());
+        }
+
+        // Documents go back to the versions they had, which nobody could
+        // have seen change while the batch held the index
+        let mut versions = self.manager.versions.lock();
+        for (id, position, version) in undo.index.into_iter().rev() {
+            match version {
+                Some(version) => versions.insert(id.clone(), version),
+                None => versions.remove(&id),
+            };
+            match position {
+                Some(position) => self.index.insert(id, position),
+                None => self.index.remove(&id),
+            };
+        }
+        drop(versions);
+        for position in undo.tombstoned {
+            if position.file_offset < undo.data_len {
+                let tombstone_offset = position.file_offset + 4 + 8 + position.length as u64;
+                write_all_at(&self.file, &[0u8], tombstone_offset)?;
+            }
+        }
+        self.file.set_len(undo.data_len)?;
+        self.file.sync_data()?;
+        if let Some(overflow) = &self.manager.overflow_file {
+            let overflow = overflow.write();
+            overflow.set_len(undo.overflow_len)?;
+            overflow.sync_data()?;
+        }
+        *self.manager.last_record.write() = undo.last_record;
+        self.writes = 0;
+        Ok(())
+    }
+
+    /// Read a record even if the batch has since superseded it
+    fn read_version(&self, position: StoragePosition) -> NVResult<NVDocument> {
+        let data = self.manager.read_payload(&self.file, position, true)?;
+        self.manager.decode(&data)
+    }
+}
+
+/// Document positions frozen at a point in time
+///
+/// Records are never changed once written (only their tombstone byte), so
+/// reading at these positions returns documents as they were when the
+/// snapshot was taken, whatever has been written since. Compaction is
+/// refused while any snapshot is open.
+pub struct StorageSnapshot {
+    manager: Arc<FileManager>,
+    positions: HashMap<String, StoragePosition>,
+    /// Versions of the documents written since the database opened
+    versions: HashMap<String, u64>,
+}
+
+impl StorageSnapshot {
+    /// The storage this snapshot reads from
+    pub fn manager(&self) -> &FileManager {
+        &self.manager
+    }
+
+    /// Read a document as of the snapshot
+    pub fn read(&self, id: &str) -> NVResult<NVDocument> {
+        let position = *self
+            .positions
+            .get(id)
+            .ok_or_else(|| NeuralVaultError::DocumentNotFound(id.to_string()))?;
+        let data = self.read_raw_at(position)?;
+        let mut document = self.manager.decode(&data)?;
+        document.version = self.version(id);
+        Ok(document)
+    }
+
+    /// IDs of a collection's documents in the snapshot, in ID order,
+    /// judged from record headers alone
+    pub fn collection_ids(&self, collection: &str) -> NVResult<Vec<String>> {
+        let mut ids = Vec::new();
+        for (id, position) in &self.positions {
+            let data = self.read_raw_at(*position)?;
+            let view = record::RecordView::parse(&data)?;
+            if view.collection() == collection && !view.deleted() {
+                ids.push(id.clone());
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Visit the serialized payload of every document in the snapshot, one
+    /// at a time
+    pub fn for_each_raw(&self, mut visit: impl FnMut(&VersionedRecord) -> NVResult<()>) -> NVResult<()> {
+        for (id, position) in &self.positions {
+            visit(&VersionedRecord { data: self.read_raw_at(*position)?, version: self.version(id) })?;
+        }
+        Ok(())
+    }
+
+    /// Serialized payloads of every document in the snapshot
+    pub fn read_all_raw(&self) -> NVResult<Vec<VersionedRecord>> {
+        self.positions
+            .iter()
+            .map(|(id, position)| Ok(VersionedRecord { data: self.read_raw_at(*position)?, version: self.version(id) }))
+            .collect()
+    }
+
+    fn version(&self, id: &str) -> u64 {
+        self.versions.get(id).copied().unwrap_or(0)
+    }
+
+    fn read_raw_at(&self, position: StoragePosition) -> NVResult<Vec<u8>> {
+        self.manager.read_payload(&self.manager.data_file.read(), position, true)
+    }
+}
+
+impl Drop for StorageSnapshot {
+    fn drop(&mut self) {
+        self.manager.snapshots.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Drop for FileManager {
+    fn drop(&mut self) {
+        if let Some(flusher) = self.flusher.take() {
+            drop(flusher);
+            let _ = self.commit.flush();
+        }
+        if !self.read_only && self.writes_since_checkpoint.load(Ordering::SeqCst) > 0 {
+            // Best effort: the next open replays whatever the checkpoint misses
+            let _ = self.checkpoint();
+        }
+    }
+}
+
+/// Where a database's disk space goes, from `FileManager::disk_usage`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DiskUsage {
+    /// Breakdown of the data file by collection
+    pub collections: HashMap<String, CollectionUsage>,
+    /// Current versions of documents
+    pub live_bytes: u64,
+    /// Superseded versions, deleted documents and deletion markers
+    pub tombstone_bytes: u64,
+    /// Index checkpoint (`index.nvidx`)
+    pub index_bytes: u64,
+    /// Data written since the last index checkpoint, replayed on open
+    pub wal_bytes: u64,
+    /// Field values stored out of line, live or not
+    pub overflow_bytes: u64,
+    /// Archive segments
+    pub archive_bytes: u64,
+    /// Attachment chunks and manifests
+    pub attachment_bytes: u64,
+    /// Data, overflow, index, archive and attachment files together
+    pub total_bytes: u64,
+    /// Share of the data file compaction would reclaim, from 0 to 1
+    pub fragmentation: f64,
+}
+
+/// Space used by one collection in the data file
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CollectionUsage {
+    pub documents: usize,
+    pub live_bytes: u64,
+    pub tombstone_bytes: u64,
+    /// Share of the collection's bytes that are tombstones
+    pub fragmentation: f64,
+}
+
+/// `dead` as a share of `total`, or 0 for an empty file
+fn fragmentation(dead: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        dead as f64 / total as f64
+    }
+}
+
+#[derive(Debug)]
+pub struct StorageStats {
+    pub document_count: usize,
+    pub file_size_bytes: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn document(id: &str, version: f64) -> NVDocument {
+        let mut data = HashMap::new();
+        data.insert("version".to_string(), NVValue::Number(version));
+        NVDocument::new(id.to_string(), "items".to_string(), data)
+    }
+
+    /// Drop a manager without the final checkpoint, as if the process died
+    fn crash(storage: FileManager) {
+        storage.writes_since_checkpoint.store(0, Ordering::SeqCst);
+        drop(storage);
+    }
+
+    #[test]
+    fn test_replace_tombstones_previous_version() {
+        let dir = tempdir().unwrap();
+        let storage = FileManager::new(dir.path().to_str().unwrap()).unwrap();
+
+        storage.append(&document("a", 1.0)).unwrap();
+        storage.append(&document("b", 1.0)).unwrap();
+        storage.replace(&document("a", 2.0)).unwrap();
+        storage.replace(&document("a", 3.0)).unwrap();
+
+        let documents = storage.scan_all().unwrap();
+        assert_eq!(documents.len(), 2);
+        let a = documents.iter().find(|d| d.id == "a").unwrap();
+        assert_eq!(a.get("version"), Some(&NVValue::Number(3.0)));
+    }
+
+    #[test]
+    fn test_versions_never_repeat() {
+        let dir = tempdir().unwrap();
+        let storage = FileManager::new(dir.path().to_str().unwrap()).unwrap();
+
+        storage.append(&document("a", 1.0)).unwrap();
+        let seen = storage.version("a").unwrap();
+
+        // Compaction moves the new version back to where the first one was
+        storage.replace(&document("a", 2.0)).unwrap();
+        storage.compact().unwrap();
+        assert_eq!(storage.index.read()["a"].file_offset, 0);
+        let current = storage.version("a").unwrap();
+        assert_ne!(current, seen);
+
+        // An undone batch leaves the version it found
+        let result: NVResult<()> = storage.write_batch_atomic(
+            |batch| {
+                batch.put(&document("a", 3.0))?;
+                Err(NeuralVaultError::Cancelled("undo".to_string()))
+            },
+            |_, _| {},
+        );
+        assert!(result.is_err());
+        assert_eq!(storage.version("a"), Some(current));
+
+        storage.mark_deleted("a").unwrap();
+        assert_eq!(storage.version("a"), None);
+    }
+
+    #[test]
+    fn test_concurrent_writes_share_syncs() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        {
+            let storage =
+                Arc::new(FileManager::new(path).unwrap().with_commit_window(Duration::from_micros(200)));
+            let writers: Vec<_> = (0..8)
+                .map(|writer| {
+                    let storage = storage.clone();
+                    std::thread::spawn(move || {
+                        for i in 0..20 {
+                            let id = format!("{}-{}", writer, i);
+                            storage.append(&document(&id, 1.0)).unwrap();
+                            storage.replace(&document(&id, 2.0)).unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            crash(Arc::try_unwrap(storage).ok().unwrap());
+        }
+
+        let storage = FileManager::new(path).unwrap();
+        storage.load_or_rebuild_index().unwrap();
+        let documents = storage.scan_all().unwrap();
+        assert_eq!(documents.len(), 160);
+        assert!(documents.iter().all(|d| d.get("version") == Some(&NVValue::Number(2.0))));
+    }
+
+    #[test]
+    fn test_background_flush() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        {
+            let storage = FileManager::new(path)
+                .unwrap()
+                .with_background_flush(Duration::from_secs(3600), 0);
+            storage.append(&document("a", 1.0)).unwrap();
+            storage.append(&document("b", 1.0)).unwrap();
+            assert!(storage.commit.buffered() > 0);
+            // Readers of the log only get records a crash can't take back
+            assert!(storage.records_since(0, 10).unwrap().0.is_empty());
+
+            storage.flush().unwrap();
+            assert_eq!(storage.commit.buffered(), 0);
+            assert_eq!(storage.records_since(0, 10).unwrap().0.len(), 2);
+
+            // A small threshold wakes the flusher without waiting the interval
+            let storage = storage.with_background_flush(Duration::from_secs(3600), 1);
+            storage.append(&document("c", 1.0)).unwrap();
+            let start = std::time::Instant::now();
+            while storage.commit.buffered() > 0 {
+                assert!(start.elapsed() < Duration::from_secs(5));
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            crash(storage);
+        }
+
+        let storage = FileManager::new(path).unwrap();
+        storage.load_or_rebuild_index().unwrap();
+        assert_eq!(storage.scan_all().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_reads_share_the_file_lock() {
+        let dir = tempdir().unwrap();
+        let storage = Arc::new(FileManager::new(dir.path().to_str().unwrap()).unwrap());
+        storage.append(&document("a", 1.0)).unwrap();
+
+        // Another reader holding the lock doesn't block this one
+        let _reader = storage.data_file.read();
+        assert_eq!(storage.read("a").unwrap().get("version"), Some(&NVValue::Number(1.0)));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let storage = storage.clone();
+                std::thread::spawn(move || storage.read("a").unwrap())
+            })
+            .collect();
+        for reader in readers {
+            assert_eq!(reader.join().unwrap().id, "a");
+        }
+    }
+
+    #[test]
+    fn test_reads_continue_while_appending() {
+        let dir = tempdir().unwrap();
+        let storage = Arc::new(FileManager::new(dir.path().to_str().unwrap()).unwrap());
+        storage.append(&document("a", 1.0)).unwrap();
+
+        let writer = {
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                for i in 0..200 {
+                    storage.append(&document(&format!("w{}", i), i as f64)).unwrap();
+                    storage.replace(&document("a", i as f64 + 2.0)).unwrap();
+                }
+            })
+        };
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let storage = storage.clone();
+                std::thread::spawn(move || {
+                    for i in 0..200 {
+                        // Every read decodes a whole record, old or new
+                        let a = storage.read("a").unwrap();
+                        assert!(matches!(a.get("version"), Some(NVValue::Number(v)) if *v >= 1.0));
+                        if let Ok(written) = storage.read(&format!("w{}", i)) {
+                            assert_eq!(written.get("version"), Some(&NVValue::Number(i as f64)));
+                        }
+                    }
+                })
+            })
+            .collect();
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(storage.read("a").unwrap().get("version"), Some(&NVValue::Number(201.0)));
+        assert_eq!(storage.scan_all().unwrap().len(), 201);
+    }
+
+    #[test]
+    fn test_disk_usage_leaves_the_log_end_alone() {
+        let dir = tempdir().unwrap();
+        let storage = Arc::new(FileManager::new(dir.path().to_str().unwrap()).unwrap());
+        storage.append(&document("a", 1.0)).unwrap();
+
+        let writer = {
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                for i in 0..200 {
+                    storage.append(&document(&format!("w{}", i), i as f64)).unwrap();
+                }
+            })
+        };
+        while !writer.is_finished() {
+            let usage = storage.disk_usage().unwrap();
+            assert!(usage.collections["items"].documents >= 1);
+        }
+        writer.join().unwrap();
+
+        // The last record appended is still known as the end of the log
+        let (position, _) = storage.last_record.read().unwrap();
+        let data_len = storage.data_file.read().metadata().unwrap().len();
+        assert_eq!(position.file_offset + 12 + position.length as u64 + 1, data_len);
+    }
+
+    #[test]
+    fn test_compaction_lets_writers_continue() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        {
+            let storage = FileManager::new(path).unwrap();
+            for id in ["a", "b", "meta"] {
+                storage.append(&document(id, 1.0)).unwrap();
+            }
+            storage.replace(&document("a", 2.0)).unwrap();
+
+            // Writes made while documents are copied would deadlock if
+            // compaction held the index lock
+            let mut replaced_len = 0;
+            storage
+                .compact_rewriting(
+                    |document, len| {
+                        if document.id == "meta" {
+                            document.set("replaced_len".to_string(), NVValue::Number(len as f64));
+                        }
+                    },
+                    |progress| {
+                        if progress.documents_copied == 0 {
+                            storage.replace(&document("a", 3.0)).unwrap();
+                            storage.mark_deleted("b").unwrap();
+                            storage.append(&document("c", 1.0)).unwrap();
+                        } else {
+                            replaced_len = storage.statistics().file_size_bytes;
+                        }
+                        true
+                    },
+                    || (),
+                )
+                .unwrap();
+
+            assert_eq!(storage.read("a").unwrap().get("version"), Some(&NVValue::Number(3.0)));
+            assert!(storage.read("b").is_err());
+            assert!(storage.read("c").is_ok());
+            assert_eq!(
+                storage.read("meta").unwrap().get("replaced_len"),
+                Some(&NVValue::Number(replaced_len as f64))
+            );
+            storage.append(&document("d", 1.0)).unwrap();
+            crash(storage);
+        }
+
+        // Rebuilding from the compacted file must not revive stale copies
+        std::fs::remove_file(dir.path().join("index.nvidx")).unwrap();
+        let storage = FileManager::new(path).unwrap();
+        storage.load_or_rebuild_index().unwrap();
+        let mut ids: Vec<_> = storage.scan_all().unwrap().into_iter().map(|d| d.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["a", "c", "d", "meta"]);
+        assert_eq!(storage.read("a").unwrap().get("version"), Some(&NVValue::Number(3.0)));
+    }
+
+    #[test]
+    fn test_rebuild_index_after_updates() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        {
+            let storage = FileManager::new(path).unwrap();
+            storage.append(&document("a", 1.0)).unwrap();
+            storage.replace(&document("a", 2.0)).unwrap();
+            storage.append(&document("b", 1.0)).unwrap();
+            storage.mark_deleted("b").unwrap();
+        }
+
+        let storage = FileManager::new(path).unwrap();
+        storage.rebuild_index().unwrap();
+
+        assert_eq!(storage.statistics().document_count, 1);
+        let a = storage.read("a").unwrap();
+        assert_eq!(a.get("version"), Some(&NVValue::Number(2.0)));
+        assert!(storage.read("b").is_err());
+    }
+
+    #[test]
+    fn test_scan_all_keeps_newest_version() {
+        let dir = tempdir().unwrap();
+        let storage = FileManager::new(dir.path().to_str().unwrap()).unwrap();
+
+        // Plain appends leave older versions live, as files from before
+        // updates tombstoned their predecessors do
+        storage.append(&document("a", 1.0)).unwrap();
+        storage.append(&document("a", 2.0)).unwrap();
+        storage.append(&document("b", 1.0)).unwrap();
+        storage.append(&document("b", 2.0)).unwrap();
+        storage.mark_deleted("b").unwrap();
+
+        let latest = storage.scan_all().unwrap();
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0].get("version"), Some(&NVValue::Number(2.0)));
+
+        // History includes every live record, superseded or not
+        let history = storage.scan_all_versions(true).unwrap();
+        assert_eq!(history.len(), 3);
+
+        storage.rebuild_index().unwrap();
+        assert!(storage.read("b").is_err());
+        assert_eq!(storage.statistics().document_count, 1);
+    }
+
+    #[test]
+    fn test_checkpoint_with_tail_replay() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        {
+            let storage = FileManager::new(path).unwrap();
+            storage.append(&document("a", 1.0)).unwrap();
+            storage.append(&document("b", 1.0)).unwrap();
+            storage.append(&document("c", 1.0)).unwrap();
+            storage.checkpoint().unwrap();
+
+            // Written after the checkpoint, then the process dies
+            storage.replace(&document("a", 2.0)).unwrap();
+            storage.mark_deleted("b").unwrap();
+            storage.append(&document("d", 1.0)).unwrap();
+            crash(storage);
+        }
+
+        let storage = FileManager::new(path).unwrap();
+        assert!(storage.load_or_rebuild_index().unwrap());
+        assert_eq!(storage.index_generation(), 1);
+
+        // A checkpoint taken right after loading must remain usable
+        storage.checkpoint().unwrap();
+        drop(storage);
+        let storage = FileManager::new(path).unwrap();
+        assert!(storage.load_or_rebuild_index().unwrap());
+        assert_eq!(storage.index_generation(), 2);
+
+        assert_eq!(storage.read("a").unwrap().get("version"), Some(&NVValue::Number(2.0)));
+        assert!(storage.read("b").is_err());
+        assert!(storage.read("d").is_ok());
+        assert_eq!(storage.statistics().document_count, 3);
+    }
+
+    #[test]
+    fn test_periodic_checkpoint_and_torn_tail() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        {
+            let storage = FileManager::new(path).unwrap().with_checkpoint_interval(2);
+            storage.append(&document("a", 1.0)).unwrap();
+            storage.append(&document("b", 1.0)).unwrap();
+            assert_eq!(storage.index_generation(), 1);
+            crash(storage);
+        }
+
+        // Simulate a record cut off mid-write
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir.path().join("data.nvdb"))
+            .unwrap();
+        file.write_all(&[200, 0, 0, 0, 1, 2, 3]).unwrap();
+
+        let storage = FileManager::new(path).unwrap();
+        assert!(storage.load_or_rebuild_index().unwrap());
+        assert_eq!(storage.statistics().document_count, 2);
+    }
+
+    #[test]
+    fn test_corrupted_record_is_skipped_and_recovered() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        let corrupted = {
+            let storage = FileManager::new(path).unwrap();
+            storage.append(&document("a", 1.0)).unwrap();
+            storage.append(&document("b", 1.0)).unwrap();
+            let position = storage.replace(&document("b", 2.0)).unwrap();
+            storage.append(&document("c", 1.0)).unwrap();
+            crash(storage);
+            position
+        };
+
+        // Garble the length of b's newest version
+        let file = OpenOptions::new()
+            .write(true)
+            .open(dir.path().join("data.nvdb"))
+            .unwrap();
+        write_all_at(&file, &[0xff; 4], corrupted.file_offset).unwrap();
+        drop(file);
+
+        let storage = FileManager::new(path).unwrap();
+        assert!(!storage.load_or_rebuild_index().unwrap());
+        assert!(storage.read("a").is_ok());
+        assert!(storage.read("b").is_err());
+        assert!(storage.read("c").is_ok());
+        assert_eq!(
+            storage.corrupt_ranges(),
+            vec![CorruptRange {
+                start: corrupted.file_offset,
+                end: corrupted.file_offset + 12 + corrupted.length as u64 + 1,
+            }]
+        );
+
+        let (report, _) = storage.recover(|_, _| {}, || ()).unwrap();
+        assert_eq!(report.documents_salvaged, 3);
+        assert_eq!(report.documents_restored, 1);
+        assert_eq!(report.bytes_lost, 12 + corrupted.length as u64 + 1);
+        assert!(storage.corrupt_ranges().is_empty());
+        assert_eq!(storage.read("b").unwrap().data.get("version"), Some(&NVValue::Number(1.0)));
+        drop(storage);
+
+        let storage = FileManager::new(path).unwrap();
+        storage.load_or_rebuild_index().unwrap();
+        assert_eq!(storage.statistics().document_count, 3);
+        assert!(storage.corrupt_ranges().is_empty());
+    }
+
+    #[test]
+    fn test_resync_skips_lengths_over_the_size_limit() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        // Every fourth offset in b's data reads as a 128 KiB record length
+        let corrupted = {
+            let storage = FileManager::new(path).unwrap().with_size_limits(0, usize::MAX);
+            storage.append(&document("a", 1.0)).unwrap();
+            let mut b = document("b", 1.0);
+            b.data.insert("blob".to_string(), NVValue::String("\0\0\x02\0".repeat(128 * 1024)));
+            let position = storage.append(&b).unwrap();
+            storage.append(&document("c", 1.0)).unwrap();
+            crash(storage);
+            position
+        };
+        let file = OpenOptions::new()
+            .write(true)
+            .open(dir.path().join("data.nvdb"))
+            .unwrap();
+        write_all_at(&file, &[0xff; 4], corrupted.file_offset).unwrap();
+        drop(file);
+
+        let storage = FileManager::new(path)
+            .unwrap()
+            .with_size_limits(64 * 1024, DEFAULT_OVERFLOW_THRESHOLD);
+        assert!(!storage.load_or_rebuild_index().unwrap());
+        assert!(storage.read("a").is_ok());
+        assert!(storage.read("b").is_err());
+        assert!(storage.read("c").is_ok());
+    }
+
+    #[test]
+    fn test_directory_lock() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        let writer = FileManager::new(path).unwrap();
+        writer.append(&document("a", 1.0)).unwrap();
+        assert!(matches!(
+            FileManager::new(path),
+            Err(NeuralVaultError::AlreadyLocked(_))
+        ));
+        assert!(matches!(
+            FileManager::open_read_only(path),
+            Err(NeuralVaultError::AlreadyLocked(_))
+        ));
+        drop(writer);
+
+        let reader = FileManager::open_read_only(path).unwrap();
+        let second_reader = FileManager::open_read_only(path).unwrap();
+        reader.load_or_rebuild_index().unwrap();
+        assert_eq!(reader.read("a").unwrap().id, "a");
+        assert!(matches!(
+            reader.append(&document("b", 1.0)),
+            Err(NeuralVaultError::ReadOnly)
+        ));
+        drop(second_reader);
+    }
+
+    #[test]
+    fn test_document_size_limits() {
+        let dir = tempdir().unwrap();
+        let storage = FileManager::new(dir.path().to_str().unwrap())
+            .unwrap()
+            .with_size_limits(4096, 256);
+
+        let mut big = document("big", 1.0);
+        big.data.insert("blob".to_string(), NVValue::String("x".repeat(2000)));
+        storage.append(&big).unwrap();
+
+        // The large value lives in the overflow file, not in the record
+        let position = storage.index.read()["big"];
+        assert!((position.length as usize) < 256);
+        assert_eq!(storage.read("big").unwrap().data, big.data);
+        assert_eq!(storage.scan_all().unwrap()[0].data, big.data);
+
+        let mut too_big = document("too_big", 1.0);
+        too_big.data.insert("blob".to_string(), NVValue::String("x".repeat(5000)));
+        assert!(matches!(
+            storage.append(&too_big),
+            Err(NeuralVaultError::ValidationError(_))
+        ));
+        assert!(storage.read("too_big").is_err());
+    }
+
+    #[test]
+    fn test_quota_rejects_before_spilling() {
+        let dir = tempdir().unwrap();
+        let storage = FileManager::new(dir.path().to_str().unwrap())
+            .unwrap()
+            .with_size_limits(0, 256)
+            .with_max_size(8 * 1024);
+
+        let mut big = document("big", 1.0);
+        big.data.insert("blob".to_string(), NVValue::String("x".repeat(4000)));
+        storage.append(&big).unwrap();
+        let overflow = storage.overflow_size().unwrap();
+        assert!(overflow >= 4000);
+
+        // The second value doesn't fit, so none of it reaches the overflow file
+        big.id = "bigger".to_string();
+        assert!(matches!(storage.append(&big), Err(NeuralVaultError::QuotaExceeded(_))));
+        assert_eq!(storage.overflow_size().unwrap(), overflow);
+        assert!(storage.read("bigger").is_err());
+    }
+
+    #[test]
+    fn test_replace_missing_document_fails() {
+        let dir = tempdir().unwrap();
+        let storage = FileManager::new(dir.path().to_str().unwrap()).unwrap();
+
+        assert!(matches!(
+            storage.replace(&document("missing", 1.0)),
+            Err(NeuralVaultError::DocumentNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_compaction_keeps_only_live_documents() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        {
+            let storage = FileManager::new(path).unwrap().with_size_limits(0, 256);
+            let mut big = document("big", 1.0);
+            big.data.insert("blob".to_string(), NVValue::String("x".repeat(2000)));
+            storage.append(&big).unwrap();
+            storage.append(&document("a", 1.0)).unwrap();
+            for version in 2..20 {
+                storage.replace(&document("a", version as f64)).unwrap();
+                big.data.insert("version".to_string(), NVValue::Number(version as f64));
+                storage.replace(&big).unwrap();
+            }
+            storage.append(&document("gone", 1.0)).unwrap();
+            storage.mark_deleted("gone").unwrap();
+
+            let before = storage.statistics().file_size_bytes;
+            let overflow_before = std::fs::metadata(dir.path().join("overflow.nvblob")).unwrap().len();
+            storage.compact().unwrap();
+
+            assert!(storage.statistics().file_size_bytes < before / 4);
+            assert_eq!(
+                std::fs::metadata(dir.path().join("overflow.nvblob")).unwrap().len(),
+                overflow_before / 19
+            );
+            assert_eq!(storage.read("big").unwrap().data, big.data);
+
+            // Writes keep working against the new files
+            storage.replace(&document("a", 100.0)).unwrap();
+        }
+
+        let storage = FileManager::new(path).unwrap();
+        assert!(storage.load_or_rebuild_index().unwrap());
+        assert_eq!(storage.statistics().document_count, 2);
+        assert_eq!(storage.read("a").unwrap().get("version"), Some(&NVValue::Number(100.0)));
+        assert!(storage.read("gone").is_err());
+    }
+
+    #[test]
+    fn test_interrupted_compaction_is_discarded() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        {
+            let storage = FileManager::new(path).unwrap();
+            storage.append(&document("a", 1.0)).unwrap();
+        }
+        std::fs::write(dir.path().join("data.nvdb.compact"), b"partial").unwrap();
+        std::fs::write(dir.path().join("overflow.nvblob.compact"), b"partial").unwrap();
+
+        let storage = FileManager::new(path).unwrap();
+        storage.load_or_rebuild_index().unwrap();
+        assert!(!dir.path().join("data.nvdb.compact").exists());
+        assert!(!dir.path().join("overflow.nvblob.compact").exists());
+        assert_eq!(storage.read("a").unwrap().get("version"), Some(&NVValue::Number(1.0)));
+    }
 }
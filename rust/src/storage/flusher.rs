@@ -0,0 +1,80 @@
+use crate::storage::group_commit::GroupCommit;
+use parking_lot::{Condvar, Mutex};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Background thread that makes buffered writes durable
+///
+/// With deferred flushing, writes return once they reach the OS and the
+/// unsynced tail of the data file acts as the write buffer. This thread syncs
+/// it every `interval`, or sooner when a writer reports that more than the
+/// threshold is buffered. A failed sync fails every write after it (see
+/// `GroupCommit`); `FileManager::flush` reports the error to its caller.
+pub struct Flusher {
+    shared: Arc<FlusherShared>,
+    thread: Option<JoinHandle<()>>,
+    /// Buffered bytes that trigger an early flush (0 = only on the interval)
+    threshold: u64,
+}
+
+struct FlusherShared {
+    /// Set to wake the thread early, or to stop it
+    state: Mutex<FlusherState>,
+    wake: Condvar,
+}
+
+#[derive(Default)]
+struct FlusherState {
+    requested: bool,
+    stopped: bool,
+}
+
+impl Flusher {
+    /// Start flushing `commit` every `interval`
+    pub fn start(commit: Arc<GroupCommit>, interval: Duration, threshold: u64) -> Self {
+        let shared = Arc::new(FlusherShared {
+            state: Mutex::new(FlusherState::default()),
+            wake: Condvar::new(),
+        });
+
+        let thread_shared = shared.clone();
+        let thread = std::thread::spawn(move || loop {
+            {
+                let mut state = thread_shared.state.lock();
+                if !state.requested && !state.stopped {
+                    thread_shared.wake.wait_for(&mut state, interval);
+                }
+                if state.stopped {
+                    return;
+                }
+                state.requested = false;
+            }
+            let _ = commit.flush();
+        });
+
+        Self {
+            shared,
+            thread: Some(thread),
+            threshold,
+        }
+    }
+
+    /// Wake the thread early if `buffered` bytes exceed the threshold
+    pub fn note_buffered(&self, buffered: u64) {
+        if self.threshold > 0 && buffered >= self.threshold {
+            self.shared.state.lock().requested = true;
+            self.shared.wake.notify_one();
+        }
+    }
+}
+
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        self.shared.state.lock().stopped = true;
+        self.shared.wake.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use crate::error::{NeuralVaultError, NVResult};
+use parking_lot::{Condvar, Mutex};
+use std::fs::File;
+use std::time::Duration;
+
+/// Shares one fsync of the data file between concurrent writers
+///
+/// Writers append under the file lock and register how far the file has been
+/// written, then release their locks and wait for a sync covering that
+/// offset. The first writer to find no sync in progress becomes the leader:
+/// it optionally waits out the latency window so more writers can join, then
+/// syncs everything registered so far. The others just wait for it.
+///
+/// A failed sync can't be retried safely: the OS may have dropped the dirty
+/// pages and a later sync would then succeed without them. The first
+/// failure is therefore final, and every later sync fails too.
+pub struct GroupCommit {
+    /// Separate handle to the data file, so syncing doesn't need its lock
+    file: Mutex<File>,
+    state: Mutex<CommitState>,
+    synced: Condvar,
+}
+
+struct CommitState {
+    /// End of the last complete write
+    written: u64,
+    /// Everything before this offset is durable
+    synced: u64,
+    syncing: bool,
+    /// A sync has failed, so nothing written since `synced` can be trusted
+    failed: bool,
+    /// Bumped by `reset`; offsets from before it refer to the old file
+    generation: u64,
+    /// How long a leader waits for other writers before syncing
+    window: Duration,
+}
+
+impl GroupCommit {
+    /// Track `file`, whose first `len` bytes are already durable
+    pub fn new(file: File, len: u64) -> Self {
+        Self {
+            file: Mutex::new(file),
+            state: Mutex::new(CommitState {
+                written: len,
+                synced: len,
+                syncing: false,
+                failed: false,
+                generation: 0,
+                window: Duration::ZERO,
+            }),
+            synced: Condvar::new(),
+        }
+    }
+
+    /// Set the latency window a leader waits before syncing
+    pub fn set_window(&self, window: Duration) {
+        self.state.lock().window = window;
+    }
+
+    /// Record that the file has been completely written up to `end`
+    ///
+    /// Must be called under the data file lock, after every write (including
+    /// tombstones) that should be covered by the next sync.
+    pub fn written(&self, end: u64) {
+        let mut state = self.state.lock();
+        state.written = state.written.max(end);
+    }
+
+    /// Block until everything up to `end` is durable
+    pub fn sync(&self, end: u64) -> NVResult<()> {
+        let mut state = self.state.lock();
+        loop {
+            if state.synced >= end {
+                return Ok(());
+            }
+            if state.failed {
+                return Err(sync_failed());
+            }
+            if !state.syncing {
+                break;
+            }
+            self.synced.wait(&mut state);
+        }
+        state.syncing = true;
+        let window = state.window;
+
+        let result = parking_lot::MutexGuard::unlocked(&mut state, || -> NVResult<(u64, u64)> {
+            if !window.is_zero() {
+                std::thread::sleep(window);
+            }
+            let file = self.file.lock();
+            let (target, generation) = {
+                let state = self.state.lock();
+                (state.written, state.generation)
+            };
+            file.sync_data()?;
+            Ok((target, generation))
+        });
+
+        state.syncing = false;
+        match result {
+            Ok((target, generation)) if generation == state.generation => {
+                state.synced = state.synced.max(target);
+            }
+            Ok(_) => {}
+            Err(_) => state.failed = true,
+        }
+        self.synced.notify_all();
+        result.map(|_| ())
+    }
+
+    /// Sync everything written so far
+    pub fn flush(&self) -> NVResult<()> {
+        let written = self.state.lock().written;
+        self.sync(written)
+    }
+
+    /// End of the durable part of the file
+    pub fn durable(&self) -> u64 {
+        self.state.lock().synced
+    }
+
+    /// Fail if a sync has failed, after which nothing more can be committed
+    pub fn check(&self) -> NVResult<()> {
+        match self.state.lock().failed {
+            true => Err(sync_failed()),
+            false => Ok(()),
+        }
+    }
+
+    /// Bytes written but not yet durable
+    pub fn buffered(&self) -> u64 {
+        let state = self.state.lock();
+        state.written.saturating_sub(state.synced)
+    }
+
+    /// Start tracking a replacement file (after compaction or truncation)
+    /// whose first `len` bytes are durable
+    pub fn reset(&self, file: File, len: u64) {
+        *self.file.lock() = file;
+        let mut state = self.state.lock();
+        state.written = len;
+        state.synced = len;
+        state.generation += 1;
+    }
+}
+
+fn sync_failed() -> NeuralVaultError {
+    NeuralVaultError::StorageError(
+        "Syncing the data file failed; reopen the database to continue".to_string(),
+    )
+}
@@ -0,0 +1,100 @@
+use crate::error::NVResult;
+use crate::storage::bloom::CollectionFilters;
+use crate::storage::file_manager::StoragePosition;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Magic bytes at the start of an index file
+const INDEX_MAGIC: &[u8; 4] = b"NVIX";
+
+/// Current index file format version
+const INDEX_VERSION: u8 = 1;
+
+/// Checkpoint of the in-memory index written to `index.nvidx`
+///
+/// The checkpoint covers the data file up to `data_len`; records appended
+/// after that are replayed from the data file when it is loaded.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PersistedIndex {
+    /// Incremented every time the index is persisted
+    pub generation: u64,
+    /// Data file length the index was built against
+    pub data_len: u64,
+    /// Last record before `data_len` and its checksum, to detect a replaced file
+    pub last_record: Option<(StoragePosition, u64)>,
+    /// Document ID to record position
+    pub positions: HashMap<String, StoragePosition>,
+    /// Per-collection bloom filters
+    pub filters: CollectionFilters,
+}
+
+/// Write an index checkpoint atomically (temp file + rename)
+pub fn save(path: &Path, index: &PersistedIndex) -> NVResult<()> {
+    let bytes = encode(index)?;
+
+    let tmp_path = path.with_extension("nvidx.tmp");
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+/// The contents of an index file holding `index`
+pub fn encode(index: &PersistedIndex) -> NVResult<Vec<u8>> {
+    let payload = bincode::serialize(index)?;
+
+    let mut bytes = Vec::with_capacity(4 + 1 + 8 + payload.len());
+    bytes.extend_from_slice(INDEX_MAGIC);
+    bytes.push(INDEX_VERSION);
+    bytes.extend_from_slice(&checksum(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+/// Load an index checkpoint
+///
+/// Returns `Ok(None)` if there is no checkpoint or it can't be used, in which
+/// case the caller should rebuild from the data file.
+pub fn load(path: &Path) -> NVResult<Option<PersistedIndex>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+
+    let header_len = INDEX_MAGIC.len() + 1 + 8;
+    if bytes.len() < header_len
+        || &bytes[..4] != INDEX_MAGIC
+        || bytes[4] != INDEX_VERSION
+    {
+        return Ok(None);
+    }
+
+    let expected = u64::from_le_bytes(bytes[5..13].try_into().unwrap());
+    let payload = &bytes[header_len..];
+    if checksum(payload) != expected {
+        return Ok(None);
+    }
+
+    Ok(bincode::deserialize(payload).ok())
+}
+
+/// FNV-1a hash of the payload
+pub(crate) fn checksum(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -0,0 +1,61 @@
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::{NVDocument, NVValue};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// `NVDocument` as data files stored it before the record format
+#[derive(Deserialize)]
+struct LegacyDocument {
+    id: String,
+    collection: String,
+    data: HashMap<String, NVValue>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    deleted: bool,
+}
+
+/// Decode a record payload written before the tagged record format
+///
+/// Those records are bincode-encoded documents. Their values were written
+/// untagged, which bincode can't read back (it can't tell what type comes
+/// next), so only documents without fields decode; for any other the error
+/// says so rather than guessing at the bytes.
+pub fn decode_document(data: &[u8]) -> NVResult<NVDocument> {
+    let document: LegacyDocument = bincode::deserialize(data).map_err(|e| match *e {
+        bincode::ErrorKind::DeserializeAnyNotSupported => NeuralVaultError::SerializationError(
+            "Legacy record has field values without type tags, which can't be decoded".to_string(),
+        ),
+        e => NeuralVaultError::SerializationError(format!("Unreadable legacy record: {}", e)),
+    })?;
+
+    Ok(NVDocument {
+        id: document.id,
+        collection: document.collection,
+        data: document.data,
+        created_at: document.created_at,
+        updated_at: document.updated_at,
+        deleted: document.deleted,
+        version: 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decodes_documents_without_fields() {
+        // Documents still serialize the way data files stored them
+        let mut document = NVDocument::new("doc-1".to_string(), "users".to_string(), HashMap::new());
+        document.deleted = true;
+        let encoded = bincode::serialize(&document).unwrap();
+        assert_eq!(decode_document(&encoded).unwrap(), document);
+
+        document.data.insert("name".to_string(), NVValue::String("Asha".to_string()));
+        let error = decode_document(&bincode::serialize(&document).unwrap()).unwrap_err();
+        assert!(error.to_string().contains("without type tags"));
+
+        assert!(decode_document(&encoded[..encoded.len() - 1]).is_err());
+    }
+}
@@ -1,3 +1,19 @@
+pub mod archive;
+pub mod attachments;
+pub mod backup;
+pub mod bloom;
 pub mod file_manager;
+pub mod flusher;
+pub mod group_commit;
+pub mod index_file;
+pub mod legacy;
+pub mod positioned;
+pub mod record;
 
-pub use file_manager::{FileManager, StoragePosition, StorageStats};
+pub use attachments::{AttachmentInfo, AttachmentReader};
+pub use backup::{BackupInfo, BackupKey, BackupScheduler};
+pub use bloom::{BloomFilter, CollectionFilter};
+pub use file_manager::{
+    CollectionUsage, CompactionProgress, CorruptRange, DiskUsage, FileManager, RecoveryReport, StoragePosition, StorageSnapshot, StorageStats, WriteBatch,
+};
+pub use record::{RecordView, VersionedRecord};
@@ -0,0 +1,118 @@
+use std::fs::File;
+use std::io;
+
+/// Fill `buf` from the file starting at `offset`
+///
+/// Doesn't move the file cursor (`pread` on Unix), so it works through a
+/// shared `&File` and concurrent calls don't interfere. Fails with
+/// `UnexpectedEof` if the file ends first.
+pub fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+    }
+    #[cfg(windows)]
+    {
+        let mut buf = buf;
+        let mut offset = offset;
+        while !buf.is_empty() {
+            match std::os::windows::fs::FileExt::seek_read(file, buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write all of `buf` to the file starting at `offset`, without moving the
+/// file cursor (`pwrite` on Unix)
+pub fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+    }
+    #[cfg(windows)]
+    {
+        let mut buf = buf;
+        let mut offset = offset;
+        while !buf.is_empty() {
+            match std::os::windows::fs::FileExt::seek_write(file, buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Seek, SeekFrom};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_reads_and_writes_at_offsets() {
+        let dir = tempdir().unwrap();
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dir.path().join("data"))
+            .unwrap();
+        write_all_at(&file, b"hello world", 0).unwrap();
+        write_all_at(&file, b"WORLD", 6).unwrap();
+
+        let mut buf = [0u8; 5];
+        read_exact_at(&file, &mut buf, 6).unwrap();
+        assert_eq!(&buf, b"WORLD");
+        // Neither call moves the file cursor
+        assert_eq!(file.stream_position().unwrap(), 0);
+
+        // Writing past the end leaves a zeroed gap
+        write_all_at(&file, b"!", 13).unwrap();
+        let mut buf = [0xffu8; 3];
+        read_exact_at(&file, &mut buf, 11).unwrap();
+        assert_eq!(&buf, b"\0\0!");
+        assert_eq!(file.seek(SeekFrom::End(0)).unwrap(), 14);
+    }
+
+    #[test]
+    fn test_read_past_end_is_unexpected_eof() {
+        let dir = tempdir().unwrap();
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dir.path().join("data"))
+            .unwrap();
+        write_all_at(&file, b"0123456789", 0).unwrap();
+
+        // A read that starts inside the file but runs off its end
+        let mut buf = [0u8; 4];
+        let err = read_exact_at(&file, &mut buf, 8).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // One that starts past the end
+        let err = read_exact_at(&file, &mut buf, 20).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // An empty read at the end succeeds
+        read_exact_at(&file, &mut [], 10).unwrap();
+        read_exact_at(&file, &mut buf, 6).unwrap();
+        assert_eq!(&buf, b"6789");
+    }
+}
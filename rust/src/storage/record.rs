@@ -0,0 +1,672 @@
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::{DocumentMeta, NVDocument, NVValue};
+use crate::object::NVObject;
+use chrono::{DateTime, Utc};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Magic bytes at the start of every encoded record payload
+const RECORD_MAGIC: &[u8; 2] = b"NV";
+
+/// Current record payload format version
+const RECORD_VERSION: u8 = 1;
+
+// Value type tags
+const TAG_NULL: u8 = 0;
+const TAG_BOOL: u8 = 1;
+const TAG_NUMBER: u8 = 2;
+const TAG_STRING: u8 = 3;
+const TAG_ARRAY: u8 = 4;
+const TAG_OBJECT: u8 = 5;
+const TAG_OVERFLOW: u8 = 6;
+const TAG_SEALED: u8 = 7;
+
+/// Loads `len` bytes stored out of line at `offset` in the overflow file
+pub type OverflowResolver<'r> = &'r (dyn Fn(u64, u32) -> NVResult<Vec<u8>> + Sync);
+
+/// Decrypts a sealed value, or returns `None` if no key is available
+pub type FieldOpener<'r> = &'r (dyn Fn(&[u8]) -> NVResult<Option<Vec<u8>>> + Sync);
+
+/// A record payload, with the stored version of the document it holds
+#[derive(Debug, Clone)]
+pub struct VersionedRecord {
+    pub data: Vec<u8>,
+    pub version: u64,
+}
+
+impl VersionedRecord {
+    /// Parse the payload, reporting the record's version
+    pub fn view(&self) -> NVResult<RecordView<'_>> {
+        Ok(RecordView::parse(&self.data)?.with_version(self.version))
+    }
+}
+
+/// Encode a document into a record payload
+///
+/// Layout:
+/// `[magic(2)][version(1)][id][collection][created_at][updated_at][deleted(1)]`
+/// `[field_count(4)][field table][values]`
+///
+/// Each field table entry is `[name][value_offset(4)][value_len(4)]` with the
+/// offset relative to the start of the value region, so a single field can be
+/// decoded without touching the others.
+pub fn encode_document(document: &NVDocument) -> Vec<u8> {
+    encode_document_spilling(document, usize::MAX, |_| Ok(0))
+        .expect("encoding without spilling can't fail")
+}
+
+/// Encode a document, moving large top-level values out of line
+///
+/// Any field whose encoded value is longer than `spill_threshold` bytes is
+/// handed to `spill`, which stores it elsewhere and returns its offset; the
+/// record only keeps a `(offset, len)` reference. Keeping huge values out of
+/// the record means scans that don't need them never load them.
+pub fn encode_document_spilling<F>(
+    document: &NVDocument,
+    spill_threshold: usize,
+    spill: F,
+) -> NVResult<Vec<u8>>
+where
+    F: FnMut(&[u8]) -> NVResult<u64>,
+{
+    encode_document_sealing(document, spill_threshold, |_, _| Ok(None), spill)
+}
+
+/// Encode a document, encrypting some values and spilling large ones
+///
+/// `seal` receives each field's name and encoded value and returns the
+/// encrypted bytes for fields that must not be stored in plaintext. Sealing
+/// happens before spilling, so spilled values stay encrypted too.
+pub fn encode_document_sealing<S, F>(
+    document: &NVDocument,
+    spill_threshold: usize,
+    mut seal: S,
+    mut spill: F,
+) -> NVResult<Vec<u8>>
+where
+    S: FnMut(&str, &[u8]) -> NVResult<Option<Vec<u8>>>,
+    F: FnMut(&[u8]) -> NVResult<u64>,
+{
+    let mut record = RecordBuilder::default();
+    let mut encoded = Vec::new();
+
+    // Fields go in name order so a document always encodes the same way
+    let mut fields: Vec<(&String, &NVValue)> = document.data.iter().collect();
+    fields.sort_by_key(|(name, _)| *name);
+    for (name, value) in fields {
+        encoded.clear();
+        encode_value(value, &mut encoded);
+        if let Some(sealed) = seal(name, &encoded)? {
+            encoded.clear();
+            encoded.push(TAG_SEALED);
+            write_u32(&mut encoded, sealed.len() as u32);
+            encoded.extend_from_slice(&sealed);
+        }
+        if encoded.len() > spill_threshold {
+            let overflow_offset = spill(&encoded)?;
+            let mut reference = vec![TAG_OVERFLOW];
+            reference.extend_from_slice(&overflow_offset.to_le_bytes());
+            write_u32(&mut reference, encoded.len() as u32);
+            record.push(name, &reference);
+        } else {
+            record.push(name, &encoded);
+        }
+    }
+
+    Ok(record.finish(
+        &document.id,
+        &document.collection,
+        &document.created_at,
+        &document.updated_at,
+        document.deleted,
+    ))
+}
+
+/// Field table and value region of a record being encoded
+#[derive(Default)]
+struct RecordBuilder {
+    field_count: u32,
+    table: Vec<u8>,
+    values: Vec<u8>,
+}
+
+impl RecordBuilder {
+    /// Add a field, given its value as stored in the value region
+    fn push(&mut self, name: &str, stored: &[u8]) {
+        write_str(&mut self.table, name);
+        write_u32(&mut self.table, self.values.len() as u32);
+        write_u32(&mut self.table, stored.len() as u32);
+        self.values.extend_from_slice(stored);
+        self.field_count += 1;
+    }
+
+    /// Lay out the record with its header
+    fn finish(
+        self,
+        id: &str,
+        collection: &str,
+        created_at: &DateTime<Utc>,
+        updated_at: &DateTime<Utc>,
+        deleted: bool,
+    ) -> Vec<u8> {
+        let mut out = Vec::with_capacity(64 + self.table.len() + self.values.len());
+        out.extend_from_slice(RECORD_MAGIC);
+        out.push(RECORD_VERSION);
+        write_str(&mut out, id);
+        write_str(&mut out, collection);
+        write_timestamp(&mut out, created_at);
+        write_timestamp(&mut out, updated_at);
+        out.push(deleted as u8);
+        write_u32(&mut out, self.field_count);
+        out.extend_from_slice(&self.table);
+        out.extend_from_slice(&self.values);
+        out
+    }
+}
+
+/// Encode several documents as a sequence of `[record_len(4)][record]`
+///
+/// Used for documents stored outside the data file, such as archive
+/// segments and incremental backups, so they share the record format's
+/// explicit value tags.
+pub fn encode_documents(documents: &[NVDocument]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for document in documents {
+        let record = encode_document(document);
+        write_u32(&mut out, record.len() as u32);
+        out.extend_from_slice(&record);
+    }
+    out
+}
+
+/// Decode `encode_documents` output
+pub fn decode_documents(data: &[u8]) -> NVResult<Vec<NVDocument>> {
+    let mut reader = Reader::new(data);
+    let mut documents = Vec::new();
+    while !reader.is_empty() {
+        let len = reader.u32()? as usize;
+        documents.push(decode_document(reader.take(len)?)?);
+    }
+    Ok(documents)
+}
+
+/// Whether a payload is in the record format, rather than the bincode
+/// encoding data files held before it
+pub fn is_record(data: &[u8]) -> bool {
+    data.starts_with(RECORD_MAGIC)
+}
+
+/// Decode a full document from a record payload
+pub fn decode_document(data: &[u8]) -> NVResult<NVDocument> {
+    RecordView::parse(data)?.materialize()
+}
+
+/// Decode a full document, loading spilled values through `overflow`
+pub fn decode_document_with(data: &[u8], overflow: OverflowResolver) -> NVResult<NVDocument> {
+    RecordView::parse(data)?.with_overflow(overflow).materialize()
+}
+
+/// Lazily decoded view over an encoded record
+///
+/// Parsing only reads the header and field table; values are decoded on
+/// demand, so filters can look at the fields they need and skip the rest.
+pub struct RecordView<'a> {
+    id: &'a str,
+    collection: &'a str,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    deleted: bool,
+    version: u64,
+    fields: Vec<(&'a str, Range<usize>)>,
+    values: &'a [u8],
+    overflow: Option<OverflowResolver<'a>>,
+    opener: Option<FieldOpener<'a>>,
+}
+
+impl<'a> RecordView<'a> {
+    /// Parse the record header and field table
+    pub fn parse(data: &'a [u8]) -> NVResult<Self> {
+        let mut reader = Reader::new(data);
+
+        if reader.take(2)? != RECORD_MAGIC {
+            return Err(NeuralVaultError::SerializationError(
+                "Unrecognized record format".to_string(),
+            ));
+        }
+        let version = reader.u8()?;
+        if version > RECORD_VERSION {
+            return Err(NeuralVaultError::SerializationError(format!(
+                "Record version {} is newer than supported version {}",
+                version, RECORD_VERSION
+            )));
+        }
+        if version == 0 {
+            return Err(NeuralVaultError::SerializationError(
+                "Unsupported record version: 0".to_string(),
+            ));
+        }
+
+        let id = reader.str()?;
+        let collection = reader.str()?;
+        let created_at = reader.timestamp()?;
+        let updated_at = reader.timestamp()?;
+        let deleted = reader.u8()? != 0;
+
+        let field_count = reader.u32()? as usize;
+        let mut fields = Vec::with_capacity(field_count.min(1024));
+        for _ in 0..field_count {
+            let name = reader.str()?;
+            let offset = reader.u32()? as usize;
+            let len = reader.u32()? as usize;
+            fields.push((name, offset..offset + len));
+        }
+
+        let values = reader.rest();
+        if fields.iter().any(|(_, range)| range.end > values.len()) {
+            return Err(truncated());
+        }
+
+        Ok(Self {
+            id,
+            collection,
+            created_at,
+            updated_at,
+            deleted,
+            version: 0,
+            fields,
+            values,
+            overflow: None,
+            opener: None,
+        })
+    }
+
+    /// Resolve spilled values through `overflow` instead of failing on them
+    pub fn with_overflow(mut self, overflow: OverflowResolver<'a>) -> Self {
+        self.overflow = Some(overflow);
+        self
+    }
+
+    /// Report `version` as the stored version of the document
+    pub fn with_version(mut self, version: u64) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Decrypt sealed values through `opener`
+    ///
+    /// Without an opener, or when it has no key, sealed fields are treated
+    /// as absent.
+    pub fn with_opener(mut self, opener: FieldOpener<'a>) -> Self {
+        self.opener = Some(opener);
+        self
+    }
+
+    pub fn id(&self) -> &'a str {
+        self.id
+    }
+
+    pub fn collection(&self) -> &'a str {
+        self.collection
+    }
+
+    pub fn deleted(&self) -> bool {
+        self.deleted
+    }
+
+    pub fn meta(&self) -> DocumentMeta<'a> {
+        DocumentMeta {
+            id: self.id,
+            collection: self.collection,
+            deleted: self.deleted,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            version: self.version,
+        }
+    }
+
+    /// Decode a single field, if present
+    pub fn field(&self, name: &str) -> NVResult<Option<NVValue>> {
+        match self.fields.iter().find(|(field, _)| *field == name) {
+            Some((_, range)) => self.decode_field(range.clone()),
+            None => Ok(None),
+        }
+    }
+
+    /// Decode the whole document
+    pub fn materialize(&self) -> NVResult<NVDocument> {
+        let mut data = HashMap::with_capacity(self.fields.len());
+        for (name, range) in &self.fields {
+            if let Some(value) = self.decode_field(range.clone())? {
+                data.insert(name.to_string(), value);
+            }
+        }
+
+        Ok(NVDocument {
+            id: self.id.to_string(),
+            collection: self.collection.to_string(),
+            data,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            deleted: self.deleted,
+            version: self.version,
+        })
+    }
+
+    /// Re-encode the record with its spilled values stored in line
+    ///
+    /// Sealed values are copied still sealed, so the result can be stored
+    /// outside the database without exposing encrypted fields.
+    pub fn inlined(&self) -> NVResult<Vec<u8>> {
+        let mut record = RecordBuilder::default();
+        for (name, range) in &self.fields {
+            record.push(name, &self.stored_value(range.clone())?);
+        }
+        Ok(record.finish(self.id, self.collection, &self.created_at, &self.updated_at, self.deleted))
+    }
+}
+
+impl RecordView<'_> {
+    /// A value's encoded bytes, loaded from the overflow file if spilled
+    fn stored_value(&self, range: Range<usize>) -> NVResult<Cow<'_, [u8]>> {
+        let inline = &self.values[range];
+        if inline.first() != Some(&TAG_OVERFLOW) {
+            return Ok(Cow::Borrowed(inline));
+        }
+        let mut reader = Reader::new(&inline[1..]);
+        let offset = u64::from_le_bytes(reader.array()?);
+        let len = reader.u32()?;
+        let overflow = self.overflow.ok_or_else(|| {
+            NeuralVaultError::StorageError("Value is stored in the overflow file".to_string())
+        })?;
+        Ok(Cow::Owned(overflow(offset, len)?))
+    }
+
+    /// Decode a value, or `None` if it is sealed and can't be opened
+    fn decode_field(&self, range: Range<usize>) -> NVResult<Option<NVValue>> {
+        let bytes = self.stored_value(range)?;
+        if bytes.first() != Some(&TAG_SEALED) {
+            return decode_value(&mut Reader::new(&bytes)).map(Some);
+        }
+
+        let mut reader = Reader::new(&bytes[1..]);
+        let len = reader.u32()? as usize;
+        let sealed = reader.take(len)?;
+        let Some(opener) = self.opener else {
+            return Ok(None);
+        };
+        match opener(sealed)? {
+            Some(plaintext) => decode_value(&mut Reader::new(&plaintext)).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+fn encode_value(value: &NVValue, out: &mut Vec<u8>) {
+    match value {
+        NVValue::Null => out.push(TAG_NULL),
+        NVValue::Bool(b) => {
+            out.push(TAG_BOOL);
+            out.push(*b as u8);
+        }
+        NVValue::Number(n) => {
+            out.push(TAG_NUMBER);
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        NVValue::String(s) => {
+            out.push(TAG_STRING);
+            write_str(out, s);
+        }
+        NVValue::Array(items) => {
+            out.push(TAG_ARRAY);
+            write_u32(out, items.len() as u32);
+            for item in items {
+                encode_value(item, out);
+            }
+        }
+        NVValue::Object(map) => {
+            out.push(TAG_OBJECT);
+            write_u32(out, map.len() as u32);
+            for (key, item) in map {
+                write_str(out, key);
+                encode_value(item, out);
+            }
+        }
+    }
+}
+
+fn decode_value(reader: &mut Reader) -> NVResult<NVValue> {
+    match reader.u8()? {
+        TAG_NULL => Ok(NVValue::Null),
+        TAG_BOOL => Ok(NVValue::Bool(reader.u8()? != 0)),
+        TAG_NUMBER => Ok(NVValue::Number(f64::from_le_bytes(reader.array()?))),
+        TAG_STRING => Ok(NVValue::String(reader.str()?.to_string())),
+        TAG_ARRAY => {
+            let len = reader.u32()? as usize;
+            let mut items = Vec::with_capacity(len.min(1024));
+            for _ in 0..len {
+                items.push(decode_value(reader)?);
+            }
+            Ok(NVValue::Array(items))
+        }
+        TAG_OBJECT => {
+            let len = reader.u32()? as usize;
+            let mut map = NVObject::with_capacity(len.min(1024));
+            for _ in 0..len {
+                let key = reader.str()?.to_string();
+                map.insert(key, decode_value(reader)?);
+            }
+            Ok(NVValue::Object(map))
+        }
+        tag => Err(NeuralVaultError::SerializationError(format!(
+            "Unknown value tag: {}",
+            tag
+        ))),
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, value: &str) {
+    write_u32(out, value.len() as u32);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn write_timestamp(out: &mut Vec<u8>, value: &DateTime<Utc>) {
+    out.extend_from_slice(&value.timestamp().to_le_bytes());
+    write_u32(out, value.timestamp_subsec_nanos());
+}
+
+fn truncated() -> NeuralVaultError {
+    NeuralVaultError::SerializationError("Truncated record".to_string())
+}
+
+/// Bounds-checked cursor over a record payload
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> NVResult<&'a [u8]> {
+        let end = self.pos.checked_add(len).ok_or_else(truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or_else(truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> NVResult<[u8; N]> {
+        let mut buf = [0u8; N];
+        buf.copy_from_slice(self.take(N)?);
+        Ok(buf)
+    }
+
+    fn u8(&mut self) -> NVResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> NVResult<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn str(&mut self) -> NVResult<&'a str> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.take(len)?)
+            .map_err(|e| NeuralVaultError::SerializationError(e.to_string()))
+    }
+
+    fn timestamp(&mut self) -> NVResult<DateTime<Utc>> {
+        let secs = i64::from_le_bytes(self.array()?);
+        let nanos = self.u32()?;
+        DateTime::from_timestamp(secs, nanos).ok_or_else(|| {
+            NeuralVaultError::SerializationError("Invalid timestamp".to_string())
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.pos..];
+        self.pos = self.data.len();
+        rest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_document() -> NVDocument {
+        let mut address = NVObject::new();
+        address.insert("city".to_string(), NVValue::String("Pune".to_string()));
+
+        let mut data = HashMap::new();
+        data.insert("name".to_string(), NVValue::String("Asha".to_string()));
+        data.insert("age".to_string(), NVValue::Number(31.5));
+        data.insert("active".to_string(), NVValue::Bool(true));
+        data.insert("nickname".to_string(), NVValue::Null);
+        data.insert(
+            "tags".to_string(),
+            NVValue::Array(vec![NVValue::String("a".to_string()), NVValue::Number(1.0)]),
+        );
+        data.insert("address".to_string(), NVValue::Object(address));
+
+        NVDocument::new("doc-1".to_string(), "users".to_string(), data)
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let doc = sample_document();
+        let decoded = decode_document(&encode_document(&doc)).unwrap();
+
+        assert_eq!(decoded.id, doc.id);
+        assert_eq!(decoded.collection, doc.collection);
+        assert_eq!(decoded.data, doc.data);
+        assert_eq!(decoded.created_at, doc.created_at);
+        assert_eq!(decoded.updated_at, doc.updated_at);
+        assert!(!decoded.deleted);
+    }
+
+    #[test]
+    fn test_lazy_field_access() {
+        let encoded = encode_document(&sample_document());
+        let view = RecordView::parse(&encoded).unwrap();
+
+        assert_eq!(view.id(), "doc-1");
+        assert_eq!(view.collection(), "users");
+        assert_eq!(view.field("age").unwrap(), Some(NVValue::Number(31.5)));
+        assert_eq!(view.field("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_spilled_values() {
+        let doc = sample_document();
+        let mut overflow = Vec::new();
+        let encoded = encode_document_spilling(&doc, 16, |bytes| {
+            let offset = overflow.len() as u64;
+            overflow.extend_from_slice(bytes);
+            Ok(offset)
+        })
+        .unwrap();
+        assert!(!overflow.is_empty());
+
+        // Without a resolver spilled fields can't be read, inline ones can
+        let view = RecordView::parse(&encoded).unwrap();
+        assert!(view.field("address").is_err());
+        assert_eq!(view.field("age").unwrap(), Some(NVValue::Number(31.5)));
+
+        let resolver = |offset: u64, len: u32| {
+            Ok(overflow[offset as usize..offset as usize + len as usize].to_vec())
+        };
+        let decoded = decode_document_with(&encoded, &resolver).unwrap();
+        assert_eq!(decoded.data, doc.data);
+
+        // Inlined, the record no longer needs the overflow file
+        let inlined = RecordView::parse(&encoded).unwrap().with_overflow(&resolver).inlined().unwrap();
+        assert_eq!(decode_document(&inlined).unwrap(), doc);
+    }
+
+    #[test]
+    fn test_sealed_values() {
+        let doc = sample_document();
+        // Stand-in cipher: reverse the bytes
+        let encoded = encode_document_sealing(
+            &doc,
+            usize::MAX,
+            |name, bytes| Ok((name == "name").then(|| bytes.iter().rev().copied().collect())),
+            |_| Ok(0),
+        )
+        .unwrap();
+        assert!(!encoded.windows(4).any(|window| window == b"Asha"));
+
+        // Without a key the sealed field is absent
+        let locked = decode_document(&encoded).unwrap();
+        assert!(!locked.data.contains_key("name"));
+        assert_eq!(locked.data.len(), doc.data.len() - 1);
+
+        let opener = |sealed: &[u8]| Ok(Some(sealed.iter().rev().copied().collect()));
+        let view = RecordView::parse(&encoded).unwrap().with_opener(&opener);
+        assert_eq!(view.materialize().unwrap().data, doc.data);
+
+        // Inlining leaves sealed values sealed
+        let inlined = view.inlined().unwrap();
+        assert!(!inlined.windows(4).any(|window| window == b"Asha"));
+        assert_eq!(RecordView::parse(&inlined).unwrap().with_opener(&opener).materialize().unwrap().data, doc.data);
+    }
+
+    #[test]
+    fn test_truncated_record_is_rejected() {
+        let encoded = encode_document(&sample_document());
+        assert!(RecordView::parse(&encoded[..encoded.len() - 3]).is_err());
+        assert!(RecordView::parse(b"garbage").is_err());
+    }
+
+    #[test]
+    fn test_document_sequence_round_trip() {
+        let mut other = sample_document();
+        other.id = "doc-2".to_string();
+        other.deleted = true;
+        let documents = vec![sample_document(), other];
+
+        let encoded = encode_documents(&documents);
+        assert_eq!(decode_documents(&encoded).unwrap(), documents);
+        assert!(decode_documents(&[]).unwrap().is_empty());
+        assert!(decode_documents(&encoded[..encoded.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_newer_record_version_is_rejected() {
+        let mut encoded = encode_document(&sample_document());
+        encoded[2] = RECORD_VERSION + 1;
+        let error = RecordView::parse(&encoded).err().unwrap().to_string();
+        assert!(error.contains("newer than supported"), "{}", error);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::audit::AUDIT_COLLECTION;
+use crate::crdt;
+use crate::database::NeuralVault;
+use crate::error::{NVResult, NeuralVaultError};
+use crate::models::NVDocument;
+use crate::replication::{deletion_marker, Change, ChangeSet};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// A database that can be synchronized with
+///
+/// Implemented by `NeuralVault` for databases in the same process; a
+/// networked peer forwards these calls to its remote database.
+pub trait SyncPeer {
+    /// Stable identity of the peer's database
+    fn replica_id(&self) -> NVResult<String>;
+    /// The peer's changefeed, as `NeuralVault::changes_since`
+    fn changes_since(&self, sequence: u64) -> NVResult<ChangeSet>;
+    /// The peer's current version of a document, if it is live, with its
+    /// stored `version` set
+    fn get(&self, id: &str) -> NVResult<Option<NVDocument>>;
+    /// Store documents (or deletion markers) as given, keeping timestamps,
+    /// through the peer's usual checks and hooks
+    ///
+    /// The writes are applied together or not at all; if a document is no
+    /// longer at its `expected` version, none is and the call fails with
+    /// `WriteConflict`.
+    fn apply(&self, writes: &[SyncWrite]) -> NVResult<()>;
+}
+
+/// A document, or deletion marker, for a peer to store
+#[derive(Debug, Clone)]
+pub struct SyncWrite {
+    pub document: NVDocument,
+    /// Version of the peer's document when `SyncPeer::get` read it, or
+    /// `None` if it had none
+    pub expected: Option<u64>,
+}
+
+impl SyncPeer for NeuralVault {
+    fn replica_id(&self) -> NVResult<String> {
+        NeuralVault::replica_id(self)
+    }
+
+    fn changes_since(&self, sequence: u64) -> NVResult<ChangeSet> {
+        NeuralVault::changes_since(self, sequence)
+    }
+
+    fn get(&self, id: &str) -> NVResult<Option<NVDocument>> {
+        match self.find_by_id_unredacted(id) {
+            Ok(document) => Ok(Some(document)),
+            Err(NeuralVaultError::DocumentNotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn apply(&self, writes: &[SyncWrite]) -> NVResult<()> {
+        self.apply_synced(writes)
+    }
+}
+
+/// A document changed on both sides since the last sync
+///
+/// `None` means the document was deleted on that side.
+pub struct Conflict<'a> {
+    pub id: &'a str,
+    pub local: Option<&'a NVDocument>,
+    pub remote: Option<&'a NVDocument>,
+}
+
+/// Picks the version both sides keep, or `None` to delete the document
+pub type MergeFn = Box<dyn Fn(&Conflict) -> NVResult<Option<NVDocument>> + Send + Sync>;
+
+/// How conflicting edits are settled
+pub enum ConflictResolver {
+    /// Keep the version with the later `updated_at`, preferring the remote
+    /// one on a tie; an edit wins over a concurrent delete
+    LastWriterWins,
+    /// Let the application merge the two versions; the result is stamped
+    /// with the current time
+    Merge(MergeFn),
+}
+
+/// What a sync did
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncReport {
+    /// Documents written to the remote side
+    pub pushed: usize,
+    /// Documents written to the local side
+    pub pulled: usize,
+    /// Documents changed on both sides and settled by the resolver
+    pub conflicts: usize,
+    /// CRDT documents changed on both sides and merged field by field
+    pub merged: usize,
+}
+
+/// How far two databases have been synchronized, stored by the local side
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncState {
+    /// Local changefeed position already sent
+    pub local_sequence: u64,
+    /// Remote changefeed position already received
+    pub remote_sequence: u64,
+}
+
+/// Exchange changes between `local` and `remote` since their last sync
+///
+/// Each side's changefeed gives the documents it changed; a document
+/// changed on only one side is copied to the other, and one changed on
+/// both is settled by `resolver`. Copies keep their timestamps, and
+/// versions already present on the other side are never resent, so a
+/// following sync finds nothing to do. Progress is stored in the local
+/// catalog only after both sides have been written, so an interrupted sync
+/// is simply repeated.
+///
+/// Documents of CRDT collections present on both sides are merged field by
+/// field instead of going to `resolver`.
+///
+/// Writes go through each side's validation, hooks and reference actions.
+/// A document written on either side while the sync runs fails it with
+/// `WriteConflict`; running it again settles the new version too. Audit
+/// entries are never copied, since each side audits its own writes.
+///
+/// When a side was compacted since the last sync its changefeed starts
+/// over, and deletes made on it before the compaction are not propagated.
+pub fn sync(local: &NeuralVault, remote: &dyn SyncPeer, resolver: &ConflictResolver) -> NVResult<SyncReport> {
+    let peer = remote.replica_id()?;
+    let state = local.sync_state(&peer)?;
+
+    let (local_changes, local_next) = collect_changes(local, state.local_sequence)?;
+    let (remote_changes, remote_next) = collect_changes(remote, state.remote_sequence)?;
+
+    let ids: HashSet<&String> = local_changes.keys().chain(remote_changes.keys()).collect();
+    let mut report = SyncReport::default();
+    let mut to_local = Vec::new();
+    let mut to_remote = Vec::new();
+
+    for id in ids {
+        // Current versions, since a page may hold older ones
+        let local_version = SyncPeer::get(local, id)?;
+        let remote_version = remote.get(id)?;
+        if same_version(local_version.as_ref(), remote_version.as_ref()) {
+            continue;
+        }
+
+        let winner = match (local_changes.contains_key(id), remote_changes.contains_key(id)) {
+            (true, false) => local_version.clone(),
+            (false, true) => remote_version.clone(),
+            _ => match (&local_version, &remote_version) {
+                (Some(local), Some(remote)) if crdt::is_crdt(local) && crdt::is_crdt(remote) => {
+                    report.merged += 1;
+                    Some(crdt::merge(local, remote))
+                }
+                _ => {
+                    report.conflicts += 1;
+                    let conflict = Conflict {
+                        id,
+                        local: local_version.as_ref(),
+                        remote: remote_version.as_ref(),
+                    };
+                    resolve(resolver, &conflict)?
+                }
+            },
+        };
+
+        let document = winner.clone().unwrap_or_else(|| deletion_marker(id));
+        if !same_version(local_version.as_ref(), winner.as_ref()) {
+            to_local.push(SyncWrite {
+                document: document.clone(),
+                expected: local_version.as_ref().map(|version| version.version),
+            });
+        }
+        if !same_version(remote_version.as_ref(), winner.as_ref()) {
+            to_remote.push(SyncWrite {
+                document,
+                expected: remote_version.as_ref().map(|version| version.version),
+            });
+        }
+    }
+
+    remote.apply(&to_remote)?;
+    local.apply_synced(&to_local)?;
+    report.pushed = to_remote.len();
+    report.pulled = to_local.len();
+
+    local.set_sync_state(
+        &peer,
+        &SyncState {
+            local_sequence: local_next,
+            remote_sequence: remote_next,
+        },
+    )?;
+    Ok(report)
+}
+
+/// Every change after `sequence`, keyed by document ID, and the position
+/// reached
+fn collect_changes(peer: &dyn SyncPeer, mut sequence: u64) -> NVResult<(HashMap<String, Change>, u64)> {
+    let mut changes = HashMap::new();
+    loop {
+        let page = peer.changes_since(sequence)?;
+        for change in page.changes {
+            if change.collection != AUDIT_COLLECTION {
+                changes.insert(change.id.clone(), change);
+            }
+        }
+        if page.next == sequence {
+            return Ok((changes, sequence));
+        }
+        sequence = page.next;
+    }
+}
+
+/// The version both sides keep after a conflict
+fn resolve(resolver: &ConflictResolver, conflict: &Conflict) -> NVResult<Option<NVDocument>> {
+    match resolver {
+        ConflictResolver::LastWriterWins => Ok(match (conflict.local, conflict.remote) {
+            (Some(local), Some(remote)) if local.updated_at > remote.updated_at => Some(local.clone()),
+            (_, Some(remote)) => Some(remote.clone()),
+            (local, None) => local.cloned(),
+        }),
+        ConflictResolver::Merge(merge) => Ok(merge(conflict)?.map(|mut document| {
+            document.id = conflict.id.to_string();
+            document.updated_at = Utc::now();
+            document
+        })),
+    }
+}
+
+/// Whether two sides hold the same version of a document
+fn same_version(a: Option<&NVDocument>, b: Option<&NVDocument>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.collection == b.collection && a.updated_at == b.updated_at && a.data == b.data,
+        (None, None) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,257 @@
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::{NVDocument, NVValue};
+use crate::storage::{FileManager, WriteBatch};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Reserved collection holding database metadata
+pub const SYSTEM_COLLECTION: &str = "_system";
+
+/// On-disk format version written to new databases
+///
+/// Version 2 stores archive segments and incremental backups as records
+/// instead of JSON. Older databases are upgraded when opened for writing,
+/// so older releases refuse them rather than misreading the new files.
+/// Databases from before the record format have no version; upgrading them
+/// also rewrites their bincode data file records.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Well-known metadata keys and key namespaces
+pub mod keys {
+    pub const FORMAT_VERSION: &str = "format_version";
+    pub const CREATED_AT: &str = "created_at";
+    /// Prefix for per-collection schema definitions
+    pub const SCHEMA_PREFIX: &str = "schema.";
+    /// Prefix for index definitions
+    pub const INDEX_PREFIX: &str = "index.";
+    /// Prefix for autocomplete index definitions
+    pub const COMPLETION_INDEX_PREFIX: &str = "completion_index.";
+    /// Prefix for full-text index definitions
+    pub const TEXT_INDEX_PREFIX: &str = "text_index.";
+    /// Prefix for vector index definitions
+    pub const VECTOR_INDEX_PREFIX: &str = "vector_index.";
+    /// Prefix for capped collection limits, keyed by collection
+    pub const CAPPED_PREFIX: &str = "capped.";
+    /// Prefix for time-series collection options, keyed by collection
+    pub const TIME_SERIES_PREFIX: &str = "time_series.";
+    /// Prefix for per-collection ID strategies
+    pub const ID_STRATEGY_PREFIX: &str = "id_strategy.";
+    /// Prefix for sequence default counters, keyed by `collection.field`;
+    /// older databases also kept per-collection ID counters here
+    pub const SEQUENCE_PREFIX: &str = "sequence.";
+    /// Last auto-increment document ID, shared by every collection
+    pub const ID_SEQUENCE: &str = "id_sequence";
+    /// Prefix for reference declarations, keyed by `collection.field`
+    pub const REFERENCE_PREFIX: &str = "reference.";
+    /// Prefix for saved views (named queries)
+    pub const VIEW_PREFIX: &str = "view.";
+    /// Prefix for materialized aggregate view definitions
+    pub const AGGREGATE_PREFIX: &str = "aggregate.";
+    /// Prefix for encrypted fields, keyed by `collection.field`
+    pub const ENCRYPTED_FIELD_PREFIX: &str = "encrypted_field.";
+    /// A sealed known value used to verify the field encryption key
+    pub const FIELD_KEY_CHECK: &str = "field_key_check";
+    /// The field master key, wrapped under the password
+    pub const WRAPPED_KEY: &str = "wrapped_key";
+    /// Prefix for access control roles
+    pub const ROLE_PREFIX: &str = "auth_role.";
+    /// Prefix for access control users
+    pub const AUTH_USER_PREFIX: &str = "auth_user.";
+    /// Prefix for API tokens, keyed by their SHA-256 digest
+    pub const TOKEN_PREFIX: &str = "auth_token.";
+    /// Whether document writes are recorded in the audit trail
+    pub const AUDIT_LOG: &str = "audit_log";
+    /// Identifies the current oplog; replaced whenever compaction moves records
+    pub const OPLOG_ID: &str = "oplog_id";
+    /// Sequence number of the current oplog's first byte
+    pub const OPLOG_BASE: &str = "oplog_base";
+    /// Prefix for collections whose documents are CRDT maps
+    pub const CRDT_PREFIX: &str = "crdt.";
+    /// Identity of this database for offline sync
+    pub const REPLICA_ID: &str = "replica_id";
+    /// Prefix for sync progress with each peer, keyed by its replica ID
+    pub const SYNC_PREFIX: &str = "sync_peer.";
+    /// A follower's position in its leader's oplog
+    pub const REPLICATION_CURSOR: &str = "replication_cursor";
+    /// Prefix for caller-defined key/values
+    pub const USER_PREFIX: &str = "user.";
+    /// Version of the last migration applied by `migrate`
+    pub const SCHEMA_VERSION: &str = "schema_version";
+    /// Prefix for migrations applied by `migrate`, keyed by version
+    pub const MIGRATION_PREFIX: &str = "migration.";
+    /// Prefix for archived documents replaced by a live document with the
+    /// same ID, keyed by `collection/id`
+    pub const SUPERSEDED_PREFIX: &str = "archive_superseded.";
+}
+
+/// Field holding a metadata entry's value
+const VALUE_FIELD: &str = "value";
+
+/// Durable key/value metadata stored in the `_system` collection
+///
+/// Each key is stored as its own document, so entries are written,
+/// replaced and recovered exactly like user documents.
+pub struct SystemCatalog {
+    storage: Arc<FileManager>,
+}
+
+impl SystemCatalog {
+    pub fn new(storage: Arc<FileManager>) -> Self {
+        Self { storage }
+    }
+
+    /// Write the format version and creation time to a new database, or
+    /// upgrade an older one
+    ///
+    /// Fails if the database was written by a newer, incompatible format.
+    /// Data file records from before the record format are rewritten by a
+    /// compaction before the version is recorded; read-only handles decode
+    /// them as they go.
+    pub fn initialize(&self) -> NVResult<()> {
+        let version = self.format_version()?;
+        match version {
+            Some(version) if version > FORMAT_VERSION => {
+                return Err(NeuralVaultError::InvalidConfiguration(format!(
+                    "Database format version {} is newer than supported version {}",
+                    version, FORMAT_VERSION
+                )));
+            }
+            Some(FORMAT_VERSION) => return Ok(()),
+            _ if self.storage.is_read_only() => return Ok(()),
+            _ => {}
+        }
+
+        if self.storage.has_legacy_records()? {
+            self.storage.compact()?;
+        }
+        if version.is_none() {
+            self.set(keys::CREATED_AT, NVValue::String(Utc::now().to_rfc3339()))?;
+        }
+        self.set(keys::FORMAT_VERSION, NVValue::Number(FORMAT_VERSION as f64))
+    }
+
+    /// Format version the database was created with, or last upgraded to
+    pub fn format_version(&self) -> NVResult<Option<u32>> {
+        Ok(match self.get(keys::FORMAT_VERSION)? {
+            Some(NVValue::Number(n)) => Some(n as u32),
+            _ => None,
+        })
+    }
+
+    /// When the database was created
+    pub fn created_at(&self) -> NVResult<Option<DateTime<Utc>>> {
+        Ok(match self.get(keys::CREATED_AT)? {
+            Some(NVValue::String(s)) => DateTime::parse_from_rfc3339(&s)
+                .ok()
+                .map(|t| t.with_timezone(&Utc)),
+            _ => None,
+        })
+    }
+
+    /// Get a metadata value
+    pub fn get(&self, key: &str) -> NVResult<Option<NVValue>> {
+        let id = Self::document_id(key);
+        if !self.storage.contains(&id) {
+            return Ok(None);
+        }
+        let document = self.storage.read(&id)?;
+        Ok(document.data.get(VALUE_FIELD).cloned())
+    }
+
+    /// Set a metadata value, replacing any previous value
+    pub fn set(&self, key: &str, value: NVValue) -> NVResult<()> {
+        let id = Self::document_id(key);
+
+        if self.storage.contains(&id) {
+            let mut document = self.storage.read(&id)?;
+            document.set(VALUE_FIELD.to_string(), value);
+            self.storage.replace(&document)?;
+        } else {
+            let mut data = HashMap::new();
+            data.insert(VALUE_FIELD.to_string(), value);
+            let document = NVDocument::new(id, SYSTEM_COLLECTION.to_string(), data);
+            self.storage.append(&document)?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether a metadata value is set
+    pub fn contains(&self, key: &str) -> bool {
+        self.storage.contains(&Self::document_id(key))
+    }
+
+    /// Set a metadata value as part of `batch`
+    pub fn set_in(batch: &mut WriteBatch, key: &str, value: NVValue) -> NVResult<()> {
+        let id = Self::document_id(key);
+        let mut data = HashMap::new();
+        data.insert(VALUE_FIELD.to_string(), value);
+        if batch.contains(&id) {
+            let mut document = batch.read(&id)?;
+            document.data = data;
+            batch.put(&document)
+        } else {
+            batch.insert(&NVDocument::new(id, SYSTEM_COLLECTION.to_string(), data))
+        }
+    }
+
+    /// Remove a metadata value as part of `batch`
+    pub fn remove_in(batch: &mut WriteBatch, key: &str) -> NVResult<()> {
+        let id = Self::document_id(key);
+        if batch.contains(&id) {
+            batch.delete(&id)?;
+        }
+        Ok(())
+    }
+
+    /// Remove a metadata value, returning whether it existed
+    pub fn remove(&self, key: &str) -> NVResult<bool> {
+        let id = Self::document_id(key);
+        if !self.storage.contains(&id) {
+            return Ok(false);
+        }
+        self.storage.mark_deleted(&id)?;
+        Ok(true)
+    }
+
+    /// All entries whose key starts with `prefix`, sorted by key
+    pub fn list(&self, prefix: &str) -> NVResult<Vec<(String, NVValue)>> {
+        let id_prefix = Self::document_id(prefix);
+        let mut entries: Vec<(String, NVValue)> = self
+            .storage
+            .scan_collection(SYSTEM_COLLECTION)?
+            .into_iter()
+            .filter(|doc| doc.id.starts_with(&id_prefix))
+            .filter_map(|mut doc| {
+                let value = doc.data.remove(VALUE_FIELD)?;
+                let key = doc.id[SYSTEM_COLLECTION.len() + 1..].to_string();
+                Some((key, value))
+            })
+            .collect();
+
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(entries)
+    }
+
+    /// Set `key`'s value on `document` if it is that key's entry
+    ///
+    /// For rewriting catalog entries during storage-level passes such as
+    /// compaction. Returns whether the document was changed.
+    pub fn update_entry(document: &mut NVDocument, key: &str, value: NVValue) -> bool {
+        if !Self::is_entry(document, key) {
+            return false;
+        }
+        document.set(VALUE_FIELD.to_string(), value);
+        true
+    }
+
+    /// Whether `document` is the entry for `key`
+    pub fn is_entry(document: &NVDocument, key: &str) -> bool {
+        document.id == Self::document_id(key)
+    }
+
+    pub(crate) fn document_id(key: &str) -> String {
+        format!("{}:{}", SYSTEM_COLLECTION, key)
+    }
+}
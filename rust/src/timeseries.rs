@@ -0,0 +1,190 @@
+use crate::error::{NeuralVaultError, NVResult};
+use crate::expression;
+use crate::models::NVDocument;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// How a time-series collection is organized
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimeSeriesOptions {
+    /// Field holding each event's time, as milliseconds since the epoch or
+    /// an RFC 3339 string
+    pub time_field: String,
+    /// Width of the buckets events are grouped into
+    #[serde(default = "default_bucket_secs")]
+    pub bucket_secs: u64,
+    /// Drop events older than this, if set
+    #[serde(default)]
+    pub retention_secs: Option<u64>,
+}
+
+fn default_bucket_secs() -> u64 {
+    3600
+}
+
+impl TimeSeriesOptions {
+    pub fn new(time_field: &str) -> Self {
+        Self {
+            time_field: time_field.to_string(),
+            bucket_secs: default_bucket_secs(),
+            retention_secs: None,
+        }
+    }
+
+    pub fn validate(&self) -> NVResult<()> {
+        if self.bucket_secs == 0 {
+            return Err(NeuralVaultError::ValidationError("Bucket width must be positive".to_string()));
+        }
+        if self.retention_secs == Some(0) {
+            return Err(NeuralVaultError::ValidationError("Retention must be positive".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// The events of a time-series collection, grouped into time buckets
+///
+/// Events are found by time without reading the collection: a range scan
+/// visits only the buckets it overlaps, and expiring old events drops
+/// whole buckets. Documents without a valid time aren't tracked.
+#[derive(Debug, Clone)]
+pub struct TimeSeries {
+    options: TimeSeriesOptions,
+    /// Bucket start to the events in it, by time then ID
+    buckets: BTreeMap<i64, BTreeSet<(i64, String)>>,
+    /// Document ID to its time
+    times: HashMap<String, i64>,
+}
+
+impl TimeSeries {
+    pub fn new(options: TimeSeriesOptions) -> Self {
+        Self {
+            options,
+            buckets: BTreeMap::new(),
+            times: HashMap::new(),
+        }
+    }
+
+    pub fn options(&self) -> &TimeSeriesOptions {
+        &self.options
+    }
+
+    /// Number of events
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    /// Number of buckets holding events
+    pub fn buckets(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Track the current version of a document
+    pub fn add(&mut self, document: &NVDocument) {
+        self.remove(&document.id);
+        if document.deleted {
+            return;
+        }
+        let Some(time) = document.get(&self.options.time_field).and_then(expression::timestamp) else {
+            return;
+        };
+        self.buckets
+            .entry(self.bucket(time))
+            .or_default()
+            .insert((time, document.id.clone()));
+        self.times.insert(document.id.clone(), time);
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        let Some(time) = self.times.remove(id) else {
+            return;
+        };
+        let bucket = self.bucket(time);
+        if let Some(events) = self.buckets.get_mut(&bucket) {
+            events.remove(&(time, id.to_string()));
+            if events.is_empty() {
+                self.buckets.remove(&bucket);
+            }
+        }
+    }
+
+    /// IDs of the events in `from..to` (milliseconds), oldest first
+    pub fn range(&self, from: i64, to: i64) -> Vec<String> {
+        if from >= to {
+            return Vec::new();
+        }
+        self.buckets
+            .range(self.bucket(from)..=self.bucket(to - 1))
+            .flat_map(|(_, events)| events.iter())
+            .filter(|(time, _)| (from..to).contains(time))
+            .map(|(_, id)| id.clone())
+            .collect()
+    }
+
+    /// IDs of the events older than the retention period as of `now`
+    pub fn expired(&self, now: i64) -> Vec<String> {
+        let Some(retention) = self.options.retention_secs else {
+            return Vec::new();
+        };
+        let cutoff = now.saturating_sub(retention as i64 * 1000);
+        self.buckets
+            .range(..=self.bucket(cutoff))
+            .flat_map(|(_, events)| events.iter())
+            .take_while(|(time, _)| *time < cutoff)
+            .map(|(_, id)| id.clone())
+            .collect()
+    }
+
+    fn bucket(&self, time: i64) -> i64 {
+        let width = self.options.bucket_secs as i64 * 1000;
+        time.div_euclid(width) * width
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::NVValue;
+
+    fn reading(id: &str, time: NVValue, value: f64) -> NVDocument {
+        let mut data = HashMap::new();
+        data.insert("at".to_string(), time);
+        data.insert("value".to_string(), NVValue::Number(value));
+        NVDocument::new(id.to_string(), "readings".to_string(), data)
+    }
+
+    fn series() -> TimeSeries {
+        TimeSeries::new(TimeSeriesOptions {
+            time_field: "at".to_string(),
+            bucket_secs: 60,
+            retention_secs: Some(120),
+        })
+    }
+
+    #[test]
+    fn test_range_across_buckets() {
+        let mut series = series();
+        series.add(&reading("c", NVValue::Number(150_000.0), 3.0));
+        series.add(&reading("a", NVValue::String("1970-01-01T00:00:10Z".to_string()), 1.0));
+        series.add(&reading("b", NVValue::Number(70_000.0), 2.0));
+        series.add(&reading("x", NVValue::String("not a time".to_string()), 0.0));
+        assert_eq!((series.len(), series.buckets()), (3, 3));
+
+        assert_eq!(series.range(0, 200_000), vec!["a", "b", "c"]);
+        assert_eq!(series.range(10_000, 150_000), vec!["a", "b"]);
+        assert!(series.range(150_000, 150_000).is_empty());
+
+        // Moving an event moves it between buckets
+        series.add(&reading("a", NVValue::Number(160_000.0), 1.0));
+        assert_eq!(series.range(0, 200_000), vec!["b", "c", "a"]);
+        assert_eq!(series.buckets(), 2);
+
+        assert_eq!(series.expired(200_000), vec!["b"]);
+        series.remove("b");
+        assert!(series.expired(200_000).is_empty());
+    }
+}
@@ -0,0 +1,202 @@
+use crate::database::NeuralVault;
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::{NVDocument, NVValue, UpdateOperation, WriteOp};
+use crate::system::SystemCatalog;
+use std::collections::HashMap;
+
+/// A point in a transaction that later writes can be rolled back to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Savepoint {
+    id: u64,
+}
+
+/// Writes collected to be applied together
+///
+/// Created by `NeuralVault::transaction`. Nothing is written until
+/// `commit`, which applies every write under one storage lock with a single
+/// fsync, so readers never see part of a transaction. Duplicate IDs and
+/// missing documents are detected before anything is written; a hook veto
+/// or error part-way through undoes the earlier writes, so a commit either
+/// applies every write or none. Dropping a transaction without committing
+/// discards it.
+///
+/// Documents read through the transaction or touched by its updates and
+/// deletes are checked on commit: if any was written by someone else in
+/// the meantime, the commit fails with `WriteConflict` and writes nothing.
+pub struct Transaction<'db> {
+    db: &'db NeuralVault,
+    ops: Vec<WriteOp>,
+    /// Version of each document seen, `None` if it didn't exist
+    versions: HashMap<String, Option<u64>>,
+    /// Live savepoints in creation order, with the write count at each
+    savepoints: Vec<(Savepoint, usize)>,
+    next_savepoint: u64,
+    /// System catalog entries written with the commit
+    catalog: Vec<(String, NVValue)>,
+}
+
+impl<'db> Transaction<'db> {
+    pub(crate) fn new(db: &'db NeuralVault) -> Self {
+        Self {
+            db,
+            ops: Vec::new(),
+            versions: HashMap::new(),
+            savepoints: Vec::new(),
+            next_savepoint: 0,
+            catalog: Vec::new(),
+        }
+    }
+
+    /// Read a document, so the commit fails if it changes meanwhile
+    ///
+    /// Returns the stored version, sensitive fields included, so it can be
+    /// written back whole; the transaction's own writes aren't visible
+    /// until commit.
+    pub fn read(&mut self, id: &str) -> NVResult<NVDocument> {
+        // Recording the version first means a write in between shows up
+        // as a conflict rather than going unnoticed
+        self.observe(id);
+        self.db.find_by_id_unredacted(id)
+    }
+
+    /// Add a write
+    pub fn write(&mut self, op: WriteOp) -> &mut Self {
+        if let WriteOp::Update { id, .. } | WriteOp::Delete { id } = &op {
+            self.observe(id);
+        }
+        self.ops.push(op);
+        self
+    }
+
+    fn observe(&mut self, id: &str) {
+        if !self.versions.contains_key(id) {
+            let version = self.db.document_version(id);
+            self.versions.insert(id.to_string(), version);
+        }
+    }
+
+    /// Create a document; its ID is assigned on commit
+    pub fn insert(&mut self, collection: &str, data: HashMap<String, NVValue>) -> &mut Self {
+        self.write(WriteOp::Insert {
+            id: None,
+            collection: collection.to_string(),
+            data,
+        })
+    }
+
+    /// Apply updates to an existing document
+    pub fn update(&mut self, id: &str, updates: Vec<UpdateOperation>) -> &mut Self {
+        self.write(WriteOp::Update {
+            id: id.to_string(),
+            updates,
+        })
+    }
+
+    /// Delete an existing document
+    pub fn delete(&mut self, id: &str) -> &mut Self {
+        self.write(WriteOp::Delete { id: id.to_string() })
+    }
+
+    /// Set a system catalog entry as part of the commit, which fails with
+    /// `WriteConflict` if someone else sets it meanwhile
+    pub(crate) fn set_catalog(&mut self, key: &str, value: NVValue) {
+        self.observe(&SystemCatalog::document_id(key));
+        self.catalog.push((key.to_string(), value));
+    }
+
+    /// Writes collected so far
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Mark the current point so later writes can be undone
+    ///
+    /// Savepoints nest: rolling back to or releasing one also discards
+    /// every savepoint created after it.
+    pub fn savepoint(&mut self) -> Savepoint {
+        let savepoint = Savepoint {
+            id: self.next_savepoint,
+        };
+        self.next_savepoint += 1;
+        self.savepoints.push((savepoint, self.ops.len()));
+        savepoint
+    }
+
+    /// Discard the writes made since `savepoint`, which stays usable
+    pub fn rollback_to(&mut self, savepoint: Savepoint) -> NVResult<()> {
+        let slot = self.find(savepoint)?;
+        self.ops.truncate(self.savepoints[slot].1);
+        self.savepoints.truncate(slot + 1);
+        Ok(())
+    }
+
+    /// Forget `savepoint` and the savepoints after it, keeping their writes
+    pub fn release(&mut self, savepoint: Savepoint) -> NVResult<()> {
+        let slot = self.find(savepoint)?;
+        self.savepoints.truncate(slot);
+        Ok(())
+    }
+
+    /// Apply the writes, returning each one's document ID in order
+    pub fn commit(self) -> NVResult<Vec<String>> {
+        if self.ops.is_empty() && self.catalog.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.db.commit_writes(self.ops, self.versions, self.catalog)
+    }
+
+    /// Discard the transaction
+    pub fn rollback(self) {}
+
+    fn find(&self, savepoint: Savepoint) -> NVResult<usize> {
+        self.savepoints
+            .iter()
+            .position(|(live, _)| *live == savepoint)
+            .ok_or_else(|| {
+                NeuralVaultError::TransactionError(
+                    "Savepoint was released or rolled back past".to_string(),
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DatabaseConfig;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_nested_savepoints() {
+        let dir = tempdir().unwrap();
+        let db = NeuralVault::new(DatabaseConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut tx = db.transaction();
+        tx.insert("rows", HashMap::new());
+        let outer = tx.savepoint();
+        tx.insert("rows", HashMap::new());
+        let inner = tx.savepoint();
+        tx.insert("rows", HashMap::new());
+
+        tx.rollback_to(outer).unwrap();
+        assert_eq!(tx.len(), 1);
+        assert!(tx.rollback_to(inner).is_err());
+
+        // The savepoint survives its own rollback
+        tx.insert("rows", HashMap::new());
+        tx.rollback_to(outer).unwrap();
+        tx.release(outer).unwrap();
+        assert!(tx.rollback_to(outer).is_err());
+
+        assert_eq!(tx.commit().unwrap().len(), 1);
+        assert_eq!(db.count("rows").unwrap(), 1);
+    }
+}
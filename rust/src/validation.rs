@@ -0,0 +1,185 @@
+use crate::error::{NeuralVaultError, NVResult};
+use crate::models::{self, NVValue};
+use std::collections::HashMap;
+
+/// Maximum collection name length in bytes
+pub const MAX_COLLECTION_NAME_LEN: usize = 64;
+
+/// Maximum field name length in bytes
+pub const MAX_FIELD_NAME_LEN: usize = 256;
+
+/// Maximum document ID length in bytes
+pub const MAX_DOCUMENT_ID_LEN: usize = 256;
+
+/// Name prefixes reserved for internal collections and fields
+pub const RESERVED_PREFIXES: &[&str] = &["_system", "_nv"];
+
+/// Check that a collection name is usable
+///
+/// Collection names may end up in file names, so they are restricted to
+/// ASCII letters, digits, `_` and `-`.
+pub fn validate_collection_name(name: &str) -> NVResult<()> {
+    if name.is_empty() {
+        return Err(invalid("Collection name must not be empty"));
+    }
+    if name.len() > MAX_COLLECTION_NAME_LEN {
+        return Err(invalid(format!(
+            "Collection name '{}' exceeds {} bytes",
+            name, MAX_COLLECTION_NAME_LEN
+        )));
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || *c == '_' || *c == '-'))
+    {
+        return Err(invalid(format!(
+            "Collection name '{}' contains invalid character {:?}",
+            name, c
+        )));
+    }
+    check_reserved("Collection", name)
+}
+
+/// Check that a tenant ID is usable for `NeuralVault::scoped`
+///
+/// Tenant IDs prefix collection names, so they're restricted to ASCII
+/// letters, digits and `-`, which keeps the `__` separating them from the
+/// collection unambiguous.
+pub fn validate_tenant_id(tenant: &str) -> NVResult<()> {
+    if tenant.is_empty() {
+        return Err(invalid("Tenant ID must not be empty"));
+    }
+    if let Some(c) = tenant.chars().find(|c| !(c.is_ascii_alphanumeric() || *c == '-')) {
+        return Err(invalid(format!(
+            "Tenant ID '{}' contains invalid character {:?}",
+            tenant, c
+        )));
+    }
+    Ok(())
+}
+
+/// Check that a field name is usable
+pub fn validate_field_name(name: &str) -> NVResult<()> {
+    if name.is_empty() {
+        return Err(invalid("Field name must not be empty"));
+    }
+    if name.len() > MAX_FIELD_NAME_LEN {
+        return Err(invalid(format!(
+            "Field name '{}' exceeds {} bytes",
+            name, MAX_FIELD_NAME_LEN
+        )));
+    }
+    if name.chars().any(char::is_control) {
+        return Err(invalid(format!(
+            "Field name {:?} contains control characters",
+            name
+        )));
+    }
+    check_reserved("Field", name)
+}
+
+/// Check that a caller-visible document ID is usable
+pub fn validate_document_id(id: &str) -> NVResult<()> {
+    if id.is_empty() {
+        return Err(invalid("Document ID must not be empty"));
+    }
+    if id.len() > MAX_DOCUMENT_ID_LEN {
+        return Err(invalid(format!(
+            "Document ID '{}' exceeds {} bytes",
+            id, MAX_DOCUMENT_ID_LEN
+        )));
+    }
+    if id.chars().any(char::is_control) {
+        return Err(invalid(format!(
+            "Document ID {:?} contains control characters",
+            id
+        )));
+    }
+    check_reserved("Document ID", id)
+}
+
+/// Check that a field name is usable for a document's data
+///
+/// Unlike other field references, data fields can't take the names of
+/// metadata pseudo-fields, which would hide them from queries.
+pub fn validate_data_field(name: &str) -> NVResult<()> {
+    if models::is_metadata_field(name) {
+        return Err(invalid(format!("Field name '{}' is reserved for document metadata", name)));
+    }
+    validate_field_name(name)
+}
+
+/// Check every top-level field name of a document
+pub fn validate_fields(data: &HashMap<String, NVValue>) -> NVResult<()> {
+    data.keys().try_for_each(|field| validate_data_field(field))
+}
+
+fn check_reserved(kind: &str, name: &str) -> NVResult<()> {
+    match RESERVED_PREFIXES.iter().find(|prefix| name.starts_with(*prefix)) {
+        Some(prefix) => Err(invalid(format!(
+            "{} name '{}' uses the reserved prefix '{}'",
+            kind, name, prefix
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn invalid(message: impl Into<String>) -> NeuralVaultError {
+    NeuralVaultError::ValidationError(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collection_names() {
+        assert!(validate_collection_name("users").is_ok());
+        assert!(validate_collection_name("audit_log-2024").is_ok());
+
+        for name in ["", "a/b", "../etc", "with space", "_system", "_systemUsers"] {
+            assert!(
+                matches!(
+                    validate_collection_name(name),
+                    Err(NeuralVaultError::ValidationError(_))
+                ),
+                "{:?} should be rejected",
+                name
+            );
+        }
+        assert!(validate_collection_name(&"a".repeat(MAX_COLLECTION_NAME_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn test_tenant_ids() {
+        assert!(validate_tenant_id("acme-42").is_ok());
+
+        for tenant in ["", "a_b", "a__b", "a b", "a.b"] {
+            assert!(validate_tenant_id(tenant).is_err(), "{:?} should be rejected", tenant);
+        }
+    }
+
+    #[test]
+    fn test_field_names() {
+        assert!(validate_field_name("name").is_ok());
+        assert!(validate_field_name("first name").is_ok());
+
+        assert!(validate_field_name("").is_err());
+        assert!(validate_field_name("a\0b").is_err());
+        assert!(validate_field_name("_nv_meta").is_err());
+        assert!(validate_field_name("_created_at").is_ok());
+        assert!(validate_data_field("_created_at").is_err());
+        assert!(validate_data_field("_idea").is_ok());
+        assert!(validate_field_name(&"f".repeat(MAX_FIELD_NAME_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn test_document_ids() {
+        assert!(validate_document_id("01HV3Q4Z8Y").is_ok());
+        assert!(validate_document_id("user:42").is_ok());
+
+        assert!(validate_document_id("").is_err());
+        assert!(validate_document_id("_system:format_version").is_err());
+        assert!(validate_document_id("line\nbreak").is_err());
+    }
+}
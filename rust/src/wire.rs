@@ -0,0 +1,89 @@
+use crate::error::{NeuralVaultError, NVResult};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Encoding of a payload returned across the FFI boundary
+///
+/// JSON is the default. The binary formats are self-describing, so they
+/// decode without a schema and keep `NVValue`'s untagged shape; bincode
+/// isn't offered because it can't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireFormat {
+    #[default]
+    Json,
+    /// MessagePack, with structs as maps keyed by field name
+    MessagePack,
+    Cbor,
+}
+
+impl WireFormat {
+    /// Name accepted by `parse`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WireFormat::Json => "json",
+            WireFormat::MessagePack => "msgpack",
+            WireFormat::Cbor => "cbor",
+        }
+    }
+
+    /// Parse "json", "msgpack" or "cbor"
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(WireFormat::Json),
+            "msgpack" => Some(WireFormat::MessagePack),
+            "cbor" => Some(WireFormat::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Serialize `value` in this format
+    pub fn encode<T: Serialize + ?Sized>(&self, value: &T) -> NVResult<Vec<u8>> {
+        match self {
+            WireFormat::Json => Ok(serde_json::to_vec(value)?),
+            WireFormat::MessagePack => rmp_serde::to_vec_named(value)
+                .map_err(|e| NeuralVaultError::SerializationError(e.to_string())),
+            WireFormat::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes)
+                    .map_err(|e| NeuralVaultError::SerializationError(e.to_string()))?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Deserialize a payload produced by `encode`
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> NVResult<T> {
+        match self {
+            WireFormat::Json => Ok(serde_json::from_slice(bytes)?),
+            WireFormat::MessagePack => rmp_serde::from_slice(bytes)
+                .map_err(|e| NeuralVaultError::SerializationError(e.to_string())),
+            WireFormat::Cbor => ciborium::from_reader(bytes)
+                .map_err(|e| NeuralVaultError::SerializationError(e.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{NVDocument, NVValue};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_documents_round_trip_in_every_format() {
+        let mut data = HashMap::new();
+        data.insert("name".to_string(), NVValue::String("ada".to_string()));
+        data.insert("age".to_string(), NVValue::Number(36.0));
+        data.insert("tags".to_string(), NVValue::Array(vec![NVValue::Bool(true), NVValue::Null]));
+        let documents = vec![NVDocument::new("a".to_string(), "people".to_string(), data)];
+
+        for format in [WireFormat::Json, WireFormat::MessagePack, WireFormat::Cbor] {
+            assert_eq!(WireFormat::parse(format.as_str()), Some(format));
+            let decoded: Vec<NVDocument> = format.decode(&format.encode(&documents).unwrap()).unwrap();
+            assert_eq!(decoded[0].id, "a");
+            assert_eq!(decoded[0].data, documents[0].data);
+            assert_eq!(decoded[0].created_at, documents[0].created_at);
+        }
+        assert_eq!(WireFormat::parse("bincode"), None);
+    }
+}